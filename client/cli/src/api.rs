@@ -1,9 +1,28 @@
+use crate::retry::RetryPolicy;
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 use uuid::Uuid;
 
+/// Longest wait for a TCP/TLS connection to the server
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Whole-request limit for JSON calls
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// Longest silence while a chunk or file body is being transferred; bodies
+/// have no overall limit since large files legitimately take a while
+const TRANSFER_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
 pub struct ApiClient {
     base_url: String,
     client: reqwest::Client,
+    retry: RetryPolicy,
+}
+
+/// The server answered with a non-success status
+#[derive(Debug, thiserror::Error)]
+#[error("API error ({status}): {body}")]
+pub struct ApiError {
+    pub status: reqwest::StatusCode,
+    pub body: String,
 }
 
 // --- Auth types ---
@@ -12,6 +31,8 @@ pub struct ApiClient {
 struct LoginRequest {
     username: String,
     password: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    totp_code: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -53,6 +74,22 @@ pub struct FileInfo {
     pub updated_at: String,
 }
 
+#[derive(Debug, Deserialize)]
+struct DirectoryListResponse {
+    entries: Vec<DirectoryEntry>,
+}
+
+/// One entry of a `/v1/files/list` directory listing
+#[derive(Debug, Clone, Deserialize)]
+pub struct DirectoryEntry {
+    pub id: String,
+    pub name: String,
+    pub is_folder: bool,
+    pub updated_at: String,
+    /// Current version (None for folders and links)
+    pub version_id: Option<Uuid>,
+}
+
 #[derive(Debug, Deserialize)]
 struct VersionListResponse {
     versions: Vec<VersionInfo>,
@@ -64,6 +101,15 @@ pub struct VersionInfo {
     pub blob_hash: String,
     pub size_bytes: i64,
     pub created_at: String,
+    /// Username of the uploader, if the server knows it
+    #[serde(default)]
+    pub created_by_username: Option<String>,
+    /// The file's current version (older servers don't say)
+    #[serde(default)]
+    pub is_current: bool,
+    /// The uploader's note, if any
+    #[serde(default)]
+    pub note: Option<String>,
 }
 
 // --- Chunk types ---
@@ -89,6 +135,8 @@ struct CreateFileRequest {
     tier_id: u8,
     content_hash: String,
     chunk_hashes: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    note: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -98,12 +146,67 @@ pub struct CreateFileResponse {
     pub path: String,
 }
 
+#[derive(Debug, Serialize)]
+struct CreateDirectoryRequest {
+    path: String,
+}
+
+/// Part of a file to download: `skip` bytes in, at most `remaining` bytes
+/// (to the end when None)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ByteWindow {
+    pub skip: u64,
+    pub remaining: Option<u64>,
+}
+
+impl ByteWindow {
+    fn is_done(&self) -> bool {
+        self.remaining == Some(0)
+    }
+
+    /// `bytes=start-end` (inclusive), or None for the whole file
+    fn range_header(&self) -> Option<String> {
+        match (self.skip, self.remaining) {
+            (0, None) => None,
+            (start, None) => Some(format!("bytes={}-", start)),
+            (start, Some(len)) => Some(format!("bytes={}-{}", start, start.saturating_add(len.max(1) - 1))),
+        }
+    }
+
+    /// The part of the next piece of the body that falls inside the window
+    fn take<'a>(&mut self, data: &'a [u8]) -> &'a [u8] {
+        let skipped = self.skip.min(data.len() as u64);
+        self.skip -= skipped;
+        let data = &data[skipped as usize..];
+        match &mut self.remaining {
+            Some(remaining) => {
+                let kept = (*remaining).min(data.len() as u64);
+                *remaining -= kept;
+                &data[..kept as usize]
+            }
+            None => data,
+        }
+    }
+}
+
 // --- Changes ---
 
 #[derive(Debug, Deserialize)]
 pub struct ChangesResponse {
     pub changes: Vec<FileChange>,
     pub server_time: String,
+    /// Position after the last change; absent on servers without cursor support
+    #[serde(default)]
+    pub next_cursor: Option<String>,
+    #[serde(default)]
+    pub has_more: bool,
+}
+
+/// `GET /v1/files/state-hash`; see [`crate::db::LocalDb::state_hash`]
+#[derive(Debug, Deserialize)]
+pub struct StateHashResponse {
+    pub hash: String,
+    pub file_count: i64,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -113,6 +216,9 @@ pub struct FileChange {
     pub action: String,
     pub size_bytes: Option<i64>,
     pub blob_hash: Option<String>,
+    /// Current version (older servers don't say)
+    #[serde(default)]
+    pub version_id: Option<String>,
     pub is_directory: bool,
     pub updated_at: String,
 }
@@ -134,11 +240,25 @@ pub struct Conflict {
 }
 
 impl ApiClient {
-    pub fn new(base_url: &str) -> Self {
-        Self {
+    /// Fails if the HTTP client can't be built (e.g. no TLS backend could
+    /// be initialized); a default client would silently drop the timeouts
+    pub fn new(base_url: &str) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .read_timeout(TRANSFER_IDLE_TIMEOUT)
+            .build()
+            .map_err(|e| anyhow::anyhow!("Failed to create HTTP client: {}", e))?;
+        Ok(Self {
             base_url: base_url.trim_end_matches('/').to_string(),
-            client: reqwest::Client::new(),
-        }
+            client,
+            retry: RetryPolicy::default(),
+        })
+    }
+
+    /// Retry idempotent calls with `retry` instead of the default policy
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
     }
 
     /// Check response status; on error, read body for detail message.
@@ -148,26 +268,39 @@ impl ApiClient {
         } else {
             let status = resp.status();
             let body = resp.text().await.unwrap_or_default();
-            anyhow::bail!("API error ({}): {}", status, body)
+            Err(ApiError { status, body }.into())
         }
     }
 
     pub async fn get_server_info(&self) -> anyhow::Result<ServerInfo> {
-        let resp = self
-            .client
-            .get(format!("{}/server/info", self.base_url))
-            .send()
-            .await?;
-        Ok(Self::ensure_ok(resp).await?.json().await?)
+        self.retry
+            .run("server info", || async {
+                let resp = self
+                    .client
+                    .get(format!("{}/server/info", self.base_url))
+                    .timeout(REQUEST_TIMEOUT)
+                    .send()
+                    .await?;
+                Ok(Self::ensure_ok(resp).await?.json().await?)
+            })
+            .await
     }
 
-    pub async fn login(&self, username: &str, password: &str) -> anyhow::Result<TokenPair> {
+    /// Log in; `totp_code` is required once the account has TOTP enabled
+    pub async fn login(
+        &self,
+        username: &str,
+        password: &str,
+        totp_code: Option<&str>,
+    ) -> anyhow::Result<TokenPair> {
         let resp = self
             .client
             .post(format!("{}/auth/login", self.base_url))
+            .timeout(REQUEST_TIMEOUT)
             .json(&LoginRequest {
                 username: username.to_string(),
                 password: password.to_string(),
+                totp_code: totp_code.map(str::to_string),
             })
             .send()
             .await?;
@@ -178,6 +311,7 @@ impl ApiClient {
         let resp = self
             .client
             .post(format!("{}/auth/refresh", self.base_url))
+            .timeout(REQUEST_TIMEOUT)
             .json(&RefreshRequest {
                 refresh_token: refresh.to_string(),
             })
@@ -187,14 +321,61 @@ impl ApiClient {
     }
 
     pub async fn list_files(&self, token: &str) -> anyhow::Result<Vec<FileInfo>> {
-        let resp = self
-            .client
-            .get(format!("{}/files", self.base_url))
-            .bearer_auth(token)
-            .send()
-            .await?;
-        let list: FileListResponse = Self::ensure_ok(resp).await?.json().await?;
-        Ok(list.files)
+        self.retry
+            .run("listing files", || async {
+                let resp = self
+                    .client
+                    .get(format!("{}/files", self.base_url))
+                    .bearer_auth(token)
+                    .timeout(REQUEST_TIMEOUT)
+                    .send()
+                    .await?;
+                let list: FileListResponse = Self::ensure_ok(resp).await?.json().await?;
+                Ok(list.files)
+            })
+            .await
+    }
+
+    /// List the direct children of a directory (`""` for the root)
+    pub async fn list_directory(
+        &self,
+        token: &str,
+        path: &str,
+    ) -> anyhow::Result<Vec<DirectoryEntry>> {
+        self.retry
+            .run("listing a directory", || async {
+                let resp = self
+                    .client
+                    .get(format!("{}/v1/files/list", self.base_url))
+                    .bearer_auth(token)
+                    .query(&[("path", path)])
+                    .timeout(REQUEST_TIMEOUT)
+                    .send()
+                    .await?;
+                let list: DirectoryListResponse = Self::ensure_ok(resp).await?.json().await?;
+                Ok(list.entries)
+            })
+            .await
+    }
+
+    /// Look up the listing entry for one path (None if nothing is there)
+    pub async fn get_entry(&self, token: &str, path: &str) -> anyhow::Result<Option<DirectoryEntry>> {
+        self.retry
+            .run("looking up a path", || async {
+                let resp = self
+                    .client
+                    .get(format!("{}/v1/files/entry", self.base_url))
+                    .bearer_auth(token)
+                    .query(&[("path", path)])
+                    .timeout(REQUEST_TIMEOUT)
+                    .send()
+                    .await?;
+                if resp.status() == reqwest::StatusCode::NOT_FOUND {
+                    return Ok(None);
+                }
+                Ok(Some(Self::ensure_ok(resp).await?.json().await?))
+            })
+            .await
     }
 
     pub async fn get_file_versions(
@@ -202,14 +383,19 @@ impl ApiClient {
         token: &str,
         file_id: Uuid,
     ) -> anyhow::Result<Vec<VersionInfo>> {
-        let resp = self
-            .client
-            .get(format!("{}/files/{}/versions", self.base_url, file_id))
-            .bearer_auth(token)
-            .send()
-            .await?;
-        let list: VersionListResponse = Self::ensure_ok(resp).await?.json().await?;
-        Ok(list.versions)
+        self.retry
+            .run("listing versions", || async {
+                let resp = self
+                    .client
+                    .get(format!("{}/files/{}/versions", self.base_url, file_id))
+                    .bearer_auth(token)
+                    .timeout(REQUEST_TIMEOUT)
+                    .send()
+                    .await?;
+                let list: VersionListResponse = Self::ensure_ok(resp).await?.json().await?;
+                Ok(list.versions)
+            })
+            .await
     }
 
     pub async fn check_chunks(
@@ -217,16 +403,22 @@ impl ApiClient {
         token: &str,
         hashes: &[String],
     ) -> anyhow::Result<ChunkCheckResponse> {
-        let resp = self
-            .client
-            .post(format!("{}/v1/chunks/check", self.base_url))
-            .bearer_auth(token)
-            .json(&ChunkCheckRequest {
-                hashes: hashes.to_vec(),
+        let request = ChunkCheckRequest {
+            hashes: hashes.to_vec(),
+        };
+        self.retry
+            .run("checking chunks", || async {
+                let resp = self
+                    .client
+                    .post(format!("{}/v1/chunks/check", self.base_url))
+                    .bearer_auth(token)
+                    .json(&request)
+                    .timeout(REQUEST_TIMEOUT)
+                    .send()
+                    .await?;
+                Ok(Self::ensure_ok(resp).await?.json().await?)
             })
-            .send()
-            .await?;
-        Ok(Self::ensure_ok(resp).await?.json().await?)
+            .await
     }
 
     pub async fn upload_chunk(
@@ -236,19 +428,25 @@ impl ApiClient {
         data: &[u8],
         tier: u8,
     ) -> anyhow::Result<()> {
-        let resp = self
-            .client
-            .put(format!("{}/v1/chunks/{}", self.base_url, hash))
-            .bearer_auth(token)
-            .header("Content-Type", "application/octet-stream")
-            .header("X-Chunk-Tier", tier.to_string())
-            .body(data.to_vec())
-            .send()
-            .await?;
-        Self::ensure_ok(resp).await?;
-        Ok(())
+        // Chunks are content-addressed, so storing one twice is harmless
+        self.retry
+            .run("uploading a chunk", || async {
+                let resp = self
+                    .client
+                    .put(format!("{}/v1/chunks/{}", self.base_url, hash))
+                    .bearer_auth(token)
+                    .header("Content-Type", "application/octet-stream")
+                    .header("X-Chunk-Tier", tier.to_string())
+                    .body(data.to_vec())
+                    .send()
+                    .await?;
+                Self::ensure_ok(resp).await?;
+                Ok(())
+            })
+            .await
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn create_file(
         &self,
         token: &str,
@@ -258,11 +456,14 @@ impl ApiClient {
         tier_id: u8,
         content_hash: &str,
         chunk_hashes: Vec<String>,
+        note: Option<&str>,
     ) -> anyhow::Result<CreateFileResponse> {
+        // Not retried: a repeat after a lost response would add a second version
         let resp = self
             .client
             .post(format!("{}/v1/files", self.base_url))
             .bearer_auth(token)
+            .timeout(REQUEST_TIMEOUT)
             .json(&CreateFileRequest {
                 path: path.to_string(),
                 size_bytes: size,
@@ -270,49 +471,187 @@ impl ApiClient {
                 tier_id,
                 content_hash: content_hash.to_string(),
                 chunk_hashes,
+                note: note.map(str::to_string),
             })
             .send()
             .await?;
         Ok(Self::ensure_ok(resp).await?.json().await?)
     }
 
+    /// Create a virtual folder; succeeds if it already exists
+    pub async fn create_directory(&self, token: &str, path: &str) -> anyhow::Result<()> {
+        self.retry
+            .run("creating a directory", || async {
+                let resp = self
+                    .client
+                    .post(format!("{}/v1/files/directory", self.base_url))
+                    .bearer_auth(token)
+                    .json(&CreateDirectoryRequest {
+                        path: path.to_string(),
+                    })
+                    .timeout(REQUEST_TIMEOUT)
+                    .send()
+                    .await?;
+                Self::ensure_ok(resp).await?;
+                Ok(())
+            })
+            .await
+    }
+
     pub async fn download_file(&self, token: &str, version_id: Uuid) -> anyhow::Result<Vec<u8>> {
-        let resp = self
-            .client
-            .get(format!(
-                "{}/v1/files/{}/download",
-                self.base_url, version_id
-            ))
-            .bearer_auth(token)
-            .send()
+        self.retry
+            .run("downloading a file", || async {
+                let resp = self
+                    .client
+                    .get(format!(
+                        "{}/v1/files/{}/download",
+                        self.base_url, version_id
+                    ))
+                    .bearer_auth(token)
+                    .send()
+                    .await?;
+                Ok(Self::ensure_ok(resp).await?.bytes().await?.to_vec())
+            })
+            .await
+    }
+
+    /// Stream a version (or a file's current version, given a file ID) into
+    /// `out` without buffering it, returning the number of bytes written
+    pub async fn download_to(
+        &self,
+        token: &str,
+        id: &str,
+        out: &mut impl std::io::Write,
+    ) -> anyhow::Result<u64> {
+        self.download_range_to(token, id, ByteWindow::default(), out).await
+    }
+
+    /// Like [`download_to`](Self::download_to), but only the bytes inside
+    /// `window`. The window is sent as a `Range` header; a server that ignores
+    /// it sends the whole file and the window is cut out here instead.
+    pub async fn download_range_to(
+        &self,
+        token: &str,
+        id: &str,
+        mut window: ByteWindow,
+        out: &mut impl std::io::Write,
+    ) -> anyhow::Result<u64> {
+        if window.is_done() {
+            return Ok(0);
+        }
+        let range = window.range_header();
+        // Only the request is retried; bytes already written to `out` can't
+        // be taken back
+        let mut resp = self
+            .retry
+            .run("downloading a file", || async {
+                let mut req = self
+                    .client
+                    .get(format!("{}/v1/files/{}/download", self.base_url, id))
+                    .bearer_auth(token);
+                if let Some(range) = &range {
+                    req = req.header(reqwest::header::RANGE, range);
+                }
+                Self::ensure_ok(req.send().await?).await
+            })
             .await?;
-        Ok(Self::ensure_ok(resp).await?.bytes().await?.to_vec())
+        if resp.status() == reqwest::StatusCode::PARTIAL_CONTENT {
+            window.skip = 0;
+        }
+        let mut written = 0;
+        while let Some(chunk) = resp.chunk().await? {
+            let data = window.take(&chunk);
+            out.write_all(data)?;
+            written += data.len() as u64;
+            if window.is_done() {
+                break;
+            }
+        }
+        Ok(written)
     }
 
+    /// Fetch a page of changes. `after` (a previous `next_cursor`) takes
+    /// precedence; `since` is the coarse timestamp fallback.
     pub async fn get_changes(
         &self,
         token: &str,
         since: Option<&str>,
+        after: Option<&str>,
     ) -> anyhow::Result<ChangesResponse> {
-        let mut req = self
-            .client
-            .get(format!("{}/v1/files/changes", self.base_url))
-            .bearer_auth(token);
-        if let Some(since) = since {
-            req = req.query(&[("since", since)]);
-        }
-        let resp = req.send().await?;
-        Ok(Self::ensure_ok(resp).await?.json().await?)
+        self.retry
+            .run("fetching changes", || async {
+                let mut req = self
+                    .client
+                    .get(format!("{}/v1/files/changes", self.base_url))
+                    .bearer_auth(token)
+                    .timeout(REQUEST_TIMEOUT);
+                if let Some(after) = after {
+                    req = req.query(&[("after", after)]);
+                } else if let Some(since) = since {
+                    req = req.query(&[("since", since)]);
+                }
+                let resp = req.send().await?;
+                Ok(Self::ensure_ok(resp).await?.json().await?)
+            })
+            .await
+    }
+
+    /// Hash over the server's `(path, content_hash)` pairs under `prefix`
+    pub async fn get_state_hash(&self, token: &str, prefix: &str) -> anyhow::Result<StateHashResponse> {
+        self.retry
+            .run("fetching the state hash", || async {
+                let resp = self
+                    .client
+                    .get(format!("{}/v1/files/state-hash", self.base_url))
+                    .bearer_auth(token)
+                    .query(&[("prefix", prefix)])
+                    .timeout(REQUEST_TIMEOUT)
+                    .send()
+                    .await?;
+                Ok(Self::ensure_ok(resp).await?.json().await?)
+            })
+            .await
     }
 
     pub async fn check_conflicts(&self, token: &str) -> anyhow::Result<Vec<Conflict>> {
-        let resp = self
-            .client
-            .get(format!("{}/conflicts", self.base_url))
-            .bearer_auth(token)
-            .send()
-            .await?;
-        let list: ConflictListResponse = Self::ensure_ok(resp).await?.json().await?;
-        Ok(list.conflicts)
+        self.retry
+            .run("checking conflicts", || async {
+                let resp = self
+                    .client
+                    .get(format!("{}/conflicts", self.base_url))
+                    .bearer_auth(token)
+                    .timeout(REQUEST_TIMEOUT)
+                    .send()
+                    .await?;
+                let list: ConflictListResponse = Self::ensure_ok(resp).await?.json().await?;
+                Ok(list.conflicts)
+            })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_byte_window() {
+        let whole = ByteWindow::default();
+        assert_eq!(whole.range_header(), None);
+
+        let mut window = ByteWindow { skip: 3, remaining: Some(4) };
+        assert_eq!(window.range_header().as_deref(), Some("bytes=3-6"));
+        assert_eq!(window.take(b"ab"), b"");
+        assert_eq!(window.take(b"cdef"), b"def");
+        assert_eq!(window.take(b"ghij"), b"g");
+        assert!(window.is_done());
+
+        let mut tail = ByteWindow { skip: 2, remaining: None };
+        assert_eq!(tail.range_header().as_deref(), Some("bytes=2-"));
+        assert_eq!(tail.take(b"abc"), b"c");
+        assert_eq!(tail.take(b"de"), b"de");
+
+        let huge = ByteWindow { skip: u64::MAX - 1, remaining: Some(u64::MAX) };
+        assert_eq!(huge.range_header(), Some(format!("bytes={}-{}", u64::MAX - 1, u64::MAX)));
     }
 }
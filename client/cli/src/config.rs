@@ -1,5 +1,7 @@
+use crate::retry::{RetryPolicy, DEFAULT_MAX_ATTEMPTS};
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Config {
@@ -8,8 +10,23 @@ pub struct Config {
     pub auth_token: Option<String>,
     pub refresh_token: Option<String>,
     pub sync_directory: Option<String>,
+    /// Name for conflict copies; see `conflict_template()` for placeholders
+    pub conflict_template: Option<String>,
+    /// Quiet period before a changed file is uploaded; see `watch_debounce()`
+    pub watch_debounce_ms: Option<u64>,
+    /// Check downloads against the server's content hash; see `verify_downloads()`
+    pub verify_downloads: Option<bool>,
+    /// Tries per idempotent API call on a flaky network; see `retry_policy()`
+    pub max_attempts: Option<u32>,
+    /// Log bytes uploaded versus skipped via dedup per sync; see `report_dedup()`
+    pub report_dedup: Option<bool>,
 }
 
+/// Default conflict copy name, e.g. `report (conflict 2024-06-01 from sync.example.com).pdf`
+pub const DEFAULT_CONFLICT_TEMPLATE: &str = "{name} (conflict {date} from {server}){ext}";
+
+pub const DEFAULT_WATCH_DEBOUNCE_MS: u64 = 500;
+
 impl Config {
     pub fn config_path() -> anyhow::Result<PathBuf> {
         let home = dirs::home_dir()
@@ -60,6 +77,41 @@ impl Config {
             .ok_or_else(|| anyhow::anyhow!("Not logged in. Run: tangle setup"))
     }
 
+    /// Template for conflict copy file names.
+    ///
+    /// Placeholders: `{name}` (file name without extension), `{ext}`
+    /// (extension including the dot, or empty), `{date}` (YYYY-MM-DD) and
+    /// `{server}` (server host name).
+    pub fn conflict_template(&self) -> &str {
+        self.conflict_template
+            .as_deref()
+            .unwrap_or(DEFAULT_CONFLICT_TEMPLATE)
+    }
+
+    /// How long a path must go without filesystem events before it is
+    /// uploaded, so editors that save via write-rename-write upload once.
+    pub fn watch_debounce(&self) -> Duration {
+        Duration::from_millis(self.watch_debounce_ms.unwrap_or(DEFAULT_WATCH_DEBOUNCE_MS))
+    }
+
+    /// Whether downloaded files are hashed and compared with the version's
+    /// BLAKE3 content hash before they replace the local copy (default on).
+    pub fn verify_downloads(&self) -> bool {
+        self.verify_downloads.unwrap_or(true)
+    }
+
+    /// Whether each sync cycle that uploaded something logs how many bytes
+    /// were sent and how many the server already had (default on)
+    pub fn report_dedup(&self) -> bool {
+        self.report_dedup.unwrap_or(true)
+    }
+
+    /// Backoff and retries for idempotent API calls; `max_attempts`
+    /// (default 4) counts the first try, so 1 turns retries off.
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy::default().with_max_attempts(self.max_attempts.unwrap_or(DEFAULT_MAX_ATTEMPTS))
+    }
+
     pub fn save_to(&self, path: &std::path::Path) -> anyhow::Result<()> {
         let content = toml::to_string_pretty(self)?;
         std::fs::write(path, content)?;
@@ -99,6 +151,11 @@ mod tests {
             auth_token: Some("tok_abc123".to_string()),
             refresh_token: Some("ref_xyz789".to_string()),
             sync_directory: Some("/home/alice/sync".to_string()),
+            conflict_template: Some("{name} ({date}){ext}".to_string()),
+            watch_debounce_ms: Some(2000),
+            verify_downloads: Some(false),
+            max_attempts: Some(1),
+            report_dedup: Some(false),
         };
 
         config.save_to(&path).expect("save should succeed");
@@ -109,5 +166,13 @@ mod tests {
         assert_eq!(loaded.auth_token, config.auth_token);
         assert_eq!(loaded.refresh_token, config.refresh_token);
         assert_eq!(loaded.sync_directory, config.sync_directory);
+        assert_eq!(loaded.conflict_template, config.conflict_template);
+        assert_eq!(loaded.watch_debounce(), Duration::from_secs(2));
+        assert!(!loaded.verify_downloads());
+        assert!(Config::default().verify_downloads());
+        assert_eq!(loaded.retry_policy().max_attempts, 1);
+        assert_eq!(Config::default().retry_policy().max_attempts, DEFAULT_MAX_ATTEMPTS);
+        assert!(!loaded.report_dedup());
+        assert!(Config::default().report_dedup());
     }
 }
@@ -47,14 +47,28 @@ pub fn check_running() -> anyhow::Result<Option<u32>> {
 }
 
 /// Start the daemon by spawning a background process.
-pub fn start() -> anyhow::Result<u32> {
+///
+/// `full` forces a complete reconciliation instead of resuming from the cursor;
+/// `watch_debounce_ms` overrides the configured debounce window, and
+/// `no_verify` turns off download verification.
+pub fn start(full: bool, watch_debounce_ms: Option<u64>, no_verify: bool) -> anyhow::Result<u32> {
     if let Some(pid) = check_running()? {
         anyhow::bail!("Already running (pid {})", pid);
     }
 
     let exe = std::env::current_exe()?;
+    let mut args = vec!["start".to_string(), "--foreground".to_string()];
+    if full {
+        args.push("--full".to_string());
+    }
+    if let Some(ms) = watch_debounce_ms {
+        args.push(format!("--watch-debounce={}", ms));
+    }
+    if no_verify {
+        args.push("--no-verify".to_string());
+    }
     let child = Command::new(&exe)
-        .args(["start", "--foreground"])
+        .args(&args)
         .stdin(Stdio::null())
         .stdout(Stdio::null())
         .stderr(Stdio::null())
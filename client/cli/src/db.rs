@@ -6,14 +6,82 @@ pub struct LocalDb {
     conn: Mutex<Connection>,
 }
 
+/// Local index entry: a file as it was at its last sync. While the file's
+/// mtime and size still match, it is taken as unchanged without being read
+/// (see [`LocalDb::stat_matches`]).
 #[derive(Debug, Clone)]
 pub struct FileRecord {
     pub path: String,
     pub blake3_hash: String,
     pub last_modified: i64,
+    /// Size in bytes (None in rows from before sizes were recorded)
+    pub size: Option<i64>,
+    /// Server version last uploaded or downloaded
+    pub version_id: Option<String>,
     pub sync_cursor: Option<String>,
 }
 
+const FILE_COLUMNS: &str = "path, blake3_hash, last_modified, size, version_id, sync_cursor";
+
+/// How long after its mtime a file's stat must have been recorded before a
+/// matching stat vouches for the content. mtimes are compared in whole
+/// seconds (two on some filesystems), so a same-size write in the second the
+/// entry was recorded leaves the stat unchanged.
+const RACY_STAT_SECS: i64 = 2;
+
+fn file_record(row: &rusqlite::Row) -> rusqlite::Result<FileRecord> {
+    Ok(FileRecord {
+        path: row.get(0)?,
+        blake3_hash: row.get(1)?,
+        last_modified: row.get(2)?,
+        size: row.get(3)?,
+        version_id: row.get(4)?,
+        sync_cursor: row.get(5)?,
+    })
+}
+
+/// Where a device left off in the server's changes feed
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyncCursor {
+    /// `server_time` of the last page applied, sent as `since`
+    pub server_time: String,
+    /// Opaque keyset cursor (`next_cursor`), absent on older servers
+    pub changes_cursor: Option<String>,
+}
+
+/// Bytes sent versus skipped because the server already had the chunk
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UploadStats {
+    pub files: u64,
+    pub uploaded_chunks: u64,
+    pub uploaded_bytes: u64,
+    pub deduped_chunks: u64,
+    pub deduped_bytes: u64,
+}
+
+impl UploadStats {
+    pub fn add(&mut self, other: UploadStats) {
+        self.files += other.files;
+        self.uploaded_chunks += other.uploaded_chunks;
+        self.uploaded_bytes += other.uploaded_bytes;
+        self.deduped_chunks += other.deduped_chunks;
+        self.deduped_bytes += other.deduped_bytes;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files == 0
+    }
+
+    /// Share of the content that didn't need sending, as a percentage
+    pub fn saved_percent(&self) -> f64 {
+        let total = self.uploaded_bytes + self.deduped_bytes;
+        if total == 0 {
+            return 0.0;
+        }
+        self.deduped_bytes as f64 * 100.0 / total as f64
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct RetryEntry {
     pub path: String,
@@ -22,6 +90,25 @@ pub struct RetryEntry {
     pub next_retry: i64,
 }
 
+/// `sync_state` keys of the upload totals, in [`stats_values`] order
+const UPLOAD_STAT_KEYS: [&str; 5] = [
+    "upload_files",
+    "upload_chunks",
+    "upload_bytes",
+    "dedup_chunks",
+    "dedup_bytes",
+];
+
+fn stats_values(stats: &UploadStats) -> [u64; 5] {
+    [
+        stats.files,
+        stats.uploaded_chunks,
+        stats.uploaded_bytes,
+        stats.deduped_chunks,
+        stats.deduped_bytes,
+    ]
+}
+
 impl LocalDb {
     pub fn open() -> anyhow::Result<Self> {
         let db_path = Self::db_path()?;
@@ -50,7 +137,9 @@ impl LocalDb {
                 path TEXT PRIMARY KEY,
                 blake3_hash TEXT NOT NULL,
                 last_modified INTEGER NOT NULL,
-                sync_cursor TEXT
+                sync_cursor TEXT,
+                size INTEGER,
+                version_id TEXT
             );
 
             CREATE TABLE IF NOT EXISTS failed_uploads (
@@ -69,22 +158,17 @@ impl LocalDb {
             CREATE INDEX IF NOT EXISTS idx_retry_next ON failed_uploads(next_retry);
             "#,
         )?;
+        // Databases created before the index recorded sizes and versions
+        add_column_if_missing(&conn, "files", "size", "INTEGER")?;
+        add_column_if_missing(&conn, "files", "version_id", "TEXT")?;
+        add_column_if_missing(&conn, "files", "recorded_at", "INTEGER")?;
         Ok(())
     }
 
     pub fn get_file(&self, path: &str) -> anyhow::Result<Option<FileRecord>> {
         let conn = self.conn.lock().map_err(|e| anyhow::anyhow!("db lock: {}", e))?;
-        let mut stmt = conn.prepare(
-            "SELECT path, blake3_hash, last_modified, sync_cursor FROM files WHERE path = ?",
-        )?;
-        let result = stmt.query_row([path], |row| {
-            Ok(FileRecord {
-                path: row.get(0)?,
-                blake3_hash: row.get(1)?,
-                last_modified: row.get(2)?,
-                sync_cursor: row.get(3)?,
-            })
-        });
+        let mut stmt = conn.prepare(&format!("SELECT {} FROM files WHERE path = ?", FILE_COLUMNS))?;
+        let result = stmt.query_row([path], file_record);
         match result {
             Ok(record) => Ok(Some(record)),
             Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
@@ -92,21 +176,47 @@ impl LocalDb {
         }
     }
 
+    /// Insert or replace a file's entry, noting when it was recorded
     pub fn upsert_file(&self, record: &FileRecord) -> anyhow::Result<()> {
+        let now = chrono::Utc::now().timestamp();
         let conn = self.conn.lock().map_err(|e| anyhow::anyhow!("db lock: {}", e))?;
         conn.execute(
-            "INSERT OR REPLACE INTO files (path, blake3_hash, last_modified, sync_cursor)
-             VALUES (?, ?, ?, ?)",
+            &format!("INSERT OR REPLACE INTO files ({}, recorded_at) VALUES (?, ?, ?, ?, ?, ?, ?)", FILE_COLUMNS),
             (
                 &record.path,
                 &record.blake3_hash,
                 &record.last_modified,
+                &record.size,
+                &record.version_id,
                 &record.sync_cursor,
+                now,
             ),
         )?;
         Ok(())
     }
 
+    /// Whether `path` is indexed with this mtime and size, recorded at least
+    /// [`RACY_STAT_SECS`] after that mtime. A younger entry may have been
+    /// recorded just before a same-size rewrite in the same second, so the
+    /// file has to be hashed (as git does for "racily clean" entries).
+    pub fn stat_matches(&self, path: &str, mtime: i64, size: i64) -> anyhow::Result<bool> {
+        let conn = self.conn.lock().map_err(|e| anyhow::anyhow!("db lock: {}", e))?;
+        let matches = conn
+            .prepare(
+                "SELECT 1 FROM files
+                 WHERE path = ?1 AND last_modified = ?2 AND size = ?3 AND recorded_at >= ?2 + ?4",
+            )?
+            .exists((path, mtime, size, RACY_STAT_SECS))?;
+        Ok(matches)
+    }
+
+    /// Record the server version a file's indexed content corresponds to
+    pub fn set_version_id(&self, path: &str, version_id: &str) -> anyhow::Result<()> {
+        let conn = self.conn.lock().map_err(|e| anyhow::anyhow!("db lock: {}", e))?;
+        conn.execute("UPDATE files SET version_id = ? WHERE path = ?", [version_id, path])?;
+        Ok(())
+    }
+
     pub fn remove_file(&self, path: &str) -> anyhow::Result<()> {
         let conn = self.conn.lock().map_err(|e| anyhow::anyhow!("db lock: {}", e))?;
         conn.execute("DELETE FROM files WHERE path = ?", [path])?;
@@ -115,17 +225,8 @@ impl LocalDb {
 
     pub fn list_files(&self) -> anyhow::Result<Vec<FileRecord>> {
         let conn = self.conn.lock().map_err(|e| anyhow::anyhow!("db lock: {}", e))?;
-        let mut stmt = conn.prepare(
-            "SELECT path, blake3_hash, last_modified, sync_cursor FROM files",
-        )?;
-        let rows = stmt.query_map([], |row| {
-            Ok(FileRecord {
-                path: row.get(0)?,
-                blake3_hash: row.get(1)?,
-                last_modified: row.get(2)?,
-                sync_cursor: row.get(3)?,
-            })
-        })?;
+        let mut stmt = conn.prepare(&format!("SELECT {} FROM files", FILE_COLUMNS))?;
+        let rows = stmt.query_map([], file_record)?;
         let mut files = Vec::new();
         for row in rows {
             files.push(row?);
@@ -133,6 +234,27 @@ impl LocalDb {
         Ok(files)
     }
 
+    /// Hash of the index as `GET /v1/files/state-hash` computes it over the
+    /// server's files: hex BLAKE3 of `path NUL hash LF` per file in byte
+    /// order of path, with the file count. Equal to the server's when the
+    /// last sync left both sides the same.
+    pub fn state_hash(&self) -> anyhow::Result<(String, i64)> {
+        let conn = self.conn.lock().map_err(|e| anyhow::anyhow!("db lock: {}", e))?;
+        // SQLite's default collation compares bytes, as the server does
+        let mut stmt = conn.prepare("SELECT path, blake3_hash FROM files ORDER BY path")?;
+        let mut rows = stmt.query([])?;
+        let mut hasher = blake3::Hasher::new();
+        let mut count = 0;
+        while let Some(row) = rows.next()? {
+            hasher.update(row.get_ref(0)?.as_str()?.as_bytes());
+            hasher.update(b"\0");
+            hasher.update(row.get_ref(1)?.as_str()?.as_bytes());
+            hasher.update(b"\n");
+            count += 1;
+        }
+        Ok((hasher.finalize().to_hex().to_string(), count))
+    }
+
     pub fn add_retry(&self, path: &str, error: &str) -> anyhow::Result<()> {
         let now = chrono::Utc::now().timestamp();
         let conn = self.conn.lock().map_err(|e| anyhow::anyhow!("db lock: {}", e))?;
@@ -177,27 +299,86 @@ impl LocalDb {
         Ok(())
     }
 
-    pub fn get_last_sync_time(&self) -> anyhow::Result<Option<String>> {
+    /// Last successful position in the server's changes feed. The index is
+    /// this machine's own, so the `sync_state` rows need no device key.
+    pub fn get_sync_cursor(&self) -> anyhow::Result<Option<SyncCursor>> {
         let conn = self.conn.lock().map_err(|e| anyhow::anyhow!("db lock: {}", e))?;
-        let mut stmt =
-            conn.prepare("SELECT value FROM sync_state WHERE key = 'last_sync_time'")?;
-        let result = stmt.query_row([], |row| row.get(0));
-        match result {
-            Ok(val) => Ok(Some(val)),
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-            Err(e) => Err(e.into()),
-        }
+        let state = |key: &str| -> anyhow::Result<Option<String>> {
+            match conn.query_row("SELECT value FROM sync_state WHERE key = ?", [key], |row| row.get(0)) {
+                Ok(value) => Ok(Some(value)),
+                Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+                Err(e) => Err(e.into()),
+            }
+        };
+        let Some(server_time) = state("last_sync_time")? else {
+            return Ok(None);
+        };
+        Ok(Some(SyncCursor {
+            server_time,
+            changes_cursor: state("changes_cursor")?,
+        }))
     }
 
-    pub fn set_last_sync_time(&self, time: &str) -> anyhow::Result<()> {
-        let conn = self.conn.lock().map_err(|e| anyhow::anyhow!("db lock: {}", e))?;
-        conn.execute(
+    pub fn set_sync_cursor(&self, cursor: &SyncCursor) -> anyhow::Result<()> {
+        let mut conn = self.conn.lock().map_err(|e| anyhow::anyhow!("db lock: {}", e))?;
+        let tx = conn.transaction()?;
+        tx.execute(
             "INSERT OR REPLACE INTO sync_state (key, value) VALUES ('last_sync_time', ?)",
-            [time],
+            [&cursor.server_time],
         )?;
+        match &cursor.changes_cursor {
+            Some(changes_cursor) => tx.execute(
+                "INSERT OR REPLACE INTO sync_state (key, value) VALUES ('changes_cursor', ?)",
+                [changes_cursor],
+            )?,
+            None => tx.execute("DELETE FROM sync_state WHERE key = 'changes_cursor'", [])?,
+        };
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Forget the cursor so the next sync is a full reconciliation
+    pub fn clear_sync_cursor(&self) -> anyhow::Result<()> {
+        let conn = self.conn.lock().map_err(|e| anyhow::anyhow!("db lock: {}", e))?;
+        conn.execute("DELETE FROM sync_state WHERE key IN ('last_sync_time', 'changes_cursor')", [])?;
         Ok(())
     }
 
+    /// Add an upload's bytes to the running totals shown by `tangle status`
+    pub fn record_upload_stats(&self, stats: &UploadStats) -> anyhow::Result<()> {
+        let conn = self.conn.lock().map_err(|e| anyhow::anyhow!("db lock: {}", e))?;
+        for (key, value) in UPLOAD_STAT_KEYS.iter().zip(stats_values(stats)) {
+            conn.execute(
+                "INSERT INTO sync_state (key, value) VALUES (?1, ?2)
+                 ON CONFLICT(key) DO UPDATE SET value = CAST(value AS INTEGER) + ?2",
+                rusqlite::params![key, value as i64],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Upload totals since the database was created
+    pub fn upload_totals(&self) -> anyhow::Result<UploadStats> {
+        let conn = self.conn.lock().map_err(|e| anyhow::anyhow!("db lock: {}", e))?;
+        let mut values = [0u64; 5];
+        for (key, value) in UPLOAD_STAT_KEYS.iter().zip(values.iter_mut()) {
+            let stored: Option<i64> = conn
+                .query_row(
+                    "SELECT CAST(value AS INTEGER) FROM sync_state WHERE key = ?",
+                    [key],
+                    |row| row.get(0),
+                )
+                .map(Some)
+                .or_else(|e| match e {
+                    rusqlite::Error::QueryReturnedNoRows => Ok(None),
+                    e => Err(e),
+                })?;
+            *value = stored.unwrap_or(0).max(0) as u64;
+        }
+        let [files, uploaded_chunks, uploaded_bytes, deduped_chunks, deduped_bytes] = values;
+        Ok(UploadStats { files, uploaded_chunks, uploaded_bytes, deduped_chunks, deduped_bytes })
+    }
+
     /// Open an in-memory database (for testing).
     #[cfg(test)]
     pub fn open_memory() -> anyhow::Result<Self> {
@@ -210,6 +391,17 @@ impl LocalDb {
     }
 }
 
+/// `ALTER TABLE ... ADD COLUMN` unless the table already has the column
+fn add_column_if_missing(conn: &Connection, table: &str, column: &str, decl: &str) -> anyhow::Result<()> {
+    let exists = conn
+        .prepare(&format!("SELECT 1 FROM pragma_table_info('{}') WHERE name = ?", table))?
+        .exists([column])?;
+    if !exists {
+        conn.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, decl), [])?;
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -238,6 +430,8 @@ mod tests {
             path: "docs/readme.md".to_string(),
             blake3_hash: "abc123".to_string(),
             last_modified: 1700000000,
+            size: Some(42),
+            version_id: Some("v1".to_string()),
             sync_cursor: Some("cursor_1".to_string()),
         };
         db.upsert_file(&record).unwrap();
@@ -248,7 +442,60 @@ mod tests {
         assert_eq!(fetched.path, "docs/readme.md");
         assert_eq!(fetched.blake3_hash, "abc123");
         assert_eq!(fetched.last_modified, 1700000000);
+        assert_eq!(fetched.size, Some(42));
+        assert_eq!(fetched.version_id, Some("v1".to_string()));
         assert_eq!(fetched.sync_cursor, Some("cursor_1".to_string()));
+
+        db.set_version_id("docs/readme.md", "v2").unwrap();
+        assert_eq!(db.get_file("docs/readme.md").unwrap().unwrap().version_id, Some("v2".to_string()));
+    }
+
+    #[test]
+    fn test_state_hash_matches_server() {
+        let db = LocalDb::open_memory().unwrap();
+        let (empty, count) = db.state_hash().unwrap();
+        assert_eq!(empty, "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262");
+        assert_eq!(count, 0);
+
+        // Inserted out of order; the server's test uses the same two files
+        for (path, hash) in [("/b/c.txt", "h2"), ("/a.txt", "h1")] {
+            db.upsert_file(&FileRecord {
+                path: path.to_string(),
+                blake3_hash: hash.to_string(),
+                last_modified: 0,
+                size: None,
+                version_id: None,
+                sync_cursor: None,
+            })
+            .unwrap();
+        }
+        let (hash, count) = db.state_hash().unwrap();
+        assert_eq!(hash, "36799c165e5336aca1c1a73a36614391ca18ef441753b6b4e7cd768a005c8067");
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn test_index_columns_added_to_old_database() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE files (
+                path TEXT PRIMARY KEY,
+                blake3_hash TEXT NOT NULL,
+                last_modified INTEGER NOT NULL,
+                sync_cursor TEXT
+            );
+            INSERT INTO files VALUES ('/old.txt', 'h', 1700000000, NULL);",
+        )
+        .unwrap();
+        let db = LocalDb { conn: Mutex::new(conn) };
+        db.init_schema().unwrap();
+        // Running it again must not try to add the columns twice
+        db.init_schema().unwrap();
+
+        let old = db.get_file("/old.txt").unwrap().unwrap();
+        assert_eq!(old.blake3_hash, "h");
+        assert_eq!(old.size, None);
+        assert_eq!(old.version_id, None);
     }
 
     #[test]
@@ -258,6 +505,8 @@ mod tests {
             path: "src/main.rs".to_string(),
             blake3_hash: "hash_v1".to_string(),
             last_modified: 1700000000,
+            size: None,
+            version_id: None,
             sync_cursor: None,
         };
         db.upsert_file(&record).unwrap();
@@ -310,4 +559,66 @@ mod tests {
             .unwrap();
         assert_eq!(count, 0);
     }
+
+    #[test]
+    fn test_sync_cursor() {
+        let db = LocalDb::open_memory().unwrap();
+        assert!(db.get_sync_cursor().unwrap().is_none());
+
+        let cursor = SyncCursor {
+            server_time: "2026-01-01T00:00:00Z".to_string(),
+            changes_cursor: Some("abc".to_string()),
+        };
+        db.set_sync_cursor(&cursor).unwrap();
+        assert_eq!(db.get_sync_cursor().unwrap(), Some(cursor));
+
+        // A page from an older server carries no keyset cursor
+        let cursor = SyncCursor {
+            server_time: "2026-01-02T00:00:00Z".to_string(),
+            changes_cursor: None,
+        };
+        db.set_sync_cursor(&cursor).unwrap();
+        assert_eq!(db.get_sync_cursor().unwrap(), Some(cursor));
+
+        db.clear_sync_cursor().unwrap();
+        assert!(db.get_sync_cursor().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_sync_cursor_from_older_client() {
+        let db = LocalDb::open_memory().unwrap();
+        {
+            let conn = db.conn.lock().unwrap();
+            conn.execute_batch(
+                "INSERT INTO sync_state (key, value) VALUES ('last_sync_time', '2025-06-01T00:00:00Z');
+                 INSERT INTO sync_state (key, value) VALUES ('changes_cursor', 'xyz');",
+            )
+            .unwrap();
+        }
+        let cursor = db.get_sync_cursor().unwrap().unwrap();
+        assert_eq!(cursor.server_time, "2025-06-01T00:00:00Z");
+        assert_eq!(cursor.changes_cursor.as_deref(), Some("xyz"));
+    }
+
+    #[test]
+    fn test_upload_totals() {
+        let db = LocalDb::open_memory().unwrap();
+        assert_eq!(db.upload_totals().unwrap(), UploadStats::default());
+
+        let stats = UploadStats {
+            files: 1,
+            uploaded_chunks: 2,
+            uploaded_bytes: 1000,
+            deduped_chunks: 6,
+            deduped_bytes: 3000,
+        };
+        db.record_upload_stats(&stats).unwrap();
+        db.record_upload_stats(&stats).unwrap();
+
+        let totals = db.upload_totals().unwrap();
+        assert_eq!(totals.files, 2);
+        assert_eq!(totals.uploaded_bytes, 2000);
+        assert_eq!(totals.deduped_chunks, 12);
+        assert_eq!(totals.saved_percent(), 75.0);
+    }
 }
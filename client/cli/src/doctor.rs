@@ -0,0 +1,189 @@
+//! `tangle doctor` - diagnose common setup problems.
+//!
+//! Runs each check in order, printing a pass/fail line with a remediation
+//! hint. Later checks are skipped when an earlier one they depend on fails.
+
+use crate::api::ApiClient;
+use crate::config::Config;
+use chrono::{DateTime, Utc};
+
+/// Maximum tolerated difference between local and server clocks
+const MAX_CLOCK_SKEW_SECS: i64 = 5;
+
+struct Report {
+    failures: usize,
+}
+
+impl Report {
+    fn pass(&self, check: &str, detail: &str) {
+        println!("[ok]   {}: {}", check, detail);
+    }
+
+    fn fail(&mut self, check: &str, detail: &str, hint: &str) {
+        self.failures += 1;
+        println!("[fail] {}: {}", check, detail);
+        println!("       hint: {}", hint);
+    }
+
+    fn skip(&self, check: &str, reason: &str) {
+        println!("[skip] {}: {}", check, reason);
+    }
+}
+
+pub async fn run() -> anyhow::Result<()> {
+    let mut report = Report { failures: 0 };
+
+    // 1. Config present and parseable
+    let path = Config::config_path()?;
+    let config = if !path.exists() {
+        report.fail("config", &format!("{} not found", path.display()), "run: tangle setup");
+        None
+    } else {
+        match Config::load() {
+            Ok(config) => {
+                report.pass("config", &path.display().to_string());
+                Some(config)
+            }
+            Err(e) => {
+                report.fail(
+                    "config",
+                    &format!("{} is not valid: {}", path.display(), e),
+                    "fix the file by hand or delete it and run: tangle setup",
+                );
+                None
+            }
+        }
+    };
+    let config = config.unwrap_or_default();
+
+    // 2. Sync root exists and is writable
+    match &config.sync_directory {
+        Some(dir) => match check_writable(dir) {
+            Ok(()) => report.pass("sync directory", dir),
+            Err(e) => report.fail(
+                "sync directory",
+                &format!("{}: {}", dir, e),
+                "create the directory or fix its permissions, or rerun: tangle setup",
+            ),
+        },
+        None => report.fail("sync directory", "not configured", "run: tangle setup"),
+    }
+
+    // 3. Server reachable
+    let client = match &config.server_url {
+        Some(url) => {
+            // One try: a diagnosis shouldn't sit through backoff
+            let connected = async {
+                let client = ApiClient::new(url)?.with_retry(config.retry_policy().with_max_attempts(1));
+                let info = client.get_server_info().await?;
+                anyhow::Ok((client, info))
+            };
+            match connected.await {
+                Ok((client, info)) => {
+                    report.pass("server", &format!("{} ({} v{})", url, info.name, info.version));
+                    Some(client)
+                }
+                Err(e) => {
+                    report.fail(
+                        "server",
+                        &format!("{} unreachable: {}", url, e),
+                        "check the server url in the config and that tangled is running",
+                    );
+                    None
+                }
+            }
+        }
+        None => {
+            report.fail("server", "no server url configured", "run: tangle setup");
+            None
+        }
+    };
+
+    // 4. Token valid, and 5. clock in sync with the server
+    match (&client, &config.auth_token) {
+        (Some(client), Some(token)) => {
+            let sent_at = Utc::now();
+            match client.get_changes(token, Some(&sent_at.to_rfc3339()), None).await {
+                Ok(changes) => {
+                    report.pass("auth", "token accepted");
+                    let received_at = Utc::now();
+                    let local = sent_at + (received_at - sent_at) / 2;
+                    match DateTime::parse_from_rfc3339(&changes.server_time) {
+                        Ok(server) => {
+                            let skew = clock_skew_secs(local, server.with_timezone(&Utc));
+                            if skew.abs() <= MAX_CLOCK_SKEW_SECS {
+                                report.pass("clock", &format!("{}s from server", skew));
+                            } else {
+                                report.fail(
+                                    "clock",
+                                    &format!("{}s from server", skew),
+                                    "enable NTP time sync on this machine (or the server)",
+                                );
+                            }
+                        }
+                        Err(e) => report.fail(
+                            "clock",
+                            &format!("unparseable server_time: {}", e),
+                            "upgrade the server",
+                        ),
+                    }
+                }
+                Err(e) => {
+                    report.fail("auth", &e.to_string(), "token expired or revoked; run: tangle setup");
+                    report.skip("clock", "needs a valid token");
+                }
+            }
+        }
+        (Some(_), None) => {
+            report.fail("auth", "not logged in", "run: tangle setup");
+            report.skip("clock", "needs a valid token");
+        }
+        (None, _) => {
+            report.skip("auth", "server unreachable");
+            report.skip("clock", "server unreachable");
+        }
+    }
+
+    if report.failures > 0 {
+        anyhow::bail!("{} check(s) failed", report.failures);
+    }
+    println!();
+    println!("all checks passed");
+    Ok(())
+}
+
+/// Verify the directory exists and a file can be created in it
+fn check_writable(dir: &str) -> anyhow::Result<()> {
+    let dir = std::path::Path::new(dir);
+    if !dir.is_dir() {
+        anyhow::bail!("does not exist");
+    }
+    let probe = dir.join(".tangle-doctor");
+    std::fs::write(&probe, b"")?;
+    std::fs::remove_file(&probe)?;
+    Ok(())
+}
+
+/// Seconds the server clock is ahead of the local clock (negative if behind)
+fn clock_skew_secs(local: DateTime<Utc>, server: DateTime<Utc>) -> i64 {
+    (server - local).num_seconds()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clock_skew_sign() {
+        let local = Utc::now();
+        assert_eq!(clock_skew_secs(local, local + chrono::Duration::seconds(7)), 7);
+        assert_eq!(clock_skew_secs(local, local - chrono::Duration::seconds(3)), -3);
+    }
+
+    #[test]
+    fn test_check_writable() {
+        let dir = tempfile::tempdir().expect("create temp dir");
+        assert!(check_writable(&dir.path().to_string_lossy()).is_ok());
+        assert!(check_writable(&dir.path().join("missing").to_string_lossy()).is_err());
+    }
+}
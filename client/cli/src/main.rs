@@ -6,9 +6,12 @@ mod chunking;
 mod config;
 mod daemon;
 mod db;
+mod doctor;
+mod retry;
 mod sync;
 
 use config::Config;
+use retry::RetryPolicy;
 
 #[derive(Parser)]
 #[command(name = "tangle")]
@@ -27,11 +30,24 @@ enum Commands {
         /// Run in foreground (don't daemonize)
         #[arg(long)]
         foreground: bool,
+        /// Ignore the saved sync cursor: rehash every local file and replay all remote changes
+        #[arg(long)]
+        full: bool,
+        /// Milliseconds a file must be left alone before it is uploaded (overrides `watch_debounce_ms`)
+        #[arg(long, value_name = "MS")]
+        watch_debounce: Option<u64>,
+        /// Skip checking downloaded files against the server's BLAKE3 hash (overrides `verify_downloads`)
+        #[arg(long)]
+        no_verify: bool,
     },
     /// Stop sync daemon
     Stop,
     /// Show daemon status and sync state
-    Status,
+    Status {
+        /// List what the next sync would upload, download, delete or conflict on, without doing it
+        #[arg(long)]
+        sync_preview: bool,
+    },
     /// List synced files
     Ls {
         /// Path prefix filter
@@ -43,24 +59,60 @@ enum Commands {
         /// File path
         path: String,
     },
+    /// Download one file without running the daemon
+    Fetch {
+        /// Path on the server, e.g. /docs/report.pdf
+        remote_path: String,
+        /// Where to write it (a directory keeps the remote name), or - for stdout
+        local_path: String,
+    },
+    /// Write a remote file's bytes to stdout
+    Cat {
+        /// Path on the server, e.g. /logs/app.log
+        path: String,
+        /// Start this many bytes into the file
+        #[arg(long, default_value_t = 0)]
+        offset: u64,
+        /// Write at most this many bytes
+        #[arg(long)]
+        length: Option<u64>,
+    },
+    /// Upload one file without a sync directory
+    Push {
+        /// File to upload
+        local_path: String,
+        /// Path on the server; a trailing / keeps the local name
+        remote_path: String,
+        /// Note to store on the new version, shown by `tangle history`
+        #[arg(short = 'm', long)]
+        note: Option<String>,
+    },
     /// Clear credentials and stop syncing
     Logout,
+    /// Check config, server, credentials, sync directory and clock
+    Doctor,
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
+
+    // Doctor must run even when the config itself is broken
+    if let Some(Commands::Doctor) = &cli.command {
+        return doctor::run().await;
+    }
+
     let config = Config::load()?;
 
     // Commands that don't need logging
     match &cli.command {
         Some(Commands::Stop) => return daemon::stop(),
-        Some(Commands::Start { foreground }) if !foreground => {
+        Some(Commands::Start { foreground, full, watch_debounce, no_verify }) if !foreground => {
             if !config.is_configured() {
                 println!("not configured. run: tangle setup");
                 return Ok(());
             }
-            let pid = daemon::start()?;
+            let pid = daemon::start(*full, *watch_debounce, *no_verify)?;
             println!("tangle started (pid {})", pid);
             if let Some(dir) = &config.sync_directory {
                 println!("syncing: {}", dir);
@@ -73,7 +125,7 @@ async fn main() -> anyhow::Result<()> {
                     println!("tangle already running (pid {})", pid);
                     return Ok(());
                 }
-                let pid = daemon::start()?;
+                let pid = daemon::start(false, None, false)?;
                 println!("tangle started (pid {})", pid);
                 return Ok(());
             } else {
@@ -94,19 +146,40 @@ async fn main() -> anyhow::Result<()> {
 
     match cli.command {
         Some(Commands::Setup) => run_setup().await,
-        Some(Commands::Start { .. }) => {
+        Some(Commands::Start { full, watch_debounce, no_verify, .. }) => {
             // Foreground mode
             config.require_auth()?;
+            let mut config = config;
+            if watch_debounce.is_some() {
+                config.watch_debounce_ms = watch_debounce;
+            }
+            if no_verify {
+                config.verify_downloads = Some(false);
+            }
             daemon::write_pid(std::process::id())?;
-            let result = sync::run(&config).await;
+            let result = sync::run(&config, full).await;
             let _ = daemon::remove_pid();
             result
         }
         Some(Commands::Stop) => unreachable!(),
-        Some(Commands::Status) => cmd_status(&config),
+        Some(Commands::Status { sync_preview }) => {
+            cmd_status(&config)?;
+            if sync_preview {
+                cmd_sync_preview(&config).await?;
+            }
+            Ok(())
+        }
         Some(Commands::Ls { path }) => cmd_list(&config, &path).await,
         Some(Commands::History { path }) => cmd_history(&config, &path).await,
+        Some(Commands::Fetch { remote_path, local_path }) => {
+            cmd_fetch(&config, &remote_path, &local_path).await
+        }
+        Some(Commands::Cat { path, offset, length }) => cmd_cat(&config, &path, offset, length).await,
+        Some(Commands::Push { local_path, remote_path, note }) => {
+            cmd_push(&config, &local_path, &remote_path, note.as_deref()).await
+        }
         Some(Commands::Logout) => cmd_logout(),
+        Some(Commands::Doctor) => unreachable!(),
         None => unreachable!(),
     }
 }
@@ -123,9 +196,9 @@ async fn run_setup() -> anyhow::Result<()> {
         format!("http://{}", server_url)
     };
 
-    // Test connection
+    // Test connection, reporting a bad URL at once rather than retrying it
     print!("connecting... ");
-    let client = api::ApiClient::new(&server_url);
+    let client = api::ApiClient::new(&server_url)?.with_retry(RetryPolicy::default().with_max_attempts(1));
     let info = client.get_server_info().await?;
     println!("connected to {} (v{})", info.name, info.version);
 
@@ -134,7 +207,16 @@ async fn run_setup() -> anyhow::Result<()> {
     let password = rpassword::prompt_password("password: ")?;
 
     print!("logging in... ");
-    let tokens = client.login(&username, &password).await?;
+    let tokens = match client.login(&username, &password, None).await {
+        // The server asks for a second factor once TOTP is enabled
+        Err(e) if e.to_string().contains("TOTP code required") => {
+            println!();
+            let code = prompt("totp code")?;
+            print!("logging in... ");
+            client.login(&username, &password, Some(&code)).await?
+        }
+        result => result?,
+    };
     println!("ok");
 
     // Sync directory
@@ -148,13 +230,19 @@ async fn run_setup() -> anyhow::Result<()> {
     std::fs::create_dir_all(&sync_dir)?;
     println!("sync directory: {}", sync_dir);
 
-    // Save config
+    // Save config, keeping settings that setup doesn't ask about
+    let previous = Config::load().unwrap_or_default();
     let config = Config {
         server_url: Some(server_url),
         username: Some(username),
         auth_token: Some(tokens.token),
         refresh_token: Some(tokens.refresh_token),
         sync_directory: Some(sync_dir),
+        conflict_template: previous.conflict_template,
+        watch_debounce_ms: previous.watch_debounce_ms,
+        verify_downloads: previous.verify_downloads,
+        max_attempts: previous.max_attempts,
+        report_dedup: previous.report_dedup,
     };
     config.save()?;
 
@@ -212,6 +300,15 @@ fn cmd_status(config: &Config) -> anyhow::Result<()> {
             Some(pid) => println!("daemon: running (pid {})", pid),
             None => println!("daemon: stopped"),
         }
+        let totals = db::LocalDb::open()?.upload_totals()?;
+        if !totals.is_empty() {
+            println!(
+                "uploads: {} sent, {} skipped via dedup ({:.0}% saved)",
+                format_size(totals.uploaded_bytes),
+                format_size(totals.deduped_bytes),
+                totals.saved_percent()
+            );
+        }
     } else {
         println!("not configured");
         println!("run: tangle setup");
@@ -219,9 +316,40 @@ fn cmd_status(config: &Config) -> anyhow::Result<()> {
     Ok(())
 }
 
+async fn cmd_sync_preview(config: &Config) -> anyhow::Result<()> {
+    config.require_auth()?;
+    let plan = sync::plan(config).await?;
+
+    println!();
+    if plan.is_empty() {
+        println!("nothing to sync");
+        return Ok(());
+    }
+
+    println!("sync preview:");
+    for (action, paths) in [
+        ("upload", &plan.uploads),
+        ("download", &plan.downloads),
+        ("delete", &plan.deletes),
+        ("conflict", &plan.conflicts),
+    ] {
+        for path in paths {
+            println!("  {:<9} {}", action, path);
+        }
+    }
+    println!(
+        "{} upload(s), {} download(s), {} delete(s), {} conflict(s)",
+        plan.uploads.len(),
+        plan.downloads.len(),
+        plan.deletes.len(),
+        plan.conflicts.len()
+    );
+    Ok(())
+}
+
 async fn cmd_list(config: &Config, _prefix: &str) -> anyhow::Result<()> {
     config.require_auth()?;
-    let client = api::ApiClient::new(config.server_url()?);
+    let client = api::ApiClient::new(config.server_url()?)?.with_retry(config.retry_policy());
     let files = client.list_files(config.auth_token()?).await?;
 
     if files.is_empty() {
@@ -242,7 +370,7 @@ async fn cmd_list(config: &Config, _prefix: &str) -> anyhow::Result<()> {
 
 async fn cmd_history(config: &Config, path: &str) -> anyhow::Result<()> {
     config.require_auth()?;
-    let client = api::ApiClient::new(config.server_url()?);
+    let client = api::ApiClient::new(config.server_url()?)?.with_retry(config.retry_policy());
     let token = config.auth_token()?;
 
     // Find file by path
@@ -262,11 +390,169 @@ async fn cmd_history(config: &Config, path: &str) -> anyhow::Result<()> {
     println!("versions of {}:", path);
     for v in versions {
         let size = format_size(v.size_bytes as u64);
-        println!("  {}  {}  {}", &v.id.to_string()[..8], v.created_at, size);
+        let marker = if v.is_current { "*" } else { " " };
+        let by = v.created_by_username.as_deref().unwrap_or("-");
+        println!("{} {}  {}  {:>10}  {}", marker, &v.id.to_string()[..8], v.created_at, size, by);
+        if let Some(note) = &v.note {
+            for line in note.lines() {
+                println!("    {}", line);
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn cmd_fetch(config: &Config, remote_path: &str, local_path: &str) -> anyhow::Result<()> {
+    use std::io::Write;
+
+    config.require_auth()?;
+    let client = api::ApiClient::new(config.server_url()?)?.with_retry(config.retry_policy());
+    let token = config.auth_token()?;
+
+    let (entry, download_id) = resolve_remote_file(&client, token, remote_path).await?;
+    let name = entry.name.as_str();
+
+    if local_path == "-" {
+        let mut stdout = std::io::stdout().lock();
+        client.download_to(token, &download_id, &mut stdout).await?;
+        stdout.flush()?;
+        return Ok(());
+    }
+
+    let mut dest = std::path::PathBuf::from(expand_tilde(local_path));
+    if dest.is_dir() {
+        dest.push(name);
+    }
+    let dir = match dest.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p.to_path_buf(),
+        _ => std::path::PathBuf::from("."),
+    };
+
+    // Download beside the destination and rename, so a failed transfer never
+    // leaves a truncated file in its place
+    let tmp = dir.join(format!(".{}.tangle-fetch", name));
+    let result = async {
+        let mut file = std::fs::File::create(&tmp)?;
+        let written = client.download_to(token, &download_id, &mut file).await?;
+        if let Ok(modified) = chrono::DateTime::parse_from_rfc3339(&entry.updated_at) {
+            file.set_modified(modified.into())?;
+        }
+        file.sync_all()?;
+        std::fs::rename(&tmp, &dest)?;
+        anyhow::Ok(written)
     }
+    .await;
+    let written = result.inspect_err(|_| {
+        let _ = std::fs::remove_file(&tmp);
+    })?;
+
+    println!("{} -> {} ({})", remote_path, dest.display(), format_size(written));
+    Ok(())
+}
+
+/// Stream a remote file, or the `offset`/`length` slice of it, to stdout
+async fn cmd_cat(config: &Config, path: &str, offset: u64, length: Option<u64>) -> anyhow::Result<()> {
+    use std::io::Write;
+
+    config.require_auth()?;
+    let client = api::ApiClient::new(config.server_url()?)?.with_retry(config.retry_policy());
+    let token = config.auth_token()?;
+
+    let (_, download_id) = resolve_remote_file(&client, token, path).await?;
+    let window = api::ByteWindow { skip: offset, remaining: length };
+    let mut stdout = std::io::stdout().lock();
+    client.download_range_to(token, &download_id, window, &mut stdout).await?;
+    stdout.flush()?;
     Ok(())
 }
 
+/// Look up a remote file by path, returning its listing entry and the ID to
+/// download it by
+async fn resolve_remote_file(
+    client: &api::ApiClient,
+    token: &str,
+    remote_path: &str,
+) -> anyhow::Result<(api::DirectoryEntry, String)> {
+    let (parent, name) = split_remote_path(remote_path)
+        .ok_or_else(|| anyhow::anyhow!("Not a file path: {}", remote_path))?;
+    let entry = match client.get_entry(token, remote_path).await {
+        Ok(entry) => entry,
+        // Servers without the lookup take `entry` for a file ID: list the parent
+        Err(e)
+            if e
+                .downcast_ref::<api::ApiError>()
+                .is_some_and(|e| e.status == reqwest::StatusCode::BAD_REQUEST) =>
+        {
+            client
+                .list_directory(token, parent)
+                .await?
+                .into_iter()
+                .find(|e| e.name == name)
+        }
+        Err(e) => return Err(e),
+    }
+    .ok_or_else(|| anyhow::anyhow!("File not found: {}", remote_path))?;
+    if entry.is_folder {
+        anyhow::bail!("{} is a folder", remote_path);
+    }
+    // Links have no version of their own; the file ID serves the target's
+    let download_id = entry.version_id.map_or(entry.id.clone(), |v| v.to_string());
+    Ok((entry, download_id))
+}
+
+async fn cmd_push(config: &Config, local_path: &str, remote_path: &str, note: Option<&str>) -> anyhow::Result<()> {
+    config.require_auth()?;
+    let client = api::ApiClient::new(config.server_url()?)?.with_retry(config.retry_policy());
+    let token = config.auth_token()?;
+
+    let local = std::path::PathBuf::from(expand_tilde(local_path));
+    if !local.is_file() {
+        anyhow::bail!("Not a file: {}", local.display());
+    }
+    let mut remote = format!("/{}", remote_path.trim_start_matches('/'));
+    if remote.ends_with('/') {
+        let name = local
+            .file_name()
+            .ok_or_else(|| anyhow::anyhow!("Not a file: {}", local.display()))?;
+        remote.push_str(&name.to_string_lossy());
+    }
+    let (parent, _) = split_remote_path(&remote)
+        .ok_or_else(|| anyhow::anyhow!("Not a file path: {}", remote_path))?;
+
+    // Create the parent folders; existing ones are left as they are
+    let mut dir = String::new();
+    for component in parent.split('/').filter(|c| !c.is_empty()) {
+        dir = format!("{}/{}", dir, component);
+        client.create_directory(token, &dir).await?;
+    }
+
+    let data = std::fs::read(&local)?;
+    let hash = chunking::hash_file(&data);
+    let modified_at = std::fs::metadata(&local)?
+        .modified()
+        .map(chrono::DateTime::<chrono::Utc>::from)
+        .unwrap_or_else(|_| chrono::Utc::now())
+        .to_rfc3339();
+    let (created, stats) = sync::upload_file(&client, token, &local, &remote, &data, &hash, &modified_at, note).await?;
+    if let Ok(db) = db::LocalDb::open() {
+        let _ = db.record_upload_stats(&stats);
+    }
+
+    println!("{} -> {} ({})", local.display(), created.path, format_size(data.len() as u64));
+    println!("version: {}", created.version_id);
+    if config.report_dedup() {
+        println!("{}", sync::upload_summary(&stats));
+    }
+    Ok(())
+}
+
+/// Split a server path into the directory to list and the entry name
+fn split_remote_path(path: &str) -> Option<(&str, &str)> {
+    let path = path.trim_matches('/');
+    let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+    (!name.is_empty()).then_some((parent, name))
+}
+
 fn cmd_logout() -> anyhow::Result<()> {
     let _ = daemon::stop();
     let mut config = Config::load()?;
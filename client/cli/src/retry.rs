@@ -0,0 +1,178 @@
+//! Retries for idempotent API calls
+//!
+//! [`RetryPolicy::run`] repeats an operation that failed with a transient
+//! error (connection refused or reset, timeout, 408/429/502/503/504) after an
+//! exponential backoff with jitter, up to `max_attempts` tries in total. Any
+//! other error is returned at once. Only wrap operations that are safe to
+//! repeat: reads, content-addressed chunk uploads, and creates that succeed
+//! when the target already exists.
+
+use crate::api::ApiError;
+use std::future::Future;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 4;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Tries in total, including the first (1 = never retry)
+    pub max_attempts: u32,
+    /// Backoff before the first retry; doubles for each one after
+    pub base_delay: Duration,
+    /// Longest backoff between two tries
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Backoff before retry number `retry` (from 1): a random point in the
+    /// upper half of the capped exponential delay, so clients that lost the
+    /// connection together don't come back in lockstep
+    fn backoff(&self, retry: u32) -> Duration {
+        let exp = self
+            .base_delay
+            .saturating_mul(1u32.checked_shl(retry.saturating_sub(1)).unwrap_or(u32::MAX));
+        let cap = exp.min(self.max_delay);
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or(0);
+        cap / 2 + cap.mul_f64(f64::from(nanos % 1000) / 2000.0)
+    }
+
+    /// Run `op`, retrying transient failures. `what` names the operation in
+    /// the log.
+    pub async fn run<T, F, Fut>(&self, what: &str, mut op: F) -> anyhow::Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = anyhow::Result<T>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match op().await {
+                Err(e) if attempt < self.max_attempts && is_transient(&e) => {
+                    let delay = self.backoff(attempt);
+                    warn!(
+                        "{} failed (attempt {}/{}): {:#}; retrying in {:?}",
+                        what, attempt, self.max_attempts, e, delay
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Whether a failed call may succeed if repeated unchanged
+pub fn is_transient(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            return e.is_timeout() || e.is_connect() || e.is_request() || e.is_body();
+        }
+        if let Some(e) = cause.downcast_ref::<ApiError>() {
+            return matches!(e.status.as_u16(), 408 | 429 | 502 | 503 | 504);
+        }
+        false
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::StatusCode;
+    use std::cell::Cell;
+
+    fn api_error(status: StatusCode) -> anyhow::Error {
+        ApiError {
+            status,
+            body: String::new(),
+        }
+        .into()
+    }
+
+    fn fast() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(2),
+        }
+    }
+
+    #[test]
+    fn test_backoff_is_capped_and_jittered() {
+        let policy = RetryPolicy::default();
+        for retry in 1..40 {
+            let delay = policy.backoff(retry);
+            let cap = (policy.base_delay * 2u32.pow(retry.min(20) - 1)).min(policy.max_delay);
+            assert!(delay >= cap / 2 && delay <= cap, "retry {}: {:?}", retry, delay);
+        }
+    }
+
+    #[test]
+    fn test_transient_errors() {
+        assert!(is_transient(&api_error(StatusCode::SERVICE_UNAVAILABLE)));
+        assert!(is_transient(&api_error(StatusCode::TOO_MANY_REQUESTS)));
+        assert!(is_transient(&api_error(StatusCode::BAD_GATEWAY).context("listing files")));
+        assert!(!is_transient(&api_error(StatusCode::NOT_FOUND)));
+        assert!(!is_transient(&api_error(StatusCode::INTERNAL_SERVER_ERROR)));
+        assert!(!is_transient(&anyhow::anyhow!("hash mismatch")));
+    }
+
+    #[tokio::test]
+    async fn test_run_retries_transient_failures() {
+        let calls = Cell::new(0);
+        let result = fast()
+            .run("test", || {
+                calls.set(calls.get() + 1);
+                let call = calls.get();
+                async move {
+                    if call < 3 {
+                        Err(api_error(StatusCode::SERVICE_UNAVAILABLE))
+                    } else {
+                        Ok(call)
+                    }
+                }
+            })
+            .await;
+        assert_eq!(result.unwrap(), 3);
+
+        // Gives up after max_attempts
+        calls.set(0);
+        let result: anyhow::Result<()> = fast()
+            .run("test", || {
+                calls.set(calls.get() + 1);
+                async { Err(api_error(StatusCode::GATEWAY_TIMEOUT)) }
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(calls.get(), 3);
+
+        // Permanent errors are returned at once
+        calls.set(0);
+        let result: anyhow::Result<()> = fast()
+            .run("test", || {
+                calls.set(calls.get() + 1);
+                async { Err(api_error(StatusCode::FORBIDDEN)) }
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(calls.get(), 1);
+    }
+}
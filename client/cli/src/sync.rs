@@ -1,9 +1,10 @@
-use crate::api::ApiClient;
+use crate::api::{ApiClient, CreateFileResponse, FileChange};
 use crate::chunking;
 use crate::config::Config;
-use crate::db::{FileRecord, LocalDb};
+use crate::db::{FileRecord, LocalDb, SyncCursor, UploadStats};
+use globset::{GlobBuilder, GlobMatcher};
 use notify::{Event, EventKind, RecursiveMode, Watcher};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap};
 use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
 use tracing::{error, info, warn};
@@ -22,7 +23,12 @@ const DEFAULT_IGNORE_PATTERNS: &[&str] = &[
 ];
 
 /// Run the sync engine: initial sync then watch for changes.
-pub async fn run(config: &Config) -> anyhow::Result<()> {
+///
+/// With a stored cursor for this device the startup pass is incremental:
+/// only local files whose mtime or size differ from the local index are
+/// hashed, and only remote changes after the cursor are fetched. `full` (or a missing cursor) rehashes every
+/// local file and replays the whole changes feed.
+pub async fn run(config: &Config, full: bool) -> anyhow::Result<()> {
     let sync_dir = config
         .sync_directory
         .as_ref()
@@ -34,103 +40,340 @@ pub async fn run(config: &Config) -> anyhow::Result<()> {
     }
 
     let db = LocalDb::open()?;
-    let api = ApiClient::new(config.server_url()?);
+    let api = ApiClient::new(config.server_url()?)?.with_retry(config.retry_policy());
     let token = config.auth_token()?;
     let ignore_patterns = load_ignore_patterns(&sync_path);
 
-    // Initial sync
-    info!("starting initial sync");
-    sync_local_changes(&api, token, &db, &sync_path, &ignore_patterns).await?;
-    sync_remote_changes(&api, token, &db, &sync_path).await?;
-    process_retries(&api, token, &db, &sync_path, &ignore_patterns).await;
+    if full {
+        db.clear_sync_cursor()?;
+    }
+    let full = match db.get_sync_cursor()? {
+        Some(cursor) => {
+            info!("resuming sync from {}", cursor.server_time);
+            false
+        }
+        None => {
+            info!("starting full sync");
+            true
+        }
+    };
+
+    let naming = ConflictNaming::from_config(config)?;
+    let verify = config.verify_downloads();
+
+    // Initial sync. Remote changes go first so edits made on both sides
+    // while offline become conflict copies instead of overwriting the server.
+    sync_remote_changes(&api, token, &db, &sync_path, &naming, verify).await?;
+    let mut uploads = sync_local_changes(&api, token, &db, &sync_path, &ignore_patterns, full).await?;
+    uploads.add(process_retries(&api, token, &db, &sync_path, &ignore_patterns).await);
+    report_uploads(config, &uploads);
 
     // Watch for changes
     info!("watching: {}", sync_dir);
-    watch_and_sync(config, &api, &db, &sync_path, &ignore_patterns).await
+    watch_and_sync(config, &api, &db, &sync_path, &ignore_patterns, &naming).await
 }
 
-/// Walk the sync directory and upload any files that have changed since last sync.
-async fn sync_local_changes(
+/// What a sync would do, computed without touching local files, the local
+/// database, the changes cursor or the server.
+#[derive(Debug, Default)]
+pub struct SyncPlan {
+    /// Local files that are new or changed since they were last synced
+    pub uploads: Vec<String>,
+    /// Remote files that are new or changed and would be written locally
+    pub downloads: Vec<String>,
+    /// Files deleted on the server that would be removed locally
+    pub deletes: Vec<String>,
+    /// Files changed both locally and on the server since the last sync
+    pub conflicts: Vec<String>,
+}
+
+impl SyncPlan {
+    pub fn is_empty(&self) -> bool {
+        self.uploads.is_empty()
+            && self.downloads.is_empty()
+            && self.deletes.is_empty()
+            && self.conflicts.is_empty()
+    }
+}
+
+/// Dry run: work out what the next sync would do without doing any of it.
+pub async fn plan(config: &Config) -> anyhow::Result<SyncPlan> {
+    let sync_dir = config
+        .sync_directory
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("No sync directory configured"))?;
+    let sync_path = PathBuf::from(sync_dir);
+
+    let db = LocalDb::open()?;
+    let api = ApiClient::new(config.server_url()?)?.with_retry(config.retry_policy());
+    let token = config.auth_token()?;
+    let ignore_patterns = load_ignore_patterns(&sync_path);
+
+    let local = scan_local_changes(&db, &sync_path, &ignore_patterns)?;
+    // Nothing changed here and the server holds what the index says it
+    // does: skip reading the change feed. Servers without the endpoint
+    // fall through to it.
+    if local.is_empty() {
+        if let Ok(remote) = api.get_state_hash(token, "/").await {
+            if (remote.hash, remote.file_count) == db.state_hash()? {
+                return Ok(SyncPlan::default());
+            }
+        }
+    }
+    let remote = fetch_remote_changes(&api, token, &db).await?;
+    build_plan(&db, &sync_path, local, &remote)
+}
+
+/// Local files that differ from the last synced state, keyed by remote path,
+/// with their hash. Files whose mtime and size match the index aren't read.
+fn scan_local_changes(
+    db: &LocalDb,
+    root: &Path,
+    ignore_patterns: &IgnoreRules,
+) -> anyhow::Result<BTreeMap<String, String>> {
+    let mut changed = BTreeMap::new();
+    for entry in local_files(root, ignore_patterns) {
+        if stat_unchanged(db, root, entry.path())? {
+            continue;
+        }
+        let data = std::fs::read(entry.path())?;
+        let hash = chunking::hash_file(&data);
+        let remote_path = to_remote_path(root, entry.path());
+        let known = db.get_file(&remote_path)?.map(|r| r.blake3_hash);
+        if known.as_deref() != Some(hash.as_str()) {
+            changed.insert(remote_path, hash);
+        }
+    }
+    Ok(changed)
+}
+
+/// Fetch every pending remote change without advancing the stored cursor.
+async fn fetch_remote_changes(
     api: &ApiClient,
     token: &str,
     db: &LocalDb,
+) -> anyhow::Result<Vec<FileChange>> {
+    let stored = db.get_sync_cursor()?;
+    let since = stored.as_ref().map(|c| c.server_time.clone());
+    let mut cursor = stored.and_then(|c| c.changes_cursor);
+
+    let mut changes = Vec::new();
+    loop {
+        let resp = api
+            .get_changes(token, since.as_deref(), cursor.as_deref())
+            .await?;
+        changes.extend(resp.changes);
+        if !resp.has_more || resp.next_cursor.is_none() {
+            break;
+        }
+        cursor = resp.next_cursor;
+    }
+    Ok(changes)
+}
+
+/// Combine local and remote changes into a plan, mirroring the decisions
+/// `sync_local_changes` and `sync_remote_changes` make.
+fn build_plan(
+    db: &LocalDb,
     root: &Path,
-    ignore_patterns: &[String],
-) -> anyhow::Result<()> {
-    let walker = walkdir::WalkDir::new(root)
+    mut local: BTreeMap<String, String>,
+    remote: &[FileChange],
+) -> anyhow::Result<SyncPlan> {
+    let mut plan = SyncPlan::default();
+
+    for change in remote {
+        if change.is_directory {
+            continue;
+        }
+        match change.action.as_str() {
+            "created" | "modified" => {
+                let known = db.get_file(&change.path)?.map(|r| r.blake3_hash);
+                if change.blob_hash.is_some() && change.blob_hash == known {
+                    // Remote side unchanged; any local edit is a plain upload
+                    continue;
+                }
+                match local.remove(&change.path) {
+                    // Both sides already hold the same content
+                    Some(hash) if change.blob_hash.as_deref() == Some(hash.as_str()) => {}
+                    Some(_) => plan.conflicts.push(change.path.clone()),
+                    None => plan.downloads.push(change.path.clone()),
+                }
+            }
+            "deleted" => {
+                if local.remove(&change.path).is_some() {
+                    plan.conflicts.push(change.path.clone());
+                } else if root.join(change.path.trim_start_matches('/')).exists() {
+                    plan.deletes.push(change.path.clone());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    plan.uploads.extend(local.into_keys());
+    plan.downloads.sort();
+    plan.deletes.sort();
+    plan.conflicts.sort();
+    Ok(plan)
+}
+
+/// Regular files under the sync root that aren't ignored.
+fn local_files<'a>(
+    root: &'a Path,
+    ignore_patterns: &'a IgnoreRules,
+) -> impl Iterator<Item = walkdir::DirEntry> + 'a {
+    walkdir::WalkDir::new(root)
         .into_iter()
+        // Prune ignored directories instead of walking into them
+        .filter_entry(move |e| e.depth() == 0 || !should_ignore(e.path(), root, ignore_patterns))
         .filter_map(|e| e.ok())
-        .filter(|e| e.file_type().is_file());
+        .filter(|e| e.file_type().is_file())
+}
 
+/// Walk the sync directory and upload any files that have changed since last sync.
+///
+/// Unless `full` is set, files whose mtime and size match the local index
+/// are skipped without being read.
+async fn sync_local_changes(
+    api: &ApiClient,
+    token: &str,
+    db: &LocalDb,
+    root: &Path,
+    ignore_patterns: &IgnoreRules,
+    full: bool,
+) -> anyhow::Result<UploadStats> {
     let mut count = 0;
-    for entry in walker {
+    let mut uploads = UploadStats::default();
+    for entry in local_files(root, ignore_patterns) {
         let file_path = entry.path();
-        if should_ignore(file_path, root, ignore_patterns) {
+        if !full && stat_unchanged(db, root, file_path)? {
             continue;
         }
 
-        if let Err(e) = upload_if_changed(api, token, db, root, file_path).await {
-            warn!("sync failed {}: {}", file_path.display(), e);
-            let remote_path = to_remote_path(root, file_path);
-            let _ = db.add_retry(&remote_path, &e.to_string());
-        } else {
-            count += 1;
+        match upload_if_changed(api, token, db, root, file_path).await {
+            Ok(stats) => {
+                uploads.add(stats);
+                count += 1;
+            }
+            Err(e) => {
+                warn!("sync failed {}: {}", file_path.display(), e);
+                let remote_path = to_remote_path(root, file_path);
+                let _ = db.add_retry(&remote_path, &e.to_string());
+            }
         }
     }
 
     if count > 0 {
         info!("synced {} local files", count);
     }
-    Ok(())
+    Ok(uploads)
+}
+
+/// Log how much of a sync cycle's uploads was sent and how much the server
+/// already had, unless `report_dedup` is off
+fn report_uploads(config: &Config, stats: &UploadStats) {
+    if config.report_dedup() && !stats.is_empty() {
+        info!("{}", upload_summary(stats));
+    }
+}
+
+/// e.g. "uploaded 12.0 MB, skipped 340.0 MB via dedup (3 files)"
+pub fn upload_summary(stats: &UploadStats) -> String {
+    format!(
+        "uploaded {}, skipped {} via dedup ({} file{})",
+        crate::format_size(stats.uploaded_bytes),
+        crate::format_size(stats.deduped_bytes),
+        stats.files,
+        if stats.files == 1 { "" } else { "s" }
+    )
+}
+
+/// Whether the file's mtime and size still match what was recorded at its
+/// last sync, long enough after the mtime to be trusted (see
+/// [`LocalDb::stat_matches`]).
+fn stat_unchanged(db: &LocalDb, root: &Path, file_path: &Path) -> anyhow::Result<bool> {
+    let stat = FileStat::of(file_path)?;
+    db.stat_matches(&to_remote_path(root, file_path), stat.mtime, stat.size)
 }
 
-/// Hash file, compare with DB, upload if changed.
+/// The metadata the local index compares a file by
+#[derive(Debug, Clone, Copy)]
+struct FileStat {
+    /// Modification time in whole seconds since the Unix epoch
+    mtime: i64,
+    size: i64,
+}
+
+impl FileStat {
+    fn of(path: &Path) -> anyhow::Result<Self> {
+        let metadata = std::fs::metadata(path)?;
+        Ok(Self {
+            mtime: metadata.modified()?.duration_since(std::time::UNIX_EPOCH)?.as_secs() as i64,
+            size: metadata.len() as i64,
+        })
+    }
+}
+
+/// Hash file, compare with DB, upload if changed. Returns what the upload
+/// sent and skipped (nothing if the file was unchanged).
 async fn upload_if_changed(
     api: &ApiClient,
     token: &str,
     db: &LocalDb,
     root: &Path,
     file_path: &Path,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<UploadStats> {
+    // Stat before reading: a write during the upload leaves a newer mtime
+    // than the one recorded, so the file is looked at again
+    let stat = FileStat::of(file_path)?;
     let data = std::fs::read(file_path)?;
     let hash = chunking::hash_file(&data);
     let remote_path = to_remote_path(root, file_path);
 
-    // Skip if unchanged
+    // Skip if unchanged, but keep the new stat so it isn't read again
     if let Some(record) = db.get_file(&remote_path)? {
         if record.blake3_hash == hash {
-            return Ok(());
+            db.upsert_file(&FileRecord {
+                last_modified: stat.mtime,
+                size: Some(stat.size),
+                ..record
+            })?;
+            return Ok(UploadStats::default());
         }
     }
 
     info!("uploading: {}", remote_path);
-    upload_file(api, token, file_path, &remote_path, &data, &hash).await?;
-
-    let mtime = std::fs::metadata(file_path)?
-        .modified()?
-        .duration_since(std::time::UNIX_EPOCH)?
-        .as_secs() as i64;
+    let modified_at = chrono::Utc::now().to_rfc3339();
+    let (created, stats) = upload_file(api, token, file_path, &remote_path, &data, &hash, &modified_at, None).await?;
 
     db.upsert_file(&FileRecord {
         path: remote_path.clone(),
         blake3_hash: hash,
-        last_modified: mtime,
+        last_modified: stat.mtime,
+        size: Some(stat.size),
+        version_id: Some(created.version_id.to_string()),
         sync_cursor: None,
     })?;
     let _ = db.clear_retry(&remote_path);
+    let _ = db.record_upload_stats(&stats);
 
-    Ok(())
+    Ok(stats)
 }
 
 /// Chunk a file, upload missing chunks to server, then create the file record.
-async fn upload_file(
+/// Also returns how many bytes were sent and how many the server already had.
+/// `note` is stored on the new version and shown in its history.
+#[allow(clippy::too_many_arguments)]
+pub async fn upload_file(
     api: &ApiClient,
     token: &str,
     file_path: &Path,
     remote_path: &str,
     data: &[u8],
     content_hash: &str,
-) -> anyhow::Result<()> {
+    modified_at: &str,
+    note: Option<&str>,
+) -> anyhow::Result<(CreateFileResponse, UploadStats)> {
     let chunks = chunking::chunk_file(file_path, data);
     let tier = chunking::select_tier(file_path, data.len() as u64);
 
@@ -139,76 +382,127 @@ async fn upload_file(
     let check = api.check_chunks(token, &chunk_hashes).await?;
 
     // Upload only missing chunks
+    let mut stats = UploadStats { files: 1, ..UploadStats::default() };
     for chunk in &chunks {
         if check.missing.contains(&chunk.hash) {
             api.upload_chunk(token, &chunk.hash, &chunk.data, tier.id())
                 .await?;
+            stats.uploaded_chunks += 1;
+            stats.uploaded_bytes += chunk.data.len() as u64;
+        } else {
+            stats.deduped_chunks += 1;
+            stats.deduped_bytes += chunk.data.len() as u64;
         }
     }
 
     // Create file record from chunks
-    let modified_at = chrono::Utc::now().to_rfc3339();
-    api.create_file(
+    let created = api.create_file(
         token,
         remote_path,
         data.len() as i64,
-        &modified_at,
+        modified_at,
         tier.id(),
         content_hash,
         chunk_hashes,
+        note,
     )
     .await?;
-
-    Ok(())
+    Ok((created, stats))
 }
 
 /// Poll server for remote changes and download new/modified files.
+///
+/// With `verify`, each download is checked against the version's content hash.
 async fn sync_remote_changes(
     api: &ApiClient,
     token: &str,
     db: &LocalDb,
     root: &Path,
+    naming: &ConflictNaming,
+    verify: bool,
 ) -> anyhow::Result<()> {
-    let since = db.get_last_sync_time()?;
-    let resp = api.get_changes(token, since.as_deref()).await?;
+    let stored = db.get_sync_cursor()?;
+    let since = stored.as_ref().map(|c| c.server_time.clone());
+    let mut cursor = stored.and_then(|c| c.changes_cursor);
 
     let mut count = 0;
-    for change in &resp.changes {
-        if change.is_directory {
-            continue;
-        }
+    loop {
+        let resp = api
+            .get_changes(token, since.as_deref(), cursor.as_deref())
+            .await?;
 
-        let local_path = root.join(change.path.trim_start_matches('/'));
+        for change in &resp.changes {
+            if change.is_directory {
+                continue;
+            }
 
-        match change.action.as_str() {
-            "created" | "modified" => {
-                // Skip if we already have this version
-                if let Some(record) = db.get_file(&change.path)? {
-                    if change.blob_hash.as_deref() == Some(&record.blake3_hash) {
-                        continue;
+            let local_path = root.join(change.path.trim_start_matches('/'));
+
+            match change.action.as_str() {
+                "created" | "modified" => {
+                    // Skip if we already have this content, noting the version it now is
+                    if let Some(record) = db.get_file(&change.path)? {
+                        if change.blob_hash.as_deref() == Some(&record.blake3_hash) {
+                            if let Some(version_id) = &change.version_id {
+                                if record.version_id.as_ref() != Some(version_id) {
+                                    db.set_version_id(&change.path, version_id)?;
+                                }
+                            }
+                            continue;
+                        }
                     }
-                }
 
-                match download_remote_file(api, token, db, &change.path, change.id, &local_path)
-                    .await
-                {
-                    Ok(_) => count += 1,
-                    Err(e) => warn!("download failed {}: {}", change.path, e),
+                    // Changed on both sides: move the local edits aside first
+                    if let Some(local_hash) = local_edit(db, root, &change.path, &local_path)? {
+                        if change.blob_hash.as_deref() != Some(local_hash.as_str()) {
+                            match save_conflict_copy(api, token, db, root, naming, &local_path).await {
+                                Ok(copy) => warn!(
+                                    "conflict: {} changed locally and on the server; local edits saved as {}",
+                                    change.path,
+                                    to_remote_path(root, &copy)
+                                ),
+                                Err(e) => {
+                                    warn!("conflict on {} left unresolved: {}", change.path, e);
+                                    continue;
+                                }
+                            }
+                        }
+                    }
+
+                    match download_remote_file(api, token, db, &change.path, change.id, &local_path, verify)
+                        .await
+                    {
+                        Ok(_) => count += 1,
+                        Err(e) => warn!("download failed {}: {}", change.path, e),
+                    }
                 }
-            }
-            "deleted" => {
-                if local_path.exists() {
-                    info!("remote deleted: {}", change.path);
-                    let _ = std::fs::remove_file(&local_path);
+                "deleted" => {
+                    if apply_remote_delete(db, root, &change.path, &local_path)? {
+                        // Edited here after the server deleted it: keep it and re-upload
+                        info!("remote deleted {} but it has local edits; keeping it", change.path);
+                        if let Err(e) = upload_if_changed(api, token, db, root, &local_path).await {
+                            let _ = db.add_retry(&change.path, &e.to_string());
+                        }
+                    }
+                    count += 1;
                 }
-                let _ = db.remove_file(&change.path);
-                count += 1;
+                _ => {}
             }
-            _ => {}
         }
-    }
 
-    db.set_last_sync_time(&resp.server_time)?;
+        // Keep the previous keyset position if this page carried none
+        let changes_cursor = resp.next_cursor.clone().or_else(|| cursor.clone());
+        db.set_sync_cursor(&SyncCursor {
+            server_time: resp.server_time.clone(),
+            changes_cursor,
+        })?;
+
+        // Older servers have no cursor, so paging would repeat the same page
+        if !resp.has_more || resp.next_cursor.is_none() {
+            break;
+        }
+        cursor = resp.next_cursor;
+    }
 
     if count > 0 {
         info!("applied {} remote changes", count);
@@ -216,7 +510,121 @@ async fn sync_remote_changes(
     Ok(())
 }
 
+/// How conflict copies are named (see `Config::conflict_template`)
+pub struct ConflictNaming {
+    template: String,
+    server: String,
+}
+
+impl ConflictNaming {
+    fn from_config(config: &Config) -> anyhow::Result<Self> {
+        let server = reqwest::Url::parse(config.server_url()?)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+            .unwrap_or_else(|| "server".to_string());
+        Ok(Self {
+            template: config.conflict_template().to_string(),
+            server,
+        })
+    }
+
+    /// An unused path next to `local_path` for its conflict copy.
+    fn copy_path(&self, local_path: &Path) -> PathBuf {
+        let date = chrono::Local::now().format("%Y-%m-%d").to_string();
+        let stem = local_path
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let ext = local_path
+            .extension()
+            .map(|e| format!(".{}", e.to_string_lossy()))
+            .unwrap_or_default();
+
+        let mut candidate =
+            local_path.with_file_name(conflict_file_name(&self.template, &stem, &ext, &date, &self.server));
+        let mut n = 2;
+        while candidate.exists() {
+            let numbered = format!("{} {}", stem, n);
+            candidate = local_path
+                .with_file_name(conflict_file_name(&self.template, &numbered, &ext, &date, &self.server));
+            n += 1;
+        }
+        candidate
+    }
+}
+
+/// Expand a conflict name template. `{name}` goes last so placeholders
+/// inside the file name itself are left alone.
+fn conflict_file_name(template: &str, name: &str, ext: &str, date: &str, server: &str) -> String {
+    template
+        .replace("{date}", date)
+        .replace("{server}", server)
+        .replace("{ext}", ext)
+        .replace("{name}", name)
+        .replace(['/', '\\'], "-")
+}
+
+/// Remove the local copy of a file the server deleted, then its index
+/// entry. A copy edited since its last sync is kept instead, and true is
+/// returned so it gets uploaded again; its entry stays until that upload
+/// replaces it, since without it the copy would look edited on every pass.
+fn apply_remote_delete(db: &LocalDb, root: &Path, remote_path: &str, local_path: &Path) -> anyhow::Result<bool> {
+    if local_edit(db, root, remote_path, local_path)?.is_some() {
+        return Ok(true);
+    }
+    if local_path.exists() {
+        info!("remote deleted: {}", remote_path);
+        if let Err(e) = std::fs::remove_file(local_path) {
+            // Keep the entry so the copy isn't taken for a new file and uploaded
+            warn!("couldn't remove {}: {}", local_path.display(), e);
+            return Ok(false);
+        }
+    }
+    db.remove_file(remote_path)?;
+    Ok(false)
+}
+
+/// Hash of the local file if it was edited since its last sync (or exists
+/// without ever having been synced), None if it is missing or unchanged.
+fn local_edit(
+    db: &LocalDb,
+    root: &Path,
+    remote_path: &str,
+    local_path: &Path,
+) -> anyhow::Result<Option<String>> {
+    if !local_path.is_file() || stat_unchanged(db, root, local_path)? {
+        return Ok(None);
+    }
+    let hash = chunking::hash_file(&std::fs::read(local_path)?);
+    let known = db.get_file(remote_path)?.map(|r| r.blake3_hash);
+    Ok((known.as_deref() != Some(hash.as_str())).then_some(hash))
+}
+
+/// Rename a locally edited file to its conflict copy name and upload it,
+/// freeing the original path for the server's version.
+async fn save_conflict_copy(
+    api: &ApiClient,
+    token: &str,
+    db: &LocalDb,
+    root: &Path,
+    naming: &ConflictNaming,
+    local_path: &Path,
+) -> anyhow::Result<PathBuf> {
+    let copy = naming.copy_path(local_path);
+    std::fs::rename(local_path, &copy)?;
+
+    // The edits are safe on disk now; a failed upload just gets retried
+    if let Err(e) = upload_if_changed(api, token, db, root, &copy).await {
+        let _ = db.add_retry(&to_remote_path(root, &copy), &e.to_string());
+    }
+    Ok(copy)
+}
+
 /// Download a file from the server and write it locally.
+///
+/// With `verify`, the reassembled content must hash to the version's BLAKE3
+/// content hash. A mismatch is downloaded once more before giving up, and the
+/// local file is left untouched unless the content checks out.
 async fn download_remote_file(
     api: &ApiClient,
     token: &str,
@@ -224,6 +632,7 @@ async fn download_remote_file(
     remote_path: &str,
     file_id: uuid::Uuid,
     local_path: &Path,
+    verify: bool,
 ) -> anyhow::Result<()> {
     let versions = api.get_file_versions(token, file_id).await?;
     let latest = versions
@@ -231,23 +640,38 @@ async fn download_remote_file(
         .ok_or_else(|| anyhow::anyhow!("No versions for {}", remote_path))?;
 
     info!("downloading: {}", remote_path);
-    let data = api.download_file(token, latest.id).await?;
+    let mut data = api.download_file(token, latest.id).await?;
+    let mut hash = chunking::hash_file(&data);
+    if verify && hash != latest.blob_hash {
+        warn!(
+            "download of {} hashed to {}, expected {}; retrying",
+            remote_path, hash, latest.blob_hash
+        );
+        data = api.download_file(token, latest.id).await?;
+        hash = chunking::hash_file(&data);
+        if hash != latest.blob_hash {
+            anyhow::bail!(
+                "verification failed for {}: content hashed to {}, expected {}",
+                remote_path,
+                hash,
+                latest.blob_hash
+            );
+        }
+    }
 
     if let Some(parent) = local_path.parent() {
         std::fs::create_dir_all(parent)?;
     }
     std::fs::write(local_path, &data)?;
 
-    let hash = chunking::hash_file(&data);
-    let mtime = std::fs::metadata(local_path)?
-        .modified()?
-        .duration_since(std::time::UNIX_EPOCH)?
-        .as_secs() as i64;
+    let stat = FileStat::of(local_path)?;
 
     db.upsert_file(&FileRecord {
         path: remote_path.to_string(),
         blake3_hash: hash,
-        last_modified: mtime,
+        last_modified: stat.mtime,
+        size: Some(stat.size),
+        version_id: Some(latest.id.to_string()),
         sync_cursor: None,
     })?;
 
@@ -260,18 +684,22 @@ async fn process_retries(
     token: &str,
     db: &LocalDb,
     root: &Path,
-    ignore_patterns: &[String],
-) {
+    ignore_patterns: &IgnoreRules,
+) -> UploadStats {
+    let mut uploads = UploadStats::default();
     let retries = match db.get_pending_retries() {
         Ok(r) => r,
-        Err(_) => return,
+        Err(_) => return uploads,
     };
 
     for retry in retries {
         let local_path = root.join(retry.path.trim_start_matches('/'));
         if local_path.exists() && !should_ignore(&local_path, root, ignore_patterns) {
             match upload_if_changed(api, token, db, root, &local_path).await {
-                Ok(_) => info!("retry succeeded: {}", retry.path),
+                Ok(stats) => {
+                    uploads.add(stats);
+                    info!("retry succeeded: {}", retry.path);
+                }
                 Err(e) => warn!(
                     "retry failed (attempt {}): {}: {}",
                     retry.attempts + 1,
@@ -281,6 +709,7 @@ async fn process_retries(
             }
         }
     }
+    uploads
 }
 
 /// Watch directory for filesystem events and sync changes.
@@ -289,8 +718,10 @@ async fn watch_and_sync(
     api: &ApiClient,
     db: &LocalDb,
     root: &Path,
-    ignore_patterns: &[String],
+    ignore_patterns: &IgnoreRules,
+    naming: &ConflictNaming,
 ) -> anyhow::Result<()> {
+    let mut ignore_patterns = ignore_patterns.clone();
     let (tx, rx) = std::sync::mpsc::channel();
 
     let mut watcher = notify::recommended_watcher(move |res: Result<Event, notify::Error>| {
@@ -302,57 +733,66 @@ async fn watch_and_sync(
     watcher.watch(root, RecursiveMode::Recursive)?;
 
     let token = config.auth_token()?;
-    let mut pending_paths: HashSet<PathBuf> = HashSet::new();
-    let mut last_event = Instant::now();
+    let mut pending = Debouncer::new(config.watch_debounce());
     let mut last_poll = Instant::now();
-    let debounce = Duration::from_millis(500);
     let poll_interval = Duration::from_secs(30);
 
     loop {
         match rx.recv_timeout(Duration::from_millis(100)) {
             Ok(event) => {
                 for path in event.paths {
-                    if should_ignore(&path, root, ignore_patterns) {
+                    if is_ignore_file(&path, root) {
+                        ignore_patterns = load_ignore_patterns(root);
+                        info!("reloaded ignore rules ({} patterns)", ignore_patterns.len());
+                    }
+                    if should_ignore(&path, root, &ignore_patterns) {
                         continue;
                     }
-                    match event.kind {
-                        EventKind::Create(_) | EventKind::Modify(_) => {
-                            if path.is_file() {
-                                pending_paths.insert(path);
-                            }
-                        }
-                        EventKind::Remove(_) => {
-                            let remote = to_remote_path(root, &path);
-                            info!("deleted: {}", remote);
-                            let _ = db.remove_file(&remote);
-                        }
-                        _ => {}
+                    // Removals are debounced too: a save that replaces the
+                    // file by rename shows up as remove + create
+                    if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_))
+                        && !path.is_dir()
+                    {
+                        pending.touch(path, Instant::now());
                     }
                 }
-                last_event = Instant::now();
             }
             Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {
-                // Process pending local changes after debounce
-                if !pending_paths.is_empty() && last_event.elapsed() >= debounce {
-                    for path in pending_paths.drain() {
-                        if path.exists() && path.is_file() {
-                            if let Err(e) = upload_if_changed(api, token, db, root, &path).await {
-                                let remote = to_remote_path(root, &path);
+                // Sync paths whose events have settled, in their final state.
+                // Files whose stat matches the index (such as ones just
+                // downloaded) aren't read; upload_if_changed skips files
+                // whose hash matches the synced version.
+                let mut uploads = UploadStats::default();
+                for path in pending.take_settled(Instant::now()) {
+                    let remote = to_remote_path(root, &path);
+                    if path.is_file() {
+                        if matches!(stat_unchanged(db, root, &path), Ok(true)) {
+                            continue;
+                        }
+                        match upload_if_changed(api, token, db, root, &path).await {
+                            Ok(stats) => uploads.add(stats),
+                            Err(e) => {
                                 error!("sync failed {}: {}", remote, e);
                                 let _ = db.add_retry(&remote, &e.to_string());
                             }
                         }
+                    } else if !path.exists() && matches!(db.get_file(&remote), Ok(Some(_))) {
+                        info!("deleted: {}", remote);
+                        let _ = db.remove_file(&remote);
                     }
                 }
 
                 // Periodically poll for remote changes
                 if last_poll.elapsed() >= poll_interval {
-                    if let Err(e) = sync_remote_changes(api, token, db, root).await {
+                    if let Err(e) =
+                        sync_remote_changes(api, token, db, root, naming, config.verify_downloads()).await
+                    {
                         warn!("remote sync poll failed: {}", e);
                     }
-                    process_retries(api, token, db, root, ignore_patterns).await;
+                    uploads.add(process_retries(api, token, db, root, &ignore_patterns).await);
                     last_poll = Instant::now();
                 }
+                report_uploads(config, &uploads);
             }
             Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => break,
         }
@@ -361,98 +801,385 @@ async fn watch_and_sync(
     Ok(())
 }
 
+/// Coalesces filesystem events per path: a path is settled once no event
+/// has touched it for the whole window.
+struct Debouncer {
+    window: Duration,
+    last_event: HashMap<PathBuf, Instant>,
+}
+
+impl Debouncer {
+    fn new(window: Duration) -> Self {
+        Self {
+            window,
+            last_event: HashMap::new(),
+        }
+    }
+
+    fn touch(&mut self, path: PathBuf, now: Instant) {
+        self.last_event.insert(path, now);
+    }
+
+    /// Remove and return the paths that have been quiet for the window.
+    fn take_settled(&mut self, now: Instant) -> Vec<PathBuf> {
+        let window = self.window;
+        let mut settled = Vec::new();
+        self.last_event.retain(|path, last| {
+            let quiet = now.duration_since(*last) >= window;
+            if quiet {
+                settled.push(path.clone());
+            }
+            !quiet
+        });
+        settled.sort();
+        settled
+    }
+}
+
 /// Convert a local filesystem path to a remote path (relative to sync root).
 fn to_remote_path(root: &Path, local_path: &Path) -> String {
     let relative = local_path.strip_prefix(root).unwrap_or(local_path);
     format!("/{}", relative.to_string_lossy().replace('\\', "/"))
 }
 
-/// Load ignore patterns from .entanglementignore + defaults.
-pub fn load_ignore_patterns(root: &Path) -> Vec<String> {
+/// Ignore files read from the sync root, in order (later rules win).
+/// `.entanglementignore` is the older name and is still honoured.
+const IGNORE_FILES: &[&str] = &[".entanglementignore", ".tangleignore"];
+
+/// Load ignore patterns: defaults, then the sync root's ignore files.
+pub fn load_ignore_patterns(root: &Path) -> IgnoreRules {
     let mut patterns: Vec<String> = DEFAULT_IGNORE_PATTERNS
         .iter()
         .map(|s| s.to_string())
         .collect();
 
-    let ignore_file = root.join(".entanglementignore");
-    if let Ok(content) = std::fs::read_to_string(&ignore_file) {
-        for line in content.lines() {
-            let trimmed = line.trim();
-            if !trimmed.is_empty() && !trimmed.starts_with('#') {
-                patterns.push(trimmed.to_string());
+    for name in IGNORE_FILES {
+        if let Ok(content) = std::fs::read_to_string(root.join(name)) {
+            for line in content.lines() {
+                let trimmed = line.trim();
+                if !trimmed.is_empty() && !trimmed.starts_with('#') {
+                    patterns.push(trimmed.to_string());
+                }
             }
         }
     }
 
-    patterns
+    IgnoreRules::new(&patterns)
 }
 
-/// Check if a path matches any ignore pattern.
-pub fn should_ignore(path: &Path, root: &Path, patterns: &[String]) -> bool {
-    let relative = path.strip_prefix(root).unwrap_or(path);
-    let relative_str = relative.to_string_lossy();
-    let filename = path
-        .file_name()
-        .map(|n| n.to_string_lossy().to_string())
-        .unwrap_or_default();
-
-    for pattern in patterns {
-        // Directory pattern (ends with /)
-        if pattern.ends_with('/') {
-            let dir_name = &pattern[..pattern.len() - 1];
-            for component in relative.components() {
-                if component.as_os_str().to_string_lossy() == dir_name {
-                    return true;
+/// Whether `path` is one of the ignore files at the sync root.
+fn is_ignore_file(path: &Path, root: &Path) -> bool {
+    path.parent() == Some(root)
+        && path
+            .file_name()
+            .is_some_and(|n| IGNORE_FILES.iter().any(|f| n == *f))
+}
+
+/// Gitignore-style patterns, compiled once, in order (later rules win).
+///
+/// Patterns containing a `/` (other than a trailing one) are anchored at the
+/// sync root; others match any path component. A trailing `/` marks a
+/// directory pattern. `*` and `?` never match a `/`; `**` spans directories.
+#[derive(Clone, Default)]
+pub struct IgnoreRules {
+    rules: Vec<IgnoreRule>,
+}
+
+#[derive(Clone)]
+struct IgnoreRule {
+    /// `!pattern`: re-include what an earlier rule excluded
+    negated: bool,
+    /// Matched against paths from the root rather than single components
+    anchored: bool,
+    glob: GlobMatcher,
+}
+
+impl IgnoreRules {
+    /// Compile `patterns`; invalid ones are skipped with a warning.
+    pub fn new<S: AsRef<str>>(patterns: &[S]) -> Self {
+        let rules = patterns
+            .iter()
+            .filter_map(|pattern| {
+                let pattern = pattern.as_ref();
+                let (negated, glob) = match pattern.strip_prefix('!') {
+                    Some(rest) => (true, rest),
+                    None => (false, pattern),
+                };
+                let glob = glob.trim_end_matches('/');
+                if glob.is_empty() {
+                    return None;
                 }
-            }
-        }
-        // Wildcard pattern
-        else if pattern.contains('*') {
-            if glob_match(pattern, &filename) || glob_match(pattern, &relative_str) {
-                return true;
-            }
+                let anchored = glob.contains('/');
+                match GlobBuilder::new(glob.trim_start_matches('/'))
+                    .literal_separator(true)
+                    .build()
+                {
+                    Ok(glob) => Some(IgnoreRule { negated, anchored, glob: glob.compile_matcher() }),
+                    Err(e) => {
+                        warn!("skipping ignore pattern {:?}: {}", pattern, e);
+                        None
+                    }
+                }
+            })
+            .collect();
+        IgnoreRules { rules }
+    }
+
+    fn len(&self) -> usize {
+        self.rules.len()
+    }
+}
+
+impl IgnoreRule {
+    /// Match against a path relative to the sync root.
+    fn matches(&self, relative: &Path) -> bool {
+        if self.anchored {
+            // The path itself or any directory above it, from the root
+            relative.ancestors().any(|ancestor| {
+                !ancestor.as_os_str().is_empty()
+                    && self.glob.is_match(ancestor.to_string_lossy().replace('\\', "/"))
+            })
+        } else {
+            relative
+                .components()
+                .any(|c| self.glob.is_match(c.as_os_str()))
         }
-        // Exact match
-        else if filename == *pattern || relative_str == *pattern {
-            return true;
+    }
+}
+
+/// Check a path against the ignore rules.
+///
+/// The last matching rule wins, so `!pattern` re-includes paths an
+/// earlier rule excluded.
+pub fn should_ignore(path: &Path, root: &Path, rules: &IgnoreRules) -> bool {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+
+    let mut ignored = false;
+    for rule in &rules.rules {
+        if rule.matches(relative) {
+            ignored = !rule.negated;
         }
     }
+    ignored
+}
 
-    false
-}
-
-/// Simple glob pattern matching (supports * and ?).
-fn glob_match(pattern: &str, text: &str) -> bool {
-    let pattern_bytes = pattern.as_bytes();
-    let text_bytes = text.as_bytes();
-
-    let mut pi = 0;
-    let mut ti = 0;
-    let mut star_pi: Option<usize> = None;
-    let mut star_ti: Option<usize> = None;
-
-    while ti < text_bytes.len() {
-        if pi < pattern_bytes.len()
-            && (pattern_bytes[pi] == b'?' || pattern_bytes[pi] == text_bytes[ti])
-        {
-            pi += 1;
-            ti += 1;
-        } else if pi < pattern_bytes.len() && pattern_bytes[pi] == b'*' {
-            star_pi = Some(pi);
-            star_ti = Some(ti);
-            pi += 1;
-        } else if let (Some(sp), Some(st)) = (star_pi, star_ti) {
-            pi = sp + 1;
-            star_ti = Some(st + 1);
-            ti = st + 1;
-        } else {
-            return false;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(path: &str, action: &str, hash: Option<&str>) -> FileChange {
+        FileChange {
+            id: uuid::Uuid::new_v4(),
+            path: path.to_string(),
+            action: action.to_string(),
+            size_bytes: None,
+            blob_hash: hash.map(str::to_string),
+            version_id: None,
+            is_directory: false,
+            updated_at: String::new(),
         }
     }
 
-    while pi < pattern_bytes.len() && pattern_bytes[pi] == b'*' {
-        pi += 1;
+    fn synced(db: &LocalDb, path: &str, hash: &str) {
+        db.upsert_file(&FileRecord {
+            path: path.to_string(),
+            blake3_hash: hash.to_string(),
+            last_modified: 0,
+            size: None,
+            version_id: None,
+            sync_cursor: None,
+        })
+        .unwrap();
+    }
+
+    fn ignored(path: &str, rules: &[&str]) -> bool {
+        let root = Path::new("/sync");
+        should_ignore(&root.join(path), root, &IgnoreRules::new(rules))
+    }
+
+    #[test]
+    fn test_ignore_patterns() {
+        assert!(ignored("build/out.tmp", &["*.tmp"]));
+        assert!(ignored("web/node_modules/pkg/index.js", &["node_modules/"]));
+        assert!(!ignored("src/main.rs", &["*.tmp", "node_modules/"]));
+
+        // Negation: the last matching rule wins
+        assert!(!ignored("keep.log", &["*.log", "!keep.log"]));
+        assert!(ignored("other.log", &["*.log", "!keep.log"]));
+        assert!(ignored("keep.log", &["!keep.log", "*.log"]));
+
+        // Patterns with a slash are anchored at the root
+        assert!(ignored("build/app", &["/build"]));
+        assert!(!ignored("src/build/app", &["/build"]));
+        assert!(ignored("docs/draft.md", &["docs/*.md"]));
+        assert!(!ignored("old/docs/draft.md", &["docs/*.md"]));
+
+        // Wildcards stop at a slash; `**` spans directories
+        assert!(!ignored("docs/sub/draft.md", &["docs/*.md"]));
+        assert!(ignored("docs/sub/draft.md", &["docs/?ub/"]));
+        assert!(ignored("docs/sub/draft.md", &["docs/**/*.md"]));
+        assert!(ignored("a/b/c.txt", &["/a/*"]));
+    }
+
+    #[test]
+    fn test_is_ignore_file() {
+        let root = Path::new("/sync");
+        assert!(is_ignore_file(&root.join(".tangleignore"), root));
+        assert!(is_ignore_file(&root.join(".entanglementignore"), root));
+        assert!(!is_ignore_file(&root.join("sub/.tangleignore"), root));
+    }
+
+    #[test]
+    fn test_debouncer_waits_per_path() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut pending = Debouncer::new(Duration::from_millis(500));
+
+        pending.touch(PathBuf::from("/sync/a.txt"), at(0));
+        pending.touch(PathBuf::from("/sync/b.txt"), at(100));
+        // Write-rename-write: further events on a.txt restart its window
+        pending.touch(PathBuf::from("/sync/a.txt"), at(300));
+
+        assert!(pending.take_settled(at(550)).is_empty());
+        assert_eq!(pending.take_settled(at(600)), vec![PathBuf::from("/sync/b.txt")]);
+        assert_eq!(pending.take_settled(at(800)), vec![PathBuf::from("/sync/a.txt")]);
+        assert!(pending.take_settled(at(5000)).is_empty());
+    }
+
+    #[test]
+    fn test_conflict_file_name() {
+        let template = crate::config::DEFAULT_CONFLICT_TEMPLATE;
+        assert_eq!(
+            conflict_file_name(template, "report", ".pdf", "2024-06-01", "sync.example.com"),
+            "report (conflict 2024-06-01 from sync.example.com).pdf"
+        );
+        assert_eq!(
+            conflict_file_name("{name}.{date}{ext}", "notes", "", "2024-06-01", "s"),
+            "notes.2024-06-01"
+        );
+        assert_eq!(conflict_file_name("{server}/{name}", "a", "", "d", "s"), "s-a");
+    }
+
+    #[test]
+    fn test_build_plan() {
+        let db = LocalDb::open_memory().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("gone.txt"), b"x").unwrap();
+        synced(&db, "/edited.txt", "old");
+        synced(&db, "/both.txt", "old");
+        synced(&db, "/gone.txt", "old");
+
+        let local = BTreeMap::from([
+            ("/edited.txt".to_string(), "new".to_string()),
+            ("/both.txt".to_string(), "mine".to_string()),
+            ("/same.txt".to_string(), "h".to_string()),
+        ]);
+        let remote = vec![
+            change("/edited.txt", "modified", Some("old")),
+            change("/both.txt", "modified", Some("theirs")),
+            change("/same.txt", "created", Some("h")),
+            change("/fresh.txt", "created", Some("f")),
+            change("/gone.txt", "deleted", None),
+        ];
+
+        let plan = build_plan(&db, root.path(), local, &remote).unwrap();
+        assert_eq!(plan.uploads, vec!["/edited.txt"]);
+        assert_eq!(plan.downloads, vec!["/fresh.txt"]);
+        assert_eq!(plan.deletes, vec!["/gone.txt"]);
+        assert_eq!(plan.conflicts, vec!["/both.txt"]);
+    }
+
+    #[test]
+    fn test_stat_unchanged() {
+        let db = LocalDb::open_memory().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let file = root.path().join("notes.txt");
+        std::fs::write(&file, b"hello").unwrap();
+        set_mtime(&file, std::time::SystemTime::now() - Duration::from_secs(60));
+        assert!(!stat_unchanged(&db, root.path(), &file).unwrap(), "not indexed yet");
+
+        let stat = FileStat::of(&file).unwrap();
+        db.upsert_file(&FileRecord {
+            path: "/notes.txt".to_string(),
+            blake3_hash: chunking::hash_file(b"hello"),
+            last_modified: stat.mtime,
+            size: Some(stat.size),
+            version_id: None,
+            sync_cursor: None,
+        })
+        .unwrap();
+        assert!(stat_unchanged(&db, root.path(), &file).unwrap());
+
+        // Rewritten with the old mtime put back: the size still gives it away
+        std::fs::write(&file, b"hello, world").unwrap();
+        set_mtime(&file, std::time::UNIX_EPOCH + Duration::from_secs(stat.mtime as u64));
+        assert!(!stat_unchanged(&db, root.path(), &file).unwrap());
+    }
+
+    #[test]
+    fn test_same_second_rewrite_is_not_trusted() {
+        let db = LocalDb::open_memory().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let file = root.path().join("notes.txt");
+        std::fs::write(&file, b"hello").unwrap();
+        index_as_synced(&db, root.path(), &file);
+        let stat = FileStat::of(&file).unwrap();
+
+        // Same size, and the same whole-second mtime as the indexed stat
+        std::fs::write(&file, b"jello").unwrap();
+        set_mtime(&file, std::time::UNIX_EPOCH + Duration::from_secs(stat.mtime as u64));
+        assert!(!stat_unchanged(&db, root.path(), &file).unwrap(), "recorded too soon after the mtime");
+        assert_eq!(local_edit(&db, root.path(), "/notes.txt", &file).unwrap(), Some(chunking::hash_file(b"jello")));
+    }
+
+    fn set_mtime(file: &Path, mtime: std::time::SystemTime) {
+        std::fs::File::options()
+            .write(true)
+            .open(file)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
     }
 
-    pi == pattern_bytes.len()
+    /// Index `file` as synced in its current state
+    fn index_as_synced(db: &LocalDb, root: &Path, file: &Path) {
+        let stat = FileStat::of(file).unwrap();
+        db.upsert_file(&FileRecord {
+            path: to_remote_path(root, file),
+            blake3_hash: chunking::hash_file(&std::fs::read(file).unwrap()),
+            last_modified: stat.mtime,
+            size: Some(stat.size),
+            version_id: None,
+            sync_cursor: None,
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_remote_delete_of_untouched_file() {
+        let db = LocalDb::open_memory().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let file = root.path().join("notes.txt");
+        std::fs::write(&file, b"hello").unwrap();
+        index_as_synced(&db, root.path(), &file);
+
+        assert!(!apply_remote_delete(&db, root.path(), "/notes.txt", &file).unwrap(), "nothing to upload");
+        assert!(!file.exists());
+        assert!(db.get_file("/notes.txt").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_remote_delete_of_edited_file() {
+        let db = LocalDb::open_memory().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let file = root.path().join("notes.txt");
+        std::fs::write(&file, b"hello").unwrap();
+        index_as_synced(&db, root.path(), &file);
+        std::fs::write(&file, b"hello, edited").unwrap();
+
+        assert!(apply_remote_delete(&db, root.path(), "/notes.txt", &file).unwrap(), "edits re-uploaded");
+        assert!(file.exists());
+        // Replaced by the upload, not before
+        assert!(db.get_file("/notes.txt").unwrap().is_some());
+    }
 }
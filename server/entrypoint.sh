@@ -111,7 +111,7 @@ fi
 #      application, allowing for graceful shutdown.
 
 echo "[entrypoint] 🚀 Starting Entanglement server..."
-echo "[entrypoint] REST API: http://0.0.0.0:${REST_PORT:-1975}"
+echo "[entrypoint] REST API: http://${BIND_ADDRESS:-127.0.0.1}:${REST_PORT:-1975}"
 echo "[entrypoint] Web UI:   http://0.0.0.0:${WEB_PORT:-3000}"
 
 # =============================================================================
@@ -0,0 +1,2 @@
+-- Keyset pagination for /v1/files/changes orders by (updated_at, id)
+CREATE INDEX IF NOT EXISTS idx_files_updated_at_id ON files(updated_at, id);
@@ -0,0 +1,6 @@
+-- Alias files: a link path points at another file and serves its current version.
+-- No foreign key so that hard-deleting the target leaves a dangling link (404 on download).
+ALTER TABLE files ADD COLUMN IF NOT EXISTS link_target_id UUID;
+
+CREATE INDEX IF NOT EXISTS idx_files_link_target_id ON files(link_target_id)
+    WHERE link_target_id IS NOT NULL;
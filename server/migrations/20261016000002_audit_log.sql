@@ -0,0 +1,25 @@
+-- Append-only audit trail of mutating operations and public share access.
+-- user_id has no foreign key so entries outlive the users they describe.
+CREATE TABLE IF NOT EXISTS audit_log (
+    id BIGSERIAL PRIMARY KEY,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    user_id UUID,
+    action VARCHAR(32) NOT NULL, -- 'upload', 'delete', 'move', 'restore', 'share_create', 'share_access'
+    path TEXT,
+    request_id VARCHAR(64),
+    ip VARCHAR(64)
+);
+
+CREATE INDEX IF NOT EXISTS idx_audit_log_created_at ON audit_log(created_at);
+
+-- Reject UPDATE and DELETE so entries cannot be rewritten after the fact
+CREATE OR REPLACE FUNCTION audit_log_append_only() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_log is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS audit_log_append_only ON audit_log;
+CREATE TRIGGER audit_log_append_only
+    BEFORE UPDATE OR DELETE ON audit_log
+    FOR EACH ROW EXECUTE FUNCTION audit_log_append_only();
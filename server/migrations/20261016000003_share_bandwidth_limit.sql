@@ -0,0 +1,2 @@
+-- Per-share download bandwidth limit in bytes per second (NULL = unlimited)
+ALTER TABLE share_links ADD COLUMN IF NOT EXISTS max_bytes_per_sec BIGINT;
@@ -0,0 +1,7 @@
+-- Optional TOTP two-factor authentication. The secret is stored AES-256-GCM
+-- encrypted by the server and stays pending until a first code is verified.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS totp_secret TEXT;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS totp_enabled BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Last accepted time step, so a code cannot be replayed
+ALTER TABLE users ADD COLUMN IF NOT EXISTS totp_last_step BIGINT;
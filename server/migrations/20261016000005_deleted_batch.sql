@@ -0,0 +1,6 @@
+-- Rows soft-deleted together by one recursive folder delete share a batch ID,
+-- so the folder can later be restored exactly as it was deleted.
+ALTER TABLE files ADD COLUMN IF NOT EXISTS deleted_batch_id UUID;
+
+CREATE INDEX IF NOT EXISTS idx_files_deleted_batch_id ON files(deleted_batch_id)
+    WHERE deleted_batch_id IS NOT NULL;
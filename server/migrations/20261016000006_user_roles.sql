@@ -0,0 +1,10 @@
+-- User roles. Viewers can read and download but not change files; `is_admin`
+-- is kept in sync with role = 'admin' for existing clients.
+DO $$ BEGIN
+    CREATE TYPE user_role AS ENUM ('admin', 'editor', 'viewer');
+EXCEPTION
+    WHEN duplicate_object THEN NULL;
+END $$;
+
+ALTER TABLE users ADD COLUMN IF NOT EXISTS role user_role NOT NULL DEFAULT 'editor';
+UPDATE users SET role = 'admin' WHERE is_admin AND role <> 'admin';
@@ -0,0 +1,3 @@
+-- Containers written while STORAGE_ENCRYPTION_KEY is set hold AES-256-GCM
+-- sealed chunks. Existing containers stay plaintext and remain readable.
+ALTER TABLE blob_containers ADD COLUMN IF NOT EXISTS encrypted BOOLEAN NOT NULL DEFAULT FALSE;
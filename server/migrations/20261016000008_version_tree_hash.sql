@@ -0,0 +1,8 @@
+-- Merkle root over each version's chunk hashes, for O(1) equality checks.
+-- Unchunked versions use their blob hash; chunked versions created before
+-- this migration are filled in on first request.
+ALTER TABLE versions ADD COLUMN IF NOT EXISTS tree_hash TEXT;
+
+UPDATE versions v SET tree_hash = blob_hash
+WHERE tree_hash IS NULL
+  AND NOT EXISTS (SELECT 1 FROM version_chunks vc WHERE vc.version_id = v.id);
@@ -0,0 +1,3 @@
+-- Permission bits of the uploaded file (e.g. 493 = 0755), so Unix clients can
+-- restore them on download. NULL means unknown: clients apply their umask.
+ALTER TABLE files ADD COLUMN IF NOT EXISTS unix_mode INTEGER;
@@ -0,0 +1,6 @@
+-- Substring search on paths (GET /v1/files/search). ILIKE '%term%' can't use
+-- the btree index on path; a trigram index lets Postgres avoid a full scan.
+-- pg_trgm is a trusted extension, so the database owner can create it.
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX IF NOT EXISTS idx_files_path_trgm ON files USING gin (path gin_trgm_ops);
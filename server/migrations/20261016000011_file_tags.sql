@@ -0,0 +1,11 @@
+-- Key-value tags on files (e.g. status=approved), for document workflows.
+-- Keyed by file id rather than path, so tags follow the file through moves.
+CREATE TABLE IF NOT EXISTS file_tags (
+    file_id UUID NOT NULL REFERENCES files(id) ON DELETE CASCADE,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (file_id, key)
+);
+
+-- GET /v1/files/search?tag=key:value
+CREATE INDEX IF NOT EXISTS idx_file_tags_key_value ON file_tags (key, value);
@@ -0,0 +1,6 @@
+-- Admins can disable an account. Disabled users are refused with 403 on
+-- every authenticated request.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS disabled BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Copied into each JWT; bumping it revokes every token issued before.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS token_version INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,4 @@
+-- Client-side encryption: key derivation data for a version's chunks (salt,
+-- wrapped key, ...), stored and returned as-is. The server never reads it;
+-- chunks of such versions hold ciphertext and are hashed as ciphertext.
+ALTER TABLE versions ADD COLUMN IF NOT EXISTS encryption_meta BYTEA;
@@ -0,0 +1,4 @@
+-- Trash listing (GET /v1/files/trash): a user's deleted rows, newest first.
+-- Partial, so live files don't pay for it.
+CREATE INDEX IF NOT EXISTS idx_files_trash ON files (owner_id, updated_at DESC)
+    WHERE is_deleted = TRUE;
@@ -0,0 +1,10 @@
+-- Storage backend holding each chunk. Existing chunks are all on local disk,
+-- in containers or as standalone blob files; 's3' chunks are objects in the
+-- configured bucket.
+DO $$ BEGIN
+    CREATE TYPE storage_backend AS ENUM ('local', 's3');
+EXCEPTION
+    WHEN duplicate_object THEN NULL;
+END $$;
+
+ALTER TABLE chunks ADD COLUMN IF NOT EXISTS backend storage_backend NOT NULL DEFAULT 'local';
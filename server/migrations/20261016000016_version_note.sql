@@ -0,0 +1,3 @@
+-- Free-form note an uploader attaches to a version ("fix totals", "draft
+-- sent to legal"), shown in the file's history. NULL when none was given.
+ALTER TABLE versions ADD COLUMN IF NOT EXISTS note TEXT;
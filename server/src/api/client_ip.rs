@@ -0,0 +1,248 @@
+//! Real client addresses behind reverse proxies
+//!
+//! Every request gets a [`ClientIp`] extension, resolved once by
+//! [`resolve_client_ip`]. It is the socket peer unless the peer is listed in
+//! `TRUSTED_PROXIES`, in which case `X-Forwarded-For` (or, without it,
+//! `Forwarded`) is read from the right: each hop a trusted proxy added is
+//! skipped, and the first untrusted address is the client. Addresses a
+//! client put in the header itself sit left of that and are never used, so
+//! only headers from a trusted proxy can change the address.
+//!
+//! The rate limiter and the audit log both key on this address.
+
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::HeaderMap;
+use axum::middleware::Next;
+use axum::response::Response;
+use serde::Deserialize;
+use std::net::{IpAddr, SocketAddr};
+use tower_governor::key_extractor::KeyExtractor;
+use tower_governor::GovernorError;
+
+/// The address a request came from, after trusted proxy headers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+/// Addresses and CIDR ranges of reverse proxies whose forwarding headers are believed
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct TrustedProxies {
+    ranges: Vec<(IpAddr, u8)>,
+}
+
+impl TrustedProxies {
+    /// Parse a comma-separated list such as `127.0.0.1, 10.0.0.0/8, ::1`
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        let mut ranges = Vec::new();
+        for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (addr, prefix) = match entry.split_once('/') {
+                Some((addr, prefix)) => (addr, Some(prefix)),
+                None => (entry, None),
+            };
+            let addr: IpAddr = addr
+                .parse()
+                .map_err(|_| anyhow::anyhow!("TRUSTED_PROXIES: {:?} is not an IP address or CIDR range", entry))?;
+            let max = if addr.is_ipv4() { 32 } else { 128 };
+            let prefix = match prefix {
+                Some(p) => p
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|&p| p <= max)
+                    .ok_or_else(|| anyhow::anyhow!("TRUSTED_PROXIES: bad prefix length in {:?}", entry))?,
+                None => max,
+            };
+            ranges.push((addr, prefix));
+        }
+        Ok(Self { ranges })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = canonical(ip);
+        self.ranges.iter().any(|&(net, prefix)| in_range(ip, canonical(net), prefix))
+    }
+
+    /// The client address for a request from `peer` carrying `headers`
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        if !self.contains(peer) {
+            return peer;
+        }
+        let hops = forwarded_for(headers);
+        // Rightmost first: the last hop was added by the proxy that sent the request
+        let mut client = peer;
+        for hop in hops.into_iter().rev() {
+            client = hop;
+            if !self.contains(hop) {
+                break;
+            }
+        }
+        client
+    }
+}
+
+/// IPv4-mapped IPv6 addresses (`::ffff:a.b.c.d`) compare as IPv4
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        v4 => v4,
+    }
+}
+
+fn in_range(ip: IpAddr, net: IpAddr, prefix: u8) -> bool {
+    match (ip, net) {
+        (IpAddr::V4(ip), IpAddr::V4(net)) => {
+            let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(net) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(net)) => {
+            let mask = u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(net) & mask
+        }
+        _ => false,
+    }
+}
+
+/// Hops listed in `X-Forwarded-For`, else in the `for=` parameters of
+/// `Forwarded`, oldest first. Entries that aren't addresses (`unknown`,
+/// obfuscated names) end the list there, as nothing left of them can be
+/// attributed.
+fn forwarded_for(headers: &HeaderMap) -> Vec<IpAddr> {
+    let xff: Vec<&str> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .collect();
+    let entries = if xff.is_empty() {
+        headers
+            .get_all("forwarded")
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .flat_map(|element| element.split(';'))
+            .filter_map(|pair| pair.trim().split_once('='))
+            .filter(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+            .map(|(_, value)| value.trim().trim_matches('"'))
+            .collect()
+    } else {
+        xff
+    };
+
+    let mut hops = Vec::new();
+    for entry in entries.iter().rev() {
+        match parse_hop(entry) {
+            Some(ip) => hops.push(ip),
+            None => break,
+        }
+    }
+    hops.reverse();
+    hops
+}
+
+/// An address as proxies write it: bare, `[v6]`, `[v6]:port` or `v4:port`
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    if let Ok(ip) = hop.parse() {
+        return Some(ip);
+    }
+    if let Some(rest) = hop.strip_prefix('[') {
+        return rest.split_once(']').and_then(|(ip, _)| ip.parse().ok());
+    }
+    hop.parse::<SocketAddr>().ok().map(|addr| addr.ip())
+}
+
+/// Attach the [`ClientIp`] extension. Requests without a peer address (none
+/// reach the server that way) are passed through without one.
+pub async fn resolve_client_ip(State(proxies): State<TrustedProxies>, mut req: Request, next: Next) -> Response {
+    let peer = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    if let Some(peer) = peer {
+        let ip = proxies.client_ip(peer, req.headers());
+        req.extensions_mut().insert(ClientIp(ip));
+    }
+    next.run(req).await
+}
+
+/// Rate limiter key: the resolved client address
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIpKeyExtractor;
+
+impl KeyExtractor for ClientIpKeyExtractor {
+    type Key = IpAddr;
+
+    fn extract<T>(&self, req: &axum::http::Request<T>) -> Result<Self::Key, GovernorError> {
+        req.extensions()
+            .get::<ClientIp>()
+            .map(|ClientIp(ip)| *ip)
+            .ok_or(GovernorError::UnableToExtractKey)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn headers(name: &'static str, value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn test_parse_and_contains() {
+        let proxies = TrustedProxies::parse("127.0.0.1, 10.0.0.0/8,fd00::/8").unwrap();
+        assert!(proxies.contains(ip("127.0.0.1")));
+        assert!(proxies.contains(ip("::ffff:127.0.0.1")));
+        assert!(proxies.contains(ip("10.200.3.4")));
+        assert!(proxies.contains(ip("fd12::1")));
+        assert!(!proxies.contains(ip("127.0.0.2")));
+        assert!(!proxies.contains(ip("11.0.0.1")));
+        assert!(TrustedProxies::parse("").unwrap().is_empty());
+        assert!(TrustedProxies::parse("0.0.0.0/0").unwrap().contains(ip("203.0.113.7")));
+        assert!(TrustedProxies::parse("10.0.0.0/33").is_err());
+        assert!(TrustedProxies::parse("proxy.local").is_err());
+    }
+
+    #[test]
+    fn test_untrusted_peer_headers_are_ignored() {
+        let proxies = TrustedProxies::parse("10.0.0.1").unwrap();
+        let spoofed = headers("x-forwarded-for", "203.0.113.7");
+        assert_eq!(proxies.client_ip(ip("198.51.100.9"), &spoofed), ip("198.51.100.9"));
+        assert_eq!(TrustedProxies::default().client_ip(ip("10.0.0.1"), &spoofed), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn test_trusted_proxy_forwarded_for() {
+        let proxies = TrustedProxies::parse("10.0.0.0/8").unwrap();
+        // The client prepended a fake hop; the proxies appended the real ones
+        let chain = headers("x-forwarded-for", "1.2.3.4, 203.0.113.7, 10.0.0.2");
+        assert_eq!(proxies.client_ip(ip("10.0.0.1"), &chain), ip("203.0.113.7"));
+        // Only proxies in the chain: the oldest hop
+        let internal = headers("x-forwarded-for", "10.0.0.3, 10.0.0.2");
+        assert_eq!(proxies.client_ip(ip("10.0.0.1"), &internal), ip("10.0.0.3"));
+        // No header: the proxy itself
+        assert_eq!(proxies.client_ip(ip("10.0.0.1"), &HeaderMap::new()), ip("10.0.0.1"));
+        // Garbage stops the walk rather than being skipped over
+        let garbage = headers("x-forwarded-for", "1.2.3.4, unknown, 10.0.0.2");
+        assert_eq!(proxies.client_ip(ip("10.0.0.1"), &garbage), ip("10.0.0.2"));
+    }
+
+    #[test]
+    fn test_trusted_proxy_forwarded() {
+        let proxies = TrustedProxies::parse("10.0.0.1").unwrap();
+        let forwarded = headers("forwarded", "for=192.0.2.60;proto=http;by=203.0.113.43, for=\"[2001:db8::17]:4711\"");
+        assert_eq!(proxies.client_ip(ip("10.0.0.1"), &forwarded), ip("2001:db8::17"));
+        let v4_port = headers("forwarded", "For=\"198.51.100.9:8080\"");
+        assert_eq!(proxies.client_ip(ip("10.0.0.1"), &v4_port), ip("198.51.100.9"));
+    }
+}
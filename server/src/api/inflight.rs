@@ -0,0 +1,134 @@
+//! In-flight upload finalization
+//!
+//! Finalizing an upload upserts the file record and then inserts its version,
+//! so a handler cancelled between the two (client disconnect, shutdown) would
+//! leave a file without the version it was uploaded for. [`InFlight::run`]
+//! moves that work onto its own task, where it completes even if the request
+//! is dropped, and counts it so shutdown can wait for it before the database
+//! pool is closed.
+
+use crate::api::rest::AppError;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+#[derive(Clone, Default)]
+pub struct InFlight {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    count: AtomicUsize,
+    idle: Notify,
+}
+
+/// Counts one operation until dropped
+pub struct InFlightGuard(Arc<Inner>);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if self.0.count.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
+impl InFlight {
+    /// Count an operation until the returned guard is dropped
+    pub fn begin(&self) -> InFlightGuard {
+        self.inner.count.fetch_add(1, Ordering::AcqRel);
+        InFlightGuard(self.inner.clone())
+    }
+
+    /// Operations currently running
+    pub fn count(&self) -> usize {
+        self.inner.count.load(Ordering::Acquire)
+    }
+
+    /// Run `op` to completion on its own task, even if the caller is dropped
+    pub async fn run<F, T>(&self, op: F) -> Result<T, AppError>
+    where
+        F: Future<Output = Result<T, AppError>> + Send + 'static,
+        T: Send + 'static,
+    {
+        let guard = self.begin();
+        tokio::spawn(async move {
+            let _guard = guard;
+            op.await
+        })
+        .await
+        .map_err(|e| AppError::Internal(format!("Upload finalization failed: {}", e)))?
+    }
+
+    /// Wait until nothing is running or `deadline` passes, returning the
+    /// number of operations that were awaited and how many are still running
+    pub async fn drain(&self, deadline: Instant) -> (usize, usize) {
+        let pending = self.count();
+        let idle = async {
+            loop {
+                // Registered before the check, so a wakeup in between isn't lost
+                let notified = self.inner.idle.notified();
+                if self.count() == 0 {
+                    return;
+                }
+                notified.await;
+            }
+        };
+        let _ = tokio::time::timeout_at(deadline, idle).await;
+        (pending, self.count())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_drain_waits_for_running_operations() {
+        let in_flight = InFlight::default();
+        assert_eq!(in_flight.drain(Instant::now()).await, (0, 0));
+
+        let guard = in_flight.begin();
+        let release = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            drop(guard);
+        });
+        let deadline = Instant::now() + Duration::from_secs(5);
+        assert_eq!(in_flight.drain(deadline).await, (1, 0));
+        release.await.unwrap();
+
+        // Gives up at the deadline
+        let _stuck = in_flight.begin();
+        let deadline = Instant::now() + Duration::from_millis(20);
+        assert_eq!(in_flight.drain(deadline).await, (1, 1));
+    }
+
+    #[tokio::test]
+    async fn test_run_completes_when_caller_is_dropped() {
+        let in_flight = InFlight::default();
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let caller = tokio::spawn({
+            let in_flight = in_flight.clone();
+            async move {
+                in_flight
+                    .run(async move {
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                        let _ = tx.send(());
+                        Ok(())
+                    })
+                    .await
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        caller.abort();
+
+        assert_eq!(in_flight.count(), 1);
+        rx.await.expect("operation finished despite the abort");
+        let deadline = Instant::now() + Duration::from_secs(5);
+        assert_eq!(in_flight.drain(deadline).await.1, 0);
+    }
+}
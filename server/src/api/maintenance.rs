@@ -0,0 +1,93 @@
+//! Read-only maintenance mode
+//!
+//! While enabled, every request that could change stored state is answered
+//! with `503` and a `Retry-After` header; reads, logins and the toggle itself
+//! keep working. Admins switch it with `POST /admin/maintenance` or
+//! `tangled maintenance on|off`, and `MAINTENANCE_MODE=true` starts the
+//! server in it. The flag lives in memory, so a restart without
+//! `MAINTENANCE_MODE` leaves maintenance.
+
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderValue, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// POST routes that only read, or that must work during maintenance
+const READ_ONLY_POSTS: &[&str] = &[
+    "/auth/login",
+    "/auth/refresh",
+    "/admin/maintenance",
+    "/chunks/check",
+    "/v1/chunks/check",
+    "/sync/check",
+];
+
+#[derive(Clone)]
+pub struct Maintenance {
+    enabled: Arc<AtomicBool>,
+    retry_after_secs: u64,
+}
+
+impl Maintenance {
+    pub fn new(enabled: bool, retry_after_secs: u64) -> Self {
+        Self {
+            enabled: Arc::new(AtomicBool::new(enabled)),
+            retry_after_secs,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Acquire)
+    }
+
+    /// Switch maintenance mode, returning the previous setting
+    pub fn set(&self, enabled: bool) -> bool {
+        self.enabled.swap(enabled, Ordering::AcqRel)
+    }
+}
+
+/// Whether a request could change stored state
+pub(crate) fn is_write_request(method: &Method, path: &str) -> bool {
+    match method.as_str() {
+        "GET" | "HEAD" | "OPTIONS" | "PROPFIND" => false,
+        "POST" => !READ_ONLY_POSTS.contains(&path),
+        _ => true,
+    }
+}
+
+/// Refuse writes with `503` while maintenance mode is on
+pub async fn reject_writes(State(maintenance): State<Maintenance>, req: Request, next: Next) -> Response {
+    if maintenance.is_enabled() && is_write_request(req.method(), req.uri().path()) {
+        let mut response = (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({ "error": "Server is in read-only maintenance mode" })),
+        )
+            .into_response();
+        response
+            .headers_mut()
+            .insert(header::RETRY_AFTER, HeaderValue::from(maintenance.retry_after_secs));
+        return response;
+    }
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_write_request() {
+        assert!(!is_write_request(&Method::GET, "/v1/files"));
+        assert!(!is_write_request(&Method::from_bytes(b"PROPFIND").unwrap(), "/webdav/docs"));
+        assert!(!is_write_request(&Method::POST, "/auth/login"));
+        assert!(!is_write_request(&Method::POST, "/v1/chunks/check"));
+        assert!(!is_write_request(&Method::POST, "/admin/maintenance"));
+        assert!(is_write_request(&Method::POST, "/v1/files"));
+        assert!(is_write_request(&Method::PUT, "/v1/chunks/abc123"));
+        assert!(is_write_request(&Method::DELETE, "/v1/files/abc"));
+        assert!(is_write_request(&Method::PATCH, "/admin/users/abc"));
+    }
+}
@@ -0,0 +1,278 @@
+//! Prometheus metrics
+//!
+//! A small hand-rolled registry rendered in the text exposition format at
+//! `GET /metrics`. Request counts and transfer bytes are recorded by
+//! [`track_requests`], which runs inside the trace layer; chunk dedup and the
+//! WebSocket gauge are updated by their handlers, and pool utilization is
+//! read from the pool at scrape time.
+//!
+//! The endpoint is off unless `METRICS_BIND` (a separate listener) or
+//! `METRICS_TOKEN` (bearer token on the main port) is set.
+
+use crate::api::rest::{is_binary_route, AppError};
+use crate::api::AppState;
+use crate::auth;
+use axum::body::{Body, Bytes, HttpBody};
+use axum::extract::{MatchedPath, Request, State};
+use axum::http::{header, HeaderMap};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use http_body::{Frame, SizeHint};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Route label for requests that matched no route
+const UNMATCHED: &str = "unmatched";
+
+/// Request counts keyed by (method, route, status)
+type RequestCounts = BTreeMap<(String, String, u16), u64>;
+
+#[derive(Clone, Default)]
+pub struct Metrics {
+    requests: Arc<Mutex<RequestCounts>>,
+    upload_bytes: Arc<AtomicU64>,
+    download_bytes: Arc<AtomicU64>,
+    dedup_hits: Arc<AtomicU64>,
+    dedup_misses: Arc<AtomicU64>,
+    ws_connections: Arc<AtomicI64>,
+    ws_reaped: Arc<AtomicU64>,
+}
+
+/// Connection pool figures sampled at scrape time
+pub struct PoolStats {
+    pub size: u32,
+    pub idle: u32,
+    pub max: u32,
+}
+
+/// Decrements the WebSocket gauge when the connection ends
+pub struct WsConnection(Arc<AtomicI64>);
+
+impl Drop for WsConnection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Metrics {
+    pub fn record_request(&self, method: &str, route: &str, status: u16) {
+        let mut requests = self.requests.lock().unwrap_or_else(|e| e.into_inner());
+        *requests
+            .entry((method.to_string(), route.to_string(), status))
+            .or_default() += 1;
+    }
+
+    /// Chunks found already stored (`hits`) or stored new (`misses`)
+    pub fn record_dedup(&self, hits: usize, misses: usize) {
+        self.dedup_hits.fetch_add(hits as u64, Ordering::Relaxed);
+        self.dedup_misses.fetch_add(misses as u64, Ordering::Relaxed);
+    }
+
+    /// Count an open WebSocket until the returned guard is dropped
+    pub fn ws_connected(&self) -> WsConnection {
+        self.ws_connections.fetch_add(1, Ordering::Relaxed);
+        WsConnection(self.ws_connections.clone())
+    }
+
+    /// A WebSocket was closed for not answering pings
+    pub fn record_ws_reaped(&self) {
+        self.ws_reaped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Render every metric in the Prometheus text format
+    pub fn render(&self, pool: &PoolStats) -> String {
+        let mut out = String::new();
+
+        header(&mut out, "http_requests_total", "counter", "HTTP requests by method, route and status");
+        let requests = self.requests.lock().unwrap_or_else(|e| e.into_inner());
+        for ((method, route, status), count) in requests.iter() {
+            let _ = writeln!(
+                out,
+                "entanglement_http_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}",
+                escape(method),
+                escape(route),
+                status,
+                count
+            );
+        }
+        drop(requests);
+
+        let counters = [
+            ("upload_bytes_total", "Request body bytes received on blob, chunk and file routes", &self.upload_bytes),
+            ("download_bytes_total", "Response body bytes sent on blob, chunk and file routes", &self.download_bytes),
+            ("chunk_dedup_hits_total", "Chunks that were already stored", &self.dedup_hits),
+            ("chunk_dedup_misses_total", "Chunks that had to be stored", &self.dedup_misses),
+            ("websocket_reaped_total", "WebSocket connections closed for not answering pings", &self.ws_reaped),
+        ];
+        for (name, help, value) in counters {
+            header(&mut out, name, "counter", help);
+            let _ = writeln!(out, "entanglement_{} {}", name, value.load(Ordering::Relaxed));
+        }
+
+        header(&mut out, "websocket_connections", "gauge", "Open WebSocket sync connections");
+        let _ = writeln!(
+            out,
+            "entanglement_websocket_connections {}",
+            self.ws_connections.load(Ordering::Relaxed)
+        );
+
+        header(&mut out, "db_pool_connections", "gauge", "Database connections by state");
+        let in_use = pool.size.saturating_sub(pool.idle);
+        let _ = writeln!(out, "entanglement_db_pool_connections{{state=\"idle\"}} {}", pool.idle);
+        let _ = writeln!(out, "entanglement_db_pool_connections{{state=\"in_use\"}} {}", in_use);
+        header(&mut out, "db_pool_max_connections", "gauge", "Configured database pool size");
+        let _ = writeln!(out, "entanglement_db_pool_max_connections {}", pool.max);
+
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP entanglement_{} {}.", name, help);
+    let _ = writeln!(out, "# TYPE entanglement_{} {}", name, kind);
+}
+
+/// Escape a label value (backslash, double quote and newline)
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// ============================================================================
+// MIDDLEWARE
+// ============================================================================
+
+/// Count each request by matched route and status. Bodies on binary routes
+/// are wrapped so transferred bytes are counted as they stream.
+pub async fn track_requests(State(metrics): State<Metrics>, req: Request, next: Next) -> Response {
+    let method = req.method().clone();
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_else(|| UNMATCHED.to_string());
+    let binary = is_binary_route(req.uri().path());
+
+    let req = if binary {
+        let counter = metrics.upload_bytes.clone();
+        req.map(|body| Body::new(CountingBody { inner: body, counter }))
+    } else {
+        req
+    };
+
+    let response = next.run(req).await;
+    metrics.record_request(method.as_str(), &route, response.status().as_u16());
+
+    if binary {
+        let counter = metrics.download_bytes.clone();
+        response.map(|body| Body::new(CountingBody { inner: body, counter }))
+    } else {
+        response
+    }
+}
+
+/// Body wrapper adding each data frame's length to a counter
+struct CountingBody {
+    inner: Body,
+    counter: Arc<AtomicU64>,
+}
+
+impl HttpBody for CountingBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, axum::Error>>> {
+        let frame = ready!(Pin::new(&mut self.inner).poll_frame(cx));
+        if let Some(data) = frame.as_ref().and_then(|f| f.as_ref().ok()).and_then(Frame::data_ref) {
+            self.counter.fetch_add(data.len() as u64, Ordering::Relaxed);
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+// ============================================================================
+// ROUTES
+// ============================================================================
+
+pub fn metrics_routes() -> Router<AppState> {
+    Router::new().route("/metrics", get(get_metrics))
+}
+
+/// GET /metrics - requires `Authorization: Bearer <METRICS_TOKEN>` when a token is configured
+async fn get_metrics(State(state): State<AppState>, headers: HeaderMap) -> Result<Response, AppError> {
+    if let Some(token) = &state.config.metrics_token {
+        let presented = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        if !presented.is_some_and(|p| auth::constant_time_eq(p.as_bytes(), token.as_bytes())) {
+            return Err(AppError::Unauthorized("Invalid metrics token".into()));
+        }
+    }
+
+    let pool = PoolStats {
+        size: state.db.size(),
+        idle: state.db.num_idle() as u32,
+        max: state.db.options().get_max_connections(),
+    };
+    let body = state.metrics.render(&pool);
+    Ok(([(header::CONTENT_TYPE, CONTENT_TYPE)], body).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_exposition() {
+        let metrics = Metrics::default();
+        metrics.record_request("GET", "/v1/files/list", 200);
+        metrics.record_request("GET", "/v1/files/list", 200);
+        metrics.record_request("PUT", "/v1/chunks/:hash", 201);
+        metrics.record_dedup(3, 1);
+        metrics.record_ws_reaped();
+        let ws = metrics.ws_connected();
+
+        let text = metrics.render(&PoolStats { size: 4, idle: 1, max: 10 });
+        assert!(text.contains("# TYPE entanglement_http_requests_total counter\n"));
+        assert!(text.contains(
+            "entanglement_http_requests_total{method=\"GET\",route=\"/v1/files/list\",status=\"200\"} 2\n"
+        ));
+        assert!(text.contains("entanglement_chunk_dedup_hits_total 3\n"));
+        assert!(text.contains("entanglement_chunk_dedup_misses_total 1\n"));
+        assert!(text.contains("entanglement_websocket_connections 1\n"));
+        assert!(text.contains("entanglement_websocket_reaped_total 1\n"));
+        assert!(text.contains("entanglement_db_pool_connections{state=\"in_use\"} 3\n"));
+        assert!(text.contains("entanglement_db_pool_max_connections 10\n"));
+
+        drop(ws);
+        let text = metrics.render(&PoolStats { size: 0, idle: 0, max: 10 });
+        assert!(text.contains("entanglement_websocket_connections 0\n"));
+    }
+
+    #[test]
+    fn test_escape_label_values() {
+        assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
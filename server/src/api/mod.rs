@@ -1,8 +1,19 @@
+pub mod client_ip;
+pub mod inflight;
+pub mod maintenance;
+pub mod metrics;
 pub mod rest;
+pub mod webdav;
+pub mod webhook;
 pub mod ws;
 
+use crate::api::inflight::InFlight;
+use crate::api::maintenance::Maintenance;
+use crate::api::metrics::Metrics;
+use crate::api::webdav::CredentialCache;
 use crate::config::Config;
 use crate::db::DbPool;
+use crate::storage::compaction::Compactor;
 use crate::storage::BlobManager;
 use std::sync::Arc;
 
@@ -16,6 +27,16 @@ pub struct AppState {
     pub config: Config,
     /// WebSocket sync hub for real-time notifications
     pub sync_hub: SyncHub,
+    /// Counters and gauges served at `/metrics`
+    pub metrics: Metrics,
+    /// Upload finalizations that shutdown waits for
+    pub in_flight: InFlight,
+    /// Read-only maintenance flag checked by every request
+    pub maintenance: Maintenance,
+    /// Background container repacking, with its last run's stats
+    pub compactor: Arc<Compactor>,
+    /// Basic credentials WebDAV has verified recently
+    pub webdav_credentials: CredentialCache,
 }
 
 impl AppState {
@@ -24,11 +45,18 @@ impl AppState {
         blob_manager: BlobManager,
         config: Config,
     ) -> Self {
+        let compactor = Compactor::new(config.compaction_threshold, config.compaction_window);
+        let maintenance = Maintenance::new(config.maintenance_mode, config.maintenance_retry_after_secs);
         Self {
             db,
             blob_manager: Arc::new(blob_manager),
             config,
             sync_hub: SyncHub::default(),
+            metrics: Metrics::default(),
+            in_flight: InFlight::default(),
+            maintenance,
+            compactor: Arc::new(compactor),
+            webdav_credentials: CredentialCache::default(),
         }
     }
 }
@@ -2,15 +2,20 @@
 //!
 //! Server info, statistics, and health check endpoints.
 
+use crate::api::ws::PresenceEntry;
 use crate::api::AppState;
-use crate::db::users;
+use crate::db::chunks::{self, ChunkDeletion};
+use crate::db::versions::ReleasedChunk;
+use crate::db::{audit, containers, files, users, ChunkLocation};
+use crate::storage::compaction::CompactionRun;
 use axum::{
-    extract::State,
+    extract::{Path, Query, State},
     http::StatusCode,
-    routing::get,
+    routing::{delete, get, post},
     Json, Router,
 };
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
 use super::error::{self, AppError};
 
@@ -21,6 +26,13 @@ use super::error::{self, AppError};
 pub fn admin_routes() -> Router<AppState> {
     Router::new()
         .route("/admin/stats", get(get_stats))
+        .route("/admin/containers", get(list_containers))
+        .route("/admin/containers/:id/repack", post(repack_container))
+        .route("/admin/chunks/:hash", delete(delete_chunk))
+        .route("/admin/maintenance", get(get_maintenance).post(set_maintenance))
+        .route("/admin/audit", get(list_audit_entries))
+        .route("/admin/presence", get(list_presence))
+        .route("/admin/duplicates", get(list_duplicates))
         .route("/server/info", get(get_server_info))
         // Health check endpoints for container orchestration
         .route("/health", get(health_check))
@@ -44,6 +56,131 @@ struct StatsResponse {
     total_files: i64,
     total_versions: i64,
     total_blob_bytes: i64,
+    logical_bytes: i64,
+    physical_bytes: i64,
+    dedup_ratio: f64,
+    container_count: i64,
+    /// Average container size as a fraction of the container size limit
+    avg_container_fill: f64,
+    compaction: CompactionStatus,
+    version_retention: VersionRetentionStatus,
+}
+
+#[derive(Serialize)]
+struct VersionRetentionStatus {
+    /// False when every version is kept
+    enabled: bool,
+    /// Newest versions kept per file
+    keep_last: Option<u32>,
+    /// Versions from the last this many days are kept
+    keep_days: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct CompactionStatus {
+    /// False when paused or given no interval
+    enabled: bool,
+    threshold: f64,
+    interval_secs: u64,
+    /// Off-peak hours (UTC) as `start-end`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    window: Option<String>,
+    /// None until the first pass inside the window completes
+    last_run: Option<CompactionRun>,
+}
+
+#[derive(Serialize)]
+struct ContainerResponse {
+    id: String,
+    disk_path: String,
+    is_sealed: bool,
+    total_size: i64,
+    chunk_count: i32,
+    live_bytes: i64,
+    live_chunks: i64,
+    /// Fraction of `total_size` still referenced by chunks
+    fill_ratio: f64,
+}
+
+#[derive(Serialize)]
+struct RepackResponse {
+    old_container_id: String,
+    /// None when the container held no live chunks and was simply removed
+    new_container_id: Option<String>,
+    chunks_moved: usize,
+    bytes_before: i64,
+    bytes_after: i64,
+}
+
+#[derive(Serialize)]
+struct DeleteChunkResponse {
+    hash: String,
+    size_bytes: i32,
+    /// Container the chunk was in; its bytes stay until the container is repacked
+    container_id: Option<String>,
+    /// Backend that held a standalone chunk (`local` or `s3`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    backend: Option<&'static str>,
+    /// Whether a standalone blob file or object was removed
+    data_removed: bool,
+}
+
+#[derive(Deserialize, Serialize)]
+struct MaintenanceMode {
+    enabled: bool,
+}
+
+#[derive(Deserialize)]
+struct AuditQuery {
+    /// ISO8601 datetime - return entries recorded at or after this time
+    since: Option<String>,
+    /// Max number of entries to return (default and max 1000)
+    limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct AuditEntryResponse {
+    id: i64,
+    timestamp: String,
+    user_id: Option<String>,
+    action: String,
+    path: Option<String>,
+    request_id: Option<String>,
+    ip: Option<String>,
+}
+
+/// Duplicate groups returned per page by default and at most
+const MAX_DUPLICATES_PAGE: i64 = 1000;
+
+#[derive(Deserialize)]
+struct DuplicatesQuery {
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct DuplicatesResponse {
+    groups: Vec<DuplicateGroupResponse>,
+    /// Groups over all pages
+    total_groups: i64,
+    /// Wasted bytes over all pages
+    total_wasted_bytes: i64,
+}
+
+#[derive(Serialize)]
+struct DuplicateGroupResponse {
+    content_hash: String,
+    size_bytes: i64,
+    /// Logical bytes the copies beyond the first take up
+    wasted_bytes: i64,
+    files: Vec<DuplicateFile>,
+}
+
+#[derive(Serialize)]
+struct DuplicateFile {
+    id: String,
+    path: String,
+    owner_id: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -64,25 +201,299 @@ async fn get_server_info(State(state): State<AppState>) -> Json<ServerInfo> {
     })
 }
 
-async fn get_stats(
-    State(state): State<AppState>,
-    headers: axum::http::HeaderMap,
-) -> Result<Json<StatsResponse>, AppError> {
-    // SECURITY: Require admin authentication
-    let user_id = error::extract_user_id(&state, &headers)?;
+/// Fail unless the request carries an admin's token
+async fn require_admin(state: &AppState, headers: &axum::http::HeaderMap) -> Result<(), AppError> {
+    let user_id = error::extract_user_id(state, headers).await?;
     let user = users::get_user_by_id(&state.db, user_id)
         .await?
         .ok_or_else(|| AppError::NotFound("User not found".into()))?;
     if !user.is_admin {
         return Err(AppError::Unauthorized("Admin access required".into()));
     }
+    Ok(())
+}
+
+async fn get_stats(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+) -> Result<Json<StatsResponse>, AppError> {
+    // SECURITY: Require admin authentication
+    require_admin(&state, &headers).await?;
 
     let stats = crate::db::get_stats(&state.db).await?;
+    let container_target_bytes = state.blob_manager.container_target_bytes();
+    let avg_container_fill = if container_target_bytes == 0 {
+        0.0
+    } else {
+        stats.avg_container_bytes as f64 / container_target_bytes as f64
+    };
+
     Ok(Json(StatsResponse {
         total_users: stats.total_users,
         total_files: stats.total_files,
         total_versions: stats.total_versions,
         total_blob_bytes: stats.total_blob_bytes,
+        logical_bytes: stats.logical_bytes,
+        physical_bytes: stats.physical_bytes,
+        dedup_ratio: stats.dedup_ratio(),
+        container_count: stats.container_count,
+        avg_container_fill,
+        compaction: CompactionStatus {
+            enabled: !state.config.compaction_paused && state.config.compaction_interval_secs > 0,
+            threshold: state.compactor.threshold(),
+            interval_secs: state.config.compaction_interval_secs,
+            window: state
+                .compactor
+                .window()
+                .map(|w| format!("{}-{}", w.start_hour, w.end_hour)),
+            last_run: state.compactor.last_run(),
+        },
+        version_retention: {
+            let retention = state.config.version_retention();
+            VersionRetentionStatus {
+                enabled: !retention.is_unlimited(),
+                keep_last: retention.keep_last,
+                keep_days: retention.keep_days,
+            }
+        },
+    }))
+}
+
+/// GET /admin/containers
+///
+/// Lists every container with its live bytes, sparsest first.
+async fn list_containers(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+) -> Result<Json<Vec<ContainerResponse>>, AppError> {
+    require_admin(&state, &headers).await?;
+
+    let usage = containers::list_container_usage(&state.db).await?;
+
+    Ok(Json(
+        usage
+            .into_iter()
+            .map(|c| ContainerResponse {
+                fill_ratio: c.fill_ratio(),
+                id: c.id.to_string(),
+                disk_path: c.disk_path,
+                is_sealed: c.is_sealed,
+                total_size: c.total_size,
+                chunk_count: c.chunk_count,
+                live_bytes: c.live_bytes,
+                live_chunks: c.live_chunks,
+            })
+            .collect(),
+    ))
+}
+
+/// POST /admin/containers/:id/repack
+///
+/// Copies the container's live chunks into a new container and deletes it.
+async fn repack_container(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    headers: axum::http::HeaderMap,
+) -> Result<Json<RepackResponse>, AppError> {
+    require_admin(&state, &headers).await?;
+
+    if containers::get_container(&state.db, id).await?.is_none() {
+        return Err(AppError::NotFound("Container not found".into()));
+    }
+    if state.blob_manager.is_open_container(id).await {
+        return Err(AppError::Conflict("Container is open for writes".into()));
+    }
+
+    let result = state.blob_manager.repack_container(id).await?;
+
+    Ok(Json(RepackResponse {
+        old_container_id: id.to_string(),
+        new_container_id: result.new_container_id.map(|id| id.to_string()),
+        chunks_moved: result.chunks_moved,
+        bytes_before: result.bytes_before,
+        bytes_after: result.bytes_after,
+    }))
+}
+
+/// DELETE /admin/chunks/:hash
+///
+/// Deletes a chunk that no version references (`409` otherwise). A
+/// standalone chunk's file or object is removed with it, unless a legacy
+/// whole-file version still uses the same local blob file. A chunk inside a
+/// container leaves dead bytes that repacking reclaims.
+async fn delete_chunk(
+    State(state): State<AppState>,
+    Path(hash): Path<String>,
+    headers: axum::http::HeaderMap,
+) -> Result<Json<DeleteChunkResponse>, AppError> {
+    require_admin(&state, &headers).await?;
+
+    if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(AppError::BadRequest("Chunk hash must be 64 hex characters".into()));
+    }
+
+    let chunk = match chunks::delete_unreferenced_chunk(&state.db, &hash).await? {
+        ChunkDeletion::NotFound => return Err(AppError::NotFound("Chunk not found".into())),
+        ChunkDeletion::Referenced(count) => {
+            return Err(AppError::Conflict(format!(
+                "Chunk is referenced by {} version chunk{}",
+                count,
+                if count == 1 { "" } else { "s" }
+            )));
+        }
+        ChunkDeletion::Deleted(chunk) => chunk,
+    };
+
+    let (container_id, backend, data_removed) = match chunk.location() {
+        ChunkLocation::Container { container_id, .. } => (Some(container_id.to_string()), None, false),
+        ChunkLocation::Standalone { hash } | ChunkLocation::Object { hash, .. } => {
+            // Removed under the chunk's data lock, so a concurrent upload of
+            // the same content keeps its data. The record is already gone,
+            // so a failure here only strands the data.
+            let released = ReleasedChunk { hash, backend: chunk.backend };
+            let removed = match state.blob_manager.delete_released_chunk(&state.db, &released).await {
+                Ok(removed) => removed,
+                Err(e) => {
+                    tracing::warn!("Deleted chunk {} but not its data: {}", released.hash, e);
+                    false
+                }
+            };
+            (None, Some(chunk.backend.as_str()), removed)
+        }
+    };
+
+    tracing::info!("Admin deleted unreferenced chunk {} ({} bytes)", chunk.hash, chunk.size_bytes);
+
+    Ok(Json(DeleteChunkResponse {
+        hash: chunk.hash,
+        size_bytes: chunk.size_bytes,
+        container_id,
+        backend,
+        data_removed,
+    }))
+}
+
+/// GET /admin/maintenance
+async fn get_maintenance(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+) -> Result<Json<MaintenanceMode>, AppError> {
+    require_admin(&state, &headers).await?;
+    Ok(Json(MaintenanceMode {
+        enabled: state.maintenance.is_enabled(),
+    }))
+}
+
+/// POST /admin/maintenance
+///
+/// Turns read-only maintenance mode on or off; see [`crate::api::maintenance`].
+async fn set_maintenance(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+    Json(req): Json<MaintenanceMode>,
+) -> Result<Json<MaintenanceMode>, AppError> {
+    require_admin(&state, &headers).await?;
+    if state.maintenance.set(req.enabled) != req.enabled {
+        if req.enabled {
+            tracing::warn!("Maintenance mode on: refusing writes");
+        } else {
+            tracing::info!("Maintenance mode off");
+        }
+    }
+    Ok(Json(MaintenanceMode { enabled: req.enabled }))
+}
+
+/// GET /admin/audit?since=2026-01-01T00:00:00Z&limit=100
+///
+/// Lists audit log entries oldest first. Page by passing the last entry's
+/// timestamp as `since` (entries sharing that timestamp are repeated).
+async fn list_audit_entries(
+    State(state): State<AppState>,
+    Query(query): Query<AuditQuery>,
+    headers: axum::http::HeaderMap,
+) -> Result<Json<Vec<AuditEntryResponse>>, AppError> {
+    require_admin(&state, &headers).await?;
+
+    let since = query
+        .since
+        .as_deref()
+        .map(|s| {
+            chrono::DateTime::parse_from_rfc3339(s)
+                .map(|t| t.with_timezone(&chrono::Utc))
+                .map_err(|e| AppError::BadRequest(format!("Invalid since timestamp: {}", e)))
+        })
+        .transpose()?;
+    let limit = query
+        .limit
+        .unwrap_or(audit::MAX_AUDIT_PAGE)
+        .clamp(1, audit::MAX_AUDIT_PAGE);
+
+    let entries = audit::list_entries(&state.db, since, limit).await?;
+
+    Ok(Json(
+        entries
+            .into_iter()
+            .map(|e| AuditEntryResponse {
+                id: e.id,
+                timestamp: e.created_at.to_rfc3339(),
+                user_id: e.user_id.map(|id| id.to_string()),
+                action: e.action,
+                path: e.path,
+                request_id: e.request_id,
+                ip: e.ip,
+            })
+            .collect(),
+    ))
+}
+
+/// Open WebSocket sync connections, oldest first
+async fn list_presence(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+) -> Result<Json<Vec<PresenceEntry>>, AppError> {
+    require_admin(&state, &headers).await?;
+    Ok(Json(state.sync_hub.roster()))
+}
+
+/// GET /admin/duplicates?limit=100&offset=0
+///
+/// Groups files whose current versions have the same content, across all
+/// users, most wasted bytes first. Storage is deduplicated already; this is
+/// for cleaning up the copies themselves. Empty files are left out.
+async fn list_duplicates(
+    State(state): State<AppState>,
+    Query(query): Query<DuplicatesQuery>,
+    headers: axum::http::HeaderMap,
+) -> Result<Json<DuplicatesResponse>, AppError> {
+    require_admin(&state, &headers).await?;
+
+    let limit = query.limit.unwrap_or(MAX_DUPLICATES_PAGE).clamp(1, MAX_DUPLICATES_PAGE);
+    let offset = query.offset.unwrap_or(0).max(0);
+    let (groups, total_groups, total_wasted_bytes) =
+        files::list_duplicate_groups(&state.db, limit, offset).await?;
+
+    Ok(Json(DuplicatesResponse {
+        groups: groups
+            .into_iter()
+            .map(|g| DuplicateGroupResponse {
+                files: g
+                    .file_ids
+                    .iter()
+                    .zip(g.paths)
+                    .zip(g.owner_ids)
+                    .map(|((id, path), owner_id)| DuplicateFile {
+                        id: id.to_string(),
+                        path,
+                        owner_id: owner_id.map(|id| id.to_string()),
+                    })
+                    .collect(),
+                content_hash: g.content_hash,
+                size_bytes: g.size_bytes,
+                wasted_bytes: g.wasted_bytes,
+            })
+            .collect(),
+        total_groups,
+        total_wasted_bytes,
     }))
 }
 
@@ -0,0 +1,61 @@
+//! Audit logging for REST handlers
+//!
+//! Handlers that mutate files or expose them through share links take an
+//! [`AuditContext`] extractor and call [`AuditContext::record`] once the
+//! operation has succeeded. Failing to write an entry never fails the request.
+
+use crate::api::client_ip::ClientIp;
+use crate::api::AppState;
+use crate::db::audit::{self, AuditAction, NewAuditEntry};
+use axum::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use std::convert::Infallible;
+use uuid::Uuid;
+
+/// Request metadata attached to every audit entry
+pub struct AuditContext {
+    request_id: Option<String>,
+    ip: Option<String>,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for AuditContext {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let request_id = parts
+            .headers
+            .get("x-request-id")
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        let ip = parts
+            .extensions
+            .get::<ClientIp>()
+            .map(|ClientIp(ip)| ip.to_string());
+
+        Ok(AuditContext { request_id, ip })
+    }
+}
+
+impl AuditContext {
+    /// Append an entry to the audit log, logging (not returning) failures
+    pub async fn record(
+        &self,
+        state: &AppState,
+        user_id: Option<Uuid>,
+        action: AuditAction,
+        path: Option<&str>,
+    ) {
+        let entry = NewAuditEntry {
+            user_id,
+            action,
+            path,
+            request_id: self.request_id.as_deref(),
+            ip: self.ip.as_deref(),
+        };
+        if let Err(e) = audit::insert_entry(&state.db, &entry).await {
+            tracing::warn!(action = action.as_str(), "Failed to write audit entry: {}", e);
+        }
+    }
+}
@@ -4,7 +4,7 @@
 
 use crate::api::AppState;
 use crate::auth;
-use crate::db::users;
+use crate::db::users::{self, UserRole};
 use axum::{
     extract::{Path, State},
     routing::{delete, get, post, put},
@@ -13,7 +13,7 @@ use axum::{
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use super::error::AppError;
+use super::error::{extract_user_id, AppError};
 
 // ============================================================================
 // ROUTES
@@ -24,12 +24,18 @@ pub fn auth_routes() -> Router<AppState> {
         // Public auth routes
         .route("/auth/login", post(login))
         .route("/auth/refresh", post(refresh_token))
+        // TOTP two-factor enrollment
+        .route("/auth/totp/enroll", post(enroll_totp))
+        .route("/auth/totp/verify", post(verify_totp))
         // Admin routes (require admin auth)
         .route("/admin/users", get(list_users))
         .route("/admin/users", post(create_user))
-        .route("/admin/users/:id", delete(delete_user))
+        .route("/admin/users/:id", delete(delete_user).patch(update_user))
         .route("/admin/users/:id/password", put(reset_user_password))
         .route("/admin/users/:id/admin", put(toggle_admin))
+        .route("/admin/users/:id/totp", delete(reset_user_totp))
+        .route("/admin/users/:id/disable", post(disable_user))
+        .route("/admin/users/:id/enable", post(enable_user))
         // Current user info
         .route("/auth/me", get(get_current_user))
 }
@@ -38,10 +44,16 @@ pub fn auth_routes() -> Router<AppState> {
 // TYPES
 // ============================================================================
 
+/// Error message for a login that needs a TOTP code; clients prompt on it
+const TOTP_REQUIRED: &str = "TOTP code required";
+
 #[derive(Deserialize)]
 struct LoginRequest {
     username: String,
     password: String,
+    /// Required once the user has enabled TOTP
+    #[serde(default)]
+    totp_code: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -51,6 +63,7 @@ struct AuthResponse {
     user_id: String,
     username: String,
     is_admin: bool,
+    role: UserRole,
     /// Token expiration time in seconds (24 hours)
     expires_in: i64,
 }
@@ -65,6 +78,8 @@ struct CreateUserRequest {
     username: String,
     password: String,
     is_admin: Option<bool>,
+    /// Takes precedence over `is_admin`; defaults to editor
+    role: Option<UserRole>,
 }
 
 #[derive(Serialize)]
@@ -72,6 +87,8 @@ struct UserResponse {
     id: String,
     username: String,
     is_admin: bool,
+    role: UserRole,
+    disabled: bool,
     created_at: String,
 }
 
@@ -80,6 +97,11 @@ struct ResetPasswordRequest {
     new_password: String,
 }
 
+#[derive(Deserialize)]
+struct UpdateUserRequest {
+    role: Option<UserRole>,
+}
+
 #[derive(Deserialize)]
 struct SetAdminRequest {
     is_admin: bool,
@@ -90,6 +112,19 @@ struct MessageResponse {
     message: String,
 }
 
+#[derive(Serialize)]
+struct TotpEnrollResponse {
+    /// Base32 secret, for apps that cannot scan the URI
+    secret: String,
+    /// `otpauth://` URI to render as a QR code
+    provisioning_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TotpCodeRequest {
+    code: String,
+}
+
 // ============================================================================
 // HANDLERS - Public
 // ============================================================================
@@ -124,7 +159,37 @@ async fn login(
         }
     }
 
-    let token = match auth::create_access_token(&state.config.jwt_secret, user.id) {
+    if user.disabled {
+        tracing::warn!("Login refused for disabled user: {}", req.username);
+        return Err(AppError::Forbidden("Account is disabled".into()));
+    }
+
+    if let Some(totp) = users::get_totp(&state.db, user.id).await?.filter(|t| t.enabled) {
+        let code = req
+            .totp_code
+            .as_deref()
+            .ok_or_else(|| AppError::Unauthorized(TOTP_REQUIRED.into()))?;
+        let step = check_totp_code(&state, &totp.secret, code)?
+            .ok_or_else(|| AppError::Unauthorized("Invalid TOTP code".into()))?;
+        if !users::consume_totp_step(&state.db, user.id, step).await? {
+            tracing::warn!("Replayed TOTP code for user: {}", req.username);
+            return Err(AppError::Unauthorized("Invalid TOTP code".into()));
+        }
+    }
+
+    // Bring hashes made under older, weaker Argon2 settings up to date
+    let hash_params = state.config.hash_params();
+    if auth::needs_rehash(&user.password_hash, &hash_params) {
+        match auth::hash_password(&req.password, &hash_params) {
+            Ok(hash) => match users::update_password(&state.db, user.id, &hash).await {
+                Ok(_) => tracing::info!("Rehashed password for user {} with current Argon2 parameters", user.id),
+                Err(e) => tracing::warn!("Failed to store rehashed password for user {}: {}", user.id, e),
+            },
+            Err(e) => tracing::warn!("Failed to rehash password for user {}: {}", user.id, e),
+        }
+    }
+
+    let token = match auth::create_access_token(&state.config.jwt_secret, user.id, user.token_version, state.config.access_token_ttl_secs) {
         Ok(t) => t,
         Err(e) => {
             tracing::error!("Token creation error: {}", e);
@@ -132,7 +197,7 @@ async fn login(
         }
     };
     
-    let refresh_token = match auth::create_refresh_token(&state.config.jwt_secret, user.id) {
+    let refresh_token = match auth::create_refresh_token(&state.config.jwt_secret, user.id, user.token_version, state.config.refresh_token_ttl_secs) {
         Ok(t) => t,
         Err(e) => {
             tracing::error!("Refresh token creation error: {}", e);
@@ -148,7 +213,8 @@ async fn login(
         user_id: user.id.to_string(),
         username: user.username,
         is_admin: user.is_admin,
-        expires_in: 24 * 60 * 60, // 24 hours in seconds
+        role: user.role,
+        expires_in: state.config.access_token_ttl_secs,
     }))
 }
 
@@ -158,17 +224,24 @@ async fn refresh_token(
     Json(req): Json<RefreshRequest>,
 ) -> Result<Json<AuthResponse>, AppError> {
     // Verify the refresh token
-    let user_id = auth::verify_refresh_token(&state.config.jwt_secret, &req.refresh_token)
+    let subject = auth::verify_refresh_token(&state.config.jwt_secret, &req.refresh_token)
         .map_err(|_| AppError::Unauthorized("Invalid or expired refresh token".into()))?;
+    let user_id = subject.user_id;
 
     // Get user to return updated info
     let user = users::get_user_by_id(&state.db, user_id)
         .await?
         .ok_or_else(|| AppError::NotFound("User not found".into()))?;
+    if user.disabled {
+        return Err(AppError::Forbidden("Account is disabled".into()));
+    }
+    if subject.token_version != user.token_version {
+        return Err(AppError::Unauthorized("Invalid or expired refresh token".into()));
+    }
 
     // Create new tokens
-    let token = auth::create_access_token(&state.config.jwt_secret, user_id)?;
-    let new_refresh_token = auth::create_refresh_token(&state.config.jwt_secret, user_id)?;
+    let token = auth::create_access_token(&state.config.jwt_secret, user_id, user.token_version, state.config.access_token_ttl_secs)?;
+    let new_refresh_token = auth::create_refresh_token(&state.config.jwt_secret, user_id, user.token_version, state.config.refresh_token_ttl_secs)?;
 
     Ok(Json(AuthResponse {
         token,
@@ -176,7 +249,8 @@ async fn refresh_token(
         user_id: user_id.to_string(),
         username: user.username,
         is_admin: user.is_admin,
-        expires_in: 24 * 60 * 60, // 24 hours in seconds
+        role: user.role,
+        expires_in: state.config.access_token_ttl_secs,
     }))
 }
 
@@ -185,7 +259,7 @@ async fn get_current_user(
     State(state): State<AppState>,
     headers: axum::http::HeaderMap,
 ) -> Result<Json<UserResponse>, AppError> {
-    let user_id = extract_user_id(&state, &headers)?;
+    let user_id = extract_user_id(&state, &headers).await?;
     
     let user = users::get_user_by_id(&state.db, user_id)
         .await?
@@ -195,10 +269,81 @@ async fn get_current_user(
         id: user.id.to_string(),
         username: user.username,
         is_admin: user.is_admin,
+        role: user.role,
+        disabled: user.disabled,
         created_at: user.created_at.to_rfc3339(),
     }))
 }
 
+// ============================================================================
+// HANDLERS - TOTP
+// ============================================================================
+
+/// Start TOTP enrollment: store a new pending secret and return it
+/// POST /auth/totp/enroll
+///
+/// Login is unaffected until the secret is confirmed via /auth/totp/verify.
+async fn enroll_totp(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+) -> Result<Json<TotpEnrollResponse>, AppError> {
+    let user_id = extract_user_id(&state, &headers).await?;
+    let user = users::get_user_by_id(&state.db, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".into()))?;
+
+    let secret = auth::totp::generate_secret();
+    let encrypted = auth::totp::encrypt_secret(state.config.totp_key(), &secret)?;
+    if !users::set_pending_totp(&state.db, user_id, &encrypted).await? {
+        return Err(AppError::Conflict("TOTP is already enabled".into()));
+    }
+
+    tracing::info!("TOTP enrollment started for user {}", user_id);
+
+    Ok(Json(TotpEnrollResponse {
+        provisioning_uri: auth::totp::provisioning_uri(&secret, &user.username)?,
+        secret,
+    }))
+}
+
+/// Confirm TOTP enrollment with a code from the authenticator app
+/// POST /auth/totp/verify
+async fn verify_totp(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+    Json(req): Json<TotpCodeRequest>,
+) -> Result<Json<MessageResponse>, AppError> {
+    let user_id = extract_user_id(&state, &headers).await?;
+    let totp = users::get_totp(&state.db, user_id)
+        .await?
+        .ok_or_else(|| AppError::BadRequest("TOTP enrollment has not been started".into()))?;
+    if totp.enabled {
+        return Err(AppError::Conflict("TOTP is already enabled".into()));
+    }
+
+    let step = check_totp_code(&state, &totp.secret, &req.code)?
+        .ok_or_else(|| AppError::BadRequest("Invalid TOTP code".into()))?;
+    if !users::enable_totp(&state.db, user_id, step).await? {
+        return Err(AppError::Conflict("TOTP is already enabled".into()));
+    }
+
+    tracing::info!("TOTP enabled for user {}", user_id);
+
+    Ok(Json(MessageResponse {
+        message: "TOTP enabled".into(),
+    }))
+}
+
+/// Verify `code` against an encrypted secret, returning the matched time step
+fn check_totp_code(state: &AppState, encrypted_secret: &str, code: &str) -> Result<Option<i64>, AppError> {
+    let secret = auth::totp::decrypt_secret(state.config.totp_key(), encrypted_secret).map_err(|e| {
+        tracing::error!("TOTP secret decryption failed: {}", e);
+        AppError::Internal("Authentication error".into())
+    })?;
+    let now = chrono::Utc::now().timestamp() as u64;
+    Ok(auth::totp::verify_code(&secret, code, now)?.map(|step| step as i64))
+}
+
 // ============================================================================
 // HANDLERS - Admin Only
 // ============================================================================
@@ -218,6 +363,8 @@ async fn list_users(
             id: u.id.to_string(),
             username: u.username,
             is_admin: u.is_admin,
+            role: u.role,
+            disabled: u.disabled,
             created_at: u.created_at.to_rfc3339(),
         })
         .collect();
@@ -247,12 +394,16 @@ async fn create_user(
     }
     
     // Validate password
-    if req.password.len() < 4 {
-        return Err(AppError::BadRequest("Password must be at least 4 characters".into()));
-    }
+    auth::validate_password_policy(&req.password, &state.config.password_policy())
+        .map_err(|e| AppError::BadRequest(e.to_string()))?;
     
-    let password_hash = auth::hash_password(&req.password)?;
-    let user = users::create_user(&state.db, &req.username, &password_hash, req.is_admin.unwrap_or(false)).await?;
+    let password_hash = auth::hash_password(&req.password, &state.config.hash_params())?;
+    let role = req.role.unwrap_or(if req.is_admin.unwrap_or(false) {
+        UserRole::Admin
+    } else {
+        UserRole::Editor
+    });
+    let user = users::create_user(&state.db, &req.username, &password_hash, role).await?;
     
     tracing::info!("Admin created new user: {}", user.username);
     
@@ -260,6 +411,8 @@ async fn create_user(
         id: user.id.to_string(),
         username: user.username,
         is_admin: user.is_admin,
+        role: user.role,
+        disabled: user.disabled,
         created_at: user.created_at.to_rfc3339(),
     }))
 }
@@ -299,11 +452,10 @@ async fn reset_user_password(
     require_admin(&state, &headers).await?;
     
     // Validate password
-    if req.new_password.len() < 4 {
-        return Err(AppError::BadRequest("Password must be at least 4 characters".into()));
-    }
+    auth::validate_password_policy(&req.new_password, &state.config.password_policy())
+        .map_err(|e| AppError::BadRequest(e.to_string()))?;
     
-    let password_hash = auth::hash_password(&req.new_password)?;
+    let password_hash = auth::hash_password(&req.new_password, &state.config.hash_params())?;
     let updated = users::update_password(&state.db, user_id, &password_hash).await?;
     
     if updated {
@@ -316,6 +468,102 @@ async fn reset_user_password(
     }
 }
 
+/// Remove a user's TOTP enrollment, e.g. after a lost device (admin only)
+async fn reset_user_totp(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<MessageResponse>, AppError> {
+    let admin_id = require_admin(&state, &headers).await?;
+
+    if users::clear_totp(&state.db, user_id).await? {
+        tracing::info!("Admin {} reset TOTP for user {}", admin_id, user_id);
+        Ok(Json(MessageResponse {
+            message: "TOTP reset successfully".into(),
+        }))
+    } else {
+        Err(AppError::NotFound("User not found".into()))
+    }
+}
+
+/// Disable a user and revoke their tokens (admin only)
+/// POST /admin/users/:id/disable
+async fn disable_user(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<MessageResponse>, AppError> {
+    let admin_id = require_admin(&state, &headers).await?;
+
+    // Prevent self-lockout
+    if user_id == admin_id {
+        return Err(AppError::BadRequest("Cannot disable yourself".into()));
+    }
+
+    if !users::set_disabled(&state.db, user_id, true).await? {
+        return Err(AppError::NotFound("User not found".into()));
+    }
+    tracing::info!("Admin {} disabled user {}", admin_id, user_id);
+    Ok(Json(MessageResponse {
+        message: "User disabled".into(),
+    }))
+}
+
+/// Re-enable a disabled user (admin only). Tokens revoked by disabling stay
+/// revoked; the user logs in again.
+/// POST /admin/users/:id/enable
+async fn enable_user(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<MessageResponse>, AppError> {
+    let admin_id = require_admin(&state, &headers).await?;
+
+    if !users::set_disabled(&state.db, user_id, false).await? {
+        return Err(AppError::NotFound("User not found".into()));
+    }
+    tracing::info!("Admin {} enabled user {}", admin_id, user_id);
+    Ok(Json(MessageResponse {
+        message: "User enabled".into(),
+    }))
+}
+
+/// Update a user's role (admin only)
+/// PATCH /admin/users/:id with `{"role": "admin" | "editor" | "viewer"}`
+async fn update_user(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+    Path(user_id): Path<Uuid>,
+    Json(req): Json<UpdateUserRequest>,
+) -> Result<Json<UserResponse>, AppError> {
+    let admin_id = require_admin(&state, &headers).await?;
+
+    let Some(role) = req.role else {
+        return Err(AppError::BadRequest("Nothing to update".into()));
+    };
+    // Prevent self-demotion
+    if user_id == admin_id && role != UserRole::Admin {
+        return Err(AppError::BadRequest("Cannot remove your own admin status".into()));
+    }
+
+    if !users::set_role(&state.db, user_id, role).await? {
+        return Err(AppError::NotFound("User not found".into()));
+    }
+    tracing::info!("Admin {} set user {} role to {}", admin_id, user_id, role.as_str());
+
+    let user = users::get_user_by_id(&state.db, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".into()))?;
+    Ok(Json(UserResponse {
+        id: user.id.to_string(),
+        username: user.username,
+        is_admin: user.is_admin,
+        role: user.role,
+        disabled: user.disabled,
+        created_at: user.created_at.to_rfc3339(),
+    }))
+}
+
 /// Toggle admin status (admin only)
 async fn toggle_admin(
     State(state): State<AppState>,
@@ -346,24 +594,9 @@ async fn toggle_admin(
 // HELPERS
 // ============================================================================
 
-/// Extract user ID from authorization header
-fn extract_user_id(state: &AppState, headers: &axum::http::HeaderMap) -> Result<Uuid, AppError> {
-    let auth_header = headers
-        .get(axum::http::header::AUTHORIZATION)
-        .and_then(|h| h.to_str().ok())
-        .ok_or_else(|| AppError::Unauthorized("Missing authorization header".into()))?;
-
-    let token = auth_header
-        .strip_prefix("Bearer ")
-        .ok_or_else(|| AppError::Unauthorized("Invalid authorization format".into()))?;
-
-    auth::verify_token(&state.config.jwt_secret, token)
-        .map_err(|_| AppError::Unauthorized("Invalid or expired token".into()))
-}
-
 /// Require user to be an admin, returns admin user ID
 async fn require_admin(state: &AppState, headers: &axum::http::HeaderMap) -> Result<Uuid, AppError> {
-    let user_id = extract_user_id(state, headers)?;
+    let user_id = extract_user_id(state, headers).await?;
     
     let user = users::get_user_by_id(&state.db, user_id)
         .await?
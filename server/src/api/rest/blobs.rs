@@ -3,6 +3,7 @@
 //! Handles blob upload/download and file metadata creation.
 
 use crate::api::AppState;
+use crate::db::audit::AuditAction;
 use crate::db::{files, versions};
 use axum::{
     extract::{Path, State},
@@ -14,7 +15,8 @@ use axum::{
 use blake3;
 use serde::Deserialize;
 
-use super::error::{extract_user_id, validate_path, AppError};
+use super::audit::AuditContext;
+use super::error::{extract_user_id, extract_writer_id, validate_path, validate_unix_mode, validate_version_note, AppError};
 use super::types::UploadResponse;
 
 // ============================================================================
@@ -40,6 +42,12 @@ struct CreateFileRequest {
     created_at: Option<String>,
     /// Original filesystem modification time (ISO8601)
     updated_at: Option<String>,
+    /// Permission bits of the local file (e.g. 493 for 0755)
+    #[serde(default)]
+    unix_mode: Option<u32>,
+    /// Note on the new version, shown in the file's history
+    #[serde(default)]
+    note: Option<String>,
 }
 
 // ============================================================================
@@ -54,7 +62,7 @@ pub async fn upload_blob(
     headers: axum::http::HeaderMap,
     body: axum::body::Bytes,
 ) -> Result<StatusCode, AppError> {
-    let _user_id = extract_user_id(&state, &headers)?;
+    let _user_id = extract_writer_id(&state, &headers).await?;
     
     // Verify the hash matches the content using BLAKE3
     let computed_hash = blake3::hash(&body).to_hex().to_string();
@@ -67,8 +75,8 @@ pub async fn upload_blob(
     }
     
     // Store blob if not exists (deduplication)
-    if !state.blob_manager.legacy_exists(&hash)? {
-        state.blob_manager.write_legacy_blob(&hash, &body)?;
+    if !state.blob_manager.legacy_exists(&hash).await? {
+        state.blob_manager.write_legacy_blob(&hash, &body).await?;
     }
     
     Ok(StatusCode::CREATED)
@@ -80,33 +88,63 @@ pub async fn download_blob(
     Path(hash): Path<String>,
     headers: axum::http::HeaderMap,
 ) -> Result<impl IntoResponse, AppError> {
-    let _user_id = extract_user_id(&state, &headers)?;
+    let _user_id = extract_user_id(&state, &headers).await?;
     
-    if !state.blob_manager.legacy_exists(&hash)? {
+    if !state.blob_manager.legacy_exists(&hash).await? {
         return Err(AppError::NotFound("Blob not found".into()));
     }
     
-    let content = state.blob_manager.read_legacy_blob(&hash)?;
+    let content = state.blob_manager.read_legacy_blob(&hash).await?;
     
     Ok((
         StatusCode::OK,
-        [(header::CONTENT_TYPE, "application/octet-stream")],
+        blob_headers(&hash, content.len() as u64),
         content,
     ))
 }
 
+/// Headers of `GET /blobs/:hash` without reading the blob
+/// HEAD /blobs/:hash
+pub async fn head_blob(
+    State(state): State<AppState>,
+    Path(hash): Path<String>,
+    headers: axum::http::HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    let _user_id = extract_user_id(&state, &headers).await?;
+
+    let length = state
+        .blob_manager
+        .legacy_blob_size(&hash)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Blob not found".into()))?;
+
+    Ok((StatusCode::OK, blob_headers(&hash, length)))
+}
+
+/// Blobs are immutable and addressed by hash, so the hash is the ETag
+fn blob_headers(hash: &str, length: u64) -> [(header::HeaderName, String); 3] {
+    [
+        (header::CONTENT_TYPE, "application/octet-stream".to_string()),
+        (header::CONTENT_LENGTH, length.to_string()),
+        (header::ETAG, format!("\"{}\"", hash)),
+    ]
+}
+
 async fn create_file_metadata(
     State(state): State<AppState>,
     headers: axum::http::HeaderMap,
+    audit: AuditContext,
     Json(req): Json<CreateFileRequest>,
 ) -> Result<Json<UploadResponse>, AppError> {
-    let _user_id = extract_user_id(&state, &headers)?;
+    let user_id = extract_writer_id(&state, &headers).await?;
     
     // SECURITY: Validate path to prevent path traversal
-    validate_path(&req.path)?;
+    validate_path(&req.path, state.config.path_limits())?;
+    let unix_mode = validate_unix_mode(req.unix_mode)?;
+    let note = validate_version_note(req.note.as_deref())?;
     
     // Verify blob exists
-    if !state.blob_manager.legacy_exists(&req.blob_hash)? {
+    if !state.blob_manager.legacy_exists(&req.blob_hash).await? {
         return Err(AppError::BadRequest("Blob not found - upload blob first".into()));
     }
     
@@ -123,6 +161,9 @@ async fn create_file_metadata(
     
     // Upsert file record with client-provided dates (shared folder system - no ownership)
     let file = files::upsert_file_with_dates(&state.db, &req.path, created_at, updated_at).await?;
+    if let Some(mode) = unix_mode {
+        files::set_unix_mode(&state.db, file.id, mode).await?;
+    }
 
     // Check if current version already has this hash (skip duplicate versions)
     if let Some(current_version_id) = file.current_version_id {
@@ -139,16 +180,23 @@ async fn create_file_metadata(
         }
     }
 
-    // Create new version (only if content changed) without user tracking (shared folder system)
-    let version = versions::create_version_global(
-        &state.db,
-        file.id,
-        &req.blob_hash,
-        req.size_bytes,
-    ).await?;
-    
-    // Update current version
-    files::set_current_version(&state.db, file.id, version.id).await?;
+    // Create new version (only if content changed) without user tracking
+    // (shared folder system) and make it current, without being cut off by a
+    // disconnect or shutdown
+    state.in_flight.run({
+        let db = state.db.clone();
+        let blob_hash = req.blob_hash.clone();
+        let size_bytes = req.size_bytes;
+        async move {
+            let version = versions::create_version_global(&db, file.id, &blob_hash, size_bytes).await?;
+            if note.is_some() {
+                versions::set_note(&db, version.id, note.as_deref()).await?;
+            }
+            files::set_current_version(&db, file.id, version.id).await?;
+            Ok(())
+        }
+    }).await?;
+    audit.record(&state, Some(user_id), AuditAction::Upload, Some(&req.path)).await;
     
     Ok(Json(UploadResponse {
         id: file.id.to_string(),
@@ -3,7 +3,9 @@
 //! Handles chunk upload, download, existence check, and chunked file creation.
 
 use crate::api::AppState;
+use crate::db::audit::AuditAction;
 use crate::db::{chunks, files, versions, ChunkTier};
+use crate::storage::chunking::EMPTY_CHUNK_HASH;
 use crate::storage::store_chunk;
 use axum::{
     extract::{Path, State},
@@ -16,7 +18,8 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 use uuid::Uuid;
 
-use super::error::{extract_user_id, validate_path, AppError};
+use super::audit::AuditContext;
+use super::error::{extract_user_id, extract_writer_id, short_id_prefix, unique_match, validate_path, AppError};
 
 // ============================================================================
 // TYPES
@@ -95,15 +98,14 @@ pub async fn check_chunks(
     headers: axum::http::HeaderMap,
     Json(req): Json<CheckChunksRequest>,
 ) -> Result<Json<CheckChunksResponse>, AppError> {
-    let _user_id = extract_user_id(&state, &headers)?;
+    let _user_id = extract_user_id(&state, &headers).await?;
     
-    let existing = chunks::get_existing_chunks(&state.db, &req.hashes).await?;
-    let existing_set: HashSet<&String> = existing.iter().collect();
-    
-    let missing: Vec<String> = req.hashes.iter()
-        .filter(|h| !existing_set.contains(h))
-        .cloned()
-        .collect();
+    // Only hashes the bloom filter can't rule out need a database lookup
+    let (possible, _) = state.blob_manager.chunk_filter().partition(&req.hashes);
+    let found = chunks::get_existing_chunks(&state.db, &possible).await?;
+    let (existing, missing) = split_existing(&req.hashes, found);
+    // Misses are counted when the missing chunks are uploaded
+    state.metrics.record_dedup(existing.len(), 0);
     
     Ok(Json(CheckChunksResponse { existing, missing }))
 }
@@ -118,36 +120,36 @@ pub async fn upload_chunk(
     headers: axum::http::HeaderMap,
     body: axum::body::Bytes,
 ) -> Result<StatusCode, AppError> {
-    let _user_id = extract_user_id(&state, &headers)?;
-    
-    // Verify the hash matches the content using BLAKE3
-    let computed_hash = blake3::hash(&body).to_hex().to_string();
+    let _user_id = extract_writer_id(&state, &headers).await?;
     
-    if computed_hash != hash {
-        return Err(AppError::BadRequest(format!(
-            "Chunk hash mismatch: expected {}, got {}",
-            hash, computed_hash
-        )));
+    if !verify_chunk(&hash, &body)? {
+        return Ok(StatusCode::OK);
     }
     
     // Check if chunk already exists
     if chunks::chunk_exists(&state.db, &hash).await? {
         // Chunk already exists - idempotent success
+        state.metrics.record_dedup(1, 0);
         return Ok(StatusCode::OK);
     }
     
-    // Get tier from header, default to Standard (2)
+    // Get tier from header; without one, skip compressing data that won't
+    // compress (or default to Standard (2) with the probe off)
     let tier = headers
         .get("X-Chunk-Tier")
         .and_then(|v| v.to_str().ok())
         .and_then(|s| s.parse::<i16>().ok())
         .and_then(ChunkTier::from_i16)
-        .unwrap_or(ChunkTier::Standard);
+        .unwrap_or_else(|| {
+            state.config.compression_probe()
+                .map_or(ChunkTier::Standard, |probe| probe.tier_for_chunk(&body))
+        });
     
     // Store chunk using BlobManager (with compression for tiers 0-2)
     store_chunk(&state.blob_manager, &state.db, &hash, &body, tier)
         .await
-        .map_err(|e| AppError::Internal(format!("Failed to store chunk: {}", e)))?;
+        .map_err(|e| AppError::from(e.context("Failed to store chunk")))?;
+    state.metrics.record_dedup(0, 1);
     
     // Chunk upload logging - trace level to avoid log spam
     tracing::trace!("Chunk uploaded: {} ({} bytes)", hash.get(..8).unwrap_or(&hash), body.len());
@@ -162,39 +164,31 @@ pub async fn download_chunk(
     Path(hash): Path<String>,
     headers: axum::http::HeaderMap,
 ) -> Result<impl IntoResponse, AppError> {
-    let _user_id = extract_user_id(&state, &headers)?;
+    let _user_id = extract_user_id(&state, &headers).await?;
+
+    // Accept a unique hash prefix (e.g. copied from logs) in place of the full hash
+    let hash = if hash.len() < 64 {
+        let prefix = short_id_prefix(&hash)?;
+        let matches = chunks::find_chunks_by_prefix(&state.db, &prefix).await?;
+        unique_match(matches, "Chunk", &prefix)?
+    } else {
+        hash
+    };
     
     // First, try to get chunk info from database to find its location
     if let Some(chunk) = chunks::get_chunk_with_location(&state.db, &hash).await? {
-        // Check if chunk is stored in a container
-        if let (Some(container_id), Some(offset), Some(length)) = 
-            (chunk.container_id, chunk.offset_bytes, chunk.length_bytes) 
-        {
-            // Determine if compressed: if length < size_bytes, it was compressed
-            let is_compressed = length < chunk.size_bytes;
-            
-            // Read from container using BlobManager
-            use crate::storage::blob_io::ChunkLocation;
-            let location = ChunkLocation {
-                container_id,
-                offset: offset as u64,
-                length: length as u32,
-                compressed: is_compressed,
-            };
-            
-            let content = state.blob_manager.read_chunk(&location)
-                .await
-                .map_err(|e| AppError::Internal(format!("Failed to read chunk: {}", e)))?;
-            
-            return Ok((
-                [(header::CONTENT_TYPE, header::HeaderValue::from_static("application/octet-stream"))],
-                content,
-            ));
-        }
+        let content = state.blob_manager.read_chunk(&chunk)
+            .await
+            .map_err(|e| AppError::Internal(format!("Failed to read chunk: {}", e)))?;
+
+        return Ok((
+            [(header::CONTENT_TYPE, header::HeaderValue::from_static("application/octet-stream"))],
+            content,
+        ));
     }
     
     // Fallback: Try legacy blob store
-    let content = state.blob_manager.read_legacy_blob(&hash)?;
+    let content = state.blob_manager.read_legacy_blob(&hash).await?;
     
     Ok((
         [(header::CONTENT_TYPE, header::HeaderValue::from_static("application/octet-stream"))],
@@ -206,15 +200,18 @@ pub async fn download_chunk(
 pub async fn create_chunked_file(
     State(state): State<AppState>,
     headers: axum::http::HeaderMap,
+    audit: AuditContext,
     Json(req): Json<CreateChunkedFileRequest>,
 ) -> Result<Json<CreateChunkedFileResponse>, AppError> {
-    let user_id = extract_user_id(&state, &headers)?;
+    let user_id = extract_writer_id(&state, &headers).await?;
     
     // SECURITY: Validate path to prevent path traversal
-    validate_path(&req.path)?;
+    validate_path(&req.path, state.config.path_limits())?;
     
+    let chunk_tuples = stored_chunk_tuples(&req.chunks);
+
     // Get unique chunk hashes (file may have duplicate chunks for repeating content)
-    let unique_hashes: HashSet<String> = req.chunks.iter().map(|c| c.hash.clone()).collect();
+    let unique_hashes: HashSet<String> = chunk_tuples.iter().map(|(hash, _, _)| hash.clone()).collect();
     let chunk_hashes: Vec<String> = unique_hashes.into_iter().collect();
     
     // Verify all unique chunks exist
@@ -243,22 +240,45 @@ pub async fn create_chunked_file(
     }
     let created_at = parse_date(&req.created_at);
     let updated_at = parse_date(&req.updated_at);
-    
-    // Upsert file record with owner and client-provided dates
-    let file = files::upsert_file_with_owner_and_dates(&state.db, &req.path, user_id, created_at, updated_at).await?;
-    
-    // Create version with chunks
-    let chunk_tuples: Vec<(String, i32, i64)> = req.chunks.iter()
-        .map(|c| (c.hash.clone(), c.size, c.offset))
-        .collect();
-    
-    let version_id = chunks::create_chunked_version(
-        &state.db,
-        file.id,
-        &req.file_hash,
-        req.size_bytes,
-        &chunk_tuples,
-    ).await?;
+
+    // Upsert file record with owner and client-provided dates, then create
+    // the version with chunks under the path's lock, without being cut off
+    // by a disconnect or shutdown
+    let (file, version_id) = state.in_flight.run({
+        let db = state.db.clone();
+        let path = req.path.clone();
+        let file_hash = req.file_hash.clone();
+        let size_bytes = req.size_bytes;
+        let case_insensitive = state.config.case_insensitive_paths;
+        async move {
+            let mut tx = db.begin().await?;
+            files::lock_path(&mut tx, user_id, &path).await?;
+            let file = files::upsert_file_with_owner_and_dates(
+                &mut tx,
+                &path,
+                user_id,
+                created_at,
+                updated_at,
+                case_insensitive,
+            )
+            .await?;
+            let version_id = chunks::create_chunked_version(
+                &mut tx,
+                file.id,
+                &file_hash,
+                size_bytes,
+                &chunk_tuples,
+            ).await?;
+            tx.commit().await?;
+            Ok((file, version_id))
+        }
+    }).await?;
+    // Creating the version upserts its chunk rows, so record them like
+    // store_chunk does
+    for hash in &chunk_hashes {
+        state.blob_manager.chunk_filter().insert(hash);
+    }
+    audit.record(&state, Some(user_id), AuditAction::Upload, Some(&req.path)).await;
     
     Ok(Json(CreateChunkedFileResponse {
         id: file.id.to_string(),
@@ -274,7 +294,7 @@ pub async fn get_file_chunks(
     Path(id): Path<String>,
     headers: axum::http::HeaderMap,
 ) -> Result<Json<FileChunksResponse>, AppError> {
-    let user_id = extract_user_id(&state, &headers)?;
+    let user_id = extract_user_id(&state, &headers).await?;
 
     let file_id = Uuid::parse_str(&id)
         .map_err(|_| AppError::BadRequest("Invalid file ID".into()))?;
@@ -348,3 +368,88 @@ pub async fn get_file_chunks(
         chunks: chunk_infos_with_size,
     }))
 }
+
+// ============================================================================
+// HELPERS
+// ============================================================================
+
+/// Split requested hashes into those already stored and those to upload,
+/// given the ones `found` in the database. The empty chunk is never stored,
+/// and never needs uploading.
+fn split_existing(requested: &[String], mut found: Vec<String>) -> (Vec<String>, Vec<String>) {
+    if requested.iter().any(|h| h == EMPTY_CHUNK_HASH) && !found.iter().any(|h| h == EMPTY_CHUNK_HASH) {
+        found.push(EMPTY_CHUNK_HASH.to_string());
+    }
+    let found_set: HashSet<&String> = found.iter().collect();
+    let missing = requested.iter()
+        .filter(|h| !found_set.contains(h))
+        .cloned()
+        .collect();
+    (found, missing)
+}
+
+/// Check an uploaded chunk against its hash (BLAKE3). Returns false for the
+/// empty chunk: an empty file has no chunks, so there is nothing to store.
+fn verify_chunk(hash: &str, body: &[u8]) -> Result<bool, AppError> {
+    let computed_hash = blake3::hash(body).to_hex().to_string();
+    if computed_hash != hash {
+        return Err(AppError::BadRequest(format!(
+            "Chunk hash mismatch: expected {}, got {}",
+            hash, computed_hash
+        )));
+    }
+    Ok(!body.is_empty())
+}
+
+/// `(hash, size, offset)` of a file's chunks, leaving out the empty chunk
+/// clients send for an empty file
+fn stored_chunk_tuples(chunks: &[ChunkInfo]) -> Vec<(String, i32, i64)> {
+    chunks.iter()
+        .filter(|c| c.hash != EMPTY_CHUNK_HASH)
+        .map(|c| (c.hash.clone(), c.size, c.offset))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hashes(list: &[&str]) -> Vec<String> {
+        list.iter().map(|h| h.to_string()).collect()
+    }
+
+    #[test]
+    fn test_split_existing_counts_empty_chunk_as_stored() {
+        let (existing, missing) = split_existing(&hashes(&[EMPTY_CHUNK_HASH]), Vec::new());
+        assert_eq!(existing, hashes(&[EMPTY_CHUNK_HASH]));
+        assert!(missing.is_empty());
+
+        let (existing, missing) = split_existing(&hashes(&["a", "b", EMPTY_CHUNK_HASH]), hashes(&["b"]));
+        assert_eq!(existing, hashes(&["b", EMPTY_CHUNK_HASH]));
+        assert_eq!(missing, hashes(&["a"]));
+
+        // Not added when nobody asked for it
+        let (existing, missing) = split_existing(&hashes(&["a"]), Vec::new());
+        assert!(existing.is_empty());
+        assert_eq!(missing, hashes(&["a"]));
+    }
+
+    #[test]
+    fn test_verify_chunk() {
+        assert!(!verify_chunk(EMPTY_CHUNK_HASH, b"").unwrap());
+        let hash = blake3::hash(b"data").to_hex().to_string();
+        assert!(verify_chunk(&hash, b"data").unwrap());
+        assert!(matches!(verify_chunk(&hash, b""), Err(AppError::BadRequest(_))));
+        assert!(matches!(verify_chunk(EMPTY_CHUNK_HASH, b"data"), Err(AppError::BadRequest(_))));
+    }
+
+    #[test]
+    fn test_stored_chunk_tuples_drops_empty_chunk() {
+        let chunk = |hash: &str, size, offset| ChunkInfo { hash: hash.to_string(), size, offset };
+        assert!(stored_chunk_tuples(&[chunk(EMPTY_CHUNK_HASH, 0, 0)]).is_empty());
+        assert_eq!(
+            stored_chunk_tuples(&[chunk("a", 4, 0), chunk(EMPTY_CHUNK_HASH, 0, 4), chunk("a", 4, 4)]),
+            vec![("a".to_string(), 4, 0), ("a".to_string(), 4, 4)]
+        );
+    }
+}
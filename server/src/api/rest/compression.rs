@@ -0,0 +1,72 @@
+//! HTTP response compression
+//!
+//! JSON listings and change feeds are compressed (gzip or zstd, negotiated via
+//! `Accept-Encoding`). Blob, chunk and download routes are excluded: chunk
+//! payloads are already zstd-compressed by the storage tiers and file contents
+//! are binary, so compressing them again only burns CPU.
+
+use axum::extract::Request;
+use axum::http::header;
+use axum::middleware::Next;
+use axum::response::Response;
+use tower_http::compression::predicate::{DefaultPredicate, Predicate, SizeAbove};
+use tower_http::compression::CompressionLayer;
+
+/// Build the compression layer, skipping responses smaller than `min_bytes`.
+pub fn compression_layer(min_bytes: u16) -> CompressionLayer<impl Predicate> {
+    CompressionLayer::new()
+        .no_br()
+        .no_deflate()
+        .compress_when(DefaultPredicate::new().and(SizeAbove::new(min_bytes)))
+}
+
+/// Strip `Accept-Encoding` from requests to binary routes so the compression
+/// layer always answers them with the identity encoding.
+pub async fn skip_binary_routes(mut req: Request, next: Next) -> Response {
+    if is_binary_route(req.uri().path()) {
+        req.headers_mut().remove(header::ACCEPT_ENCODING);
+    }
+    next.run(req).await
+}
+
+/// Routes whose bodies are raw blobs, chunks, archives, WebDAV files, form uploads or WebSocket upgrades.
+pub(crate) fn is_binary_route(path: &str) -> bool {
+    if path.ends_with("/check") {
+        return false;
+    }
+    path.starts_with("/blobs/")
+        || path.starts_with("/chunks/")
+        || path.starts_with("/v1/chunks/")
+        || path.starts_with("/ws/")
+        || path.starts_with("/webdav/")
+        || path == "/v1/files/upload"
+        || path.ends_with("/download")
+        || path.ends_with("/download-zip")
+        || path.ends_with("/download-tar")
+        || path.contains("/download/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_binary_routes_excluded() {
+        assert!(is_binary_route("/v1/chunks/abc123"));
+        assert!(is_binary_route("/blobs/abc123"));
+        assert!(is_binary_route("/files/42/download"));
+        assert!(is_binary_route("/v1/files/download-zip"));
+        assert!(is_binary_route("/v1/files/download-tar"));
+        assert!(is_binary_route("/share/tok/download/docs/a.txt"));
+        assert!(is_binary_route("/webdav/docs/a.pdf"));
+        assert!(is_binary_route("/v1/files/upload"));
+    }
+
+    #[test]
+    fn test_json_routes_compressed() {
+        assert!(!is_binary_route("/v1/files/list"));
+        assert!(!is_binary_route("/v1/files/changes"));
+        assert!(!is_binary_route("/v1/chunks/check"));
+        assert!(!is_binary_route("/files"));
+    }
+}
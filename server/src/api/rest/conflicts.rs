@@ -3,7 +3,6 @@
 //! Handles sync conflict detection, listing, and resolution.
 
 use crate::api::AppState;
-use crate::auth;
 use axum::{
     extract::{Path, Query, State},
     routing::{get, post},
@@ -13,7 +12,7 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use super::error::AppError;
+use super::error::{extract_user_id, extract_writer_id, AppError};
 
 // ============================================================================
 // ROUTES
@@ -126,25 +125,13 @@ struct DetectedConflict {
 // HANDLERS
 // ============================================================================
 
-/// Extract user ID from authorization header
-fn extract_user_id(state: &AppState, headers: &axum::http::HeaderMap) -> Result<Uuid, AppError> {
-    let auth_header = headers
-        .get(axum::http::header::AUTHORIZATION)
-        .and_then(|h| h.to_str().ok())
-        .and_then(|h| h.strip_prefix("Bearer "))
-        .ok_or_else(|| AppError::Unauthorized("Missing authorization".into()))?;
-
-    auth::verify_token(&state.config.jwt_secret, auth_header)
-        .map_err(|_| AppError::Unauthorized("Invalid token".into()))
-}
-
 /// List user's sync conflicts
 async fn list_conflicts(
     State(state): State<AppState>,
     Query(query): Query<ListConflictsQuery>,
     headers: axum::http::HeaderMap,
 ) -> Result<Json<ListConflictsResponse>, AppError> {
-    let user_id = extract_user_id(&state, &headers)?;
+    let user_id = extract_user_id(&state, &headers).await?;
     
     let include_resolved = query.include_resolved.unwrap_or(false);
     let limit = query.limit.unwrap_or(50);
@@ -259,7 +246,7 @@ async fn get_conflict(
     Path(conflict_id): Path<Uuid>,
     headers: axum::http::HeaderMap,
 ) -> Result<Json<ConflictResponse>, AppError> {
-    let user_id = extract_user_id(&state, &headers)?;
+    let user_id = extract_user_id(&state, &headers).await?;
     
     let conflict = sqlx::query_as::<_, (Uuid, Uuid, String, Option<Uuid>, Option<Uuid>, Option<DateTime<Utc>>, Option<String>, DateTime<Utc>, String)>(
         r#"
@@ -333,7 +320,7 @@ async fn resolve_conflict(
     headers: axum::http::HeaderMap,
     Json(req): Json<ResolveConflictRequest>,
 ) -> Result<Json<ResolveConflictResponse>, AppError> {
-    let user_id = extract_user_id(&state, &headers)?;
+    let user_id = extract_writer_id(&state, &headers).await?;
     
     // Validate resolution type
     let valid_resolutions = ["keep_local", "keep_remote", "keep_both"];
@@ -392,7 +379,7 @@ async fn resolve_conflict(
                     .await?;
                 
                 // Create conflict copy path
-                let conflict_path = create_conflict_path(&path);
+                let conflict_path = crate::db::files::normalize_path(&create_conflict_path(&path));
                 
                 // Create new file with local version
                 sqlx::query(
@@ -439,7 +426,7 @@ async fn detect_conflicts(
     headers: axum::http::HeaderMap,
     Json(req): Json<DetectConflictsRequest>,
 ) -> Result<Json<DetectConflictsResponse>, AppError> {
-    let user_id = extract_user_id(&state, &headers)?;
+    let user_id = extract_user_id(&state, &headers).await?;
     
     let mut detected = Vec::new();
     
@@ -0,0 +1,199 @@
+//! CORS origin allowlist
+//!
+//! `CORS_ORIGINS` is a comma-separated list of exact origins
+//! (`https://app.example.com`) and wildcard subdomain patterns
+//! (`https://*.example.com`). A wildcard matches any subdomain, however deep,
+//! but not the bare domain; scheme and port must match exactly. Malformed
+//! entries are skipped with a warning.
+//!
+//! `CORS_ALLOW_ANY=true` reflects every origin back. It exists for local
+//! development only, since it lets any website make credentialed requests.
+
+use axum::http::{header, HeaderValue, Method};
+use std::fmt;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+const DEFAULT_ORIGINS: &str = "http://localhost:3000,http://127.0.0.1:3000";
+
+/// One allowlist entry, lowercased
+#[derive(Debug, PartialEq, Eq)]
+enum OriginRule {
+    Exact(String),
+    /// `scheme://*.host[:port]`, stored as the scheme and `.host[:port]`
+    Subdomain { scheme: String, suffix: String },
+}
+
+impl OriginRule {
+    fn parse(entry: &str) -> Result<Self, &'static str> {
+        let entry = entry.trim().to_ascii_lowercase();
+        let (scheme, authority) = entry.split_once("://").ok_or("missing scheme")?;
+        if scheme.is_empty() || !scheme.chars().all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c)) {
+            return Err("invalid scheme");
+        }
+        let host = authority.strip_prefix("*.").unwrap_or(authority);
+        let wildcard = host.len() != authority.len();
+        if !is_authority(host) || (wildcard && host.starts_with('[')) {
+            return Err("origins are scheme://host[:port], with an optional leading *. label");
+        }
+
+        if wildcard {
+            Ok(OriginRule::Subdomain {
+                scheme: scheme.to_string(),
+                suffix: format!(".{}", host),
+            })
+        } else {
+            HeaderValue::from_str(&entry).map_err(|_| "invalid header value")?;
+            Ok(OriginRule::Exact(entry))
+        }
+    }
+
+    fn matches(&self, origin: &str) -> bool {
+        match self {
+            OriginRule::Exact(allowed) => origin.eq_ignore_ascii_case(allowed),
+            OriginRule::Subdomain { scheme, suffix } => {
+                let origin = origin.to_ascii_lowercase();
+                let Some(authority) = origin
+                    .strip_prefix(scheme.as_str())
+                    .and_then(|rest| rest.strip_prefix("://"))
+                else {
+                    return false;
+                };
+                match authority.strip_suffix(suffix.as_str()) {
+                    Some(subdomain) => is_host_labels(subdomain),
+                    None => false,
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for OriginRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OriginRule::Exact(origin) => f.write_str(origin),
+            OriginRule::Subdomain { scheme, suffix } => write!(f, "{}://*{}", scheme, suffix),
+        }
+    }
+}
+
+/// `host[:port]` with no path, query, credentials or wildcards. IPv6 hosts
+/// are bracketed, as in `http://[::1]:3000`.
+fn is_authority(s: &str) -> bool {
+    let (host, port) = match s.rsplit_once(':') {
+        Some((host, port)) if !s.ends_with(']') => (host, Some(port)),
+        _ => (s, None),
+    };
+    let port_ok = port.is_none_or(|p| p.parse::<u16>().is_ok());
+    let host_ok = match host.strip_prefix('[').and_then(|h| h.strip_suffix(']')) {
+        Some(ipv6) => ipv6.parse::<std::net::Ipv6Addr>().is_ok(),
+        None => is_host_labels(host),
+    };
+    port_ok && host_ok
+}
+
+/// Dot-separated, non-empty labels of letters, digits and hyphens
+fn is_host_labels(s: &str) -> bool {
+    !s.is_empty()
+        && s.split('.')
+            .all(|label| !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'))
+}
+
+/// Parse a comma-separated allowlist, warning about entries that are skipped
+fn parse_allowlist(spec: &str) -> Vec<OriginRule> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .filter_map(|entry| match OriginRule::parse(entry) {
+            Ok(rule) => Some(rule),
+            Err(reason) => {
+                tracing::warn!("Ignoring CORS origin {:?}: {}", entry, reason);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Build the CORS layer from `CORS_ORIGINS` and `CORS_ALLOW_ANY`
+pub fn cors_layer() -> CorsLayer {
+    let allow_any = std::env::var("CORS_ALLOW_ANY")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false);
+
+    let allow_origin = if allow_any {
+        tracing::warn!(
+            "CORS_ALLOW_ANY is set: every origin may make credentialed requests. \
+             This is for local development only - never enable it in production."
+        );
+        AllowOrigin::mirror_request()
+    } else {
+        let spec = std::env::var("CORS_ORIGINS").unwrap_or_else(|_| DEFAULT_ORIGINS.to_string());
+        let rules = parse_allowlist(&spec);
+        let listed: Vec<String> = rules.iter().map(ToString::to_string).collect();
+        tracing::info!("CORS allowed origins: {}", listed.join(", "));
+        AllowOrigin::predicate(move |origin: &HeaderValue, _| {
+            origin
+                .to_str()
+                .map(|origin| rules.iter().any(|rule| rule.matches(origin)))
+                .unwrap_or(false)
+        })
+    };
+
+    CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods([
+            Method::GET,
+            Method::POST,
+            Method::PUT,
+            Method::DELETE,
+            Method::OPTIONS,
+        ])
+        .allow_headers([
+            header::AUTHORIZATION,
+            header::CONTENT_TYPE,
+            header::ACCEPT,
+            header::IF_MATCH,
+        ])
+        .allow_credentials(true)
+        // Expose X-Request-Id header to clients
+        .expose_headers(vec![header::HeaderName::from_static("x-request-id")])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowed(spec: &str, origin: &str) -> bool {
+        parse_allowlist(spec).iter().any(|rule| rule.matches(origin))
+    }
+
+    #[test]
+    fn test_exact_origins() {
+        let spec = "http://localhost:3000, https://App.example.com, http://[::1]:3000";
+        assert!(allowed(spec, "http://localhost:3000"));
+        assert!(allowed(spec, "http://[::1]:3000"));
+        assert!(allowed(spec, "https://app.example.com"));
+        assert!(!allowed(spec, "http://localhost:3001"));
+        assert!(!allowed(spec, "http://app.example.com"));
+    }
+
+    #[test]
+    fn test_wildcard_subdomains() {
+        let spec = "https://*.example.com,https://*.preview.dev:8443";
+        assert!(allowed(spec, "https://pr-12.example.com"));
+        assert!(allowed(spec, "https://a.b.example.com"));
+        assert!(allowed(spec, "https://pr-1.preview.dev:8443"));
+        assert!(!allowed(spec, "https://example.com"));
+        assert!(!allowed(spec, "https://evilexample.com"));
+        assert!(!allowed(spec, "https://x.example.com.evil.net"));
+        assert!(!allowed(spec, "http://pr-12.example.com"));
+        assert!(!allowed(spec, "https://pr-1.preview.dev"));
+    }
+
+    #[test]
+    fn test_invalid_entries_skipped() {
+        for entry in ["example.com", "https://*.", "https://a.*.com", "https://*", "https://x.com/path", "https://x.com:port", "http://*.[::1]"] {
+            assert!(OriginRule::parse(entry).is_err(), "{} should be rejected", entry);
+        }
+        assert_eq!(parse_allowlist("nonsense, https://ok.com,,").len(), 1);
+    }
+}
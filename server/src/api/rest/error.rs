@@ -4,6 +4,8 @@
 
 use crate::api::AppState;
 use crate::auth;
+use crate::config::PathLimits;
+use crate::db::users;
 use axum::{
     http::{header, StatusCode},
     response::IntoResponse,
@@ -19,7 +21,16 @@ use uuid::Uuid;
 pub enum AppError {
     BadRequest(String),
     Unauthorized(String),
+    /// Authenticated but not permitted (e.g. a viewer attempting a write)
+    Forbidden(String),
     NotFound(String),
+    Conflict(String),
+    /// A conditional request header (`If-Unmodified-Since`) did not hold
+    PreconditionFailed(String),
+    /// A streamed upload exceeded the configured size limit
+    PayloadTooLarge(String),
+    /// Accepting the upload would eat into the reserved disk space
+    InsufficientStorage(String),
     Internal(String),
 }
 
@@ -28,7 +39,15 @@ impl IntoResponse for AppError {
         let (status, message) = match self {
             AppError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
             AppError::Unauthorized(msg) => (StatusCode::UNAUTHORIZED, msg),
+            AppError::Forbidden(msg) => (StatusCode::FORBIDDEN, msg),
             AppError::NotFound(msg) => (StatusCode::NOT_FOUND, msg),
+            AppError::Conflict(msg) => (StatusCode::CONFLICT, msg),
+            AppError::PreconditionFailed(msg) => (StatusCode::PRECONDITION_FAILED, msg),
+            AppError::PayloadTooLarge(msg) => (StatusCode::PAYLOAD_TOO_LARGE, msg),
+            AppError::InsufficientStorage(msg) => {
+                tracing::warn!("{}", msg);
+                (StatusCode::INSUFFICIENT_STORAGE, "Insufficient storage on the server".to_string())
+            }
             AppError::Internal(msg) => {
                 // SECURITY: Log full details server-side, return generic message to client
                 tracing::error!(details = %msg, "Internal server error");
@@ -43,8 +62,21 @@ impl IntoResponse for AppError {
 
 impl From<anyhow::Error> for AppError {
     fn from(err: anyhow::Error) -> Self {
+        // Case-insensitive path collisions are a client error, not an internal one
+        if let Some(collision) = err.downcast_ref::<crate::db::files::PathCollision>() {
+            return AppError::Conflict(collision.to_string());
+        }
+        if let Some(refused) = err.downcast_ref::<crate::storage::blob_io::UnencryptedBlobRefused>() {
+            return AppError::BadRequest(refused.to_string());
+        }
+        if let Some(busy) = err.downcast_ref::<crate::storage::blob_io::RepackInProgress>() {
+            return AppError::Conflict(busy.to_string());
+        }
+        if let Some(full) = err.downcast_ref::<crate::storage::disk_space::InsufficientSpace>() {
+            return AppError::InsufficientStorage(full.to_string());
+        }
         // SECURITY: Log the full error server-side but return generic message to client
-        tracing::error!("Internal error: {}", err);
+        tracing::error!("Internal error: {:#}", err);
         AppError::Internal("An internal error occurred".to_string())
     }
 }
@@ -63,7 +95,25 @@ impl From<sqlx::Error> for AppError {
 // ============================================================================
 
 /// Extract user ID from Authorization header
-pub fn extract_user_id(state: &AppState, headers: &axum::http::HeaderMap) -> Result<Uuid, AppError> {
+pub async fn extract_user_id(state: &AppState, headers: &axum::http::HeaderMap) -> Result<Uuid, AppError> {
+    Ok(authenticate_request(state, headers).await?.0)
+}
+
+/// Extract the user ID for a request that changes files, rejecting viewers
+/// with `403`. The role is read from the database so a demotion takes effect
+/// immediately rather than when the token expires.
+pub async fn extract_writer_id(state: &AppState, headers: &axum::http::HeaderMap) -> Result<Uuid, AppError> {
+    let (user_id, access) = authenticate_request(state, headers).await?;
+    if !access.role.can_write() {
+        return Err(AppError::Forbidden("Viewers have read-only access".into()));
+    }
+    Ok(user_id)
+}
+
+async fn authenticate_request(
+    state: &AppState,
+    headers: &axum::http::HeaderMap,
+) -> Result<(Uuid, users::UserAccess), AppError> {
     let auth_header = headers
         .get(header::AUTHORIZATION)
         .and_then(|v| v.to_str().ok())
@@ -73,8 +123,185 @@ pub fn extract_user_id(state: &AppState, headers: &axum::http::HeaderMap) -> Res
         .strip_prefix("Bearer ")
         .ok_or_else(|| AppError::Unauthorized("Invalid authorization format".into()))?;
 
-    let user_id = auth::verify_token(&state.config.jwt_secret, token)?;
-    Ok(user_id)
+    authenticate_token(state, token).await
+}
+
+/// Verify an access token and check it against the user's current state:
+/// disabled accounts get `403`, and tokens issued before the account's token
+/// version was bumped (e.g. by disabling it) get `401`.
+pub async fn authenticate_token(state: &AppState, token: &str) -> Result<(Uuid, users::UserAccess), AppError> {
+    let subject = auth::verify_token_subject(&state.config.jwt_secret, token)
+        .map_err(|_| AppError::Unauthorized("Invalid or expired token".into()))?;
+    let access = users::get_access(&state.db, subject.user_id)
+        .await?
+        .ok_or_else(|| AppError::Unauthorized("User not found".into()))?;
+    if access.disabled {
+        return Err(AppError::Forbidden("Account is disabled".into()));
+    }
+    if subject.token_version != access.token_version {
+        return Err(AppError::Unauthorized("Token has been revoked".into()));
+    }
+    Ok((subject.user_id, access))
+}
+
+/// ETag for a version, as accepted back by [`if_match_version`]
+pub fn version_etag(version_id: Uuid) -> String {
+    format!("\"{}\"", version_id)
+}
+
+/// Parse an optional `If-Match: <version_id>` precondition header.
+///
+/// Accepts a bare UUID or a quoted ETag (`"<uuid>"`, `W/"<uuid>"`).
+/// Returns `None` when the header is absent or is the `*` wildcard.
+pub fn if_match_version(headers: &axum::http::HeaderMap) -> Result<Option<Uuid>, AppError> {
+    let Some(value) = headers.get(header::IF_MATCH) else {
+        return Ok(None);
+    };
+    let value = value
+        .to_str()
+        .map_err(|_| AppError::BadRequest("Invalid If-Match header".into()))?
+        .trim();
+    if value == "*" {
+        return Ok(None);
+    }
+    let tag = value.strip_prefix("W/").unwrap_or(value).trim_matches('"');
+    Uuid::parse_str(tag)
+        .map(Some)
+        .map_err(|_| AppError::BadRequest("If-Match must be a version ID".into()))
+}
+
+/// Refuse with `409` if `If-Match` names a version other than the current one.
+///
+/// Directories have no versions, so `If-Match` with a version on one is a
+/// `400` rather than a mismatch that could never succeed.
+///
+/// For operations without a conditional SQL update; deletes also check the
+/// version atomically.
+pub fn check_if_match(headers: &axum::http::HeaderMap, current_version_id: Option<Uuid>) -> Result<(), AppError> {
+    match if_match_version(headers)? {
+        Some(_) if current_version_id.is_none() => Err(AppError::BadRequest(
+            "If-Match names a file version, and directories have none (use If-Unmodified-Since)".into(),
+        )),
+        Some(expected) if current_version_id != Some(expected) => Err(AppError::Conflict(
+            "File was modified since it was listed (If-Match version mismatch)".into(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Parse an optional `If-Unmodified-Since` header, as RFC 3339 or an HTTP-date
+pub fn if_unmodified_since(
+    headers: &axum::http::HeaderMap,
+) -> Result<Option<chrono::DateTime<chrono::Utc>>, AppError> {
+    let Some(value) = headers.get(header::IF_UNMODIFIED_SINCE) else {
+        return Ok(None);
+    };
+    let invalid = || AppError::BadRequest("If-Unmodified-Since must be an RFC 3339 or HTTP date".into());
+    let value = value.to_str().map_err(|_| invalid())?.trim();
+    chrono::DateTime::parse_from_rfc3339(value)
+        .or_else(|_| chrono::DateTime::parse_from_rfc2822(value))
+        .map(|dt| Some(dt.with_timezone(&chrono::Utc)))
+        .map_err(|_| invalid())
+}
+
+/// Refuse with `412` if the file changed after the `If-Unmodified-Since` time.
+///
+/// A whole-second timestamp (as every HTTP-date is) is compared against
+/// `updated_at` truncated to the second, so echoing back a `Last-Modified`
+/// value does not fail on sub-second precision.
+pub fn check_unmodified_since(
+    headers: &axum::http::HeaderMap,
+    updated_at: chrono::DateTime<chrono::Utc>,
+) -> Result<(), AppError> {
+    use chrono::{SubsecRound, Timelike};
+
+    let Some(since) = if_unmodified_since(headers)? else {
+        return Ok(());
+    };
+    let updated_at = if since.nanosecond() == 0 { updated_at.trunc_subsecs(0) } else { updated_at };
+    if updated_at > since {
+        return Err(AppError::PreconditionFailed(format!(
+            "File was modified at {} (If-Unmodified-Since)",
+            updated_at.to_rfc3339()
+        )));
+    }
+    Ok(())
+}
+
+/// Format a time as an HTTP-date (`Sun, 06 Nov 1994 08:49:37 GMT`), as used
+/// by `Last-Modified`
+pub fn http_date(dt: chrono::DateTime<chrono::Utc>) -> String {
+    dt.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// Whether a `GET` of content tagged `etag` and last modified at
+/// `modified_at` can be answered with `304 Not Modified`.
+///
+/// `If-None-Match` takes precedence: with it, only a matching tag (compared
+/// weakly) or `*` counts. Otherwise `If-Modified-Since` is compared to the
+/// second; an unparseable date is ignored rather than rejected, as RFC 9110
+/// requires.
+pub fn is_not_modified(
+    headers: &axum::http::HeaderMap,
+    etag: &str,
+    modified_at: chrono::DateTime<chrono::Utc>,
+) -> bool {
+    use chrono::SubsecRound;
+
+    if let Some(value) = headers.get(header::IF_NONE_MATCH) {
+        let Ok(value) = value.to_str() else {
+            return false;
+        };
+        let etag = etag.strip_prefix("W/").unwrap_or(etag);
+        return value.split(',').map(str::trim).any(|tag| {
+            tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag
+        });
+    }
+
+    let Some(since) = headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| chrono::DateTime::parse_from_rfc2822(v.trim()).ok())
+    else {
+        return false;
+    };
+    modified_at.trunc_subsecs(0) <= since
+}
+
+// ============================================================================
+// SHORT IDS
+// ============================================================================
+
+/// Shortest id/hash prefix accepted in place of a full version id or chunk hash
+pub const MIN_SHORT_ID_LEN: usize = 8;
+
+/// Validate a user-supplied id or hash prefix, returning it lowercased.
+///
+/// Prefixes must be at least [`MIN_SHORT_ID_LEN`] characters of hex
+/// (hyphens allowed, as in UUIDs).
+pub fn short_id_prefix(raw: &str) -> Result<String, AppError> {
+    if raw.len() < MIN_SHORT_ID_LEN {
+        return Err(AppError::BadRequest(format!(
+            "ID prefix must be at least {} characters",
+            MIN_SHORT_ID_LEN
+        )));
+    }
+    if !raw.chars().all(|c| c.is_ascii_hexdigit() || c == '-') {
+        return Err(AppError::BadRequest("ID prefix must be hexadecimal".into()));
+    }
+    Ok(raw.to_ascii_lowercase())
+}
+
+/// Pick the single match for a prefix lookup: 404 if none, 409 if ambiguous.
+pub fn unique_match<T>(mut matches: Vec<T>, what: &str, prefix: &str) -> Result<T, AppError> {
+    match matches.len() {
+        0 => Err(AppError::NotFound(format!("{} not found", what))),
+        1 => Ok(matches.remove(0)),
+        _ => Err(AppError::Conflict(format!(
+            "{} prefix {} is ambiguous; use more characters",
+            what, prefix
+        ))),
+    }
 }
 
 // ============================================================================
@@ -82,8 +309,9 @@ pub fn extract_user_id(state: &AppState, headers: &axum::http::HeaderMap) -> Res
 // ============================================================================
 
 /// Validate and normalize a file path to prevent path traversal and injection attacks.
-/// Returns the normalized path on success, or an error if the path is invalid.
-pub fn validate_path(path: &str) -> Result<String, AppError> {
+/// Returns the normalized path on success, or an error if the path is invalid
+/// or exceeds `limits`.
+pub fn validate_path(path: &str, limits: PathLimits) -> Result<String, AppError> {
     // 1. Reject empty paths
     if path.is_empty() {
         return Err(AppError::BadRequest("Path cannot be empty".into()));
@@ -146,16 +374,97 @@ pub fn validate_path(path: &str) -> Result<String, AppError> {
         return Err(AppError::BadRequest("Path contains invalid control characters".into()));
     }
 
-    // 8. Whitelist valid characters: alphanumeric, /, ., -, _, space
-    if !normalized.chars().all(|c| {
-        c.is_alphanumeric() || matches!(c, '/' | '.' | '-' | '_' | ' ')
+    // 8. Whitelist valid characters: alphanumeric, /, ., -, _, space. This
+    // already excludes the characters Windows forbids (: * ? " < > |).
+    if let Some(c) = normalized.chars().find(|&c| {
+        !(c.is_alphanumeric() || matches!(c, '/' | '.' | '-' | '_' | ' '))
     }) {
-        return Err(AppError::BadRequest("Path contains invalid characters".into()));
+        return Err(AppError::BadRequest(format!("Path contains invalid character {:?}", c)));
+    }
+
+    // 9. Strict mode: refuse names Windows can't represent
+    if limits.strict {
+        for segment in normalized.split('/').filter(|segment| !segment.is_empty()) {
+            check_windows_name(segment)?;
+        }
+    }
+
+    // 10. Keep within the configured size, so paths stay usable on client filesystems
+    if normalized.len() > limits.max_length {
+        return Err(AppError::BadRequest(format!(
+            "Path is too long ({} bytes, at most {})",
+            normalized.len(),
+            limits.max_length
+        )));
+    }
+    let depth = normalized.split('/').filter(|segment| !segment.is_empty()).count();
+    if depth > limits.max_depth {
+        return Err(AppError::BadRequest(format!(
+            "Path is too deep ({} components, at most {})",
+            depth, limits.max_depth
+        )));
     }
 
     Ok(normalized)
 }
 
+/// Device names Windows reserves, with or without an extension
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Refuse a path component Windows can't create as named
+fn check_windows_name(segment: &str) -> Result<(), AppError> {
+    if segment.ends_with('.') || segment.ends_with(' ') {
+        return Err(AppError::BadRequest(format!(
+            "Path component {:?} ends with a dot or space, which Windows strips",
+            segment
+        )));
+    }
+    let stem = segment.split('.').next().unwrap_or_default().trim_end();
+    if WINDOWS_RESERVED_NAMES.iter().any(|name| stem.eq_ignore_ascii_case(name)) {
+        return Err(AppError::BadRequest(format!(
+            "Path component {:?} is a reserved device name on Windows",
+            segment
+        )));
+    }
+    Ok(())
+}
+
+/// Validate client-sent Unix permission bits (`0` to `0o7777`: rwx for
+/// user/group/other plus setuid, setgid and sticky). File type bits are refused.
+pub fn validate_unix_mode(unix_mode: Option<u32>) -> Result<Option<i32>, AppError> {
+    match unix_mode {
+        Some(mode) if mode > 0o7777 => Err(AppError::BadRequest(format!(
+            "unix_mode must be permission bits between 0 and 0o7777 (4095), got {}",
+            mode
+        ))),
+        mode => Ok(mode.map(|m| m as i32)),
+    }
+}
+
+/// Longest version note accepted, in characters
+pub const MAX_VERSION_NOTE_CHARS: usize = 1000;
+
+/// Validate the optional note sent with a new version. Surrounding
+/// whitespace is trimmed, and a blank note is no note.
+pub fn validate_version_note(note: Option<&str>) -> Result<Option<String>, AppError> {
+    let Some(note) = note.map(str::trim).filter(|n| !n.is_empty()) else {
+        return Ok(None);
+    };
+    if note.chars().count() > MAX_VERSION_NOTE_CHARS {
+        return Err(AppError::BadRequest(format!(
+            "note must be at most {} characters",
+            MAX_VERSION_NOTE_CHARS
+        )));
+    }
+    if note.chars().any(|c| c.is_control() && c != '\n' && c != '\t') {
+        return Err(AppError::BadRequest("note must not contain control characters".into()));
+    }
+    Ok(Some(note.to_string()))
+}
+
 /// Simple percent-decoding for path validation.
 /// Decodes %XX sequences to their byte values.
 fn percent_decode(input: &str) -> String {
@@ -192,46 +501,210 @@ fn hex_val(b: u8) -> Option<u8> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_if_match_version() {
+        let id = Uuid::new_v4();
+        let mut headers = axum::http::HeaderMap::new();
+        assert_eq!(if_match_version(&headers).unwrap(), None);
+
+        headers.insert(header::IF_MATCH, format!("\"{}\"", id).parse().unwrap());
+        assert_eq!(if_match_version(&headers).unwrap(), Some(id));
+
+        headers.insert(header::IF_MATCH, "*".parse().unwrap());
+        assert_eq!(if_match_version(&headers).unwrap(), None);
+
+        headers.insert(header::IF_MATCH, "not-a-uuid".parse().unwrap());
+        assert!(if_match_version(&headers).is_err());
+    }
+
+    #[test]
+    fn test_check_if_match() {
+        let current = Uuid::new_v4();
+        let mut headers = axum::http::HeaderMap::new();
+        assert!(check_if_match(&headers, None).is_ok());
+
+        headers.insert(header::IF_MATCH, version_etag(current).parse().unwrap());
+        assert!(check_if_match(&headers, Some(current)).is_ok());
+        assert!(matches!(check_if_match(&headers, Some(Uuid::new_v4())), Err(AppError::Conflict(_))));
+        // A directory has no version to match
+        assert!(matches!(check_if_match(&headers, None), Err(AppError::BadRequest(_))));
+
+        headers.insert(header::IF_MATCH, "*".parse().unwrap());
+        assert!(check_if_match(&headers, None).is_ok());
+    }
+
+    #[test]
+    fn test_check_unmodified_since() {
+        let updated_at = chrono::DateTime::parse_from_rfc3339("2026-01-02T03:04:05.250Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        let mut headers = axum::http::HeaderMap::new();
+        assert!(check_unmodified_since(&headers, updated_at).is_ok());
+
+        // HTTP-date of the same second passes despite the sub-second remainder
+        headers.insert(header::IF_UNMODIFIED_SINCE, "Fri, 02 Jan 2026 03:04:05 GMT".parse().unwrap());
+        assert!(check_unmodified_since(&headers, updated_at).is_ok());
+
+        headers.insert(header::IF_UNMODIFIED_SINCE, "2026-01-02T03:04:05.100Z".parse().unwrap());
+        assert!(matches!(
+            check_unmodified_since(&headers, updated_at),
+            Err(AppError::PreconditionFailed(_))
+        ));
+
+        headers.insert(header::IF_UNMODIFIED_SINCE, "2026-01-02T03:04:04Z".parse().unwrap());
+        assert!(check_unmodified_since(&headers, updated_at).is_err());
+
+        headers.insert(header::IF_UNMODIFIED_SINCE, "yesterday".parse().unwrap());
+        assert!(matches!(check_unmodified_since(&headers, updated_at), Err(AppError::BadRequest(_))));
+    }
+
+    #[test]
+    fn test_is_not_modified() {
+        let etag = version_etag(Uuid::new_v4());
+        let modified_at = chrono::DateTime::parse_from_rfc3339("2026-01-02T03:04:05.250Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        assert_eq!(http_date(modified_at), "Fri, 02 Jan 2026 03:04:05 GMT");
+
+        let mut headers = axum::http::HeaderMap::new();
+        assert!(!is_not_modified(&headers, &etag, modified_at));
+
+        // Echoing Last-Modified back matches despite the sub-second remainder
+        headers.insert(header::IF_MODIFIED_SINCE, http_date(modified_at).parse().unwrap());
+        assert!(is_not_modified(&headers, &etag, modified_at));
+        headers.insert(header::IF_MODIFIED_SINCE, "Fri, 02 Jan 2026 03:04:04 GMT".parse().unwrap());
+        assert!(!is_not_modified(&headers, &etag, modified_at));
+        headers.insert(header::IF_MODIFIED_SINCE, "yesterday".parse().unwrap());
+        assert!(!is_not_modified(&headers, &etag, modified_at));
+
+        // If-None-Match wins over the date
+        headers.insert(header::IF_MODIFIED_SINCE, http_date(modified_at).parse().unwrap());
+        headers.insert(header::IF_NONE_MATCH, "\"other\"".parse().unwrap());
+        assert!(!is_not_modified(&headers, &etag, modified_at));
+        headers.insert(header::IF_NONE_MATCH, format!("\"other\", W/{}", etag).parse().unwrap());
+        assert!(is_not_modified(&headers, &etag, modified_at));
+        headers.insert(header::IF_NONE_MATCH, "*".parse().unwrap());
+        assert!(is_not_modified(&headers, &etag, modified_at));
+    }
+
+    #[test]
+    fn test_short_id_prefix() {
+        assert_eq!(short_id_prefix("ABCDEF12").unwrap(), "abcdef12");
+        assert!(short_id_prefix("abcdef12-34").is_ok());
+        assert!(short_id_prefix("abcdef1").is_err());
+        assert!(short_id_prefix("abcdefgh").is_err());
+        assert!(short_id_prefix("abcd%ef12").is_err());
+    }
+
+    #[test]
+    fn test_unique_match() {
+        assert_eq!(unique_match(vec![1], "Version", "abcdef12").unwrap(), 1);
+        assert!(matches!(unique_match(Vec::<i32>::new(), "Version", "abcdef12"), Err(AppError::NotFound(_))));
+        assert!(matches!(unique_match(vec![1, 2], "Version", "abcdef12"), Err(AppError::Conflict(_))));
+    }
+
     #[test]
     fn test_valid_paths() {
-        assert!(validate_path("/foo").is_ok());
-        assert!(validate_path("/foo/bar.txt").is_ok());
-        assert!(validate_path("/foo bar/baz.txt").is_ok());
+        assert!(validate_path("/foo", PathLimits::default()).is_ok());
+        assert!(validate_path("/foo/bar.txt", PathLimits::default()).is_ok());
+        assert!(validate_path("/foo bar/baz.txt", PathLimits::default()).is_ok());
     }
 
     #[test]
     fn test_rejects_empty() {
-        assert!(validate_path("").is_err());
+        assert!(validate_path("", PathLimits::default()).is_err());
     }
 
     #[test]
     fn test_rejects_traversal() {
-        assert!(validate_path("/../etc/passwd").is_err());
-        assert!(validate_path("/foo/../bar").is_err());
-        assert!(validate_path("/foo/%2e%2e/bar").is_err());
+        assert!(validate_path("/../etc/passwd", PathLimits::default()).is_err());
+        assert!(validate_path("/foo/../bar", PathLimits::default()).is_err());
+        assert!(validate_path("/foo/%2e%2e/bar", PathLimits::default()).is_err());
     }
 
     #[test]
     fn test_rejects_null_bytes() {
-        assert!(validate_path("/foo\0bar").is_err());
+        assert!(validate_path("/foo\0bar", PathLimits::default()).is_err());
     }
 
     #[test]
     fn test_normalizes_slashes() {
-        let result = validate_path("//foo///bar").unwrap();
+        let result = validate_path("//foo///bar", PathLimits::default()).unwrap();
         assert_eq!(result, "/foo/bar");
     }
 
     #[test]
     fn test_rejects_invalid_chars() {
-        assert!(validate_path("/foo<bar").is_err());
-        assert!(validate_path("/foo>bar").is_err());
-        assert!(validate_path("/foo|bar").is_err());
+        assert!(validate_path("/foo<bar", PathLimits::default()).is_err());
+        assert!(validate_path("/foo>bar", PathLimits::default()).is_err());
+        assert!(validate_path("/foo|bar", PathLimits::default()).is_err());
+    }
+
+    #[test]
+    fn test_invalid_char_is_named() {
+        for c in [':', '*', '?', '"', '<', '>', '|'] {
+            match validate_path(&format!("/a{}b.txt", c), PathLimits::default()) {
+                Err(AppError::BadRequest(message)) => assert!(message.contains(&format!("{:?}", c)), "{}", message),
+                other => panic!("{:?}: {:?}", c, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_strict_paths() {
+        let strict = PathLimits { strict: true, ..PathLimits::default() };
+        for path in ["/docs/con", "/docs/CON.txt", "/aux/a.txt", "/lpt1.tar.gz", "/a/b.", "/a./b", "/a/b "] {
+            assert!(validate_path(path, PathLimits::default()).is_ok(), "{}", path);
+            assert!(validate_path(path, strict).is_err(), "{}", path);
+        }
+        for path in ["/docs/console.txt", "/com10", "/a/.hidden", "/a/b.txt"] {
+            assert!(validate_path(path, strict).is_ok(), "{}", path);
+        }
     }
 
     #[test]
     fn test_rejects_backslash() {
-        assert!(validate_path("/foo\\bar").is_err());
+        assert!(validate_path("/foo\\bar", PathLimits::default()).is_err());
+    }
+
+    #[test]
+    fn test_path_limits() {
+        let limits = PathLimits { max_length: 16, max_depth: 3, strict: false };
+        assert!(validate_path("/a/b/c.txt", limits).is_ok());
+        assert!(validate_path("/a/b/c/d.txt", limits).is_err());
+        assert!(validate_path("/abcdefghijklmnop", limits).is_err());
+        // Measured after normalization
+        assert!(validate_path("//a///b//c.txt/", limits).is_ok());
+
+        let deep = "/d".repeat(32);
+        assert!(validate_path(&deep, PathLimits::default()).is_ok());
+        assert!(validate_path(&format!("{}/f", deep), PathLimits::default()).is_err());
+        let long = format!("/{}", "x".repeat(1023));
+        assert!(validate_path(&long, PathLimits::default()).is_ok());
+        assert!(validate_path(&format!("{}x", long), PathLimits::default()).is_err());
+    }
+
+    #[test]
+    fn test_validate_unix_mode() {
+        assert_eq!(validate_unix_mode(None).unwrap(), None);
+        assert_eq!(validate_unix_mode(Some(0o755)).unwrap(), Some(0o755));
+        assert_eq!(validate_unix_mode(Some(0o4755)).unwrap(), Some(0o4755));
+        // st_mode of a regular file, with the file type bits
+        assert!(validate_unix_mode(Some(0o100644)).is_err());
+    }
+
+    #[test]
+    fn test_validate_version_note() {
+        assert_eq!(validate_version_note(None).unwrap(), None);
+        assert_eq!(validate_version_note(Some("  \n ")).unwrap(), None);
+        assert_eq!(
+            validate_version_note(Some(" fix totals\nper review ")).unwrap().as_deref(),
+            Some("fix totals\nper review")
+        );
+        let longest = "é".repeat(MAX_VERSION_NOTE_CHARS);
+        assert!(validate_version_note(Some(&longest)).is_ok());
+        assert!(validate_version_note(Some(&format!("{}x", longest))).is_err());
+        assert!(validate_version_note(Some("bell\u{7}")).is_err());
     }
 }
 
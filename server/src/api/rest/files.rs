@@ -2,7 +2,9 @@
 //!
 //! CRUD operations for files including list, get, update, delete, and download.
 
+use crate::api::ws::SyncNotification;
 use crate::api::AppState;
+use crate::db::audit::AuditAction;
 use crate::db::{chunks, files, versions};
 use axum::{
     extract::{Path, Query, State},
@@ -15,11 +17,12 @@ use blake3;
 use serde::Deserialize;
 use uuid::Uuid;
 
-use super::blobs::{upload_blob, download_blob};
+use super::blobs::{upload_blob, download_blob, head_blob};
 use super::chunks::{check_chunks, upload_chunk, download_chunk, create_chunked_file, get_file_chunks};
-use super::error::{extract_user_id, validate_path, AppError};
+use super::audit::AuditContext;
+use super::error::{check_if_match, check_unmodified_since, extract_user_id, extract_writer_id, if_match_version, if_unmodified_since, validate_path, version_etag, AppError};
 use super::types::{FileResponse, ListFilesQuery, ListFilesResponse, UploadResponse};
-use super::versions::{list_file_versions, restore_version};
+use super::versions::{list_file_versions, restore_tree, restore_version};
 
 // ============================================================================
 // ROUTES
@@ -28,24 +31,40 @@ use super::versions::{list_file_versions, restore_version};
 pub fn file_routes() -> Router<AppState> {
     Router::new()
         .route("/files", get(list_files))
-        .route("/files", axum::routing::post(upload_file))
-        .route("/files/:id", get(get_file))
+        .route("/files/:id", get(get_file).head(head_file))
         .route("/files/:id", axum::routing::patch(update_file))
         .route("/files/:id", axum::routing::delete(delete_file))
         .route("/files/:id/download", get(download_file))
         .route("/files/:id/versions", get(list_file_versions))
         .route("/files/:id/restore/:version_id", axum::routing::post(restore_version))
-        // Raw binary blob upload - most efficient
-        .route("/blobs/:hash", axum::routing::put(upload_blob))
-        .route("/blobs/:hash", get(download_blob))
+        .route("/files/:id/restore-tree", axum::routing::post(restore_tree))
+        .route("/blobs/:hash", get(download_blob).head(head_blob))
         // Chunk-based upload/download (CDC for delta sync)
         .route("/chunks/check", axum::routing::post(check_chunks))
-        .route("/chunks/:hash", axum::routing::put(upload_chunk))
         .route("/chunks/:hash", get(download_chunk))
         .route("/files/chunked", axum::routing::post(create_chunked_file))
         .route("/files/:id/chunks", get(get_file_chunks))
 }
 
+/// Largest body a base64 `POST /files` upload may have, whatever
+/// `MAX_JSON_BODY_SIZE` is: the body, its decoded content and the stored
+/// blob are all held in memory. Larger files go through the chunk protocol.
+pub const MAX_BASE64_UPLOAD_BYTES: usize = 8 * 1024 * 1024;
+
+/// Base64 upload route, served with the lower of `MAX_JSON_BODY_SIZE` and
+/// [`MAX_BASE64_UPLOAD_BYTES`]
+pub fn base64_upload_routes() -> Router<AppState> {
+    Router::new().route("/files", axum::routing::post(upload_file))
+}
+
+/// Raw binary upload routes, served with the larger upload body limit
+pub fn file_upload_routes() -> Router<AppState> {
+    Router::new()
+        // Raw binary blob upload - most efficient
+        .route("/blobs/:hash", axum::routing::put(upload_blob))
+        .route("/chunks/:hash", axum::routing::put(upload_chunk))
+}
+
 // ============================================================================
 // TYPES
 // ============================================================================
@@ -69,46 +88,53 @@ struct UploadRequest {
 async fn upload_file(
     State(state): State<AppState>,
     headers: axum::http::HeaderMap,
+    audit: AuditContext,
     Json(req): Json<UploadRequest>,
 ) -> Result<Json<UploadResponse>, AppError> {
-    let _user_id = extract_user_id(&state, &headers)?;
+    let user_id = extract_writer_id(&state, &headers).await?;
     
     // SECURITY: Validate path to prevent path traversal
-    validate_path(&req.path)?;
+    validate_path(&req.path, state.config.path_limits())?;
     
-    // Decode base64 content
+    // Decode base64 content, freeing the encoded copy
     use base64::{Engine, engine::general_purpose::STANDARD};
-    let content = STANDARD.decode(&req.content)
+    let UploadRequest { path, content } = req;
+    let content = STANDARD.decode(content)
         .map_err(|e| AppError::BadRequest(format!("Invalid base64: {}", e)))?;
     
     // Compute hash using BLAKE3
     let blob_hash = blake3::hash(&content).to_hex().to_string();
     
     // Store blob
-    if !state.blob_manager.legacy_exists(&blob_hash)? {
-        state.blob_manager.write_legacy_blob(&blob_hash, &content)?;
+    if !state.blob_manager.legacy_exists(&blob_hash).await? {
+        state.blob_manager.write_legacy_blob(&blob_hash, &content).await?;
     }
     
-    // Upsert file record (shared folder system - no ownership)
-    let file = files::upsert_file_global(&state.db, &req.path).await?;
-
-    // Create version without user tracking (shared folder system)
-    let version = versions::create_version_global(
-        &state.db,
-        file.id,
-        &blob_hash,
-        content.len() as i64,
-    ).await?;
-    
-    // Update current version
-    files::set_current_version(&state.db, file.id, version.id).await?;
+    // Upsert file record, create the version and make it current (shared
+    // folder system - no ownership or user tracking), without being cut off
+    // by a disconnect or shutdown
+    let file = state.in_flight.run({
+        let db = state.db.clone();
+        let path = path.clone();
+        let blob_hash = blob_hash.clone();
+        let size_bytes = content.len() as i64;
+        async move {
+            // Still holds the replaced version, which picks the notified action
+            let file = files::upsert_file_global(&db, &path).await?;
+            let version = versions::create_version_global(&db, file.id, &blob_hash, size_bytes).await?;
+            files::set_current_version(&db, file.id, version.id).await?;
+            Ok(file)
+        }
+    }).await?;
     
     // Notify connected clients about the new file (send actual path for menu bar display)
-    state.sync_hub.notify_file_changed(&req.path, "create");
+    let action = SyncNotification::content_action(file.current_version_id);
+    state.sync_hub.notify_file_changed(None, &path, action);
+    audit.record(&state, Some(user_id), AuditAction::Upload, Some(&path)).await;
     
     Ok(Json(UploadResponse {
         id: file.id.to_string(),
-        path: req.path,
+        path,
         blob_hash,
         size_bytes: content.len() as i64,
     }))
@@ -119,7 +145,7 @@ async fn list_files(
     Query(query): Query<ListFilesQuery>,
     headers: axum::http::HeaderMap,
 ) -> Result<Json<ListFilesResponse>, AppError> {
-    let user_id = extract_user_id(&state, &headers)?;
+    let user_id = extract_user_id(&state, &headers).await?;
 
     let (file_list, total) = files::list_files(
         &state.db,
@@ -152,9 +178,44 @@ async fn get_file(
     State(state): State<AppState>,
     Path(id): Path<String>,
     headers: axum::http::HeaderMap,
-) -> Result<Json<FileResponse>, AppError> {
-    let user_id = extract_user_id(&state, &headers)?;
+) -> Result<axum::response::Response, AppError> {
+    let user_id = extract_user_id(&state, &headers).await?;
+    let (file, version_id) = file_metadata(&state, user_id, id).await?;
+    let etag = version_id.map(|v| [(header::ETAG, version_etag(v))]);
+    Ok((etag, Json(file)).into_response())
+}
+
+/// Headers of `GET /files/:id` without its body
+/// HEAD /files/:id
+async fn head_file(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    headers: axum::http::HeaderMap,
+) -> Result<axum::response::Response, AppError> {
+    let user_id = extract_user_id(&state, &headers).await?;
+    let (file, version_id) = file_metadata(&state, user_id, id).await?;
+    let length = serde_json::to_vec(&file)
+        .map_err(|e| AppError::Internal(format!("Failed to serialize file: {}", e)))?
+        .len();
+    let etag = version_id.map(|v| [(header::ETAG, version_etag(v))]);
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/json".to_string()),
+            (header::CONTENT_LENGTH, length.to_string()),
+        ],
+        etag,
+        (),
+    )
+        .into_response())
+}
 
+/// Look up a file, or a folder by its path hash, returning it with its
+/// current version (the ETag)
+async fn file_metadata(
+    state: &AppState,
+    user_id: Uuid,
+    id: String,
+) -> Result<(FileResponse, Option<Uuid>), AppError> {
     let file = if let Ok(file_id) = Uuid::parse_str(&id) {
         // Try UUID first (regular files) - with ownership check
         files::get_file_by_id_with_owner(&state.db, file_id, user_id)
@@ -181,47 +242,28 @@ async fn get_file(
             let mut found_path = None;
             let mut seen_dirs = std::collections::HashSet::new();
 
-            // Look for directory paths matching this hash
-            for raw_path in all_paths {
-                // Ensure path starts with / for processing
-                let path = if raw_path.starts_with('/') {
-                    raw_path.clone()
-                } else {
-                    format!("/{}", raw_path)
-                };
-
-                // Scan character by character for directory separators
-                for (i, c) in path.chars().enumerate() {
-                    if c == '/' && i > 0 {
-                        // Found a directory path (e.g., "/music/")
-                        let candidate = &path[0..=i];
-
-                        // Clean double slashes
-                        let clean_candidate = candidate.replace("//", "/");
-
-                        // Avoid duplicate work
-                        if seen_dirs.contains(&clean_candidate) {
-                            continue;
-                        }
-                        seen_dirs.insert(clean_candidate.clone());
-
-                        // Check if this path's hash matches the requested ID
-                        let hash = blake3::hash(clean_candidate.as_bytes()).to_hex().to_string();
-
-                        if hash == id {
-                            found_path = Some(clean_candidate);
-                            break;
-                        }
+            // Look for directory paths matching this hash (stored paths are
+            // normalized, so each directory has exactly one spelling)
+            'search: for path in &all_paths {
+                for candidate in files::dir_prefixes(path) {
+                    // Avoid duplicate work
+                    if !seen_dirs.insert(candidate) {
+                        continue;
+                    }
+
+                    // Check if this path's hash matches the requested ID
+                    let hash = blake3::hash(candidate.as_bytes()).to_hex().to_string();
+
+                    if hash == id {
+                        found_path = Some(candidate.to_string());
+                        break 'search;
                     }
-                }
-                if found_path.is_some() {
-                    break;
                 }
             }
 
             if let Some(virtual_path) = found_path {
                 // Return a virtual folder response
-                return Ok(Json(FileResponse {
+                return Ok((FileResponse {
                     id: id, // Return the hash as ID for virtual folders
                     path: virtual_path,
                     size_bytes: Some(0),
@@ -230,7 +272,7 @@ async fn get_file(
                     is_deleted: false,
                     created_at: chrono::Utc::now().to_rfc3339(),
                     updated_at: chrono::Utc::now().to_rfc3339(),
-                }));
+                }, None));
             } else {
                 return Err(AppError::NotFound("Folder not found".into()));
             }
@@ -239,7 +281,7 @@ async fn get_file(
         return Err(AppError::BadRequest("Invalid file ID".into()));
     };
 
-    Ok(Json(FileResponse {
+    Ok((FileResponse {
         // Return the original_hash_id if it exists (Sticky ID), otherwise use the UUID
         id: file.original_hash_id.unwrap_or(file.id.to_string()),
         path: file.path.clone(),
@@ -249,13 +291,14 @@ async fn get_file(
         is_deleted: file.is_deleted,
         created_at: file.created_at.to_rfc3339(),
         updated_at: file.updated_at.to_rfc3339(),
-    }))
+    }, file.current_version_id))
 }
 
 async fn update_file(
     State(state): State<AppState>,
     Path(id): Path<String>,
     headers: axum::http::HeaderMap,
+    audit: AuditContext,
     Json(req): Json<UpdateFileRequest>,
 ) -> Result<Json<FileResponse>, AppError> {
     // CRITICAL DEBUG LOG
@@ -264,7 +307,7 @@ async fn update_file(
     tracing::info!("New path: {}", req.path);
     tracing::info!("ID type: {}", if id.len() == 64 && id.chars().all(|c| c.is_ascii_hexdigit()) { "HASH" } else if Uuid::parse_str(&id).is_ok() { "UUID" } else { "OTHER" });
 
-    let user_id = extract_user_id(&state, &headers)?;
+    let user_id = extract_writer_id(&state, &headers).await?;
 
     // Validate new path
     if req.path.trim().is_empty() {
@@ -274,18 +317,24 @@ async fn update_file(
 
     // Try to parse as UUID first (Real File or Real Folder)
     let updated_file = if let Ok(file_id) = Uuid::parse_str(&id) {
-        files::move_file(&state.db, file_id, &req.path, user_id).await?
+        // Preconditions apply to records; virtual folders have no version or timestamp
+        if let Some(file) = files::get_file_by_id_with_owner(&state.db, file_id, user_id).await? {
+            check_if_match(&headers, file.current_version_id)?;
+            check_unmodified_since(&headers, file.updated_at)?;
+        }
+        files::move_file(&state.db, file_id, &req.path, user_id, state.config.case_insensitive_paths).await?
     } else if id.len() == 64 && id.chars().all(|c| c.is_ascii_hexdigit()) {
         // BLAKE3 Hash (Virtual Folder OR Materialized Folder with Sticky ID)
         
         // 1. Check if we have a real record that "claims" this hash (Sticky ID)
         if let Some(existing_file) = files::get_file_by_original_hash(&state.db, &id).await? {
             tracing::warn!("DEBUG: Found materialized folder via Sticky ID: {}", id);
-            files::move_file(&state.db, existing_file.id, &req.path, user_id).await?
+            check_if_match(&headers, existing_file.current_version_id)?;
+            check_unmodified_since(&headers, existing_file.updated_at)?;
+            files::move_file(&state.db, existing_file.id, &req.path, user_id, state.config.case_insensitive_paths).await?
         } else {
             // 2. Fallback to Virtual Resolution (Scan all paths)
             // We need to resolve the hash to a path by scanning existing files.
-            // CRITICAL: We must replicate `list_directory`'s normalization exactly.
             
             let all_paths: Vec<String> = sqlx::query_scalar(
                 "SELECT path FROM files WHERE is_deleted = FALSE"
@@ -298,37 +347,18 @@ async fn update_file(
             
             tracing::warn!("DEBUG: Resolving Virtual ID: {}", id);
 
-            'search: for raw_path in all_paths {
-                // Ensure path starts with / for processing
-                let path = if raw_path.starts_with('/') {
-                    raw_path.clone()
-                } else {
-                    format!("/{}", raw_path)
-                };
-
-                // Scan character by character for directory separators
-                for (i, c) in path.chars().enumerate() {
-                    if c == '/' && i > 0 {
-                         // Found a separator at 'i'. 
-                         // Substring [0..=i] is a candidate directory path (e.g. "/music/")
-                         let candidate = &path[0..=i];
-                         
-                         // DOUBLE SLASH REGRESSION FIX:
-                         // Ensure no double-slashes before hashing
-                         let clean_candidate = candidate.replace("//", "/");
-                         
-                         if seen_dirs.contains(&clean_candidate) {
-                             continue;
-                         }
-                         seen_dirs.insert(clean_candidate.clone());
-                         
-                         let hash = blake3::hash(clean_candidate.as_bytes()).to_hex().to_string();
-                         
-                         if hash == id {
-                             tracing::warn!("DEBUG: MATCH FOUND! Path: {}", clean_candidate);
-                             found_path = Some(clean_candidate);
-                             break 'search;
-                         }
+            'search: for path in &all_paths {
+                for candidate in files::dir_prefixes(path) {
+                    if !seen_dirs.insert(candidate) {
+                        continue;
+                    }
+
+                    let hash = blake3::hash(candidate.as_bytes()).to_hex().to_string();
+
+                    if hash == id {
+                        tracing::warn!("DEBUG: MATCH FOUND! Path: {}", candidate);
+                        found_path = Some(candidate.to_string());
+                        break 'search;
                     }
                 }
             }
@@ -337,7 +367,7 @@ async fn update_file(
                 // Found it! resolved_path (e.g. "/music/ppooll/")
                 tracing::warn!("DEBUG: Found virtual folder at path: {}", resolved_path);
                 tracing::warn!("DEBUG: Moving to: {}", req.path);
-                files::move_path(&state.db, &resolved_path, &req.path, user_id).await?
+                files::move_path(&state.db, &resolved_path, &req.path, user_id, state.config.case_insensitive_paths).await?
             } else {
                 tracing::error!("DEBUG: FAILED to find path for ID: {}", id);
                 return Err(AppError::NotFound(format!("Folder not found for ID {}", id)));
@@ -354,7 +384,8 @@ async fn update_file(
     tracing::warn!("Original hash ID: {:?}", updated_file.original_hash_id);
 
     // Notify connected clients about the move/rename (send actual path for menu bar display)
-    state.sync_hub.notify_file_changed(&updated_file.path, "move");
+    state.sync_hub.notify_file_changed(updated_file.owner_id, &updated_file.path, "move");
+    audit.record(&state, Some(user_id), AuditAction::Move, Some(&updated_file.path)).await;
 
     Ok(Json(FileResponse {
         // CRITICAL: Return the Sticky ID (original hash) if it exists.
@@ -372,12 +403,16 @@ async fn update_file(
 }
 
 /// Soft delete a file - keeps blob for history, marks as deleted
+///
+/// With `If-Match: <version_id>`, returns 409 Conflict unless that is still the
+/// file's current version.
 async fn delete_file(
     State(state): State<AppState>,
     Path(id): Path<String>,
     headers: axum::http::HeaderMap,
+    audit: AuditContext,
 ) -> Result<StatusCode, AppError> {
-    let user_id = extract_user_id(&state, &headers)?;
+    let user_id = extract_writer_id(&state, &headers).await?;
     
     // Try to parse as UUID first (for regular files)
     let file_id = if let Ok(uuid) = Uuid::parse_str(&id) {
@@ -415,16 +450,35 @@ async fn delete_file(
     let file_info = files::get_file_by_id_with_owner(&state.db, file_id, user_id)
         .await?
         .ok_or_else(|| AppError::NotFound("File not found".into()))?;
-
-    // Soft delete with ownership check - set is_deleted = true (recursive for directories)
-    let deleted = files::soft_delete_recursive_with_owner(&state.db, file_id, user_id).await?;
+    check_if_match(&headers, file_info.current_version_id)?;
+    check_unmodified_since(&headers, file_info.updated_at)?;
+
+    // Optimistic concurrency: with If-Match or If-Unmodified-Since, the
+    // preconditions are checked again as part of the delete itself
+    let expected_version = if_match_version(&headers)?;
+    let unmodified_since = if_unmodified_since(&headers)?;
+    let deleted = if expected_version.is_some() || unmodified_since.is_some() {
+        let deleted = files::soft_delete_if_unchanged(&state.db, file_id, user_id, expected_version, unmodified_since).await?;
+        if !deleted {
+            // Changed since the checks above: report which precondition now fails
+            if let Some(file) = files::get_file_by_id_with_owner(&state.db, file_id, user_id).await? {
+                check_if_match(&headers, file.current_version_id)?;
+                check_unmodified_since(&headers, file.updated_at)?;
+            }
+        }
+        deleted
+    } else {
+        // Soft delete with ownership check - set is_deleted = true (recursive for directories)
+        files::soft_delete_recursive_with_owner(&state.db, file_id, user_id).await?
+    };
 
     if !deleted {
         return Err(AppError::NotFound("File not found or access denied".into()));
     }
 
     // Notify connected clients about the deletion (send actual path for menu bar display)
-    state.sync_hub.notify_file_changed(&file_info.path, "delete");
+    state.sync_hub.notify_file_changed(file_info.owner_id, &file_info.path, "delete");
+    audit.record(&state, Some(user_id), AuditAction::Delete, Some(&file_info.path)).await;
     
     Ok(StatusCode::NO_CONTENT)
 }
@@ -434,7 +488,7 @@ async fn download_file(
     Path(id): Path<String>,
     headers: axum::http::HeaderMap,
 ) -> Result<impl IntoResponse, AppError> {
-    let user_id = extract_user_id(&state, &headers)?;
+    let user_id = extract_user_id(&state, &headers).await?;
 
     tracing::info!("Download request for file ID: {}", id);
 
@@ -470,7 +524,7 @@ async fn download_file(
         
         // Read and concatenate chunks in order
         for vc in version_chunks {
-            let chunk_data = state.blob_manager.read_legacy_blob(&vc.chunk_hash)?;
+            let chunk_data = state.blob_manager.read_legacy_blob(&vc.chunk_hash).await?;
             reassembled.extend_from_slice(&chunk_data);
         }
         
@@ -479,7 +533,7 @@ async fn download_file(
         // Non-chunked file - read single blob
         let blob_hash = file.blob_hash
             .ok_or_else(|| AppError::NotFound("File has no content".into()))?;
-        state.blob_manager.read_legacy_blob(&blob_hash)?
+        state.blob_manager.read_legacy_blob(&blob_hash).await?
     };
     
     let filename = std::path::Path::new(&file.path)
@@ -0,0 +1,198 @@
+//! HTTP connections: HTTP/1.1 and HTTP/2 on the one port
+//!
+//! A connection that opens with the HTTP/2 preface is served as HTTP/2:
+//! h2c with prior knowledge, as sent by clients configured for it and by
+//! TLS-terminating proxies that speak HTTP/2 to their backends. Anything
+//! else is served as HTTP/1.1. Over HTTP/2 a sync client multiplexes its
+//! chunk uploads and downloads on one connection instead of holding one
+//! connection per request in flight.
+//!
+//! `HTTP2=false` serves HTTP/1.1 only, `HTTP1_KEEP_ALIVE=false` closes
+//! HTTP/1.1 connections after each response, and HTTP/2 connections are
+//! pinged every `HTTP2_PING_INTERVAL_SECS` so dead peers (and idle proxies)
+//! are noticed.
+//!
+//! With a [`TlsAcceptor`] (see [`super::tls`]) every connection is TLS
+//! first, and HTTP/2 is chosen over ALPN rather than by prior knowledge.
+
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::http::Request;
+use axum::Router;
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+use hyper_util::server::conn::auto;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tokio_rustls::TlsAcceptor;
+use tower::Service;
+
+/// Wait after an accept error that isn't about a single connection (e.g. out
+/// of file descriptors) before accepting again
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Time a client gets to complete the TLS handshake
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Protocol and keep-alive settings for client connections
+#[derive(Debug, Clone, Copy)]
+pub struct HttpSettings {
+    /// Serve HTTP/2 alongside HTTP/1.1
+    pub http2: bool,
+    /// Keep HTTP/1.1 connections open between requests
+    pub http1_keep_alive: bool,
+    /// Ping idle HTTP/2 connections this often (None = never)
+    pub http2_ping_interval: Option<Duration>,
+    /// Requests one HTTP/2 connection may have in flight
+    pub http2_max_concurrent_streams: u32,
+}
+
+/// Connection builders for the configured [`HttpSettings`], shared by
+/// every connection task
+struct Protocols {
+    http2: bool,
+    auto: auto::Builder<TokioExecutor>,
+    http1: http1::Builder,
+}
+
+impl Protocols {
+    fn new(settings: HttpSettings) -> Self {
+        let mut auto = auto::Builder::new(TokioExecutor::new());
+        auto.http1().keep_alive(settings.http1_keep_alive);
+        auto.http2()
+            .timer(TokioTimer::new())
+            .keep_alive_interval(settings.http2_ping_interval)
+            .max_concurrent_streams(settings.http2_max_concurrent_streams)
+            .adaptive_window(true);
+        let mut http1 = http1::Builder::new();
+        http1.keep_alive(settings.http1_keep_alive);
+        Self {
+            http2: settings.http2,
+            auto,
+            http1,
+        }
+    }
+}
+
+/// Serve `app` on `listener` until `signal` resolves, then let open
+/// connections finish their requests and return once all have closed.
+/// With `tls`, connections must complete a TLS handshake first.
+pub async fn serve<F>(
+    listener: TcpListener,
+    app: Router,
+    settings: HttpSettings,
+    tls: Option<TlsAcceptor>,
+    signal: F,
+) -> std::io::Result<()>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let protocols = Arc::new(Protocols::new(settings));
+
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let mut connections = JoinSet::new();
+    tokio::pin!(signal);
+
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) if is_connection_error(&e) => continue,
+                Err(e) => {
+                    tracing::error!("Failed to accept connection: {}", e);
+                    tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                    continue;
+                }
+            },
+            // Reap finished connections as they go
+            Some(_) = connections.join_next(), if !connections.is_empty() => continue,
+            _ = &mut signal => break,
+        };
+        // Chunk requests are small; don't hold them back to fill packets
+        let _ = stream.set_nodelay(true);
+
+        // ConnectInfo supplies the peer address the client IP is resolved from
+        let app = app.clone();
+        let service = hyper::service::service_fn(move |mut req: Request<Incoming>| {
+            req.extensions_mut().insert(ConnectInfo(peer));
+            app.clone().call(req.map(Body::new))
+        });
+
+        let protocols = protocols.clone();
+        let shutdown = shutdown_rx.clone();
+        let Some(tls) = tls.clone() else {
+            connections.spawn(async move { serve_io(&protocols, stream, service, shutdown).await });
+            continue;
+        };
+        // Handshake on the connection's own task so a slow client can't
+        // hold up accepting
+        connections.spawn(async move {
+            match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, tls.accept(stream)).await {
+                Ok(Ok(stream)) => serve_io(&protocols, stream, service, shutdown).await,
+                Ok(Err(e)) => tracing::debug!("TLS handshake with {} failed: {}", peer, e),
+                Err(_) => tracing::debug!("TLS handshake with {} timed out", peer),
+            }
+        });
+    }
+
+    // Stop accepting, then ask every connection to finish up
+    drop(listener);
+    let _ = shutdown_tx.send(true);
+    while connections.join_next().await.is_some() {}
+    Ok(())
+}
+
+/// Serve HTTP on an accepted (and, with TLS, handshaken) stream
+async fn serve_io<I, S>(protocols: &Protocols, stream: I, service: S, shutdown: watch::Receiver<bool>)
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S: hyper::service::Service<Request<Incoming>, Response = axum::response::Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let io = TokioIo::new(stream);
+    if protocols.http2 {
+        let conn = protocols.auto.serve_connection_with_upgrades(io, service);
+        serve_connection(conn, shutdown, auto::UpgradeableConnection::graceful_shutdown).await;
+    } else {
+        let conn = protocols.http1.serve_connection(io, service).with_upgrades();
+        serve_connection(conn, shutdown, http1::UpgradeableConnection::graceful_shutdown).await;
+    }
+}
+
+/// Drive one connection, shutting it down gracefully (open requests finish,
+/// no new ones start) once `shutdown` changes
+async fn serve_connection<C, E>(conn: C, mut shutdown: watch::Receiver<bool>, graceful_shutdown: fn(Pin<&mut C>))
+where
+    C: Future<Output = Result<(), E>>,
+    E: std::fmt::Display,
+{
+    tokio::pin!(conn);
+    let result = tokio::select! {
+        result = conn.as_mut() => result,
+        _ = shutdown.changed() => {
+            graceful_shutdown(conn.as_mut());
+            conn.await
+        }
+    };
+    if let Err(e) = result {
+        tracing::debug!("Connection closed with error: {}", e);
+    }
+}
+
+/// Errors that end one connection attempt, not the listener
+fn is_connection_error(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        std::io::ErrorKind::ConnectionRefused
+            | std::io::ErrorKind::ConnectionAborted
+            | std::io::ErrorKind::ConnectionReset
+    )
+}
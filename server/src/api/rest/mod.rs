@@ -3,30 +3,43 @@
 //! Organized into domain-specific submodules for maintainability.
 
 mod admin;
+mod audit;
 mod auth;
 mod blobs;
 mod chunks;
+mod compression;
 mod conflicts;
+mod cors;
 mod error;
 mod files;
+mod listener;
+mod openapi;
 mod selective_sync;
 mod sharing;
+mod tar_stream;
+mod throttle;
+mod timeout;
+mod tls;
 mod types;
 mod v1;
 mod versions;
+mod zip_stream;
 
+use crate::api::client_ip::{resolve_client_ip, ClientIpKeyExtractor};
+use crate::api::metrics::track_requests;
 use crate::api::AppState;
-use axum::extract::DefaultBodyLimit;
-use axum::http::{header, HeaderValue, Method};
+use axum::extract::{DefaultBodyLimit, Request, State};
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
 use axum::Router;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use tower_governor::governor::GovernorConfigBuilder;
 use tower_governor::GovernorLayer;
-use tower_http::cors::CorsLayer;
 use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
 use tower_http::set_header::SetResponseHeaderLayer;
-use tower_http::trace::{DefaultMakeSpan, DefaultOnResponse, TraceLayer};
+use tower_http::trace::{DefaultOnResponse, TraceLayer};
 use tracing::Level;
 
 // Re-export router functions for external use
@@ -34,60 +47,91 @@ pub use admin::admin_routes;
 pub use auth::auth_routes;
 pub use blobs::metadata_routes;
 pub use conflicts::conflict_routes;
-pub use files::file_routes;
+pub use files::{base64_upload_routes, file_routes, file_upload_routes, MAX_BASE64_UPLOAD_BYTES};
+pub use openapi::openapi_routes;
 pub use selective_sync::selective_sync_routes;
 pub use sharing::sharing_routes;
-pub use v1::v1_routes;
+pub use v1::{v1_routes, v1_upload_routes};
+pub(crate) use error::{authenticate_token, validate_path};
+pub(crate) use compression::is_binary_route;
+pub(crate) use error::AppError;
+pub use listener::HttpSettings;
+pub(crate) use v1::download_response;
 
 pub async fn serve(addr: SocketAddr, state: AppState) -> anyhow::Result<()> {
-    // CORS: Read allowed origins from CORS_ORIGINS env var (comma-separated)
-    // Falls back to localhost for development
-    let cors_origins: Vec<HeaderValue> = std::env::var("CORS_ORIGINS")
-        .unwrap_or_else(|_| "http://localhost:3000,http://127.0.0.1:3000".to_string())
-        .split(',')
-        .filter_map(|s| s.trim().parse().ok())
-        .collect();
-
-    tracing::info!("CORS allowed origins: {:?}", cors_origins);
-
-    let cors = CorsLayer::new()
-        .allow_origin(cors_origins)
-        .allow_methods([
-            Method::GET,
-            Method::POST,
-            Method::PUT,
-            Method::DELETE,
-            Method::OPTIONS,
-        ])
-        .allow_headers([
-            header::AUTHORIZATION,
-            header::CONTENT_TYPE,
-            header::ACCEPT,
-        ])
-        .allow_credentials(true)
-        // Expose X-Request-Id header to clients
-        .expose_headers(vec![header::HeaderName::from_static("x-request-id")]);
-
-    // SECURITY: Body size limit - 1GB max for file uploads
-    let body_limit = DefaultBodyLimit::max(1024 * 1024 * 1024); // 1GB
-
-    // SECURITY: Global rate limiting (100 requests burst, refill ~1 per 100ms per IP)
+    // CORS: allowlist from CORS_ORIGINS (exact origins and *.domain wildcards)
+    let cors = cors::cors_layer();
+
+    // SECURITY: Body size limits - raw chunk/blob uploads get MAX_UPLOAD_SIZE,
+    // everything else is capped much lower, and base64 uploads (decoded in
+    // memory) lower still
+    let max_upload_bytes = state.config.max_upload_bytes;
+    let max_json_bytes = state.config.max_json_bytes;
+    let max_base64_bytes = max_json_bytes.min(MAX_BASE64_UPLOAD_BYTES);
+
+    // SECURITY: Global rate limiting (100 requests burst, refill ~1 per 100ms
+    // per client IP, as resolved through TRUSTED_PROXIES)
     let governor_conf = Arc::new(
         GovernorConfigBuilder::default()
+            .key_extractor(ClientIpKeyExtractor)
             .per_second(10)
             .burst_size(100)
             .finish()
             .expect("Failed to build rate limiter config"),
     );
 
+    // Response compression for JSON bodies (binary routes are excluded below)
+    let compression_enabled = state.config.compression_enabled;
+    let compression_min_bytes = state.config.compression_min_bytes;
+
+    // HTTP/2 and keep-alive for client connections, over TLS when
+    // TLS_CERT_PATH and TLS_KEY_PATH are set
+    let http_settings = state.config.http_settings();
+    let tls = match (&state.config.tls_cert_path, &state.config.tls_key_path) {
+        (Some(cert_path), Some(key_path)) => {
+            let acceptor = tls::acceptor(cert_path.as_ref(), key_path.as_ref(), http_settings.http2)?;
+            tracing::info!("serving HTTPS with the certificate in {} (SIGHUP reloads it)", cert_path);
+            Some(acceptor)
+        }
+        _ => None,
+    };
+
+    // Shutdown waits this long for open requests and upload finalizations
+    let grace = std::time::Duration::from_secs(state.config.shutdown_grace_secs);
+    let in_flight = state.in_flight.clone();
+
     // Request ID header name
     let x_request_id = header::HeaderName::from_static("x-request-id");
 
-    // Tracing layer with request ID included in spans
+    // Tracing layer with the request ID as a span field. Headers are left
+    // out so bearer tokens never reach the logs.
     let trace_layer = TraceLayer::new_for_http()
-        .make_span_with(DefaultMakeSpan::new().include_headers(true).level(Level::INFO))
+        .make_span_with(|request: &Request| {
+            let request_id = request
+                .headers()
+                .get("x-request-id")
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default();
+            tracing::info_span!(
+                "request",
+                method = %request.method(),
+                uri = %request.uri(),
+                version = ?request.version(),
+                request_id,
+            )
+        })
         .on_response(DefaultOnResponse::new().level(Level::INFO));
 
+    let upload_routes = Router::new()
+        .merge(file_upload_routes())
+        .merge(v1_upload_routes())
+        .layer(DefaultBodyLimit::max(max_upload_bytes))
+        .layer(middleware::from_fn_with_state(max_upload_bytes, body_limit_error));
+
+    let base64_routes = base64_upload_routes()
+        .layer(DefaultBodyLimit::max(max_base64_bytes))
+        .layer(middleware::from_fn_with_state(max_base64_bytes, base64_limit_error));
+
     // Build app with request ID middleware
     let app = Router::new()
         .merge(auth_routes())
@@ -95,15 +139,59 @@ pub async fn serve(addr: SocketAddr, state: AppState) -> anyhow::Result<()> {
         .merge(v1_routes())
         .merge(metadata_routes())
         .merge(admin_routes())
+        .merge(openapi_routes())
         .merge(conflict_routes())
         .merge(sharing_routes())
         .merge(selective_sync_routes())
+        .layer(DefaultBodyLimit::max(max_json_bytes))
+        .layer(middleware::from_fn_with_state(max_json_bytes, body_limit_error))
+        .merge(upload_routes)
+        .merge(base64_routes)
         // SECURITY: Rate limiting per IP
         .layer(GovernorLayer {
-            config: governor_conf,
+            config: governor_conf.clone(),
         })
         .layer(cors)
-        .layer(body_limit)
+        // WebDAV sits outside CORS, which would answer its OPTIONS probes as preflights
+        .merge(crate::api::webdav::webdav_routes().layer(GovernorLayer {
+            config: governor_conf,
+        }));
+
+    // Metrics share the main port only when a token protects them and no
+    // separate listener is configured
+    let app = if state.config.metrics_token.is_some() && state.config.metrics_bind.is_none() {
+        app.merge(crate::api::metrics::metrics_routes())
+    } else {
+        app
+    };
+
+    // The client IP is resolved before the rate limiter and handlers see the request
+    let app = app.layer(middleware::from_fn_with_state(
+        state.config.trusted_proxies.clone(),
+        resolve_client_ip,
+    ));
+
+    // Maintenance mode refuses writes before any handler (or WebDAV) runs
+    let app = app.layer(middleware::from_fn_with_state(
+        state.maintenance.clone(),
+        crate::api::maintenance::reject_writes,
+    ));
+
+    // Deadlines wrap everything, WebDAV included, so a stalled body can't pin a worker
+    let timeouts = timeout::RequestTimeouts::from_secs(
+        state.config.request_timeout_secs,
+        state.config.transfer_timeout_secs,
+    );
+    let app = app.layer(middleware::from_fn_with_state(timeouts, timeout::enforce_deadline));
+
+    let app = if compression_enabled {
+        app.layer(compression::compression_layer(compression_min_bytes))
+            .layer(middleware::from_fn(compression::skip_binary_routes))
+    } else {
+        app
+    };
+
+    let app = app
         // SECURITY: Content Security Policy - prevents XSS and injection attacks
         .layer(SetResponseHeaderLayer::overriding(
             header::HeaderName::from_static("content-security-policy"),
@@ -134,22 +222,98 @@ pub async fn serve(addr: SocketAddr, state: AppState) -> anyhow::Result<()> {
             header::HeaderName::from_static("x-xss-protection"),
             HeaderValue::from_static("1; mode=block"),
         ))
-        // Request ID: Generate UUID, set on request, propagate to response
+        .layer(trace_layer)
+        // Request ID: Generate UUID, set on request, propagate to response.
+        // Outside the trace layer, so the request span can record it.
         .layer(PropagateRequestIdLayer::new(x_request_id.clone()))
         .layer(SetRequestIdLayer::new(x_request_id, MakeRequestUuid))
-        .layer(trace_layer)
-        .with_state(state);
+        .layer(middleware::from_fn_with_state(state.metrics.clone(), track_requests))
+        .with_state(state.clone());
+
+    if let Some(metrics_addr) = state.config.metrics_bind {
+        let metrics_app = crate::api::metrics::metrics_routes().with_state(state);
+        let listener = tokio::net::TcpListener::bind(metrics_addr).await?;
+        tracing::info!("Metrics listening on {}", metrics_addr);
+        tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, metrics_app).await {
+                tracing::error!("Metrics listener failed: {}", e);
+            }
+        });
+    }
 
     let listener = tokio::net::TcpListener::bind(addr).await?;
+    crate::systemd::notify("READY=1");
     
-    // Graceful shutdown: wait for SIGTERM or SIGINT
-    axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal())
-        .await?;
+    // Graceful shutdown: on SIGTERM or SIGINT stop accepting connections, then
+    // give open requests and upload finalizations the grace period to finish
+    let (deadline_tx, deadline_rx) = tokio::sync::watch::channel(None);
+    let server = listener::serve(listener, app, http_settings, tls, async move {
+        shutdown_signal().await;
+        crate::systemd::notify("STOPPING=1");
+        let _ = deadline_tx.send(Some(tokio::time::Instant::now() + grace));
+    });
+    let mut signalled = deadline_rx.clone();
+    let grace_expired = async move {
+        let signalled = signalled.wait_for(Option::is_some).await.map(|d| d.expect("deadline is set"));
+        let Ok(deadline) = signalled else {
+            // Sender dropped: the server stopped without a signal
+            return std::future::pending().await;
+        };
+        tokio::time::sleep_until(deadline).await
+    };
+
+    tokio::select! {
+        result = server => result?,
+        _ = grace_expired => {
+            tracing::warn!("Connections still open after {}s, closing them", grace.as_secs());
+        }
+    }
+
+    // Finalizations run on their own tasks, so they can outlive their connection
+    let deadline = deadline_rx
+        .borrow()
+        .unwrap_or_else(|| tokio::time::Instant::now() + grace);
+    let (awaited, unfinished) = in_flight.drain(deadline).await;
+    if unfinished > 0 {
+        tracing::warn!(
+            "Shutting down with {} of {} upload finalization(s) unfinished",
+            unfinished, awaited
+        );
+    } else {
+        tracing::info!("Waited for {} in-flight upload finalization(s)", awaited);
+    }
 
     Ok(())
 }
 
+/// Replace axum's plain-text 413 with a JSON error naming the configured limit
+async fn body_limit_error(State(limit): State<usize>, req: Request, next: Next) -> Response {
+    let response = next.run(req).await;
+    if response.status() != StatusCode::PAYLOAD_TOO_LARGE {
+        return response;
+    }
+    let body = serde_json::json!({
+        "error": format!("Request body exceeds the {} byte limit", limit)
+    });
+    (StatusCode::PAYLOAD_TOO_LARGE, axum::Json(body)).into_response()
+}
+
+/// [`body_limit_error`] for base64 uploads, pointing at the routes for large files
+async fn base64_limit_error(State(limit): State<usize>, req: Request, next: Next) -> Response {
+    let response = next.run(req).await;
+    if response.status() != StatusCode::PAYLOAD_TOO_LARGE {
+        return response;
+    }
+    let body = serde_json::json!({
+        "error": format!(
+            "Request body exceeds the {} byte limit for base64 uploads; \
+             upload larger files with POST /v1/files/upload or the chunk API",
+            limit
+        )
+    });
+    (StatusCode::PAYLOAD_TOO_LARGE, axum::Json(body)).into_response()
+}
+
 /// Wait for shutdown signal (SIGTERM or SIGINT)
 async fn shutdown_signal() {
     let ctrl_c = async {
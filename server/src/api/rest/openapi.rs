@@ -0,0 +1,72 @@
+//! OpenAPI description of the REST API
+//!
+//! `GET /openapi.json` serves a hand-maintained OpenAPI 3 document
+//! (`openapi.json` next to this file) covering auth, chunks and the v1 file
+//! endpoints, so clients in other languages can be generated or checked
+//! against it. It describes the API and holds no data, so it is served without
+//! auth. `info.version` is filled in from the server's own version.
+//!
+//! Keep the document in step with the handlers when a v1 endpoint changes.
+
+use crate::api::AppState;
+use axum::routing::get;
+use axum::{Json, Router};
+use std::sync::OnceLock;
+
+const SPEC: &str = include_str!("openapi.json");
+
+pub fn openapi_routes() -> Router<AppState> {
+    Router::new().route("/openapi.json", get(get_openapi))
+}
+
+/// GET /openapi.json
+async fn get_openapi() -> Json<serde_json::Value> {
+    static DOCUMENT: OnceLock<serde_json::Value> = OnceLock::new();
+    Json(DOCUMENT.get_or_init(document).clone())
+}
+
+fn document() -> serde_json::Value {
+    let mut spec: serde_json::Value = serde_json::from_str(SPEC).expect("openapi.json is valid JSON");
+    spec["info"]["version"] = env!("CARGO_PKG_VERSION").into();
+    spec
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every `$ref` in `value`
+    fn refs<'a>(value: &'a serde_json::Value, out: &mut Vec<&'a str>) {
+        match value {
+            serde_json::Value::Object(map) => {
+                for (key, value) in map {
+                    match value.as_str() {
+                        Some(target) if key == "$ref" => out.push(target),
+                        _ => refs(value, out),
+                    }
+                }
+            }
+            serde_json::Value::Array(items) => items.iter().for_each(|item| refs(item, out)),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn test_openapi_document() {
+        let spec = document();
+        assert!(spec["openapi"].as_str().unwrap().starts_with("3."));
+        assert_eq!(spec["info"]["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(spec["components"]["securitySchemes"]["bearerAuth"]["scheme"], "bearer");
+        for path in ["/auth/login", "/v1/chunks/check", "/v1/chunks/{hash}", "/v1/files", "/v1/files/list"] {
+            assert!(spec["paths"].get(path).is_some(), "{} is not documented", path);
+        }
+
+        let mut targets = Vec::new();
+        refs(&spec, &mut targets);
+        assert!(!targets.is_empty());
+        for target in targets {
+            let pointer = target.strip_prefix('#').expect("only local references");
+            assert!(spec.pointer(pointer).is_some(), "dangling reference {}", target);
+        }
+    }
+}
@@ -3,7 +3,6 @@
 //! Handles per-user sync preferences and rules.
 
 use crate::api::AppState;
-use crate::auth;
 use axum::{
     extract::{Path, Query, State},
     http::StatusCode,
@@ -14,7 +13,7 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use super::error::AppError;
+use super::error::{extract_user_id, AppError};
 
 // ============================================================================
 // ROUTES
@@ -116,25 +115,13 @@ struct UpdateDeviceRequest {
 // HANDLERS
 // ============================================================================
 
-/// Extract user ID from authorization header
-fn extract_user_id(state: &AppState, headers: &axum::http::HeaderMap) -> Result<Uuid, AppError> {
-    let auth_header = headers
-        .get(axum::http::header::AUTHORIZATION)
-        .and_then(|h| h.to_str().ok())
-        .and_then(|h| h.strip_prefix("Bearer "))
-        .ok_or_else(|| AppError::Unauthorized("Missing authorization".into()))?;
-
-    auth::verify_token(&state.config.jwt_secret, auth_header)
-        .map_err(|_| AppError::Unauthorized("Invalid token".into()))
-}
-
 /// List user's sync rules
 async fn list_rules(
     State(state): State<AppState>,
     Query(query): Query<ListRulesQuery>,
     headers: axum::http::HeaderMap,
 ) -> Result<Json<ListRulesResponse>, AppError> {
-    let user_id = extract_user_id(&state, &headers)?;
+    let user_id = extract_user_id(&state, &headers).await?;
     
     let include_inactive = query.include_inactive.unwrap_or(false);
     
@@ -175,7 +162,7 @@ async fn create_rule(
     headers: axum::http::HeaderMap,
     Json(req): Json<CreateRuleRequest>,
 ) -> Result<Json<SyncRuleResponse>, AppError> {
-    let user_id = extract_user_id(&state, &headers)?;
+    let user_id = extract_user_id(&state, &headers).await?;
     
     // Validate rule type
     if req.rule_type != "include" && req.rule_type != "exclude" {
@@ -223,7 +210,7 @@ async fn get_rule(
     Path(rule_id): Path<Uuid>,
     headers: axum::http::HeaderMap,
 ) -> Result<Json<SyncRuleResponse>, AppError> {
-    let user_id = extract_user_id(&state, &headers)?;
+    let user_id = extract_user_id(&state, &headers).await?;
     
     let rule = sqlx::query_as::<_, (Uuid, String, String, i32, bool, DateTime<Utc>, DateTime<Utc>)>(
         r#"
@@ -258,7 +245,7 @@ async fn update_rule(
     headers: axum::http::HeaderMap,
     Json(req): Json<UpdateRuleRequest>,
 ) -> Result<Json<SyncRuleResponse>, AppError> {
-    let user_id = extract_user_id(&state, &headers)?;
+    let user_id = extract_user_id(&state, &headers).await?;
     
     // Validate rule type if provided
     if let Some(ref rt) = req.rule_type {
@@ -327,7 +314,7 @@ async fn delete_rule(
     Path(rule_id): Path<Uuid>,
     headers: axum::http::HeaderMap,
 ) -> Result<StatusCode, AppError> {
-    let user_id = extract_user_id(&state, &headers)?;
+    let user_id = extract_user_id(&state, &headers).await?;
     
     let result = sqlx::query(
         "DELETE FROM selective_sync_rules WHERE id = $1 AND user_id = $2"
@@ -350,7 +337,7 @@ async fn check_paths(
     headers: axum::http::HeaderMap,
     Json(req): Json<CheckPathsRequest>,
 ) -> Result<Json<CheckPathsResponse>, AppError> {
-    let user_id = extract_user_id(&state, &headers)?;
+    let user_id = extract_user_id(&state, &headers).await?;
     
     // Get all active rules ordered by priority
     let rules = sqlx::query_as::<_, (Uuid, String, String, i32)>(
@@ -394,7 +381,7 @@ async fn list_devices(
     State(state): State<AppState>,
     headers: axum::http::HeaderMap,
 ) -> Result<Json<Vec<DeviceResponse>>, AppError> {
-    let user_id = extract_user_id(&state, &headers)?;
+    let user_id = extract_user_id(&state, &headers).await?;
     
     let devices = sqlx::query_as::<_, (String, Option<String>, Option<DateTime<Utc>>, i64, Option<i64>, bool, DateTime<Utc>, DateTime<Utc>)>(
         r#"
@@ -435,7 +422,7 @@ async fn update_device(
     headers: axum::http::HeaderMap,
     Json(req): Json<UpdateDeviceRequest>,
 ) -> Result<Json<DeviceResponse>, AppError> {
-    let user_id = extract_user_id(&state, &headers)?;
+    let user_id = extract_user_id(&state, &headers).await?;
     
     // Get current device state
     let current = sqlx::query_as::<_, (Option<String>, Option<i64>, bool)>(
@@ -495,7 +482,7 @@ async fn remove_device(
     Path(device_id): Path<String>,
     headers: axum::http::HeaderMap,
 ) -> Result<StatusCode, AppError> {
-    let user_id = extract_user_id(&state, &headers)?;
+    let user_id = extract_user_id(&state, &headers).await?;
     
     let result = sqlx::query(
         "DELETE FROM device_sync_state WHERE user_id = $1 AND device_id = $2"
@@ -4,10 +4,10 @@
 
 use crate::api::AppState;
 use crate::auth;
-use crate::db::{chunks, versions, ChunkLocation};
-use crate::storage::blob_io;
+use crate::config::Config;
+use crate::db::audit::AuditAction;
+use crate::db::{chunks, files, versions};
 use axum::{
-    body::Body,
     extract::{Path, Query, State},
     http::{header, StatusCode},
     routing::{get, post, delete},
@@ -17,7 +17,11 @@ use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use super::error::AppError;
+use super::audit::AuditContext;
+use super::throttle;
+use super::zip_stream;
+use super::error::{extract_user_id, extract_writer_id, http_date, is_not_modified, version_etag, AppError};
+use super::v1::not_modified_response;
 
 // ============================================================================
 // ROUTES
@@ -55,6 +59,8 @@ struct ShareResponse {
     password_protected: bool,
     expires_at: Option<String>,
     max_downloads: Option<i32>,
+    /// Download bandwidth limit (None = unlimited)
+    max_bytes_per_sec: Option<i64>,
     download_count: i32,
     is_active: bool,
     created_at: String,
@@ -73,16 +79,89 @@ struct CreateShareRequest {
     expires_in_hours: Option<i64>,
     /// Maximum number of downloads
     max_downloads: Option<i32>,
+    /// Download bandwidth limit in bytes per second
+    max_bytes_per_sec: Option<i64>,
 }
 
 #[derive(Deserialize)]
 struct ListSharesQuery {
     file_id: Option<String>,
     include_expired: Option<bool>,
+    /// Only shares in this state; overrides `include_expired`
+    status: Option<ShareStatus>,
+    #[serde(default)]
+    sort: ShareSort,
     limit: Option<i64>,
     offset: Option<i64>,
 }
 
+/// State of a share link, as filtered by `GET /shares?status=`. A share is
+/// in exactly one: revoked wins over expired, which wins over exhausted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ShareStatus {
+    /// Can still be opened
+    Active,
+    /// Past its `expires_at`
+    Expired,
+    /// Revoked by its owner
+    Revoked,
+    /// Has reached its `max_downloads`
+    Exhausted,
+}
+
+impl ShareStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            ShareStatus::Active => "active",
+            ShareStatus::Expired => "expired",
+            ShareStatus::Revoked => "revoked",
+            ShareStatus::Exhausted => "exhausted",
+        }
+    }
+}
+
+/// Order of `GET /shares`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ShareSort {
+    /// Newest first
+    #[default]
+    CreatedAt,
+    /// Most downloaded first
+    DownloadCount,
+    /// Soonest to expire first, shares that never expire last
+    ExpiresAt,
+}
+
+impl ShareSort {
+    fn as_str(self) -> &'static str {
+        match self {
+            ShareSort::CreatedAt => "created_at",
+            ShareSort::DownloadCount => "download_count",
+            ShareSort::ExpiresAt => "expires_at",
+        }
+    }
+}
+
+/// Largest page `GET /shares` returns
+const MAX_SHARES_PAGE: i64 = 500;
+
+/// `WHERE` conditions shared by the share listing and its count. Binds:
+/// $1 owner, $2 file id, $3 include_expired, $4 status name.
+const SHARE_FILTER_SQL: &str = r#"
+    s.created_by = $1
+    AND ($2::uuid IS NULL OR s.file_id = $2)
+    AND ($4::text IS NOT NULL OR $3 OR s.is_active = TRUE)
+    AND ($4::text IS NOT NULL OR $3 OR s.expires_at IS NULL OR s.expires_at > NOW())
+    AND ($4::text IS NULL OR $4 = CASE
+        WHEN NOT s.is_active THEN 'revoked'
+        WHEN s.expires_at IS NOT NULL AND s.expires_at <= NOW() THEN 'expired'
+        WHEN s.max_downloads IS NOT NULL AND s.download_count >= s.max_downloads THEN 'exhausted'
+        ELSE 'active'
+    END)
+"#;
+
 #[derive(Serialize)]
 struct ListSharesResponse {
     shares: Vec<ShareResponse>,
@@ -96,6 +175,31 @@ struct SharedFileInfo {
     is_folder: bool,
     can_download: bool,
     password_required: bool,
+    /// `SERVER_NAME`, for the share page's title
+    server_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    branding: Option<Branding>,
+}
+
+/// Operator branding for the share page, present when any of it is configured
+#[derive(Serialize)]
+struct Branding {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    logo_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    accent_color: Option<String>,
+}
+
+impl Branding {
+    fn from_config(config: &Config) -> Option<Self> {
+        if config.branding_logo_url.is_none() && config.branding_accent_color.is_none() {
+            return None;
+        }
+        Some(Self {
+            logo_url: config.branding_logo_url.clone(),
+            accent_color: config.branding_accent_color.clone(),
+        })
+    }
 }
 
 #[derive(Serialize)]
@@ -123,18 +227,6 @@ struct AccessShareQuery {
 // HANDLERS
 // ============================================================================
 
-/// Extract user ID from authorization header
-fn extract_user_id(state: &AppState, headers: &axum::http::HeaderMap) -> Result<Uuid, AppError> {
-    let auth_header = headers
-        .get(axum::http::header::AUTHORIZATION)
-        .and_then(|h| h.to_str().ok())
-        .and_then(|h| h.strip_prefix("Bearer "))
-        .ok_or_else(|| AppError::Unauthorized("Missing authorization".into()))?;
-
-    auth::verify_token(&state.config.jwt_secret, auth_header)
-        .map_err(|_| AppError::Unauthorized("Invalid token".into()))
-}
-
 /// Generate a random share token (URL-safe)
 fn generate_share_token() -> String {
     let bytes: [u8; 24] = rand::random();
@@ -147,29 +239,37 @@ async fn list_shares(
     Query(query): Query<ListSharesQuery>,
     headers: axum::http::HeaderMap,
 ) -> Result<Json<ListSharesResponse>, AppError> {
-    let user_id = extract_user_id(&state, &headers)?;
+    let user_id = extract_user_id(&state, &headers).await?;
     
-    let limit = query.limit.unwrap_or(50);
-    let offset = query.offset.unwrap_or(0);
+    let limit = query.limit.unwrap_or(50).clamp(1, MAX_SHARES_PAGE);
+    let offset = query.offset.unwrap_or(0).max(0);
     let include_expired = query.include_expired.unwrap_or(false);
-    
-    let shares = sqlx::query_as::<_, (Uuid, Uuid, String, String, bool, bool, bool, Option<String>, Option<DateTime<Utc>>, Option<i32>, i32, bool, DateTime<Utc>)>(
-        r#"
-        SELECT s.id, s.file_id, f.path, s.token, s.can_view, s.can_download, s.can_edit,
-               s.password_hash, s.expires_at, s.max_downloads, s.download_count, s.is_active, s.created_at
-        FROM share_links s
-        JOIN files f ON s.file_id = f.id
-        WHERE s.created_by = $1
-          AND ($2::uuid IS NULL OR s.file_id = $2)
-          AND ($3 OR s.is_active = TRUE)
-          AND ($3 OR s.expires_at IS NULL OR s.expires_at > NOW())
-        ORDER BY s.created_at DESC
-        LIMIT $4 OFFSET $5
-        "#
+    let file_id = query.file_id.as_ref().and_then(|id| Uuid::parse_str(id).ok());
+    let status = query.status.map(ShareStatus::as_str);
+    
+    let shares = sqlx::query_as::<_, (Uuid, Uuid, String, String, bool, bool, bool, Option<String>, Option<DateTime<Utc>>, Option<i32>, Option<i64>, i32, bool, DateTime<Utc>)>(
+        &format!(
+            r#"
+            SELECT s.id, s.file_id, f.path, s.token, s.can_view, s.can_download, s.can_edit,
+                   s.password_hash, s.expires_at, s.max_downloads, s.max_bytes_per_sec,
+                   s.download_count, s.is_active, s.created_at
+            FROM share_links s
+            JOIN files f ON s.file_id = f.id
+            WHERE {}
+            ORDER BY
+                CASE WHEN $5 = 'download_count' THEN s.download_count END DESC,
+                CASE WHEN $5 = 'expires_at' THEN s.expires_at END ASC NULLS LAST,
+                s.created_at DESC, s.id
+            LIMIT $6 OFFSET $7
+            "#,
+            SHARE_FILTER_SQL
+        )
     )
     .bind(user_id)
-    .bind(query.file_id.as_ref().and_then(|id| Uuid::parse_str(id).ok()))
+    .bind(file_id)
     .bind(include_expired)
+    .bind(status)
+    .bind(query.sort.as_str())
     .bind(limit)
     .bind(offset)
     .fetch_all(&state.db)
@@ -181,7 +281,7 @@ async fn list_shares(
     
     let share_responses: Vec<ShareResponse> = shares
         .into_iter()
-        .map(|(id, file_id, path, token, can_view, can_download, can_edit, pw_hash, expires_at, max_dl, dl_count, is_active, created_at)| {
+        .map(|(id, file_id, path, token, can_view, can_download, can_edit, pw_hash, expires_at, max_dl, max_rate, dl_count, is_active, created_at)| {
             ShareResponse {
                 id: id.to_string(),
                 file_id: file_id.to_string(),
@@ -194,6 +294,7 @@ async fn list_shares(
                 password_protected: pw_hash.is_some(),
                 expires_at: expires_at.map(|t| t.to_rfc3339()),
                 max_downloads: max_dl,
+                max_bytes_per_sec: max_rate,
                 download_count: dl_count,
                 is_active,
                 created_at: created_at.to_rfc3339(),
@@ -202,18 +303,14 @@ async fn list_shares(
         .collect();
     
     // Get total count
-    let total: (i64,) = sqlx::query_as(
-        r#"
-        SELECT COUNT(*)
-        FROM share_links s
-        WHERE s.created_by = $1
-          AND ($2::uuid IS NULL OR s.file_id = $2)
-          AND ($3 OR s.is_active = TRUE)
-        "#
-    )
+    let total: (i64,) = sqlx::query_as(&format!(
+        "SELECT COUNT(*) FROM share_links s WHERE {}",
+        SHARE_FILTER_SQL
+    ))
     .bind(user_id)
-    .bind(query.file_id.as_ref().and_then(|id| Uuid::parse_str(id).ok()))
+    .bind(file_id)
     .bind(include_expired)
+    .bind(status)
     .fetch_one(&state.db)
     .await?;
     
@@ -227,9 +324,14 @@ async fn list_shares(
 async fn create_share(
     State(state): State<AppState>,
     headers: axum::http::HeaderMap,
+    audit: AuditContext,
     Json(req): Json<CreateShareRequest>,
 ) -> Result<Json<ShareResponse>, AppError> {
-    let user_id = extract_user_id(&state, &headers)?;
+    let user_id = extract_writer_id(&state, &headers).await?;
+    
+    if req.max_bytes_per_sec.is_some_and(|rate| rate <= 0) {
+        return Err(AppError::BadRequest("max_bytes_per_sec must be positive".into()));
+    }
     
     // Validate file ID
     let file_id = if let Ok(uuid) = Uuid::parse_str(&req.file_id) {
@@ -259,41 +361,26 @@ async fn create_share(
             let mut found_id = None;
             let mut seen_dirs = std::collections::HashSet::new();
             
-            for (id, raw_path) in &all_paths {
-                let path = if raw_path.starts_with('/') {
-                    raw_path.clone()
-                } else {
-                    format!("/{}", raw_path)
-                };
-                
+            'search: for (id, path) in &all_paths {
                 // Check the file/folder itself
                 let hash = blake3::hash(path.as_bytes()).to_hex().to_string();
                 if hash == req.file_id {
                     found_id = Some(*id);
                     break;
                 }
-                
+
                 // Check parent directories (for virtual folders)
-                for (i, c) in path.chars().enumerate() {
-                    if c == '/' && i > 0 {
-                        let candidate = &path[0..=i];
-                        let clean_candidate = candidate.replace("//", "/");
-                        
-                        if seen_dirs.contains(&clean_candidate) {
-                            continue;
-                        }
-                        seen_dirs.insert(clean_candidate.clone());
-                        
-                        let dir_hash = blake3::hash(clean_candidate.as_bytes()).to_hex().to_string();
-                        if dir_hash == req.file_id {
-                            // Virtual folder - use any file inside it as the anchor
-                            found_id = Some(*id);
-                            break;
-                        }
+                for candidate in files::dir_prefixes(path) {
+                    if !seen_dirs.insert(candidate) {
+                        continue;
+                    }
+
+                    let dir_hash = blake3::hash(candidate.as_bytes()).to_hex().to_string();
+                    if dir_hash == req.file_id {
+                        // Virtual folder - use any file inside it as the anchor
+                        found_id = Some(*id);
+                        break 'search;
                     }
-                }
-                if found_id.is_some() {
-                    break;
                 }
             }
             
@@ -320,7 +407,7 @@ async fn create_share(
     
     // Hash password if provided
     let password_hash = if let Some(ref pw) = req.password {
-        Some(auth::hash_password(pw)?)
+        Some(auth::hash_password(pw, &state.config.hash_params())?)
     } else {
         None
     };
@@ -333,8 +420,9 @@ async fn create_share(
     sqlx::query(
         r#"
         INSERT INTO share_links (id, file_id, created_by, token, password_hash, 
-                                  can_view, can_download, can_edit, expires_at, max_downloads)
-        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+                                  can_view, can_download, can_edit, expires_at, max_downloads,
+                                  max_bytes_per_sec)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
         "#
     )
     .bind(share_id)
@@ -347,8 +435,10 @@ async fn create_share(
     .bind(req.can_edit.unwrap_or(false))
     .bind(expires_at)
     .bind(req.max_downloads)
+    .bind(req.max_bytes_per_sec)
     .execute(&state.db)
     .await?;
+    audit.record(&state, Some(user_id), AuditAction::ShareCreate, Some(&file_path)).await;
     
     let web_base_url = std::env::var("PUBLIC_WEB_URL").unwrap_or_else(|_| 
         std::env::var("PUBLIC_URL").unwrap_or_else(|_| "http://localhost:3000".to_string())
@@ -366,6 +456,7 @@ async fn create_share(
         password_protected: password_hash.is_some(),
         expires_at: expires_at.map(|t| t.to_rfc3339()),
         max_downloads: req.max_downloads,
+        max_bytes_per_sec: req.max_bytes_per_sec,
         download_count: 0,
         is_active: true,
         created_at: Utc::now().to_rfc3339(),
@@ -378,12 +469,13 @@ async fn get_share(
     Path(share_id): Path<Uuid>,
     headers: axum::http::HeaderMap,
 ) -> Result<Json<ShareResponse>, AppError> {
-    let user_id = extract_user_id(&state, &headers)?;
+    let user_id = extract_user_id(&state, &headers).await?;
     
-    let share = sqlx::query_as::<_, (Uuid, Uuid, String, String, bool, bool, bool, Option<String>, Option<DateTime<Utc>>, Option<i32>, i32, bool, DateTime<Utc>)>(
+    let share = sqlx::query_as::<_, (Uuid, Uuid, String, String, bool, bool, bool, Option<String>, Option<DateTime<Utc>>, Option<i32>, Option<i64>, i32, bool, DateTime<Utc>)>(
         r#"
         SELECT s.id, s.file_id, f.path, s.token, s.can_view, s.can_download, s.can_edit,
-               s.password_hash, s.expires_at, s.max_downloads, s.download_count, s.is_active, s.created_at
+               s.password_hash, s.expires_at, s.max_downloads, s.max_bytes_per_sec,
+               s.download_count, s.is_active, s.created_at
         FROM share_links s
         JOIN files f ON s.file_id = f.id
         WHERE s.id = $1 AND s.created_by = $2
@@ -395,7 +487,7 @@ async fn get_share(
     .await?
     .ok_or_else(|| AppError::NotFound("Share not found".into()))?;
     
-    let (id, file_id, path, token, can_view, can_download, can_edit, pw_hash, expires_at, max_dl, dl_count, is_active, created_at) = share;
+    let (id, file_id, path, token, can_view, can_download, can_edit, pw_hash, expires_at, max_dl, max_rate, dl_count, is_active, created_at) = share;
     let web_base_url = std::env::var("PUBLIC_WEB_URL").unwrap_or_else(|_| 
         std::env::var("PUBLIC_URL").unwrap_or_else(|_| "http://localhost:3000".to_string())
     );
@@ -412,6 +504,7 @@ async fn get_share(
         password_protected: pw_hash.is_some(),
         expires_at: expires_at.map(|t| t.to_rfc3339()),
         max_downloads: max_dl,
+        max_bytes_per_sec: max_rate,
         download_count: dl_count,
         is_active,
         created_at: created_at.to_rfc3339(),
@@ -424,7 +517,7 @@ async fn revoke_share(
     Path(share_id): Path<Uuid>,
     headers: axum::http::HeaderMap,
 ) -> Result<StatusCode, AppError> {
-    let user_id = extract_user_id(&state, &headers)?;
+    let user_id = extract_user_id(&state, &headers).await?;
     
     let result = sqlx::query(
         "UPDATE share_links SET is_active = FALSE WHERE id = $1 AND created_by = $2"
@@ -446,6 +539,7 @@ async fn access_share(
     State(state): State<AppState>,
     Path(token): Path<String>,
     Query(query): Query<AccessShareQuery>,
+    audit: AuditContext,
 ) -> Result<Json<SharedFileInfo>, AppError> {
     // Look up share by token
     let share = sqlx::query_as::<_, (Uuid, bool, bool, Option<String>, Option<DateTime<Utc>>, Option<i32>, i32, bool)>(
@@ -507,6 +601,7 @@ async fn access_share(
     .ok_or_else(|| AppError::NotFound("Shared file not found".into()))?;
     
     let (path, size) = file;
+    audit.record(&state, None, AuditAction::ShareAccess, Some(&path)).await;
     let name = std::path::Path::new(&path)
         .file_name()
         .map(|n| n.to_string_lossy().to_string())
@@ -518,6 +613,8 @@ async fn access_share(
         is_folder: path.ends_with('/'),
         can_download,
         password_required: password_hash.is_some(),
+        server_name: state.config.server_name.clone(),
+        branding: Branding::from_config(&state.config),
     }))
 }
 
@@ -526,12 +623,14 @@ async fn download_shared_file(
     State(state): State<AppState>,
     Path(token): Path<String>,
     Query(query): Query<AccessShareQuery>,
+    headers: axum::http::HeaderMap,
+    audit: AuditContext,
 ) -> Result<axum::response::Response, AppError> {
     // Look up share by token
-    let share = sqlx::query_as::<_, (Uuid, bool, Option<String>, Option<DateTime<Utc>>, Option<i32>, i32, bool)>(
+    let share = sqlx::query_as::<_, (Uuid, bool, Option<String>, Option<DateTime<Utc>>, Option<i32>, i32, bool, Option<i64>)>(
         r#"
         SELECT s.file_id, s.can_download, s.password_hash, 
-               s.expires_at, s.max_downloads, s.download_count, s.is_active
+               s.expires_at, s.max_downloads, s.download_count, s.is_active, s.max_bytes_per_sec
         FROM share_links s
         WHERE s.token = $1
         "#
@@ -541,7 +640,8 @@ async fn download_shared_file(
     .await?
     .ok_or_else(|| AppError::NotFound("Share link not found".into()))?;
     
-    let (file_id, can_download, password_hash, expires_at, max_downloads, download_count, is_active) = share;
+    let (file_id, can_download, password_hash, expires_at, max_downloads, download_count, is_active, max_bytes_per_sec) = share;
+    let rate_limit = max_bytes_per_sec.map(|rate| rate as u64);
     
     // Validate share access
     if !is_active {
@@ -588,12 +688,18 @@ async fn download_shared_file(
     .ok_or_else(|| AppError::NotFound("File not found".into()))?;
     
     let (path, version_id) = file;
+    audit.record(&state, None, AuditAction::ShareAccess, Some(&path)).await;
     let version_id = version_id.ok_or_else(|| AppError::NotFound("File has no version".into()))?;
     
     // Get version details
     let version = versions::get_version_ext(&state.db, version_id)
         .await?
         .ok_or_else(|| AppError::NotFound("Version not found".into()))?;
+
+    // A cached copy that is still current costs no download
+    if is_not_modified(&headers, &version_etag(version.id), version.created_at) {
+        return Ok(not_modified_response(&version));
+    }
     
     // Increment download counter
     sqlx::query("UPDATE share_links SET download_count = download_count + 1, last_accessed_at = NOW() WHERE token = $1")
@@ -631,39 +737,18 @@ async fn download_shared_file(
         
         let stream = async_stream::stream! {
             for (_vc, chunk) in chunk_list {
-                match chunk.location() {
-                    ChunkLocation::Container { container_id, offset, length } => {
-                        let is_compressed = length < chunk.size_bytes;
-                        let location = blob_io::ChunkLocation {
-                            container_id,
-                            offset: offset as u64,
-                            length: length as u32,
-                            compressed: is_compressed,
-                        };
-                        match blob_manager.read_chunk(&location).await {
-                            Ok(data) => yield Ok::<_, std::io::Error>(axum::body::Bytes::from(data)),
-                            Err(e) => {
-                                tracing::error!("Failed to read chunk from container: {}", e);
-                                yield Err(std::io::Error::new(std::io::ErrorKind::Other, e.to_string()));
-                                return;
-                            }
-                        }
-                    },
-                    ChunkLocation::Standalone { hash } => {
-                        match blob_manager.read_legacy_blob(&hash) {
-                            Ok(data) => yield Ok::<_, std::io::Error>(axum::body::Bytes::from(data)),
-                            Err(e) => {
-                                tracing::error!("Failed to read standalone chunk {}: {}", hash, e);
-                                yield Err(std::io::Error::new(std::io::ErrorKind::Other, e.to_string()));
-                                return;
-                            }
-                        }
+                match blob_manager.read_chunk(&chunk).await {
+                    Ok(data) => yield Ok::<_, std::io::Error>(axum::body::Bytes::from(data)),
+                    Err(e) => {
+                        tracing::error!("Failed to read chunk {}: {}", chunk.hash, e);
+                        yield Err(std::io::Error::new(std::io::ErrorKind::Other, e.to_string()));
+                        return;
                     }
                 }
             }
         };
         
-        let body = Body::from_stream(stream);
+        let body = throttle::limited_stream_body(stream, rate_limit);
         let response = axum::response::Response::builder()
             .status(StatusCode::OK)
             .header(header::CONTENT_TYPE, &content_type[..])
@@ -672,6 +757,8 @@ async fn download_shared_file(
                 header::CONTENT_DISPOSITION,
                 format!("attachment; filename=\"{}\"", safe_filename),
             )
+            .header(header::ETAG, version_etag(version.id))
+            .header(header::LAST_MODIFIED, http_date(version.created_at))
             .body(body)
             .map_err(|e| AppError::Internal(format!("Failed to build response: {}", e)))?;
         
@@ -680,9 +767,9 @@ async fn download_shared_file(
         // Legacy/Unchunked file - serve the single blob
         let blob_hash = version.content_hash();
         
-        let content = state.blob_manager.read_legacy_blob(blob_hash)?;
+        let content = state.blob_manager.read_legacy_blob(blob_hash).await?;
         
-        let body = Body::from(content);
+        let body = throttle::limited_body(content, rate_limit);
         let response = axum::response::Response::builder()
             .status(StatusCode::OK)
             .header(header::CONTENT_TYPE, &content_type[..])
@@ -691,6 +778,8 @@ async fn download_shared_file(
                 header::CONTENT_DISPOSITION,
                 format!("attachment; filename=\"{}\"", safe_filename),
             )
+            .header(header::ETAG, version_etag(version.id))
+            .header(header::LAST_MODIFIED, http_date(version.created_at))
             .body(body)
             .map_err(|e| AppError::Internal(format!("Failed to build response: {}", e)))?;
         
@@ -704,6 +793,7 @@ async fn list_shared_folder_contents(
     State(state): State<AppState>,
     Path(token): Path<String>,
     Query(query): Query<AccessShareQuery>,
+    audit: AuditContext,
 ) -> Result<Json<SharedFolderContentsResponse>, AppError> {
     // Look up share by token
     let share = sqlx::query_as::<_, (Uuid, bool, Option<String>, Option<DateTime<Utc>>, Option<i32>, i32, bool)>(
@@ -758,6 +848,7 @@ async fn list_shared_folder_contents(
     .ok_or_else(|| AppError::NotFound("Shared folder not found".into()))?;
     
     let root_folder_path = folder.0;
+    audit.record(&state, None, AuditAction::ShareAccess, Some(&root_folder_path)).await;
     
     // Ensure it's a folder
     if !root_folder_path.ends_with('/') {
@@ -848,12 +939,14 @@ async fn download_shared_file_by_path(
     State(state): State<AppState>,
     Path((token, file_path)): Path<(String, String)>,
     Query(query): Query<AccessShareQuery>,
+    headers: axum::http::HeaderMap,
+    audit: AuditContext,
 ) -> Result<axum::response::Response, AppError> {
     // Look up share by token
-    let share = sqlx::query_as::<_, (Uuid, bool, Option<String>, Option<DateTime<Utc>>, Option<i32>, i32, bool)>(
+    let share = sqlx::query_as::<_, (Uuid, bool, Option<String>, Option<DateTime<Utc>>, Option<i32>, i32, bool, Option<i64>)>(
         r#"
         SELECT s.file_id, s.can_download, s.password_hash, 
-               s.expires_at, s.max_downloads, s.download_count, s.is_active
+               s.expires_at, s.max_downloads, s.download_count, s.is_active, s.max_bytes_per_sec
         FROM share_links s
         WHERE s.token = $1
         "#
@@ -863,7 +956,8 @@ async fn download_shared_file_by_path(
     .await?
     .ok_or_else(|| AppError::NotFound("Share link not found".into()))?;
     
-    let (folder_id, can_download, password_hash, expires_at, max_downloads, download_count, is_active) = share;
+    let (folder_id, can_download, password_hash, expires_at, max_downloads, download_count, is_active, max_bytes_per_sec) = share;
+    let rate_limit = max_bytes_per_sec.map(|rate| rate as u64);
     
     // Validate share access
     if !is_active {
@@ -944,12 +1038,18 @@ async fn download_shared_file_by_path(
     })?;
     
     let (_file_id, path, version_id) = file;
+    audit.record(&state, None, AuditAction::ShareAccess, Some(&path)).await;
     let version_id = version_id.ok_or_else(|| AppError::NotFound("File has no version".into()))?;
     
     // Get version details
     let version = versions::get_version_ext(&state.db, version_id)
         .await?
         .ok_or_else(|| AppError::NotFound("Version not found".into()))?;
+
+    // A cached copy that is still current costs no download
+    if is_not_modified(&headers, &version_etag(version.id), version.created_at) {
+        return Ok(not_modified_response(&version));
+    }
     
     // Increment download counter
     sqlx::query("UPDATE share_links SET download_count = download_count + 1, last_accessed_at = NOW() WHERE token = $1")
@@ -987,39 +1087,18 @@ async fn download_shared_file_by_path(
         
         let stream = async_stream::stream! {
             for (_vc, chunk) in chunk_list {
-                match chunk.location() {
-                    ChunkLocation::Container { container_id, offset, length } => {
-                        let is_compressed = length < chunk.size_bytes;
-                        let location = blob_io::ChunkLocation {
-                            container_id,
-                            offset: offset as u64,
-                            length: length as u32,
-                            compressed: is_compressed,
-                        };
-                        match blob_manager.read_chunk(&location).await {
-                            Ok(data) => yield Ok::<_, std::io::Error>(axum::body::Bytes::from(data)),
-                            Err(e) => {
-                                tracing::error!("Failed to read chunk from container: {}", e);
-                                yield Err(std::io::Error::new(std::io::ErrorKind::Other, e.to_string()));
-                                return;
-                            }
-                        }
-                    },
-                    ChunkLocation::Standalone { hash } => {
-                        match blob_manager.read_legacy_blob(&hash) {
-                            Ok(data) => yield Ok::<_, std::io::Error>(axum::body::Bytes::from(data)),
-                            Err(e) => {
-                                tracing::error!("Failed to read standalone chunk {}: {}", hash, e);
-                                yield Err(std::io::Error::new(std::io::ErrorKind::Other, e.to_string()));
-                                return;
-                            }
-                        }
+                match blob_manager.read_chunk(&chunk).await {
+                    Ok(data) => yield Ok::<_, std::io::Error>(axum::body::Bytes::from(data)),
+                    Err(e) => {
+                        tracing::error!("Failed to read chunk {}: {}", chunk.hash, e);
+                        yield Err(std::io::Error::new(std::io::ErrorKind::Other, e.to_string()));
+                        return;
                     }
                 }
             }
         };
         
-        let body = Body::from_stream(stream);
+        let body = throttle::limited_stream_body(stream, rate_limit);
         let response = axum::response::Response::builder()
             .status(StatusCode::OK)
             .header(header::CONTENT_TYPE, &content_type[..])
@@ -1028,6 +1107,8 @@ async fn download_shared_file_by_path(
                 header::CONTENT_DISPOSITION,
                 format!("attachment; filename=\"{}\"", safe_filename),
             )
+            .header(header::ETAG, version_etag(version.id))
+            .header(header::LAST_MODIFIED, http_date(version.created_at))
             .body(body)
             .map_err(|e| AppError::Internal(format!("Failed to build response: {}", e)))?;
         
@@ -1036,9 +1117,9 @@ async fn download_shared_file_by_path(
         // Legacy/Unchunked file - serve the single blob
         let blob_hash = version.content_hash();
         
-        let content = state.blob_manager.read_legacy_blob(blob_hash)?;
+        let content = state.blob_manager.read_legacy_blob(blob_hash).await?;
         
-        let body = Body::from(content);
+        let body = throttle::limited_body(content, rate_limit);
         let response = axum::response::Response::builder()
             .status(StatusCode::OK)
             .header(header::CONTENT_TYPE, &content_type[..])
@@ -1047,6 +1128,8 @@ async fn download_shared_file_by_path(
                 header::CONTENT_DISPOSITION,
                 format!("attachment; filename=\"{}\"", safe_filename),
             )
+            .header(header::ETAG, version_etag(version.id))
+            .header(header::LAST_MODIFIED, http_date(version.created_at))
             .body(body)
             .map_err(|e| AppError::Internal(format!("Failed to build response: {}", e)))?;
         
@@ -1060,12 +1143,13 @@ async fn download_shared_folder_as_zip(
     State(state): State<AppState>,
     Path(token): Path<String>,
     Query(query): Query<AccessShareQuery>,
+    audit: AuditContext,
 ) -> Result<axum::response::Response, AppError> {
     // 1. Look up share by token
-    let share = sqlx::query_as::<_, (Uuid, bool, Option<String>, Option<DateTime<Utc>>, Option<i32>, i32, bool)>(
+    let share = sqlx::query_as::<_, (Uuid, bool, Option<String>, Option<DateTime<Utc>>, Option<i32>, i32, bool, Option<i64>)>(
         r#"
         SELECT s.file_id, s.can_download, s.password_hash, 
-               s.expires_at, s.max_downloads, s.download_count, s.is_active
+               s.expires_at, s.max_downloads, s.download_count, s.is_active, s.max_bytes_per_sec
         FROM share_links s
         WHERE s.token = $1
         "#
@@ -1075,7 +1159,8 @@ async fn download_shared_folder_as_zip(
     .await?
     .ok_or_else(|| AppError::NotFound("Share link not found".into()))?;
     
-    let (file_id, can_download, password_hash, expires_at, max_downloads, download_count, is_active) = share;
+    let (file_id, can_download, password_hash, expires_at, max_downloads, download_count, is_active, max_bytes_per_sec) = share;
+    let rate_limit = max_bytes_per_sec.map(|rate| rate as u64);
     
     // 2. Validate share access
     if !is_active {
@@ -1127,6 +1212,7 @@ async fn download_shared_folder_as_zip(
     if !folder_path.ends_with('/') {
         return Err(AppError::BadRequest("This share is not a folder".into()));
     }
+    audit.record(&state, None, AuditAction::ShareAccess, Some(&folder_path)).await;
     
     // 4. Get folder name for zip filename
     let folder_name = folder_path
@@ -1146,12 +1232,14 @@ async fn download_shared_folder_as_zip(
     };
     
     // 5. Get all files under this folder (no owner check for public share)
-    let all_files: Vec<crate::db::files::File> = sqlx::query_as(
+    let all_files: Vec<crate::db::files::FileWithVersion> = sqlx::query_as(
         r#"
-        SELECT id, path, current_version_id, is_deleted, created_at, updated_at, owner_id, original_hash_id
-        FROM files
-        WHERE path LIKE $1 AND is_deleted = FALSE
-        ORDER BY path
+        SELECT f.id, f.path, f.current_version_id, f.is_deleted, f.created_at, f.updated_at,
+               v.size_bytes, v.blob_hash, f.original_hash_id, f.owner_id, f.link_target_id, f.unix_mode
+        FROM files f
+        LEFT JOIN versions v ON f.current_version_id = v.id
+        WHERE f.path LIKE $1 AND f.is_deleted = FALSE
+        ORDER BY f.path
         "#
     )
     .bind(format!("{}%", folder_path))
@@ -1165,104 +1253,14 @@ async fn download_shared_folder_as_zip(
     
     tracing::info!("Creating shared ZIP archive for {} with {} files", folder_path, all_files.len());
     
-    // 5. Build the ZIP in memory
-    let mut zip_buffer = std::io::Cursor::new(Vec::new());
-    {
-        let mut zip = zip::ZipWriter::new(&mut zip_buffer);
-        let options = zip::write::SimpleFileOptions::default()
-            .compression_method(zip::CompressionMethod::Deflated);
-        
-        for f in &all_files {
-            // Skip folders (virtual)
-            if f.path.ends_with('/') {
-                continue;
-            }
-            
-            // Get version for this file
-            let version_id = match f.current_version_id {
-                Some(id) => id,
-                None => continue,
-            };
-            
-            let version = match versions::get_version_ext(&state.db, version_id).await? {
-                Some(v) => v,
-                None => continue,
-            };
-            
-            // Calculate relative path within the zip
-            let relative_path = f.path.strip_prefix(&folder_path).unwrap_or(&f.path);
-            
-            // Read file content
-            let content = if version.is_chunked {
-                let chunk_list = chunks::get_version_chunks_with_location(&state.db, version.id).await?;
-                let mut file_data = Vec::with_capacity(version.size_bytes as usize);
-                
-                for (_vc, chunk) in chunk_list {
-                    match chunk.location() {
-                        ChunkLocation::Container { container_id, offset, length } => {
-                            let is_compressed = length < chunk.size_bytes;
-                            let location = blob_io::ChunkLocation {
-                                container_id,
-                                offset: offset as u64,
-                                length: length as u32,
-                                compressed: is_compressed,
-                            };
-                            match state.blob_manager.read_chunk(&location).await {
-                                Ok(data) => file_data.extend(data),
-                                Err(e) => {
-                                    tracing::warn!("Failed to read chunk for {}: {}", f.path, e);
-                                    continue;
-                                }
-                            }
-                        },
-                        ChunkLocation::Standalone { hash } => {
-                            match state.blob_manager.read_legacy_blob(&hash) {
-                                Ok(data) => file_data.extend(data),
-                                Err(e) => {
-                                    tracing::warn!("Failed to read legacy chunk for {}: {}", f.path, e);
-                                    continue;
-                                }
-                            }
-                        }
-                    }
-                }
-                file_data
-            } else {
-                // Legacy blob
-                let blob_hash = version.content_hash();
-                match state.blob_manager.read_legacy_blob(blob_hash) {
-                    Ok(data) => data,
-                    Err(e) => {
-                        tracing::warn!("Failed to read blob for {}: {}", f.path, e);
-                        continue;
-                    }
-                }
-            };
-            
-            // Add file to zip
-            if let Err(e) = zip.start_file(relative_path, options) {
-                tracing::warn!("Failed to start zip entry for {}: {}", relative_path, e);
-                continue;
-            }
-            if let Err(e) = std::io::Write::write_all(&mut zip, &content) {
-                tracing::warn!("Failed to write zip entry for {}: {}", relative_path, e);
-                continue;
-            }
-        }
-        
-        zip.finish().map_err(|e| AppError::Internal(format!("Failed to finalize zip: {}", e)))?;
-    }
-    
-    let zip_data = zip_buffer.into_inner();
-    let zip_size = zip_data.len();
-    
-    tracing::info!("Shared ZIP archive created: {} bytes", zip_size);
-    
-    let body = Body::from(zip_data);
+    // Stream the archive as it is built; its size is unknown up front
+    let body = throttle::limited_stream_body(
+        zip_stream::zip_stream(&state, folder_path, all_files),
+        rate_limit,
+    );
     let response = axum::response::Response::builder()
         .status(StatusCode::OK)
         .header(header::CONTENT_TYPE, "application/zip")
-        .header(header::CONTENT_LENGTH, zip_size.to_string())
         .header(
             header::CONTENT_DISPOSITION,
             format!("attachment; filename=\"{}\"", zip_filename),
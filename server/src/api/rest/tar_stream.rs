@@ -0,0 +1,347 @@
+//! Streaming gzip-compressed tar archives for folder downloads
+//!
+//! Unlike ZIP, a tar member's header is complete before its data, so the
+//! archive is written strictly front to back: no spooling, just a gzip
+//! encoder over the channel backing the response body. Each entry carries the
+//! file's recorded `unix_mode` (0644 for files and 0755 for directories when
+//! unset) and its modification time.
+//!
+//! Headers are ustar; paths longer than 100 bytes and members of 8GB or more
+//! get a PAX extended header in front.
+
+use super::zip_stream::{Member, CHANNEL_DEPTH, PIECE_BYTES};
+use crate::api::AppState;
+use crate::db::files::FileWithVersion;
+use axum::body::Bytes;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::{self, Write};
+use tokio::runtime::Handle;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::Stream;
+
+const BLOCK: usize = 512;
+
+const DEFAULT_FILE_MODE: u32 = 0o644;
+const DEFAULT_DIR_MODE: u32 = 0o755;
+
+/// Largest size the 11-digit octal ustar field can hold
+const MAX_USTAR_SIZE: u64 = 0o77777777777;
+
+const TYPE_FILE: u8 = b'0';
+const TYPE_DIR: u8 = b'5';
+const TYPE_PAX: u8 = b'x';
+
+/// Stream a tar.gz of `files`, with entry names relative to `folder_path`.
+///
+/// Files without a current version are skipped. A read failure mid-archive
+/// ends the stream with an error rather than emitting a truncated member.
+pub fn tar_stream(
+    state: &AppState,
+    folder_path: String,
+    files: Vec<FileWithVersion>,
+) -> impl Stream<Item = io::Result<Bytes>> {
+    let (tx, rx) = mpsc::channel(CHANNEL_DEPTH);
+    let state = state.clone();
+    let handle = Handle::current();
+
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = write_archive(&handle, &state, &folder_path, &files, tx.clone()) {
+            tracing::warn!("tar archive for {} aborted: {}", folder_path, e);
+            let _ = tx.blocking_send(Err(io::Error::other(e.to_string())));
+        }
+    });
+
+    ReceiverStream::new(rx)
+}
+
+fn write_archive(
+    handle: &Handle,
+    state: &AppState,
+    folder_path: &str,
+    files: &[FileWithVersion],
+    tx: mpsc::Sender<io::Result<Bytes>>,
+) -> anyhow::Result<()> {
+    let mut tar = GzEncoder::new(ChannelWriter::new(tx), Compression::default());
+
+    let mut entries = 0usize;
+    for file in files {
+        let relative_path = file.path.strip_prefix(folder_path).unwrap_or(&file.path);
+        // The folder's own record has nothing left to name
+        if relative_path.is_empty() {
+            continue;
+        }
+        let mode = file.unix_mode.map(|m| m as u32);
+        let mtime = file.updated_at.timestamp().max(0) as u64;
+
+        if relative_path.ends_with('/') {
+            let entry = Entry {
+                path: relative_path,
+                mode: mode.unwrap_or(DEFAULT_DIR_MODE),
+                size: 0,
+                mtime,
+                kind: TYPE_DIR,
+            };
+            entry.write_header(&mut tar)?;
+            entries += 1;
+            continue;
+        }
+
+        let Some(member) = Member::lookup(handle, state, file)? else {
+            continue;
+        };
+        let entry = Entry {
+            path: relative_path,
+            mode: mode.unwrap_or(DEFAULT_FILE_MODE),
+            size: member.size,
+            mtime,
+            kind: TYPE_FILE,
+        };
+        entry.write_header(&mut tar)?;
+
+        let mut counted = CountingWriter { inner: &mut tar, count: 0 };
+        member.write_to(handle, state, &mut counted)?;
+        if counted.count != entry.size {
+            anyhow::bail!(
+                "{} has {} bytes of content but its version records {}",
+                file.path,
+                counted.count,
+                entry.size
+            );
+        }
+        tar.write_all(&[0u8; BLOCK][..padding(entry.size)])?;
+        entries += 1;
+    }
+
+    // End-of-archive marker
+    tar.write_all(&[0u8; 2 * BLOCK])?;
+    tar.finish()?.flush()?;
+    tracing::info!("tar archive for {} streamed with {} entries", folder_path, entries);
+    Ok(())
+}
+
+/// Zero bytes that pad `size` bytes of member data to a whole block
+fn padding(size: u64) -> usize {
+    (BLOCK - (size % BLOCK as u64) as usize) % BLOCK
+}
+
+struct Entry<'a> {
+    path: &'a str,
+    mode: u32,
+    size: u64,
+    mtime: u64,
+    kind: u8,
+}
+
+impl Entry<'_> {
+    /// Write the header block(s), preceded by a PAX header if a field
+    /// doesn't fit ustar
+    fn write_header(&self, out: &mut impl Write) -> io::Result<()> {
+        let mut records = Vec::new();
+        if self.path.len() > 100 {
+            records.extend(pax_record("path", self.path));
+        }
+        if self.size > MAX_USTAR_SIZE {
+            records.extend(pax_record("size", &self.size.to_string()));
+        }
+        if !records.is_empty() {
+            let pax_name = format!("PaxHeaders/{}", truncate(self.path, 89));
+            out.write_all(&ustar_header(&pax_name, 0o644, records.len() as u64, self.mtime, TYPE_PAX))?;
+            out.write_all(&records)?;
+            out.write_all(&[0u8; BLOCK][..padding(records.len() as u64)])?;
+        }
+        out.write_all(&ustar_header(
+            truncate(self.path, 100),
+            self.mode,
+            self.size.min(MAX_USTAR_SIZE),
+            self.mtime,
+            self.kind,
+        ))
+    }
+}
+
+/// A ustar header block. `name` must fit in 100 bytes and `size` in 11
+/// octal digits; anything longer goes in a PAX header.
+fn ustar_header(name: &str, mode: u32, size: u64, mtime: u64, kind: u8) -> [u8; BLOCK] {
+    let mut block = [0u8; BLOCK];
+    block[..name.len()].copy_from_slice(name.as_bytes());
+    octal(&mut block[100..108], (mode & 0o7777) as u64);
+    octal(&mut block[108..116], 0); // uid
+    octal(&mut block[116..124], 0); // gid
+    octal(&mut block[124..136], size);
+    octal(&mut block[136..148], mtime.min(MAX_USTAR_SIZE));
+    block[156] = kind;
+    block[257..263].copy_from_slice(b"ustar\0");
+    block[263..265].copy_from_slice(b"00");
+
+    // Checksum is computed with its own field read as spaces
+    block[148..156].fill(b' ');
+    let checksum: u32 = block.iter().map(|&b| b as u32).sum();
+    octal(&mut block[148..155], checksum as u64);
+    block
+}
+
+/// Zero-padded octal digits followed by a NUL, filling `field`
+fn octal(field: &mut [u8], value: u64) {
+    let digits = field.len() - 1;
+    let text = format!("{:0width$o}", value, width = digits);
+    field[..digits].copy_from_slice(&text.as_bytes()[text.len() - digits..]);
+    field[digits] = 0;
+}
+
+/// One `"<len> <key>=<value>\n"` record, where `len` counts itself
+fn pax_record(key: &str, value: &str) -> Vec<u8> {
+    let body = key.len() + value.len() + 3; // space, '=', newline
+    let mut len = body + 1;
+    while body + len.to_string().len() != len {
+        len = body + len.to_string().len();
+    }
+    format!("{} {}={}\n", len, key, value).into_bytes()
+}
+
+/// Longest prefix of `s` within `max` bytes, cut on a char boundary
+fn truncate(s: &str, max: usize) -> &str {
+    if s.len() <= max {
+        return s;
+    }
+    let mut end = max;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+/// Counts bytes passed through to `inner`
+struct CountingWriter<W> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Sink that sends compressed output to the channel in pieces of
+/// [`PIECE_BYTES`], plus whatever is left on `flush`
+struct ChannelWriter {
+    buf: Vec<u8>,
+    tx: mpsc::Sender<io::Result<Bytes>>,
+}
+
+impl ChannelWriter {
+    fn new(tx: mpsc::Sender<io::Result<Bytes>>) -> Self {
+        ChannelWriter { buf: Vec::with_capacity(PIECE_BYTES), tx }
+    }
+
+    fn send(&mut self) -> io::Result<()> {
+        let piece = std::mem::replace(&mut self.buf, Vec::with_capacity(PIECE_BYTES));
+        self.tx
+            .blocking_send(Ok(Bytes::from(piece)))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "client disconnected"))
+    }
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        if self.buf.len() >= PIECE_BYTES {
+            self.send()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        self.send()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    fn field(block: &[u8], range: std::ops::Range<usize>) -> u64 {
+        let text = std::str::from_utf8(&block[range]).unwrap();
+        u64::from_str_radix(text.trim_matches(|c| c == '\0' || c == ' '), 8).unwrap()
+    }
+
+    #[test]
+    fn test_ustar_header_fields() {
+        let block = ustar_header("bin/run.sh", 0o755, 1234, 1_700_000_000, TYPE_FILE);
+        assert_eq!(&block[..10], b"bin/run.sh");
+        assert_eq!(field(&block, 100..108), 0o755);
+        assert_eq!(field(&block, 124..136), 1234);
+        assert_eq!(field(&block, 136..148), 1_700_000_000);
+        assert_eq!((&block[257..263], &block[263..265]), (&b"ustar\0"[..], &b"00"[..]));
+
+        let mut unsummed = block;
+        unsummed[148..156].fill(b' ');
+        let sum: u64 = unsummed.iter().map(|&b| b as u64).sum();
+        assert_eq!(field(&block, 148..156), sum);
+    }
+
+    #[test]
+    fn test_pax_record_length_counts_itself() {
+        assert_eq!(pax_record("path", "a"), b"9 path=a\n");
+        // 98 bytes plus a 2-digit length would be 100, which needs 3 digits
+        let value = "x".repeat(91);
+        let record = pax_record("path", &value);
+        assert_eq!(record.len(), 101);
+        assert!(record.starts_with(b"101 path="));
+    }
+
+    #[test]
+    fn test_gzip_archive_round_trip() {
+        let (tx, mut rx) = mpsc::channel(1024);
+        let mut tar = GzEncoder::new(ChannelWriter::new(tx), Compression::default());
+        let long_name = format!("{}/file.txt", "d".repeat(120));
+        for (path, data, kind) in [
+            ("docs/", &b""[..], TYPE_DIR),
+            ("docs/a.txt", &b"hello"[..], TYPE_FILE),
+            (long_name.as_str(), &b"long"[..], TYPE_FILE),
+        ] {
+            let entry = Entry { path, mode: 0o600, size: data.len() as u64, mtime: 0, kind };
+            entry.write_header(&mut tar).unwrap();
+            tar.write_all(data).unwrap();
+            tar.write_all(&[0u8; BLOCK][..padding(entry.size)]).unwrap();
+        }
+        tar.write_all(&[0u8; 2 * BLOCK]).unwrap();
+        tar.finish().unwrap().flush().unwrap();
+
+        let mut compressed = Vec::new();
+        while let Ok(piece) = rx.try_recv() {
+            compressed.extend_from_slice(&piece.unwrap());
+        }
+        let mut archive = Vec::new();
+        GzDecoder::new(compressed.as_slice()).read_to_end(&mut archive).unwrap();
+        assert_eq!(archive.len() % BLOCK, 0);
+
+        let blocks: Vec<&[u8]> = archive.chunks(BLOCK).collect();
+        assert_eq!(&blocks[0][..5], b"docs/");
+        assert_eq!(blocks[0][156], TYPE_DIR);
+        assert_eq!(&blocks[1][..10], b"docs/a.txt");
+        assert_eq!(field(blocks[1], 100..108), 0o600);
+        assert_eq!(&blocks[2][..5], b"hello");
+        // PAX header, its records, then the real header with a truncated name
+        assert_eq!(blocks[3][156], TYPE_PAX);
+        let records = format!("{} path={}\n", long_name.len() + 10, long_name);
+        assert!(blocks[4].starts_with(records.as_bytes()));
+        assert_eq!(&blocks[5][..100], &long_name.as_bytes()[..100]);
+        assert_eq!(&blocks[6][..4], b"long");
+        assert!(blocks[7..].iter().all(|b| b.iter().all(|&x| x == 0)));
+        assert_eq!(blocks.len(), 9);
+    }
+}
@@ -0,0 +1,133 @@
+//! Download bandwidth throttling
+//!
+//! Share links can carry a `max_bytes_per_sec` limit. The response body
+//! stream is wrapped with a token bucket: each piece sent spends tokens, and
+//! the stream sleeps whenever the bucket runs into debt. The bucket holds at
+//! most one second of tokens, so an idle connection cannot burst beyond that.
+
+use axum::body::{Body, Bytes};
+use std::time::Duration;
+use tokio::time::Instant;
+use tokio_stream::{Stream, StreamExt};
+
+/// Largest slice sent between token checks, so big chunks are paced smoothly
+const MAX_PIECE_BYTES: usize = 64 * 1024;
+
+/// Token bucket measured in bytes
+struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn new(bytes_per_sec: u64, now: Instant) -> Self {
+        let rate = bytes_per_sec.max(1) as f64;
+        TokenBucket { rate, tokens: rate, last: now }
+    }
+
+    /// Spend `n` bytes of tokens, returning how long to wait before sending them
+    fn reserve(&mut self, n: usize, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate) - n as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+/// Limit a byte stream to `bytes_per_sec`
+pub fn throttle<S>(stream: S, bytes_per_sec: u64) -> impl Stream<Item = Result<Bytes, std::io::Error>>
+where
+    S: Stream<Item = Result<Bytes, std::io::Error>>,
+{
+    let piece_len = (bytes_per_sec as usize).clamp(1, MAX_PIECE_BYTES);
+
+    async_stream::stream! {
+        tokio::pin!(stream);
+        let mut bucket = TokenBucket::new(bytes_per_sec, Instant::now());
+
+        while let Some(item) = stream.next().await {
+            let mut data = match item {
+                Ok(data) => data,
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            };
+            while !data.is_empty() {
+                let piece = data.split_to(piece_len.min(data.len()));
+                let wait = bucket.reserve(piece.len(), Instant::now());
+                if !wait.is_zero() {
+                    tokio::time::sleep(wait).await;
+                }
+                yield Ok(piece);
+            }
+        }
+    }
+}
+
+/// Response body for `stream`, throttled when a limit is set
+pub fn limited_stream_body<S>(stream: S, bytes_per_sec: Option<u64>) -> Body
+where
+    S: Stream<Item = Result<Bytes, std::io::Error>> + Send + 'static,
+{
+    match bytes_per_sec {
+        Some(limit) => Body::from_stream(throttle(stream, limit)),
+        None => Body::from_stream(stream),
+    }
+}
+
+/// Response body for an in-memory payload, throttled when a limit is set
+pub fn limited_body(data: Vec<u8>, bytes_per_sec: Option<u64>) -> Body {
+    match bytes_per_sec {
+        Some(limit) => Body::from_stream(throttle(
+            tokio_stream::once(Ok(Bytes::from(data))),
+            limit,
+        )),
+        None => Body::from(data),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_allows_one_second_burst() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(1000, start);
+        assert_eq!(bucket.reserve(1000, start), Duration::ZERO);
+        assert_eq!(bucket.reserve(500, start), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_bucket_refills_but_caps_idle_credit() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(1000, start);
+        bucket.reserve(1000, start);
+        assert_eq!(bucket.reserve(1000, start + Duration::from_secs(1)), Duration::ZERO);
+        // A long idle period still only earns one second of tokens
+        let later = start + Duration::from_secs(60);
+        assert_eq!(bucket.reserve(2000, later), Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_throttle_paces_stream() {
+        let payload = Bytes::from(vec![7u8; 30_000]);
+        let stream = tokio_stream::once(Ok::<_, std::io::Error>(payload));
+
+        let start = Instant::now();
+        let pieces: Vec<Bytes> = throttle(stream, 20_000)
+            .map(|r| r.unwrap())
+            .collect()
+            .await;
+
+        assert_eq!(pieces.iter().map(|p| p.len()).collect::<Vec<_>>(), vec![20_000, 10_000]);
+        // The first 20KB is burst credit; the remaining 10KB waits half a second
+        assert!(start.elapsed() >= Duration::from_millis(450));
+    }
+}
@@ -0,0 +1,82 @@
+//! Per-request deadlines
+//!
+//! A request that hasn't been read and answered within its deadline is
+//! dropped with `408`, so a client trickling a body (or never finishing it)
+//! can't hold a worker and a database connection indefinitely. Uploads and
+//! downloads ([`is_binary_route`]) get `TRANSFER_TIMEOUT_SECS`, everything
+//! else `REQUEST_TIMEOUT_SECS`. The deadline covers the handler up to its
+//! response headers; a streamed download body is not cut off once it starts.
+//! Upload finalization runs on its own task (see [`crate::api::inflight`]),
+//! so it still completes if the request times out around it.
+
+use super::compression::is_binary_route;
+use axum::extract::{Request, State};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug)]
+pub struct RequestTimeouts {
+    /// None = no limit
+    pub json: Option<Duration>,
+    pub transfer: Option<Duration>,
+}
+
+impl RequestTimeouts {
+    /// Deadlines from seconds, where 0 means no limit
+    pub fn from_secs(json: u64, transfer: u64) -> Self {
+        let limit = |secs| (secs > 0).then(|| Duration::from_secs(secs));
+        Self {
+            json: limit(json),
+            transfer: limit(transfer),
+        }
+    }
+
+    fn for_path(&self, path: &str) -> Option<Duration> {
+        if is_binary_route(path) {
+            self.transfer
+        } else {
+            self.json
+        }
+    }
+}
+
+/// Answer `408` if the request outlives its deadline
+pub async fn enforce_deadline(State(timeouts): State<RequestTimeouts>, req: Request, next: Next) -> Response {
+    let Some(deadline) = timeouts.for_path(req.uri().path()) else {
+        return next.run(req).await;
+    };
+    let path = req.uri().path().to_string();
+    match tokio::time::timeout(deadline, next.run(req)).await {
+        Ok(response) => response,
+        Err(_) => {
+            tracing::warn!("Request to {} timed out after {}s", path, deadline.as_secs());
+            (
+                StatusCode::REQUEST_TIMEOUT,
+                Json(serde_json::json!({ "error": "Request timed out" })),
+            )
+                .into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deadline_by_route() {
+        let timeouts = RequestTimeouts::from_secs(30, 600);
+        assert_eq!(timeouts.for_path("/v1/files"), Some(Duration::from_secs(30)));
+        assert_eq!(timeouts.for_path("/v1/chunks/check"), Some(Duration::from_secs(30)));
+        assert_eq!(timeouts.for_path("/v1/chunks/abc123"), Some(Duration::from_secs(600)));
+        assert_eq!(timeouts.for_path("/v1/files/upload"), Some(Duration::from_secs(600)));
+        assert_eq!(timeouts.for_path("/files/42/download"), Some(Duration::from_secs(600)));
+
+        let unlimited = RequestTimeouts::from_secs(0, 0);
+        assert_eq!(unlimited.for_path("/v1/files"), None);
+        assert_eq!(unlimited.for_path("/v1/chunks/abc123"), None);
+    }
+}
@@ -0,0 +1,109 @@
+//! Native TLS (`TLS_CERT_PATH` / `TLS_KEY_PATH`)
+//!
+//! With both set the REST port serves HTTPS only, negotiating HTTP/2 or
+//! HTTP/1.1 over ALPN, so a small deployment needs no reverse proxy. The
+//! certificate file holds the PEM chain (leaf first), the key file a PEM
+//! PKCS#8, PKCS#1 or SEC1 private key.
+//!
+//! SIGHUP re-reads both files, so a renewed certificate is picked up without
+//! a restart: new connections get it, open ones keep the one they started
+//! with. A reload that fails (unreadable file, key not matching the
+//! certificate, renewal caught half-written) is logged and the certificate
+//! already loaded stays in use.
+
+use anyhow::Context;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use tokio_rustls::rustls::crypto::{aws_lc_rs, CryptoProvider};
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::{ClientHello, ResolvesServerCert};
+use tokio_rustls::rustls::sign::CertifiedKey;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+
+/// The certificate handed to new connections, replaced on reload
+#[derive(Debug)]
+struct ReloadableCert {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    provider: Arc<CryptoProvider>,
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ReloadableCert {
+    fn reload(&self) -> anyhow::Result<()> {
+        let key = load_certified_key(&self.cert_path, &self.key_path, &self.provider)?;
+        *self.current.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(key);
+        Ok(())
+    }
+}
+
+impl ResolvesServerCert for ReloadableCert {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap_or_else(|e| e.into_inner()).clone())
+    }
+}
+
+/// Load the certificate and key and build the acceptor for the REST port,
+/// reloading both on SIGHUP. Offers HTTP/2 over ALPN when `http2` is set.
+pub fn acceptor(cert_path: &Path, key_path: &Path, http2: bool) -> anyhow::Result<TlsAcceptor> {
+    let provider = Arc::new(aws_lc_rs::default_provider());
+    let key = load_certified_key(cert_path, key_path, &provider)?;
+    let cert = Arc::new(ReloadableCert {
+        cert_path: cert_path.to_path_buf(),
+        key_path: key_path.to_path_buf(),
+        provider: provider.clone(),
+        current: RwLock::new(Arc::new(key)),
+    });
+
+    let mut config = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_cert_resolver(cert.clone());
+    config.alpn_protocols = if http2 {
+        vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+    } else {
+        vec![b"http/1.1".to_vec()]
+    };
+
+    #[cfg(unix)]
+    tokio::spawn(reload_on_sighup(cert));
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+#[cfg(unix)]
+async fn reload_on_sighup(cert: Arc<ReloadableCert>) {
+    let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(signal) => signal,
+        Err(e) => {
+            tracing::warn!("Failed to install SIGHUP handler, TLS certificate won't reload: {}", e);
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        match cert.reload() {
+            Ok(()) => tracing::info!("Received SIGHUP, reloaded TLS certificate from {}", cert.cert_path.display()),
+            Err(e) => tracing::error!("Received SIGHUP, keeping the current TLS certificate: {:#}", e),
+        }
+    }
+}
+
+/// Read a PEM certificate chain and private key, checking that they belong together
+fn load_certified_key(cert_path: &Path, key_path: &Path, provider: &CryptoProvider) -> anyhow::Result<CertifiedKey> {
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("TLS_CERT_PATH: can't read certificates from {}", cert_path.display()))?;
+    if certs.is_empty() {
+        anyhow::bail!("TLS_CERT_PATH: no certificates in {}", cert_path.display());
+    }
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .with_context(|| format!("TLS_KEY_PATH: can't read a private key from {}", key_path.display()))?;
+    CertifiedKey::from_der(certs, key, provider).with_context(|| {
+        format!(
+            "TLS_KEY_PATH: {} doesn't fit the certificate in {}",
+            key_path.display(),
+            cert_path.display()
+        )
+    })
+}
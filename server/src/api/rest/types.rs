@@ -2,6 +2,7 @@
 //!
 //! Common request/response structs used across multiple endpoint modules.
 
+use crate::db::files::DirectorySort;
 use serde::{Deserialize, Serialize};
 
 // ============================================================================
@@ -48,18 +49,28 @@ pub struct DirectoryEntryResponse {
     pub updated_at: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub version_id: Option<String>,
+    /// True for alias files created via POST /v1/files/link
+    pub is_link: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub link_target_id: Option<String>,
 }
 
 #[derive(Serialize)]
 pub struct ListDirectoryResponse {
     pub entries: Vec<DirectoryEntryResponse>,
     pub path: String,
+    pub total: usize,
+    pub folder_count: usize,
+    pub file_count: usize,
 }
 
 #[derive(Deserialize)]
 pub struct ListDirectoryQuery {
     #[serde(default)]
     pub path: String,
+    /// `name` (default), `folders_first`, `size` or `modified`
+    #[serde(default)]
+    pub sort: DirectorySort,
 }
 
 // ============================================================================
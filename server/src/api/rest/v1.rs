@@ -2,23 +2,34 @@
 //!
 //! Preferred endpoints for new clients using container-based chunk storage.
 
+use crate::api::ws::SyncNotification;
 use crate::api::AppState;
-use crate::db::{chunks, files, versions, ChunkLocation, ChunkTier};
-use crate::storage::blob_io;
+use crate::db::audit::AuditAction;
+use crate::db::{chunks, files, tags, versions, ChunkTier};
+use crate::storage::chunking::{Chunk, ChunkManifest, StreamChunker, stored_chunk_hashes};
+use crate::storage::store_chunk;
+use crate::storage::tiering::{streaming_tier, DefaultTierStrategy, TierStrategy};
 use axum::{
     body::Body,
-    extract::{Path, Query, State},
+    extract::{multipart::MultipartError, Multipart, Path, Query, State},
     http::{header, StatusCode},
     response::IntoResponse,
     routing::{get, post},
     Json, Router,
 };
+use base64::prelude::{Engine as _, BASE64_STANDARD};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tokio_stream::StreamExt;
 use uuid::Uuid;
 
-use super::error::{extract_user_id, validate_path, AppError};
-use super::types::{DirectoryEntryResponse, ListDirectoryQuery, ListDirectoryResponse};
+use super::audit::AuditContext;
+use super::error::{check_if_match, check_unmodified_since, extract_user_id, extract_writer_id, http_date, is_not_modified, validate_path, validate_unix_mode, validate_version_note, version_etag, AppError};
+use super::types::{DirectoryEntryResponse, FileResponse, ListDirectoryQuery, ListDirectoryResponse};
 use super::chunks::{check_chunks, upload_chunk, download_chunk};
+use super::versions::resolve_version_id;
+use super::tar_stream;
+use super::zip_stream;
 
 // ============================================================================
 // ROUTES
@@ -28,27 +39,59 @@ pub fn v1_routes() -> Router<AppState> {
     Router::new()
         // Chunk deduplication check
         .route("/v1/chunks/check", post(check_chunks))
-        // Chunk upload/download with container storage
-        .route("/v1/chunks/:hash", axum::routing::put(upload_chunk))
+        // Chunk download from container storage
         .route("/v1/chunks/:hash", get(download_chunk))
         // File manifest - finalize upload by linking chunks to a file path
         .route("/v1/files", post(create_v1_file))
         // Directory creation - creates a virtual folder (path ending in /)
         .route("/v1/files/directory", post(create_directory_v1))
+        // Give a virtual folder a real record, keeping its path-hash ID
+        .route("/v1/files/materialize", post(materialize_folder_v1))
         // Directory listing with virtual folders (must be before :id to avoid conflicts)
         .route("/v1/files/list", get(list_directory_v1))
+        // One listing entry, looked up by path
+        .route("/v1/files/entry", get(directory_entry_v1))
+        // Recursive folder size and counts
+        .route("/v1/files/stat", get(folder_stat_v1))
+        // One hash over (path, content hash) of every file under a prefix
+        .route("/v1/files/state-hash", get(state_hash_v1))
+        // Case-insensitive substring search on paths
+        .route("/v1/files/search", get(search_files_v1))
+        // Recently deleted files, for a trash view
+        .route("/v1/files/trash", get(list_trash_v1))
         // Changed since - incremental sync (must be before :id to avoid conflicts)
         .route("/v1/files/changes", get(get_file_changes))
+        // Changes as a JSON Lines stream, for trees too large to page through
+        .route("/v1/files/changes/stream", get(stream_file_changes))
         // Folder download as ZIP
         .route("/v1/files/download-zip", get(download_folder_as_zip))
+        .route("/v1/files/download-tar", get(download_folder_as_tar))
+        // Alias files - must be before :id route
+        .route("/v1/files/link", post(create_link_v1))
+        // Move/rename by path - must be before :id route
+        .route("/v1/files/move", post(move_v1))
+        // Soft-delete many files and directories in one request
+        .route("/v1/files/delete-batch", post(delete_batch_v1))
         // File download - stream file content from chunks (must be before :id)
-        .route("/v1/files/:version_id/download", get(download_v1_file))
+        .route("/v1/files/:version_id/download", get(download_v1_file).head(head_v1_file))
+        // Key-value tags, kept by file id across moves
+        .route("/v1/files/:id/tags", get(get_tags_v1).put(put_tags_v1))
+        // Chunk-level diff between two versions of a file
+        .route("/v1/files/:id/diff", get(diff_versions_v1))
         // File metadata lookup by ID
         .route("/v1/files/:id", get(get_file_metadata_v1))
         // WebSocket sync notifications
         .route("/ws/sync", get(crate::api::ws::ws_handler))
 }
 
+/// Chunk upload route, served with the larger upload body limit
+pub fn v1_upload_routes() -> Router<AppState> {
+    Router::new()
+        .route("/v1/chunks/:hash", axum::routing::put(upload_chunk))
+        // Browser upload - multipart form chunked on the server
+        .route("/v1/files/upload", post(upload_multipart_v1))
+}
+
 // ============================================================================
 // TYPES
 // ============================================================================
@@ -62,12 +105,83 @@ struct FileMetadataResponse {
     path: String,
     size_bytes: i64,
     updated_at: String,
+    /// Merkle root of the current version's chunk hashes; equal roots mean equal content
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tree_hash: Option<String>,
+    /// Permission bits last sent with an upload; null if none ever was
+    /// (apply the umask)
+    unix_mode: Option<i32>,
+    /// Base64 encryption metadata of the current version, if its content
+    /// was encrypted by the client
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encryption_meta: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SearchQuery {
+    /// Substring to find anywhere in the path, ignoring case
+    q: Option<String>,
+    /// Only files with this tag, as `key:value` or just `key` for any value
+    tag: Option<String>,
+    /// Max number of results (default 100, at most 1000)
+    limit: Option<i64>,
+    #[serde(default)]
+    include_deleted: bool,
+}
+
+#[derive(Serialize)]
+struct SearchResponse {
+    files: Vec<FileResponse>,
+}
+
+#[derive(Deserialize)]
+struct TrashQuery {
+    /// Max number of entries (default 100, at most 1000)
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct TrashResponse {
+    files: Vec<TrashEntryResponse>,
+    /// Deleted entries in all pages
+    total: i64,
+}
+
+#[derive(Serialize)]
+struct TrashEntryResponse {
+    id: String,
+    path: String,
+    is_directory: bool,
+    size_bytes: Option<i64>,
+    deleted_at: String,
+    /// Set on everything removed by the same directory delete (undo it with
+    /// `POST /files/:id/restore-tree` on the directory); null for files
+    /// deleted on their own
+    deleted_batch_id: Option<String>,
+}
+
+/// Most tags one file can carry
+const MAX_TAGS_PER_FILE: usize = 64;
+const MAX_TAG_KEY_LEN: usize = 128;
+const MAX_TAG_VALUE_LEN: usize = 1024;
+
+/// Limit on a version's encryption metadata, which only needs to hold a
+/// wrapped key, a salt and KDF parameters
+const MAX_ENCRYPTION_META_BYTES: usize = 4096;
+
+/// Body of `PUT /v1/files/:id/tags` and response of both tag routes
+#[derive(Serialize, Deserialize)]
+struct TagsBody {
+    tags: BTreeMap<String, String>,
 }
 
 #[derive(Deserialize)]
 struct ChangesQuery {
-    /// ISO8601 datetime - return files changed after this time
+    /// ISO8601 datetime - return files changed after this time (coarse, for first sync)
     since: Option<String>,
+    /// Opaque cursor from a previous response's `next_cursor` (exact, preferred)
+    after: Option<String>,
     /// Max number of changes to return (default 1000)
     limit: Option<i64>,
 }
@@ -78,6 +192,10 @@ struct ChangesResponse {
     changes: Vec<FileChangeResponse>,
     /// Current server time (use for next sync)
     server_time: String,
+    /// Pass as `after` to continue from the last change returned
+    next_cursor: Option<String>,
+    /// True if the page was full and more changes may be waiting
+    has_more: bool,
 }
 
 #[derive(Serialize)]
@@ -88,10 +206,36 @@ struct FileChangeResponse {
     action: String,
     size_bytes: Option<i64>,
     blob_hash: Option<String>,
+    /// Current version, which a client can record as the one it last synced
+    version_id: Option<String>,
     is_directory: bool,
     updated_at: String,
 }
 
+/// One line of `/v1/files/changes/stream`
+#[derive(Serialize)]
+struct StreamedChange {
+    #[serde(flatten)]
+    change: FileChangeResponse,
+    /// Pass as `after` to resume the stream after this change
+    cursor: String,
+}
+
+/// Final line of `/v1/files/changes/stream`
+#[derive(Serialize)]
+struct ChangesStreamEnd {
+    /// Server time when the stream started
+    server_time: String,
+    /// Cursor of the last change sent (or the request's `after`)
+    next_cursor: Option<String>,
+}
+
+/// `since` timestamp and `after` keyset position of a changes query
+type ChangesPosition = (
+    Option<chrono::DateTime<chrono::Utc>>,
+    Option<(chrono::DateTime<chrono::Utc>, Uuid)>,
+);
+
 /// Request to create a directory
 #[derive(Deserialize)]
 struct CreateDirectoryRequest {
@@ -112,6 +256,129 @@ struct CreateDirectoryResponse {
     updated_at: String,
 }
 
+/// Response for folder materialization
+#[derive(Serialize)]
+struct MaterializeFolderResponse {
+    /// ID of the folder's record
+    id: String,
+    /// Path-hash ID the folder was known by while virtual, which keeps working
+    original_hash_id: Option<String>,
+    path: String,
+    /// False if the folder already had a record
+    created: bool,
+}
+
+/// Request to create a link (alias) file
+#[derive(Deserialize)]
+struct CreateLinkRequest {
+    /// Path of the new link
+    path: String,
+    /// Path of the existing file the link serves
+    target_path: String,
+}
+
+/// Response for link creation
+#[derive(Serialize)]
+struct CreateLinkResponse {
+    id: String,
+    path: String,
+    target_id: String,
+    created_at: String,
+}
+
+#[derive(Deserialize)]
+struct DiffQuery {
+    /// Version the client has (ID or unique prefix)
+    from: String,
+    /// Version the client wants
+    to: String,
+}
+
+/// Chunk in a version diff
+#[derive(Serialize)]
+struct DiffChunk {
+    hash: String,
+    /// Offset in the version the chunk is listed for
+    offset: u64,
+    length: u32,
+}
+
+/// Chunks that differ between two versions of a file
+#[derive(Serialize)]
+struct VersionDiffResponse {
+    file_id: String,
+    from: String,
+    to: String,
+    /// Chunks of `to` not in `from`, at their offsets in `to`: the ones to download
+    added: Vec<DiffChunk>,
+    /// Chunks of `from` not in `to`, at their offsets in `from`
+    removed: Vec<DiffChunk>,
+    /// Chunks of `to` already in `from`, at their offsets in `to`
+    retained: Vec<DiffChunk>,
+    added_bytes: u64,
+    removed_bytes: u64,
+    retained_bytes: u64,
+    /// `added_bytes + removed_bytes`
+    bytes_changed: u64,
+}
+
+/// Recursive size and counts for a folder
+#[derive(Serialize)]
+struct FolderStatResponse {
+    path: String,
+    file_count: i64,
+    folder_count: i64,
+    total_bytes: i64,
+    latest_updated_at: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct StateHashQuery {
+    /// Folder to cover; empty for every file
+    #[serde(default)]
+    prefix: String,
+}
+
+/// Reconciliation hash for a folder
+#[derive(Serialize)]
+struct StateHashResponse {
+    /// The folder covered, as stored (e.g. `/docs/`)
+    prefix: String,
+    /// Hex BLAKE3 over the sorted `(path, content_hash)` pairs
+    hash: String,
+    file_count: i64,
+}
+
+/// Request to move or rename a file or directory by path
+#[derive(Deserialize)]
+struct MoveRequest {
+    from_path: String,
+    to_path: String,
+}
+
+/// Most IDs and paths accepted by one `/v1/files/delete-batch` request
+const MAX_DELETE_BATCH: usize = 1000;
+
+#[derive(Deserialize)]
+struct DeleteBatchRequest {
+    /// File or directory record IDs
+    #[serde(default)]
+    ids: Vec<String>,
+    /// File or directory paths; a directory may be virtual
+    #[serde(default)]
+    paths: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct DeleteBatchResponse {
+    /// IDs and paths (as sent) that were deleted
+    deleted: Vec<String>,
+    /// IDs and paths that don't exist, are already deleted or aren't the
+    /// caller's. Other users' files are deliberately not told apart, so a
+    /// batch can't probe for them.
+    not_found: Vec<String>,
+}
+
 /// Request to create a file version from uploaded chunks
 #[derive(Deserialize)]
 struct V1CreateFileRequest {
@@ -127,6 +394,17 @@ struct V1CreateFileRequest {
     content_hash: String,
     /// Ordered list of chunk hashes that compose the file
     chunk_hashes: Vec<String>,
+    /// Permission bits of the local file (e.g. 493 for 0755), restored by Unix
+    /// clients. Without it the file keeps the mode it had.
+    #[serde(default)]
+    unix_mode: Option<u32>,
+    /// Base64 key data for client-side encrypted chunks, stored as-is on the
+    /// version. The chunks and both hashes are then over ciphertext.
+    #[serde(default)]
+    encryption_meta: Option<String>,
+    /// Note on this version, shown in the file's history
+    #[serde(default)]
+    note: Option<String>,
 }
 
 /// Response after successfully creating a file version
@@ -157,7 +435,7 @@ async fn get_file_metadata_v1(
     Path(id): Path<Uuid>,
     headers: axum::http::HeaderMap,
 ) -> Result<Json<FileMetadataResponse>, AppError> {
-    let user_id = extract_user_id(&state, &headers)?;
+    let user_id = extract_user_id(&state, &headers).await?;
 
     // SECURITY: Verify ownership before returning metadata
     let file = files::get_file_by_id_with_owner(&state.db, id, user_id)
@@ -169,6 +447,16 @@ async fn get_file_metadata_v1(
         .file_name()
         .map(|n| n.to_string_lossy().to_string())
         .unwrap_or_default();
+    let (tree_hash, encryption_meta) = match file.current_version_id {
+        Some(version_id) => (
+            versions::tree_hash(&state.db, version_id).await?,
+            versions::get_version(&state.db, version_id)
+                .await?
+                .and_then(|v| v.encryption_meta)
+                .map(|meta| BASE64_STANDARD.encode(meta)),
+        ),
+        None => (None, None),
+    };
     
     Ok(Json(FileMetadataResponse {
         id: file.id.to_string(),
@@ -177,37 +465,290 @@ async fn get_file_metadata_v1(
         path: file.path,
         size_bytes: file.size_bytes.unwrap_or(0),
         updated_at: file.updated_at.to_rfc3339(),
+        tree_hash,
+        unix_mode: file.unix_mode,
+        encryption_meta,
     }))
 }
 
+/// List the user's deleted files and directories, most recent first
+/// GET /v1/files/trash?limit=100&offset=0
+async fn list_trash_v1(
+    State(state): State<AppState>,
+    Query(query): Query<TrashQuery>,
+    headers: axum::http::HeaderMap,
+) -> Result<Json<TrashResponse>, AppError> {
+    let user_id = extract_user_id(&state, &headers).await?;
+
+    let (entries, total) = files::list_trash(
+        &state.db,
+        user_id,
+        query.limit.unwrap_or(100).clamp(1, 1000),
+        query.offset.unwrap_or(0).max(0),
+    )
+    .await?;
+
+    let files = entries
+        .into_iter()
+        .map(|e| TrashEntryResponse {
+            id: e.id.to_string(),
+            is_directory: e.path.ends_with('/'),
+            path: e.path,
+            size_bytes: e.size_bytes,
+            deleted_at: e.deleted_at.to_rfc3339(),
+            deleted_batch_id: e.deleted_batch_id.map(|id| id.to_string()),
+        })
+        .collect();
+
+    Ok(Json(TrashResponse { files, total }))
+}
+
+/// Find files by a substring of their path, a tag, or both
+/// GET /v1/files/search?q=report&tag=status:approved&limit=100&include_deleted=false
+async fn search_files_v1(
+    State(state): State<AppState>,
+    Query(query): Query<SearchQuery>,
+    headers: axum::http::HeaderMap,
+) -> Result<Json<SearchResponse>, AppError> {
+    let user_id = extract_user_id(&state, &headers).await?;
+
+    let term = query.q.as_deref().map(str::trim).filter(|q| !q.is_empty());
+    let tag = query.tag.as_deref().map(str::trim).filter(|t| !t.is_empty()).map(|t| match t.split_once(':') {
+        Some((key, value)) => (key, Some(value)),
+        None => (t, None),
+    });
+    if term.is_none() && tag.is_none() {
+        return Err(AppError::BadRequest("Search needs a query q or a tag".into()));
+    }
+
+    let matches = files::search_files(
+        &state.db,
+        user_id,
+        term,
+        tag,
+        query.include_deleted,
+        query.limit.unwrap_or(100),
+    )
+    .await?;
+
+    let files = matches
+        .into_iter()
+        .map(|f| FileResponse {
+            // The sticky ID, as listings and metadata lookups report it
+            id: f.original_hash_id.unwrap_or_else(|| f.id.to_string()),
+            is_directory: f.path.ends_with('/'),
+            path: f.path,
+            size_bytes: f.size_bytes,
+            blob_hash: f.blob_hash,
+            is_deleted: f.is_deleted,
+            created_at: f.created_at.to_rfc3339(),
+            updated_at: f.updated_at.to_rfc3339(),
+        })
+        .collect();
+
+    Ok(Json(SearchResponse { files }))
+}
+
+/// Get a file's tags
+/// GET /v1/files/:id/tags
+async fn get_tags_v1(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    headers: axum::http::HeaderMap,
+) -> Result<Json<TagsBody>, AppError> {
+    let user_id = extract_user_id(&state, &headers).await?;
+    files::get_file_by_id_with_owner(&state.db, id, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("File not found".into()))?;
+
+    let tags = tags::get_tags(&state.db, id).await?;
+    Ok(Json(TagsBody { tags }))
+}
+
+/// Replace a file's tags; an empty map removes them all
+/// PUT /v1/files/:id/tags  {"tags": {"status": "approved"}}
+async fn put_tags_v1(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    headers: axum::http::HeaderMap,
+    Json(body): Json<TagsBody>,
+) -> Result<Json<TagsBody>, AppError> {
+    let user_id = extract_writer_id(&state, &headers).await?;
+    validate_tags(&body.tags)?;
+    files::get_file_by_id_with_owner(&state.db, id, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("File not found".into()))?;
+
+    tags::set_tags(&state.db, id, &body.tags).await?;
+    Ok(Json(body))
+}
+
+/// Decode the base64 `encryption_meta` of an upload, enforcing the size limit
+fn decode_encryption_meta(encoded: Option<&str>) -> Result<Option<Vec<u8>>, AppError> {
+    let Some(encoded) = encoded else {
+        return Ok(None);
+    };
+    let meta = BASE64_STANDARD
+        .decode(encoded)
+        .map_err(|_| AppError::BadRequest("encryption_meta must be base64".into()))?;
+    if meta.is_empty() || meta.len() > MAX_ENCRYPTION_META_BYTES {
+        return Err(AppError::BadRequest(format!(
+            "encryption_meta must be 1 to {} bytes",
+            MAX_ENCRYPTION_META_BYTES
+        )));
+    }
+    Ok(Some(meta))
+}
+
+/// Keys must be non-empty and free of `:`, which separates key from value in
+/// the search filter
+fn validate_tags(tags: &BTreeMap<String, String>) -> Result<(), AppError> {
+    if tags.len() > MAX_TAGS_PER_FILE {
+        return Err(AppError::BadRequest(format!(
+            "A file can have at most {} tags",
+            MAX_TAGS_PER_FILE
+        )));
+    }
+    for (key, value) in tags {
+        if key.trim().is_empty() || key.contains(':') || key.chars().any(char::is_control) {
+            return Err(AppError::BadRequest(format!("Invalid tag key {:?}", key)));
+        }
+        if key.len() > MAX_TAG_KEY_LEN || value.len() > MAX_TAG_VALUE_LEN {
+            return Err(AppError::BadRequest(format!(
+                "Tag keys are limited to {} bytes and values to {}",
+                MAX_TAG_KEY_LEN, MAX_TAG_VALUE_LEN
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Compare two versions of a file chunk by chunk
+///
+/// GET /v1/files/:id/diff?from=<version>&to=<version>
+///
+/// Both versions must belong to the file (400 otherwise). Unchunked legacy
+/// versions count as a single chunk named by their blob hash.
+async fn diff_versions_v1(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<DiffQuery>,
+    headers: axum::http::HeaderMap,
+) -> Result<Json<VersionDiffResponse>, AppError> {
+    let user_id = extract_user_id(&state, &headers).await?;
+
+    // SECURITY: Verify ownership before reading versions
+    let file = files::get_file_by_id_with_owner(&state.db, id, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("File not found".into()))?;
+
+    let mut manifests = Vec::with_capacity(2);
+    let mut names = std::collections::HashMap::new();
+    for raw in [&query.from, &query.to] {
+        let version_id = resolve_version_id(&state, raw, user_id).await?;
+        let version = versions::get_version(&state.db, version_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("Version {} not found", raw)))?;
+        if version.file_id != file.id {
+            return Err(AppError::BadRequest(format!("Version {} does not belong to this file", raw)));
+        }
+        manifests.push((version_id, version_manifest(&state, &version, &mut names).await?));
+    }
+    let (to_id, to) = manifests.pop().expect("two versions");
+    let (from_id, from) = manifests.pop().expect("two versions");
+
+    // `to_upload` is what `from` has that `to` lacks; `to_download` the reverse
+    let diff = from.diff(&to);
+    let listed = |chunks: &[Chunk]| -> Vec<DiffChunk> {
+        chunks
+            .iter()
+            .map(|c| DiffChunk {
+                hash: names.get(&c.hash).cloned().unwrap_or_else(|| c.hash_hex()),
+                offset: c.offset,
+                length: c.length,
+            })
+            .collect()
+    };
+
+    Ok(Json(VersionDiffResponse {
+        file_id: file.id.to_string(),
+        from: from_id.to_string(),
+        to: to_id.to_string(),
+        added: listed(&diff.to_download),
+        removed: listed(&diff.to_upload),
+        retained: listed(&diff.reusable),
+        added_bytes: diff.bytes_to_download(),
+        removed_bytes: diff.bytes_to_upload(),
+        retained_bytes: diff.bytes_reusable(),
+        bytes_changed: diff.bytes_to_download() + diff.bytes_to_upload(),
+    }))
+}
+
+/// A version's chunk list as a manifest. Hashes that aren't 32-byte hex are
+/// keyed by their BLAKE3 digest, with the original recorded in `names`.
+async fn version_manifest(
+    state: &AppState,
+    version: &versions::Version,
+    names: &mut std::collections::HashMap<[u8; 32], String>,
+) -> Result<ChunkManifest, AppError> {
+    let mut key = |hash: &str| -> [u8; 32] {
+        match blake3::Hash::from_hex(hash) {
+            Ok(h) => *h.as_bytes(),
+            Err(_) => {
+                let digest = *blake3::hash(hash.as_bytes()).as_bytes();
+                names.insert(digest, hash.to_string());
+                digest
+            }
+        }
+    };
+
+    let rows = chunks::get_version_chunks_with_location(&state.db, version.id).await?;
+    let chunks: Vec<Chunk> = if rows.is_empty() {
+        vec![Chunk {
+            offset: 0,
+            length: version.size_bytes as u32,
+            hash: key(&version.blob_hash),
+        }]
+    } else {
+        rows.iter()
+            .map(|(vc, chunk)| Chunk {
+                offset: vc.chunk_offset as u64,
+                length: chunk.size_bytes as u32,
+                hash: key(&vc.chunk_hash),
+            })
+            .collect()
+    };
+
+    Ok(ChunkManifest {
+        total_size: version.size_bytes as u64,
+        file_hash: key(&version.blob_hash),
+        chunks,
+    })
+}
+
 /// List directory contents with virtual folder support
 ///
-/// GET /v1/files/list?path=documents/
+/// GET /v1/files/list?path=documents/&sort=folders_first
 ///
-/// Returns direct children (files) and virtual folders (subdirectories)
+/// Returns direct children (files) and virtual folders (subdirectories),
+/// ordered by `sort` (`name`, `folders_first`, `size` or `modified`).
 async fn list_directory_v1(
     State(state): State<AppState>,
     Query(query): Query<ListDirectoryQuery>,
     headers: axum::http::HeaderMap,
 ) -> Result<Json<ListDirectoryResponse>, AppError> {
-    let _user_id = extract_user_id(&state, &headers)?;
+    let _user_id = extract_user_id(&state, &headers).await?;
     
     // Normalize path: strip leading slash, keep trailing slash if present
     let normalized_path = query.path.trim_start_matches('/').to_string();
     
-    let entries = files::list_directory(&state.db, &normalized_path).await?;
+    let mut entries = files::list_directory(&state.db, &normalized_path).await?;
+    query.sort.apply(&mut entries);
+    let folder_count = entries.iter().filter(|e| e.is_folder).count();
     
     let response_entries: Vec<DirectoryEntryResponse> = entries
         .into_iter()
-        .map(|e| DirectoryEntryResponse {
-            id: e.id,
-            name: e.name,
-            path: e.path,
-            is_folder: e.is_folder,
-            size_bytes: e.size_bytes,
-            updated_at: e.updated_at.to_rfc3339(),
-            version_id: e.version_id.map(|v| v.to_string()),
-        })
+        .map(directory_entry_response)
         .collect();
     
     // Return the normalized path (what was actually queried)
@@ -220,63 +761,172 @@ async fn list_directory_v1(
     };
     
     Ok(Json(ListDirectoryResponse {
+        total: response_entries.len(),
+        folder_count,
+        file_count: response_entries.len() - folder_count,
         entries: response_entries,
         path: response_path,
     }))
 }
 
-/// Get files changed since a timestamp (for incremental sync)
+fn directory_entry_response(e: files::DirectoryEntry) -> DirectoryEntryResponse {
+    DirectoryEntryResponse {
+        id: e.id,
+        name: e.name,
+        path: e.path,
+        is_folder: e.is_folder,
+        size_bytes: e.size_bytes,
+        updated_at: e.updated_at.to_rfc3339(),
+        version_id: e.version_id.map(|v| v.to_string()),
+        is_link: e.link_target_id.is_some(),
+        link_target_id: e.link_target_id.map(|t| t.to_string()),
+    }
+}
+
+/// The entry for one path, as its parent's listing would show it
+/// GET /v1/files/entry?path=documents/report.pdf
+///
+/// Looks the path up directly instead of listing the parent.
+async fn directory_entry_v1(
+    State(state): State<AppState>,
+    Query(query): Query<ListDirectoryQuery>,
+    headers: axum::http::HeaderMap,
+) -> Result<Json<DirectoryEntryResponse>, AppError> {
+    let user_id = extract_user_id(&state, &headers).await?;
+    let path = validate_path(&query.path, state.config.path_limits())?;
+
+    let entry = files::get_directory_entry(&state.db, user_id, &path)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("/{} not found", path.trim_matches('/'))))?;
+    Ok(Json(directory_entry_response(entry)))
+}
+
+/// Recursive file count, folder count and byte total for a folder
+/// GET /v1/files/stat?path=documents/
+///
+/// Soft-deleted files are excluded; the root is `path=` or `path=/`.
+async fn folder_stat_v1(
+    State(state): State<AppState>,
+    Query(query): Query<ListDirectoryQuery>,
+    headers: axum::http::HeaderMap,
+) -> Result<Json<FolderStatResponse>, AppError> {
+    let user_id = extract_user_id(&state, &headers).await?;
+    let folder = folder_path(&state, &query.path)?;
+
+    let stats = files::folder_stats(&state.db, user_id, &folder).await?;
+
+    // Nothing underneath: only an empty directory record (or the root) is a folder
+    if stats.latest_updated_at.is_none() && folder != "/" {
+        let record = files::get_file_by_path(&state.db, user_id, &folder).await?;
+        if record.is_none_or(|f| f.is_deleted) {
+            return Err(AppError::NotFound(format!("{} not found", folder)));
+        }
+    }
+
+    Ok(Json(FolderStatResponse {
+        path: folder,
+        file_count: stats.file_count,
+        folder_count: stats.folder_count,
+        total_bytes: stats.total_bytes,
+        latest_updated_at: stats.latest_updated_at.map(|t| t.to_rfc3339()),
+    }))
+}
+
+/// A folder path as stored: leading and trailing `/` (the root is `/`)
+fn folder_path(state: &AppState, raw: &str) -> Result<String, AppError> {
+    let trimmed = raw.trim_matches('/');
+    if trimmed.is_empty() {
+        return Ok("/".to_string());
+    }
+    // SECURITY: Validate path to prevent path traversal
+    validate_path(trimmed, state.config.path_limits())?;
+    Ok(format!("/{}/", trimmed))
+}
+
+/// One hash over every file under a folder, for a quick "are we in sync" check
+///
+/// GET /v1/files/state-hash?prefix=/docs
+///
+/// The hash is the hex BLAKE3 of `path NUL content_hash LF` for each live
+/// file under the prefix, in byte order of path; folders are left out. A
+/// client computing the same over its index and getting the same hash has
+/// nothing to sync there. An empty or missing prefix covers every file.
+async fn state_hash_v1(
+    State(state): State<AppState>,
+    Query(query): Query<StateHashQuery>,
+    headers: axum::http::HeaderMap,
+) -> Result<Json<StateHashResponse>, AppError> {
+    let user_id = extract_user_id(&state, &headers).await?;
+    let folder = folder_path(&state, &query.prefix)?;
+
+    let mut hasher = StateHasher::default();
+    let mut entries = std::pin::pin!(files::stream_content_hashes(&state.db, user_id, &folder));
+    while let Some((path, content_hash)) = entries.next().await.transpose()? {
+        hasher.add(&path, &content_hash);
+    }
+
+    Ok(Json(StateHashResponse {
+        prefix: folder,
+        hash: hasher.finalize(),
+        file_count: hasher.file_count,
+    }))
+}
+
+/// Running hash for [`state_hash_v1`]; entries must be added in path order
+#[derive(Default)]
+struct StateHasher {
+    hasher: blake3::Hasher,
+    file_count: i64,
+}
+
+impl StateHasher {
+    fn add(&mut self, path: &str, content_hash: &str) {
+        self.hasher.update(path.as_bytes());
+        self.hasher.update(b"\0");
+        self.hasher.update(content_hash.as_bytes());
+        self.hasher.update(b"\n");
+        self.file_count += 1;
+    }
+
+    fn finalize(&self) -> String {
+        self.hasher.finalize().to_hex().to_string()
+    }
+}
+
+/// Get files changed since a cursor or timestamp (for incremental sync)
 /// 
+/// GET /v1/files/changes?after=<next_cursor>&limit=1000
 /// GET /v1/files/changes?since=2024-12-22T00:00:00Z&limit=1000
 ///
-/// Returns files created, modified, or deleted since the given timestamp.
-/// If `since` is omitted, returns all files (useful for first sync).
+/// Returns files created, modified, or deleted after the given position, ordered
+/// by `(updated_at, id)`. Page with `after=<next_cursor>` while `has_more` is true.
+/// If both are omitted, returns all files (useful for first sync).
 async fn get_file_changes(
     State(state): State<AppState>,
     Query(query): Query<ChangesQuery>,
     headers: axum::http::HeaderMap,
 ) -> Result<Json<ChangesResponse>, AppError> {
-    let user_id = extract_user_id(&state, &headers)?;
-    
-    // Parse the since timestamp if provided
-    let cursor = if let Some(since_str) = &query.since {
-        Some(
-            chrono::DateTime::parse_from_rfc3339(since_str)
-                .map_err(|e| AppError::BadRequest(format!("Invalid since timestamp: {}", e)))?
-                .with_timezone(&chrono::Utc)
-        )
-    } else {
-        None
-    };
+    let user_id = extract_user_id(&state, &headers).await?;
     
-    let limit = query.limit.unwrap_or(1000).min(10000); // Cap at 10k
+    let (since, after) = parse_changes_position(&query)?;
+
+    let limit = query.limit.unwrap_or(files::MAX_CHANGES_PAGE).clamp(1, files::MAX_CHANGES_PAGE);
     
     // Get changes from database
-    let changes = files::get_changes(&state.db, user_id, cursor, limit).await?;
+    let changes = files::get_changes(&state.db, user_id, since, after, limit).await?;
+    let has_more = changes.len() as i64 >= limit;
+    let next_cursor = changes
+        .last()
+        .map(|c| encode_changes_cursor(c.updated_at, c.id))
+        .or(query.after);
+
+    // "created" vs "modified" is judged against the client's last known position
+    let cursor = after.map(|(t, _)| t).or(since);
     
     // Convert to response format
     let response_changes: Vec<FileChangeResponse> = changes
         .into_iter()
-        .map(|change| {
-            // Determine action based on state
-            let action = if change.is_deleted {
-                "deleted"
-            } else if cursor.is_some() && change.created_at > cursor.unwrap() {
-                "created"
-            } else {
-                "modified"
-            };
-            
-            FileChangeResponse {
-                id: change.id.to_string(),
-                path: change.path.clone(),
-                action: action.to_string(),
-                size_bytes: change.size_bytes,
-                blob_hash: change.blob_hash,
-                is_directory: change.path.ends_with('/'),
-                updated_at: change.updated_at.to_rfc3339(),
-            }
-        })
+        .map(|change| change_response(change, cursor))
         .collect();
     
     // Return current server time for use in next sync
@@ -285,9 +935,125 @@ async fn get_file_changes(
     Ok(Json(ChangesResponse {
         changes: response_changes,
         server_time,
+        next_cursor,
+        has_more,
     }))
 }
 
+/// Stream all changes as JSON Lines (for very large trees)
+///
+/// GET /v1/files/changes/stream?after=<cursor>
+/// GET /v1/files/changes/stream?since=2024-12-22T00:00:00Z
+///
+/// Each line is one change object with the fields of `/v1/files/changes` plus
+/// a `cursor` the client can checkpoint after processing that line. The last
+/// line is `{"server_time": ..., "next_cursor": ...}`; a stream that ends
+/// without it was cut short and should be resumed from the last cursor.
+async fn stream_file_changes(
+    State(state): State<AppState>,
+    Query(query): Query<ChangesQuery>,
+    headers: axum::http::HeaderMap,
+) -> Result<axum::response::Response, AppError> {
+    let user_id = extract_user_id(&state, &headers).await?;
+    let (since, after) = parse_changes_position(&query)?;
+    let cursor = after.map(|(t, _)| t).or(since);
+
+    // Taken before the query so changes committed mid-stream are not missed
+    let server_time = chrono::Utc::now().to_rfc3339();
+
+    let body = Body::from_stream(async_stream::stream! {
+        let mut changes = std::pin::pin!(files::stream_changes(&state.db, user_id, since, after));
+        let mut next_cursor = query.after;
+        while let Some(change) = changes.next().await {
+            let change = match change {
+                Ok(change) => change,
+                Err(e) => {
+                    tracing::error!("Changes stream failed: {}", e);
+                    yield Err(std::io::Error::other(e.to_string()));
+                    return;
+                }
+            };
+            let line_cursor = encode_changes_cursor(change.updated_at, change.id);
+            let line = StreamedChange {
+                change: change_response(change, cursor),
+                cursor: line_cursor.clone(),
+            };
+            next_cursor = Some(line_cursor);
+            yield json_line(&line);
+        }
+        yield json_line(&ChangesStreamEnd { server_time, next_cursor });
+    });
+
+    axum::response::Response::builder()
+        .header(header::CONTENT_TYPE, "application/x-ndjson")
+        .body(body)
+        .map_err(|e| AppError::Internal(e.to_string()))
+}
+
+/// Serialize one JSON Lines record
+fn json_line<T: Serialize>(value: &T) -> std::io::Result<axum::body::Bytes> {
+    let mut line = serde_json::to_vec(value).map_err(std::io::Error::other)?;
+    line.push(b'\n');
+    Ok(line.into())
+}
+
+/// Parse the `since` timestamp and `after` cursor of a changes query
+fn parse_changes_position(query: &ChangesQuery) -> Result<ChangesPosition, AppError> {
+    let since = query
+        .since
+        .as_deref()
+        .map(|since| {
+            chrono::DateTime::parse_from_rfc3339(since)
+                .map(|dt| dt.with_timezone(&chrono::Utc))
+                .map_err(|e| AppError::BadRequest(format!("Invalid since timestamp: {}", e)))
+        })
+        .transpose()?;
+    let after = query.after.as_deref().map(decode_changes_cursor).transpose()?;
+    Ok((since, after))
+}
+
+/// Convert a change row; "created" vs "modified" is judged against `cursor`,
+/// the client's last known position
+fn change_response(change: files::FileChange, cursor: Option<chrono::DateTime<chrono::Utc>>) -> FileChangeResponse {
+    let action = if change.is_deleted {
+        "deleted"
+    } else if cursor.is_some_and(|cursor| change.created_at > cursor) {
+        "created"
+    } else {
+        "modified"
+    };
+
+    FileChangeResponse {
+        id: change.id.to_string(),
+        is_directory: change.path.ends_with('/'),
+        path: change.path,
+        action: action.to_string(),
+        size_bytes: change.size_bytes,
+        blob_hash: change.blob_hash,
+        version_id: change.current_version_id.map(|v| v.to_string()),
+        updated_at: change.updated_at.to_rfc3339(),
+    }
+}
+
+/// Encode a changes-feed position as an opaque URL-safe token
+fn encode_changes_cursor(updated_at: chrono::DateTime<chrono::Utc>, id: Uuid) -> String {
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    URL_SAFE_NO_PAD.encode(format!("{}:{}", updated_at.timestamp_micros(), id))
+}
+
+/// Decode a token produced by `encode_changes_cursor`
+fn decode_changes_cursor(cursor: &str) -> Result<(chrono::DateTime<chrono::Utc>, Uuid), AppError> {
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    let invalid = || AppError::BadRequest("Invalid changes cursor".into());
+    let raw = URL_SAFE_NO_PAD.decode(cursor).map_err(|_| invalid())?;
+    let raw = String::from_utf8(raw).map_err(|_| invalid())?;
+    let (micros, id) = raw.split_once(':').ok_or_else(invalid)?;
+    let micros: i64 = micros.parse().map_err(|_| invalid())?;
+    let updated_at = chrono::DateTime::from_timestamp_micros(micros).ok_or_else(invalid)?;
+    let id = Uuid::parse_str(id).map_err(|_| invalid())?;
+    Ok((updated_at, id))
+}
+
 /// Create a directory (virtual folder)
 /// POST /v1/files/directory
 /// 
@@ -297,7 +1063,7 @@ async fn create_directory_v1(
     headers: axum::http::HeaderMap,
     Json(req): Json<CreateDirectoryRequest>,
 ) -> Result<Json<CreateDirectoryResponse>, AppError> {
-    let user_id = extract_user_id(&state, &headers)?;
+    let user_id = extract_writer_id(&state, &headers).await?;
     
     // Normalize path: ensure it ends with /
     let mut dir_path = req.path.trim().to_string();
@@ -306,7 +1072,7 @@ async fn create_directory_v1(
     }
     
     // SECURITY: Validate path to prevent path traversal
-    validate_path(&dir_path)?;
+    validate_path(&dir_path, state.config.path_limits())?;
     
     // Ensure leading slash
     if !dir_path.starts_with('/') {
@@ -319,12 +1085,12 @@ async fn create_directory_v1(
     }
     
     // Create directory record (upsert) with ownership
-    let file = files::upsert_file_with_owner(&state.db, &dir_path, user_id).await?;
+    let file = files::upsert_file_with_owner(&state.db, &dir_path, user_id, state.config.case_insensitive_paths).await?;
     
     tracing::debug!("Created directory: {}", dir_path);
     
     // Notify connected clients about the new directory (send actual path for menu bar display)
-    state.sync_hub.notify_file_changed(&dir_path, "create");
+    state.sync_hub.notify_file_changed(file.owner_id, &dir_path, "create");
     
     Ok(Json(CreateDirectoryResponse {
         id: file.id.to_string(),
@@ -338,6 +1104,289 @@ async fn create_directory_v1(
     }))
 }
 
+/// Materialize a virtual folder
+/// POST /v1/files/materialize
+///
+/// Creates a record for a folder that so far only exists as the parent of
+/// live files, so its ID no longer has to be resolved by scanning every path.
+/// Clients can call this before sharing or renaming a folder. A folder that
+/// already has a record is returned unchanged.
+async fn materialize_folder_v1(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+    Json(req): Json<CreateDirectoryRequest>,
+) -> Result<Json<MaterializeFolderResponse>, AppError> {
+    let user_id = extract_writer_id(&state, &headers).await?;
+
+    if req.path.trim().is_empty() {
+        return Err(AppError::BadRequest("Path cannot be empty".into()));
+    }
+    let mut dir_path = validate_path(req.path.trim(), state.config.path_limits())?;
+    if !dir_path.ends_with('/') {
+        dir_path.push('/');
+    }
+    if dir_path == "/" {
+        return Err(AppError::BadRequest("The root folder can't be materialized".into()));
+    }
+
+    let respond = |file: files::File, created: bool| MaterializeFolderResponse {
+        id: file.id.to_string(),
+        original_hash_id: file.original_hash_id,
+        path: file.path,
+        created,
+    };
+
+    if let Some(file) = files::get_file_by_path(&state.db, user_id, &dir_path).await? {
+        if !file.is_deleted {
+            return Ok(Json(respond(file, false)));
+        }
+    }
+    let file_path = dir_path.trim_end_matches('/');
+    if files::get_file_by_path(&state.db, user_id, file_path).await?.is_some_and(|f| !f.is_deleted) {
+        return Err(AppError::Conflict(format!("{} is a file", file_path)));
+    }
+    if !files::path_exists(&state.db, &dir_path, user_id).await? {
+        return Err(AppError::NotFound("Folder not found".into()));
+    }
+
+    let (file, created) = files::materialize_folder(&state.db, &dir_path, user_id).await?;
+    if created {
+        tracing::debug!("Materialized folder {} as {}", file.path, file.id);
+    }
+
+    Ok(Json(respond(file, created)))
+}
+
+/// Create a link (alias) file
+/// POST /v1/files/link
+///
+/// The link has no versions of its own; downloads serve the target's current
+/// version. Links to links are collapsed to the final target. Deleting the
+/// target leaves the link dangling (downloads return 404).
+async fn create_link_v1(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+    Json(req): Json<CreateLinkRequest>,
+) -> Result<axum::response::Response, AppError> {
+    let user_id = extract_writer_id(&state, &headers).await?;
+
+    if req.path.trim().is_empty() || req.target_path.trim().is_empty() {
+        return Err(AppError::BadRequest("Path cannot be empty".into()));
+    }
+
+    // SECURITY: Validate both paths to prevent path traversal
+    validate_path(&req.path, state.config.path_limits())?;
+    validate_path(&req.target_path, state.config.path_limits())?;
+
+    if req.path.ends_with('/') || req.target_path.ends_with('/') {
+        return Err(AppError::BadRequest("Links must point file to file".into()));
+    }
+    if req.path == req.target_path {
+        return Err(AppError::BadRequest("A link cannot point to itself".into()));
+    }
+
+    let target = files::get_file_by_path(&state.db, user_id, &req.target_path)
+        .await?
+        .filter(|f| !f.is_deleted)
+        .ok_or_else(|| AppError::NotFound("Link target not found".into()))?;
+
+    // Point at the final target so downloads resolve in a single hop
+    let target_id = files::get_link_target(&state.db, target.id)
+        .await?
+        .unwrap_or(target.id);
+
+    let link = files::create_link(
+        &state.db,
+        &req.path,
+        user_id,
+        target_id,
+        state.config.case_insensitive_paths,
+    )
+    .await?
+    .ok_or_else(|| AppError::Conflict(format!("{} already exists", req.path)))?;
+
+    tracing::debug!("Created link {} -> {}", req.path, req.target_path);
+
+    state.sync_hub.notify_file_changed(link.owner_id, &link.path, "create");
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreateLinkResponse {
+            id: link.id.to_string(),
+            path: link.path,
+            target_id: target_id.to_string(),
+            created_at: link.created_at.to_rfc3339(),
+        }),
+    )
+        .into_response())
+}
+
+/// Move or rename a file or directory by path
+/// POST /v1/files/move
+///
+/// Directories (real or virtual) move recursively. A `to_path` ending in `/`
+/// is a folder to move into: the source keeps its name there, and `409` is
+/// returned if that name is taken. To rename a directory, leave the slash
+/// off. The response carries the sticky ID: a virtual folder keeps the ID
+/// clients derived from its old path.
+async fn move_v1(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+    audit: AuditContext,
+    Json(req): Json<MoveRequest>,
+) -> Result<Json<FileResponse>, AppError> {
+    let user_id = extract_writer_id(&state, &headers).await?;
+
+    if req.from_path.trim().is_empty() || req.to_path.trim().is_empty() {
+        return Err(AppError::BadRequest("Path cannot be empty".into()));
+    }
+
+    // SECURITY: Validate both paths to prevent path traversal
+    validate_path(&req.from_path, state.config.path_limits())?;
+    validate_path(&req.to_path, state.config.path_limits())?;
+
+    let from_path = format!("/{}", req.from_path.trim_start_matches('/'));
+    let to_path = move_target(&from_path, &format!("/{}", req.to_path.trim_start_matches('/')));
+    // Moving into a folder appends the source's name, which the limits
+    // must allow too
+    validate_path(&to_path, state.config.path_limits())?;
+
+    let from_dir = from_path.trim_end_matches('/');
+    if to_path.trim_end_matches('/') == from_dir {
+        return Err(AppError::BadRequest("Source and destination are the same".into()));
+    }
+    if to_path.starts_with(&format!("{}/", from_dir)) {
+        return Err(AppError::BadRequest("Cannot move a directory into itself".into()));
+    }
+
+    if !files::path_exists(&state.db, &from_path, user_id).await? {
+        return Err(AppError::NotFound(format!("{} not found", from_path)));
+    }
+    if files::path_exists(&state.db, &to_path, user_id).await? {
+        return Err(AppError::Conflict(format!("{} already exists", to_path)));
+    }
+
+    // Preconditions apply to the source record, if it has one (virtual folders don't)
+    let source = match files::get_file_by_path(&state.db, user_id, &from_path).await? {
+        Some(file) => Some(file),
+        None => files::get_file_by_path(&state.db, user_id, &format!("{}/", from_dir)).await?,
+    };
+    if let Some(source) = source.filter(|f| !f.is_deleted) {
+        check_if_match(&headers, source.current_version_id)?;
+        check_unmodified_since(&headers, source.updated_at)?;
+    }
+
+    let moved = files::move_path(
+        &state.db,
+        &from_path,
+        &to_path,
+        user_id,
+        state.config.case_insensitive_paths,
+    )
+    .await?;
+
+    tracing::debug!("Moved {} -> {}", from_path, moved.path);
+
+    state.sync_hub.notify_file_changed(moved.owner_id, &moved.path, "move");
+    audit.record(&state, Some(user_id), AuditAction::Move, Some(&moved.path)).await;
+
+    // Directories have no content; files report their current version
+    let current = files::get_file_by_id_with_owner(&state.db, moved.id, user_id).await?;
+
+    Ok(Json(FileResponse {
+        id: moved.original_hash_id.clone().unwrap_or_else(|| moved.id.to_string()),
+        is_directory: moved.path.ends_with('/'),
+        size_bytes: current.as_ref().and_then(|f| f.size_bytes),
+        blob_hash: current.and_then(|f| f.blob_hash),
+        path: moved.path,
+        is_deleted: moved.is_deleted,
+        created_at: moved.created_at.to_rfc3339(),
+        updated_at: moved.updated_at.to_rfc3339(),
+    }))
+}
+
+/// Soft-delete files and directories by ID or path
+/// POST /v1/files/delete-batch
+///
+/// Directories are deleted recursively, as by `DELETE /files/:id`. Each entry
+/// succeeds or fails on its own; entries the caller can't see are reported as
+/// not found, without saying whether they exist.
+async fn delete_batch_v1(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+    audit: AuditContext,
+    Json(req): Json<DeleteBatchRequest>,
+) -> Result<Json<DeleteBatchResponse>, AppError> {
+    let user_id = extract_writer_id(&state, &headers).await?;
+
+    if req.ids.len() + req.paths.len() > MAX_DELETE_BATCH {
+        return Err(AppError::BadRequest(format!(
+            "At most {} ids and paths per batch",
+            MAX_DELETE_BATCH
+        )));
+    }
+
+    let mut response = DeleteBatchResponse {
+        deleted: Vec::new(),
+        not_found: Vec::new(),
+    };
+    let mut deleted_paths = Vec::new();
+
+    for id in req.ids {
+        let file = match Uuid::parse_str(&id) {
+            Ok(file_id) => files::get_file_by_id_with_owner(&state.db, file_id, user_id).await?,
+            Err(_) => None,
+        };
+        match file.filter(|f| !f.is_deleted) {
+            Some(file) if files::soft_delete_recursive_with_owner(&state.db, file.id, user_id).await? => {
+                deleted_paths.push((file.owner_id, file.path));
+                response.deleted.push(id);
+            }
+            _ => response.not_found.push(id),
+        }
+    }
+
+    for raw_path in req.paths {
+        let Ok(path) = validate_path(&raw_path, state.config.path_limits()) else {
+            response.not_found.push(raw_path);
+            continue;
+        };
+        let path = format!("/{}", path.trim_matches('/'));
+
+        // A file record at the path, else a directory (real or virtual)
+        let file = files::get_file_by_path(&state.db, user_id, &path)
+            .await?
+            .filter(|f| !f.is_deleted);
+        let (deleted, owner_id) = if let Some(file) = file {
+            (files::soft_delete_with_owner(&state.db, file.id, user_id).await?, file.owner_id)
+        } else if path != "/" && files::path_exists(&state.db, &path, user_id).await? {
+            let dir_path = format!("{}/", path);
+            (files::soft_delete_tree_with_owner(&state.db, &dir_path, user_id).await? > 0, Some(user_id))
+        } else {
+            (false, None)
+        };
+        if deleted {
+            deleted_paths.push((owner_id, path));
+            response.deleted.push(raw_path);
+        } else {
+            response.not_found.push(raw_path);
+        }
+    }
+
+    tracing::debug!(
+        "Batch delete: {} deleted, {} not found",
+        response.deleted.len(),
+        response.not_found.len()
+    );
+
+    for (owner_id, path) in &deleted_paths {
+        state.sync_hub.notify_file_changed(*owner_id, path, "delete");
+        audit.record(&state, Some(user_id), AuditAction::Delete, Some(path)).await;
+    }
+
+    Ok(Json(response))
+}
+
 /// Create a file version from previously uploaded chunks
 /// POST /v1/files
 /// 
@@ -348,9 +1397,10 @@ async fn create_directory_v1(
 async fn create_v1_file(
     State(state): State<AppState>,
     headers: axum::http::HeaderMap,
+    audit: AuditContext,
     Json(req): Json<V1CreateFileRequest>,
 ) -> Result<axum::response::Response, AppError> {
-    let user_id = extract_user_id(&state, &headers)?;
+    let user_id = extract_writer_id(&state, &headers).await?;
     
     // 1. Validate path is not empty
     if req.path.trim().is_empty() {
@@ -358,10 +1408,15 @@ async fn create_v1_file(
     }
     
     // SECURITY: Validate path to prevent path traversal
-    validate_path(&req.path)?;
+    validate_path(&req.path, state.config.path_limits())?;
+    let unix_mode = validate_unix_mode(req.unix_mode)?;
+    let encryption_meta = decode_encryption_meta(req.encryption_meta.as_deref())?;
+    let note = validate_version_note(req.note.as_deref())?;
     
-    // 2. Integrity check - ALL chunks must exist in the database
-    let missing = chunks::find_missing_chunks(&state.db, &req.chunk_hashes).await?;
+    // 2. Integrity check - ALL chunks must exist in the database. The empty
+    // chunk clients send for an empty file is dropped: an empty file has none.
+    let chunk_hashes = stored_chunk_hashes(&req.chunk_hashes);
+    let missing = chunks::find_missing_chunks(&state.db, &chunk_hashes).await?;
     if !missing.is_empty() {
         let body = MissingChunksError {
             error: "Missing chunks".into(),
@@ -370,14 +1425,26 @@ async fn create_v1_file(
         return Ok((StatusCode::BAD_REQUEST, Json(body)).into_response());
     }
     
-    // 3. Get chunk sizes from DB to calculate offsets
-    let chunk_sizes = chunks::get_chunk_sizes(&state.db, &req.chunk_hashes).await?;
-    
+    // 3. Get chunk sizes from DB to calculate offsets. A zero-size record is
+    // a placeholder from an upload that never finished, so it counts as missing.
+    let chunk_sizes = chunks::get_chunk_sizes(&state.db, &chunk_hashes).await?;
+    let placeholders: Vec<String> = chunk_hashes.iter()
+        .filter(|hash| chunk_sizes.get(*hash).is_some_and(|&size| size <= 0))
+        .cloned()
+        .collect();
+    if !placeholders.is_empty() {
+        let body = MissingChunksError {
+            error: "Missing chunks".into(),
+            missing_hashes: placeholders,
+        };
+        return Ok((StatusCode::BAD_REQUEST, Json(body)).into_response());
+    }
+
     // 4. Build chunk info list with calculated offsets
-    let mut chunk_infos: Vec<chunks::ChunkInfo> = Vec::with_capacity(req.chunk_hashes.len());
+    let mut chunk_infos: Vec<chunks::ChunkInfo> = Vec::with_capacity(chunk_hashes.len());
     let mut current_offset: i64 = 0;
     
-    for hash in &req.chunk_hashes {
+    for hash in &chunk_hashes {
         let size = chunk_sizes.get(hash)
             .copied()
             .ok_or_else(|| AppError::Internal(format!("Chunk size not found for {}", hash)))?;
@@ -404,49 +1471,354 @@ async fn create_v1_file(
         .map(|dt| dt.with_timezone(&chrono::Utc))
         .ok();
     
-    // 7. Upsert file record with owner (creates if not exists, updates timestamp if exists)
-    let file = files::upsert_file_with_owner_and_dates(&state.db, &req.path, user_id, None, modified_at).await?;
-    
-    // 8. Create version with tier (transactional - links chunks and updates file)
+    // 7-8. Upsert the file record with owner, then create the version with
+    // tier, in one transaction holding the path's lock so concurrent uploads
+    // of the file serialize and If-Match sees the version being replaced.
+    // Runs to completion even if the client disconnects, and holds up
+    // shutdown until it does.
     let tier = ChunkTier::from_i16(req.tier_id).unwrap_or_default();
-    let version_id = chunks::create_version_with_tier(
-        &state.db,
-        file.id,
-        &req.content_hash,
-        req.size_bytes,
-        tier,
-        &chunk_infos,
-    ).await?;
+    let (file, version_id) = state.in_flight.run({
+        let db = state.db.clone();
+        let blob_manager = state.blob_manager.clone();
+        let headers = headers.clone();
+        let path = req.path.clone();
+        let content_hash = req.content_hash.clone();
+        let size_bytes = req.size_bytes;
+        let case_insensitive = state.config.case_insensitive_paths;
+        let retention = state.config.version_retention();
+        async move {
+            let mut tx = db.begin().await?;
+            files::lock_path(&mut tx, user_id, &path).await?;
+            let file = files::upsert_file_with_owner_and_dates(
+                &mut tx,
+                &path,
+                user_id,
+                None,
+                modified_at,
+                case_insensitive,
+            )
+            .await?;
+            check_if_match(&headers, file.current_version_id)?;
+            if let Some(mode) = unix_mode {
+                files::set_unix_mode(&mut *tx, file.id, mode).await?;
+            }
+            let (version_id, released) = chunks::create_version_with_tier(
+                &mut tx,
+                file.id,
+                &content_hash,
+                size_bytes,
+                tier,
+                &chunk_infos,
+                Some(user_id),
+                retention,
+            ).await?;
+            if encryption_meta.is_some() {
+                versions::set_encryption_meta(&mut *tx, version_id, encryption_meta.as_deref()).await?;
+            }
+            if note.is_some() {
+                versions::set_note(&mut *tx, version_id, note.as_deref()).await?;
+            }
+            tx.commit().await?;
+            blob_manager.delete_released(&db, &released).await;
+            Ok((file, version_id))
+        }
+    }).await?;
     
     tracing::debug!(
         "Created file version for path '{}' ({} chunks, {} bytes)",
-        req.path, req.chunk_hashes.len(), req.size_bytes
+        req.path, chunk_hashes.len(), req.size_bytes
     );
 
     // 9. Return 201 Created
     let response = V1CreateFileResponse {
         id: file.id.to_string(),
         version_id: version_id.to_string(),
-        path: req.path.clone(),
+        path: file.path.clone(),
     };
 
     // 10. Notify connected clients about the new file (send actual path for menu bar display)
-    state.sync_hub.notify_file_changed(&req.path, "create");
+    let action = SyncNotification::content_action(file.current_version_id);
+    state.sync_hub.notify_file_changed(file.owner_id, &file.path, action);
+    audit.record(&state, Some(user_id), AuditAction::Upload, Some(&file.path)).await;
+
+    Ok((StatusCode::CREATED, Json(response)).into_response())
+}
 
+/// Upload a file from a browser form
+/// POST /v1/files/upload (multipart/form-data)
+///
+/// Text fields `path` (required), `size` (optional; picks the chunking tier
+/// the sync clients would use) and `modified_at` (optional, ISO8601) must
+/// precede the `file` part. The file part is streamed through the chunker,
+/// so only about one chunk is buffered at a time.
+async fn upload_multipart_v1(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+    audit: AuditContext,
+    mut multipart: Multipart,
+) -> Result<axum::response::Response, AppError> {
+    let user_id = extract_writer_id(&state, &headers).await?;
+    let limit = state.config.max_upload_bytes as u64;
+
+    let mut path: Option<String> = None;
+    let mut declared_size: Option<u64> = None;
+    let mut modified_at = None;
+    let mut upload: Option<StreamedUpload> = None;
+
+    while let Some(mut field) = multipart.next_field().await.map_err(multipart_error)? {
+        match field.name() {
+            Some("path") => path = Some(field.text().await.map_err(multipart_error)?),
+            Some("size") => {
+                let size = field.text().await.map_err(multipart_error)?;
+                let size = size.trim().parse()
+                    .map_err(|_| AppError::BadRequest(format!("Invalid size: {}", size)))?;
+                declared_size = Some(size);
+            }
+            Some("modified_at") => {
+                let value = field.text().await.map_err(multipart_error)?;
+                modified_at = chrono::DateTime::parse_from_rfc3339(value.trim())
+                    .map(|dt| dt.with_timezone(&chrono::Utc))
+                    .ok();
+            }
+            Some("file") => {
+                let path = path.as_deref()
+                    .ok_or_else(|| AppError::BadRequest("The path field must come before the file part".into()))?;
+                if path.trim().is_empty() {
+                    return Err(AppError::BadRequest("Path cannot be empty".into()));
+                }
+                // SECURITY: Validate path to prevent path traversal
+                validate_path(path, state.config.path_limits())?;
+
+                let mut chunker = UploadChunker::new(path, declared_size, limit);
+                let tier = chunker.tier;
+                let mut chunk_infos = Vec::new();
+
+                while let Some(data) = field.chunk().await.map_err(multipart_error)? {
+                    for chunk in chunker.push(&data)? {
+                        store_upload_chunk(&state, tier, &chunk, &mut chunk_infos).await?;
+                    }
+                }
+                let (rest, file_hash, size_bytes) = chunker.finish()?;
+                for chunk in rest {
+                    store_upload_chunk(&state, tier, &chunk, &mut chunk_infos).await?;
+                }
+
+                upload = Some(StreamedUpload {
+                    content_hash: hex::encode(file_hash),
+                    size_bytes,
+                    tier,
+                    chunks: chunk_infos,
+                });
+            }
+            // Ignore anything else a form may carry (submit buttons, etc.)
+            _ => {}
+        }
+    }
+
+    let (Some(path), Some(upload)) = (path, upload) else {
+        return Err(AppError::BadRequest("Multipart upload needs a path field and a file part".into()));
+    };
+
+    let (chunk_count, size_bytes) = (upload.chunks.len(), upload.size_bytes);
+    let (file, version_id) = state.in_flight.run({
+        let db = state.db.clone();
+        let blob_manager = state.blob_manager.clone();
+        let path = path.clone();
+        let case_insensitive = state.config.case_insensitive_paths;
+        let retention = state.config.version_retention();
+        async move {
+            let mut tx = db.begin().await?;
+            files::lock_path(&mut tx, user_id, &path).await?;
+            let file = files::upsert_file_with_owner_and_dates(
+                &mut tx,
+                &path,
+                user_id,
+                None,
+                modified_at,
+                case_insensitive,
+            )
+            .await?;
+            let (version_id, released) = chunks::create_version_with_tier(
+                &mut tx,
+                file.id,
+                &upload.content_hash,
+                upload.size_bytes,
+                upload.tier,
+                &upload.chunks,
+                Some(user_id),
+                retention,
+            ).await?;
+            tx.commit().await?;
+            blob_manager.delete_released(&db, &released).await;
+            Ok((file, version_id))
+        }
+    }).await?;
+
+    tracing::debug!(
+        "Multipart upload of '{}' ({} chunks, {} bytes)",
+        path, chunk_count, size_bytes
+    );
+
+    let action = SyncNotification::content_action(file.current_version_id);
+    state.sync_hub.notify_file_changed(file.owner_id, &file.path, action);
+    audit.record(&state, Some(user_id), AuditAction::Upload, Some(&file.path)).await;
+
+    let response = V1CreateFileResponse {
+        id: file.id.to_string(),
+        version_id: version_id.to_string(),
+        path: file.path.clone(),
+    };
     Ok((StatusCode::CREATED, Json(response)).into_response())
 }
 
+/// Remaining chunks, whole-file hash and size of a finished upload
+type FinishedChunks = (Vec<Vec<u8>>, [u8; 32], i64);
+
+/// Chunker for the file part of a multipart upload
+///
+/// Checks the upload limit and the declared `size` as bytes arrive, so
+/// neither a lying `size` nor an oversized body is buffered first.
+struct UploadChunker {
+    chunker: StreamChunker,
+    tier: ChunkTier,
+    declared_size: Option<u64>,
+    limit: u64,
+}
+
+impl UploadChunker {
+    fn new(path: &str, declared_size: Option<u64>, limit: u64) -> Self {
+        let tier = declared_size
+            .map(|size| streaming_tier(DefaultTierStrategy::determine_tier(std::path::Path::new(path), size).into()))
+            .unwrap_or_default();
+        Self {
+            chunker: StreamChunker::new(tier.into()),
+            tier,
+            declared_size,
+            limit,
+        }
+    }
+
+    fn push(&mut self, data: &[u8]) -> Result<Vec<Vec<u8>>, AppError> {
+        let received = self.chunker.total_size() + data.len() as u64;
+        if received > self.limit {
+            return Err(AppError::PayloadTooLarge(format!(
+                "File exceeds the {} byte upload limit", self.limit
+            )));
+        }
+        if let Some(size) = self.declared_size.filter(|&size| received > size) {
+            return Err(AppError::BadRequest(format!(
+                "Size mismatch: received more than the declared {} bytes", size
+            )));
+        }
+        Ok(self.chunker.push(data))
+    }
+
+    /// Remaining chunks, whole-file hash and size
+    fn finish(self) -> Result<FinishedChunks, AppError> {
+        let size_bytes = self.chunker.total_size();
+        if let Some(size) = self.declared_size.filter(|&size| size != size_bytes) {
+            return Err(AppError::BadRequest(format!(
+                "Size mismatch: received {} bytes, but size is {}",
+                size_bytes, size
+            )));
+        }
+        let (chunks, file_hash) = self.chunker.finish();
+        Ok((chunks, file_hash, size_bytes as i64))
+    }
+}
+
+/// File part of a multipart upload, chunked and stored
+struct StreamedUpload {
+    content_hash: String,
+    size_bytes: i64,
+    tier: ChunkTier,
+    chunks: Vec<chunks::ChunkInfo>,
+}
+
+/// Store one chunk of a streamed upload (unless already stored) and append it
+/// to the version's chunk list
+async fn store_upload_chunk(
+    state: &AppState,
+    tier: ChunkTier,
+    data: &[u8],
+    chunk_infos: &mut Vec<chunks::ChunkInfo>,
+) -> Result<(), AppError> {
+    let hash = blake3::hash(data).to_hex().to_string();
+    if chunks::chunk_exists(&state.db, &hash).await? {
+        state.metrics.record_dedup(1, 0);
+    } else {
+        store_chunk(&state.blob_manager, &state.db, &hash, data, tier)
+            .await
+            .map_err(|e| AppError::from(e.context("Failed to store chunk")))?;
+        state.metrics.record_dedup(0, 1);
+    }
+
+    let offset_in_file = chunk_infos
+        .last()
+        .map_or(0, |c| c.offset_in_file + c.size_bytes as i64);
+    chunk_infos.push(chunks::ChunkInfo {
+        hash,
+        size_bytes: data.len() as i32,
+        offset_in_file,
+    });
+    Ok(())
+}
+
+/// Map a multipart parse error, keeping axum's body-limit status
+fn multipart_error(err: MultipartError) -> AppError {
+    if err.status() == StatusCode::PAYLOAD_TOO_LARGE {
+        AppError::PayloadTooLarge(err.body_text())
+    } else {
+        AppError::BadRequest(err.body_text())
+    }
+}
+
 /// Download a file version by streaming its chunks
 /// GET /v1/files/:version_id/download
 ///
 /// Returns a streaming response that reconstructs the file from its chunks.
-/// Memory-safe: only one chunk is in memory at a time.
+/// Memory-safe: at most the prefetch depth of chunks is in memory at a time.
+/// Accepts a full version or file ID, or a unique version ID prefix (8+ chars).
 async fn download_v1_file(
     State(state): State<AppState>,
-    Path(version_id): Path<Uuid>,
+    Path(version_id): Path<String>,
+    headers: axum::http::HeaderMap,
+) -> Result<axum::response::Response, AppError> {
+    let user_id = extract_user_id(&state, &headers).await?;
+    download_response(&state, user_id, &version_id, &headers).await
+}
+
+/// Headers of a download without its body
+/// HEAD /v1/files/:version_id/download
+///
+/// Resolves the version like `GET` but reads no chunks, so clients can check
+/// existence and size cheaply.
+async fn head_v1_file(
+    State(state): State<AppState>,
+    Path(version_id): Path<String>,
     headers: axum::http::HeaderMap,
 ) -> Result<axum::response::Response, AppError> {
-    let user_id = extract_user_id(&state, &headers)?;
+    let user_id = extract_user_id(&state, &headers).await?;
+    let (version, file_path) = resolve_download(&state, user_id, &version_id).await?;
+    if !version.is_chunked && !state.blob_manager.legacy_exists(version.content_hash()).await? {
+        return Err(AppError::NotFound("Blob not found".into()));
+    }
+    if is_not_modified(&headers, &version_etag(version.id), version.created_at) {
+        return Ok(not_modified_response(&version));
+    }
+    download_headers(&version, &file_path)
+        .body(Body::empty())
+        .map_err(|e| AppError::Internal(format!("Failed to build response: {}", e)))
+}
+
+/// Resolve a version or file ID owned by `user_id` to the version to serve
+/// and the path it is served under
+async fn resolve_download(
+    state: &AppState,
+    user_id: Uuid,
+    raw_id: &str,
+) -> Result<(versions::VersionExt, String), AppError> {
+    let version_id = resolve_version_id(state, raw_id, user_id).await?;
 
     // 1. Try to resolve as version first
     let (version, file_path) = match versions::get_version_ext(&state.db, version_id).await? {
@@ -466,9 +1838,17 @@ async fn download_v1_file(
             let f = files::get_file_by_id_with_owner(&state.db, version_id, user_id)
                 .await?
                 .ok_or_else(|| AppError::NotFound("File/Version not found".into()))?;
-            
-            let current_version_id = f.current_version_id
-                .ok_or_else(|| AppError::NotFound("File has no current version".into()))?;
+
+            // Links serve the target's current version under the link's own name
+            let current_version_id = match f.link_target_id {
+                Some(target_id) => files::get_file_by_id_with_owner(&state.db, target_id, user_id)
+                    .await?
+                    .filter(|t| !t.is_deleted)
+                    .ok_or_else(|| AppError::NotFound("Link target not found".into()))?
+                    .current_version_id,
+                None => f.current_version_id,
+            }
+            .ok_or_else(|| AppError::NotFound("File has no current version".into()))?;
                 
             let v = versions::get_version_ext(&state.db, current_version_id)
                 .await?
@@ -477,9 +1857,13 @@ async fn download_v1_file(
             (v, f.path)
         }
     };
-    
-    // 5. Extract filename from path for Content-Disposition
-    let filename = std::path::Path::new(&file_path)
+    Ok((version, file_path))
+}
+
+/// Response headers shared by `GET` and `HEAD` downloads
+fn download_headers(version: &versions::VersionExt, file_path: &str) -> axum::http::response::Builder {
+    // Extract filename from path for Content-Disposition
+    let filename = std::path::Path::new(file_path)
         .file_name()
         .map(|n| n.to_string_lossy().to_string())
         .unwrap_or_else(|| "download".to_string());
@@ -491,11 +1875,52 @@ async fn download_v1_file(
         .collect();
     let safe_filename = if safe_filename.is_empty() { "download".to_string() } else { safe_filename };
     
-    // 6. Determine MIME type based on file extension
-    let content_type = mime_guess::from_path(&file_path)
+    // Determine MIME type based on file extension
+    let content_type = mime_guess::from_path(file_path)
         .first_or_octet_stream()
         .to_string();
 
+    axum::response::Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::CONTENT_LENGTH, version.size_bytes.to_string())
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", safe_filename),
+        )
+        .header(header::ETAG, version_etag(version.id))
+        .header(header::LAST_MODIFIED, http_date(version.created_at))
+}
+
+/// `304 Not Modified` for a conditional download of `version`, carrying the
+/// validators a `200` would have
+pub(super) fn not_modified_response(version: &versions::VersionExt) -> axum::response::Response {
+    (
+        StatusCode::NOT_MODIFIED,
+        [
+            (header::ETAG, version_etag(version.id)),
+            (header::LAST_MODIFIED, http_date(version.created_at)),
+        ],
+    )
+        .into_response()
+}
+
+/// Streaming download response for a version or file ID owned by `user_id`
+///
+/// Shared by the v1 download route and WebDAV `GET`. Answers `304` when the
+/// request's `If-None-Match` or `If-Modified-Since` still holds.
+pub(crate) async fn download_response(
+    state: &AppState,
+    user_id: Uuid,
+    raw_id: &str,
+    headers: &axum::http::HeaderMap,
+) -> Result<axum::response::Response, AppError> {
+    let (version, file_path) = resolve_download(state, user_id, raw_id).await?;
+    let version_id = version.id;
+    if is_not_modified(headers, &version_etag(version_id), version.created_at) {
+        return Ok(not_modified_response(&version));
+    }
+
     tracing::debug!(
         "Streaming download for version {} ({} bytes)",
         version_id, version.size_bytes
@@ -510,52 +1935,16 @@ async fn download_v1_file(
             return Err(AppError::NotFound("Version has no chunks".into()));
         }
         
-        // Create async stream that yields chunk data in order
-        let blob_manager = state.blob_manager.clone();
-        
-        let stream = async_stream::stream! {
-            for (_vc, chunk) in chunk_list {
-                match chunk.location() {
-                     ChunkLocation::Container { container_id, offset, length } => {
-                        let is_compressed = length < chunk.size_bytes;
-                        let location = blob_io::ChunkLocation {
-                            container_id,
-                            offset: offset as u64,
-                            length: length as u32,
-                            compressed: is_compressed,
-                        };
-                        match blob_manager.read_chunk(&location).await {
-                            Ok(data) => yield Ok::<_, std::io::Error>(axum::body::Bytes::from(data)),
-                            Err(e) => {
-                                tracing::error!("Failed to read chunk from container: {}", e);
-                                yield Err(std::io::Error::new(std::io::ErrorKind::Other, e.to_string()));
-                                return;
-                            }
-                        }
-                    },
-                    ChunkLocation::Standalone { hash } => {
-                        match blob_manager.read_legacy_blob(&hash) {
-                            Ok(data) => yield Ok::<_, std::io::Error>(axum::body::Bytes::from(data)),
-                            Err(e) => {
-                                tracing::error!("Failed to read standalone chunk {}: {}", hash, e);
-                                yield Err(std::io::Error::new(std::io::ErrorKind::Other, e.to_string()));
-                                return;
-                            }
-                        }
-                    }
-                }
-            }
-        };
+        // Chunks are read ahead concurrently, from whichever backend holds each
+        let chunk_list: Vec<_> = chunk_list.into_iter().map(|(_vc, chunk)| chunk).collect();
+        let body = Body::from_stream(state.blob_manager.read_chunks_ordered(&chunk_list).map(|read| {
+            read.map(axum::body::Bytes::from).map_err(|e| {
+                tracing::error!("Failed to read chunk: {}", e);
+                std::io::Error::other(e.to_string())
+            })
+        }));
 
-        let body = Body::from_stream(stream);
-        let response = axum::response::Response::builder()
-            .status(StatusCode::OK)
-            .header(header::CONTENT_TYPE, &content_type[..])
-            .header(header::CONTENT_LENGTH, version.size_bytes.to_string())
-            .header(
-                header::CONTENT_DISPOSITION,
-                format!("attachment; filename=\"{}\"", safe_filename),
-            )
+        let response = download_headers(&version, &file_path)
             .body(body)
             .map_err(|e| AppError::Internal(format!("Failed to build response: {}", e)))?;
         
@@ -565,7 +1954,7 @@ async fn download_v1_file(
         // Legacy/Unchunked file - serve the single blob
         let blob_hash = version.content_hash(); // Use content hash
         
-        if !state.blob_manager.legacy_exists(blob_hash)? {
+        if !state.blob_manager.legacy_exists(blob_hash).await? {
              return Err(AppError::NotFound("Blob not found".into()));
         }
         
@@ -573,7 +1962,7 @@ async fn download_v1_file(
         let hash = blob_hash.to_string();
         
         let stream = async_stream::stream! {
-            match blob_manager.read_legacy_blob(&hash) {
+            match blob_manager.read_legacy_blob(&hash).await {
                 Ok(bytes) => {
                      yield Ok::<_, std::io::Error>(axum::body::Bytes::from(bytes));
                 },
@@ -586,14 +1975,7 @@ async fn download_v1_file(
         };
 
         let body = Body::from_stream(stream);
-        let response = axum::response::Response::builder()
-            .status(StatusCode::OK)
-            .header(header::CONTENT_TYPE, &content_type[..])
-            .header(header::CONTENT_LENGTH, version.size_bytes.to_string())
-            .header(
-                header::CONTENT_DISPOSITION,
-                format!("attachment; filename=\"{}\"", safe_filename),
-            )
+        let response = download_headers(&version, &file_path)
             .body(body)
             .map_err(|e| AppError::Internal(format!("Failed to build response: {}", e)))?;
         
@@ -601,6 +1983,17 @@ async fn download_v1_file(
     }
 }
 
+/// Where a move of `from_path` to `to_path` lands: a `to_path` ending in `/`
+/// (including the root) is the folder to move into, under the source's name
+fn move_target(from_path: &str, to_path: &str) -> String {
+    if !to_path.ends_with('/') {
+        return to_path.to_string();
+    }
+    let name = from_path.trim_end_matches('/').rsplit('/').next().unwrap_or_default();
+    let slash = if from_path.ends_with('/') { "/" } else { "" };
+    format!("{}{}{}", to_path, name, slash)
+}
+
 /// Query parameters for folder zip download
 #[derive(Deserialize)]
 struct DownloadZipQuery {
@@ -616,19 +2009,83 @@ async fn download_folder_as_zip(
     Query(query): Query<DownloadZipQuery>,
     headers: axum::http::HeaderMap,
 ) -> Result<axum::response::Response, AppError> {
-    let user_id = extract_user_id(&state, &headers)?;
+    let user_id = extract_user_id(&state, &headers).await?;
+    let (folder_path, all_files) = folder_archive_files(&state, user_id, &query.path).await?;
+    let zip_filename = archive_filename(&folder_path, "zip");
+    
+    tracing::info!("Creating ZIP archive for {} with {} files", folder_path, all_files.len());
+    
+    // Stream the archive as it is built; its size is unknown up front
+    let body = Body::from_stream(zip_stream::zip_stream(&state, folder_path, all_files));
+    let response = axum::response::Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/zip")
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", zip_filename),
+        )
+        .body(body)
+        .map_err(|e| AppError::Internal(format!("Failed to build response: {}", e)))?;
+    
+    Ok(response)
+}
+
+/// Download a folder as a gzip-compressed tar archive
+/// GET /v1/files/download-tar?path=documents/
+///
+/// Like download-zip, but entries keep their recorded Unix permission bits.
+async fn download_folder_as_tar(
+    State(state): State<AppState>,
+    Query(query): Query<DownloadZipQuery>,
+    headers: axum::http::HeaderMap,
+) -> Result<axum::response::Response, AppError> {
+    let user_id = extract_user_id(&state, &headers).await?;
+    let (folder_path, all_files) = folder_archive_files(&state, user_id, &query.path).await?;
+    let tar_filename = archive_filename(&folder_path, "tar.gz");
+    
+    tracing::info!("Creating tar archive for {} with {} files", folder_path, all_files.len());
+    
+    let body = Body::from_stream(tar_stream::tar_stream(&state, folder_path, all_files));
+    let response = axum::response::Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/gzip")
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", tar_filename),
+        )
+        .body(body)
+        .map_err(|e| AppError::Internal(format!("Failed to build response: {}", e)))?;
     
+    Ok(response)
+}
+
+/// Normalize and validate a folder path, and list everything under it
+async fn folder_archive_files(
+    state: &AppState,
+    user_id: Uuid,
+    path: &str,
+) -> Result<(String, Vec<files::FileWithVersion>), AppError> {
     // Normalize folder path
-    let folder_path = if query.path.ends_with('/') {
-        query.path.clone()
+    let folder_path = if path.ends_with('/') {
+        path.to_string()
     } else {
-        format!("{}/", query.path)
+        format!("{}/", path)
     };
     
     // Validate path
-    validate_path(&folder_path)?;
+    validate_path(&folder_path, state.config.path_limits())?;
+    
+    // Get all files under this folder (including nested folders)
+    let all_files = files::list_files_by_user_under_path(&state.db, user_id, &folder_path).await?;
     
-    // Get folder name for zip filename
+    if all_files.is_empty() {
+        return Err(AppError::NotFound("No files found in folder".into()));
+    }
+    Ok((folder_path, all_files))
+}
+
+/// Download filename for an archive of `folder_path`, e.g. `documents.zip`
+fn archive_filename(folder_path: &str, extension: &str) -> String {
     let folder_name = folder_path
         .trim_end_matches('/')
         .split('/')
@@ -639,127 +2096,237 @@ async fn download_folder_as_zip(
         .chars()
         .filter(|c| c.is_alphanumeric() || *c == '_' || *c == '-')
         .collect();
-    let zip_filename = if safe_folder_name.is_empty() { 
-        "archive.zip".to_string() 
+    if safe_folder_name.is_empty() { 
+        format!("archive.{}", extension)
     } else { 
-        format!("{}.zip", safe_folder_name) 
-    };
-    
-    // Get all files under this folder (including nested folders)
-    let all_files = files::list_files_by_user_under_path(&state.db, user_id, &folder_path).await?;
-    
-    if all_files.is_empty() {
-        return Err(AppError::NotFound("No files found in folder".into()));
+        format!("{}.{}", safe_folder_name, extension)
     }
-    
-    tracing::info!("Creating ZIP archive for {} with {} files", folder_path, all_files.len());
-    
-    // Build the ZIP in memory (for simplicity - could be optimized for very large folders)
-    // For very large folders, we'd want to stream directly but zip crate doesn't support async
-    let mut zip_buffer = std::io::Cursor::new(Vec::new());
-    {
-        let mut zip = zip::ZipWriter::new(&mut zip_buffer);
-        let options = zip::write::SimpleFileOptions::default()
-            .compression_method(zip::CompressionMethod::Deflated);
-        
-        for file in &all_files {
-            // Skip folders (they're virtual)
-            if file.path.ends_with('/') {
-                continue;
-            }
-            
-            // Get version for this file
-            let version_id = match file.current_version_id {
-                Some(id) => id,
-                None => continue, // Skip files without version
-            };
-            
-            let version = match versions::get_version_ext(&state.db, version_id).await? {
-                Some(v) => v,
-                None => continue,
-            };
-            
-            // Calculate relative path within the zip
-            let relative_path = file.path.strip_prefix(&folder_path).unwrap_or(&file.path);
-            
-            // Read file content
-            let content = if version.is_chunked {
-                let chunk_list = chunks::get_version_chunks_with_location(&state.db, version.id).await?;
-                let mut file_data = Vec::with_capacity(version.size_bytes as usize);
-                
-                for (_vc, chunk) in chunk_list {
-                    match chunk.location() {
-                        ChunkLocation::Container { container_id, offset, length } => {
-                            let is_compressed = length < chunk.size_bytes;
-                            let location = blob_io::ChunkLocation {
-                                container_id,
-                                offset: offset as u64,
-                                length: length as u32,
-                                compressed: is_compressed,
-                            };
-                            match state.blob_manager.read_chunk(&location).await {
-                                Ok(data) => file_data.extend(data),
-                                Err(e) => {
-                                    tracing::warn!("Failed to read chunk for {}: {}", file.path, e);
-                                    continue;
-                                }
-                            }
-                        },
-                        ChunkLocation::Standalone { hash } => {
-                            match state.blob_manager.read_legacy_blob(&hash) {
-                                Ok(data) => file_data.extend(data),
-                                Err(e) => {
-                                    tracing::warn!("Failed to read legacy chunk for {}: {}", file.path, e);
-                                    continue;
-                                }
-                            }
-                        }
-                    }
-                }
-                file_data
-            } else {
-                // Legacy blob
-                let blob_hash = version.content_hash();
-                match state.blob_manager.read_legacy_blob(blob_hash) {
-                    Ok(data) => data,
-                    Err(e) => {
-                        tracing::warn!("Failed to read blob for {}: {}", file.path, e);
-                        continue;
-                    }
-                }
-            };
-            
-            // Add file to zip
-            if let Err(e) = zip.start_file(relative_path, options) {
-                tracing::warn!("Failed to start zip entry for {}: {}", relative_path, e);
-                continue;
-            }
-            if let Err(e) = std::io::Write::write_all(&mut zip, &content) {
-                tracing::warn!("Failed to write zip entry for {}: {}", relative_path, e);
-                continue;
-            }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::chunking::EMPTY_CHUNK_HASH;
+
+    #[test]
+    fn test_move_target_into_folder() {
+        assert_eq!(move_target("/a.txt", "/docs/"), "/docs/a.txt");
+        assert_eq!(move_target("/inbox/a.txt", "/"), "/a.txt");
+        assert_eq!(move_target("/music/", "/archive/"), "/archive/music/");
+        assert_eq!(move_target("/music", "/archive/"), "/archive/music");
+        // Without the slash it is a rename
+        assert_eq!(move_target("/a.txt", "/docs/b.txt"), "/docs/b.txt");
+        assert_eq!(move_target("/music/", "/tunes"), "/tunes");
+    }
+
+    /// Send `request` to the v1 and file routes as `user_id`, returning the
+    /// status and body
+    async fn send(
+        state: &AppState,
+        user_id: Uuid,
+        method: axum::http::Method,
+        uri: &str,
+        body: Option<serde_json::Value>,
+    ) -> (StatusCode, Vec<u8>) {
+        use tower::Service;
+
+        let token = crate::auth::create_access_token(&state.config.jwt_secret, user_id, 0, 60).unwrap();
+        let request = axum::http::Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(axum::http::header::AUTHORIZATION, format!("Bearer {}", token))
+            .header(axum::http::header::CONTENT_TYPE, "application/json")
+            .body(body.map_or_else(axum::body::Body::empty, |b| b.to_string().into()))
+            .unwrap();
+        let mut app = v1_routes()
+            .merge(super::super::files::file_routes())
+            .with_state(state.clone());
+        let response = app.call(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, body.to_vec())
+    }
+
+    #[tokio::test]
+    async fn test_empty_file_round_trip() {
+        use axum::http::Method;
+
+        let Some(pool) = crate::db::test_pool().await else { return };
+        let dir = tempfile::tempdir().unwrap();
+        if std::env::var("JWT_SECRET").is_err() {
+            std::env::set_var("JWT_SECRET", "test_secret");
         }
-        
-        zip.finish().map_err(|e| AppError::Internal(format!("Failed to finalize zip: {}", e)))?;
+        let config = crate::config::Config::from_env().unwrap();
+        let blob_manager = crate::storage::BlobManager::new(dir.path(), pool.clone()).unwrap();
+        let state = AppState::new(pool.clone(), blob_manager, config);
+        let user = crate::db::users::create_user(&pool, "alice", "unused", crate::db::users::UserRole::Editor)
+            .await
+            .unwrap();
+
+        // Sync clients send the empty chunk as the one chunk of an empty file
+        let create = serde_json::json!({
+            "path": "/empty.txt",
+            "size_bytes": 0,
+            "modified_at": chrono::Utc::now().to_rfc3339(),
+            "tier_id": ChunkTier::Granular as i16,
+            "content_hash": EMPTY_CHUNK_HASH,
+            "chunk_hashes": [EMPTY_CHUNK_HASH],
+        });
+        let (status, body) = send(&state, user.id, Method::POST, "/v1/files", Some(create)).await;
+        assert_eq!(status, StatusCode::CREATED, "{}", String::from_utf8_lossy(&body));
+        let created: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let file_id = created["id"].as_str().unwrap();
+        let version_id = created["version_id"].as_str().unwrap();
+
+        let uri = format!("/v1/files/{}/download", version_id);
+        let (status, body) = send(&state, user.id, Method::GET, &uri, None).await;
+        assert_eq!(status, StatusCode::OK, "{}", String::from_utf8_lossy(&body));
+        assert!(body.is_empty());
+
+        let uri = format!("/files/{}/chunks", file_id);
+        let (status, body) = send(&state, user.id, Method::GET, &uri, None).await;
+        assert_eq!(status, StatusCode::OK, "{}", String::from_utf8_lossy(&body));
+        let manifest: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(manifest["is_chunked"], true);
+        assert_eq!(manifest["size_bytes"], 0);
+        assert_eq!(manifest["chunks"], serde_json::json!([]));
+
+        let uri = format!("/files/{}/versions", file_id);
+        let (status, body) = send(&state, user.id, Method::GET, &uri, None).await;
+        assert_eq!(status, StatusCode::OK, "{}", String::from_utf8_lossy(&body));
+        let listed: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(listed["total"], 1);
+        assert_eq!(listed["versions"][0]["id"], version_id);
+        assert_eq!(listed["versions"][0]["size_bytes"], 0);
+        assert_eq!(listed["versions"][0]["blob_hash"], EMPTY_CHUNK_HASH);
+
+        // Found by path without listing the root
+        let (status, body) = send(&state, user.id, Method::GET, "/v1/files/entry?path=/empty.txt", None).await;
+        assert_eq!(status, StatusCode::OK, "{}", String::from_utf8_lossy(&body));
+        let entry: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!((entry["name"].as_str(), entry["version_id"].as_str()), (Some("empty.txt"), Some(version_id)));
+        let (status, _) = send(&state, user.id, Method::GET, "/v1/files/entry?path=/missing.txt", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
     }
-    
-    let zip_data = zip_buffer.into_inner();
-    let zip_size = zip_data.len();
-    
-    tracing::info!("ZIP archive created: {} bytes", zip_size);
-    
-    let body = Body::from(zip_data);
-    let response = axum::response::Response::builder()
-        .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, "application/zip")
-        .header(header::CONTENT_LENGTH, zip_size.to_string())
-        .header(
-            header::CONTENT_DISPOSITION,
-            format!("attachment; filename=\"{}\"", zip_filename),
-        )
-        .body(body)
-        .map_err(|e| AppError::Internal(format!("Failed to build response: {}", e)))?;
-    
-    Ok(response)
-}
 
+    /// Pseudo-random bytes so FastCDC finds content-defined cuts
+    fn noise(len: usize) -> Vec<u8> {
+        let mut data = vec![0u8; len];
+        blake3::Hasher::new().update(b"noise").finalize_xof().fill(&mut data);
+        data
+    }
+
+    #[test]
+    fn test_upload_chunker_streams_disk_image() {
+        let data = noise(5 * 1024 * 1024);
+        let mut chunker = UploadChunker::new("/vm/disk.iso", Some(data.len() as u64), u64::MAX);
+        assert_eq!(chunker.tier, ChunkTier::Large);
+
+        let mut chunks = Vec::new();
+        for piece in data.chunks(64 * 1024) {
+            chunks.extend(chunker.push(piece).unwrap());
+        }
+        let (rest, file_hash, size_bytes) = chunker.finish().unwrap();
+        chunks.extend(rest);
+
+        assert_eq!(size_bytes, data.len() as i64);
+        assert_eq!(file_hash, *blake3::hash(&data).as_bytes());
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|c| c.len() <= 2 * 1024 * 1024));
+        assert_eq!(chunks.concat(), data);
+    }
+
+    #[test]
+    fn test_upload_chunker_enforces_declared_size() {
+        // A tiny declared size doesn't make the upload one in-memory chunk
+        let mut chunker = UploadChunker::new("/a.bin", Some(0), u64::MAX);
+        assert_eq!(chunker.tier, ChunkTier::Granular);
+        assert!(matches!(chunker.push(b"x"), Err(AppError::BadRequest(_))));
+
+        let mut chunker = UploadChunker::new("/a.bin", Some(10), u64::MAX);
+        chunker.push(b"short").unwrap();
+        assert!(matches!(chunker.finish(), Err(AppError::BadRequest(_))));
+
+        let mut chunker = UploadChunker::new("/a.bin", None, 4);
+        assert!(matches!(chunker.push(b"too long"), Err(AppError::PayloadTooLarge(_))));
+    }
+
+    #[test]
+    fn test_changes_cursor_roundtrip() {
+        let updated_at = chrono::DateTime::from_timestamp_micros(1_734_825_600_123_456).unwrap();
+        let id = Uuid::new_v4();
+        let cursor = encode_changes_cursor(updated_at, id);
+        let (t, i) = decode_changes_cursor(&cursor).unwrap();
+        assert_eq!(t, updated_at);
+        assert_eq!(i, id);
+    }
+
+    #[test]
+    fn test_changes_cursor_rejects_garbage() {
+        assert!(decode_changes_cursor("not a cursor").is_err());
+        assert!(decode_changes_cursor("").is_err());
+    }
+
+    #[test]
+    fn test_streamed_change_is_one_flat_line() {
+        let line = StreamedChange {
+            change: FileChangeResponse {
+                id: "id".into(),
+                path: "/docs/".into(),
+                action: "modified".into(),
+                size_bytes: None,
+                blob_hash: None,
+                version_id: None,
+                is_directory: true,
+                updated_at: "2026-01-01T00:00:00+00:00".into(),
+            },
+            cursor: "abc".into(),
+        };
+        let bytes = json_line(&line).unwrap();
+        assert_eq!(bytes.iter().filter(|&&b| b == b'\n').count(), 1);
+        assert!(bytes.ends_with(b"\n"));
+
+        let value: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(value["path"], "/docs/");
+        assert_eq!(value["cursor"], "abc");
+    }
+
+    #[test]
+    fn test_decode_encryption_meta() {
+        assert_eq!(decode_encryption_meta(None).unwrap(), None);
+        assert_eq!(decode_encryption_meta(Some("AQID")).unwrap(), Some(vec![1, 2, 3]));
+        assert!(decode_encryption_meta(Some("not base64!")).is_err());
+        assert!(decode_encryption_meta(Some("")).is_err());
+        let too_big = BASE64_STANDARD.encode(vec![0u8; MAX_ENCRYPTION_META_BYTES + 1]);
+        assert!(decode_encryption_meta(Some(&too_big)).is_err());
+    }
+
+    #[test]
+    fn test_validate_tags() {
+        let tags = |pairs: &[(&str, &str)]| -> BTreeMap<String, String> {
+            pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+        };
+        assert!(validate_tags(&tags(&[("status", "approved"), ("owner", "")])).is_ok());
+        assert!(validate_tags(&tags(&[])).is_ok());
+        for bad in ["", " ", "a:b", "line\nbreak"] {
+            assert!(validate_tags(&tags(&[(bad, "x")])).is_err(), "{:?} should be rejected", bad);
+        }
+        assert!(validate_tags(&tags(&[("k", &"v".repeat(MAX_TAG_VALUE_LEN + 1))])).is_err());
+        let many: Vec<(String, String)> = (0..=MAX_TAGS_PER_FILE).map(|i| (i.to_string(), String::new())).collect();
+        assert!(validate_tags(&many.into_iter().collect()).is_err());
+    }
+
+    #[test]
+    fn test_state_hasher() {
+        let empty = StateHasher::default();
+        assert_eq!(empty.finalize(), "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262");
+        assert_eq!(empty.file_count, 0);
+
+        // Clients hash their index the same way, so this value is fixed
+        let mut hasher = StateHasher::default();
+        hasher.add("/a.txt", "h1");
+        hasher.add("/b/c.txt", "h2");
+        assert_eq!(hasher.finalize(), "36799c165e5336aca1c1a73a36614391ca18ef441753b6b4e7cd768a005c8067");
+        assert_eq!(hasher.file_count, 2);
+    }
+}
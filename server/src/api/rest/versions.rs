@@ -3,15 +3,18 @@
 //! Handles file version listing and restoration.
 
 use crate::api::AppState;
+use crate::db::audit::AuditAction;
 use crate::db::{files, versions};
 use axum::{
     extract::{Path, Query, State},
     Json,
 };
+use base64::prelude::{Engine as _, BASE64_STANDARD};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use super::error::{extract_user_id, AppError};
+use super::audit::AuditContext;
+use super::error::{extract_user_id, extract_writer_id, short_id_prefix, unique_match, AppError};
 
 // ============================================================================
 // TYPES
@@ -24,6 +27,15 @@ pub struct VersionResponse {
     pub size_bytes: i64,
     pub created_at: String,
     pub created_by: String,
+    /// Username of `created_by`; null if unknown
+    pub created_by_username: Option<String>,
+    /// Whether this is the file's current version
+    pub is_current: bool,
+    /// Base64 encryption metadata, if the client encrypted this version
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encryption_meta: Option<String>,
+    /// The uploader's note; null if none was given
+    pub note: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -44,17 +56,38 @@ pub struct RestoreResponse {
     pub new_version_id: String,
 }
 
+#[derive(Serialize)]
+pub struct RestoreTreeResponse {
+    pub success: bool,
+    /// Paths un-deleted, including the folder itself
+    pub restored: Vec<String>,
+}
+
 // ============================================================================
 // HANDLERS
 // ============================================================================
 
+/// Resolve a full version ID or a unique prefix of one (as shown by `tangle history`)
+pub async fn resolve_version_id(
+    state: &AppState,
+    raw: &str,
+    user_id: Uuid,
+) -> Result<Uuid, AppError> {
+    if let Ok(id) = Uuid::parse_str(raw) {
+        return Ok(id);
+    }
+    let prefix = short_id_prefix(raw)?;
+    let matches = versions::find_versions_by_prefix(&state.db, &prefix, user_id).await?;
+    unique_match(matches, "Version", &prefix)
+}
+
 pub async fn list_file_versions(
     State(state): State<AppState>,
     Path(id): Path<String>,
     Query(query): Query<ListVersionsQuery>,
     headers: axum::http::HeaderMap,
 ) -> Result<Json<ListVersionsResponse>, AppError> {
-    let user_id = extract_user_id(&state, &headers)?;
+    let user_id = extract_user_id(&state, &headers).await?;
     let file_id = Uuid::parse_str(&id).map_err(|_| AppError::BadRequest("Invalid file ID".into()))?;
 
     // SECURITY: Verify ownership before listing versions
@@ -72,12 +105,19 @@ pub async fn list_file_versions(
 
     let versions = version_list
         .into_iter()
-        .map(|v| VersionResponse {
-            id: v.id.to_string(),
-            blob_hash: v.blob_hash,
-            size_bytes: v.size_bytes,
-            created_at: v.created_at.to_rfc3339(),
-            created_by: v.created_by.map(|u| u.to_string()).unwrap_or_default(),
+        .map(|entry| {
+            let v = entry.version;
+            VersionResponse {
+                id: v.id.to_string(),
+                blob_hash: v.blob_hash,
+                size_bytes: v.size_bytes,
+                created_at: v.created_at.to_rfc3339(),
+                created_by: v.created_by.map(|u| u.to_string()).unwrap_or_default(),
+                created_by_username: entry.created_by_username,
+                is_current: entry.is_current,
+                encryption_meta: v.encryption_meta.map(|meta| BASE64_STANDARD.encode(meta)),
+                note: v.note,
+            }
         })
         .collect();
 
@@ -88,12 +128,12 @@ pub async fn restore_version(
     State(state): State<AppState>,
     Path((file_id, version_id)): Path<(String, String)>,
     headers: axum::http::HeaderMap,
+    audit: AuditContext,
 ) -> Result<Json<RestoreResponse>, AppError> {
-    let user_id = extract_user_id(&state, &headers)?;
+    let user_id = extract_writer_id(&state, &headers).await?;
     let file_id =
         Uuid::parse_str(&file_id).map_err(|_| AppError::BadRequest("Invalid file ID".into()))?;
-    let version_id = Uuid::parse_str(&version_id)
-        .map_err(|_| AppError::BadRequest("Invalid version ID".into()))?;
+    let version_id = resolve_version_id(&state, &version_id, user_id).await?;
 
     // SECURITY: Verify ownership before restoring version
     let file = files::get_file_by_id_with_owner(&state.db, file_id, user_id)
@@ -120,15 +160,63 @@ pub async fn restore_version(
         old_version.size_bytes,
     )
     .await?;
+    if old_version.encryption_meta.is_some() {
+        versions::set_encryption_meta(&state.db, new_version.id, old_version.encryption_meta.as_deref()).await?;
+    }
 
     // Update current version and undelete if needed
     files::set_current_version(&state.db, file.id, new_version.id).await?;
     if file.is_deleted {
         files::undelete(&state.db, file.id).await?;
     }
+    let action = if file.is_deleted { "create" } else { "modify" };
+    state.sync_hub.notify_file_changed(file.owner_id, &file.path, action);
+    audit.record(&state, Some(user_id), AuditAction::Restore, Some(&file.path)).await;
 
     Ok(Json(RestoreResponse {
         success: true,
         new_version_id: new_version.id.to_string(),
     }))
 }
+
+/// Undo a recursive folder delete
+/// POST /files/:id/restore-tree
+///
+/// Restores the folder and every descendant deleted in the same operation,
+/// each at its current version. Files deleted separately (before or after)
+/// stay deleted.
+pub async fn restore_tree(
+    State(state): State<AppState>,
+    Path(file_id): Path<String>,
+    headers: axum::http::HeaderMap,
+    audit: AuditContext,
+) -> Result<Json<RestoreTreeResponse>, AppError> {
+    let user_id = extract_writer_id(&state, &headers).await?;
+    let file_id =
+        Uuid::parse_str(&file_id).map_err(|_| AppError::BadRequest("Invalid file ID".into()))?;
+
+    let dir = files::get_file_by_id_with_owner(&state.db, file_id, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Folder not found".into()))?;
+    if !dir.path.ends_with('/') {
+        return Err(AppError::BadRequest(
+            "Not a folder; use /files/:id/restore/:version_id for files".into(),
+        ));
+    }
+    if !dir.is_deleted {
+        return Err(AppError::Conflict("Folder is not deleted".into()));
+    }
+
+    let restored = files::restore_deleted_tree(&state.db, dir.id, &dir.path, user_id).await?;
+    if restored.is_empty() {
+        return Err(AppError::Conflict(
+            "Folder was not removed by a recursive delete and cannot be restored as a tree".into(),
+        ));
+    }
+
+    tracing::info!("Restored {} entries under {}", restored.len(), dir.path);
+    state.sync_hub.notify_file_changed(dir.owner_id, &dir.path, "create");
+    audit.record(&state, Some(user_id), AuditAction::Restore, Some(&dir.path)).await;
+
+    Ok(Json(RestoreTreeResponse { success: true, restored }))
+}
@@ -0,0 +1,254 @@
+//! Streaming ZIP archives for folder downloads
+//!
+//! The `zip` crate is synchronous and seeks back to patch each local file
+//! header once the entry's CRC and sizes are known, so archives are built on
+//! a blocking task. Each entry is spooled to an anonymous temp file while it
+//! is written; when the zip writer finishes the entry it flushes, and the
+//! spool is drained into the channel backing the response body. Memory use is
+//! bounded by the channel, disk use by the largest single entry.
+
+use crate::api::AppState;
+use crate::db::files::FileWithVersion;
+use crate::db::{chunks, versions, Chunk};
+use axum::body::Bytes;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use tokio::runtime::Handle;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::Stream;
+
+/// Pieces of archive data buffered between the zip task and the response
+pub(super) const CHANNEL_DEPTH: usize = 8;
+
+/// Size of each piece sent to the response body
+pub(super) const PIECE_BYTES: usize = 256 * 1024;
+
+/// Members at least this large get ZIP64 headers. Deflate can slightly grow
+/// incompressible data, so the cutoff sits below the 4GB limit.
+const ZIP64_MEMBER_THRESHOLD: u64 = u32::MAX as u64 - 64 * 1024 * 1024;
+
+/// Stream a ZIP of `files`, with entry names relative to `folder_path`.
+///
+/// Directories and files without a current version are skipped. A read
+/// failure mid-archive ends the stream with an error rather than emitting a
+/// corrupt entry.
+pub fn zip_stream(
+    state: &AppState,
+    folder_path: String,
+    files: Vec<FileWithVersion>,
+) -> impl Stream<Item = io::Result<Bytes>> {
+    let (tx, rx) = mpsc::channel(CHANNEL_DEPTH);
+    let state = state.clone();
+    let handle = Handle::current();
+
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = write_archive(&handle, &state, &folder_path, &files, tx.clone()) {
+            tracing::warn!("ZIP archive for {} aborted: {}", folder_path, e);
+            let _ = tx.blocking_send(Err(io::Error::other(e.to_string())));
+        }
+    });
+
+    ReceiverStream::new(rx)
+}
+
+fn write_archive(
+    handle: &Handle,
+    state: &AppState,
+    folder_path: &str,
+    files: &[FileWithVersion],
+    tx: mpsc::Sender<io::Result<Bytes>>,
+) -> anyhow::Result<()> {
+    let mut zip = zip::ZipWriter::new(SpoolWriter::new(tx)?);
+    zip.set_flush_on_finish_file(true);
+
+    let mut entries = 0usize;
+    for file in files {
+        // Skip folders (they're virtual)
+        if file.path.ends_with('/') {
+            continue;
+        }
+        let Some(member) = Member::lookup(handle, state, file)? else {
+            continue;
+        };
+
+        let relative_path = file.path.strip_prefix(folder_path).unwrap_or(&file.path);
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated)
+            .large_file(member.size >= ZIP64_MEMBER_THRESHOLD);
+        zip.start_file(relative_path, options)?;
+        member.write_to(handle, state, &mut zip)?;
+        entries += 1;
+    }
+
+    // Central directory and footer
+    zip.finish()?.flush()?;
+    tracing::info!("ZIP archive for {} streamed with {} entries", folder_path, entries);
+    Ok(())
+}
+
+/// Content of a file's current version, as written into an archive
+pub(super) struct Member {
+    pub(super) size: u64,
+    content: MemberContent,
+}
+
+enum MemberContent {
+    Chunks(Vec<Chunk>),
+    /// Legacy single-blob version, already read
+    Blob(Vec<u8>),
+}
+
+impl Member {
+    /// Look up the current version of `file`. None for files without one,
+    /// and for legacy blobs that can't be read (logged and left out).
+    pub(super) fn lookup(handle: &Handle, state: &AppState, file: &FileWithVersion) -> anyhow::Result<Option<Self>> {
+        let Some(version_id) = file.current_version_id else {
+            return Ok(None);
+        };
+        let Some(version) = handle.block_on(versions::get_version_ext(&state.db, version_id))? else {
+            return Ok(None);
+        };
+
+        let content = if version.is_chunked {
+            let chunk_list =
+                handle.block_on(chunks::get_version_chunks_with_location(&state.db, version.id))?;
+            MemberContent::Chunks(chunk_list.into_iter().map(|(_vc, chunk)| chunk).collect())
+        } else {
+            match handle.block_on(state.blob_manager.read_legacy_blob(version.content_hash())) {
+                Ok(data) => MemberContent::Blob(data),
+                Err(e) => {
+                    tracing::warn!("Failed to read blob for {}: {}", file.path, e);
+                    return Ok(None);
+                }
+            }
+        };
+        Ok(Some(Member {
+            size: version.size_bytes as u64,
+            content,
+        }))
+    }
+
+    /// Write the content to `out`, reading one chunk at a time
+    pub(super) fn write_to(self, handle: &Handle, state: &AppState, out: &mut impl Write) -> anyhow::Result<()> {
+        match self.content {
+            MemberContent::Chunks(chunk_list) => {
+                for chunk in chunk_list {
+                    let data = handle.block_on(state.blob_manager.read_chunk(&chunk))?;
+                    out.write_all(&data)?;
+                }
+            }
+            MemberContent::Blob(data) => out.write_all(&data)?,
+        }
+        Ok(())
+    }
+}
+
+/// `Write + Seek` sink that spools bytes to a temp file and, on `flush`,
+/// sends everything spooled so far to the channel.
+///
+/// Positions are archive offsets; seeking back before data already sent is
+/// an error.
+struct SpoolWriter {
+    spool: std::fs::File,
+    /// Archive offset of the spool's first byte (bytes already sent)
+    sent: u64,
+    tx: mpsc::Sender<io::Result<Bytes>>,
+}
+
+impl SpoolWriter {
+    fn new(tx: mpsc::Sender<io::Result<Bytes>>) -> io::Result<Self> {
+        Ok(SpoolWriter { spool: tempfile::tempfile()?, sent: 0, tx })
+    }
+}
+
+impl Write for SpoolWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.spool.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let len = self.spool.metadata()?.len();
+        // Only drain when positioned at the end, i.e. nothing is pending a patch
+        if len == 0 || self.spool.stream_position()? != len {
+            return Ok(());
+        }
+
+        self.spool.seek(SeekFrom::Start(0))?;
+        let mut remaining = len;
+        while remaining > 0 {
+            let mut piece = vec![0u8; remaining.min(PIECE_BYTES as u64) as usize];
+            self.spool.read_exact(&mut piece)?;
+            remaining -= piece.len() as u64;
+            self.tx
+                .blocking_send(Ok(Bytes::from(piece)))
+                .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "client disconnected"))?;
+        }
+
+        self.spool.set_len(0)?;
+        self.spool.seek(SeekFrom::Start(0))?;
+        self.sent += len;
+        Ok(())
+    }
+}
+
+// `ZipWriter::set_flush_on_finish_file` requires `Read`; only unsent bytes are readable
+impl Read for SpoolWriter {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.spool.read(buf)
+    }
+}
+
+impl Seek for SpoolWriter {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(offset) => {
+                let local = offset.checked_sub(self.sent).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::Unsupported, "cannot seek into data already sent")
+                })?;
+                SeekFrom::Start(local)
+            }
+            relative => relative,
+        };
+        Ok(self.sent + self.spool.seek(pos)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spooled_archive_is_readable() {
+        let (tx, mut rx) = mpsc::channel(1024);
+        let mut zip = zip::ZipWriter::new(SpoolWriter::new(tx).unwrap());
+        zip.set_flush_on_finish_file(true);
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated);
+        zip.start_file("a.txt", options).unwrap();
+        zip.write_all(b"hello").unwrap();
+        zip.start_file("dir/b.txt", options.large_file(true)).unwrap();
+        zip.write_all(&[42u8; 100_000]).unwrap();
+        zip.finish().unwrap().flush().unwrap();
+
+        let mut archive = Vec::new();
+        while let Ok(piece) = rx.try_recv() {
+            archive.extend_from_slice(&piece.unwrap());
+        }
+
+        let mut reader = zip::ZipArchive::new(io::Cursor::new(archive)).unwrap();
+        let mut contents = String::new();
+        reader.by_name("a.txt").unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "hello");
+        assert_eq!(reader.by_name("dir/b.txt").unwrap().size(), 100_000);
+    }
+
+    #[test]
+    fn test_spool_rejects_seek_into_sent_data() {
+        let (tx, _rx) = mpsc::channel(16);
+        let mut spool = SpoolWriter::new(tx).unwrap();
+        spool.write_all(b"abc").unwrap();
+        spool.flush().unwrap();
+        assert_eq!(spool.stream_position().unwrap(), 3);
+        assert!(spool.seek(SeekFrom::Start(1)).is_err());
+    }
+}
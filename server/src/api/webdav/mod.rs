@@ -0,0 +1,347 @@
+//! Read-only WebDAV access
+//!
+//! Serves the file tree under `/webdav/` so it can be mounted as a network
+//! drive in Finder or Explorer. Only `OPTIONS`, `PROPFIND`, `GET` and `HEAD`
+//! are supported; every other method returns `405`. Clients authenticate with
+//! HTTP Basic credentials, checked against the same password hashes as
+//! `/auth/login`. Accounts with TOTP enabled cannot use WebDAV, since Basic
+//! auth has no way to carry a second factor. Verified credentials are
+//! remembered for [`CREDENTIAL_TTL`], since a client walking a tree sends
+//! them with every request and each Argon2 check is deliberately slow.
+//!
+//! Listings come from [`files::list_directory`] and downloads from the v1
+//! download path, so WebDAV sees exactly what the sync clients see.
+
+mod xml;
+
+use crate::api::rest::{download_response, AppError};
+use crate::api::AppState;
+use crate::auth;
+use crate::db::files::{self, DirectoryEntry};
+use crate::db::users;
+use axum::body::Body;
+use axum::extract::{Path, State};
+use axum::http::{header, HeaderMap, HeaderValue, Method, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::any;
+use axum::Router;
+use base64::Engine;
+use chrono::Utc;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+use xml::DavProps;
+
+/// URL prefix the WebDAV tree is served under
+const MOUNT: &str = "/webdav";
+
+/// Methods advertised in `Allow`
+const ALLOWED_METHODS: &str = "OPTIONS, PROPFIND, GET, HEAD";
+
+/// Realm sent in Basic auth challenges
+const REALM: &str = "Basic realm=\"Entanglement\", charset=\"UTF-8\"";
+
+/// How long verified credentials are accepted without checking the
+/// password again. A disabled account or newly enabled TOTP is refused once
+/// this runs out.
+const CREDENTIAL_TTL: Duration = Duration::from_secs(60);
+
+/// User and verification time, by BLAKE3 hash of the `Authorization` header
+type VerifiedCredentials = HashMap<[u8; 32], (Uuid, Instant)>;
+
+/// Recently verified Basic credentials, keyed by a BLAKE3 hash of the
+/// `Authorization` header so no password is kept in memory
+#[derive(Clone, Default)]
+pub struct CredentialCache {
+    verified: Arc<Mutex<VerifiedCredentials>>,
+}
+
+impl CredentialCache {
+    fn get(&self, key: &[u8; 32]) -> Option<Uuid> {
+        let verified = self.verified.lock().unwrap_or_else(|e| e.into_inner());
+        verified
+            .get(key)
+            .filter(|(_, at)| at.elapsed() < CREDENTIAL_TTL)
+            .map(|(user_id, _)| *user_id)
+    }
+
+    fn insert(&self, key: [u8; 32], user_id: Uuid) {
+        let mut verified = self.verified.lock().unwrap_or_else(|e| e.into_inner());
+        verified.retain(|_, (_, at)| at.elapsed() < CREDENTIAL_TTL);
+        verified.insert(key, (user_id, Instant::now()));
+    }
+}
+
+// ============================================================================
+// ROUTES
+// ============================================================================
+
+pub fn webdav_routes() -> Router<AppState> {
+    Router::new()
+        .route("/webdav", any(handle_root))
+        .route("/webdav/", any(handle_root))
+        .route("/webdav/*path", any(handle_path))
+}
+
+// ============================================================================
+// HANDLERS
+// ============================================================================
+
+async fn handle_root(State(state): State<AppState>, method: Method, headers: HeaderMap) -> Response {
+    dispatch(&state, method, &headers, "").await
+}
+
+async fn handle_path(
+    State(state): State<AppState>,
+    Path(path): Path<String>,
+    method: Method,
+    headers: HeaderMap,
+) -> Response {
+    dispatch(&state, method, &headers, &path).await
+}
+
+async fn dispatch(state: &AppState, method: Method, headers: &HeaderMap, path: &str) -> Response {
+    // Clients probe OPTIONS before sending credentials
+    if method == Method::OPTIONS {
+        return (
+            StatusCode::OK,
+            [
+                (header::ALLOW, ALLOWED_METHODS),
+                (header::HeaderName::from_static("dav"), "1"),
+                (header::HeaderName::from_static("ms-author-via"), "DAV"),
+            ],
+        )
+            .into_response();
+    }
+    if !matches!(method.as_str(), "PROPFIND" | "GET" | "HEAD") {
+        return (StatusCode::METHOD_NOT_ALLOWED, [(header::ALLOW, ALLOWED_METHODS)]).into_response();
+    }
+
+    let user_id = match authenticate(state, headers).await {
+        Ok(user_id) => user_id,
+        Err(response) => return response,
+    };
+
+    let path = path.trim_matches('/');
+    let result = if method.as_str() == "PROPFIND" {
+        propfind(state, user_id, headers, path).await
+    } else {
+        get(state, user_id, headers, path).await
+    };
+    result.unwrap_or_else(IntoResponse::into_response)
+}
+
+/// PROPFIND with `Depth: 0` or `1`; all live properties are returned
+/// regardless of the request body
+async fn propfind(state: &AppState, user_id: Uuid, headers: &HeaderMap, path: &str) -> Result<Response, AppError> {
+    let Some(depth) = parse_depth(headers) else {
+        return Ok(xml_response(StatusCode::FORBIDDEN, xml::finite_depth_error()));
+    };
+
+    let Some(resource) = resolve(state, user_id, path).await? else {
+        return Err(AppError::NotFound("Resource not found".into()));
+    };
+
+    let mut entries = vec![props(path, &resource)];
+    if depth == 1 && resource.is_folder {
+        // Scoped like `resolve`, so every listed child can be opened
+        for child in files::list_directory_with_owner(&state.db, user_id, path).await? {
+            let child_path = if path.is_empty() {
+                child.name.clone()
+            } else {
+                format!("{}/{}", path, child.name)
+            };
+            entries.push(props(&child_path, &child));
+        }
+    }
+
+    Ok(xml_response(StatusCode::MULTI_STATUS, xml::multistatus(&entries)))
+}
+
+/// GET/HEAD streams the file's current version
+async fn get(state: &AppState, user_id: Uuid, headers: &HeaderMap, path: &str) -> Result<Response, AppError> {
+    let Some(resource) = resolve(state, user_id, path).await? else {
+        return Err(AppError::NotFound("Resource not found".into()));
+    };
+    if resource.is_folder {
+        return Ok((StatusCode::METHOD_NOT_ALLOWED, [(header::ALLOW, "OPTIONS, PROPFIND")]).into_response());
+    }
+    download_response(state, user_id, &resource.id, headers).await
+}
+
+// ============================================================================
+// HELPERS
+// ============================================================================
+
+/// Look up the entry at `path` (relative, no surrounding slashes) as its
+/// parent's listing shows it. The root is always a collection.
+async fn resolve(state: &AppState, user_id: Uuid, path: &str) -> Result<Option<DirectoryEntry>, AppError> {
+    if path.is_empty() {
+        return Ok(Some(DirectoryEntry {
+            id: String::new(),
+            name: String::new(),
+            path: "/".into(),
+            is_folder: true,
+            size_bytes: 0,
+            updated_at: Utc::now(),
+            version_id: None,
+            link_target_id: None,
+        }));
+    }
+
+    Ok(files::get_directory_entry(&state.db, user_id, path).await?)
+}
+
+fn props(path: &str, entry: &DirectoryEntry) -> DavProps {
+    DavProps {
+        path: path.to_string(),
+        display_name: entry.name.clone(),
+        is_collection: entry.is_folder,
+        size_bytes: entry.size_bytes,
+        updated_at: entry.updated_at,
+        etag: entry.version_id.map(|v| v.to_string()),
+    }
+}
+
+fn xml_response(status: StatusCode, body: String) -> Response {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/xml; charset=utf-8")
+        .body(Body::from(body))
+        .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())
+}
+
+/// Verify Basic credentials, returning the user ID or a `401` challenge
+async fn authenticate(state: &AppState, headers: &HeaderMap) -> Result<Uuid, Response> {
+    let challenge = || {
+        (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, HeaderValue::from_static(REALM))],
+        )
+            .into_response()
+    };
+
+    let Some((username, password)) = parse_basic_auth(headers) else {
+        return Err(challenge());
+    };
+    let key = *blake3::hash(headers[header::AUTHORIZATION].as_bytes()).as_bytes();
+    if let Some(user_id) = state.webdav_credentials.get(&key) {
+        return Ok(user_id);
+    }
+
+    let user = match users::get_user_by_username(&state.db, &username).await {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            tracing::warn!("WebDAV login for unknown user: {}", username);
+            return Err(challenge());
+        }
+        Err(e) => {
+            tracing::error!("Database error during WebDAV login: {}", e);
+            return Err(AppError::Internal("Database error".into()).into_response());
+        }
+    };
+
+    match auth::verify_password(&password, &user.password_hash) {
+        Ok(true) => {}
+        Ok(false) => {
+            tracing::warn!("Invalid WebDAV password for user: {}", username);
+            return Err(challenge());
+        }
+        Err(e) => {
+            tracing::error!("Password verification error: {}", e);
+            return Err(AppError::Internal("Authentication error".into()).into_response());
+        }
+    }
+
+    if user.disabled {
+        tracing::warn!("WebDAV login refused for disabled user: {}", username);
+        return Err(AppError::Forbidden("Account is disabled".into()).into_response());
+    }
+
+    // Basic auth has no second factor, so TOTP-protected accounts are refused
+    match users::get_totp(&state.db, user.id).await {
+        Ok(Some(totp)) if totp.enabled => {
+            tracing::warn!("WebDAV login refused for TOTP-enabled user: {}", username);
+            Err(challenge())
+        }
+        Ok(_) => {
+            state.webdav_credentials.insert(key, user.id);
+            Ok(user.id)
+        }
+        Err(e) => {
+            tracing::error!("Database error during WebDAV login: {}", e);
+            Err(AppError::Internal("Database error".into()).into_response())
+        }
+    }
+}
+
+/// Decode `Authorization: Basic <base64(user:pass)>`
+fn parse_basic_auth(headers: &HeaderMap) -> Option<(String, String)> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, encoded) = value.split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(encoded.trim())
+        .ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+    Some((username.to_string(), password.to_string()))
+}
+
+/// PROPFIND depth; `None` for `infinity`, which is the default when the
+/// header is absent (RFC 4918 section 9.1) and is not supported
+fn parse_depth(headers: &HeaderMap) -> Option<u8> {
+    match headers.get("depth").and_then(|v| v.to_str().ok()).map(str::trim) {
+        Some("0") => Some(0),
+        Some("1") => Some(1),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(name: &'static str, value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn test_parse_basic_auth() {
+        // "alice:pa:ss" - only the first colon separates the password
+        let parsed = parse_basic_auth(&headers("authorization", "Basic YWxpY2U6cGE6c3M="));
+        assert_eq!(parsed, Some(("alice".into(), "pa:ss".into())));
+
+        assert_eq!(parse_basic_auth(&headers("authorization", "Bearer abc")), None);
+        assert_eq!(parse_basic_auth(&headers("authorization", "Basic !!!")), None);
+        assert_eq!(parse_basic_auth(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn test_credential_cache_expires() {
+        let cache = CredentialCache::default();
+        let user_id = Uuid::new_v4();
+        cache.insert([1; 32], user_id);
+        assert_eq!(cache.get(&[1; 32]), Some(user_id));
+        assert_eq!(cache.get(&[2; 32]), None);
+
+        let stale = Instant::now() - CREDENTIAL_TTL;
+        cache.verified.lock().unwrap().get_mut(&[1; 32]).unwrap().1 = stale;
+        assert_eq!(cache.get(&[1; 32]), None);
+        cache.insert([2; 32], user_id);
+        assert!(!cache.verified.lock().unwrap().contains_key(&[1; 32]));
+    }
+
+    #[test]
+    fn test_parse_depth() {
+        assert_eq!(parse_depth(&headers("depth", "0")), Some(0));
+        assert_eq!(parse_depth(&headers("depth", "1")), Some(1));
+        assert_eq!(parse_depth(&headers("depth", "infinity")), None);
+        assert_eq!(parse_depth(&HeaderMap::new()), None);
+    }
+}
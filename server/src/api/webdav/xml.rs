@@ -0,0 +1,139 @@
+//! WebDAV XML rendering (RFC 4918 multistatus bodies)
+
+use chrono::{DateTime, Utc};
+
+/// Properties reported for one resource in a PROPFIND response
+pub struct DavProps {
+    /// Path relative to the WebDAV root, without leading or trailing slashes
+    pub path: String,
+    pub display_name: String,
+    pub is_collection: bool,
+    pub size_bytes: i64,
+    pub updated_at: DateTime<Utc>,
+    /// Entity tag (version ID for files)
+    pub etag: Option<String>,
+}
+
+/// Render a `207 Multi-Status` body listing `entries` with all live properties
+pub fn multistatus(entries: &[DavProps]) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n");
+    for entry in entries {
+        xml.push_str("<D:response>\n");
+        xml.push_str(&format!("<D:href>{}</D:href>\n", escape(&href(&entry.path, entry.is_collection))));
+        xml.push_str("<D:propstat>\n<D:prop>\n");
+        xml.push_str(&format!("<D:displayname>{}</D:displayname>\n", escape(&entry.display_name)));
+        xml.push_str(&format!(
+            "<D:getlastmodified>{}</D:getlastmodified>\n",
+            http_date(entry.updated_at)
+        ));
+        if entry.is_collection {
+            xml.push_str("<D:resourcetype><D:collection/></D:resourcetype>\n");
+        } else {
+            let content_type = mime_guess::from_path(&entry.path).first_or_octet_stream();
+            xml.push_str("<D:resourcetype/>\n");
+            xml.push_str(&format!("<D:getcontentlength>{}</D:getcontentlength>\n", entry.size_bytes));
+            xml.push_str(&format!("<D:getcontenttype>{}</D:getcontenttype>\n", escape(content_type.as_ref())));
+            if let Some(etag) = &entry.etag {
+                xml.push_str(&format!("<D:getetag>\"{}\"</D:getetag>\n", escape(etag)));
+            }
+        }
+        xml.push_str("</D:prop>\n<D:status>HTTP/1.1 200 OK</D:status>\n</D:propstat>\n</D:response>\n");
+    }
+    xml.push_str("</D:multistatus>\n");
+    xml
+}
+
+/// Body for a `403` response to a PROPFIND with `Depth: infinity`
+pub fn finite_depth_error() -> String {
+    "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:error xmlns:D=\"DAV:\"><D:propfind-finite-depth/></D:error>\n"
+        .to_string()
+}
+
+/// Absolute href for a resource, percent-encoding each path segment
+pub fn href(path: &str, is_collection: bool) -> String {
+    let mut href = String::from(super::MOUNT);
+    for segment in path.split('/').filter(|s| !s.is_empty()) {
+        href.push('/');
+        for byte in segment.bytes() {
+            if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+                href.push(byte as char);
+            } else {
+                href.push_str(&format!("%{:02X}", byte));
+            }
+        }
+    }
+    if is_collection {
+        href.push('/');
+    }
+    href
+}
+
+/// RFC 1123 date, as required for `getlastmodified`
+fn http_date(dt: DateTime<Utc>) -> String {
+    dt.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// Escape text for use in XML character data
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_href_encodes_segments() {
+        assert_eq!(href("", true), "/webdav/");
+        assert_eq!(href("docs/a b&c.txt", false), "/webdav/docs/a%20b%26c.txt");
+        assert_eq!(href("docs/caf\u{e9}", true), "/webdav/docs/caf%C3%A9/");
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("a<b>&\"c'"), "a&lt;b&gt;&amp;&quot;c&apos;");
+    }
+
+    #[test]
+    fn test_multistatus_properties() {
+        let updated_at = Utc.with_ymd_and_hms(2026, 1, 2, 3, 4, 5).unwrap();
+        let xml = multistatus(&[
+            DavProps {
+                path: "docs".into(),
+                display_name: "docs".into(),
+                is_collection: true,
+                size_bytes: 0,
+                updated_at,
+                etag: None,
+            },
+            DavProps {
+                path: "docs/r&d.txt".into(),
+                display_name: "r&d.txt".into(),
+                is_collection: false,
+                size_bytes: 42,
+                updated_at,
+                etag: Some("v1".into()),
+            },
+        ]);
+
+        assert!(xml.contains("<D:href>/webdav/docs/</D:href>"));
+        assert!(xml.contains("<D:resourcetype><D:collection/></D:resourcetype>"));
+        assert!(xml.contains("<D:displayname>r&amp;d.txt</D:displayname>"));
+        assert!(xml.contains("<D:getcontentlength>42</D:getcontentlength>"));
+        assert!(xml.contains("<D:getcontenttype>text/plain</D:getcontenttype>"));
+        assert!(xml.contains("<D:getlastmodified>Fri, 02 Jan 2026 03:04:05 GMT</D:getlastmodified>"));
+        assert!(xml.contains("<D:getetag>\"v1\"</D:getetag>"));
+    }
+}
@@ -0,0 +1,177 @@
+//! File change webhook
+//!
+//! When `WEBHOOK_URL` is set, every file change the [`SyncHub`] broadcasts
+//! (create, modify, move, delete) is POSTed there as JSON, whoever owns the file.
+//! With `WEBHOOK_SECRET` the body is signed with HMAC-SHA256 and the
+//! signature sent as `X-Signature: sha256=<hex>`.
+//!
+//! Events wait in a queue of `WEBHOOK_QUEUE_SIZE` and are delivered one at a
+//! time, in order. A failed delivery (error or non-2xx) is retried
+//! `WEBHOOK_MAX_RETRIES` times with doubling backoff, then dropped. While
+//! the queue is full new events are dropped and logged, so a slow or dead
+//! webhook never holds up requests. Retries carry the same `id`, for
+//! receivers that need to ignore duplicates.
+
+use crate::api::ws::{SyncHub, SyncNotification};
+use crate::config::Config;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+use uuid::Uuid;
+
+/// Time allowed for one delivery attempt
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Wait before the first retry; doubled for each one after
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Longest wait between retries
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Body POSTed to the webhook
+#[derive(Debug, Serialize)]
+struct WebhookEvent {
+    id: Uuid,
+    event: String,
+    action: String,
+    path: String,
+    /// `None` for ownerless shared files
+    owner_id: Option<Uuid>,
+    timestamp: DateTime<Utc>,
+}
+
+impl From<SyncNotification> for WebhookEvent {
+    fn from(notification: SyncNotification) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            event: notification.msg_type,
+            action: notification.action,
+            path: notification.path,
+            owner_id: notification.owner_id,
+            timestamp: Utc::now(),
+        }
+    }
+}
+
+struct Webhook {
+    client: reqwest::Client,
+    url: String,
+    secret: Option<String>,
+    max_retries: u32,
+}
+
+/// Start forwarding file changes to `WEBHOOK_URL`. Does nothing when it isn't set.
+pub fn spawn(config: &Config, sync_hub: &SyncHub) -> anyhow::Result<()> {
+    let Some(url) = config.webhook_url.clone() else {
+        return Ok(());
+    };
+    let webhook = Webhook {
+        client: reqwest::Client::builder().timeout(DELIVERY_TIMEOUT).build()?,
+        url,
+        secret: config.webhook_secret.clone(),
+        max_retries: config.webhook_max_retries,
+    };
+    tracing::info!(
+        "file changes are sent to {} (WEBHOOK_URL){}",
+        webhook.url,
+        if webhook.secret.is_some() { ", signed" } else { "" }
+    );
+
+    let (tx, rx) = mpsc::channel(config.webhook_queue_size);
+    tokio::spawn(enqueue(sync_hub.subscribe_all(), tx));
+    tokio::spawn(deliver(webhook, rx));
+    Ok(())
+}
+
+/// Move hub notifications into the bounded queue without ever waiting on it
+async fn enqueue(mut notifications: broadcast::Receiver<SyncNotification>, queue: mpsc::Sender<WebhookEvent>) {
+    loop {
+        match notifications.recv().await {
+            Ok(notification) => {
+                let event = WebhookEvent::from(notification);
+                if let Err(mpsc::error::TrySendError::Full(event)) = queue.try_send(event) {
+                    tracing::warn!("webhook queue full, dropping {} {}", event.action, event.path);
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                tracing::warn!("webhook fell behind, {} file changes not sent", missed);
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+async fn deliver(webhook: Webhook, mut queue: mpsc::Receiver<WebhookEvent>) {
+    while let Some(event) = queue.recv().await {
+        let body = match serde_json::to_vec(&event) {
+            Ok(body) => body,
+            Err(e) => {
+                tracing::warn!("webhook event not serializable: {}", e);
+                continue;
+            }
+        };
+
+        let mut backoff = INITIAL_BACKOFF;
+        for attempt in 0..=webhook.max_retries {
+            if attempt > 0 {
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+            match webhook.post(&body).await {
+                Ok(()) => break,
+                Err(e) if attempt < webhook.max_retries => {
+                    tracing::debug!("webhook delivery of {} failed, retrying: {}", event.id, e);
+                }
+                Err(e) => tracing::warn!(
+                    "webhook gave up on {} {} after {} attempts: {}",
+                    event.action,
+                    event.path,
+                    attempt + 1,
+                    e
+                ),
+            }
+        }
+    }
+}
+
+impl Webhook {
+    async fn post(&self, body: &[u8]) -> anyhow::Result<()> {
+        let mut request = self
+            .client
+            .post(&self.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.to_vec());
+        if let Some(secret) = &self.secret {
+            request = request.header("X-Signature", signature(secret, body));
+        }
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            anyhow::bail!("HTTP {}", response.status());
+        }
+        Ok(())
+    }
+}
+
+/// `sha256=` and the hex HMAC-SHA256 of `body` keyed with `secret`
+fn signature(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signature() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            signature("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}
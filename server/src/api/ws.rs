@@ -2,6 +2,19 @@
 //!
 //! Provides real-time push notifications to connected clients when files change.
 //! Clients can subscribe to receive updates and trigger FileProvider reimport.
+//! Each subscriber only receives changes to files it can see (its own files and
+//! ownerless shared files), optionally narrowed to a path prefix.
+//!
+//! The hub also keeps a presence roster of open connections. Clients name
+//! their device when connecting; each client is sent join/leave events and a
+//! periodic roster for its own user's connections only, and a connection
+//! leaves the roster when its socket task ends, however it ends.
+//!
+//! Connections that die without a close frame (laptop sleep, network drop)
+//! are found by pinging: every `WS_PING_INTERVAL_SECS` the server sends a
+//! ping, and a connection that hasn't sent anything, pong included, for
+//! `WS_IDLE_TIMEOUT_SECS` is closed and counted in
+//! `entanglement_websocket_reaped_total`.
 //! Rate limiter is reserved for future per-user broadcast throttling.
 
 use axum::{
@@ -9,19 +22,20 @@ use axum::{
         ws::{Message, WebSocket, WebSocketUpgrade},
         Query, State,
     },
-    http::StatusCode,
     response::{IntoResponse, Response},
 };
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::sync::Arc;
-use std::time::Instant;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::sync::{broadcast, RwLock};
 use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
 use crate::api::AppState;
-use crate::auth;
+use crate::api::rest::authenticate_token;
+use crate::config::Config;
 
 /// Message broadcast to connected clients when files change
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -30,14 +44,160 @@ pub struct SyncNotification {
     pub msg_type: String,
     pub path: String,
     pub action: String,
+    /// Owner of the changed file; `None` for ownerless shared files.
+    /// Used for per-subscriber filtering only, never sent to clients.
+    #[serde(skip)]
+    pub owner_id: Option<Uuid>,
 }
 
 impl SyncNotification {
-    pub fn file_changed(path: &str, action: &str) -> Self {
+    pub fn file_changed(owner_id: Option<Uuid>, path: &str, action: &str) -> Self {
         Self {
             msg_type: "file_changed".to_string(),
             path: path.to_string(),
             action: action.to_string(),
+            owner_id,
+        }
+    }
+
+    /// Action for new content written to a file: `modify` when it replaced
+    /// the file's current version, `create` when the file had none
+    pub fn content_action(previous_version_id: Option<Uuid>) -> &'static str {
+        if previous_version_id.is_some() { "modify" } else { "create" }
+    }
+}
+
+/// How often each connection is sent the full presence roster
+const ROSTER_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Longest device name kept in the roster
+const MAX_DEVICE_NAME_LEN: usize = 64;
+
+/// When to ping a connection and when to give up on it
+#[derive(Clone, Copy, Debug)]
+pub struct Keepalive {
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+impl Keepalive {
+    /// None when pings are disabled (`WS_PING_INTERVAL_SECS=0`)
+    pub fn from_config(config: &Config) -> Option<Self> {
+        (config.ws_ping_interval_secs > 0).then(|| Self {
+            interval: Duration::from_secs(config.ws_ping_interval_secs),
+            timeout: Duration::from_secs(config.ws_idle_timeout_secs),
+        })
+    }
+
+    /// True once nothing has been heard from the client for `timeout`
+    fn is_stale(&self, last_seen: Instant) -> bool {
+        last_seen.elapsed() >= self.timeout
+    }
+}
+
+/// One open WebSocket connection
+#[derive(Clone, Debug, Serialize)]
+pub struct PresenceEntry {
+    pub connection_id: Uuid,
+    pub user_id: Uuid,
+    pub device: String,
+    pub connected_at: DateTime<Utc>,
+}
+
+/// Presence message sent to the connected clients of the same user
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PresenceEvent {
+    PresenceJoin { client: PresenceEntry },
+    PresenceLeave { client: PresenceEntry },
+    PresenceRoster { clients: Vec<PresenceEntry> },
+}
+
+/// Join and leave events of one user's connections
+pub struct PresenceSubscriber {
+    rx: broadcast::Receiver<PresenceEvent>,
+    user_id: Uuid,
+}
+
+impl PresenceSubscriber {
+    /// Receive the next join or leave of one of this user's connections
+    pub async fn recv(&mut self) -> Result<PresenceEvent, broadcast::error::RecvError> {
+        loop {
+            let event = self.rx.recv().await?;
+            let user_id = match &event {
+                PresenceEvent::PresenceJoin { client } | PresenceEvent::PresenceLeave { client } => client.user_id,
+                PresenceEvent::PresenceRoster { .. } => continue,
+            };
+            if user_id == self.user_id {
+                return Ok(event);
+            }
+        }
+    }
+}
+
+/// Keeps a connection in the roster until dropped
+pub struct PresenceGuard {
+    hub: SyncHub,
+    connection_id: Uuid,
+}
+
+impl Drop for PresenceGuard {
+    fn drop(&mut self) {
+        let entry = self.hub.lock_presence().remove(&self.connection_id);
+        if let Some(client) = entry {
+            debug!("Presence leave: {} ({})", client.user_id, client.device);
+            let _ = self.hub.presence_tx.send(PresenceEvent::PresenceLeave { client });
+        }
+    }
+}
+
+/// Which notifications a subscriber wants to receive
+#[derive(Clone, Debug)]
+pub struct SubscriptionFilter {
+    owner_id: Uuid,
+    /// Normalized prefix without trailing slash (e.g. "/docs")
+    path_prefix: Option<String>,
+}
+
+impl SubscriptionFilter {
+    pub fn new(owner_id: Uuid, path_prefix: Option<&str>) -> Self {
+        let path_prefix = path_prefix
+            .map(|p| format!("/{}", p.trim().trim_matches('/')))
+            .filter(|p| p != "/");
+        Self { owner_id, path_prefix }
+    }
+
+    /// True if the notification belongs to this subscriber and lies under its prefix
+    pub fn matches(&self, notification: &SyncNotification) -> bool {
+        if let Some(owner) = notification.owner_id {
+            if owner != self.owner_id {
+                return false;
+            }
+        }
+        match &self.path_prefix {
+            None => true,
+            Some(prefix) => match notification.path.strip_prefix(prefix.as_str()) {
+                Some(rest) => rest.is_empty() || rest.starts_with('/'),
+                None => false,
+            },
+        }
+    }
+}
+
+/// A filtered view of the hub's broadcast channel for one subscriber
+pub struct SyncSubscriber {
+    rx: broadcast::Receiver<SyncNotification>,
+    filter: SubscriptionFilter,
+}
+
+impl SyncSubscriber {
+    /// Receive the next notification matching this subscriber's filter
+    pub async fn recv(&mut self) -> Result<SyncNotification, broadcast::error::RecvError> {
+        loop {
+            let notification = self.rx.recv().await?;
+            if self.filter.matches(&notification) {
+                return Ok(notification);
+            }
         }
     }
 }
@@ -106,15 +266,22 @@ pub struct SyncHub {
     tx: broadcast::Sender<SyncNotification>,
     /// Rate limiter for broadcasts
     rate_limiter: BroadcastRateLimiter,
+    /// Open connections by connection id
+    presence: Arc<Mutex<HashMap<Uuid, PresenceEntry>>>,
+    /// Presence events, delivered to every connection unfiltered
+    presence_tx: broadcast::Sender<PresenceEvent>,
 }
 
 impl SyncHub {
     /// Create a new SyncHub with specified channel capacity
     pub fn new(capacity: usize) -> Self {
         let (tx, _) = broadcast::channel(capacity);
+        let (presence_tx, _) = broadcast::channel(capacity);
         Self {
             tx,
             rate_limiter: BroadcastRateLimiter::default(),
+            presence: Arc::new(Mutex::new(HashMap::new())),
+            presence_tx,
         }
     }
 
@@ -131,23 +298,78 @@ impl SyncHub {
             warn!("Dropping broadcast for user {} due to rate limiting", user_id);
             return false;
         }
-        let notification = SyncNotification::file_changed(path, action);
+        let notification = SyncNotification::file_changed(Some(user_id), path, action);
         debug!("Broadcasting sync notification: {:?}", notification);
         self.broadcast(notification);
         true
     }
 
     /// Broadcast a file change event (no rate limiting - for internal use)
-    pub fn notify_file_changed(&self, path: &str, action: &str) {
-        let notification = SyncNotification::file_changed(path, action);
+    ///
+    /// `owner_id` is `None` for ownerless shared files, which every subscriber sees.
+    pub fn notify_file_changed(&self, owner_id: Option<Uuid>, path: &str, action: &str) {
+        let notification = SyncNotification::file_changed(owner_id, path, action);
         debug!("Broadcasting sync notification: {:?}", notification);
         self.broadcast(notification);
     }
 
-    /// Subscribe to receive notifications
-    pub fn subscribe(&self) -> broadcast::Receiver<SyncNotification> {
+    /// Subscribe to receive notifications matching `filter`
+    pub fn subscribe(&self, filter: SubscriptionFilter) -> SyncSubscriber {
+        SyncSubscriber {
+            rx: self.tx.subscribe(),
+            filter,
+        }
+    }
+
+    /// Receive every notification unfiltered, for server-side consumers
+    pub fn subscribe_all(&self) -> broadcast::Receiver<SyncNotification> {
         self.tx.subscribe()
     }
+
+    /// Add a connection to the roster and announce it. The connection leaves
+    /// the roster when the returned guard is dropped.
+    pub fn join(&self, user_id: Uuid, device: &str) -> PresenceGuard {
+        let client = PresenceEntry {
+            connection_id: Uuid::new_v4(),
+            user_id,
+            device: device.to_string(),
+            connected_at: Utc::now(),
+        };
+        let connection_id = client.connection_id;
+        debug!("Presence join: {} ({})", client.user_id, client.device);
+        self.lock_presence().insert(connection_id, client.clone());
+        let _ = self.presence_tx.send(PresenceEvent::PresenceJoin { client });
+        PresenceGuard {
+            hub: self.clone(),
+            connection_id,
+        }
+    }
+
+    /// Open connections of every user, oldest first (for admins)
+    pub fn roster(&self) -> Vec<PresenceEntry> {
+        let mut clients: Vec<PresenceEntry> = self.lock_presence().values().cloned().collect();
+        clients.sort_by_key(|c| (c.connected_at, c.connection_id));
+        clients
+    }
+
+    /// Open connections of one user, oldest first
+    pub fn roster_for(&self, user_id: Uuid) -> Vec<PresenceEntry> {
+        let mut roster = self.roster();
+        roster.retain(|c| c.user_id == user_id);
+        roster
+    }
+
+    /// Receive join and leave events of `user_id`'s connections
+    pub fn subscribe_presence(&self, user_id: Uuid) -> PresenceSubscriber {
+        PresenceSubscriber {
+            rx: self.presence_tx.subscribe(),
+            user_id,
+        }
+    }
+
+    fn lock_presence(&self) -> std::sync::MutexGuard<'_, HashMap<Uuid, PresenceEntry>> {
+        self.presence.lock().unwrap_or_else(|e| e.into_inner())
+    }
 }
 
 impl Default for SyncHub {
@@ -161,13 +383,35 @@ impl Default for SyncHub {
 pub struct WsQuery {
     /// Authentication token
     token: String,
+    /// Only deliver changes under this path (e.g. "/docs")
+    prefix: Option<String>,
+    /// Device name shown in the presence roster
+    device: Option<String>,
+}
+
+/// Trimmed and truncated device name, or "unknown"
+fn device_name(device: Option<&str>) -> String {
+    let device: String = device
+        .map(str::trim)
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_DEVICE_NAME_LEN)
+        .collect();
+    if device.is_empty() {
+        "unknown".to_string()
+    } else {
+        device
+    }
 }
 
 /// WebSocket upgrade handler
 ///
-/// GET /ws/sync?token=<jwt>
+/// GET /ws/sync?token=<jwt>[&prefix=/some/dir][&device=<name>]
 ///
-/// Upgrades the connection to WebSocket and subscribes to sync notifications.
+/// Upgrades the connection to WebSocket and subscribes to sync notifications
+/// for the authenticated user, optionally limited to a path prefix. The
+/// connection joins the presence roster under `device`.
 /// Returns 401 Unauthorized if authentication fails (does NOT upgrade connection).
 pub async fn ws_handler(
     ws: WebSocketUpgrade,
@@ -176,26 +420,44 @@ pub async fn ws_handler(
 ) -> Response {
     // Validate token BEFORE upgrading connection
     // This prevents resource exhaustion from failed auth attempts
-    match auth::verify_token(&state.config.jwt_secret, &query.token) {
-        Ok(user_id) => {
+    match authenticate_token(&state, &query.token).await {
+        Ok((user_id, _)) => {
             info!("WebSocket connection authenticated for user: {}", user_id);
-            ws.on_upgrade(move |socket| handle_socket(socket, state)).into_response()
+            let filter = SubscriptionFilter::new(user_id, query.prefix.as_deref());
+            let device = device_name(query.device.as_deref());
+            ws.on_upgrade(move |socket| handle_socket(socket, state, filter, device))
+                .into_response()
         }
         Err(e) => {
-            warn!("WebSocket auth failed: {}", e);
-            // Return 401 Unauthorized WITHOUT upgrading the connection
+            warn!("WebSocket auth failed: {:?}", e);
+            // Return 401 Unauthorized (403 for a disabled account) WITHOUT
+            // upgrading the connection
             // This prevents resource allocation for unauthenticated requests
-            (StatusCode::UNAUTHORIZED, "Invalid or expired token").into_response()
+            e.into_response()
         }
     }
 }
 
 /// Handle an individual WebSocket connection
-async fn handle_socket(mut socket: WebSocket, state: AppState) {
+async fn handle_socket(mut socket: WebSocket, state: AppState, filter: SubscriptionFilter, device: String) {
     info!("WebSocket client connected");
+    let _connection = state.metrics.ws_connected();
+    let user_id = filter.owner_id;
 
-    // Subscribe to sync notifications
-    let mut rx = state.sync_hub.subscribe();
+    // Subscribe before joining so the client sees its own join
+    let mut presence_rx = state.sync_hub.subscribe_presence(user_id);
+    let _presence = state.sync_hub.join(filter.owner_id, &device);
+    // First tick fires immediately, so the client gets the roster on connect
+    let mut roster_interval = tokio::time::interval(ROSTER_INTERVAL);
+
+    let keepalive = Keepalive::from_config(&state.config);
+    // The period only matters when pings are enabled
+    let ping_period = keepalive.map_or(ROSTER_INTERVAL, |k| k.interval);
+    let mut ping_interval = tokio::time::interval_at(tokio::time::Instant::now() + ping_period, ping_period);
+    let mut last_seen = Instant::now();
+
+    // Subscribe to sync notifications visible to this user
+    let mut rx = state.sync_hub.subscribe(filter);
 
     // Send/receive loop
     loop {
@@ -221,8 +483,50 @@ async fn handle_socket(mut socket: WebSocket, state: AppState) {
                 }
             }
 
+            // Forward presence changes to client
+            result = presence_rx.recv() => {
+                match result {
+                    Ok(event) => {
+                        if !send_presence(&mut socket, &event).await {
+                            break;
+                        }
+                    }
+                    // The periodic roster catches the client up
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+
+            // Periodic roster; a failed send also reaps connections that died silently
+            _ = roster_interval.tick() => {
+                let roster = PresenceEvent::PresenceRoster { clients: state.sync_hub.roster_for(user_id) };
+                if !send_presence(&mut socket, &roster).await {
+                    break;
+                }
+            }
+
+            // Ping the client, or close the connection if it has gone quiet
+            _ = ping_interval.tick(), if keepalive.is_some() => {
+                let Some(keepalive) = keepalive else { continue };
+                if keepalive.is_stale(last_seen) {
+                    info!(
+                        "Reaping stale WebSocket connection for {} ({}): silent for {:?}",
+                        user_id, device, last_seen.elapsed()
+                    );
+                    state.metrics.record_ws_reaped();
+                    break;
+                }
+                if socket.send(Message::Ping(Vec::new())).await.is_err() {
+                    debug!("WebSocket ping failed, client disconnected");
+                    break;
+                }
+            }
+
             // Handle incoming messages from client (ping/pong, close)
             result = socket.recv() => {
+                if let Some(Ok(_)) = result {
+                    last_seen = Instant::now();
+                }
                 match result {
                     Some(Ok(Message::Text(text))) => {
                         debug!("Received message from client: {}", text);
@@ -249,3 +553,110 @@ async fn handle_socket(mut socket: WebSocket, state: AppState) {
 
     info!("WebSocket client disconnected");
 }
+
+/// Send a presence event, returning false if the client is gone
+async fn send_presence(socket: &mut WebSocket, event: &PresenceEvent) -> bool {
+    let json = serde_json::to_string(event).unwrap_or_default();
+    if socket.send(Message::Text(json)).await.is_err() {
+        debug!("WebSocket send failed, client disconnected");
+        return false;
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_by_owner() {
+        let me = Uuid::new_v4();
+        let filter = SubscriptionFilter::new(me, None);
+        assert!(filter.matches(&SyncNotification::file_changed(Some(me), "/a.txt", "create")));
+        assert!(filter.matches(&SyncNotification::file_changed(None, "/a.txt", "create")));
+        assert!(!filter.matches(&SyncNotification::file_changed(Some(Uuid::new_v4()), "/a.txt", "create")));
+    }
+
+    #[test]
+    fn test_presence_join_and_leave() {
+        let hub = SyncHub::default();
+        let user = Uuid::new_v4();
+        let mut events = hub.subscribe_presence(user);
+
+        let laptop = hub.join(user, "laptop");
+        let phone = hub.join(user, "phone");
+        let mut devices: Vec<String> = hub.roster().into_iter().map(|c| c.device).collect();
+        devices.sort();
+        assert_eq!(devices, ["laptop", "phone"]);
+
+        drop(laptop);
+        let roster = hub.roster();
+        assert_eq!(roster.len(), 1);
+        assert_eq!(roster[0].device, "phone");
+        drop(phone);
+        assert!(hub.roster().is_empty());
+
+        let kinds: Vec<String> = std::iter::from_fn(|| events.rx.try_recv().ok())
+            .map(|e| serde_json::to_value(e).unwrap()["type"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(kinds, ["presence_join", "presence_join", "presence_leave", "presence_leave"]);
+    }
+
+    #[tokio::test]
+    async fn test_presence_is_per_user() {
+        let hub = SyncHub::default();
+        let (me, other) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut events = hub.subscribe_presence(me);
+
+        let theirs = hub.join(other, "their-laptop");
+        let _mine = hub.join(me, "my-phone");
+        drop(theirs);
+
+        let devices: Vec<String> = hub.roster_for(me).into_iter().map(|c| c.device).collect();
+        assert_eq!(devices, ["my-phone"]);
+        assert!(hub.roster_for(other).is_empty());
+        assert_eq!(hub.roster().len(), 1);
+
+        // Only my own join gets through; the other user's join and leave are skipped
+        let PresenceEvent::PresenceJoin { client } = events.recv().await.unwrap() else {
+            panic!("expected my join");
+        };
+        assert_eq!(client.user_id, me);
+        assert!(tokio::time::timeout(Duration::from_millis(50), events.recv()).await.is_err());
+    }
+
+    #[test]
+    fn test_keepalive_staleness() {
+        let keepalive = Keepalive {
+            interval: Duration::from_secs(30),
+            timeout: Duration::from_secs(90),
+        };
+        assert!(!keepalive.is_stale(Instant::now()));
+        assert!(!keepalive.is_stale(Instant::now() - Duration::from_secs(60)));
+        assert!(keepalive.is_stale(Instant::now() - Duration::from_secs(90)));
+    }
+
+    #[test]
+    fn test_device_name() {
+        assert_eq!(device_name(None), "unknown");
+        assert_eq!(device_name(Some("  ")), "unknown");
+        assert_eq!(device_name(Some(" Mac\nBook ")), "MacBook");
+        assert_eq!(device_name(Some(&"x".repeat(100))).len(), MAX_DEVICE_NAME_LEN);
+    }
+
+    #[test]
+    fn test_filter_by_prefix() {
+        let me = Uuid::new_v4();
+        let filter = SubscriptionFilter::new(me, Some("docs/"));
+        assert!(filter.matches(&SyncNotification::file_changed(Some(me), "/docs/", "create")));
+        assert!(filter.matches(&SyncNotification::file_changed(Some(me), "/docs/a.txt", "create")));
+        assert!(!filter.matches(&SyncNotification::file_changed(Some(me), "/docs2/a.txt", "create")));
+        assert!(!filter.matches(&SyncNotification::file_changed(Some(me), "/other.txt", "create")));
+    }
+
+    #[test]
+    fn test_content_action() {
+        assert_eq!(SyncNotification::content_action(None), "create");
+        assert_eq!(SyncNotification::content_action(Some(Uuid::new_v4())), "modify");
+    }
+}
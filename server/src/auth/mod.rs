@@ -1,24 +1,114 @@
 mod token;
+pub mod totp;
 
-pub use token::{create_token, create_access_token, create_refresh_token, verify_token, verify_refresh_token};
+pub use token::{
+    create_access_token, create_refresh_token, verify_refresh_token, verify_token_subject,
+    DEFAULT_ACCESS_TOKEN_TTL_SECS, DEFAULT_REFRESH_TOKEN_TTL_SECS,
+};
 
 use argon2::{
     password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
-    Argon2,
+    Algorithm, Argon2, Params, Version,
 };
 
-/// Hash a password using Argon2
-pub fn hash_password(password: &str) -> anyhow::Result<String> {
+/// Argon2id cost parameters for new password hashes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HashParams {
+    /// Memory cost in KiB
+    pub memory_kib: u32,
+    /// Number of passes
+    pub iterations: u32,
+    /// Degree of parallelism (lanes)
+    pub parallelism: u32,
+}
+
+impl Default for HashParams {
+    /// The argon2 crate's defaults (OWASP minimum: 19 MiB, 2 passes, 1 lane)
+    fn default() -> Self {
+        HashParams {
+            memory_kib: Params::DEFAULT_M_COST,
+            iterations: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+impl HashParams {
+    /// Hasher for these parameters; fails if they are out of Argon2's range
+    pub fn hasher(&self) -> anyhow::Result<Argon2<'static>> {
+        let params = Params::new(self.memory_kib, self.iterations, self.parallelism, None)
+            .map_err(|e| anyhow::anyhow!("Invalid Argon2 parameters: {}", e))?;
+        Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+    }
+}
+
+/// Hash a password using Argon2id with the given cost parameters
+pub fn hash_password(password: &str, params: &HashParams) -> anyhow::Result<String> {
     let salt = SaltString::generate(&mut OsRng);
-    let argon2 = Argon2::default();
-    let hash = argon2
+    let hash = params
+        .hasher()?
         .hash_password(password.as_bytes(), &salt)
         .map_err(|e| anyhow::anyhow!("Failed to hash password: {}", e))?
         .to_string();
     Ok(hash)
 }
 
-/// Verify a password against a hash
+/// Password requirements shared by every account entry point
+/// (CLI `user create`, admin REST endpoints, TUI setup)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    /// Require lowercase, uppercase, digit and symbol characters
+    pub require_complexity: bool,
+}
+
+/// A password that fails the policy, with every unmet requirement
+#[derive(Debug, PartialEq, Eq)]
+pub struct PasswordPolicyError {
+    pub unmet: Vec<String>,
+}
+
+impl std::fmt::Display for PasswordPolicyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Password must {}", self.unmet.join(", "))
+    }
+}
+
+impl std::error::Error for PasswordPolicyError {}
+
+/// Check a password against the policy, listing all unmet requirements
+pub fn validate_password_policy(
+    password: &str,
+    policy: &PasswordPolicy,
+) -> Result<(), PasswordPolicyError> {
+    let mut unmet = Vec::new();
+
+    if password.chars().count() < policy.min_length {
+        unmet.push(format!("be at least {} characters", policy.min_length));
+    }
+    if policy.require_complexity {
+        if !password.chars().any(|c| c.is_lowercase()) {
+            unmet.push("contain a lowercase letter".to_string());
+        }
+        if !password.chars().any(|c| c.is_uppercase()) {
+            unmet.push("contain an uppercase letter".to_string());
+        }
+        if !password.chars().any(|c| c.is_ascii_digit()) {
+            unmet.push("contain a digit".to_string());
+        }
+        if password.chars().all(|c| c.is_alphanumeric()) {
+            unmet.push("contain a symbol".to_string());
+        }
+    }
+
+    if unmet.is_empty() {
+        Ok(())
+    } else {
+        Err(PasswordPolicyError { unmet })
+    }
+}
+
+/// Verify a password against a hash (the hash's own parameters are used)
 pub fn verify_password(password: &str, hash: &str) -> anyhow::Result<bool> {
     let parsed_hash = PasswordHash::new(hash)
         .map_err(|e| anyhow::anyhow!("Invalid password hash: {}", e))?;
@@ -27,6 +117,32 @@ pub fn verify_password(password: &str, hash: &str) -> anyhow::Result<bool> {
         .is_ok())
 }
 
+/// Compare secrets in time that depends only on their lengths, so a
+/// mismatch doesn't reveal how many leading bytes were right
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Whether `hash` should be recomputed with `params`: it is not Argon2id, or
+/// any of its costs is below the configured one. Unparseable hashes are left
+/// alone, since [`verify_password`] already rejects them.
+pub fn needs_rehash(hash: &str, params: &HashParams) -> bool {
+    let Ok(parsed) = PasswordHash::new(hash) else {
+        return false;
+    };
+    if parsed.algorithm != Algorithm::Argon2id.ident() {
+        return true;
+    }
+    match Params::try_from(&parsed) {
+        Ok(current) => {
+            current.m_cost() < params.memory_kib
+                || current.t_cost() < params.iterations
+                || current.p_cost() < params.parallelism
+        }
+        Err(_) => true,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -34,10 +150,60 @@ mod tests {
     #[test]
     fn test_password_hashing() {
         let password = "test_password_123";
-        let hash = hash_password(password).unwrap();
+        let hash = hash_password(password, &HashParams::default()).unwrap();
 
         assert!(verify_password(password, &hash).unwrap());
         assert!(!verify_password("wrong_password", &hash).unwrap());
     }
+
+    #[test]
+    fn test_needs_rehash_when_costs_increase() {
+        let weak = HashParams { memory_kib: 8 * 1024, iterations: 1, parallelism: 1 };
+        let hash = hash_password("test_password_123", &weak).unwrap();
+        assert!(!needs_rehash(&hash, &weak));
+
+        let stronger = HashParams { iterations: 2, ..weak };
+        assert!(needs_rehash(&hash, &stronger));
+        assert!(needs_rehash(&hash, &HashParams { memory_kib: 16 * 1024, ..weak }));
+        // Lowering the configured costs never downgrades an existing hash
+        assert!(!needs_rehash(&hash, &HashParams { memory_kib: 4 * 1024, ..weak }));
+
+        // Hashes made with the new parameters verify and are current
+        let rehashed = hash_password("test_password_123", &stronger).unwrap();
+        assert!(verify_password("test_password_123", &rehashed).unwrap());
+        assert!(!needs_rehash(&rehashed, &stronger));
+    }
+
+    #[test]
+    fn test_invalid_hash_params_rejected() {
+        let params = HashParams { memory_kib: 1, iterations: 0, parallelism: 1 };
+        assert!(hash_password("test_password_123", &params).is_err());
+    }
+
+    #[test]
+    fn test_password_policy_min_length() {
+        let policy = PasswordPolicy { min_length: 8, require_complexity: false };
+        assert!(validate_password_policy("longenough", &policy).is_ok());
+
+        let err = validate_password_policy("short", &policy).unwrap_err();
+        assert_eq!(err.to_string(), "Password must be at least 8 characters");
+    }
+
+    #[test]
+    fn test_password_policy_lists_unmet_complexity() {
+        let policy = PasswordPolicy { min_length: 4, require_complexity: true };
+        assert!(validate_password_policy("Tr0ub4dor&3", &policy).is_ok());
+
+        let err = validate_password_policy("abc", &policy).unwrap_err();
+        assert_eq!(
+            err.unmet,
+            vec![
+                "be at least 4 characters",
+                "contain an uppercase letter",
+                "contain a digit",
+                "contain a symbol",
+            ]
+        );
+    }
 }
 
@@ -3,12 +3,12 @@ use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation}
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-/// Access token expiration time in hours
+/// Default access token lifetime (24 hours)
 /// SECURITY: Reduced from 30 days to 24 hours for better security
-const ACCESS_TOKEN_HOURS: i64 = 24;
+pub const DEFAULT_ACCESS_TOKEN_TTL_SECS: i64 = 24 * 60 * 60;
 
-/// Refresh token expiration time in days
-const REFRESH_TOKEN_DAYS: i64 = 30;
+/// Default refresh token lifetime (30 days)
+pub const DEFAULT_REFRESH_TOKEN_TTL_SECS: i64 = 30 * 24 * 60 * 60;
 
 #[derive(Debug, Serialize, Deserialize)]
 struct Claims {
@@ -16,24 +16,36 @@ struct Claims {
     exp: i64,          // Expiration time
     iat: i64,          // Issued at
     token_type: String, // "access" or "refresh"
+    /// The user's token version when issued; tokens from before the
+    /// account was disabled carry an older one
+    #[serde(default)]
+    tv: i32,
+}
+
+/// Who a verified token was issued to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenSubject {
+    pub user_id: Uuid,
+    pub token_version: i32,
 }
 
 /// Create an access JWT token for a user (short-lived)
 #[allow(dead_code)]
 pub fn create_token(secret: &str, user_id: Uuid) -> anyhow::Result<String> {
-    create_access_token(secret, user_id)
+    create_access_token(secret, user_id, 0, DEFAULT_ACCESS_TOKEN_TTL_SECS)
 }
 
-/// Create an access token (short-lived, for API requests)
-pub fn create_access_token(secret: &str, user_id: Uuid) -> anyhow::Result<String> {
+/// Create an access token (short-lived, for API requests) valid for `ttl_secs`
+pub fn create_access_token(secret: &str, user_id: Uuid, token_version: i32, ttl_secs: i64) -> anyhow::Result<String> {
     let now = Utc::now();
-    let exp = now + Duration::hours(ACCESS_TOKEN_HOURS);
+    let exp = now + Duration::seconds(ttl_secs);
 
     let claims = Claims {
         sub: user_id.to_string(),
         exp: exp.timestamp(),
         iat: now.timestamp(),
         token_type: "access".to_string(),
+        tv: token_version,
     };
 
     let token = encode(
@@ -45,16 +57,17 @@ pub fn create_access_token(secret: &str, user_id: Uuid) -> anyhow::Result<String
     Ok(token)
 }
 
-/// Create a refresh token (long-lived, for obtaining new access tokens)
-pub fn create_refresh_token(secret: &str, user_id: Uuid) -> anyhow::Result<String> {
+/// Create a refresh token (long-lived, for obtaining new access tokens) valid for `ttl_secs`
+pub fn create_refresh_token(secret: &str, user_id: Uuid, token_version: i32, ttl_secs: i64) -> anyhow::Result<String> {
     let now = Utc::now();
-    let exp = now + Duration::days(REFRESH_TOKEN_DAYS);
+    let exp = now + Duration::seconds(ttl_secs);
 
     let claims = Claims {
         sub: user_id.to_string(),
         exp: exp.timestamp(),
         iat: now.timestamp(),
         token_type: "refresh".to_string(),
+        tv: token_version,
     };
 
     let token = encode(
@@ -66,8 +79,8 @@ pub fn create_refresh_token(secret: &str, user_id: Uuid) -> anyhow::Result<Strin
     Ok(token)
 }
 
-/// Verify a JWT token and extract the user ID
-pub fn verify_token(secret: &str, token: &str) -> anyhow::Result<Uuid> {
+/// Verify a JWT token and extract the user ID and token version
+pub fn verify_token_subject(secret: &str, token: &str) -> anyhow::Result<TokenSubject> {
     // SECURITY: Explicit algorithm prevents algorithm confusion attacks
     let token_data = decode::<Claims>(
         token,
@@ -75,13 +88,15 @@ pub fn verify_token(secret: &str, token: &str) -> anyhow::Result<Uuid> {
         &Validation::new(jsonwebtoken::Algorithm::HS256),
     )?;
 
-    let user_id = Uuid::parse_str(&token_data.claims.sub)?;
-    Ok(user_id)
+    Ok(TokenSubject {
+        user_id: Uuid::parse_str(&token_data.claims.sub)?,
+        token_version: token_data.claims.tv,
+    })
 }
 
-/// Verify a refresh token and extract the user ID
+/// Verify a refresh token and extract the user ID and token version
 /// Returns an error if the token is not a refresh token
-pub fn verify_refresh_token(secret: &str, token: &str) -> anyhow::Result<Uuid> {
+pub fn verify_refresh_token(secret: &str, token: &str) -> anyhow::Result<TokenSubject> {
     // SECURITY: Explicit algorithm prevents algorithm confusion attacks
     let token_data = decode::<Claims>(
         token,
@@ -93,8 +108,10 @@ pub fn verify_refresh_token(secret: &str, token: &str) -> anyhow::Result<Uuid> {
         anyhow::bail!("Not a refresh token");
     }
 
-    let user_id = Uuid::parse_str(&token_data.claims.sub)?;
-    Ok(user_id)
+    Ok(TokenSubject {
+        user_id: Uuid::parse_str(&token_data.claims.sub)?,
+        token_version: token_data.claims.tv,
+    })
 }
 
 #[cfg(test)]
@@ -107,7 +124,7 @@ mod tests {
         let user_id = Uuid::new_v4();
 
         let token = create_token(secret, user_id).unwrap();
-        let extracted_id = verify_token(secret, &token).unwrap();
+        let extracted_id = verify_token_subject(secret, &token).unwrap().user_id;
 
         assert_eq!(user_id, extracted_id);
     }
@@ -115,7 +132,7 @@ mod tests {
     #[test]
     fn test_invalid_token() {
         let secret = "test_secret";
-        let result = verify_token(secret, "invalid_token");
+        let result = verify_token_subject(secret, "invalid_token");
         assert!(result.is_err());
     }
 
@@ -123,8 +140,8 @@ mod tests {
     fn test_access_token_is_valid_access_type() {
         let secret = "test_secret";
         let user_id = Uuid::new_v4();
-        let token = create_access_token(secret, user_id).unwrap();
-        let extracted_id = verify_token(secret, &token).unwrap();
+        let token = create_access_token(secret, user_id, 0, DEFAULT_ACCESS_TOKEN_TTL_SECS).unwrap();
+        let extracted_id = verify_token_subject(secret, &token).unwrap().user_id;
         assert_eq!(user_id, extracted_id);
     }
 
@@ -132,8 +149,8 @@ mod tests {
     fn test_refresh_token_roundtrip() {
         let secret = "test_secret";
         let user_id = Uuid::new_v4();
-        let token = create_refresh_token(secret, user_id).unwrap();
-        let extracted_id = verify_refresh_token(secret, &token).unwrap();
+        let token = create_refresh_token(secret, user_id, 0, DEFAULT_REFRESH_TOKEN_TTL_SECS).unwrap();
+        let extracted_id = verify_refresh_token(secret, &token).unwrap().user_id;
         assert_eq!(user_id, extracted_id);
     }
 
@@ -141,7 +158,7 @@ mod tests {
     fn test_access_token_rejected_as_refresh() {
         let secret = "test_secret";
         let user_id = Uuid::new_v4();
-        let token = create_access_token(secret, user_id).unwrap();
+        let token = create_access_token(secret, user_id, 0, DEFAULT_ACCESS_TOKEN_TTL_SECS).unwrap();
         let result = verify_refresh_token(secret, &token);
         assert!(result.is_err());
     }
@@ -156,6 +173,7 @@ mod tests {
             exp: past.timestamp(),
             iat: (past - Duration::hours(1)).timestamp(),
             token_type: "access".to_string(),
+            tv: 0,
         };
         let token = encode(
             &Header::default(),
@@ -163,15 +181,54 @@ mod tests {
             &EncodingKey::from_secret(secret.as_bytes()),
         )
         .unwrap();
-        let result = verify_token(secret, &token);
+        let result = verify_token_subject(secret, &token);
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_token_lifetime() {
+        let secret = "test_secret";
+        let token = create_access_token(secret, Uuid::new_v4(), 0, 3600).unwrap();
+        let claims = decode::<Claims>(
+            &token,
+            &DecodingKey::from_secret(secret.as_bytes()),
+            &Validation::new(jsonwebtoken::Algorithm::HS256),
+        )
+        .unwrap()
+        .claims;
+        assert_eq!(claims.exp - claims.iat, 3600);
+
+        // Already expired (beyond the default 60s leeway)
+        let token = create_access_token(secret, Uuid::new_v4(), 0, -120).unwrap();
+        assert!(verify_token_subject(secret, &token).is_err());
+    }
+
+    #[test]
+    fn test_token_version_roundtrip() {
+        let secret = "test_secret";
+        let user_id = Uuid::new_v4();
+        let token = create_access_token(secret, user_id, 3, DEFAULT_ACCESS_TOKEN_TTL_SECS).unwrap();
+        let subject = verify_token_subject(secret, &token).unwrap();
+        assert_eq!(subject, TokenSubject { user_id, token_version: 3 });
+
+        let token = create_refresh_token(secret, user_id, 3, DEFAULT_REFRESH_TOKEN_TTL_SECS).unwrap();
+        assert_eq!(verify_refresh_token(secret, &token).unwrap().token_version, 3);
+
+        // Tokens issued before the claim existed count as version 0
+        let legacy = encode(
+            &Header::default(),
+            &serde_json::json!({"sub": user_id.to_string(), "exp": (Utc::now() + Duration::hours(1)).timestamp(), "iat": Utc::now().timestamp(), "token_type": "access"}),
+            &EncodingKey::from_secret(secret.as_bytes()),
+        )
+        .unwrap();
+        assert_eq!(verify_token_subject(secret, &legacy).unwrap().token_version, 0);
+    }
+
     #[test]
     fn test_wrong_secret_rejected() {
         let user_id = Uuid::new_v4();
-        let token = create_access_token("secret_one", user_id).unwrap();
-        let result = verify_token("secret_two", &token);
+        let token = create_access_token("secret_one", user_id, 0, DEFAULT_ACCESS_TOKEN_TTL_SECS).unwrap();
+        let result = verify_token_subject("secret_two", &token);
         assert!(result.is_err());
     }
 }
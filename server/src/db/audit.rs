@@ -0,0 +1,100 @@
+//! Database operations for the audit log
+//!
+//! The `audit_log` table is append-only (a trigger rejects UPDATE and DELETE),
+//! so this module only inserts and queries.
+
+use super::DbPool;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+
+/// Largest page `list_entries` callers should request
+pub const MAX_AUDIT_PAGE: i64 = 1000;
+
+/// Operations recorded in the audit log
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditAction {
+    Upload,
+    Delete,
+    Move,
+    Restore,
+    ShareCreate,
+    ShareAccess,
+}
+
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::Upload => "upload",
+            AuditAction::Delete => "delete",
+            AuditAction::Move => "move",
+            AuditAction::Restore => "restore",
+            AuditAction::ShareCreate => "share_create",
+            AuditAction::ShareAccess => "share_access",
+        }
+    }
+}
+
+/// An audit log row
+#[derive(Debug, Clone, sqlx::FromRow, Serialize)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub created_at: DateTime<Utc>,
+    pub user_id: Option<Uuid>,
+    pub action: String,
+    pub path: Option<String>,
+    pub request_id: Option<String>,
+    pub ip: Option<String>,
+}
+
+/// An audit log row to insert
+#[derive(Debug, Clone)]
+pub struct NewAuditEntry<'a> {
+    pub user_id: Option<Uuid>,
+    pub action: AuditAction,
+    pub path: Option<&'a str>,
+    pub request_id: Option<&'a str>,
+    pub ip: Option<&'a str>,
+}
+
+/// Append an entry to the audit log
+pub async fn insert_entry(pool: &DbPool, entry: &NewAuditEntry<'_>) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO audit_log (user_id, action, path, request_id, ip)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+    )
+    .bind(entry.user_id)
+    .bind(entry.action.as_str())
+    .bind(entry.path)
+    .bind(entry.request_id)
+    .bind(entry.ip)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// List entries recorded at or after `since` (all entries if None), oldest first
+pub async fn list_entries(
+    pool: &DbPool,
+    since: Option<DateTime<Utc>>,
+    limit: i64,
+) -> anyhow::Result<Vec<AuditEntry>> {
+    let entries = sqlx::query_as::<_, AuditEntry>(
+        r#"
+        SELECT id, created_at, user_id, action, path, request_id, ip
+        FROM audit_log
+        WHERE $1::timestamptz IS NULL OR created_at >= $1
+        ORDER BY created_at ASC, id ASC
+        LIMIT $2
+        "#,
+    )
+    .bind(since)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(entries)
+}
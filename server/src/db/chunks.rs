@@ -5,8 +5,10 @@
 
 #![allow(dead_code)]
 
-use super::models::{Chunk, NewChunk, VersionChunk};
+use super::models::{Chunk, NewChunk, StorageBackend, VersionChunk};
 use super::DbPool;
+use crate::storage::chunking::merkle_root;
+use sqlx::{PgConnection, PgExecutor};
 use uuid::Uuid;
 
 /// Legacy database representation of a chunk (for backwards compatibility)
@@ -226,29 +228,33 @@ pub async fn get_version_chunk_hashes(
     Ok(hashes.into_iter().map(|(h,)| h).collect())
 }
 
-/// Create a chunked version with all its chunks in a transaction
+/// Create a chunked version with all its chunks and make it current.
+///
+/// Runs on the caller's transaction, so it commits together with the file
+/// upsert (see [`files::lock_path`](super::files::lock_path)).
 pub async fn create_chunked_version(
-    pool: &DbPool,
+    conn: &mut PgConnection,
     file_id: Uuid,
     blob_hash: &str,  // Overall file hash
     size_bytes: i64,
     chunks: &[(String, i32, i64)],  // (hash, size, offset)
 ) -> anyhow::Result<Uuid> {
-    // Use a transaction to ensure atomicity
-    let mut tx = pool.begin().await?;
-    
+    let tree_hash = merkle_root(&chunks.iter().map(|(hash, _, _)| hash).collect::<Vec<_>>())
+        .unwrap_or_else(|| blob_hash.to_string());
+
     // Create version record
     let version_id: (Uuid,) = sqlx::query_as(
         r#"
-        INSERT INTO versions (file_id, blob_hash, size_bytes, is_chunked)
-        VALUES ($1, $2, $3, TRUE)
+        INSERT INTO versions (file_id, blob_hash, size_bytes, is_chunked, tree_hash)
+        VALUES ($1, $2, $3, TRUE, $4)
         RETURNING id
         "#,
     )
     .bind(file_id)
     .bind(blob_hash)
     .bind(size_bytes)
-    .fetch_one(&mut *tx)
+    .bind(&tree_hash)
+    .fetch_one(&mut *conn)
     .await?;
     
     let version_id = version_id.0;
@@ -266,7 +272,7 @@ pub async fn create_chunked_version(
         )
         .bind(hash)
         .bind(size)
-        .execute(&mut *tx)
+        .execute(&mut *conn)
         .await?;
         
         // Create version-chunk mapping
@@ -280,7 +286,7 @@ pub async fn create_chunked_version(
         .bind(hash)
         .bind(index as i32)
         .bind(offset)
-        .execute(&mut *tx)
+        .execute(&mut *conn)
         .await?;
     }
     
@@ -294,11 +300,9 @@ pub async fn create_chunked_version(
     )
     .bind(version_id)
     .bind(file_id)
-    .execute(&mut *tx)
+    .execute(&mut *conn)
     .await?;
     
-    tx.commit().await?;
-    
     Ok(version_id)
 }
 
@@ -348,6 +352,7 @@ impl ChunkStats {
 // =============================================================================
 
 use super::models::ChunkTier;
+use super::versions::{prune_versions, ReleasedChunk, VersionRetention};
 
 /// Chunk info for version creation
 #[derive(Debug, Clone)]
@@ -361,21 +366,31 @@ pub struct ChunkInfo {
 /// This is the primary API for creating new file versions.
 ///
 /// Prerequisites: All chunks must already exist in the database.
+///
+/// Runs on the caller's transaction, so it commits together with the file
+/// upsert (see [`files::lock_path`](super::files::lock_path)). Older versions
+/// outside `retention` are pruned in the same transaction; returns the new
+/// version's ID and the chunks pruning released, whose data the caller
+/// deletes once committed.
+#[allow(clippy::too_many_arguments)]
 pub async fn create_version_with_tier(
-    pool: &DbPool,
+    conn: &mut PgConnection,
     file_id: Uuid,
     blake3_hash: &str,
     size_bytes: i64,
     tier: ChunkTier,
     chunks: &[ChunkInfo],
-) -> anyhow::Result<Uuid> {
-    let mut tx = pool.begin().await?;
-    
+    created_by: Option<Uuid>,
+    retention: VersionRetention,
+) -> anyhow::Result<(Uuid, Vec<ReleasedChunk>)> {
+    let tree_hash = merkle_root(&chunks.iter().map(|c| &c.hash).collect::<Vec<_>>())
+        .unwrap_or_else(|| blake3_hash.to_string());
+
     // Create version record with tier and blake3_hash
     let version_id: (Uuid,) = sqlx::query_as(
         r#"
-        INSERT INTO versions (file_id, blob_hash, blake3_hash, size_bytes, tier_id, is_chunked)
-        VALUES ($1, $2, $2, $3, $4, $5)
+        INSERT INTO versions (file_id, blob_hash, blake3_hash, size_bytes, tier_id, is_chunked, tree_hash, created_by)
+        VALUES ($1, $2, $2, $3, $4, $5, $6, $7)
         RETURNING id
         "#,
     )
@@ -383,8 +398,10 @@ pub async fn create_version_with_tier(
     .bind(blake3_hash)
     .bind(size_bytes)
     .bind(tier as i16)
-    .bind(!chunks.is_empty()) // is_chunked = true if we have chunks
-    .fetch_one(&mut *tx)
+    .bind(is_chunked(chunks.len(), size_bytes))
+    .bind(&tree_hash)
+    .bind(created_by)
+    .fetch_one(&mut *conn)
     .await?;
     
     let version_id = version_id.0;
@@ -398,7 +415,7 @@ pub async fn create_version_with_tier(
             "#,
         )
         .bind(&chunk.hash)
-        .execute(&mut *tx)
+        .execute(&mut *conn)
         .await?;
         
         // Create version-chunk mapping
@@ -412,7 +429,7 @@ pub async fn create_version_with_tier(
         .bind(&chunk.hash)
         .bind(index as i32)
         .bind(chunk.offset_in_file)
-        .execute(&mut *tx)
+        .execute(&mut *conn)
         .await?;
     }
     
@@ -426,33 +443,152 @@ pub async fn create_version_with_tier(
     )
     .bind(version_id)
     .bind(file_id)
-    .execute(&mut *tx)
+    .execute(&mut *conn)
     .await?;
     
-    tx.commit().await?;
-    
     tracing::info!(
         "Created version {} for file {} with tier {:?} ({} chunks)",
         version_id, file_id, tier, chunks.len()
     );
+
+    let pruned = prune_versions(&mut *conn, file_id, retention).await?;
+    if !pruned.version_ids.is_empty() {
+        tracing::info!("Pruned {} old versions of file {}", pruned.version_ids.len(), file_id);
+    }
     
-    Ok(version_id)
+    Ok((version_id, pruned.released))
 }
 
-/// Upsert a chunk with container location
-pub async fn upsert_chunk_with_location(
+/// Whether a new version is chunked: it has chunks, or it is an empty file,
+/// which is chunked with none. A non-empty version without chunks is a
+/// whole-file (legacy) version.
+fn is_chunked(chunk_count: usize, size_bytes: i64) -> bool {
+    chunk_count > 0 || size_bytes == 0
+}
+
+/// Rewrite a whole-file version as chunked, in place
+///
+/// Prerequisites: all chunks must already exist in the database. The
+/// version keeps its id, file and hash. Returns false, changing nothing, if
+/// the version is gone or already chunked.
+pub async fn convert_to_chunked(
     pool: &DbPool,
+    version_id: Uuid,
+    tier: ChunkTier,
+    chunks: &[ChunkInfo],
+) -> anyhow::Result<bool> {
+    let mut tx = pool.begin().await?;
+
+    let version: Option<(String, bool)> =
+        sqlx::query_as("SELECT blob_hash, is_chunked FROM versions WHERE id = $1 FOR UPDATE")
+            .bind(version_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+    let blob_hash = match version {
+        Some((blob_hash, false)) => blob_hash,
+        _ => return Ok(false),
+    };
+
+    for (index, chunk) in chunks.iter().enumerate() {
+        sqlx::query("UPDATE chunks SET ref_count = ref_count + 1 WHERE hash = $1")
+            .bind(&chunk.hash)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            r#"
+            INSERT INTO version_chunks (version_id, chunk_hash, chunk_index, chunk_offset)
+            VALUES ($1, $2, $3, $4)
+            "#,
+        )
+        .bind(version_id)
+        .bind(&chunk.hash)
+        .bind(index as i32)
+        .bind(chunk.offset_in_file)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    let tree_hash = merkle_root(&chunks.iter().map(|c| &c.hash).collect::<Vec<_>>())
+        .unwrap_or_else(|| blob_hash.clone());
+    sqlx::query(
+        r#"
+        UPDATE versions
+        SET is_chunked = TRUE, tier_id = $2, blake3_hash = blob_hash, tree_hash = $3
+        WHERE id = $1
+        "#,
+    )
+    .bind(version_id)
+    .bind(tier as i16)
+    .bind(&tree_hash)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// Hold the data lock of chunk `hash` shared until the transaction on
+/// `conn` ends.
+///
+/// Writers hold it from writing a chunk's data until its record commits, so
+/// [`lock_chunk_data`] never removes data a new record is about to name.
+pub async fn share_chunk_data_lock(conn: &mut PgConnection, hash: &str) -> anyhow::Result<()> {
+    sqlx::query("SELECT pg_advisory_xact_lock_shared(hashtextextended($1, 0))")
+        .bind(hash)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Take the data lock of chunk `hash` exclusively until the transaction on
+/// `conn` ends, and report whether its data stored in `backend` is in use:
+/// a chunk record names the hash (it was uploaded again since it was
+/// released), or a whole-file version reads the same local file as its blob.
+///
+/// The caller removes the data only if it is unused, before committing.
+pub async fn lock_chunk_data(
+    conn: &mut PgConnection,
+    hash: &str,
+    backend: StorageBackend,
+) -> anyhow::Result<bool> {
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtextextended($1, 0))")
+        .bind(hash)
+        .execute(&mut *conn)
+        .await?;
+    let (in_use,): (bool,) = sqlx::query_as(
+        r#"
+        SELECT EXISTS(SELECT 1 FROM chunks WHERE hash = $1)
+            OR ($2 = $3 AND EXISTS(SELECT 1 FROM versions WHERE blob_hash = $1 AND NOT is_chunked))
+        "#,
+    )
+    .bind(hash)
+    .bind(backend)
+    .bind(StorageBackend::Local)
+    .fetch_one(&mut *conn)
+    .await?;
+    Ok(in_use)
+}
+
+/// Upsert a chunk with its storage location
+///
+/// A chunk already recorded with a location keeps it (a concurrent writer
+/// got there first); only a local record without a container takes the new
+/// location and backend.
+pub async fn upsert_chunk_with_location(
+    conn: &mut PgConnection,
     new_chunk: &NewChunk,
 ) -> anyhow::Result<Chunk> {
     let chunk = sqlx::query_as::<_, Chunk>(
         r#"
-        INSERT INTO chunks (hash, size_bytes, ref_count, container_id, offset_bytes, length_bytes)
-        VALUES ($1, $2, 0, $3, $4, $5)
+        INSERT INTO chunks (hash, size_bytes, ref_count, container_id, offset_bytes, length_bytes, backend)
+        VALUES ($1, $2, 0, $3, $4, $5, $6)
         ON CONFLICT (hash) DO UPDATE
-            SET container_id = COALESCE(chunks.container_id, EXCLUDED.container_id),
-                offset_bytes = COALESCE(chunks.offset_bytes, EXCLUDED.offset_bytes),
-                length_bytes = COALESCE(chunks.length_bytes, EXCLUDED.length_bytes)
-        RETURNING hash, size_bytes, ref_count, container_id, offset_bytes, length_bytes, created_at
+            SET container_id = EXCLUDED.container_id,
+                offset_bytes = EXCLUDED.offset_bytes,
+                length_bytes = EXCLUDED.length_bytes,
+                backend = EXCLUDED.backend
+            WHERE chunks.backend = 'local' AND chunks.container_id IS NULL
+        RETURNING hash, size_bytes, ref_count, container_id, offset_bytes, length_bytes, backend, created_at
         "#,
     )
     .bind(&new_chunk.hash)
@@ -460,23 +596,63 @@ pub async fn upsert_chunk_with_location(
     .bind(new_chunk.container_id)
     .bind(new_chunk.offset_bytes)
     .bind(new_chunk.length_bytes)
-    .fetch_one(pool)
+    .bind(new_chunk.backend)
+    .fetch_optional(&mut *conn)
     .await?;
 
-    Ok(chunk)
+    match chunk {
+        Some(chunk) => Ok(chunk),
+        None => get_chunk_with_location(conn, &new_chunk.hash)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Chunk {} disappeared during upsert", new_chunk.hash)),
+    }
+}
+
+/// Find chunk hashes starting with `prefix` (at most two, to detect ambiguity)
+pub async fn find_chunks_by_prefix(pool: &DbPool, prefix: &str) -> anyhow::Result<Vec<String>> {
+    let hashes: Vec<(String,)> = sqlx::query_as(
+        "SELECT hash FROM chunks WHERE hash LIKE $1 || '%' ORDER BY hash LIMIT 2",
+    )
+    .bind(prefix)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(hashes.into_iter().map(|(h,)| h).collect())
+}
+
+/// List chunks whose hash starts with `prefix` (empty for all), in hash order
+pub async fn list_chunks(pool: &DbPool, prefix: &str, limit: i64) -> anyhow::Result<Vec<Chunk>> {
+    let chunks = sqlx::query_as::<_, Chunk>(
+        r#"
+        SELECT hash, size_bytes, ref_count, container_id, offset_bytes, length_bytes, backend, created_at
+        FROM chunks
+        WHERE hash LIKE $1 || '%'
+        ORDER BY hash
+        LIMIT $2
+        "#,
+    )
+    .bind(prefix)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(chunks)
 }
 
 /// Get a chunk with full location info
-pub async fn get_chunk_with_location(pool: &DbPool, hash: &str) -> anyhow::Result<Option<Chunk>> {
+pub async fn get_chunk_with_location(
+    executor: impl PgExecutor<'_>,
+    hash: &str,
+) -> anyhow::Result<Option<Chunk>> {
     let chunk = sqlx::query_as::<_, Chunk>(
         r#"
-        SELECT hash, size_bytes, ref_count, container_id, offset_bytes, length_bytes, created_at
+        SELECT hash, size_bytes, ref_count, container_id, offset_bytes, length_bytes, backend, created_at
         FROM chunks
         WHERE hash = $1
         "#,
     )
     .bind(hash)
-    .fetch_optional(pool)
+    .fetch_optional(executor)
     .await?;
 
     Ok(chunk)
@@ -488,11 +664,11 @@ pub async fn get_version_chunks_with_location(
     version_id: Uuid,
 ) -> anyhow::Result<Vec<(VersionChunk, Chunk)>> {
     // We need to do a join here
-    let rows: Vec<(Uuid, Uuid, String, i32, i64, String, i32, i32, Option<Uuid>, Option<i64>, Option<i32>, chrono::DateTime<chrono::Utc>)> = sqlx::query_as(
+    let rows: Vec<(Uuid, Uuid, String, i32, i64, String, i32, i32, Option<Uuid>, Option<i64>, Option<i32>, StorageBackend, chrono::DateTime<chrono::Utc>)> = sqlx::query_as(
         r#"
         SELECT 
             vc.id, vc.version_id, vc.chunk_hash, vc.chunk_index, vc.chunk_offset,
-            c.hash, c.size_bytes, c.ref_count, c.container_id, c.offset_bytes, c.length_bytes, c.created_at
+            c.hash, c.size_bytes, c.ref_count, c.container_id, c.offset_bytes, c.length_bytes, c.backend, c.created_at
         FROM version_chunks vc
         JOIN chunks c ON vc.chunk_hash = c.hash
         WHERE vc.version_id = $1
@@ -518,7 +694,8 @@ pub async fn get_version_chunks_with_location(
             container_id: row.8,
             offset_bytes: row.9,
             length_bytes: row.10,
-            created_at: row.11,
+            backend: row.11,
+            created_at: row.12,
         };
         (vc, chunk)
     }).collect();
@@ -540,8 +717,8 @@ pub async fn batch_upsert_chunks(
     for chunk in chunks {
         sqlx::query(
             r#"
-            INSERT INTO chunks (hash, size_bytes, ref_count, container_id, offset_bytes, length_bytes)
-            VALUES ($1, $2, 0, $3, $4, $5)
+            INSERT INTO chunks (hash, size_bytes, ref_count, container_id, offset_bytes, length_bytes, backend)
+            VALUES ($1, $2, 0, $3, $4, $5, $6)
             ON CONFLICT (hash) DO NOTHING
             "#,
         )
@@ -550,6 +727,7 @@ pub async fn batch_upsert_chunks(
         .bind(chunk.container_id)
         .bind(chunk.offset_bytes)
         .bind(chunk.length_bytes)
+        .bind(chunk.backend)
         .execute(&mut *tx)
         .await?;
     }
@@ -559,3 +737,132 @@ pub async fn batch_upsert_chunks(
     Ok(())
 }
 
+
+/// Hashes of chunks referenced by some version that live outside a
+/// container: standalone blob files on local storage, or objects in `backend`
+pub async fn list_standalone_version_chunks(
+    pool: &DbPool,
+    backend: StorageBackend,
+) -> anyhow::Result<Vec<String>> {
+    let hashes: Vec<(String,)> = sqlx::query_as(
+        r#"
+        SELECT DISTINCT c.hash
+        FROM version_chunks vc
+        JOIN chunks c ON c.hash = vc.chunk_hash
+        WHERE c.container_id IS NULL AND c.backend = $1
+        "#,
+    )
+    .bind(backend)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(hashes.into_iter().map(|(h,)| h).collect())
+}
+
+/// Delete records of unreadable chunks (zero-size placeholders, or data in
+/// `missing_containers` / `missing_blobs`) that no version references any
+/// more, so a client uploading the same content stores it again instead of
+/// being told the chunk already exists
+pub async fn forget_missing_chunks(
+    pool: &DbPool,
+    missing_containers: &[Uuid],
+    missing_blobs: &[String],
+) -> anyhow::Result<u64> {
+    let result = sqlx::query(
+        r#"
+        DELETE FROM chunks c
+        WHERE (c.size_bytes <= 0 OR c.container_id = ANY($1) OR c.hash = ANY($2))
+          AND NOT EXISTS (SELECT 1 FROM version_chunks vc WHERE vc.chunk_hash = c.hash)
+        "#,
+    )
+    .bind(missing_containers)
+    .bind(missing_blobs)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Outcome of [`delete_unreferenced_chunk`]
+#[derive(Debug)]
+pub enum ChunkDeletion {
+    NotFound,
+    /// Still in use by this many `version_chunks` rows; nothing was deleted
+    Referenced(i64),
+    /// The record was deleted; its stored data is still there for the
+    /// caller to remove with
+    /// [`BlobManager::delete_released_chunk`](crate::storage::BlobManager::delete_released_chunk)
+    Deleted(Chunk),
+}
+
+/// Delete a chunk record only if no version references it
+///
+/// The row is locked first, so a version being created with the chunk
+/// either commits before the check (and the chunk is kept) or waits and
+/// then fails its foreign key.
+pub async fn delete_unreferenced_chunk(pool: &DbPool, hash: &str) -> anyhow::Result<ChunkDeletion> {
+    let mut tx = pool.begin().await?;
+
+    let chunk = sqlx::query_as::<_, Chunk>(
+        r#"
+        SELECT hash, size_bytes, ref_count, container_id, offset_bytes, length_bytes, backend, created_at
+        FROM chunks
+        WHERE hash = $1
+        FOR UPDATE
+        "#,
+    )
+    .bind(hash)
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some(chunk) = chunk else {
+        return Ok(ChunkDeletion::NotFound);
+    };
+
+    let (references,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM version_chunks WHERE chunk_hash = $1")
+        .bind(hash)
+        .fetch_one(&mut *tx)
+        .await?;
+    if references > 0 {
+        return Ok(ChunkDeletion::Referenced(references));
+    }
+
+    sqlx::query("DELETE FROM chunks WHERE hash = $1")
+        .bind(hash)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    Ok(ChunkDeletion::Deleted(chunk))
+}
+
+/// Whether the legacy blob `hash` is still needed: a whole-file version
+/// names it as its blob, or a local standalone chunk record (which shares
+/// the blob's file) points at it
+pub async fn legacy_blob_in_use(pool: &DbPool, hash: &str) -> anyhow::Result<bool> {
+    let (in_use,): (bool,) = sqlx::query_as(
+        r#"
+        SELECT EXISTS(SELECT 1 FROM versions WHERE blob_hash = $1 AND NOT is_chunked)
+            OR EXISTS(
+                SELECT 1 FROM chunks
+                WHERE hash = $1 AND container_id IS NULL AND backend = $2
+            )
+        "#,
+    )
+    .bind(hash)
+    .bind(StorageBackend::Local)
+    .fetch_one(pool)
+    .await?;
+    Ok(in_use)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_chunked() {
+        assert!(is_chunked(0, 0));
+        assert!(is_chunked(3, 1024));
+        assert!(!is_chunked(0, 1024));
+    }
+}
@@ -16,12 +16,13 @@ pub async fn create_container(
 ) -> anyhow::Result<BlobContainer> {
     let container = sqlx::query_as::<_, BlobContainer>(
         r#"
-        INSERT INTO blob_containers (disk_path)
-        VALUES ($1)
-        RETURNING id, disk_path, total_size, chunk_count, is_sealed, created_at, sealed_at
+        INSERT INTO blob_containers (disk_path, encrypted)
+        VALUES ($1, $2)
+        RETURNING id, disk_path, total_size, chunk_count, is_sealed, created_at, sealed_at, encrypted
         "#,
     )
     .bind(&new_container.disk_path)
+    .bind(new_container.encrypted)
     .fetch_one(pool)
     .await?;
 
@@ -32,7 +33,7 @@ pub async fn create_container(
 pub async fn get_container(pool: &DbPool, id: Uuid) -> anyhow::Result<Option<BlobContainer>> {
     let container = sqlx::query_as::<_, BlobContainer>(
         r#"
-        SELECT id, disk_path, total_size, chunk_count, is_sealed, created_at, sealed_at
+        SELECT id, disk_path, total_size, chunk_count, is_sealed, created_at, sealed_at, encrypted
         FROM blob_containers
         WHERE id = $1
         "#,
@@ -53,7 +54,7 @@ pub async fn find_open_container(
 ) -> anyhow::Result<Option<BlobContainer>> {
     let container = sqlx::query_as::<_, BlobContainer>(
         r#"
-        SELECT id, disk_path, total_size, chunk_count, is_sealed, created_at, sealed_at
+        SELECT id, disk_path, total_size, chunk_count, is_sealed, created_at, sealed_at, encrypted
         FROM blob_containers
         WHERE is_sealed = FALSE
           AND total_size + $1 <= $2
@@ -107,6 +108,17 @@ pub async fn seal_container(pool: &DbPool, container_id: Uuid) -> anyhow::Result
     Ok(())
 }
 
+/// Record that a container's file moved. Returns false if the container is gone.
+pub async fn set_disk_path(pool: &DbPool, id: Uuid, disk_path: &str) -> anyhow::Result<bool> {
+    let result = sqlx::query("UPDATE blob_containers SET disk_path = $2 WHERE id = $1")
+        .bind(id)
+        .bind(disk_path)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
 /// List all containers
 #[allow(dead_code)]
 pub async fn list_containers(
@@ -115,7 +127,7 @@ pub async fn list_containers(
 ) -> anyhow::Result<Vec<BlobContainer>> {
     let containers = sqlx::query_as::<_, BlobContainer>(
         r#"
-        SELECT id, disk_path, total_size, chunk_count, is_sealed, created_at, sealed_at
+        SELECT id, disk_path, total_size, chunk_count, is_sealed, created_at, sealed_at, encrypted
         FROM blob_containers
         WHERE $1 OR is_sealed = FALSE
         ORDER BY created_at DESC
@@ -163,13 +175,142 @@ pub async fn get_container_stats(pool: &DbPool) -> anyhow::Result<ContainerStats
     })
 }
 
+/// A container alongside the bytes still referenced by chunks
+#[derive(Debug, sqlx::FromRow)]
+pub struct ContainerUsage {
+    pub id: Uuid,
+    pub disk_path: String,
+    pub total_size: i64,
+    pub chunk_count: i32,
+    pub is_sealed: bool,
+    /// Bytes of chunks that still point into this container
+    pub live_bytes: i64,
+    pub live_chunks: i64,
+}
 
+impl ContainerUsage {
+    /// Fraction of the container still referenced (0.0 for empty containers)
+    pub fn fill_ratio(&self) -> f64 {
+        if self.total_size <= 0 {
+            0.0
+        } else {
+            self.live_bytes as f64 / self.total_size as f64
+        }
+    }
+}
 
+/// List containers with their live byte counts, sparsest first
+pub async fn list_container_usage(pool: &DbPool) -> anyhow::Result<Vec<ContainerUsage>> {
+    let containers = sqlx::query_as::<_, ContainerUsage>(
+        r#"
+        SELECT bc.id, bc.disk_path, bc.total_size, bc.chunk_count, bc.is_sealed,
+               COALESCE(SUM(COALESCE(c.length_bytes, c.size_bytes)), 0)::BIGINT as live_bytes,
+               COUNT(c.hash) as live_chunks
+        FROM blob_containers bc
+        LEFT JOIN chunks c ON c.container_id = bc.id
+        GROUP BY bc.id
+        ORDER BY COALESCE(SUM(COALESCE(c.length_bytes, c.size_bytes)), 0)::FLOAT8
+                 / GREATEST(bc.total_size, 1), bc.created_at
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
 
+    Ok(containers)
+}
 
+/// A chunk stored in a container, as needed to copy it elsewhere
+#[derive(Debug, sqlx::FromRow)]
+pub struct ContainerChunk {
+    pub hash: String,
+    pub offset_bytes: i64,
+    pub length_bytes: i32,
+}
 
+/// List the chunks that point into a container, in file order
+pub async fn get_container_chunks(
+    pool: &DbPool,
+    container_id: Uuid,
+) -> anyhow::Result<Vec<ContainerChunk>> {
+    let chunks = sqlx::query_as::<_, ContainerChunk>(
+        r#"
+        SELECT hash, offset_bytes, COALESCE(length_bytes, size_bytes) as length_bytes
+        FROM chunks
+        WHERE container_id = $1 AND offset_bytes IS NOT NULL
+        ORDER BY offset_bytes
+        "#,
+    )
+    .bind(container_id)
+    .fetch_all(pool)
+    .await?;
 
+    Ok(chunks)
+}
+
+/// Atomically point chunks at a repacked container and drop the old one
+///
+/// `moved` holds each chunk's hash and its offset in the new file. When
+/// `new_disk_path` is None (no live chunks) the old container is simply
+/// removed. Fails, leaving everything untouched, if a chunk still points at
+/// the old container after the moves.
+pub async fn replace_container(
+    pool: &DbPool,
+    old_id: Uuid,
+    new_disk_path: Option<&str>,
+    moved: &[(String, i64)],
+    new_total_size: i64,
+) -> anyhow::Result<Option<Uuid>> {
+    let mut tx = pool.begin().await?;
 
+    // Serialize against other repacks of the same container
+    sqlx::query("SELECT id FROM blob_containers WHERE id = $1 FOR UPDATE")
+        .bind(old_id)
+        .fetch_one(&mut *tx)
+        .await?;
 
+    let new_id = match new_disk_path {
+        Some(disk_path) => {
+            // Chunks are copied verbatim, so the new container inherits `encrypted`
+            let (id,): (Uuid,) = sqlx::query_as(
+                r#"
+                INSERT INTO blob_containers (disk_path, total_size, chunk_count, is_sealed, sealed_at, encrypted)
+                SELECT $1, $2, $3, TRUE, NOW(), encrypted FROM blob_containers WHERE id = $4
+                RETURNING id
+                "#,
+            )
+            .bind(disk_path)
+            .bind(new_total_size)
+            .bind(moved.len() as i32)
+            .bind(old_id)
+            .fetch_one(&mut *tx)
+            .await?;
 
+            for (hash, offset) in moved {
+                sqlx::query(
+                    r#"
+                    UPDATE chunks SET container_id = $1, offset_bytes = $2
+                    WHERE hash = $3 AND container_id = $4
+                    "#,
+                )
+                .bind(id)
+                .bind(offset)
+                .bind(hash)
+                .bind(old_id)
+                .execute(&mut *tx)
+                .await?;
+            }
+            Some(id)
+        }
+        None => None,
+    };
 
+    // ON DELETE RESTRICT rejects this if any chunk was missed
+    sqlx::query("DELETE FROM blob_containers WHERE id = $1")
+        .bind(old_id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(new_id)
+}
@@ -1,17 +1,153 @@
 use super::DbPool;
 use chrono::{DateTime, Utc};
+use sqlx::{PgConnection, PgExecutor};
+use tokio_stream::{Stream, StreamExt};
 use uuid::Uuid;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 /// Escape special characters in LIKE patterns to prevent SQL injection.
 /// Escapes `\`, `%`, and `_` with a backslash so they are treated as literals.
 /// Use with `ESCAPE '\'` in the SQL query.
-fn escape_like(s: &str) -> String {
+pub(crate) fn escape_like(s: &str) -> String {
     s.replace('\\', "\\\\")
         .replace('%', "\\%")
         .replace('_', "\\_")
 }
 
+/// LIKE pattern (for `ESCAPE '\'`) matching every path under `folder`, a
+/// stored folder path such as `/docs/` or `/`
+fn under_folder_pattern(folder: &str) -> String {
+    format!("{}%", escape_like(folder))
+}
+
+/// Canonical form of a stored path: a leading slash, single slashes, and a
+/// trailing slash only for directories (signalled by the input's trailing
+/// slash). "docs//a.txt" -> "/docs/a.txt", "docs/" -> "/docs/", "" -> "/".
+///
+/// Applied to every path written by this module, so virtual-folder IDs (the
+/// BLAKE3 hash of "/dir/") only ever need to hash one variant.
+pub fn normalize_path(path: &str) -> String {
+    let mut normalized = String::with_capacity(path.len() + 2);
+    for segment in path.split('/').filter(|s| !s.is_empty()) {
+        normalized.push('/');
+        normalized.push_str(segment);
+    }
+    if normalized.is_empty() {
+        return "/".to_string();
+    }
+    if path.ends_with('/') {
+        normalized.push('/');
+    }
+    normalized
+}
+
+/// Directory prefixes of a normalized path, including the path itself when it
+/// is a directory, e.g. "/a/b/" -> ["/a/", "/a/b/"]
+pub fn dir_prefixes(path: &str) -> impl Iterator<Item = &str> {
+    path.match_indices('/')
+        .filter(|(i, _)| *i > 0)
+        .map(move |(i, _)| &path[..=i])
+}
+
+/// Maximum number of rows returned by one `get_changes` page
+pub const MAX_CHANGES_PAGE: i64 = 1000;
+
+/// A path that differs from an existing path only by letter case.
+///
+/// Returned (inside `anyhow::Error`) when case-insensitive collision detection
+/// is enabled, since such paths clobber each other on macOS/Windows clients.
+#[derive(Debug)]
+pub struct PathCollision {
+    pub path: String,
+    pub existing: String,
+}
+
+impl std::fmt::Display for PathCollision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Path '{}' collides with existing path '{}' (paths differ only by case)",
+            self.path, self.existing
+        )
+    }
+}
+
+impl std::error::Error for PathCollision {}
+
+/// Directory prefixes of a path, e.g. "/a/b/c.txt" -> ["/a/", "/a/b/"]
+fn ancestor_dirs(path: &str) -> Vec<String> {
+    let trimmed = path.trim_end_matches('/');
+    trimmed
+        .match_indices('/')
+        .filter(|(i, _)| *i > 0)
+        .map(|(i, _)| trimmed[..=i].to_string())
+        .collect()
+}
+
+/// Find an existing path visible to `owner_id` that equals `path` (or one of its
+/// parent directories) under case folding but not exactly.
+///
+/// Paths at or under `exclude` are ignored so a case-only rename does not
+/// collide with itself.
+pub async fn find_case_collision(
+    executor: impl PgExecutor<'_>,
+    path: &str,
+    owner_id: Uuid,
+    exclude: Option<&str>,
+) -> anyhow::Result<Option<String>> {
+    let leaf = path.trim_end_matches('/');
+    let prefixes: Vec<String> = ancestor_dirs(path)
+        .iter()
+        .map(|d| format!("{}%", escape_like(d)))
+        .collect();
+    let exclude = exclude.map(|e| e.trim_end_matches('/'));
+    let exclude_children = exclude.map(|e| format!("{}/%", escape_like(e)));
+
+    let existing: Option<(String,)> = sqlx::query_as(
+        r#"
+        SELECT path FROM files
+        WHERE is_deleted = FALSE
+          AND (owner_id = $2 OR owner_id IS NULL)
+          AND ($4::text IS NULL OR (RTRIM(path, '/') <> $4 AND path NOT LIKE $5 ESCAPE '\'))
+          AND (
+            (LOWER(RTRIM(path, '/')) = LOWER($1) AND RTRIM(path, '/') <> $1)
+            OR EXISTS (
+                SELECT 1 FROM UNNEST($3::text[]) AS d(prefix)
+                WHERE LOWER(path) LIKE LOWER(d.prefix) ESCAPE '\'
+                  AND path NOT LIKE d.prefix ESCAPE '\'
+            )
+          )
+        LIMIT 1
+        "#,
+    )
+    .bind(leaf)
+    .bind(owner_id)
+    .bind(&prefixes)
+    .bind(exclude)
+    .bind(&exclude_children)
+    .fetch_optional(executor)
+    .await?;
+
+    Ok(existing.map(|(p,)| p))
+}
+
+/// Fail with [`PathCollision`] if `path` collides case-insensitively
+async fn ensure_no_case_collision(
+    executor: impl PgExecutor<'_>,
+    path: &str,
+    owner_id: Uuid,
+    exclude: Option<&str>,
+) -> anyhow::Result<()> {
+    if let Some(existing) = find_case_collision(executor, path, owner_id, exclude).await? {
+        return Err(PathCollision {
+            path: path.to_string(),
+            existing,
+        }
+        .into());
+    }
+    Ok(())
+}
+
 #[allow(dead_code)]
 #[derive(Debug, Serialize, sqlx::FromRow)]
 pub struct File {
@@ -37,6 +173,11 @@ pub struct FileWithVersion {
     pub size_bytes: Option<i64>,
     pub blob_hash: Option<String>,
     pub original_hash_id: Option<String>,
+    pub owner_id: Option<Uuid>,
+    /// Set for alias files: the file whose current version this path serves
+    pub link_target_id: Option<Uuid>,
+    /// Permission bits recorded by the uploading client (None = unknown)
+    pub unix_mode: Option<i32>,
 }
 
 #[allow(dead_code)]
@@ -57,6 +198,47 @@ pub struct FileChange {
 mod tests {
     use super::*;
 
+    fn entry(name: &str, is_folder: bool, size_bytes: i64, minutes_ago: i64) -> DirectoryEntry {
+        DirectoryEntry {
+            id: name.to_string(),
+            name: name.to_string(),
+            path: format!("/{}", name),
+            is_folder,
+            size_bytes,
+            updated_at: Utc::now() - chrono::Duration::minutes(minutes_ago),
+            version_id: None,
+            link_target_id: None,
+        }
+    }
+
+    #[test]
+    fn test_directory_sort_orders() {
+        let mut entries = vec![
+            entry("b.txt", false, 10, 5),
+            entry("Docs", true, 0, 1),
+            entry("a.txt", false, 30, 9),
+            entry("B.txt", false, 10, 5),
+            entry("archive", true, 0, 3),
+        ];
+        let names = |entries: &[DirectoryEntry]| entries.iter().map(|e| e.name.clone()).collect::<Vec<_>>();
+
+        DirectorySort::Name.apply(&mut entries);
+        assert_eq!(names(&entries), ["a.txt", "archive", "B.txt", "b.txt", "Docs"]);
+        DirectorySort::FoldersFirst.apply(&mut entries);
+        assert_eq!(names(&entries), ["archive", "Docs", "a.txt", "B.txt", "b.txt"]);
+        DirectorySort::Size.apply(&mut entries);
+        assert_eq!(names(&entries), ["a.txt", "B.txt", "b.txt", "archive", "Docs"]);
+        DirectorySort::Modified.apply(&mut entries);
+        assert_eq!(names(&entries), ["Docs", "archive", "B.txt", "b.txt", "a.txt"]);
+    }
+
+    #[test]
+    fn test_ancestor_dirs() {
+        assert_eq!(ancestor_dirs("/a/b/c.txt"), vec!["/a/", "/a/b/"]);
+        assert_eq!(ancestor_dirs("/a/b/"), vec!["/a/"]);
+        assert!(ancestor_dirs("/top.txt").is_empty());
+    }
+
     #[test]
     fn test_escape_like_percent() {
         assert_eq!(escape_like("%"), "\\%");
@@ -77,24 +259,143 @@ mod tests {
         assert_eq!(escape_like("hello"), "hello");
     }
 
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(normalize_path("docs/a.txt"), "/docs/a.txt");
+        assert_eq!(normalize_path("//docs//a.txt"), "/docs/a.txt");
+        assert_eq!(normalize_path("docs/"), "/docs/");
+        assert_eq!(normalize_path("/docs//sub//"), "/docs/sub/");
+        assert_eq!(normalize_path(""), "/");
+        assert_eq!(normalize_path("//"), "/");
+    }
+
+    #[tokio::test]
+    async fn test_lookup_by_non_canonical_path() {
+        let Some(pool) = crate::db::test_pool().await else { return };
+        let file = upsert_file_global(&pool, "/docs/a.txt").await.unwrap();
+        let user_id = Uuid::new_v4();
+
+        for path in ["/docs/a.txt", "docs/a.txt", "//docs//a.txt"] {
+            let found = get_file_by_path(&pool, user_id, path).await.unwrap();
+            assert_eq!(found.map(|f| f.id), Some(file.id), "{}", path);
+            assert!(path_exists(&pool, path, user_id).await.unwrap(), "{}", path);
+        }
+        assert!(path_exists(&pool, "docs", user_id).await.unwrap());
+        assert!(get_file_by_path(&pool, user_id, "docs/b.txt").await.unwrap().is_none());
+
+        let moved = move_path(&pool, "docs/a.txt", "docs//b.txt", user_id, false).await.unwrap();
+        assert_eq!(moved.path, "/docs/b.txt");
+    }
+
+    #[tokio::test]
+    async fn test_get_directory_entry() {
+        let Some(pool) = crate::db::test_pool().await else { return };
+        let user_id = Uuid::new_v4();
+        upsert_file_global(&pool, "/docs/a.txt").await.unwrap();
+        upsert_file_global(&pool, "/docs/sub/b.txt").await.unwrap();
+        upsert_file_global(&pool, "/docs/my_sub/c.txt").await.unwrap();
+
+        // `_` is a LIKE wildcard, escaped in the folder lookup
+        for path in ["docs", "docs/sub", "docs/my_sub"] {
+            let listed = list_directory(&pool, path.rsplit_once('/').map_or("", |(p, _)| p))
+                .await
+                .unwrap()
+                .into_iter()
+                .find(|e| path.ends_with(e.name.as_str()))
+                .unwrap();
+            let entry = get_directory_entry(&pool, user_id, path).await.unwrap().unwrap();
+            assert!(entry.is_folder);
+            assert_eq!((entry.id, entry.name, entry.path), (listed.id, listed.name, listed.path));
+        }
+
+        let entry = get_directory_entry(&pool, user_id, "/docs/a.txt").await.unwrap().unwrap();
+        assert!(!entry.is_folder);
+        assert_eq!(entry.name, "a.txt");
+        assert!(get_directory_entry(&pool, user_id, "docs/su").await.unwrap().is_none());
+        assert!(get_directory_entry(&pool, user_id, "/").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_list_directory_with_owner() {
+        use crate::db::users::{create_user, UserRole};
+
+        let Some(pool) = crate::db::test_pool().await else { return };
+        let mut owners = Vec::new();
+        for _ in 0..2 {
+            let name = format!("lister-{}", Uuid::new_v4());
+            owners.push(create_user(&pool, &name, "unused", UserRole::Editor).await.unwrap().id);
+        }
+        let root = format!("/owned-{}", Uuid::new_v4());
+        upsert_file_with_owner(&pool, &format!("{}/mine.txt", root), owners[0], false).await.unwrap();
+        upsert_file_with_owner(&pool, &format!("{}/theirs/b.txt", root), owners[1], false).await.unwrap();
+
+        let names = |entries: Vec<DirectoryEntry>| entries.into_iter().map(|e| e.name).collect::<Vec<_>>();
+        assert_eq!(names(list_directory(&pool, &root).await.unwrap()), ["mine.txt", "theirs"]);
+        let listed = list_directory_with_owner(&pool, owners[0], &root).await.unwrap();
+        assert_eq!(names(listed.clone()), ["mine.txt"]);
+        // Everything listed can be looked up by the same user
+        for entry in listed {
+            assert!(get_directory_entry(&pool, owners[0], &entry.path).await.unwrap().is_some());
+        }
+        assert!(get_directory_entry(&pool, owners[0], &format!("{}/theirs", root)).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_soft_delete_if_unchanged() {
+        let Some(pool) = crate::db::test_pool().await else { return };
+        let user_id = Uuid::new_v4();
+        let root = format!("/unchanged-{}", Uuid::new_v4());
+        let dir = upsert_file_global(&pool, &format!("{}/", root)).await.unwrap();
+        let child = upsert_file_global(&pool, &format!("{}/a.txt", root)).await.unwrap();
+
+        // Modified after the given time, or not at the given version: left alone
+        let before = dir.updated_at - chrono::Duration::seconds(5);
+        assert!(!soft_delete_if_unchanged(&pool, dir.id, user_id, None, Some(before)).await.unwrap());
+        assert!(!soft_delete_if_unchanged(&pool, dir.id, user_id, Some(Uuid::new_v4()), None).await.unwrap());
+        assert!(!get_file_by_id(&pool, child.id, user_id).await.unwrap().unwrap().is_deleted);
+
+        // The same second as an HTTP-date passes despite the sub-second remainder
+        use chrono::SubsecRound;
+        let since = Some(dir.updated_at.trunc_subsecs(0));
+        assert!(soft_delete_if_unchanged(&pool, dir.id, user_id, None, since).await.unwrap());
+        assert!(get_file_by_id(&pool, child.id, user_id).await.unwrap().unwrap().is_deleted);
+        assert!(!soft_delete_if_unchanged(&pool, dir.id, user_id, None, since).await.unwrap());
+    }
+
+    #[test]
+    fn test_dir_prefixes() {
+        assert_eq!(dir_prefixes("/a/b/c.txt").collect::<Vec<_>>(), vec!["/a/", "/a/b/"]);
+        assert_eq!(dir_prefixes("/a/b/").collect::<Vec<_>>(), vec!["/a/", "/a/b/"]);
+        assert_eq!(dir_prefixes("/c.txt").count(), 0);
+    }
+
     #[test]
     fn test_escape_like_mixed() {
         assert_eq!(escape_like("foo%bar_baz"), "foo\\%bar\\_baz");
     }
+
+    #[test]
+    fn test_under_folder_pattern() {
+        assert_eq!(under_folder_pattern("/"), "/%");
+        // "_" must not stand for any character, or "/myXdocs/" would match
+        assert_eq!(under_folder_pattern("/my_docs/"), "/my\\_docs/%");
+        assert_eq!(under_folder_pattern("/100%/"), "/100\\%/%");
+    }
 }
 
 /// Create or update a file record (upsert) - global (no owner)
 pub async fn upsert_file_global(pool: &DbPool, path: &str) -> anyhow::Result<File> {
+    let path = normalize_path(path);
     let file = sqlx::query_as::<_, File>(
         r#"
         INSERT INTO files (path)
         VALUES ($1)
         ON CONFLICT (path)
-        DO UPDATE SET updated_at = NOW(), is_deleted = FALSE
+        DO UPDATE SET updated_at = NOW(), is_deleted = FALSE, link_target_id = NULL
         RETURNING id, path, current_version_id, is_deleted, created_at, updated_at, owner_id, original_hash_id
         "#,
     )
-    .bind(path)
+    .bind(&path)
     .fetch_one(pool)
     .await?;
 
@@ -109,6 +410,7 @@ pub async fn upsert_file_with_dates(
     created_at: Option<DateTime<Utc>>,
     updated_at: Option<DateTime<Utc>>,
 ) -> anyhow::Result<File> {
+    let path = normalize_path(path);
     let file = sqlx::query_as::<_, File>(
         r#"
         INSERT INTO files (path, created_at, updated_at)
@@ -116,11 +418,12 @@ pub async fn upsert_file_with_dates(
         ON CONFLICT (path)
         DO UPDATE SET 
             updated_at = COALESCE($3, files.updated_at),
-            is_deleted = FALSE
+            is_deleted = FALSE,
+            link_target_id = NULL
         RETURNING id, path, current_version_id, is_deleted, created_at, updated_at, owner_id, original_hash_id
         "#,
     )
-    .bind(path)
+    .bind(&path)
     .bind(created_at)
     .bind(updated_at)
     .fetch_one(pool)
@@ -129,14 +432,40 @@ pub async fn upsert_file_with_dates(
     Ok(file)
 }
 
+/// Serialize writers of `path` until the transaction on `conn` ends.
+///
+/// Uploads hold this from the file upsert until their version is current, so
+/// two uploads of one path can't both build on the same `current_version_id`.
+/// The key is the owner and the path ignoring case, which also covers
+/// `CASE_INSENSITIVE_PATHS` collisions, so one user's uploads never wait on
+/// another's.
+pub async fn lock_path(conn: &mut PgConnection, owner_id: Uuid, path: &str) -> anyhow::Result<()> {
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtextextended($1::text || ':' || LOWER($2), 0))")
+        .bind(owner_id)
+        .bind(normalize_path(path))
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
 /// Create or update a file record with owner and client-provided dates (secure version)
+///
+/// Runs on the caller's transaction, which should hold [`lock_path`]. With
+/// `case_insensitive`, fails with [`PathCollision`] if the path differs from
+/// an existing one only by case.
 pub async fn upsert_file_with_owner_and_dates(
-    pool: &DbPool, 
+    conn: &mut PgConnection,
     path: &str,
     owner_id: Uuid,
     created_at: Option<DateTime<Utc>>,
     updated_at: Option<DateTime<Utc>>,
+    case_insensitive: bool,
 ) -> anyhow::Result<File> {
+    let path = normalize_path(path);
+    if case_insensitive {
+        ensure_no_case_collision(&mut *conn, &path, owner_id, None).await?;
+    }
+
     let file = sqlx::query_as::<_, File>(
         r#"
         INSERT INTO files (path, owner_id, created_at, updated_at)
@@ -144,38 +473,48 @@ pub async fn upsert_file_with_owner_and_dates(
         ON CONFLICT (path)
         DO UPDATE SET 
             updated_at = COALESCE($4, files.updated_at),
-            is_deleted = FALSE
+            is_deleted = FALSE,
+            link_target_id = NULL
         WHERE files.owner_id = $2 OR files.owner_id IS NULL
         RETURNING id, path, current_version_id, is_deleted, created_at, updated_at, owner_id, original_hash_id
         "#,
     )
-    .bind(path)
+    .bind(&path)
     .bind(owner_id)
     .bind(created_at)
     .bind(updated_at)
-    .fetch_one(pool)
+    .fetch_one(conn)
     .await?;
 
     Ok(file)
 }
 
 /// Create or update a file record with owner
+///
+/// With `case_insensitive`, fails with [`PathCollision`] if the path differs
+/// from an existing one only by case.
 pub async fn upsert_file_with_owner(
     pool: &DbPool,
     path: &str,
     owner_id: Uuid,
+    case_insensitive: bool,
 ) -> anyhow::Result<File> {
+    let path = normalize_path(path);
+    if case_insensitive {
+        ensure_no_case_collision(pool, &path, owner_id, None).await?;
+    }
+
     let file = sqlx::query_as::<_, File>(
         r#"
         INSERT INTO files (path, owner_id)
         VALUES ($1, $2)
         ON CONFLICT (path)
-        DO UPDATE SET updated_at = NOW(), is_deleted = FALSE
+        DO UPDATE SET updated_at = NOW(), is_deleted = FALSE, link_target_id = NULL
         WHERE files.owner_id = $2 OR files.owner_id IS NULL
         RETURNING id, path, current_version_id, is_deleted, created_at, updated_at, owner_id, original_hash_id
         "#,
     )
-    .bind(path)
+    .bind(&path)
     .bind(owner_id)
     .fetch_one(pool)
     .await?;
@@ -191,6 +530,7 @@ pub async fn upsert_file_with_owner_and_hash(
     owner_id: Uuid,
     original_hash_id: Option<String>,
 ) -> anyhow::Result<File> {
+    let path = normalize_path(path);
     let file = sqlx::query_as::<_, File>(
         r#"
         INSERT INTO files (path, owner_id, original_hash_id)
@@ -199,12 +539,13 @@ pub async fn upsert_file_with_owner_and_hash(
         DO UPDATE SET
             updated_at = NOW(),
             is_deleted = FALSE,
+            link_target_id = NULL,
             original_hash_id = COALESCE($3, files.original_hash_id)
         WHERE files.owner_id = $2 OR files.owner_id IS NULL
         RETURNING id, path, current_version_id, is_deleted, created_at, updated_at, owner_id, original_hash_id
         "#,
     )
-    .bind(path)
+    .bind(&path)
     .bind(owner_id)
     .bind(&original_hash_id)
     .fetch_one(pool)
@@ -213,6 +554,42 @@ pub async fn upsert_file_with_owner_and_hash(
     Ok(file)
 }
 
+/// Give a virtual folder a record of its own, returning it and whether this
+/// call created it. Its `original_hash_id` is the ID clients derive from the
+/// folder's path (the BLAKE3 hash of `dir_path`), so that ID resolves without
+/// scanning every path. The hash is left unset if another record already
+/// claims it, e.g. a folder moved away from here.
+pub async fn materialize_folder(pool: &DbPool, dir_path: &str, owner_id: Uuid) -> anyhow::Result<(File, bool)> {
+    let dir_path = normalize_path(dir_path);
+    let virtual_id = blake3::hash(dir_path.as_bytes()).to_hex().to_string();
+
+    // Either unique column may conflict: the path if a concurrent request
+    // created the record first, the hash if another record claims it
+    let inserted = sqlx::query_as::<_, File>(
+        r#"
+        INSERT INTO files (path, owner_id, original_hash_id)
+        VALUES ($1, $2, $3)
+        ON CONFLICT DO NOTHING
+        RETURNING id, path, current_version_id, is_deleted, created_at, updated_at, owner_id, original_hash_id
+        "#,
+    )
+    .bind(&dir_path)
+    .bind(owner_id)
+    .bind(&virtual_id)
+    .fetch_optional(pool)
+    .await?;
+    if let Some(file) = inserted {
+        return Ok((file, true));
+    }
+
+    match get_file_by_path(pool, owner_id, &dir_path).await? {
+        Some(file) if !file.is_deleted => Ok((file, false)),
+        // A deleted record is revived with the hash it had; without one, the
+        // hash was claimed and the record is created without it
+        _ => Ok((upsert_file_with_owner_and_hash(pool, &dir_path, owner_id, None).await?, true)),
+    }
+}
+
 /// Legacy: Create or update a file record with user (for API compatibility)
 #[allow(dead_code)]
 pub async fn upsert_file(pool: &DbPool, _user_id: Uuid, path: &str) -> anyhow::Result<File> {
@@ -252,7 +629,7 @@ pub async fn get_file_by_id_global(
         r#"
         SELECT f.id, f.path, f.current_version_id, f.is_deleted,
                f.created_at, f.updated_at, v.size_bytes, v.blob_hash,
-               f.original_hash_id
+               f.original_hash_id, f.owner_id, f.link_target_id, f.unix_mode
         FROM files f
         LEFT JOIN versions v ON f.current_version_id = v.id
         WHERE f.id = $1
@@ -295,7 +672,7 @@ pub async fn get_file_by_id_with_owner(
         r#"
         SELECT f.id, f.path, f.current_version_id, f.is_deleted,
                f.created_at, f.updated_at, v.size_bytes, v.blob_hash,
-               f.original_hash_id
+               f.original_hash_id, f.owner_id, f.link_target_id, f.unix_mode
         FROM files f
         LEFT JOIN versions v ON f.current_version_id = v.id
         WHERE f.id = $1 AND (f.owner_id = $2 OR f.owner_id IS NULL)
@@ -309,8 +686,8 @@ pub async fn get_file_by_id_with_owner(
     Ok(file)
 }
 
-/// Get a file by path with ownership check
-#[allow(dead_code)]
+/// Get a file by path with ownership check. `path` may be in any form
+/// [`normalize_path`] accepts.
 pub async fn get_file_by_path(
     pool: &DbPool,
     user_id: Uuid,
@@ -323,7 +700,7 @@ pub async fn get_file_by_path(
         WHERE path = $1 AND (owner_id = $2 OR owner_id IS NULL)
         "#,
     )
-    .bind(path)
+    .bind(normalize_path(path))
     .bind(user_id)
     .fetch_optional(pool)
     .await?;
@@ -331,6 +708,56 @@ pub async fn get_file_by_path(
     Ok(file)
 }
 
+/// Create a link at `path` pointing at `target_id`.
+///
+/// Revives a soft-deleted record at the same path. Returns None if a live
+/// file already exists there.
+pub async fn create_link(
+    pool: &DbPool,
+    path: &str,
+    owner_id: Uuid,
+    target_id: Uuid,
+    case_insensitive: bool,
+) -> anyhow::Result<Option<File>> {
+    let path = normalize_path(path);
+    if case_insensitive {
+        ensure_no_case_collision(pool, &path, owner_id, None).await?;
+    }
+
+    let file = sqlx::query_as::<_, File>(
+        r#"
+        INSERT INTO files (path, owner_id, link_target_id)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (path)
+        DO UPDATE SET
+            link_target_id = $3,
+            current_version_id = NULL,
+            is_deleted = FALSE,
+            updated_at = NOW()
+        WHERE files.is_deleted = TRUE AND (files.owner_id = $2 OR files.owner_id IS NULL)
+        RETURNING id, path, current_version_id, is_deleted, created_at, updated_at, owner_id, original_hash_id
+        "#,
+    )
+    .bind(&path)
+    .bind(owner_id)
+    .bind(target_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(file)
+}
+
+/// Get the target of a link file (None if the file is not a link)
+pub async fn get_link_target(pool: &DbPool, file_id: Uuid) -> anyhow::Result<Option<Uuid>> {
+    let target: Option<(Option<Uuid>,)> =
+        sqlx::query_as("SELECT link_target_id FROM files WHERE id = $1")
+            .bind(file_id)
+            .fetch_optional(pool)
+            .await?;
+
+    Ok(target.and_then(|(t,)| t))
+}
+
 /// Set the current version of a file
 /// NOTE: Does NOT update `updated_at` to preserve the original file modification date
 pub async fn set_current_version(
@@ -353,13 +780,28 @@ pub async fn set_current_version(
     Ok(())
 }
 
+/// Record the permission bits of a file's content, as sent by the client.
+/// They belong to the file, so uploads that send none leave them as they are.
+pub async fn set_unix_mode(
+    executor: impl PgExecutor<'_>,
+    file_id: Uuid,
+    unix_mode: i32,
+) -> anyhow::Result<()> {
+    sqlx::query("UPDATE files SET unix_mode = $2 WHERE id = $1")
+        .bind(file_id)
+        .bind(unix_mode)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
 /// Soft delete a file
 #[allow(dead_code)]
 pub async fn soft_delete(pool: &DbPool, file_id: Uuid) -> anyhow::Result<()> {
     sqlx::query(
         r#"
         UPDATE files
-        SET is_deleted = TRUE, updated_at = NOW()
+        SET is_deleted = TRUE, deleted_batch_id = NULL, updated_at = NOW()
         WHERE id = $1
         "#,
     )
@@ -386,7 +828,7 @@ pub async fn soft_delete_with_owner(pool: &DbPool, file_id: Uuid, user_id: Uuid)
     let result = sqlx::query(
         r#"
         UPDATE files
-        SET is_deleted = TRUE, updated_at = NOW()
+        SET is_deleted = TRUE, deleted_batch_id = NULL, updated_at = NOW()
         WHERE id = $1 AND (owner_id = $2 OR owner_id IS NULL)
         "#,
     )
@@ -398,7 +840,62 @@ pub async fn soft_delete_with_owner(pool: &DbPool, file_id: Uuid, user_id: Uuid)
     Ok(result.rows_affected() > 0)
 }
 
-/// Soft delete a file and all children (recursive) with ownership check
+/// Soft delete a file, or a directory and everything beneath it, only if it
+/// still meets the caller's preconditions: its current version is
+/// `expected_version_id`, and it was not modified after `unmodified_since`
+/// (compared to the second when that is a whole second, as an HTTP-date is).
+///
+/// The preconditions are checked by the same statement that deletes the
+/// record, so a change between the caller's look and the delete can't slip
+/// through. Returns false if the record no longer meets them (or was deleted).
+pub async fn soft_delete_if_unchanged(
+    pool: &DbPool,
+    file_id: Uuid,
+    user_id: Uuid,
+    expected_version_id: Option<Uuid>,
+    unmodified_since: Option<DateTime<Utc>>,
+) -> anyhow::Result<bool> {
+    let mut tx = pool.begin().await?;
+    let batch_id = Uuid::new_v4();
+
+    let path: Option<String> = sqlx::query_scalar(
+        r#"
+        UPDATE files
+        SET is_deleted = TRUE,
+            deleted_batch_id = CASE WHEN path LIKE '%/' THEN $5 END,
+            updated_at = NOW()
+        WHERE id = $1 AND (owner_id = $2 OR owner_id IS NULL) AND is_deleted = FALSE
+          AND ($3::uuid IS NULL OR current_version_id = $3)
+          AND ($4::timestamptz IS NULL
+               OR CASE WHEN date_trunc('second', $4) = $4
+                       THEN date_trunc('second', updated_at)
+                       ELSE updated_at END <= $4)
+        RETURNING path
+        "#,
+    )
+    .bind(file_id)
+    .bind(user_id)
+    .bind(expected_version_id)
+    .bind(unmodified_since)
+    .bind(batch_id)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let Some(path) = path else {
+        return Ok(false);
+    };
+    if path.ends_with('/') {
+        soft_delete_tree_in_batch(&mut *tx, &path, user_id, batch_id).await?;
+    }
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// Soft delete a file and all children (recursive) with ownership check.
+///
+/// Rows newly deleted by a directory delete are tagged with a fresh
+/// `deleted_batch_id` so [`restore_deleted_tree`] can undo exactly this
+/// operation; rows that were already deleted keep their earlier batch.
 pub async fn soft_delete_recursive_with_owner(pool: &DbPool, file_id: Uuid, user_id: Uuid) -> anyhow::Result<bool> {
     // 1. Get the file with ownership check
     let file = get_file_by_id_with_owner(pool, file_id, user_id)
@@ -407,31 +904,93 @@ pub async fn soft_delete_recursive_with_owner(pool: &DbPool, file_id: Uuid, user
 
     // 2. If it's a directory (path ends in /), delete all children AND the directory itself
     if file.path.ends_with('/') {
-        let prefix_pattern = format!("{}%", escape_like(&file.path));
-
-        // Delete children matching the prefix AND the directory record itself (with ownership check)
-        let result = sqlx::query(
-            r#"
-            UPDATE files
-            SET is_deleted = TRUE, updated_at = NOW()
-            WHERE (path LIKE $1 ESCAPE '\' OR id = $2) AND (owner_id = $3 OR owner_id IS NULL)
-            "#
-        )
-        .bind(prefix_pattern)
-        .bind(file_id)
-        .bind(user_id)
-        .execute(pool)
-        .await?;
-        
-        Ok(result.rows_affected() > 0)
+        Ok(soft_delete_tree_with_owner(pool, &file.path, user_id).await? > 0)
     } else {
         // Just delete the single file with ownership check
         soft_delete_with_owner(pool, file_id, user_id).await
     }
 }
 
+/// Soft delete directory `dir_path` (ending in `/`) and everything beneath
+/// it, whether or not the directory has a record of its own. Rows newly
+/// deleted here share one batch ID, so [`restore_deleted_tree`] can undo them
+/// together.
+/// Returns the number of rows deleted.
+pub async fn soft_delete_tree_with_owner(pool: &DbPool, dir_path: &str, user_id: Uuid) -> anyhow::Result<u64> {
+    soft_delete_tree_in_batch(pool, dir_path, user_id, Uuid::new_v4()).await
+}
+
+/// Soft delete `dir_path` and everything beneath it, tagging newly deleted
+/// rows with `batch_id`
+async fn soft_delete_tree_in_batch(
+    executor: impl PgExecutor<'_>,
+    dir_path: &str,
+    user_id: Uuid,
+    batch_id: Uuid,
+) -> anyhow::Result<u64> {
+    let prefix_pattern = format!("{}%", escape_like(&normalize_path(dir_path)));
+
+    let result = sqlx::query(
+        r#"
+        UPDATE files
+        SET is_deleted = TRUE,
+            deleted_batch_id = CASE WHEN is_deleted THEN deleted_batch_id ELSE $3 END,
+            updated_at = NOW()
+        WHERE path LIKE $1 ESCAPE '\' AND (owner_id = $2 OR owner_id IS NULL)
+        "#
+    )
+    .bind(prefix_pattern)
+    .bind(user_id)
+    .bind(batch_id)
+    .execute(executor)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Undo the recursive delete that removed directory `dir_id`: un-delete the
+/// directory and every row beneath it that was deleted in the same operation,
+/// each keeping its current version. Returns the restored paths (empty if the
+/// directory is not deleted or was not removed by a recursive delete).
+pub async fn restore_deleted_tree(
+    pool: &DbPool,
+    dir_id: Uuid,
+    dir_path: &str,
+    user_id: Uuid,
+) -> anyhow::Result<Vec<String>> {
+    let prefix_pattern = format!("{}%", escape_like(dir_path));
+
+    let restored: Vec<(String,)> = sqlx::query_as(
+        r#"
+        UPDATE files
+        SET is_deleted = FALSE, deleted_batch_id = NULL, updated_at = NOW()
+        WHERE is_deleted = TRUE
+          AND (owner_id = $3 OR owner_id IS NULL)
+          AND (id = $1 OR path LIKE $2 ESCAPE '\')
+          AND deleted_batch_id = (
+              SELECT deleted_batch_id FROM files
+              WHERE id = $1 AND is_deleted = TRUE AND (owner_id = $3 OR owner_id IS NULL)
+          )
+        RETURNING path
+        "#,
+    )
+    .bind(dir_id)
+    .bind(prefix_pattern)
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(restored.into_iter().map(|(path,)| path).collect())
+}
+
 /// Move or rename a file (and its children if it's a directory)
-pub async fn move_file(pool: &DbPool, file_id: Uuid, new_path: &str, user_id: Uuid) -> anyhow::Result<File> {
+pub async fn move_file(
+    pool: &DbPool,
+    file_id: Uuid,
+    new_path: &str,
+    user_id: Uuid,
+    case_insensitive: bool,
+) -> anyhow::Result<File> {
     tracing::debug!(file_id = %file_id, target = %new_path, "move_file entry");
 
     // 1. Get the original file to check permissions and get old path
@@ -443,13 +1002,54 @@ pub async fn move_file(pool: &DbPool, file_id: Uuid, new_path: &str, user_id: Uu
     tracing::debug!(old_path = %old_path, new_path = %new_path, "move_file resolving to move_path");
 
     // Delegate to path-based move logic
-    move_path(pool, &old_path, new_path, user_id).await
+    move_path(pool, &old_path, new_path, user_id, case_insensitive).await
+}
+
+/// Whether a live file, directory record or virtual folder (a prefix of
+/// live files) exists at `path`
+pub async fn path_exists(pool: &DbPool, path: &str, user_id: Uuid) -> anyhow::Result<bool> {
+    let normalized = normalize_path(path);
+    let trimmed = normalized.trim_end_matches('/');
+    let (exists,): (bool,) = sqlx::query_as(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM files
+            WHERE is_deleted = FALSE
+              AND (owner_id = $3 OR owner_id IS NULL)
+              AND (path = $1 OR path = $1 || '/' OR path LIKE $2 ESCAPE '\')
+        )
+        "#,
+    )
+    .bind(trimmed)
+    .bind(format!("{}/%", escape_like(trimmed)))
+    .bind(user_id)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(exists)
 }
 
 /// Core move logic working on paths (handles both real and virtual folders)
-pub async fn move_path(pool: &DbPool, old_path: &str, new_path: &str, user_id: Uuid) -> anyhow::Result<File> {
+///
+/// With `case_insensitive`, fails with [`PathCollision`] if the target differs
+/// from another existing path only by case (case-only renames are allowed).
+pub async fn move_path(
+    pool: &DbPool,
+    old_path: &str,
+    new_path: &str,
+    user_id: Uuid,
+    case_insensitive: bool,
+) -> anyhow::Result<File> {
     tracing::debug!(old_path = %old_path, new_path = %new_path, "move_path start");
 
+    if new_path.is_empty() {
+        return Err(anyhow::anyhow!("Invalid empty target path"));
+    }
+    let old_path = normalize_path(old_path);
+    let old_path = old_path.as_str();
+    let new_path = normalize_path(new_path);
+    let new_path = new_path.as_str();
+
     // Smart Root Handling:
     // If user tries to move to "/", they probably mean "Move INTO root", not "Rename TO root".
     // We should infer the new path from the old filename.
@@ -480,9 +1080,6 @@ pub async fn move_path(pool: &DbPool, old_path: &str, new_path: &str, user_id: U
              // Fallback if we can't parse name (shouldn't happen for valid paths)
              return Err(anyhow::anyhow!("Cannot move root to root"));
         }
-    } else if new_path == "" {
-        // Handle empty path as root too just in case
-        return Err(anyhow::anyhow!("Invalid empty target path"));
     }
 
     // Use the resolved path for existence check
@@ -503,6 +1100,10 @@ pub async fn move_path(pool: &DbPool, old_path: &str, new_path: &str, user_id: U
         return Err(anyhow::anyhow!("Target path already exists"));
     }
 
+    if case_insensitive {
+        ensure_no_case_collision(pool, new_path_str, user_id, Some(old_path)).await?;
+    }
+
     // 2. Perform the move
     
     // Self-Healing Check: Does this path have children?
@@ -677,6 +1278,73 @@ pub async fn move_path(pool: &DbPool, old_path: &str, new_path: &str, user_id: U
     }
 }
 
+/// Outcome of [`normalize_stored_paths`]
+#[derive(Debug, Default)]
+pub struct PathNormalization {
+    /// Rows rewritten to their canonical path
+    pub updated: u64,
+    /// Rows left alone because their canonical path is taken by another row
+    pub conflicts: Vec<String>,
+}
+
+/// Rewrite stored paths into [`normalize_path`] form (run by `tangled migrate`)
+///
+/// Legacy directory records stored without a trailing slash (no version, not
+/// a link, but with rows beneath them) gain one. Idempotent.
+pub async fn normalize_stored_paths(pool: &DbPool) -> anyhow::Result<PathNormalization> {
+    let candidates: Vec<(Uuid, String, bool)> = sqlx::query_as(
+        r#"
+        SELECT id, path, is_legacy_dir FROM (
+            SELECT f.id, f.path,
+                   f.path NOT LIKE '%/'
+                   AND f.current_version_id IS NULL
+                   AND f.link_target_id IS NULL
+                   AND EXISTS (
+                       SELECT 1 FROM files c
+                       WHERE left(c.path, length(f.path) + 1) = f.path || '/'
+                   ) AS is_legacy_dir
+            FROM files f
+        ) candidates
+        WHERE path NOT LIKE '/%' OR path LIKE '%//%' OR is_legacy_dir
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut report = PathNormalization::default();
+    let mut tx = pool.begin().await?;
+    for (id, path, is_legacy_dir) in candidates {
+        let canonical = if is_legacy_dir {
+            normalize_path(&format!("{}/", path))
+        } else {
+            normalize_path(&path)
+        };
+        if canonical == path {
+            continue;
+        }
+
+        let result = sqlx::query(
+            r#"
+            UPDATE files SET path = $2
+            WHERE id = $1 AND NOT EXISTS (SELECT 1 FROM files WHERE path = $2)
+            "#,
+        )
+        .bind(id)
+        .bind(&canonical)
+        .execute(&mut *tx)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            report.conflicts.push(path);
+        } else {
+            report.updated += 1;
+        }
+    }
+    tx.commit().await?;
+
+    Ok(report)
+}
+
 /// Undelete a file
 pub async fn undelete(pool: &DbPool, file_id: Uuid) -> anyhow::Result<()> {
     sqlx::query(
@@ -711,7 +1379,7 @@ pub async fn list_files(
         r#"
         SELECT f.id, f.path, f.current_version_id, f.is_deleted,
                f.created_at, f.updated_at, v.size_bytes, v.blob_hash,
-               f.original_hash_id
+               f.original_hash_id, f.owner_id, f.link_target_id, f.unix_mode
         FROM files f
         LEFT JOIN versions v ON f.current_version_id = v.id
         WHERE ($1::text IS NULL OR f.path LIKE $1 ESCAPE '\')
@@ -747,6 +1415,156 @@ pub async fn list_files(
     Ok((files, total.0))
 }
 
+/// Files whose path contains `term`, ignoring case, and that carry `tag`
+/// (a key, and the value it must have if given), ordered by path. A `None`
+/// filter matches everything. Served by the trigram index on `files.path`.
+pub async fn search_files(
+    pool: &DbPool,
+    user_id: Uuid,
+    term: Option<&str>,
+    tag: Option<(&str, Option<&str>)>,
+    include_deleted: bool,
+    limit: i64,
+) -> anyhow::Result<Vec<FileWithVersion>> {
+    let pattern = term.map(|t| format!("%{}%", escape_like(t)));
+    let (tag_key, tag_value) = tag.unzip();
+
+    let files = sqlx::query_as::<_, FileWithVersion>(
+        r#"
+        SELECT f.id, f.path, f.current_version_id, f.is_deleted,
+               f.created_at, f.updated_at, v.size_bytes, v.blob_hash,
+               f.original_hash_id, f.owner_id, f.link_target_id, f.unix_mode
+        FROM files f
+        LEFT JOIN versions v ON f.current_version_id = v.id
+        WHERE ($1::text IS NULL OR f.path ILIKE $1 ESCAPE '\')
+          AND ($2 OR f.is_deleted = FALSE)
+          AND (f.owner_id = $3 OR f.owner_id IS NULL)
+          AND ($5::text IS NULL OR EXISTS (
+                SELECT 1 FROM file_tags t
+                WHERE t.file_id = f.id AND t.key = $5 AND ($6::text IS NULL OR t.value = $6)
+          ))
+        ORDER BY f.path
+        LIMIT $4
+        "#,
+    )
+    .bind(&pattern)
+    .bind(include_deleted)
+    .bind(user_id)
+    .bind(limit.clamp(1, 1000))
+    .bind(tag_key)
+    .bind(tag_value.flatten())
+    .fetch_all(pool)
+    .await?;
+
+    Ok(files)
+}
+
+/// A soft-deleted file or directory, as listed in the trash
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct TrashEntry {
+    pub id: Uuid,
+    pub path: String,
+    /// Size of the current version; None for directories
+    pub size_bytes: Option<i64>,
+    /// When the row was deleted (its last update)
+    pub deleted_at: DateTime<Utc>,
+    /// Shared by rows removed by one recursive directory delete; None for
+    /// files deleted on their own
+    pub deleted_batch_id: Option<Uuid>,
+}
+
+/// A user's deleted files and directories, most recently deleted first,
+/// with the total count for paging
+pub async fn list_trash(
+    pool: &DbPool,
+    user_id: Uuid,
+    limit: i64,
+    offset: i64,
+) -> anyhow::Result<(Vec<TrashEntry>, i64)> {
+    let entries = sqlx::query_as::<_, TrashEntry>(
+        r#"
+        SELECT f.id, f.path, v.size_bytes, f.updated_at AS deleted_at, f.deleted_batch_id
+        FROM files f
+        LEFT JOIN versions v ON f.current_version_id = v.id
+        WHERE f.is_deleted = TRUE AND (f.owner_id = $1 OR f.owner_id IS NULL)
+        ORDER BY f.updated_at DESC, f.path
+        LIMIT $2 OFFSET $3
+        "#,
+    )
+    .bind(user_id)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool)
+    .await?;
+
+    let total: (i64,) = sqlx::query_as(
+        "SELECT COUNT(*) FROM files WHERE is_deleted = TRUE AND (owner_id = $1 OR owner_id IS NULL)",
+    )
+    .bind(user_id)
+    .fetch_one(pool)
+    .await?;
+
+    Ok((entries, total.0))
+}
+
+/// Files whose current versions have the same content
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct DuplicateGroup {
+    pub content_hash: String,
+    pub size_bytes: i64,
+    /// Logical bytes beyond the first copy
+    pub wasted_bytes: i64,
+    /// The files, by path; `file_ids`, `paths` and `owner_ids` line up
+    pub file_ids: Vec<Uuid>,
+    pub paths: Vec<String>,
+    pub owner_ids: Vec<Option<Uuid>>,
+}
+
+/// Live, non-empty files grouped by their current version's content hash,
+/// where a group has more than one file
+const DUPLICATE_GROUPS_SQL: &str = r#"
+    SELECT v.blob_hash AS content_hash,
+           MAX(v.size_bytes) AS size_bytes,
+           ((COUNT(*) - 1) * MAX(v.size_bytes))::BIGINT AS wasted_bytes,
+           ARRAY_AGG(f.id ORDER BY f.path) AS file_ids,
+           ARRAY_AGG(f.path ORDER BY f.path) AS paths,
+           ARRAY_AGG(f.owner_id ORDER BY f.path) AS owner_ids
+    FROM files f
+    JOIN versions v ON v.id = f.current_version_id
+    WHERE f.is_deleted = FALSE
+      AND f.link_target_id IS NULL
+      AND f.path NOT LIKE '%/'
+      AND v.size_bytes > 0
+    GROUP BY v.blob_hash
+    HAVING COUNT(*) > 1
+"#;
+
+/// Duplicate content across every user's files, most wasted bytes first,
+/// with the group count and wasted bytes over all groups
+pub async fn list_duplicate_groups(
+    pool: &DbPool,
+    limit: i64,
+    offset: i64,
+) -> anyhow::Result<(Vec<DuplicateGroup>, i64, i64)> {
+    let groups = sqlx::query_as::<_, DuplicateGroup>(&format!(
+        "{} ORDER BY wasted_bytes DESC, content_hash LIMIT $1 OFFSET $2",
+        DUPLICATE_GROUPS_SQL
+    ))
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool)
+    .await?;
+
+    let (total_groups, total_wasted_bytes): (i64, i64) = sqlx::query_as(&format!(
+        "SELECT COUNT(*), COALESCE(SUM(wasted_bytes), 0)::BIGINT FROM ({}) g",
+        DUPLICATE_GROUPS_SQL
+    ))
+    .fetch_one(pool)
+    .await?;
+
+    Ok((groups, total_groups, total_wasted_bytes))
+}
+
 /// Get a file by its version ID (looks up version -> file relationship)
 pub async fn get_file_by_version_id(
     pool: &DbPool,
@@ -767,41 +1585,162 @@ pub async fn get_file_by_version_id(
     Ok(file)
 }
 
-/// Get file changes since a cursor (for delta sync) with ownership check
+/// Get file changes for delta sync with ownership check, ordered by `(updated_at, id)`.
+///
+/// `after` is an exact keyset position (the last `(updated_at, id)` seen), so
+/// rows sharing a timestamp at a page boundary are neither skipped nor repeated.
+/// `since` is a coarse timestamp filter for clients without a cursor.
 pub async fn get_changes(
     pool: &DbPool,
     user_id: Uuid,
-    cursor: Option<DateTime<Utc>>,
+    since: Option<DateTime<Utc>>,
+    after: Option<(DateTime<Utc>, Uuid)>,
     limit: i64,
 ) -> anyhow::Result<Vec<FileChange>> {
     // SECURITY: Cap limit to prevent memory exhaustion
-    let capped_limit = limit.min(1000);
+    let capped_limit = limit.min(MAX_CHANGES_PAGE);
+    let (after_time, after_id) = after.unzip();
 
     let changes = sqlx::query_as::<_, FileChange>(
         r#"
         SELECT f.id, f.path, f.current_version_id, f.is_deleted,
-               f.created_at, f.updated_at, v.size_bytes, v.blob_hash
+               f.created_at, f.updated_at, v.size_bytes, v.blob_hash,
+               f.original_hash_id
         FROM files f
         LEFT JOIN versions v ON f.current_version_id = v.id
         WHERE ($1::timestamptz IS NULL OR f.updated_at > $1)
+          AND ($4::timestamptz IS NULL OR (f.updated_at, f.id) > ($4, $5))
           AND (f.owner_id = $3 OR f.owner_id IS NULL)
-        ORDER BY f.updated_at ASC
+        ORDER BY f.updated_at ASC, f.id ASC
         LIMIT $2
         "#,
     )
-    .bind(cursor)
+    .bind(since)
     .bind(capped_limit)
     .bind(user_id)
+    .bind(after_time)
+    .bind(after_id)
     .fetch_all(pool)
     .await?;
 
     Ok(changes)
 }
 
+/// Stream every change matching `since`/`after`, in the same order and with the
+/// same ownership check as [`get_changes`] but without a page limit.
+///
+/// Rows are yielded as Postgres sends them, so memory use does not grow with
+/// the number of changes. The stream holds a pool connection until dropped.
+pub fn stream_changes(
+    pool: &DbPool,
+    user_id: Uuid,
+    since: Option<DateTime<Utc>>,
+    after: Option<(DateTime<Utc>, Uuid)>,
+) -> impl Stream<Item = anyhow::Result<FileChange>> + Send + '_ {
+    let (after_time, after_id) = after.unzip();
+
+    sqlx::query_as::<_, FileChange>(
+        r#"
+        SELECT f.id, f.path, f.current_version_id, f.is_deleted,
+               f.created_at, f.updated_at, v.size_bytes, v.blob_hash,
+               f.original_hash_id
+        FROM files f
+        LEFT JOIN versions v ON f.current_version_id = v.id
+        WHERE ($1::timestamptz IS NULL OR f.updated_at > $1)
+          AND ($3::timestamptz IS NULL OR (f.updated_at, f.id) > ($3, $4))
+          AND (f.owner_id = $2 OR f.owner_id IS NULL)
+        ORDER BY f.updated_at ASC, f.id ASC
+        "#,
+    )
+    .bind(since)
+    .bind(user_id)
+    .bind(after_time)
+    .bind(after_id)
+    .fetch(pool)
+    .map(|row| row.map_err(Into::into))
+}
+
 // =============================================================================
 // Directory Listing (Virtual Folders)
 // =============================================================================
 
+/// Recursive totals for everything under a folder
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct FolderStats {
+    pub file_count: i64,
+    /// Nested folders, real or virtual
+    pub folder_count: i64,
+    /// Sum of current version sizes (links and folders contribute nothing)
+    pub total_bytes: i64,
+    pub latest_updated_at: Option<DateTime<Utc>>,
+}
+
+/// Compute recursive stats for `folder` (a DB path ending in `/`, or `/` for
+/// the root) in one query. Virtual folders are counted from the directory
+/// prefixes of live paths.
+pub async fn folder_stats(
+    pool: &DbPool,
+    user_id: Uuid,
+    folder: &str,
+) -> anyhow::Result<FolderStats> {
+    let stats = sqlx::query_as::<_, FolderStats>(
+        r#"
+        WITH live AS (
+            SELECT substr(f.path, char_length($2) + 1) AS rel, f.updated_at, v.size_bytes
+            FROM files f
+            LEFT JOIN versions v ON v.id = f.current_version_id
+            WHERE f.path LIKE $1 ESCAPE '\'
+              AND f.path <> $2
+              AND f.is_deleted = FALSE
+              AND (f.owner_id = $3 OR f.owner_id IS NULL)
+        ),
+        folders AS (
+            SELECT DISTINCT left(live.rel, pos) AS rel
+            FROM live, generate_series(1, char_length(live.rel)) AS pos
+            WHERE substr(live.rel, pos, 1) = '/'
+        )
+        SELECT
+            (SELECT COUNT(*) FROM live WHERE rel NOT LIKE '%/') AS file_count,
+            (SELECT COUNT(*) FROM folders) AS folder_count,
+            (SELECT COALESCE(SUM(size_bytes), 0)::BIGINT FROM live WHERE rel NOT LIKE '%/') AS total_bytes,
+            (SELECT MAX(updated_at) FROM live) AS latest_updated_at
+        "#,
+    )
+    .bind(under_folder_pattern(folder))
+    .bind(folder)
+    .bind(user_id)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(stats)
+}
+
+/// Stream `(path, content hash)` of every live file with content under a
+/// folder, ordered by path byte for byte (so the order doesn't depend on the
+/// database collation). The stream holds a pool connection until dropped.
+pub fn stream_content_hashes<'a>(
+    pool: &'a DbPool,
+    user_id: Uuid,
+    folder: &str,
+) -> impl Stream<Item = anyhow::Result<(String, String)>> + Send + 'a {
+    sqlx::query_as::<_, (String, String)>(
+        r#"
+        SELECT f.path, v.blob_hash
+        FROM files f
+        JOIN versions v ON v.id = f.current_version_id
+        WHERE f.path LIKE $1 ESCAPE '\'
+          AND f.path NOT LIKE '%/'
+          AND f.is_deleted = FALSE
+          AND (f.owner_id = $2 OR f.owner_id IS NULL)
+        ORDER BY f.path COLLATE "C"
+        "#,
+    )
+    .bind(under_folder_pattern(folder))
+    .bind(user_id)
+    .fetch(pool)
+    .map(|row| row.map_err(Into::into))
+}
+
 /// An entry in a directory listing (file or virtual folder)
 #[derive(Debug, Clone)]
 pub struct DirectoryEntry {
@@ -817,8 +1756,51 @@ pub struct DirectoryEntry {
     pub size_bytes: i64,
     /// Last updated timestamp
     pub updated_at: DateTime<Utc>,
-    /// Current version ID (None for folders)
+    /// Current version ID (None for folders and links)
     pub version_id: Option<Uuid>,
+    /// Target file ID if this entry is a link
+    pub link_target_id: Option<Uuid>,
+}
+
+/// Order of a directory listing. `Name` is the default and mixes folders
+/// with files; every order falls back to the name, then the path, so ties
+/// are broken the same way on each request.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DirectorySort {
+    /// Case-insensitive by name
+    #[default]
+    Name,
+    /// Folders, then files, each by name
+    FoldersFirst,
+    /// Largest first
+    Size,
+    /// Most recently updated first
+    Modified,
+}
+
+impl DirectorySort {
+    pub fn apply(self, entries: &mut [DirectoryEntry]) {
+        let by_name = |a: &DirectoryEntry, b: &DirectoryEntry| {
+            a.name
+                .to_lowercase()
+                .cmp(&b.name.to_lowercase())
+                .then_with(|| a.name.cmp(&b.name))
+                .then_with(|| a.path.cmp(&b.path))
+        };
+        match self {
+            DirectorySort::Name => entries.sort_by(by_name),
+            DirectorySort::FoldersFirst => {
+                entries.sort_by(|a, b| b.is_folder.cmp(&a.is_folder).then_with(|| by_name(a, b)))
+            }
+            DirectorySort::Size => {
+                entries.sort_by(|a, b| b.size_bytes.cmp(&a.size_bytes).then_with(|| by_name(a, b)))
+            }
+            DirectorySort::Modified => {
+                entries.sort_by(|a, b| b.updated_at.cmp(&a.updated_at).then_with(|| by_name(a, b)))
+            }
+        }
+    }
 }
 
 /// List directory contents with virtual folder support.
@@ -831,6 +1813,24 @@ pub struct DirectoryEntry {
 pub async fn list_directory(
     pool: &DbPool,
     prefix: &str,
+) -> anyhow::Result<Vec<DirectoryEntry>> {
+    list_directory_scoped(pool, prefix, None).await
+}
+
+/// [`list_directory`] limited to files `user_id` owns (or unowned legacy
+/// files), the same ones [`get_directory_entry`] finds
+pub async fn list_directory_with_owner(
+    pool: &DbPool,
+    user_id: Uuid,
+    prefix: &str,
+) -> anyhow::Result<Vec<DirectoryEntry>> {
+    list_directory_scoped(pool, prefix, Some(user_id)).await
+}
+
+async fn list_directory_scoped(
+    pool: &DbPool,
+    prefix: &str,
+    owner_id: Option<Uuid>,
 ) -> anyhow::Result<Vec<DirectoryEntry>> {
     use std::collections::{HashMap, HashSet};
     
@@ -854,14 +1854,18 @@ pub async fn list_directory(
         r#"
         SELECT f.id, f.path, f.current_version_id, f.is_deleted,
                f.created_at, f.updated_at, v.size_bytes, v.blob_hash,
-               f.original_hash_id
+               f.original_hash_id, f.owner_id, f.link_target_id, f.unix_mode
         FROM files f
-        LEFT JOIN versions v ON f.current_version_id = v.id
+        -- Links have no versions of their own; report the live target's size
+        LEFT JOIN files t ON t.id = f.link_target_id AND t.is_deleted = FALSE
+        LEFT JOIN versions v ON v.id = COALESCE(f.current_version_id, t.current_version_id)
         WHERE f.path LIKE $1 ESCAPE '\' AND f.is_deleted = FALSE
+          AND ($2::uuid IS NULL OR f.owner_id = $2 OR f.owner_id IS NULL)
         ORDER BY f.path
         "#,
     )
     .bind(&prefix_pattern)
+    .bind(owner_id)
     .fetch_all(pool)
     .await?;
     
@@ -914,6 +1918,7 @@ pub async fn list_directory(
                 size_bytes: file.size_bytes.unwrap_or(0),
                 updated_at: file.updated_at,
                 version_id: file.current_version_id,
+                link_target_id: file.link_target_id,
             });
         }
     }
@@ -973,32 +1978,124 @@ pub async fn list_directory(
             size_bytes: 0,
             updated_at,
             version_id: None,
+            link_target_id: None,
         });
     }
     
     // Sort by name (folders and files mixed, alphabetically)
-    entries.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
+    DirectorySort::Name.apply(&mut entries);
     
     Ok(entries)
 }
 
-/// List all files under a path for a user (for zip download)
-/// Returns all files (not folders) recursively under the given path prefix
+/// The entry [`list_directory`] would show for `path` in its parent's
+/// listing, looked up directly: the live file at `path`, otherwise the
+/// folder of live files under it. Costs a few indexed queries rather than a
+/// scan of the parent's subtree.
+pub async fn get_directory_entry(
+    pool: &DbPool,
+    user_id: Uuid,
+    path: &str,
+) -> anyhow::Result<Option<DirectoryEntry>> {
+    let normalized = normalize_path(path);
+    let trimmed = normalized.trim_end_matches('/');
+    if trimmed.is_empty() {
+        return Ok(None);
+    }
+    let name = trimmed.rsplit('/').next().unwrap_or_default().to_string();
+
+    let file = sqlx::query_as::<_, FileWithVersion>(
+        r#"
+        SELECT f.id, f.path, f.current_version_id, f.is_deleted,
+               f.created_at, f.updated_at, v.size_bytes, v.blob_hash,
+               f.original_hash_id, f.owner_id, f.link_target_id, f.unix_mode
+        FROM files f
+        LEFT JOIN files t ON t.id = f.link_target_id AND t.is_deleted = FALSE
+        LEFT JOIN versions v ON v.id = COALESCE(f.current_version_id, t.current_version_id)
+        WHERE f.path = $1 AND f.is_deleted = FALSE
+          AND (f.owner_id = $2 OR f.owner_id IS NULL)
+        "#,
+    )
+    .bind(trimmed)
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?;
+    if let Some(file) = file {
+        return Ok(Some(DirectoryEntry {
+            id: file.id.to_string(),
+            name,
+            path: file.path,
+            is_folder: false,
+            size_bytes: file.size_bytes.unwrap_or(0),
+            updated_at: file.updated_at,
+            version_id: file.current_version_id,
+            link_target_id: file.link_target_id,
+        }));
+    }
+
+    // A folder exists while anything live is under it; it was last updated
+    // when the most recent of those was
+    let folder_path = format!("{}/", trimmed);
+    let (updated_at,): (Option<DateTime<Utc>>,) = sqlx::query_as(
+        r#"
+        SELECT MAX(updated_at) FROM files
+        WHERE path LIKE $1 ESCAPE '\' AND is_deleted = FALSE
+          AND (owner_id = $2 OR owner_id IS NULL)
+        "#,
+    )
+    .bind(under_folder_pattern(&folder_path))
+    .bind(user_id)
+    .fetch_one(pool)
+    .await?;
+    let Some(updated_at) = updated_at else {
+        return Ok(None);
+    };
+
+    // Same ID as the listing gives it: the folder record's, else a hash
+    let record: Option<(Uuid, Option<String>)> = sqlx::query_as(
+        "SELECT id, original_hash_id FROM files WHERE path = $1 AND is_deleted = FALSE",
+    )
+    .bind(&folder_path)
+    .fetch_optional(pool)
+    .await?;
+    let id = match record {
+        Some((id, original_hash)) => original_hash.unwrap_or(id.to_string()),
+        None => blake3::hash(folder_path.as_bytes()).to_hex().to_string(),
+    };
+
+    Ok(Some(DirectoryEntry {
+        id,
+        name,
+        path: folder_path,
+        is_folder: true,
+        size_bytes: 0,
+        updated_at,
+        version_id: None,
+        link_target_id: None,
+    }))
+}
+
+/// List all files under a path for a user (for zip and tar downloads)
+/// Returns every live record, files and directory records, recursively under
+/// the given path prefix
 pub async fn list_files_by_user_under_path(
     pool: &DbPool,
     user_id: Uuid,
     path_prefix: &str,
-) -> anyhow::Result<Vec<File>> {
+) -> anyhow::Result<Vec<FileWithVersion>> {
     let prefix_pattern = format!("{}%", escape_like(path_prefix));
 
-    let files = sqlx::query_as::<_, File>(
+    let files = sqlx::query_as::<_, FileWithVersion>(
         r#"
-        SELECT id, path, current_version_id, is_deleted, created_at, updated_at, owner_id, original_hash_id
-        FROM files
-        WHERE path LIKE $1 ESCAPE '\'
-          AND is_deleted = FALSE
-          AND (owner_id = $2 OR owner_id IS NULL)
-        ORDER BY path
+        SELECT f.id, f.path, f.current_version_id, f.is_deleted,
+               f.created_at, f.updated_at, v.size_bytes, v.blob_hash,
+               f.original_hash_id, f.owner_id, f.link_target_id, f.unix_mode
+        FROM files f
+        LEFT JOIN versions v ON f.current_version_id = v.id
+        WHERE f.path LIKE $1 ESCAPE '\'
+          AND f.is_deleted = FALSE
+          AND (f.owner_id = $2 OR f.owner_id IS NULL)
+        ORDER BY f.path
         "#,
     )
     .bind(&prefix_pattern)
@@ -1,7 +1,9 @@
+pub mod audit;
 pub mod chunks;
 pub mod containers;
 pub mod files;
 pub mod models;
+pub mod tags;
 pub mod users;
 pub mod versions;
 
@@ -14,7 +16,7 @@ pub type DbPool = Pool<Postgres>;
 pub use models::{
     BlobContainer, Chunk, ChunkLocation, ChunkTier, File, FileVersion,
     FileWithVersion, NewBlobContainer, NewChunk, NewFileVersion, NewVersionChunk,
-    TierConfig, VersionChunk,
+    StorageBackend, TierConfig, VersionChunk,
 };
 
 /// Create a database connection pool with production-ready timeouts
@@ -38,12 +40,53 @@ pub async fn run_migrations(pool: &DbPool) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// A fresh, migrated database for a test, on the server `DATABASE_URL`
+/// points at. None when `DATABASE_URL` isn't set, so tests that need
+/// Postgres pass trivially without one (CI always sets it).
+#[cfg(test)]
+pub(crate) async fn test_pool() -> Option<DbPool> {
+    use sqlx::postgres::PgConnectOptions;
+
+    let url = std::env::var("DATABASE_URL").ok()?;
+    let admin = create_pool(&url).await.expect("connect to DATABASE_URL");
+    let name = format!("entanglement_test_{}", uuid::Uuid::new_v4().simple());
+    sqlx::query(&format!("CREATE DATABASE {}", name))
+        .execute(&admin)
+        .await
+        .expect("create test database");
+
+    let options: PgConnectOptions = url.parse().expect("parse DATABASE_URL");
+    let pool = PgPoolOptions::new()
+        .max_connections(5)
+        .connect_with(options.database(&name))
+        .await
+        .expect("connect to test database");
+    run_migrations(&pool).await.expect("migrate test database");
+    Some(pool)
+}
+
 /// Server statistics
 pub struct Stats {
     pub total_users: i64,
     pub total_files: i64,
     pub total_versions: i64,
     pub total_blob_bytes: i64,
+    /// Sum of all version sizes (what users have uploaded)
+    pub logical_bytes: i64,
+    /// Bytes actually stored: distinct chunks plus distinct legacy blobs
+    pub physical_bytes: i64,
+    pub container_count: i64,
+    pub avg_container_bytes: i64,
+}
+
+impl Stats {
+    /// Logical bytes per physical byte stored (e.g., 2.0 = 50% savings)
+    pub fn dedup_ratio(&self) -> f64 {
+        if self.physical_bytes == 0 {
+            return 1.0;
+        }
+        self.logical_bytes as f64 / self.physical_bytes as f64
+    }
 }
 
 /// Get server statistics
@@ -66,11 +109,40 @@ pub async fn get_stats(pool: &DbPool) -> anyhow::Result<Stats> {
             .fetch_one(pool)
             .await?;
 
+    // Stored chunk bytes (on-disk length when containerized) plus whole-file
+    // blobs of non-chunked versions, each counted once per distinct hash
+    let physical_bytes: (Option<i64>,) = sqlx::query_as(
+        r#"
+        SELECT CAST(
+            (SELECT COALESCE(SUM(COALESCE(length_bytes, size_bytes)), 0) FROM chunks)
+            + (SELECT COALESCE(SUM(size_bytes), 0) FROM (
+                SELECT DISTINCT ON (blob_hash) blob_hash, size_bytes
+                FROM versions
+                WHERE is_chunked = FALSE
+            ) legacy)
+        AS BIGINT)
+        "#,
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let containers: (i64, Option<i64>) = sqlx::query_as(
+        "SELECT COUNT(*), CAST(AVG(total_size) AS BIGINT) FROM blob_containers",
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let total_blob_bytes = total_blob_bytes.0.unwrap_or(0);
+
     Ok(Stats {
         total_users: total_users.0,
         total_files: total_files.0,
         total_versions: total_versions.0,
-        total_blob_bytes: total_blob_bytes.0.unwrap_or(0),
+        total_blob_bytes,
+        logical_bytes: total_blob_bytes,
+        physical_bytes: physical_bytes.0.unwrap_or(0),
+        container_count: containers.0,
+        avg_container_bytes: containers.1.unwrap_or(0),
     })
 }
 
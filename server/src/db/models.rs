@@ -15,7 +15,7 @@ use uuid::Uuid;
 
 /// Dynamic Chunking Tier
 /// Matches the `tier_id` column in the database.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, sqlx::Type)]
 #[repr(i16)]
 pub enum ChunkTier {
     /// Tier 0: Inline (< 4KB) - No chunking, whole file
@@ -92,12 +92,47 @@ pub struct BlobContainer {
     pub created_at: DateTime<Utc>,
     /// When the container was sealed (NULL if still open)
     pub sealed_at: Option<DateTime<Utc>>,
+    /// Whether chunks in this container are sealed with the storage encryption key
+    pub encrypted: bool,
 }
 
 /// Input for creating a new blob container
 #[derive(Debug, Clone)]
 pub struct NewBlobContainer {
     pub disk_path: String,
+    pub encrypted: bool,
+}
+
+// =============================================================================
+// Storage Backend
+// =============================================================================
+
+/// Where chunk data lives. Matches the `backend` column of `chunks`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "storage_backend", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// Containers and standalone blob files under BLOB_STORAGE_PATH
+    Local,
+    /// Objects in an S3-compatible bucket
+    S3,
+}
+
+impl StorageBackend {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            StorageBackend::Local => "local",
+            StorageBackend::S3 => "s3",
+        }
+    }
+
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "local" => Ok(StorageBackend::Local),
+            "s3" => Ok(StorageBackend::S3),
+            _ => Err(anyhow::anyhow!("Unknown storage backend {:?} (expected local or s3)", s)),
+        }
+    }
 }
 
 // =============================================================================
@@ -105,7 +140,8 @@ pub struct NewBlobContainer {
 // =============================================================================
 
 /// A content-addressed chunk identified by its BLAKE3 hash.
-/// Chunks are stored either in a blob container or as standalone files.
+/// Chunks are stored in a blob container, as standalone files, or as objects
+/// in a remote backend.
 #[derive(Debug, Clone, sqlx::FromRow, Serialize)]
 pub struct Chunk {
     /// BLAKE3 hash of the chunk content (64-char hex, primary key)
@@ -120,6 +156,8 @@ pub struct Chunk {
     pub offset_bytes: Option<i64>,
     /// Length of data in container (should equal size_bytes)
     pub length_bytes: Option<i32>,
+    /// Backend holding the data; container fields only apply to `Local`
+    pub backend: StorageBackend,
     pub created_at: DateTime<Utc>,
 }
 
@@ -131,6 +169,12 @@ impl Chunk {
     
     /// Get the storage location info
     pub fn location(&self) -> ChunkLocation {
+        if self.backend != StorageBackend::Local {
+            return ChunkLocation::Object {
+                backend: self.backend,
+                hash: self.hash.clone(),
+            };
+        }
         match (self.container_id, self.offset_bytes, self.length_bytes) {
             (Some(container_id), Some(offset), Some(length)) => {
                 ChunkLocation::Container {
@@ -155,6 +199,8 @@ pub enum ChunkLocation {
         offset: i64,
         length: i32,
     },
+    /// Stored as an object in a remote backend
+    Object { backend: StorageBackend, hash: String },
 }
 
 /// Input for creating a new chunk
@@ -168,6 +214,7 @@ pub struct NewChunk {
     pub container_id: Option<Uuid>,
     pub offset_bytes: Option<i64>,
     pub length_bytes: Option<i32>,
+    pub backend: StorageBackend,
 }
 
 // =============================================================================
@@ -0,0 +1,43 @@
+//! Database operations for file tags
+//!
+//! Tags are free-form `key=value` pairs attached to a file id, so they stay
+//! with the file when it is moved or renamed. A file has at most one value
+//! per key.
+
+use super::DbPool;
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+/// Tags of a file, ordered by key
+pub async fn get_tags(pool: &DbPool, file_id: Uuid) -> anyhow::Result<BTreeMap<String, String>> {
+    let rows: Vec<(String, String)> =
+        sqlx::query_as("SELECT key, value FROM file_tags WHERE file_id = $1 ORDER BY key")
+            .bind(file_id)
+            .fetch_all(pool)
+            .await?;
+    Ok(rows.into_iter().collect())
+}
+
+/// Replace every tag of a file with `tags`
+pub async fn set_tags(pool: &DbPool, file_id: Uuid, tags: &BTreeMap<String, String>) -> anyhow::Result<()> {
+    let (keys, values): (Vec<&str>, Vec<&str>) = tags.iter().map(|(k, v)| (k.as_str(), v.as_str())).unzip();
+
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM file_tags WHERE file_id = $1")
+        .bind(file_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query(
+        r#"
+        INSERT INTO file_tags (file_id, key, value)
+        SELECT $1, t.key, t.value FROM UNNEST($2::text[], $3::text[]) AS t(key, value)
+        "#,
+    )
+    .bind(file_id)
+    .bind(&keys)
+    .bind(&values)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
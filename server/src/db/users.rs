@@ -1,7 +1,35 @@
 use super::DbPool;
 use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+/// What a user may do. `is_admin` mirrors `Admin` for older clients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "user_role", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum UserRole {
+    Admin,
+    /// Read-write access to files (the default)
+    Editor,
+    /// Read-only: cannot upload, delete, move or share
+    Viewer,
+}
+
+impl UserRole {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            UserRole::Admin => "admin",
+            UserRole::Editor => "editor",
+            UserRole::Viewer => "viewer",
+        }
+    }
+
+    /// Whether the role may change files
+    pub fn can_write(self) -> bool {
+        self != UserRole::Viewer
+    }
+}
+
 #[allow(dead_code)]
 #[derive(Debug, Clone, sqlx::FromRow)]
 pub struct User {
@@ -9,21 +37,26 @@ pub struct User {
     pub username: String,
     pub password_hash: String,
     pub is_admin: bool,
+    pub role: UserRole,
+    /// Refused on every authenticated request while set
+    pub disabled: bool,
+    /// Embedded in issued tokens; tokens with an older version are rejected
+    pub token_version: i32,
     pub created_at: DateTime<Utc>,
 }
 
 /// Create a new user
-pub async fn create_user(pool: &DbPool, username: &str, password_hash: &str, is_admin: bool) -> anyhow::Result<User> {
+pub async fn create_user(pool: &DbPool, username: &str, password_hash: &str, role: UserRole) -> anyhow::Result<User> {
     let user = sqlx::query_as::<_, User>(
         r#"
-        INSERT INTO users (username, email, password_hash, is_admin)
-        VALUES ($1, $1 || '@localhost', $2, $3)
-        RETURNING id, username, password_hash, is_admin, created_at
+        INSERT INTO users (username, email, password_hash, is_admin, role)
+        VALUES ($1, $1 || '@localhost', $2, ($3 = 'admin'), $3)
+        RETURNING id, username, password_hash, is_admin, role, disabled, token_version, created_at
         "#,
     )
     .bind(username)
     .bind(password_hash)
-    .bind(is_admin)
+    .bind(role)
     .fetch_one(pool)
     .await?;
 
@@ -34,7 +67,7 @@ pub async fn create_user(pool: &DbPool, username: &str, password_hash: &str, is_
 pub async fn get_user_by_username(pool: &DbPool, username: &str) -> anyhow::Result<Option<User>> {
     let user = sqlx::query_as::<_, User>(
         r#"
-        SELECT id, username, password_hash, is_admin, created_at
+        SELECT id, username, password_hash, is_admin, role, disabled, token_version, created_at
         FROM users
         WHERE username = $1
         "#,
@@ -51,7 +84,7 @@ pub async fn get_user_by_username(pool: &DbPool, username: &str) -> anyhow::Resu
 pub async fn get_user_by_id(pool: &DbPool, id: Uuid) -> anyhow::Result<Option<User>> {
     let user = sqlx::query_as::<_, User>(
         r#"
-        SELECT id, username, password_hash, is_admin, created_at
+        SELECT id, username, password_hash, is_admin, role, disabled, token_version, created_at
         FROM users
         WHERE id = $1
         "#,
@@ -67,7 +100,7 @@ pub async fn get_user_by_id(pool: &DbPool, id: Uuid) -> anyhow::Result<Option<Us
 pub async fn list_users(pool: &DbPool) -> anyhow::Result<Vec<User>> {
     let users = sqlx::query_as::<_, User>(
         r#"
-        SELECT id, username, password_hash, is_admin, created_at
+        SELECT id, username, password_hash, is_admin, role, disabled, token_version, created_at
         FROM users
         ORDER BY created_at DESC
         "#,
@@ -107,11 +140,16 @@ pub async fn delete_user(pool: &DbPool, user_id: Uuid) -> anyhow::Result<bool> {
     Ok(result.rows_affected() > 0)
 }
 
-/// Set admin status for a user
+/// Set admin status for a user. Demoted admins become editors.
 pub async fn set_admin(pool: &DbPool, user_id: Uuid, is_admin: bool) -> anyhow::Result<bool> {
     let result = sqlx::query(
         r#"
-        UPDATE users SET is_admin = $2 WHERE id = $1
+        UPDATE users
+        SET is_admin = $2,
+            role = CASE WHEN $2 THEN 'admin'
+                        WHEN role = 'admin' THEN 'editor'
+                        ELSE role END
+        WHERE id = $1
         "#,
     )
     .bind(user_id)
@@ -121,3 +159,147 @@ pub async fn set_admin(pool: &DbPool, user_id: Uuid, is_admin: bool) -> anyhow::
 
     Ok(result.rows_affected() > 0)
 }
+
+/// Set a user's role (and `is_admin` to match)
+pub async fn set_role(pool: &DbPool, user_id: Uuid, role: UserRole) -> anyhow::Result<bool> {
+    let result = sqlx::query(
+        r#"
+        UPDATE users SET role = $2, is_admin = ($2 = 'admin') WHERE id = $1
+        "#,
+    )
+    .bind(user_id)
+    .bind(role)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// What a request's token is checked against on every authenticated call
+#[derive(Debug, Clone, Copy, sqlx::FromRow)]
+pub struct UserAccess {
+    pub role: UserRole,
+    pub disabled: bool,
+    pub token_version: i32,
+}
+
+/// A user's role, disabled flag and token version, or None if the user
+/// doesn't exist
+pub async fn get_access(pool: &DbPool, user_id: Uuid) -> anyhow::Result<Option<UserAccess>> {
+    let access = sqlx::query_as::<_, UserAccess>(
+        "SELECT role, disabled, token_version FROM users WHERE id = $1",
+    )
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(access)
+}
+
+/// Disable or re-enable a user. Disabling also bumps the token version, so
+/// tokens issued before stay revoked after the account is enabled again.
+pub async fn set_disabled(pool: &DbPool, user_id: Uuid, disabled: bool) -> anyhow::Result<bool> {
+    let result = sqlx::query(
+        r#"
+        UPDATE users
+        SET disabled = $2,
+            token_version = token_version + CASE WHEN $2 THEN 1 ELSE 0 END
+        WHERE id = $1
+        "#,
+    )
+    .bind(user_id)
+    .bind(disabled)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// A user's TOTP enrollment; `secret` is still encrypted
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct TotpEnrollment {
+    pub secret: String,
+    /// False until the first code has been verified
+    pub enabled: bool,
+}
+
+/// Get a user's TOTP enrollment, if one has been started
+pub async fn get_totp(pool: &DbPool, user_id: Uuid) -> anyhow::Result<Option<TotpEnrollment>> {
+    let enrollment = sqlx::query_as::<_, TotpEnrollment>(
+        r#"
+        SELECT totp_secret AS secret, totp_enabled AS enabled
+        FROM users
+        WHERE id = $1 AND totp_secret IS NOT NULL
+        "#,
+    )
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(enrollment)
+}
+
+/// Store a pending (not yet enabled) TOTP secret, replacing any earlier
+/// pending one. Returns false if TOTP is already enabled.
+pub async fn set_pending_totp(pool: &DbPool, user_id: Uuid, encrypted_secret: &str) -> anyhow::Result<bool> {
+    let result = sqlx::query(
+        r#"
+        UPDATE users SET totp_secret = $2, totp_enabled = FALSE, totp_last_step = NULL
+        WHERE id = $1 AND totp_enabled = FALSE
+        "#,
+    )
+    .bind(user_id)
+    .bind(encrypted_secret)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Enable a pending TOTP enrollment, consuming the verified time step
+pub async fn enable_totp(pool: &DbPool, user_id: Uuid, step: i64) -> anyhow::Result<bool> {
+    let result = sqlx::query(
+        r#"
+        UPDATE users SET totp_enabled = TRUE, totp_last_step = $2
+        WHERE id = $1 AND totp_secret IS NOT NULL AND totp_enabled = FALSE
+        "#,
+    )
+    .bind(user_id)
+    .bind(step)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Record a verified TOTP time step. Returns false if that step (or a later
+/// one) was already used, i.e. the code is a replay.
+pub async fn consume_totp_step(pool: &DbPool, user_id: Uuid, step: i64) -> anyhow::Result<bool> {
+    let result = sqlx::query(
+        r#"
+        UPDATE users SET totp_last_step = $2
+        WHERE id = $1 AND (totp_last_step IS NULL OR totp_last_step < $2)
+        "#,
+    )
+    .bind(user_id)
+    .bind(step)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Remove a user's TOTP enrollment (admin recovery for a lost device)
+pub async fn clear_totp(pool: &DbPool, user_id: Uuid) -> anyhow::Result<bool> {
+    let result = sqlx::query(
+        r#"
+        UPDATE users SET totp_secret = NULL, totp_enabled = FALSE, totp_last_step = NULL
+        WHERE id = $1
+        "#,
+    )
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
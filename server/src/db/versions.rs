@@ -2,8 +2,11 @@
 
 #![allow(dead_code)]
 
+use super::models::StorageBackend;
 use super::DbPool;
+use crate::storage::chunking::merkle_root;
 use chrono::{DateTime, Utc};
+use sqlx::{PgConnection, PgExecutor};
 use uuid::Uuid;
 
 /// Legacy Version struct (for backwards compatibility)
@@ -15,6 +18,21 @@ pub struct Version {
     pub size_bytes: i64,
     pub created_at: DateTime<Utc>,
     pub created_by: Option<Uuid>,
+    /// Opaque key data for client-side encrypted content; see [`set_encryption_meta`]
+    pub encryption_meta: Option<Vec<u8>>,
+    /// Uploader's note on this version; see [`set_note`]
+    pub note: Option<String>,
+}
+
+/// A version as listed in a file's history
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct VersionHistoryEntry {
+    #[sqlx(flatten)]
+    pub version: Version,
+    /// Whether this is the file's current version
+    pub is_current: bool,
+    /// Username of `created_by` (None if unset or the user is gone)
+    pub created_by_username: Option<String>,
 }
 
 /// Extended Version struct with tier and BLAKE3 support
@@ -48,9 +66,9 @@ pub async fn create_version(
 ) -> anyhow::Result<Version> {
     let version = sqlx::query_as::<_, Version>(
         r#"
-        INSERT INTO versions (file_id, blob_hash, size_bytes, created_by)
-        VALUES ($1, $2, $3, $4)
-        RETURNING id, file_id, blob_hash, size_bytes, created_at, created_by
+        INSERT INTO versions (file_id, blob_hash, size_bytes, created_by, tree_hash)
+        VALUES ($1, $2, $3, $4, $2)
+        RETURNING id, file_id, blob_hash, size_bytes, created_at, created_by, encryption_meta, note
         "#,
     )
     .bind(file_id)
@@ -72,9 +90,9 @@ pub async fn create_version_global(
 ) -> anyhow::Result<Version> {
     let version = sqlx::query_as::<_, Version>(
         r#"
-        INSERT INTO versions (file_id, blob_hash, size_bytes, created_by)
-        VALUES ($1, $2, $3, NULL)
-        RETURNING id, file_id, blob_hash, size_bytes, created_at, created_by
+        INSERT INTO versions (file_id, blob_hash, size_bytes, created_by, tree_hash)
+        VALUES ($1, $2, $3, NULL, $2)
+        RETURNING id, file_id, blob_hash, size_bytes, created_at, created_by, encryption_meta, note
         "#,
     )
     .bind(file_id)
@@ -90,7 +108,7 @@ pub async fn create_version_global(
 pub async fn get_version(pool: &DbPool, version_id: Uuid) -> anyhow::Result<Option<Version>> {
     let version = sqlx::query_as::<_, Version>(
         r#"
-        SELECT id, file_id, blob_hash, size_bytes, created_at, created_by
+        SELECT id, file_id, blob_hash, size_bytes, created_at, created_by, encryption_meta, note
         FROM versions
         WHERE id = $1
         "#,
@@ -102,19 +120,80 @@ pub async fn get_version(pool: &DbPool, version_id: Uuid) -> anyhow::Result<Opti
     Ok(version)
 }
 
-/// List versions for a file (newest first)
+/// Attach a client's encryption metadata to a version. The server stores it
+/// unread: chunks of such a version are ciphertext, hashed as uploaded, and
+/// are served back unchanged for the client to decrypt.
+pub async fn set_encryption_meta(
+    executor: impl PgExecutor<'_>,
+    version_id: Uuid,
+    encryption_meta: Option<&[u8]>,
+) -> anyhow::Result<()> {
+    sqlx::query("UPDATE versions SET encryption_meta = $2 WHERE id = $1")
+        .bind(version_id)
+        .bind(encryption_meta)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+/// Attach the uploader's note to a version
+pub async fn set_note(
+    executor: impl PgExecutor<'_>,
+    version_id: Uuid,
+    note: Option<&str>,
+) -> anyhow::Result<()> {
+    sqlx::query("UPDATE versions SET note = $2 WHERE id = $1")
+        .bind(version_id)
+        .bind(note)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+/// Find versions whose ID starts with `prefix` among the user's files
+///
+/// Returns at most two IDs, which is enough to tell unique from ambiguous.
+/// `prefix` must already be validated as lowercase hex (and hyphens).
+pub async fn find_versions_by_prefix(
+    pool: &DbPool,
+    prefix: &str,
+    user_id: Uuid,
+) -> anyhow::Result<Vec<Uuid>> {
+    let ids: Vec<(Uuid,)> = sqlx::query_as(
+        r#"
+        SELECT v.id
+        FROM versions v
+        JOIN files f ON f.id = v.file_id
+        WHERE v.id::text LIKE $1 || '%' AND (f.owner_id = $2 OR f.owner_id IS NULL)
+        LIMIT 2
+        "#,
+    )
+    .bind(prefix)
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(ids.into_iter().map(|(id,)| id).collect())
+}
+
+/// List versions for a file (newest first), flagging the current one and
+/// resolving who created each
 pub async fn list_versions(
     pool: &DbPool,
     file_id: Uuid,
     limit: i64,
     offset: i64,
-) -> anyhow::Result<(Vec<Version>, i64)> {
-    let versions = sqlx::query_as::<_, Version>(
+) -> anyhow::Result<(Vec<VersionHistoryEntry>, i64)> {
+    let versions = sqlx::query_as::<_, VersionHistoryEntry>(
         r#"
-        SELECT id, file_id, blob_hash, size_bytes, created_at, created_by
-        FROM versions
-        WHERE file_id = $1
-        ORDER BY created_at DESC
+        SELECT v.id, v.file_id, v.blob_hash, v.size_bytes, v.created_at, v.created_by, v.encryption_meta, v.note,
+               COALESCE(v.id = f.current_version_id, FALSE) AS is_current,
+               u.username AS created_by_username
+        FROM versions v
+        JOIN files f ON f.id = v.file_id
+        LEFT JOIN users u ON u.id = v.created_by
+        WHERE v.file_id = $1
+        ORDER BY v.created_at DESC
         LIMIT $2 OFFSET $3
         "#,
     )
@@ -143,7 +222,7 @@ pub async fn list_versions(
 pub async fn get_latest_version(pool: &DbPool, file_id: Uuid) -> anyhow::Result<Option<Version>> {
     let version = sqlx::query_as::<_, Version>(
         r#"
-        SELECT id, file_id, blob_hash, size_bytes, created_at, created_by
+        SELECT id, file_id, blob_hash, size_bytes, created_at, created_by, encryption_meta, note
         FROM versions
         WHERE file_id = $1
         ORDER BY created_at DESC
@@ -175,8 +254,9 @@ pub async fn create_version_with_tier(
 ) -> anyhow::Result<VersionExt> {
     let version = sqlx::query_as::<_, VersionExt>(
         r#"
-        INSERT INTO versions (file_id, blob_hash, blake3_hash, size_bytes, tier_id, is_chunked, created_by)
-        VALUES ($1, $2, $2, $3, $4, $5, $6)
+        INSERT INTO versions (file_id, blob_hash, blake3_hash, size_bytes, tier_id, is_chunked, created_by, tree_hash)
+        -- Chunked versions get their tree hash once chunks are attached (see `tree_hash`)
+        VALUES ($1, $2, $2, $3, $4, $5, $6, CASE WHEN $5 THEN NULL ELSE $2 END)
         RETURNING id, file_id, blob_hash, blake3_hash, size_bytes, tier_id, is_chunked, created_at, created_by
         "#,
     )
@@ -192,6 +272,38 @@ pub async fn create_version_with_tier(
     Ok(version)
 }
 
+/// Merkle root of a version's chunk hashes (see [`merkle_root`]), or the blob
+/// hash for unchunked versions. Versions created before the column existed
+/// are computed on first request and stored.
+pub async fn tree_hash(pool: &DbPool, version_id: Uuid) -> anyhow::Result<Option<String>> {
+    let row: Option<(Option<String>, String)> =
+        sqlx::query_as("SELECT tree_hash, blob_hash FROM versions WHERE id = $1")
+            .bind(version_id)
+            .fetch_optional(pool)
+            .await?;
+    let blob_hash = match row {
+        None => return Ok(None),
+        Some((Some(tree_hash), _)) => return Ok(Some(tree_hash)),
+        Some((None, blob_hash)) => blob_hash,
+    };
+
+    let chunk_hashes: Vec<(String,)> = sqlx::query_as(
+        "SELECT chunk_hash FROM version_chunks WHERE version_id = $1 ORDER BY chunk_index",
+    )
+    .bind(version_id)
+    .fetch_all(pool)
+    .await?;
+    let hashes: Vec<&str> = chunk_hashes.iter().map(|(h,)| h.as_str()).collect();
+    let tree_hash = merkle_root(&hashes).unwrap_or(blob_hash);
+
+    sqlx::query("UPDATE versions SET tree_hash = $1 WHERE id = $2 AND tree_hash IS NULL")
+        .bind(&tree_hash)
+        .bind(version_id)
+        .execute(pool)
+        .await?;
+    Ok(Some(tree_hash))
+}
+
 /// Get a version by ID with extended info
 pub async fn get_version_ext(pool: &DbPool, version_id: Uuid) -> anyhow::Result<Option<VersionExt>> {
     let version = sqlx::query_as::<_, VersionExt>(
@@ -250,3 +362,439 @@ pub async fn get_latest_version_ext(
     Ok(version)
 }
 
+
+// =============================================================================
+// Orphaned versions
+// =============================================================================
+
+/// A version whose chunk manifest references chunks that can't be read
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct OrphanedVersion {
+    pub id: Uuid,
+    pub file_id: Uuid,
+    pub path: String,
+    pub created_at: DateTime<Utc>,
+    /// Whether this is the file's current version
+    pub is_current: bool,
+    pub missing_chunks: i64,
+    pub total_chunks: i64,
+}
+
+/// Find versions referencing a chunk with no record, a zero-size placeholder
+/// record, or data in one of `missing_containers` / `missing_blobs` (checked
+/// on disk by the caller)
+pub async fn find_orphaned_versions(
+    pool: &DbPool,
+    missing_containers: &[Uuid],
+    missing_blobs: &[String],
+) -> anyhow::Result<Vec<OrphanedVersion>> {
+    let orphans = sqlx::query_as::<_, OrphanedVersion>(
+        r#"
+        SELECT v.id, v.file_id, f.path, v.created_at,
+               COALESCE(f.current_version_id = v.id, FALSE) AS is_current,
+               COUNT(*) FILTER (
+                   WHERE c.hash IS NULL
+                      OR c.size_bytes <= 0
+                      OR c.container_id = ANY($1)
+                      OR vc.chunk_hash = ANY($2)
+               ) AS missing_chunks,
+               COUNT(*) AS total_chunks
+        FROM versions v
+        JOIN files f ON f.id = v.file_id
+        JOIN version_chunks vc ON vc.version_id = v.id
+        LEFT JOIN chunks c ON c.hash = vc.chunk_hash
+        GROUP BY v.id, f.id
+        HAVING COUNT(*) FILTER (
+            WHERE c.hash IS NULL
+               OR c.size_bytes <= 0
+               OR c.container_id = ANY($1)
+               OR vc.chunk_hash = ANY($2)
+        ) > 0
+        ORDER BY f.path, v.created_at
+        "#,
+    )
+    .bind(missing_containers)
+    .bind(missing_blobs)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(orphans)
+}
+
+// =============================================================================
+// Legacy whole-file versions
+// =============================================================================
+
+/// A non-empty version stored as one whole-file blob rather than chunks
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct LegacyVersion {
+    pub id: Uuid,
+    pub path: String,
+    pub blob_hash: String,
+    pub size_bytes: i64,
+}
+
+/// List whole-file versions at or under `prefix` (every path when None),
+/// deleted files included
+pub async fn list_legacy_versions(pool: &DbPool, prefix: Option<&str>) -> anyhow::Result<Vec<LegacyVersion>> {
+    let prefix = prefix.map(|p| super::files::normalize_path(p).trim_end_matches('/').to_string());
+    let children = prefix.as_ref().map(|p| format!("{}/%", super::files::escape_like(p)));
+    let versions = sqlx::query_as::<_, LegacyVersion>(
+        r#"
+        SELECT v.id, f.path, v.blob_hash, v.size_bytes
+        FROM versions v
+        JOIN files f ON f.id = v.file_id
+        WHERE NOT v.is_chunked
+          AND v.size_bytes > 0
+          AND NOT EXISTS (SELECT 1 FROM version_chunks vc WHERE vc.version_id = v.id)
+          AND ($1::text IS NULL OR $1 = '' OR RTRIM(f.path, '/') = $1 OR f.path LIKE $2 ESCAPE '\')
+        ORDER BY f.path, v.created_at
+        "#,
+    )
+    .bind(prefix)
+    .bind(children)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(versions)
+}
+
+/// What deleting a version did to its file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VersionRemoval {
+    /// The version wasn't the file's current one
+    NotCurrent,
+    /// The newest remaining version became current
+    Promoted(Uuid),
+    /// No usable version was left, so the file was deleted
+    FileDeleted,
+}
+
+/// Delete a version, releasing its chunk references. If it was the file's
+/// current version the newest other version not listed in `unusable` takes
+/// its place; with none left the file is soft-deleted. The caller deletes
+/// the data of the returned chunks (see [`release_versions`]).
+pub async fn delete_version(
+    pool: &DbPool,
+    version_id: Uuid,
+    unusable: &[Uuid],
+) -> anyhow::Result<(VersionRemoval, Vec<ReleasedChunk>)> {
+    let mut tx = pool.begin().await?;
+
+    let (file_id, is_current): (Uuid, bool) = sqlx::query_as(
+        r#"
+        SELECT f.id, COALESCE(f.current_version_id = v.id, FALSE)
+        FROM versions v
+        JOIN files f ON f.id = v.file_id
+        WHERE v.id = $1
+        FOR UPDATE OF f
+        "#,
+    )
+    .bind(version_id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let removal = if is_current {
+        let previous: Option<(Uuid,)> = sqlx::query_as(
+            r#"
+            SELECT id FROM versions
+            WHERE file_id = $1 AND id <> $2 AND id <> ALL($3)
+            ORDER BY created_at DESC
+            LIMIT 1
+            "#,
+        )
+        .bind(file_id)
+        .bind(version_id)
+        .bind(unusable)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let previous = previous.map(|(id,)| id);
+
+        sqlx::query(
+            r#"
+            UPDATE files
+            SET current_version_id = $2,
+                is_deleted = is_deleted OR $2 IS NULL,
+                updated_at = NOW()
+            WHERE id = $1
+            "#,
+        )
+        .bind(file_id)
+        .bind(previous)
+        .execute(&mut *tx)
+        .await?;
+        previous.map_or(VersionRemoval::FileDeleted, VersionRemoval::Promoted)
+    } else {
+        VersionRemoval::NotCurrent
+    };
+
+    let released = release_versions(&mut tx, &[version_id]).await?;
+
+    tx.commit().await?;
+    Ok((removal, released))
+}
+
+/// A standalone chunk (file or object) whose record [`release_versions`]
+/// deleted. Its data is removed with
+/// [`BlobManager::delete_released`](crate::storage::BlobManager::delete_released)
+/// once the transaction has committed, unless an upload recorded the same
+/// chunk again in the meantime.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct ReleasedChunk {
+    pub hash: String,
+    pub backend: StorageBackend,
+}
+
+/// Delete versions and release their chunk references. Chunks nothing else
+/// uses are dropped, so a fresh upload of the same content is stored again
+/// and compaction reclaims their container space. Returns the dropped
+/// chunks stored outside containers, leaving out local files a whole-file
+/// version still reads as its blob.
+async fn release_versions(conn: &mut PgConnection, version_ids: &[Uuid]) -> anyhow::Result<Vec<ReleasedChunk>> {
+    let released: Vec<(String,)> = sqlx::query_as(
+        r#"
+        UPDATE chunks c
+        SET ref_count = c.ref_count - m.refs
+        FROM (
+            SELECT chunk_hash, COUNT(*)::int AS refs
+            FROM version_chunks
+            WHERE version_id = ANY($1)
+            GROUP BY chunk_hash
+        ) m
+        WHERE c.hash = m.chunk_hash
+        RETURNING c.hash
+        "#,
+    )
+    .bind(version_ids)
+    .fetch_all(&mut *conn)
+    .await?;
+    let released: Vec<String> = released.into_iter().map(|(h,)| h).collect();
+
+    sqlx::query("DELETE FROM versions WHERE id = ANY($1)")
+        .bind(version_ids)
+        .execute(&mut *conn)
+        .await?;
+    let dropped = sqlx::query_as::<_, ReleasedChunk>(
+        r#"
+        WITH dropped AS (
+            DELETE FROM chunks
+            WHERE hash = ANY($1) AND ref_count <= 0
+            RETURNING hash, backend, container_id
+        )
+        SELECT d.hash, d.backend
+        FROM dropped d
+        WHERE d.container_id IS NULL
+          AND NOT (
+              d.backend = $2
+              AND EXISTS (SELECT 1 FROM versions v WHERE v.blob_hash = d.hash AND NOT v.is_chunked)
+          )
+        "#,
+    )
+    .bind(&released)
+    .bind(StorageBackend::Local)
+    .fetch_all(&mut *conn)
+    .await?;
+    Ok(dropped)
+}
+
+/// How much history to keep per file. A version is pruned only when it is
+/// outside every limit that is set; with no limit set, history is kept forever.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VersionRetention {
+    /// Keep this many of the newest versions
+    pub keep_last: Option<u32>,
+    /// Keep versions created within this many days
+    pub keep_days: Option<u32>,
+}
+
+impl VersionRetention {
+    pub fn is_unlimited(&self) -> bool {
+        self.keep_last.is_none() && self.keep_days.is_none()
+    }
+
+    /// Reject a policy that would keep no versions at all
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.keep_last == Some(0) {
+            anyhow::bail!("VERSION_KEEP_LAST must be at least 1 (leave it unset to keep every version)");
+        }
+        Ok(())
+    }
+}
+
+/// A version as [`expired_versions`] weighs it
+#[derive(Debug, Clone, sqlx::FromRow)]
+struct RetainedVersion {
+    id: Uuid,
+    blob_hash: String,
+    created_at: DateTime<Utc>,
+    is_chunked: bool,
+}
+
+/// IDs of a file's `versions` that fall outside `retention` at `now`: those
+/// outside the newest `keep_last` and older than `keep_days`, except the
+/// `current` version and any version a later restore was made from.
+fn expired_versions(
+    versions: &[RetainedVersion],
+    current: Option<Uuid>,
+    retention: VersionRetention,
+    now: DateTime<Utc>,
+) -> Vec<Uuid> {
+    if retention.is_unlimited() {
+        return Vec::new();
+    }
+    let mut newest_first: Vec<&RetainedVersion> = versions.iter().collect();
+    newest_first.sort_by_key(|v| std::cmp::Reverse((v.created_at, v.id)));
+
+    newest_first
+        .iter()
+        .enumerate()
+        .filter(|(rank, v)| {
+            let kept_by_count = retention.keep_last.is_some_and(|n| *rank < n as usize);
+            let kept_by_age = retention
+                .keep_days
+                .is_some_and(|days| v.created_at >= now - chrono::Duration::days(days.into()));
+            // A restored version refers to its source's content by hash
+            // rather than holding chunks of its own
+            let restored_from = versions
+                .iter()
+                .any(|r| !r.is_chunked && r.blob_hash == v.blob_hash && r.created_at > v.created_at);
+            !kept_by_count && !kept_by_age && Some(v.id) != current && !restored_from
+        })
+        .map(|(_, v)| v.id)
+        .collect()
+}
+
+/// What [`prune_versions`] deleted
+#[derive(Debug, Default)]
+pub struct PrunedVersions {
+    pub version_ids: Vec<Uuid>,
+    /// Chunks whose data the caller deletes after committing
+    pub released: Vec<ReleasedChunk>,
+}
+
+/// Delete a file's versions that fall outside `retention`. The current
+/// version is always kept, as is any version a later restore was made from:
+/// a restored version refers to its source's content by hash rather than
+/// holding chunks of its own.
+pub async fn prune_versions(
+    conn: &mut PgConnection,
+    file_id: Uuid,
+    retention: VersionRetention,
+) -> anyhow::Result<PrunedVersions> {
+    if retention.is_unlimited() {
+        return Ok(PrunedVersions::default());
+    }
+
+    let versions = sqlx::query_as::<_, RetainedVersion>(
+        "SELECT id, blob_hash, created_at, is_chunked FROM versions WHERE file_id = $1",
+    )
+    .bind(file_id)
+    .fetch_all(&mut *conn)
+    .await?;
+    let (current,): (Option<Uuid>,) = sqlx::query_as("SELECT current_version_id FROM files WHERE id = $1")
+        .bind(file_id)
+        .fetch_one(&mut *conn)
+        .await?;
+    let version_ids = expired_versions(&versions, current, retention, Utc::now());
+
+    let released = if version_ids.is_empty() {
+        Vec::new()
+    } else {
+        release_versions(conn, &version_ids).await?
+    };
+    Ok(PrunedVersions { version_ids, released })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(n: u128, hash: &str, days_ago: i64, is_chunked: bool) -> RetainedVersion {
+        RetainedVersion {
+            id: Uuid::from_u128(n),
+            blob_hash: hash.to_string(),
+            created_at: Utc::now() - chrono::Duration::days(days_ago),
+            is_chunked,
+        }
+    }
+
+    fn ids(list: &[u128]) -> Vec<Uuid> {
+        list.iter().map(|&n| Uuid::from_u128(n)).collect()
+    }
+
+    /// Five chunked versions with distinct content, 40, 30, 20, 10 and 0
+    /// days old
+    fn history() -> Vec<RetainedVersion> {
+        (1..=5).map(|n| version(n, &format!("h{}", n), 50 - n as i64 * 10, true)).collect()
+    }
+
+    fn expired(versions: &[RetainedVersion], current: u128, keep_last: Option<u32>, keep_days: Option<u32>) -> Vec<Uuid> {
+        let mut expired = expired_versions(
+            versions,
+            Some(Uuid::from_u128(current)),
+            VersionRetention { keep_last, keep_days },
+            Utc::now(),
+        );
+        expired.sort();
+        expired
+    }
+
+    #[test]
+    fn test_unlimited_retention_keeps_everything() {
+        assert!(expired(&history(), 5, None, None).is_empty());
+    }
+
+    #[test]
+    fn test_keep_last() {
+        assert_eq!(expired(&history(), 5, Some(2), None), ids(&[1, 2, 3]));
+        assert!(expired(&history(), 5, Some(5), None).is_empty());
+        assert_eq!(expired(&history(), 5, Some(1), None), ids(&[1, 2, 3, 4]));
+    }
+
+    #[test]
+    fn test_keep_days() {
+        assert_eq!(expired(&history(), 5, None, Some(25)), ids(&[1, 2]));
+        assert!(expired(&history(), 5, None, Some(45)).is_empty());
+    }
+
+    #[test]
+    fn test_version_kept_if_either_limit_keeps_it() {
+        assert_eq!(expired(&history(), 5, Some(1), Some(25)), ids(&[1, 2]));
+        assert_eq!(expired(&history(), 5, Some(4), Some(5)), ids(&[1]));
+    }
+
+    #[test]
+    fn test_current_version_is_never_pruned() {
+        assert_eq!(expired(&history(), 1, Some(1), None), ids(&[2, 3, 4]));
+    }
+
+    #[test]
+    fn test_restore_source_is_kept() {
+        // Version 6 restored version 2's content: a whole-file version with its hash
+        let mut versions = history();
+        versions.push(version(6, "h2", 0, false));
+        assert_eq!(expired(&versions, 6, Some(1), None), ids(&[1, 3, 4, 5]));
+
+        // A later chunked upload of the same content is not a restore
+        let mut versions = history();
+        versions.push(version(6, "h2", 0, true));
+        assert_eq!(expired(&versions, 6, Some(1), None), ids(&[1, 2, 3, 4, 5]));
+    }
+
+    #[test]
+    fn test_same_timestamp_ranks_by_id() {
+        let now = Utc::now();
+        let mut versions = history();
+        for v in &mut versions {
+            v.created_at = now;
+        }
+        assert_eq!(expired(&versions, 5, Some(2), None), ids(&[1, 2, 3]));
+    }
+
+    #[test]
+    fn test_validate_retention() {
+        assert!(VersionRetention::default().validate().is_ok());
+        assert!(VersionRetention { keep_last: Some(1), keep_days: Some(0) }.validate().is_ok());
+        assert!(VersionRetention { keep_last: Some(0), keep_days: None }.validate().is_err());
+    }
+}
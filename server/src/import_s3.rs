@@ -0,0 +1,301 @@
+//! Import from S3-compatible object storage (`tangled import-s3`)
+//!
+//! Seeds this server from a bucket: every object under a prefix is streamed
+//! down, chunked the way a multipart upload is, and recorded as the current
+//! version of the file whose path is the object key. Chunk storage is
+//! content-addressed, so chunks already stored are not written again, and an
+//! object whose size and modification time match the file's current version
+//! is not downloaded at all. An interrupted run can simply be started again.
+//!
+//! Credentials, region and endpoint come from the standard AWS environment
+//! (`AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`, `AWS_REGION`,
+//! `AWS_ENDPOINT_URL`, profiles), as for the AWS CLI.
+
+use crate::api::rest::validate_path;
+use crate::config::PathLimits;
+use crate::db::versions::{self, VersionRetention};
+use crate::db::{chunks, files, ChunkTier, DbPool};
+use crate::storage::backend::s3_client;
+use crate::storage::chunking::StreamChunker;
+use crate::storage::tiering::{streaming_tier, DefaultTierStrategy, TierStrategy};
+use crate::storage::{store_chunk, BlobManager};
+use anyhow::{anyhow, Context, Result};
+use aws_sdk_s3::error::DisplayErrorContext;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+/// Counters reported at the end of a run
+#[derive(Debug, Default)]
+pub struct ImportStats {
+    pub files_imported: usize,
+    pub files_skipped: usize,
+    pub chunks_stored: usize,
+    pub chunks_deduplicated: usize,
+    pub bytes_downloaded: u64,
+    pub errors: usize,
+}
+
+/// How imported files are recorded
+#[derive(Debug, Clone, Copy)]
+pub struct ImportOptions {
+    pub owner_id: Uuid,
+    pub path_limits: PathLimits,
+    pub case_insensitive: bool,
+    pub retention: VersionRetention,
+}
+
+// ============================================================================
+// SOURCE BUCKET
+// ============================================================================
+
+/// An object listed in the source bucket
+#[derive(Debug)]
+pub struct SourceObject {
+    pub key: String,
+    pub size: i64,
+    pub last_modified: Option<DateTime<Utc>>,
+}
+
+/// A bucket to import from
+pub struct S3Source {
+    client: aws_sdk_s3::Client,
+    bucket: String,
+}
+
+impl S3Source {
+    /// Connect with credentials from the environment. `path_style` addresses
+    /// the bucket as `endpoint/bucket`, which MinIO and most self-hosted
+    /// S3-compatible servers need.
+    pub async fn connect(bucket: &str, path_style: bool) -> Self {
+        Self {
+            client: s3_client(path_style).await,
+            bucket: bucket.to_string(),
+        }
+    }
+
+    pub fn bucket(&self) -> &str {
+        &self.bucket
+    }
+
+    /// Every object under `prefix`, in key order
+    pub async fn list(&self, prefix: Option<&str>) -> Result<Vec<SourceObject>> {
+        let mut pages = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .set_prefix(prefix.map(str::to_string))
+            .into_paginator()
+            .send();
+
+        let mut objects = Vec::new();
+        while let Some(page) = pages.next().await {
+            let page = page.map_err(|e| anyhow!("Failed to list bucket {}: {}", self.bucket, DisplayErrorContext(e)))?;
+            for object in page.contents() {
+                let Some(key) = object.key() else {
+                    continue;
+                };
+                objects.push(SourceObject {
+                    key: key.to_string(),
+                    size: object.size().unwrap_or(0),
+                    last_modified: object
+                        .last_modified()
+                        .and_then(|t| DateTime::from_timestamp(t.secs(), t.subsec_nanos())),
+                });
+            }
+        }
+        Ok(objects)
+    }
+
+    async fn open(&self, key: &str) -> Result<aws_sdk_s3::primitives::ByteStream> {
+        let object = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| anyhow!("Failed to download {}: {}", key, DisplayErrorContext(e)))?;
+        Ok(object.body)
+    }
+}
+
+/// Path an object is imported under, or None for a folder marker (a key
+/// ending in `/`, as created by the S3 console)
+fn object_path(key: &str, limits: PathLimits) -> Result<Option<String>> {
+    if key.ends_with('/') {
+        return Ok(None);
+    }
+    let path = files::normalize_path(&format!("/{}", key));
+    validate_path(&path, limits).map_err(|e| anyhow!("Invalid path {}: {:?}", path, e))?;
+    Ok(Some(path))
+}
+
+// ============================================================================
+// IMPORT
+// ============================================================================
+
+/// Import every object under `prefix` from `source`
+pub async fn import(
+    pool: &DbPool,
+    blob_manager: &BlobManager,
+    source: &S3Source,
+    prefix: Option<&str>,
+    options: ImportOptions,
+) -> Result<ImportStats> {
+    println!("listing s3://{}/{}...", source.bucket(), prefix.unwrap_or_default());
+    let objects = source.list(prefix).await?;
+    let total = objects.len();
+    println!("{} objects", total);
+
+    let mut stats = ImportStats::default();
+    for (index, object) in objects.iter().enumerate() {
+        let progress = format!("[{}/{}]", index + 1, total);
+        let path = match object_path(&object.key, options.path_limits) {
+            Ok(Some(path)) => path,
+            Ok(None) => continue,
+            Err(e) => {
+                println!("  ✗ {} {} (error: {})", progress, object.key, e);
+                stats.errors += 1;
+                continue;
+            }
+        };
+        match import_object(pool, blob_manager, source, object, &path, options, &mut stats).await {
+            Ok(Some(chunk_count)) => {
+                println!("  ✓ {} {} ({} chunks, {} bytes)", progress, path, chunk_count, object.size);
+                stats.files_imported += 1;
+            }
+            Ok(None) => {
+                println!("  = {} {} (unchanged)", progress, path);
+                stats.files_skipped += 1;
+            }
+            Err(e) => {
+                println!("  ✗ {} {} (error: {:#})", progress, path, e);
+                stats.errors += 1;
+            }
+        }
+    }
+
+    Ok(stats)
+}
+
+/// Download, chunk and store one object, then record it as the current
+/// version of `path`. Returns the chunk count, or None if the file already
+/// holds this content.
+async fn import_object(
+    pool: &DbPool,
+    blob_manager: &BlobManager,
+    source: &S3Source,
+    object: &SourceObject,
+    path: &str,
+    options: ImportOptions,
+    stats: &mut ImportStats,
+) -> Result<Option<usize>> {
+    let existing = files::get_file_by_path(pool, options.owner_id, path).await?;
+    let current = match existing.as_ref().filter(|f| !f.is_deleted).and_then(|f| f.current_version_id) {
+        Some(version_id) => versions::get_version(pool, version_id).await?,
+        None => None,
+    };
+
+    // A previous import of the same object: don't download it again
+    if let (Some(file), Some(version)) = (&existing, &current) {
+        if version.size_bytes == object.size && object.last_modified == Some(file.updated_at) {
+            return Ok(None);
+        }
+    }
+
+    let tier = streaming_tier(
+        DefaultTierStrategy::determine_tier(std::path::Path::new(path), object.size.max(0) as u64).into(),
+    );
+    let mut chunker = StreamChunker::new(tier.into());
+    let mut chunk_infos = Vec::new();
+
+    let mut body = source.open(&object.key).await?;
+    while let Some(data) = body.try_next().await.context("Download interrupted")? {
+        stats.bytes_downloaded += data.len() as u64;
+        for chunk in chunker.push(&data) {
+            store_import_chunk(pool, blob_manager, tier, &chunk, &mut chunk_infos, stats).await?;
+        }
+    }
+    let size_bytes = chunker.total_size() as i64;
+    let (rest, file_hash) = chunker.finish();
+    for chunk in rest {
+        store_import_chunk(pool, blob_manager, tier, &chunk, &mut chunk_infos, stats).await?;
+    }
+
+    let content_hash = hex::encode(file_hash);
+    if current.is_some_and(|v| v.blob_hash == content_hash) {
+        return Ok(None);
+    }
+
+    let mut tx = pool.begin().await?;
+    files::lock_path(&mut tx, options.owner_id, path).await?;
+    let file = files::upsert_file_with_owner_and_dates(
+        &mut tx,
+        path,
+        options.owner_id,
+        None,
+        object.last_modified,
+        options.case_insensitive,
+    )
+    .await?;
+    let (_, released) = chunks::create_version_with_tier(
+        &mut tx,
+        file.id,
+        &content_hash,
+        size_bytes,
+        tier,
+        &chunk_infos,
+        Some(options.owner_id),
+        options.retention,
+    )
+    .await?;
+    tx.commit().await?;
+    blob_manager.delete_released(pool, &released).await;
+
+    Ok(Some(chunk_infos.len()))
+}
+
+/// Store one chunk of an object (unless already stored) and append it to the
+/// version's chunk list
+async fn store_import_chunk(
+    pool: &DbPool,
+    blob_manager: &BlobManager,
+    tier: ChunkTier,
+    data: &[u8],
+    chunk_infos: &mut Vec<chunks::ChunkInfo>,
+    stats: &mut ImportStats,
+) -> Result<()> {
+    let hash = blake3::hash(data).to_hex().to_string();
+    if chunks::chunk_exists(pool, &hash).await? {
+        stats.chunks_deduplicated += 1;
+    } else {
+        store_chunk(blob_manager, pool, &hash, data, tier)
+            .await
+            .context("Failed to store chunk")?;
+        stats.chunks_stored += 1;
+    }
+
+    let offset_in_file = chunk_infos
+        .last()
+        .map_or(0, |c| c.offset_in_file + c.size_bytes as i64);
+    chunk_infos.push(chunks::ChunkInfo {
+        hash,
+        size_bytes: data.len() as i32,
+        offset_in_file,
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_object_path() {
+        let limits = PathLimits::default();
+        assert_eq!(object_path("photos/2024/a.jpg", limits).unwrap().as_deref(), Some("/photos/2024/a.jpg"));
+        assert_eq!(object_path("a//b.txt", limits).unwrap().as_deref(), Some("/a/b.txt"));
+        assert_eq!(object_path("photos/", limits).unwrap(), None);
+        assert!(object_path("../etc/passwd", limits).is_err());
+    }
+}
@@ -2,6 +2,11 @@ pub mod api;
 pub mod auth;
 pub mod config;
 pub mod db;
+pub mod import_s3;
+pub mod rechunk;
+pub mod reshard;
+pub mod replicate;
 pub mod storage;
+pub mod systemd;
 pub mod tui;
 
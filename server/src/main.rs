@@ -2,6 +2,7 @@
 
 use clap::{Parser, Subcommand};
 use std::fs;
+use std::net::IpAddr;
 use std::path::PathBuf;
 use std::process::{Command, Stdio};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
@@ -10,10 +11,16 @@ mod api;
 mod auth;
 mod config;
 mod db;
+mod import_s3;
+mod rechunk;
+mod reshard;
+mod replicate;
 mod storage;
+mod systemd;
 mod tui;
 
 use config::Config;
+use db::users::UserRole;
 
 #[derive(Parser)]
 #[command(name = "tangled")]
@@ -32,6 +39,17 @@ enum Commands {
         /// Run in foreground (don't daemonize)
         #[arg(long)]
         foreground: bool,
+        /// Wait until the daemon reports ready (/health/ready); exit non-zero
+        /// if it exits or isn't ready within --wait-timeout
+        #[arg(long, conflicts_with = "foreground")]
+        wait: bool,
+        /// Seconds --wait gives the daemon to become ready
+        #[arg(long, default_value_t = 30, requires = "wait")]
+        wait_timeout: u64,
+        /// Address to listen on, overriding BIND_ADDRESS (default 127.0.0.1;
+        /// 0.0.0.0 or :: listens on every interface)
+        #[arg(long, value_name = "ADDR")]
+        bind: Option<IpAddr>,
     },
     /// Stop the server
     Down,
@@ -42,7 +60,7 @@ enum Commands {
         /// Folder to index
         path: String,
     },
-    /// Run database migrations
+    /// Run database migrations and normalize stored file paths
     Migrate,
     /// Reset database (drop all tables and data)
     Reset {
@@ -50,16 +68,88 @@ enum Commands {
         #[arg(long)]
         force: bool,
     },
-    /// Export all files to a plain folder (emergency recovery)
+    /// Export files to a plain folder (emergency recovery / incremental backup)
     Export {
         /// Output folder
         path: String,
+        /// Only export files updated after this time (RFC 3339)
+        #[arg(long)]
+        since: Option<String>,
+    },
+    /// Push files to another Entanglement server (additive, resumable)
+    Replicate {
+        /// Base URL of the target server
+        #[arg(long)]
+        target: String,
+        /// Account to log in to on the target
+        #[arg(long)]
+        username: String,
+        /// Only push this user's files (and ownerless shared ones); required
+        /// when files here belong to more than one user
+        #[arg(long)]
+        owner: Option<String>,
+    },
+    /// Import objects from an S3 bucket as files (credentials from the AWS environment)
+    ImportS3 {
+        /// Source bucket
+        bucket: String,
+        /// Only import keys starting with this prefix
+        #[arg(long)]
+        prefix: Option<String>,
+        /// Account that will own the imported files
+        #[arg(long)]
+        owner: String,
+        /// Address the bucket as endpoint/bucket (MinIO and other self-hosted servers)
+        #[arg(long)]
+        path_style: bool,
     },
     /// User management
     User {
         #[command(subcommand)]
         command: UserCommands,
     },
+    /// Inspect stored chunks and blobs (debugging)
+    Blob {
+        #[command(subcommand)]
+        command: BlobCommands,
+    },
+    /// Compare FastCDC parameter sets on sample files (stores nothing)
+    BenchChunking {
+        /// Folder of representative files
+        sample_dir: String,
+    },
+    /// Check stored data for damage (runs every check unless one is named)
+    Verify {
+        /// Find versions whose chunks are missing, e.g. after an interrupted upload
+        #[arg(long)]
+        orphans: bool,
+        /// Delete the damaged versions found, promoting each file's previous good version
+        #[arg(long)]
+        delete: bool,
+    },
+    /// Convert whole-file blobs (from `index`) to deduplicated chunks
+    Rechunk {
+        /// Only files at or under this path
+        #[arg(long)]
+        path: Option<String>,
+    },
+    /// Move blob and container files into the BLOB_SHARD_LEVELS layout
+    Reshard,
+    /// Switch the running server's read-only maintenance mode
+    Maintenance {
+        #[command(subcommand)]
+        command: MaintenanceCommands,
+    },
+}
+
+#[derive(Subcommand)]
+enum MaintenanceCommands {
+    /// Refuse writes with 503 until turned off; reads keep working
+    On,
+    /// Accept writes again
+    Off,
+    /// Show whether maintenance mode is on
+    Status,
 }
 
 #[derive(Subcommand)]
@@ -72,11 +162,39 @@ enum UserCommands {
         /// Make user an admin
         #[arg(long)]
         admin: bool,
+        /// Make user a read-only viewer
+        #[arg(long, conflicts_with = "admin")]
+        viewer: bool,
     },
     /// List all users
     List,
 }
 
+#[derive(Subcommand)]
+enum BlobCommands {
+    /// List known chunk hashes with their size and location
+    Ls {
+        /// Only hashes starting with this hex prefix
+        prefix: Option<String>,
+        /// Maximum number of chunks to list
+        #[arg(long, default_value_t = 100)]
+        limit: i64,
+    },
+    /// Write a chunk's (decompressed) bytes to stdout
+    Cat {
+        /// Chunk hash or unique prefix
+        hash: String,
+        /// Write even when stdout is a terminal
+        #[arg(long)]
+        force: bool,
+    },
+    /// Show where a chunk is physically stored
+    Where {
+        /// Chunk hash or unique prefix
+        hash: String,
+    },
+}
+
 fn pid_file() -> PathBuf {
     dirs::runtime_dir()
         .or_else(|| dirs::data_local_dir())
@@ -126,40 +244,70 @@ async fn main() -> anyhow::Result<()> {
         Commands::Status => {
             return show_status();
         }
-        Commands::Serve { foreground } if !foreground => {
-            return start_daemon();
+        // Under systemd the service manager supervises the process, so stay in
+        // the foreground instead of forking
+        Commands::Serve { foreground: false, wait, wait_timeout, bind } if !systemd::under_systemd() => {
+            let wait = wait.then(|| std::time::Duration::from_secs(*wait_timeout));
+            return start_daemon(wait, *bind).await;
+        }
+        Commands::BenchChunking { sample_dir } => {
+            return bench_chunking(sample_dir);
         }
         _ => {}
     }
 
-    // Initialize logging for foreground commands
+    // .env first, so it can set RUST_LOG and LOG_FORMAT too
+    dotenvy::dotenv().ok();
+
+    // Initialize logging for foreground commands: human-readable lines by
+    // default, one JSON object per line (with the request span's fields,
+    // including request_id) for log aggregators
+    let json = match std::env::var("LOG_FORMAT").unwrap_or_default().as_str() {
+        "" | "text" | "pretty" => false,
+        "json" => true,
+        other => anyhow::bail!("LOG_FORMAT must be text or json, got {:?}", other),
+    };
     tracing_subscriber::registry()
         .with(
             tracing_subscriber::EnvFilter::try_from_default_env()
                 .unwrap_or_else(|_| "tangled=info,tower_http=info".into()),
         )
-        .with(tracing_subscriber::fmt::layer())
+        .with((!json).then(tracing_subscriber::fmt::layer))
+        .with(json.then(|| tracing_subscriber::fmt::layer().json().with_current_span(true)))
         .init();
 
     // Load configuration
-    dotenvy::dotenv().ok();
-    let config = Config::from_env()?;
+    let mut config = Config::from_env()?;
 
     match cli.command {
         Commands::Setup => {
             tui::run_setup(config).await?;
         }
-        Commands::Serve { foreground: _ } => {
+        Commands::Serve { bind, .. } => {
             // Running in foreground mode
+            if let Some(bind) = bind {
+                config.bind_address = bind;
+            }
             run_server(config).await?;
         }
         Commands::Down => unreachable!(),
         Commands::Status => unreachable!(),
+        Commands::BenchChunking { .. } => unreachable!(),
         Commands::Index { path } => {
             index_folder(&config, &path).await?;
         }
-        Commands::Export { path } => {
-            export_files(&config, &path).await?;
+        Commands::Export { path, since } => {
+            let since = since
+                .map(|s| chrono::DateTime::parse_from_rfc3339(&s).map(|dt| dt.with_timezone(&chrono::Utc)))
+                .transpose()
+                .map_err(|e| anyhow::anyhow!("Invalid --since timestamp (expected RFC 3339): {}", e))?;
+            export_files(&config, &path, since).await?;
+        }
+        Commands::Replicate { target, username, owner } => {
+            replicate_to(&config, &target, &username, owner.as_deref()).await?;
+        }
+        Commands::ImportS3 { bucket, prefix, owner, path_style } => {
+            import_from_s3(&config, &bucket, prefix.as_deref(), &owner, path_style).await?;
         }
         Commands::Migrate => {
             run_migrations(&config).await?;
@@ -168,31 +316,71 @@ async fn main() -> anyhow::Result<()> {
             reset_database(&config, force).await?;
         }
         Commands::User { command } => match command {
-            UserCommands::Create { username, admin } => {
-                create_user(&config, &username, admin).await?;
+            UserCommands::Create { username, admin, viewer } => {
+                let role = if admin {
+                    UserRole::Admin
+                } else if viewer {
+                    UserRole::Viewer
+                } else {
+                    UserRole::Editor
+                };
+                create_user(&config, &username, role).await?;
             }
             UserCommands::List => {
                 list_users(&config).await?;
             }
         },
+        Commands::Blob { command } => {
+            blob_command(&config, command).await?;
+        }
+        // --orphans is the only check so far, so it always runs
+        Commands::Verify { orphans: _, delete } => {
+            verify_orphans(&config, delete).await?;
+        }
+        Commands::Rechunk { path } => {
+            rechunk_legacy_blobs(&config, path.as_deref()).await?;
+        }
+        Commands::Reshard => {
+            reshard_blob_files(&config).await?;
+        }
+        Commands::Maintenance { command } => {
+            let enabled = match command {
+                MaintenanceCommands::On => Some(true),
+                MaintenanceCommands::Off => Some(false),
+                MaintenanceCommands::Status => None,
+            };
+            maintenance_command(&config, enabled).await?;
+        }
     }
 
     Ok(())
 }
 
-fn start_daemon() -> anyhow::Result<()> {
+/// Start the server in the background, listening on `bind` if given. With
+/// `wait`, block until it is ready to serve requests, failing if it exits
+/// first or takes longer than `wait`.
+async fn start_daemon(wait: Option<std::time::Duration>, bind: Option<IpAddr>) -> anyhow::Result<()> {
     // Check if already running
     if let Some(pid) = is_server_running() {
         println!("tangled already running (pid {})", pid);
         return Ok(());
     }
 
+    // Load config to get ports (and to fail here on a bad .env rather than
+    // in a detached child)
+    dotenvy::dotenv().ok();
+    let mut config = Config::from_env()?;
+    if let Some(bind) = bind {
+        config.bind_address = bind;
+    }
+
     // Get current executable path
     let exe = std::env::current_exe()?;
     
     // Spawn detached process with --foreground flag
-    let child = Command::new(&exe)
+    let mut child = Command::new(&exe)
         .args(["serve", "--foreground"])
+        .args(bind.map(|bind| format!("--bind={}", bind)))
         .stdin(Stdio::null())
         .stdout(Stdio::null())
         .stderr(Stdio::null())
@@ -207,16 +395,96 @@ fn start_daemon() -> anyhow::Result<()> {
     }
     fs::write(&pid_path, pid.to_string())?;
 
-    // Load config to get ports
-    dotenvy::dotenv().ok();
-    let config = Config::from_env()?;
+    if let Some(timeout) = wait {
+        if let Err(e) = wait_until_ready(&mut child, &config, timeout).await {
+            if child.try_wait()?.is_some() {
+                let _ = fs::remove_file(&pid_path);
+            }
+            return Err(e);
+        }
+    }
 
-    println!("tangled serving on localhost:{}", config.rest_port);
+    println!("tangled serving on {}", config.rest_addr());
     println!("pid: {}", pid);
 
     Ok(())
 }
 
+/// Client for the CLI's own requests to the server on this machine. With
+/// TLS the certificate names the public host, not 127.0.0.1, so it isn't
+/// verified on this loopback connection.
+fn local_client(config: &Config, timeout: std::time::Duration) -> reqwest::Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .timeout(timeout)
+        .danger_accept_invalid_certs(config.tls_cert_path.is_some())
+        .build()
+}
+
+/// Poll the daemon's readiness probe until it answers 200
+async fn wait_until_ready(
+    child: &mut std::process::Child,
+    config: &Config,
+    timeout: std::time::Duration,
+) -> anyhow::Result<()> {
+    let url = format!("{}/health/ready", config.local_base_url());
+    let client = local_client(config, std::time::Duration::from_secs(2))?;
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            anyhow::bail!(
+                "tangled exited during startup ({}); run `tangled serve --foreground` to see why",
+                status
+            );
+        }
+        if let Ok(response) = client.get(&url).send().await {
+            if response.status().is_success() {
+                return Ok(());
+            }
+        }
+        if tokio::time::Instant::now() >= deadline {
+            anyhow::bail!(
+                "tangled (pid {}) was not ready within {}s",
+                child.id(),
+                timeout.as_secs()
+            );
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(250)).await;
+    }
+}
+
+/// Read or set the running server's maintenance mode through
+/// `/admin/maintenance`, authenticating as the oldest enabled admin with a
+/// short-lived token signed with JWT_SECRET
+async fn maintenance_command(config: &Config, enabled: Option<bool>) -> anyhow::Result<()> {
+    let pool = db::create_pool(&config.database_url).await?;
+    let admin = db::users::list_users(&pool)
+        .await?
+        .into_iter()
+        .rev()
+        .find(|u| u.is_admin && !u.disabled)
+        .ok_or_else(|| anyhow::anyhow!("No enabled admin user; create one with `tangled user create --admin`"))?;
+    let token = auth::create_access_token(&config.jwt_secret, admin.id, admin.token_version, 60)?;
+
+    let url = format!("{}/admin/maintenance", config.local_base_url());
+    let client = local_client(config, std::time::Duration::from_secs(10))?;
+    let request = match enabled {
+        Some(enabled) => client.post(&url).json(&serde_json::json!({ "enabled": enabled })),
+        None => client.get(&url),
+    };
+    let response = request
+        .bearer_auth(token)
+        .send()
+        .await
+        .map_err(|e| anyhow::anyhow!("Could not reach tangled on port {} (is it running?): {}", config.rest_port, e))?;
+    if !response.status().is_success() {
+        anyhow::bail!("tangled answered {}: {}", response.status(), response.text().await.unwrap_or_default());
+    }
+    let body: serde_json::Value = response.json().await?;
+    let on = body["enabled"].as_bool().unwrap_or(false);
+    println!("maintenance mode {}", if on { "on (read-only)" } else { "off" });
+    Ok(())
+}
+
 fn stop_server() -> anyhow::Result<()> {
     if let Some(pid) = is_server_running() {
         #[cfg(unix)]
@@ -254,13 +522,36 @@ fn show_status() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Blob manager over the local containers, plus the S3 backend when
+/// S3_BUCKET is set
+async fn open_blob_manager(
+    config: &Config,
+    containers_path: &str,
+    pool: &db::DbPool,
+) -> anyhow::Result<storage::BlobManager> {
+    let remote: Option<Box<dyn storage::BlobBackend>> = match &config.s3_bucket {
+        Some(bucket) => Some(Box::new(
+            storage::S3Backend::connect(bucket, &config.s3_prefix, config.s3_path_style).await,
+        )),
+        None => None,
+    };
+    Ok(storage::BlobManager::new(containers_path, pool.clone())?
+        .with_encryption_key(config.storage_encryption_key.as_deref())
+        .with_shard_levels(config.blob_shard_levels)
+        .with_remote_backend(remote, config.storage_backend == db::StorageBackend::S3))
+}
+
 async fn run_server(config: Config) -> anyhow::Result<()> {
-    // Save PID for foreground mode too
-    let pid_path = pid_file();
-    if let Some(parent) = pid_path.parent() {
-        fs::create_dir_all(parent)?;
+    // Save PID for foreground mode too, unless systemd is tracking it
+    let write_pid = !systemd::under_systemd();
+    if write_pid {
+        let pid_path = pid_file();
+        if let Some(parent) = pid_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&pid_path, std::process::id().to_string())?;
     }
-    fs::write(&pid_path, std::process::id().to_string())?;
+    systemd::spawn_watchdog();
 
     // Initialize database pool
     let db_pool = db::create_pool(&config.database_url).await?;
@@ -277,24 +568,91 @@ async fn run_server(config: Config) -> anyhow::Result<()> {
 
     // Initialize container-based blob manager (handles both chunked and legacy storage)
     let containers_path = format!("{}/containers", config.blob_storage_path);
-    let blob_manager = storage::BlobManager::new(&containers_path, db_pool.clone())?;
+    let blob_manager = open_blob_manager(&config, &containers_path, &db_pool)
+        .await?
+        .with_container_target_bytes(config.container_target_bytes)
+        .with_prefetch_depth(config.download_prefetch_depth)
+        .with_chunk_filter_fp_rate(config.chunk_filter_fp_rate)
+        .with_disk_reserve(config.disk_reserve);
+    tracing::info!(
+        "blob containers roll over at {} bytes (CONTAINER_TARGET_BYTES)",
+        blob_manager.container_target_bytes()
+    );
+    tracing::info!("new chunks are stored in {} storage (STORAGE_BACKEND)", config.storage_backend.as_str());
+    if config.trusted_proxies.is_empty() {
+        tracing::info!("forwarding headers are ignored; set TRUSTED_PROXIES behind a reverse proxy");
+    } else {
+        tracing::info!("client addresses are taken from forwarding headers of TRUSTED_PROXIES");
+    }
 
     // Create shared application state
     let app_state = api::AppState::new(db_pool.clone(), blob_manager, config.clone());
 
+    // POST file changes to WEBHOOK_URL, if set
+    api::webhook::spawn(&config, &app_state.sync_hub)?;
+
+    // Build the chunk existence filter, then rebuild it periodically to bound drift
+    let filter_state = app_state.clone();
+    let rebuild_secs = config.chunk_filter_rebuild_secs;
+    tokio::spawn(async move {
+        loop {
+            let filter = filter_state.blob_manager.chunk_filter();
+            match filter.rebuild(&filter_state.db).await {
+                Ok(count) => tracing::info!(
+                    "chunk filter rebuilt: {} hashes, {} KiB",
+                    count,
+                    filter.size_bytes() / 1024
+                ),
+                Err(e) => tracing::warn!("chunk filter rebuild failed: {}", e),
+            }
+            if rebuild_secs == 0 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_secs(rebuild_secs)).await;
+        }
+    });
+
+    // Repack sparse containers in the background, one at a time
+    if config.compaction_paused {
+        tracing::info!("container compaction paused (COMPACTION_PAUSED)");
+    } else if config.compaction_interval_secs > 0 {
+        let compaction_state = app_state.clone();
+        let interval = std::time::Duration::from_secs(config.compaction_interval_secs);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                let state = &compaction_state;
+                match state.compactor.run_once(&state.blob_manager, &state.db).await {
+                    Ok(Some(run)) if run.candidates > 0 => tracing::info!(
+                        "compaction: repacked {} of {} containers, reclaimed {} bytes{}",
+                        run.containers_repacked,
+                        run.candidates,
+                        run.bytes_reclaimed,
+                        if run.interrupted { " (window closed)" } else { "" }
+                    ),
+                    Ok(_) => {}
+                    Err(e) => tracing::warn!("compaction failed: {}", e),
+                }
+            }
+        });
+    }
+
     // Start REST server
-    let rest_addr = format!("0.0.0.0:{}", config.rest_port).parse()?;
+    let rest_addr = config.rest_addr();
     let rest_state = app_state.clone();
     let rest_handle = tokio::spawn(async move {
         tracing::info!("REST listening on {}", rest_addr);
         api::rest::serve(rest_addr, rest_state).await
     });
 
-    // Wait for REST server
+    // Wait for REST server (returns once in-flight uploads have settled)
     rest_handle.await??;
+    db_pool.close().await;
 
     // Cleanup PID file
-    let _ = fs::remove_file(pid_file());
+    if write_pid {
+        let _ = fs::remove_file(pid_file());
+    }
 
     Ok(())
 }
@@ -303,11 +661,19 @@ async fn run_migrations(config: &Config) -> anyhow::Result<()> {
     println!("running migrations...");
     let pool = db::create_pool(&config.database_url).await?;
     db::run_migrations(&pool).await?;
+
+    let normalized = db::files::normalize_stored_paths(&pool).await?;
+    if normalized.updated > 0 {
+        println!("normalized {} file paths", normalized.updated);
+    }
+    for path in &normalized.conflicts {
+        println!("warning: left '{}' as is, its normalized path is already in use", path);
+    }
     println!("migrations complete");
     Ok(())
 }
 
-async fn create_user(config: &Config, username: &str, is_admin: bool) -> anyhow::Result<()> {
+async fn create_user(config: &Config, username: &str, role: UserRole) -> anyhow::Result<()> {
     use std::io::{self, Write};
     
     // Validate username
@@ -339,9 +705,7 @@ async fn create_user(config: &Config, username: &str, is_admin: bool) -> anyhow:
         anyhow::bail!("Passwords do not match");
     }
     
-    if password.len() < 4 {
-        anyhow::bail!("Password must be at least 4 characters");
-    }
+    auth::validate_password_policy(&password, &config.password_policy())?;
 
     println!("Connecting to database...");
     
@@ -352,12 +716,12 @@ async fn create_user(config: &Config, username: &str, is_admin: bool) -> anyhow:
         .map_err(|_| anyhow::anyhow!("Database connection timed out. Is PostgreSQL running?"))??;
     
     println!("Hashing password...");
-    let password_hash = auth::hash_password(&password)?;
+    let password_hash = auth::hash_password(&password, &config.hash_params())?;
     
     println!("Creating user in database...");
-    let user = db::users::create_user(&pool, username, &password_hash, is_admin).await?;
+    let user = db::users::create_user(&pool, username, &password_hash, role).await?;
 
-    println!("User created: {} (admin: {})", user.id, user.is_admin);
+    println!("User created: {} (role: {})", user.id, user.role.as_str());
 
     Ok(())
 }
@@ -370,14 +734,221 @@ async fn list_users(config: &Config) -> anyhow::Result<()> {
         println!("no users");
     } else {
         for user in users {
-            let role = if user.is_admin { "admin" } else { "user" };
-            println!("{} - {} ({})", user.id, user.username, role);
+            let disabled = if user.disabled { ", disabled" } else { "" };
+            println!("{} - {} ({}{})", user.id, user.username, user.role.as_str(), disabled);
+        }
+    }
+
+    Ok(())
+}
+
+/// Resolve a full chunk hash from a hash or unique prefix. Hashes with no
+/// chunk row are returned as-is so standalone legacy blobs can be inspected.
+async fn resolve_chunk_hash(pool: &db::DbPool, hash: &str) -> anyhow::Result<String> {
+    let hash = hash.trim().to_ascii_lowercase();
+    if hash.is_empty() || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        anyhow::bail!("Chunk hash must be hexadecimal");
+    }
+    if hash.len() >= 64 {
+        return Ok(hash);
+    }
+    let mut matches = db::chunks::find_chunks_by_prefix(pool, &hash).await?;
+    match matches.len() {
+        0 => anyhow::bail!("No chunk matches prefix {}", hash),
+        1 => Ok(matches.remove(0)),
+        _ => anyhow::bail!("Prefix {} matches more than one chunk", hash),
+    }
+}
+
+fn describe_location(chunk: &db::Chunk) -> String {
+    match chunk.location() {
+        db::ChunkLocation::Container { container_id, offset, length } => {
+            let compressed = if length < chunk.size_bytes { ", zstd" } else { "" };
+            format!("container {} @ {}+{}{}", container_id, offset, length, compressed)
+        }
+        db::ChunkLocation::Standalone { .. } => "standalone".to_string(),
+        db::ChunkLocation::Object { backend, .. } => format!("{} object, {} bytes stored", backend.as_str(), chunk.length_bytes.unwrap_or(0)),
+    }
+}
+
+async fn blob_command(config: &Config, command: BlobCommands) -> anyhow::Result<()> {
+    use std::io::{IsTerminal, Write};
+
+    let pool = db::create_pool(&config.database_url).await?;
+    let containers_path = format!("{}/containers", config.blob_storage_path);
+    let blob_manager = open_blob_manager(config, &containers_path, &pool).await?;
+
+    match command {
+        BlobCommands::Ls { prefix, limit } => {
+            let prefix = prefix.unwrap_or_default().to_ascii_lowercase();
+            if !prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+                anyhow::bail!("Prefix must be hexadecimal");
+            }
+            let chunks = db::chunks::list_chunks(&pool, &prefix, limit).await?;
+            if chunks.is_empty() {
+                println!("no chunks");
+            }
+            for chunk in &chunks {
+                println!(
+                    "{}  {:>10}  refs={}  {}",
+                    chunk.hash,
+                    chunk.size_bytes,
+                    chunk.ref_count,
+                    describe_location(chunk)
+                );
+            }
+            if chunks.len() as i64 == limit {
+                println!("(first {} shown; use --limit for more)", limit);
+            }
+        }
+        BlobCommands::Cat { hash, force } => {
+            let mut stdout = std::io::stdout();
+            if stdout.is_terminal() && !force {
+                anyhow::bail!("Refusing to write binary data to a terminal (redirect stdout or pass --force)");
+            }
+            let hash = resolve_chunk_hash(&pool, &hash).await?;
+            let data = match db::chunks::get_chunk_with_location(&pool, &hash).await? {
+                Some(chunk) => blob_manager.read_chunk(&chunk).await?,
+                None => blob_manager.read_legacy_blob(&hash).await?,
+            };
+            stdout.write_all(&data)?;
+            stdout.flush()?;
+        }
+        BlobCommands::Where { hash } => {
+            let hash = resolve_chunk_hash(&pool, &hash).await?;
+            let Some(chunk) = db::chunks::get_chunk_with_location(&pool, &hash).await? else {
+                // Not a chunk, but may still be a whole-file legacy blob
+                let path = blob_manager.legacy_blob_path(&hash)?;
+                if !path.exists() {
+                    anyhow::bail!("Unknown chunk or blob: {}", hash);
+                }
+                println!("hash:      {}", hash);
+                println!("location:  standalone (legacy blob, no chunk record)");
+                println!("file:      {}", path.display());
+                return Ok(());
+            };
+
+            println!("hash:      {}", chunk.hash);
+            println!("size:      {} bytes", chunk.size_bytes);
+            println!("refs:      {}", chunk.ref_count);
+            println!("created:   {}", chunk.created_at.to_rfc3339());
+            match chunk.location() {
+                db::ChunkLocation::Container { container_id, offset, length } => {
+                    println!("location:  container");
+                    println!("container: {}", container_id);
+                    println!("offset:    {}", offset);
+                    println!("length:    {} ({})", length, if length < chunk.size_bytes { "zstd" } else { "uncompressed" });
+                    match db::containers::get_container(&pool, container_id).await? {
+                        Some(container) => {
+                            let sealed = if container.is_sealed { "sealed" } else { "open" };
+                            println!("file:      {}/{} ({})", containers_path, container.disk_path, sealed);
+                        }
+                        None => println!("file:      (container record missing)"),
+                    }
+                }
+                db::ChunkLocation::Standalone { hash } => {
+                    let path = blob_manager.legacy_blob_path(&hash)?;
+                    let state = if path.exists() { "" } else { " (missing)" };
+                    println!("location:  standalone");
+                    println!("file:      {}{}", path.display(), state);
+                }
+                db::ChunkLocation::Object { backend, hash } => {
+                    println!("location:  {} object", backend.as_str());
+                    println!("length:    {} (stored)", chunk.length_bytes.unwrap_or(0));
+                    if let (db::StorageBackend::S3, Some(bucket)) = (backend, &config.s3_bucket) {
+                        println!("object:    s3://{}/{}chunks/{}/{}", bucket, config.s3_prefix, &hash[..2], hash);
+                    }
+                }
+            }
         }
     }
 
     Ok(())
 }
 
+/// Find versions whose chunks can't be read back (no data on disk, or a
+/// zero-size placeholder record) and, with `delete`, remove them
+async fn verify_orphans(config: &Config, delete: bool) -> anyhow::Result<()> {
+    let pool = db::create_pool(&config.database_url).await?;
+    let containers_path = format!("{}/containers", config.blob_storage_path);
+    let blob_manager = open_blob_manager(config, &containers_path, &pool).await?;
+
+    let containers = db::containers::list_containers(&pool, true).await?;
+    let standalone = db::chunks::list_standalone_version_chunks(&pool, db::StorageBackend::Local).await?;
+    let mut objects = db::chunks::list_standalone_version_chunks(&pool, db::StorageBackend::S3).await?;
+    if config.s3_bucket.is_none() && !objects.is_empty() {
+        println!("skipping {} chunks stored in S3 (S3_BUCKET is not set)", objects.len());
+        objects.clear();
+    }
+    println!(
+        "checking {} containers, {} standalone chunks and {} S3 objects",
+        containers.len(),
+        standalone.len(),
+        objects.len()
+    );
+
+    let mut missing_containers = Vec::new();
+    for container in &containers {
+        let path = std::path::Path::new(&containers_path).join(&container.disk_path);
+        if !path.exists() {
+            println!("missing container file: {} ({} chunks)", path.display(), container.chunk_count);
+            missing_containers.push(container.id);
+        }
+    }
+    let mut missing_blobs = Vec::new();
+    for hash in standalone {
+        if !blob_manager.standalone_exists(db::StorageBackend::Local, &hash).await? {
+            missing_blobs.push(hash);
+        }
+    }
+    for hash in objects {
+        if !blob_manager.standalone_exists(db::StorageBackend::S3, &hash).await? {
+            println!("missing S3 object for chunk {}", hash);
+            missing_blobs.push(hash);
+        }
+    }
+
+    let orphans = db::versions::find_orphaned_versions(&pool, &missing_containers, &missing_blobs).await?;
+    for orphan in &orphans {
+        println!(
+            "orphaned: {} version {} from {} ({} of {} chunks missing{})",
+            orphan.path,
+            orphan.id,
+            orphan.created_at.to_rfc3339(),
+            orphan.missing_chunks,
+            orphan.total_chunks,
+            if orphan.is_current { ", current" } else { "" }
+        );
+    }
+    if orphans.is_empty() {
+        println!("no orphaned versions");
+        return Ok(());
+    }
+    if !delete {
+        anyhow::bail!("found {} orphaned versions (rerun with --delete to remove them)", orphans.len());
+    }
+
+    let unusable: Vec<uuid::Uuid> = orphans.iter().map(|o| o.id).collect();
+    for orphan in &orphans {
+        let (removal, released) = db::versions::delete_version(&pool, orphan.id, &unusable).await?;
+        blob_manager.delete_released(&pool, &released).await;
+        match removal {
+            db::versions::VersionRemoval::NotCurrent => {
+                println!("deleted {} version {}", orphan.path, orphan.id);
+            }
+            db::versions::VersionRemoval::Promoted(previous) => {
+                println!("deleted {} version {}, restored version {}", orphan.path, orphan.id, previous);
+            }
+            db::versions::VersionRemoval::FileDeleted => {
+                println!("deleted {} version {}, no good version left so the file was deleted", orphan.path, orphan.id);
+            }
+        }
+    }
+    let forgotten = db::chunks::forget_missing_chunks(&pool, &missing_containers, &missing_blobs).await?;
+    println!("deleted {} orphaned versions and {} missing chunk records", orphans.len(), forgotten);
+    Ok(())
+}
+
 async fn reset_database(config: &Config, force: bool) -> anyhow::Result<()> {
     if !force {
         println!("this will DELETE ALL DATA.");
@@ -414,7 +985,7 @@ async fn index_folder(config: &Config, path: &str) -> anyhow::Result<()> {
     
     let pool = db::create_pool(&config.database_url).await?;
     let containers_path = format!("{}/containers", config.blob_storage_path);
-    let blob_manager = storage::BlobManager::new(&containers_path, pool.clone())?;
+    let blob_manager = open_blob_manager(config, &containers_path, &pool).await?;
     
     let base_path = std::path::Path::new(path);
     if !base_path.exists() {
@@ -453,8 +1024,8 @@ async fn index_folder(config: &Config, path: &str) -> anyhow::Result<()> {
         let blob_hash = blake3::hash(&content).to_hex().to_string();
         
         // Store blob if not exists (using legacy format for compatibility)
-        if !blob_manager.legacy_exists(&blob_hash)? {
-            blob_manager.write_legacy_blob(&blob_hash, &content)?;
+        if !blob_manager.legacy_exists(&blob_hash).await? {
+            blob_manager.write_legacy_blob(&blob_hash, &content).await?;
         }
         
         // Create file record (no user ownership)
@@ -479,11 +1050,88 @@ async fn index_folder(config: &Config, path: &str) -> anyhow::Result<()> {
     Ok(())
 }
 
-/// Export all files from blob storage to plain files (emergency recovery)
-async fn export_files(config: &Config, output_path: &str) -> anyhow::Result<()> {
+/// Chunk every file under `sample_dir` with each benchmark parameter set and
+/// print how they compare
+fn bench_chunking(sample_dir: &str) -> anyhow::Result<()> {
+    use storage::chunk_bench::{default_param_sets, recommend, ChunkBench};
+
+    let base_path = std::path::Path::new(sample_dir);
+    if !base_path.is_dir() {
+        anyhow::bail!("not a folder: {}", sample_dir);
+    }
+
+    let sets = default_param_sets();
+    println!("chunking {} with {} parameter sets...", sample_dir, sets.len());
+    let mut bench = ChunkBench::new(sets);
+    let mut count = 0;
+    for entry in walkdir::WalkDir::new(base_path)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+    {
+        let file = fs::File::open(entry.path())?;
+        let size = file.metadata()?.len();
+        bench.add(entry.path(), size, file)?;
+        count += 1;
+    }
+    if count == 0 {
+        anyhow::bail!("no files in {}", sample_dir);
+    }
+    let results = bench.finish();
+    println!("{} files, {} bytes", count, results[0].total_bytes);
+    println!();
+
+    println!(
+        "{:<10} {:>24} {:>10} {:>10} {:>8} {:>10} {:>14}",
+        "set", "min/avg/max", "chunks", "avg size", "dedup", "MiB/s", "footprint"
+    );
+    for result in &results {
+        let sizes = match result.set.config {
+            Some(c) => format!("{}/{}/{}", c.min_size, c.avg_size, c.max_size),
+            None => "per-file tier".to_string(),
+        };
+        println!(
+            "{:<10} {:>24} {:>10} {:>10} {:>7.3}x {:>10.1} {:>14}",
+            result.set.name,
+            sizes,
+            result.chunk_count,
+            result.avg_chunk_size(),
+            result.dedup_ratio(),
+            result.throughput_mib_s(),
+            result.estimated_footprint()
+        );
+    }
+
+    if let Some(best) = recommend(&results) {
+        println!();
+        match best.set.config {
+            Some(c) => println!(
+                "recommended: {} (min {}, avg {}, max {} bytes)",
+                best.set.name, c.min_size, c.avg_size, c.max_size
+            ),
+            None => println!("recommended: auto (keep the per-file tiers)"),
+        }
+        println!("footprint is unique chunk bytes plus estimated database rows per chunk and reference");
+    }
+
+    Ok(())
+}
+
+/// Export files from blob storage to plain files (emergency recovery / backup)
+///
+/// `manifest.json` in the output folder records the version exported for each
+/// path, so repeated exports only write files that changed. With `since`, only
+/// files updated after that time are considered.
+async fn export_files(
+    config: &Config,
+    output_path: &str,
+    since: Option<chrono::DateTime<chrono::Utc>>,
+) -> anyhow::Result<()> {
+    use storage::export_manifest::{ExportManifest, ManifestEntry};
+
     let pool = db::create_pool(&config.database_url).await?;
     let containers_path = format!("{}/containers", config.blob_storage_path);
-    let blob_manager = storage::BlobManager::new(&containers_path, pool.clone())?;
+    let blob_manager = open_blob_manager(config, &containers_path, &pool).await?;
     
     let output_dir = std::path::Path::new(output_path);
     let current_dir = output_dir.join("current");
@@ -491,8 +1139,13 @@ async fn export_files(config: &Config, output_path: &str) -> anyhow::Result<()>
     
     fs::create_dir_all(&current_dir)?;
     fs::create_dir_all(&deleted_dir)?;
+
+    let mut manifest = ExportManifest::load(output_dir)?;
     
     println!("exporting files to {}...", output_path);
+    if let Some(since) = since {
+        println!("only files updated after {}", since.to_rfc3339());
+    }
     println!();
     
     // Get ALL files with their current versions (including deleted)
@@ -503,114 +1156,292 @@ async fn export_files(config: &Config, output_path: &str) -> anyhow::Result<()>
         FROM files f
         LEFT JOIN versions v ON f.current_version_id = v.id
         WHERE f.current_version_id IS NOT NULL
+          AND ($1::timestamptz IS NULL OR f.updated_at > $1)
         ORDER BY f.is_deleted, f.path
         "#
     )
+    .bind(since)
     .fetch_all(&pool)
     .await?;
     
     let mut current_count = 0;
     let mut deleted_count = 0;
+    let mut unchanged_count = 0;
     let mut errors = 0;
     
-    // Helper function to read file content (handles both chunked and non-chunked)
-    async fn read_file_content(
+    // Helper function to write file content (handles both chunked and non-chunked).
+    // Chunks are read and written one at a time, so memory stays bounded by
+    // the largest chunk rather than the file size.
+    async fn write_file_content(
         pool: &db::DbPool,
         blob_manager: &storage::BlobManager,
         version_id: uuid::Uuid,
         blob_hash: &str,
         is_chunked: bool,
-    ) -> anyhow::Result<Vec<u8>> {
+        out: &mut fs::File,
+    ) -> anyhow::Result<()> {
+        use std::io::Write;
+
         if is_chunked {
-            // Reassemble from chunks
-            let version_chunks = db::chunks::get_version_chunks(pool, version_id).await?;
-            let mut content = Vec::new();
-            for vc in version_chunks {
-                let chunk_data = blob_manager.read_legacy_blob(&vc.chunk_hash)?;
-                content.extend_from_slice(&chunk_data);
+            // Reassemble from chunks (container-packed or standalone)
+            let version_chunks = db::chunks::get_version_chunks_with_location(pool, version_id).await?;
+            for (_vc, chunk) in version_chunks {
+                let chunk_data = blob_manager.read_chunk(&chunk).await?;
+                out.write_all(&chunk_data)?;
             }
-            Ok(content)
+        } else if blob_manager.legacy_blob_path(blob_hash)?.exists() {
+            // Copy single blob
+            let mut blob = blob_manager.open_legacy_blob(blob_hash)?;
+            std::io::copy(&mut blob, out)?;
         } else {
-            // Read single blob
-            Ok(blob_manager.read_legacy_blob(blob_hash)?)
+            out.write_all(&blob_manager.read_legacy_blob(blob_hash).await?)?;
         }
+        out.sync_all()?;
+        Ok(())
     }
     
-    println!("current files:");
-    for (path, version_id, blob_hash, is_deleted, is_chunked) in &files {
-        if *is_deleted { continue; }
-        
-        let version_id = match version_id {
-            Some(v) => *v,
-            None => continue,
-        };
-        
-        let blob_hash = blob_hash.as_deref().unwrap_or("");
-        
-        let relative_path = path.trim_start_matches('/');
-        let file_path = current_dir.join(relative_path);
-        
-        if let Some(parent) = file_path.parent() {
-            fs::create_dir_all(parent)?;
+    for (section, want_deleted) in [("current files:", false), ("deleted files:", true)] {
+        if want_deleted {
+            println!();
         }
-        
-        match read_file_content(&pool, &blob_manager, version_id, blob_hash, *is_chunked).await {
-            Ok(content) => {
-                fs::write(&file_path, content)?;
-                let chunked_marker = if *is_chunked { " (chunked)" } else { "" };
-                println!("  ✓ {}{}", relative_path, chunked_marker);
-                current_count += 1;
+        println!("{}", section);
+        let (target_dir, other_dir) = if want_deleted {
+            (&deleted_dir, &current_dir)
+        } else {
+            (&current_dir, &deleted_dir)
+        };
+
+        for (path, version_id, blob_hash, is_deleted, is_chunked) in &files {
+            if *is_deleted != want_deleted { continue; }
+            
+            let version_id = match version_id {
+                Some(v) => *v,
+                None => continue,
+            };
+            
+            let blob_hash = blob_hash.as_deref().unwrap_or("");
+            let entry = ManifestEntry {
+                version_id,
+                hash: blob_hash.to_string(),
+                deleted: *is_deleted,
+            };
+            if !manifest.is_stale(path, &entry) {
+                unchanged_count += 1;
+                continue;
             }
-            Err(e) => {
-                println!("  ✗ {} (error: {})", relative_path, e);
-                errors += 1;
+            
+            let relative_path = path.trim_start_matches('/');
+            let file_path = target_dir.join(relative_path);
+            
+            if let Some(parent) = file_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            
+            // Written beside the target and renamed into place, so a failed
+            // export leaves the previous copy rather than a truncated one
+            let partial_path = file_path.with_file_name(format!(
+                ".{}.partial",
+                file_path.file_name().unwrap_or_default().to_string_lossy()
+            ));
+            let mut out = fs::File::create(&partial_path)?;
+            let written = write_file_content(&pool, &blob_manager, version_id, blob_hash, *is_chunked, &mut out).await;
+            drop(out);
+            match written.and_then(|()| Ok(fs::rename(&partial_path, &file_path)?)) {
+                Ok(()) => {
+                    // A file deleted (or restored) since the last export moves sides
+                    if manifest.files.get(path.as_str()).is_some_and(|e| e.deleted != *is_deleted) {
+                        let _ = fs::remove_file(other_dir.join(relative_path));
+                    }
+                    manifest.files.insert(path.clone(), entry);
+                    let chunked_marker = if *is_chunked { " (chunked)" } else { "" };
+                    println!("  ✓ {}{}", relative_path, chunked_marker);
+                    if *is_deleted {
+                        deleted_count += 1;
+                    } else {
+                        current_count += 1;
+                    }
+                }
+                Err(e) => {
+                    let _ = fs::remove_file(&partial_path);
+                    println!("  ✗ {} (error: {})", relative_path, e);
+                    errors += 1;
+                }
             }
         }
     }
-    
-    println!();
-    println!("deleted files:");
-    for (path, version_id, blob_hash, is_deleted, is_chunked) in &files {
-        if !*is_deleted { continue; }
-        
-        let version_id = match version_id {
-            Some(v) => *v,
-            None => continue,
-        };
-        
-        let blob_hash = blob_hash.as_deref().unwrap_or("");
-        
-        let relative_path = path.trim_start_matches('/');
-        let file_path = deleted_dir.join(relative_path);
-        
-        if let Some(parent) = file_path.parent() {
-            fs::create_dir_all(parent)?;
-        }
-        
-        match read_file_content(&pool, &blob_manager, version_id, blob_hash, *is_chunked).await {
-            Ok(content) => {
-                fs::write(&file_path, content)?;
-                let chunked_marker = if *is_chunked { " (chunked)" } else { "" };
-                println!("  ✓ {}{}", relative_path, chunked_marker);
-                deleted_count += 1;
-            }
-            Err(e) => {
-                println!("  ✗ {} (error: {})", relative_path, e);
-                errors += 1;
+
+    // A full export also drops paths that no longer exist on the server
+    let mut removed_count = 0;
+    if since.is_none() {
+        let live: std::collections::HashSet<&str> = files.iter().map(|(path, ..)| path.as_str()).collect();
+        let gone: Vec<String> = manifest
+            .files
+            .keys()
+            .filter(|path| !live.contains(path.as_str()))
+            .cloned()
+            .collect();
+        for path in gone {
+            if let Some(entry) = manifest.files.remove(&path) {
+                let dir = if entry.deleted { &deleted_dir } else { &current_dir };
+                let _ = fs::remove_file(dir.join(path.trim_start_matches('/')));
+                removed_count += 1;
             }
         }
     }
+
+    manifest.exported_at = Some(chrono::Utc::now());
+    manifest.save(output_dir)?;
     
     println!();
     println!("═══════════════════════════════════");
     println!("exported {} current files", current_count);
     println!("exported {} deleted files", deleted_count);
+    println!("skipped {} unchanged files", unchanged_count);
+    if removed_count > 0 {
+        println!("removed {} files no longer on the server", removed_count);
+    }
     if errors > 0 {
         println!("errors: {} (blobs missing)", errors);
     }
     println!();
     println!("current files: {}/current/", output_path);
     println!("deleted files: {}/deleted/", output_path);
+    println!("manifest: {}/{}", output_path, storage::export_manifest::MANIFEST_FILE);
     
     Ok(())
 }
+
+/// Import the objects under `prefix` in `bucket` as files owned by `owner`
+async fn import_from_s3(
+    config: &Config,
+    bucket: &str,
+    prefix: Option<&str>,
+    owner: &str,
+    path_style: bool,
+) -> anyhow::Result<()> {
+    let pool = db::create_pool(&config.database_url).await?;
+    let owner = db::users::get_user_by_username(&pool, owner)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("user not found: {}", owner))?;
+    let containers_path = format!("{}/containers", config.blob_storage_path);
+    let blob_manager = open_blob_manager(config, &containers_path, &pool).await?;
+
+    let source = import_s3::S3Source::connect(bucket, path_style).await;
+    let options = import_s3::ImportOptions {
+        owner_id: owner.id,
+        path_limits: config.path_limits(),
+        case_insensitive: config.case_insensitive_paths,
+        retention: config.version_retention(),
+    };
+    let stats = import_s3::import(&pool, &blob_manager, &source, prefix, options).await?;
+
+    println!();
+    println!("═══════════════════════════════════");
+    println!("imported {} files ({} unchanged)", stats.files_imported, stats.files_skipped);
+    println!(
+        "stored {} chunks ({} already present), downloaded {} bytes",
+        stats.chunks_stored, stats.chunks_deduplicated, stats.bytes_downloaded
+    );
+    if stats.errors > 0 {
+        anyhow::bail!("{} objects failed to import (run again to retry)", stats.errors);
+    }
+
+    Ok(())
+}
+
+/// Convert whole-file versions under `prefix` to chunks
+async fn rechunk_legacy_blobs(config: &Config, prefix: Option<&str>) -> anyhow::Result<()> {
+    let pool = db::create_pool(&config.database_url).await?;
+    let containers_path = format!("{}/containers", config.blob_storage_path);
+    let blob_manager = open_blob_manager(config, &containers_path, &pool).await?;
+
+    let stats = rechunk::rechunk(&pool, &blob_manager, prefix).await?;
+    blob_manager.flush().await?;
+
+    println!();
+    println!("═══════════════════════════════════");
+    println!("rechunked {} versions ({} already done)", stats.versions_converted, stats.versions_skipped);
+    println!(
+        "stored {} chunks ({} already present), removed {} blobs ({} bytes)",
+        stats.chunks_stored, stats.chunks_deduplicated, stats.blobs_removed, stats.bytes_reclaimed
+    );
+    if stats.errors > 0 {
+        println!("errors: {} (run again to retry)", stats.errors);
+    }
+
+    Ok(())
+}
+
+/// Move local blob and container files to the configured shard levels
+async fn reshard_blob_files(config: &Config) -> anyhow::Result<()> {
+    let pool = db::create_pool(&config.database_url).await?;
+    let containers_path = format!("{}/containers", config.blob_storage_path);
+    let blob_manager = open_blob_manager(config, &containers_path, &pool).await?;
+
+    println!("resharding {} into {} levels...", config.blob_storage_path, blob_manager.shard_levels());
+    let stats = reshard::reshard(&pool, &blob_manager).await?;
+
+    println!();
+    println!("═══════════════════════════════════");
+    println!(
+        "moved {} blob files and {} container files ({} already in place)",
+        stats.blobs_moved, stats.containers_moved, stats.already_in_place
+    );
+    if stats.errors > 0 {
+        println!("errors: {} (run again to retry)", stats.errors);
+    }
+
+    Ok(())
+}
+
+/// Replicate this server's files to `target_url`
+async fn replicate_to(config: &Config, target_url: &str, username: &str, owner: Option<&str>) -> anyhow::Result<()> {
+    use std::io::{self, Write};
+
+    let pool = db::create_pool(&config.database_url).await?;
+    let owner_id = match owner {
+        Some(name) => Some(
+            db::users::get_user_by_username(&pool, name)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("user not found: {}", name))?
+                .id,
+        ),
+        None => None,
+    };
+
+    print!("Password for {} on target: ", username);
+    io::stdout().flush()?;
+    let password = tokio::task::spawn_blocking(|| -> anyhow::Result<String> {
+        Ok(rpassword::read_password()?)
+    }).await??;
+
+    let target = match replicate::ReplicationTarget::login(target_url, username, &password, None).await {
+        // The target asks for a second factor once TOTP is enabled
+        Err(e) if e.to_string().contains("TOTP code required") => {
+            print!("TOTP code: ");
+            io::stdout().flush()?;
+            let mut code = String::new();
+            io::stdin().read_line(&mut code)?;
+            replicate::ReplicationTarget::login(target_url, username, &password, Some(code.trim())).await?
+        }
+        result => result?,
+    };
+
+    let containers_path = format!("{}/containers", config.blob_storage_path);
+    let blob_manager = open_blob_manager(config, &containers_path, &pool).await?;
+
+    println!("replicating to {}...", target.base_url());
+    println!();
+    let stats = replicate::replicate(&pool, &blob_manager, &target, owner_id).await?;
+
+    println!();
+    println!("═══════════════════════════════════");
+    println!("pushed {} files ({} unchanged)", stats.files_pushed, stats.files_skipped);
+    println!("created {} folders and {} links", stats.directories_created, stats.links_created);
+    println!("uploaded {} chunks ({} bytes)", stats.chunks_uploaded, stats.bytes_uploaded);
+    if stats.errors > 0 {
+        println!("errors: {} (run again to retry)", stats.errors);
+    }
+
+    Ok(())
+}
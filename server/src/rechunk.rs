@@ -0,0 +1,289 @@
+//! Convert whole-file legacy blobs to chunks (`tangled rechunk`)
+//!
+//! Files added with `tangled index` or the legacy blob upload API are stored
+//! as one blob per version, so content shared between files is stored once
+//! per file. Rechunking reads each such version, splits it with the same
+//! content-defined chunker uploads use, stores the chunks not already stored
+//! and rewrites the version in place as chunked: its id, file and hash stay
+//! the same. A blob no whole-file version needs any more is then deleted.
+//!
+//! Each version is converted in its own transaction, so an interrupted run
+//! can simply be started again.
+
+use crate::db::chunks::{self, ChunkInfo};
+use crate::db::versions::{self, LegacyVersion};
+use crate::db::{ChunkTier, DbPool};
+use crate::storage::chunking::StreamChunker;
+use crate::storage::tiering::{streaming_tier, DefaultTierStrategy, TierStrategy};
+use crate::storage::{store_chunk, BlobManager};
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashMap;
+use std::io::Read;
+
+/// Bytes read from a blob at a time
+const READ_BUFFER_BYTES: usize = 1024 * 1024;
+
+/// Counters reported at the end of a run
+#[derive(Debug, Default)]
+pub struct RechunkStats {
+    pub versions_converted: usize,
+    pub versions_skipped: usize,
+    pub chunks_stored: usize,
+    pub chunks_deduplicated: usize,
+    pub blobs_removed: usize,
+    pub bytes_reclaimed: u64,
+    pub errors: usize,
+}
+
+/// Rechunk every whole-file version at or under `prefix` (everything when None)
+pub async fn rechunk(pool: &DbPool, blob_manager: &BlobManager, prefix: Option<&str>) -> Result<RechunkStats> {
+    let legacy = versions::list_legacy_versions(pool, prefix).await?;
+    let total = legacy.len();
+    println!("{} whole-file versions", total);
+
+    // Versions of the same content chunked with the same tier share a manifest
+    let mut manifests: HashMap<(String, ChunkTier), Vec<ChunkInfo>> = HashMap::new();
+    let mut stats = RechunkStats::default();
+    for (index, version) in legacy.iter().enumerate() {
+        let progress = format!("[{}/{}]", index + 1, total);
+        match rechunk_version(pool, blob_manager, version, &mut manifests, &mut stats).await {
+            Ok(Some(chunk_count)) => {
+                println!("  ✓ {} {} ({} chunks, {} bytes)", progress, version.path, chunk_count, version.size_bytes);
+                stats.versions_converted += 1;
+            }
+            Ok(None) => {
+                println!("  = {} {} (already chunked)", progress, version.path);
+                stats.versions_skipped += 1;
+            }
+            Err(e) => {
+                println!("  ✗ {} {} (error: {:#})", progress, version.path, e);
+                stats.errors += 1;
+            }
+        }
+    }
+
+    Ok(stats)
+}
+
+/// Convert one version, then drop its blob if nothing else needs it.
+/// Returns the chunk count, or None if another run converted it first.
+async fn rechunk_version(
+    pool: &DbPool,
+    blob_manager: &BlobManager,
+    version: &LegacyVersion,
+    manifests: &mut HashMap<(String, ChunkTier), Vec<ChunkInfo>>,
+    stats: &mut RechunkStats,
+) -> Result<Option<usize>> {
+    let tier = rechunk_tier(version);
+    let key = (version.blob_hash.clone(), tier);
+    if !manifests.contains_key(&key) {
+        let chunk_infos = chunk_blob(pool, blob_manager, &version.blob_hash, tier, stats).await?;
+        manifests.insert(key.clone(), chunk_infos);
+    }
+    let chunk_infos = &manifests[&key];
+
+    if !chunks::convert_to_chunked(pool, version.id, tier, chunk_infos).await? {
+        return Ok(None);
+    }
+
+    if !chunks::legacy_blob_in_use(pool, &version.blob_hash).await?
+        && blob_manager.delete_legacy_blob(&version.blob_hash).await?
+    {
+        stats.blobs_removed += 1;
+        stats.bytes_reclaimed += version.size_bytes as u64;
+    }
+
+    Ok(Some(chunk_infos.len()))
+}
+
+/// The tier uploads would pick for the version, clamped to one the server
+/// can stream (see [`streaming_tier`])
+fn rechunk_tier(version: &LegacyVersion) -> ChunkTier {
+    let tier = DefaultTierStrategy::determine_tier(std::path::Path::new(&version.path), version.size_bytes as u64);
+    streaming_tier(tier.into())
+}
+
+/// Split a legacy blob into stored chunks. The blob is hashed first, so
+/// nothing is stored for one whose content doesn't match its name (damaged,
+/// or named by SHA-256 before the switch to BLAKE3).
+async fn chunk_blob(
+    pool: &DbPool,
+    blob_manager: &BlobManager,
+    hash: &str,
+    tier: ChunkTier,
+    stats: &mut RechunkStats,
+) -> Result<Vec<ChunkInfo>> {
+    let mut buffer = vec![0u8; READ_BUFFER_BYTES];
+
+    let mut hasher = blake3::Hasher::new();
+    let mut blob = open_blob(blob_manager, hash).await?;
+    loop {
+        let n = blob.read(&mut buffer).context("Failed to read blob")?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    if hasher.finalize().to_hex().as_str() != hash {
+        return Err(anyhow!("blob content doesn't match its BLAKE3 hash; left as it is"));
+    }
+
+    let mut chunker = StreamChunker::new(tier.into());
+    let mut chunk_infos = Vec::new();
+    let mut blob = open_blob(blob_manager, hash).await?;
+    loop {
+        let n = blob.read(&mut buffer).context("Failed to read blob")?;
+        if n == 0 {
+            break;
+        }
+        for chunk in chunker.push(&buffer[..n]) {
+            store_rechunked_chunk(pool, blob_manager, tier, &chunk, &mut chunk_infos, stats).await?;
+        }
+    }
+    let (rest, _) = chunker.finish();
+    for chunk in rest {
+        store_rechunked_chunk(pool, blob_manager, tier, &chunk, &mut chunk_infos, stats).await?;
+    }
+
+    Ok(chunk_infos)
+}
+
+/// The local blob file, or the blob read into memory from the remote backend
+async fn open_blob(blob_manager: &BlobManager, hash: &str) -> Result<Box<dyn Read + Send>> {
+    match blob_manager.open_legacy_blob(hash) {
+        Ok(file) => Ok(Box::new(file)),
+        Err(_) => Ok(Box::new(std::io::Cursor::new(blob_manager.read_legacy_blob(hash).await?))),
+    }
+}
+
+/// Store one chunk (unless already stored) and append it to the manifest
+async fn store_rechunked_chunk(
+    pool: &DbPool,
+    blob_manager: &BlobManager,
+    tier: ChunkTier,
+    data: &[u8],
+    chunk_infos: &mut Vec<ChunkInfo>,
+    stats: &mut RechunkStats,
+) -> Result<()> {
+    let hash = blake3::hash(data).to_hex().to_string();
+    if chunks::chunk_exists(pool, &hash).await? {
+        stats.chunks_deduplicated += 1;
+    } else {
+        store_chunk(blob_manager, pool, &hash, data, tier)
+            .await
+            .context("Failed to store chunk")?;
+        stats.chunks_stored += 1;
+    }
+
+    let offset_in_file = chunk_infos
+        .last()
+        .map_or(0, |c| c.offset_in_file + c.size_bytes as i64);
+    chunk_infos.push(ChunkInfo {
+        hash,
+        size_bytes: data.len() as i32,
+        offset_in_file,
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{self, files};
+
+    /// Bytes FastCDC finds content-defined cuts in, deterministically
+    fn noise(seed: &[u8], len: usize) -> Vec<u8> {
+        let mut data = vec![0u8; len];
+        blake3::Hasher::new().update(seed).finalize_xof().fill(&mut data);
+        data
+    }
+
+    /// Record `data` at `path` as a whole-file version, as `tangled index` does
+    async fn add_legacy_version(pool: &DbPool, blob_manager: &BlobManager, path: &str, data: &[u8]) -> uuid::Uuid {
+        let hash = blake3::hash(data).to_hex().to_string();
+        blob_manager.write_legacy_blob(&hash, data).await.unwrap();
+        let file = files::upsert_file_global(pool, path).await.unwrap();
+        let version = versions::create_version_global(pool, file.id, &hash, data.len() as i64).await.unwrap();
+        files::set_current_version(pool, file.id, version.id).await.unwrap();
+        version.id
+    }
+
+    /// Content of a chunked version, read back chunk by chunk
+    async fn read_chunked(pool: &DbPool, blob_manager: &BlobManager, version_id: uuid::Uuid) -> (Vec<u8>, usize) {
+        let chunks = chunks::get_version_chunks_with_location(pool, version_id).await.unwrap();
+        let mut data = Vec::new();
+        for (_, chunk) in &chunks {
+            data.extend(blob_manager.read_chunk(chunk).await.unwrap());
+        }
+        (data, chunks.len())
+    }
+
+    #[tokio::test]
+    async fn test_rechunk_large_disk_image() {
+        let Some(pool) = db::test_pool().await else { return };
+        let dir = tempfile::tempdir().unwrap();
+        let blob_manager = BlobManager::new(dir.path(), pool.clone()).unwrap();
+
+        // Disk images pick Jumbo, whose 4MB minimum FastCDC can't stream
+        let data = noise(b"iso", 5 * 1024 * 1024);
+        let version_id = add_legacy_version(&pool, &blob_manager, "/vm/disk.iso", &data).await;
+
+        let stats = rechunk(&pool, &blob_manager, None).await.unwrap();
+        assert_eq!((stats.versions_converted, stats.errors, stats.blobs_removed), (1, 0, 1));
+
+        let (content, chunk_count) = read_chunked(&pool, &blob_manager, version_id).await;
+        assert!(chunk_count > 1);
+        assert_eq!(content, data);
+        let (tier,): (i16,) = sqlx::query_as("SELECT tier_id FROM versions WHERE id = $1")
+            .bind(version_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(ChunkTier::from_i16(tier), Some(ChunkTier::Large));
+    }
+
+    #[tokio::test]
+    async fn test_rechunk_mixed_versions() {
+        let Some(pool) = db::test_pool().await else { return };
+        let dir = tempfile::tempdir().unwrap();
+        let blob_manager = BlobManager::new(dir.path(), pool.clone()).unwrap();
+
+        // One file: an indexed whole-file version, then a chunked upload
+        let old = noise(b"old", 100 * 1024);
+        let legacy_id = add_legacy_version(&pool, &blob_manager, "/docs/report.bin", &old).await;
+        let new = noise(b"new", 100 * 1024);
+        store_chunk(&blob_manager, &pool, &blake3::hash(&new).to_hex(), &new, ChunkTier::Granular)
+            .await
+            .unwrap();
+        let file = files::upsert_file_global(&pool, "/docs/report.bin").await.unwrap();
+        let mut tx = pool.begin().await.unwrap();
+        let (chunked_id, _) = chunks::create_version_with_tier(
+            &mut tx,
+            file.id,
+            blake3::hash(&new).to_hex().as_str(),
+            new.len() as i64,
+            ChunkTier::Granular,
+            &[ChunkInfo { hash: blake3::hash(&new).to_hex().to_string(), size_bytes: new.len() as i32, offset_in_file: 0 }],
+            None,
+            Default::default(),
+        )
+        .await
+        .unwrap();
+        tx.commit().await.unwrap();
+
+        let legacy = versions::list_legacy_versions(&pool, Some("/docs")).await.unwrap();
+        assert_eq!(legacy.iter().map(|v| v.id).collect::<Vec<_>>(), vec![legacy_id]);
+        assert!(versions::list_legacy_versions(&pool, Some("/other")).await.unwrap().is_empty());
+
+        let stats = rechunk(&pool, &blob_manager, Some("/docs")).await.unwrap();
+        assert_eq!((stats.versions_converted, stats.versions_skipped, stats.errors), (1, 0, 0));
+        assert!(versions::list_legacy_versions(&pool, None).await.unwrap().is_empty());
+
+        assert_eq!(read_chunked(&pool, &blob_manager, legacy_id).await.0, old);
+        assert_eq!(read_chunked(&pool, &blob_manager, chunked_id).await, (new, 1));
+
+        // Nothing left to do on a second run
+        let stats = rechunk(&pool, &blob_manager, None).await.unwrap();
+        assert_eq!(stats.versions_converted + stats.versions_skipped + stats.errors, 0);
+    }
+}
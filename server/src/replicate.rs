@@ -0,0 +1,452 @@
+//! Server-to-server replication (`tangled replicate`)
+//!
+//! Pushes this server's live files to another Entanglement instance through
+//! its public v1 API, the same way a sync client uploads: chunks the target
+//! is missing go up with `PUT /v1/chunks/:hash`, then each version is recorded
+//! with `POST /v1/files`, along with its permission bits and, for
+//! client-encrypted content, its key data. Paths that already hold the same content hash on the
+//! target are skipped and chunk storage is content-addressed, so an
+//! interrupted run can simply be started again.
+//!
+//! Replication is additive: files deleted here are not deleted on the target.
+
+use crate::db::{chunks, DbPool};
+use crate::storage::{self, BlobManager};
+use anyhow::{anyhow, Context, Result};
+use base64::prelude::{Engine as _, BASE64_STANDARD};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+/// Page size used when reading the target's change feed
+const CHANGES_PAGE: usize = 1000;
+
+/// Hashes sent per `/v1/chunks/check` request
+const CHECK_BATCH: usize = 1000;
+
+/// Counters reported at the end of a run
+#[derive(Debug, Default)]
+pub struct ReplicationStats {
+    pub files_pushed: usize,
+    pub files_skipped: usize,
+    pub directories_created: usize,
+    pub links_created: usize,
+    pub chunks_uploaded: usize,
+    pub bytes_uploaded: u64,
+    pub errors: usize,
+}
+
+// ============================================================================
+// TARGET CLIENT
+// ============================================================================
+
+#[derive(Serialize)]
+struct LoginRequest<'a> {
+    username: &'a str,
+    password: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    totp_code: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct LoginResponse {
+    token: String,
+}
+
+#[derive(Deserialize)]
+struct ChangesPage {
+    changes: Vec<RemoteChange>,
+    next_cursor: Option<String>,
+    has_more: bool,
+}
+
+#[derive(Deserialize)]
+struct RemoteChange {
+    path: String,
+    action: String,
+    blob_hash: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CheckChunksResponse {
+    missing: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct CreateFileRequest<'a> {
+    path: &'a str,
+    size_bytes: i64,
+    modified_at: String,
+    tier_id: i16,
+    content_hash: &'a str,
+    chunk_hashes: &'a [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unix_mode: Option<i32>,
+    /// Base64 key data of client-encrypted content
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encryption_meta: Option<String>,
+}
+
+/// Authenticated connection to the server being replicated to
+pub struct ReplicationTarget {
+    client: reqwest::Client,
+    base_url: String,
+    token: String,
+}
+
+impl ReplicationTarget {
+    /// Log in to the target; `totp_code` is required if the account has TOTP enabled
+    pub async fn login(url: &str, username: &str, password: &str, totp_code: Option<&str>) -> Result<Self> {
+        let base_url = normalize_base_url(url)?;
+        let client = reqwest::Client::new();
+        let resp = client
+            .post(format!("{}/auth/login", base_url))
+            .json(&LoginRequest { username, password, totp_code })
+            .send()
+            .await
+            .with_context(|| format!("Failed to connect to {}", base_url))?;
+        let login: LoginResponse = ensure_ok(resp).await?.json().await?;
+        Ok(Self { client, base_url, token: login.token })
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Live paths on the target mapped to their current content hash
+    /// (`None` for directories and links)
+    async fn remote_files(&self) -> Result<HashMap<String, Option<String>>> {
+        let mut files = HashMap::new();
+        let mut after: Option<String> = None;
+        loop {
+            let mut query = vec![("limit", CHANGES_PAGE.to_string())];
+            if let Some(cursor) = &after {
+                query.push(("after", cursor.clone()));
+            }
+            let resp = self
+                .client
+                .get(format!("{}/v1/files/changes", self.base_url))
+                .bearer_auth(&self.token)
+                .query(&query)
+                .send()
+                .await?;
+            let page: ChangesPage = ensure_ok(resp).await?.json().await?;
+
+            for change in page.changes {
+                if change.action == "deleted" {
+                    files.remove(&change.path);
+                } else {
+                    files.insert(change.path, change.blob_hash);
+                }
+            }
+            if !page.has_more || page.next_cursor.is_none() {
+                return Ok(files);
+            }
+            after = page.next_cursor;
+        }
+    }
+
+    /// Which of `hashes` the target does not have yet
+    async fn missing_chunks(&self, hashes: &[String]) -> Result<HashSet<String>> {
+        let mut missing = HashSet::new();
+        for batch in hashes.chunks(CHECK_BATCH) {
+            let resp = self
+                .client
+                .post(format!("{}/v1/chunks/check", self.base_url))
+                .bearer_auth(&self.token)
+                .json(&serde_json::json!({ "hashes": batch }))
+                .send()
+                .await?;
+            let check: CheckChunksResponse = ensure_ok(resp).await?.json().await?;
+            missing.extend(check.missing);
+        }
+        Ok(missing)
+    }
+
+    async fn put_chunk(&self, hash: &str, data: Vec<u8>) -> Result<()> {
+        let resp = self
+            .client
+            .put(format!("{}/v1/chunks/{}", self.base_url, hash))
+            .bearer_auth(&self.token)
+            .body(data)
+            .send()
+            .await?;
+        ensure_ok(resp).await?;
+        Ok(())
+    }
+
+    async fn create_file(&self, req: &CreateFileRequest<'_>) -> Result<()> {
+        let resp = self
+            .client
+            .post(format!("{}/v1/files", self.base_url))
+            .bearer_auth(&self.token)
+            .json(req)
+            .send()
+            .await?;
+        ensure_ok(resp).await?;
+        Ok(())
+    }
+
+    async fn create_directory(&self, path: &str) -> Result<()> {
+        let resp = self
+            .client
+            .post(format!("{}/v1/files/directory", self.base_url))
+            .bearer_auth(&self.token)
+            .json(&serde_json::json!({ "path": path }))
+            .send()
+            .await?;
+        ensure_ok(resp).await?;
+        Ok(())
+    }
+
+    async fn create_link(&self, path: &str, target_path: &str) -> Result<()> {
+        let resp = self
+            .client
+            .post(format!("{}/v1/files/link", self.base_url))
+            .bearer_auth(&self.token)
+            .json(&serde_json::json!({ "path": path, "target_path": target_path }))
+            .send()
+            .await?;
+        ensure_ok(resp).await?;
+        Ok(())
+    }
+}
+
+async fn ensure_ok(resp: reqwest::Response) -> Result<reqwest::Response> {
+    if resp.status().is_success() {
+        Ok(resp)
+    } else {
+        let status = resp.status();
+        let body = resp.text().await.unwrap_or_default();
+        Err(anyhow!("Target returned {}: {}", status, body))
+    }
+}
+
+/// Validate a target URL and strip any trailing slash
+pub fn normalize_base_url(url: &str) -> Result<String> {
+    let url = url.trim().trim_end_matches('/');
+    if !(url.starts_with("http://") || url.starts_with("https://")) {
+        anyhow::bail!("Target URL must start with http:// or https://: {}", url);
+    }
+    Ok(url.to_string())
+}
+
+// ============================================================================
+// REPLICATION
+// ============================================================================
+
+/// A live file on this server with its current version
+#[derive(sqlx::FromRow)]
+struct SourceFile {
+    path: String,
+    updated_at: chrono::DateTime<chrono::Utc>,
+    version_id: Option<Uuid>,
+    blob_hash: Option<String>,
+    blake3_hash: Option<String>,
+    size_bytes: Option<i64>,
+    tier_id: Option<i16>,
+    is_chunked: Option<bool>,
+    /// Path of the file this entry links to, if it is a link
+    link_target: Option<String>,
+    unix_mode: Option<i32>,
+    /// Key data the content can't be decrypted without, if client-encrypted
+    encryption_meta: Option<Vec<u8>>,
+}
+
+impl SourceFile {
+    /// Hash the target reports for this content, compared to skip unchanged files
+    fn content_hash(&self) -> Option<&str> {
+        self.blake3_hash.as_deref().or(self.blob_hash.as_deref())
+    }
+}
+
+/// Whether `path` with `hash` still has to be pushed to the target
+fn needs_push(remote: &HashMap<String, Option<String>>, path: &str, hash: &str) -> bool {
+    remote.get(path).and_then(|h| h.as_deref()) != Some(hash)
+}
+
+/// Push every live file, directory and link on this server to `target`
+///
+/// The target account gets one namespace, so with `owner` only that user's
+/// files (and ownerless shared ones) are pushed. Without it the server must
+/// not hold more than one user's files.
+pub async fn replicate(
+    pool: &DbPool,
+    blob_manager: &BlobManager,
+    target: &ReplicationTarget,
+    owner: Option<Uuid>,
+) -> Result<ReplicationStats> {
+    if owner.is_none() {
+        let (owners,): (i64,) = sqlx::query_as(
+            "SELECT COUNT(DISTINCT owner_id) FROM files WHERE is_deleted = FALSE AND owner_id IS NOT NULL",
+        )
+        .fetch_one(pool)
+        .await?;
+        if owners > 1 {
+            anyhow::bail!(
+                "Files here belong to {} users and would all land in one target account; pass --owner to replicate one user's files",
+                owners
+            );
+        }
+    }
+
+    let sources = sqlx::query_as::<_, SourceFile>(
+        r#"
+        SELECT f.path, f.updated_at, f.current_version_id AS version_id,
+               v.blob_hash, v.blake3_hash, v.size_bytes, v.tier_id, v.is_chunked,
+               t.path AS link_target, f.unix_mode, v.encryption_meta
+        FROM files f
+        LEFT JOIN versions v ON v.id = f.current_version_id
+        LEFT JOIN files t ON t.id = f.link_target_id AND t.is_deleted = FALSE
+        WHERE f.is_deleted = FALSE
+          AND ($1::uuid IS NULL OR f.owner_id = $1 OR f.owner_id IS NULL)
+        ORDER BY f.path
+        "#,
+    )
+    .bind(owner)
+    .fetch_all(pool)
+    .await?;
+
+    println!("reading file list from {}...", target.base_url());
+    let remote = target.remote_files().await?;
+    let mut stats = ReplicationStats::default();
+
+    // Directories first, then files, then links (whose targets must exist)
+    for file in sources.iter().filter(|f| f.path.ends_with('/')) {
+        if remote.contains_key(&file.path) {
+            continue;
+        }
+        match target.create_directory(&file.path).await {
+            Ok(()) => stats.directories_created += 1,
+            Err(e) => {
+                println!("  ✗ {} (error: {})", file.path, e);
+                stats.errors += 1;
+            }
+        }
+    }
+
+    for file in sources.iter().filter(|f| !f.path.ends_with('/') && f.link_target.is_none()) {
+        let Some(hash) = file.content_hash() else {
+            continue;
+        };
+        if !needs_push(&remote, &file.path, hash) {
+            stats.files_skipped += 1;
+            continue;
+        }
+        match push_file(pool, blob_manager, target, file, hash, &mut stats).await {
+            Ok((chunk_count, bytes)) => {
+                println!("  ✓ {} ({} chunks, {} bytes sent)", file.path, chunk_count, bytes);
+                stats.files_pushed += 1;
+            }
+            Err(e) => {
+                println!("  ✗ {} (error: {})", file.path, e);
+                stats.errors += 1;
+            }
+        }
+    }
+
+    for file in &sources {
+        let Some(link_target) = &file.link_target else {
+            continue;
+        };
+        if remote.contains_key(&file.path) {
+            continue;
+        }
+        match target.create_link(&file.path, link_target).await {
+            Ok(()) => stats.links_created += 1,
+            Err(e) => {
+                println!("  ✗ {} (error: {})", file.path, e);
+                stats.errors += 1;
+            }
+        }
+    }
+
+    Ok(stats)
+}
+
+/// Upload the chunks of one file the target is missing, then record the
+/// version. Returns the chunk count and bytes sent.
+async fn push_file(
+    pool: &DbPool,
+    blob_manager: &BlobManager,
+    target: &ReplicationTarget,
+    file: &SourceFile,
+    content_hash: &str,
+    stats: &mut ReplicationStats,
+) -> Result<(usize, u64)> {
+    let version_id = file.version_id.ok_or_else(|| anyhow!("No current version"))?;
+    let mut sent = 0u64;
+
+    let chunk_hashes: Vec<String> = if file.is_chunked.unwrap_or(false) {
+        let chunk_list = chunks::get_version_chunks_with_location(pool, version_id).await?;
+        let hashes: Vec<String> = chunk_list.iter().map(|(_vc, chunk)| chunk.hash.clone()).collect();
+        let mut missing = target.missing_chunks(&hashes).await?;
+        for (_vc, chunk) in &chunk_list {
+            // remove() so a chunk repeated within the file is only sent once
+            if missing.remove(&chunk.hash) {
+                let data = blob_manager.read_chunk(chunk).await?;
+                sent += data.len() as u64;
+                target.put_chunk(&chunk.hash, data).await?;
+                stats.chunks_uploaded += 1;
+            }
+        }
+        hashes
+    } else {
+        // Legacy single blob, stored under its blob hash: split it the way a
+        // client would before upload
+        let blob_hash = file.blob_hash.as_deref().ok_or_else(|| anyhow!("No blob hash"))?;
+        let data = blob_manager.read_legacy_blob(blob_hash).await?;
+        let manifest = storage::chunk_data(&data)?;
+        let hashes: Vec<String> = manifest.chunks.iter().map(|c| c.hash_hex()).collect();
+        let mut missing = target.missing_chunks(&hashes).await?;
+        for (chunk, hash) in manifest.chunks.iter().zip(&hashes) {
+            if missing.remove(hash) {
+                let start = chunk.offset as usize;
+                let piece = data[start..start + chunk.length as usize].to_vec();
+                sent += piece.len() as u64;
+                target.put_chunk(hash, piece).await?;
+                stats.chunks_uploaded += 1;
+            }
+        }
+        hashes
+    };
+    stats.bytes_uploaded += sent;
+
+    target
+        .create_file(&CreateFileRequest {
+            path: &file.path,
+            size_bytes: file.size_bytes.unwrap_or(0),
+            modified_at: file.updated_at.to_rfc3339(),
+            tier_id: file.tier_id.unwrap_or(2),
+            content_hash,
+            chunk_hashes: &chunk_hashes,
+            unix_mode: file.unix_mode,
+            encryption_meta: file.encryption_meta.as_ref().map(|meta| BASE64_STANDARD.encode(meta)),
+        })
+        .await?;
+
+    Ok((chunk_hashes.len(), sent))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_base_url() {
+        assert_eq!(normalize_base_url("https://backup.example.com/").unwrap(), "https://backup.example.com");
+        assert_eq!(normalize_base_url(" http://10.0.0.2:1975 ").unwrap(), "http://10.0.0.2:1975");
+        assert!(normalize_base_url("backup.example.com").is_err());
+    }
+
+    #[test]
+    fn test_needs_push() {
+        let mut remote = HashMap::new();
+        remote.insert("/a.txt".to_string(), Some("abc".to_string()));
+        remote.insert("/docs/".to_string(), None);
+
+        assert!(!needs_push(&remote, "/a.txt", "abc"));
+        assert!(needs_push(&remote, "/a.txt", "def"));
+        assert!(needs_push(&remote, "/b.txt", "abc"));
+    }
+}
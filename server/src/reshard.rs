@@ -0,0 +1,73 @@
+//! Move blob files to the configured shard layout (`tangled reshard`)
+//!
+//! `BLOB_SHARD_LEVELS` only decides where new files go. Legacy blobs and
+//! standalone chunks written under another setting are still found, since
+//! reads try every shard level, and containers are opened by the path
+//! recorded for them. Resharding moves both into the configured layout so
+//! directories stay small and lookups hit on the first try.
+//!
+//! Files are hard-linked to their new name before the old one is removed,
+//! so it is safe to run while the server is up, and an interrupted run can
+//! simply be started again.
+
+use crate::db::{containers, DbPool};
+use crate::storage::blob_io::MAX_SHARD_LEVELS;
+use crate::storage::BlobManager;
+use anyhow::Result;
+
+/// Counters reported at the end of a run
+#[derive(Debug, Default)]
+pub struct ReshardStats {
+    pub blobs_moved: usize,
+    pub containers_moved: usize,
+    pub already_in_place: usize,
+    pub errors: usize,
+}
+
+/// Move every local blob file and container file to the configured shard levels
+pub async fn reshard(pool: &DbPool, blob_manager: &BlobManager) -> Result<ReshardStats> {
+    let mut stats = ReshardStats::default();
+
+    // Blob files sit at most MAX_SHARD_LEVELS directories down, beside the
+    // containers directory
+    let containers_dir = blob_manager.base_path();
+    let blobs = walkdir::WalkDir::new(blob_manager.legacy_base()?)
+        .min_depth(1)
+        .max_depth(MAX_SHARD_LEVELS as usize + 1)
+        .into_iter()
+        .filter_entry(|e| e.path() != containers_dir)
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file() && is_blob_name(&e.file_name().to_string_lossy()));
+    for entry in blobs {
+        let hash = entry.file_name().to_string_lossy().to_string();
+        match blob_manager.reshard_legacy_blob(entry.path(), &hash) {
+            Ok(true) => stats.blobs_moved += 1,
+            Ok(false) => stats.already_in_place += 1,
+            Err(e) => {
+                println!("  ✗ blob {} (error: {:#})", hash, e);
+                stats.errors += 1;
+            }
+        }
+    }
+    println!("{} blob files moved", stats.blobs_moved);
+
+    for container in containers::list_containers(pool, true).await? {
+        match blob_manager.reshard_container(&container).await {
+            Ok(true) => stats.containers_moved += 1,
+            Ok(false) => stats.already_in_place += 1,
+            Err(e) => {
+                println!("  ✗ container {} (error: {:#})", container.id, e);
+                stats.errors += 1;
+            }
+        }
+    }
+    println!("{} container files moved", stats.containers_moved);
+
+    Ok(stats)
+}
+
+/// Blob files are named by their 64-character hex hash; anything else
+/// (temp files mid-write, stray files) is left alone
+fn is_blob_name(name: &str) -> bool {
+    name.len() == 64 && name.bytes().all(|b| b.is_ascii_hexdigit())
+}
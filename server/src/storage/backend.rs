@@ -0,0 +1,318 @@
+//! Pluggable storage backends
+//!
+//! [`BlobManager`](super::BlobManager) compresses and encrypts chunks, then
+//! hands the stored bytes to a [`BlobBackend`]:
+//!
+//! - [`LocalBackend`](super::blob_io::LocalBackend): containers and
+//!   standalone blob files under `BLOB_STORAGE_PATH` (the default)
+//! - [`S3Backend`]: one object per chunk in an S3-compatible bucket
+//!
+//! `STORAGE_BACKEND` picks where new chunks and legacy blobs are written.
+//! Every chunk row records the backend holding it, so switching doesn't move
+//! existing data: local storage stays readable, and S3 chunks stay readable
+//! while `S3_BUCKET` is set.
+//!
+//! ## S3 layout
+//!
+//! ```text
+//! {S3_PREFIX}chunks/<shard>/<hash>   header (8 bytes) + stored chunk
+//! {S3_PREFIX}blobs/<shard>/<hash>    legacy whole-file blob, as uploaded
+//! ```
+//!
+//! `<shard>` is the first two hex characters of the hash. The
+//! chunk header is magic "ENTO", version 0x01, a flags byte (bit 0 zstd
+//! compressed, bit 1 encrypted) and two reserved bytes.
+
+use crate::db::{self, StorageBackend};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use aws_config::meta::region::RegionProviderChain;
+use aws_sdk_s3::config::Region;
+use aws_sdk_s3::error::DisplayErrorContext;
+use aws_sdk_s3::primitives::ByteStream;
+use uuid::Uuid;
+
+/// Region used when the environment names none
+const DEFAULT_REGION: &str = "us-east-1";
+
+const OBJECT_MAGIC: &[u8; 4] = b"ENTO";
+const OBJECT_FORMAT_VERSION: u8 = 0x01;
+const OBJECT_HEADER_SIZE: usize = 8;
+const FLAG_COMPRESSED: u8 = 0x01;
+const FLAG_ENCRYPTED: u8 = 0x02;
+
+/// A chunk as a backend holds it: compressed and/or sealed already
+#[derive(Debug, Clone)]
+pub struct StoredChunk {
+    pub data: Vec<u8>,
+    /// zstd-compressed (inside the envelope, for encrypted chunks)
+    pub compressed: bool,
+    /// Sealed by [`ChunkCipher`](super::encryption::ChunkCipher)
+    pub encrypted: bool,
+}
+
+/// Where a backend put a chunk, as recorded in `chunks`
+#[derive(Debug, Clone, Copy)]
+pub struct StoredLocation {
+    pub container_id: Option<Uuid>,
+    pub offset: Option<i64>,
+    /// Stored length, which differs from the chunk size once compressed or sealed
+    pub length: i32,
+}
+
+/// Kinds of standalone object a backend keeps by hash
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectKind {
+    /// A chunk stored outside a container
+    Chunk,
+    /// A whole-file blob from the legacy upload API
+    LegacyBlob,
+}
+
+/// Physical storage for chunks and legacy blobs
+#[async_trait]
+pub trait BlobBackend: Send + Sync {
+    /// Value recorded in `chunks.backend` for chunks stored here
+    fn kind(&self) -> StorageBackend;
+
+    /// Store a chunk and return its location
+    async fn store_chunk(&self, hash: &str, chunk: &StoredChunk) -> Result<StoredLocation>;
+
+    /// Read back a chunk recorded as stored in this backend
+    async fn read_chunk(&self, chunk: &db::Chunk) -> Result<StoredChunk>;
+
+    async fn read_legacy_blob(&self, hash: &str) -> Result<Vec<u8>>;
+
+    async fn write_legacy_blob(&self, hash: &str, content: &[u8]) -> Result<()>;
+
+    /// Whether a standalone object of `kind` is stored for `hash`. Chunks in
+    /// containers aren't standalone objects.
+    async fn exists(&self, kind: ObjectKind, hash: &str) -> Result<bool>;
+
+    /// Length in bytes of the standalone object of `kind` for `hash`, or
+    /// None if there is none. For S3 chunk objects this includes the header.
+    async fn size(&self, kind: ObjectKind, hash: &str) -> Result<Option<u64>>;
+
+    /// Remove the standalone object of `kind` for `hash`. Returns false if
+    /// there was none.
+    async fn delete(&self, kind: ObjectKind, hash: &str) -> Result<bool>;
+}
+
+/// Shard directory for a hash (its first two characters)
+pub(crate) fn shard(hash: &str) -> Result<&str> {
+    if hash.len() < 4 || !hash.is_ascii() {
+        return Err(anyhow!("Invalid hash format: {}", hash));
+    }
+    Ok(&hash[..2])
+}
+
+/// S3 client with credentials, region and endpoint from the standard AWS
+/// environment, as for the AWS CLI. `path_style` addresses buckets as
+/// `endpoint/bucket`, which MinIO and most self-hosted S3-compatible servers
+/// need.
+pub async fn s3_client(path_style: bool) -> aws_sdk_s3::Client {
+    let region = RegionProviderChain::default_provider().or_else(Region::new(DEFAULT_REGION));
+    let shared = aws_config::from_env().region(region).load().await;
+    let config = aws_sdk_s3::config::Builder::from(&shared)
+        .force_path_style(path_style)
+        .build();
+    aws_sdk_s3::Client::from_conf(config)
+}
+
+// ============================================================================
+// S3
+// ============================================================================
+
+/// Chunks and legacy blobs as objects in an S3-compatible bucket
+pub struct S3Backend {
+    client: aws_sdk_s3::Client,
+    bucket: String,
+    prefix: String,
+}
+
+impl S3Backend {
+    /// Connect to `bucket`, keeping every object under `prefix`
+    pub async fn connect(bucket: &str, prefix: &str, path_style: bool) -> Self {
+        Self {
+            client: s3_client(path_style).await,
+            bucket: bucket.to_string(),
+            prefix: prefix.to_string(),
+        }
+    }
+
+    fn key(&self, kind: ObjectKind, hash: &str) -> Result<String> {
+        let dir = match kind {
+            ObjectKind::Chunk => "chunks",
+            ObjectKind::LegacyBlob => "blobs",
+        };
+        Ok(format!("{}{}/{}/{}", self.prefix, dir, shard(hash)?, hash))
+    }
+
+    async fn put(&self, key: &str, body: Vec<u8>) -> Result<()> {
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .body(ByteStream::from(body))
+            .send()
+            .await
+            .map_err(|e| anyhow!("Failed to write s3://{}/{}: {}", self.bucket, key, DisplayErrorContext(e)))?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str, what: &str, hash: &str) -> Result<Vec<u8>> {
+        let object = match self.client.get_object().bucket(&self.bucket).key(key).send().await {
+            Ok(object) => object,
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => {
+                return Err(anyhow!("{} not found: {}", what, hash));
+            }
+            Err(e) => {
+                return Err(anyhow!("Failed to read s3://{}/{}: {}", self.bucket, key, DisplayErrorContext(e)));
+            }
+        };
+        let body = object
+            .body
+            .collect()
+            .await
+            .map_err(|e| anyhow!("Failed to read s3://{}/{}: {}", self.bucket, key, e))?;
+        Ok(body.into_bytes().to_vec())
+    }
+}
+
+#[async_trait]
+impl BlobBackend for S3Backend {
+    fn kind(&self) -> StorageBackend {
+        StorageBackend::S3
+    }
+
+    async fn store_chunk(&self, hash: &str, chunk: &StoredChunk) -> Result<StoredLocation> {
+        let key = self.key(ObjectKind::Chunk, hash)?;
+        self.put(&key, encode_object(chunk)).await?;
+        tracing::debug!("Wrote chunk {} to s3://{}/{} ({} bytes)", hash, self.bucket, key, chunk.data.len());
+        Ok(StoredLocation {
+            container_id: None,
+            offset: None,
+            length: chunk.data.len() as i32,
+        })
+    }
+
+    async fn read_chunk(&self, chunk: &db::Chunk) -> Result<StoredChunk> {
+        let key = self.key(ObjectKind::Chunk, &chunk.hash)?;
+        let object = self.get(&key, "Chunk", &chunk.hash).await?;
+        decode_object(object).map_err(|e| e.context(format!("Bad chunk object s3://{}/{}", self.bucket, key)))
+    }
+
+    async fn read_legacy_blob(&self, hash: &str) -> Result<Vec<u8>> {
+        let key = self.key(ObjectKind::LegacyBlob, hash)?;
+        let content = self.get(&key, "Legacy blob", hash).await?;
+        tracing::debug!("Read legacy blob {} from s3 ({} bytes)", hash, content.len());
+        Ok(content)
+    }
+
+    async fn write_legacy_blob(&self, hash: &str, content: &[u8]) -> Result<()> {
+        let key = self.key(ObjectKind::LegacyBlob, hash)?;
+        self.put(&key, content.to_vec()).await?;
+        tracing::debug!("Wrote legacy blob {} to s3 ({} bytes)", hash, content.len());
+        Ok(())
+    }
+
+    async fn exists(&self, kind: ObjectKind, hash: &str) -> Result<bool> {
+        let key = self.key(kind, hash)?;
+        match self.client.head_object().bucket(&self.bucket).key(&key).send().await {
+            Ok(_) => Ok(true),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_not_found()) => Ok(false),
+            Err(e) => Err(anyhow!("Failed to check s3://{}/{}: {}", self.bucket, key, DisplayErrorContext(e))),
+        }
+    }
+
+    async fn size(&self, kind: ObjectKind, hash: &str) -> Result<Option<u64>> {
+        let key = self.key(kind, hash)?;
+        match self.client.head_object().bucket(&self.bucket).key(&key).send().await {
+            Ok(object) => Ok(Some(object.content_length().unwrap_or(0).max(0) as u64)),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_not_found()) => Ok(None),
+            Err(e) => Err(anyhow!("Failed to check s3://{}/{}: {}", self.bucket, key, DisplayErrorContext(e))),
+        }
+    }
+
+    async fn delete(&self, kind: ObjectKind, hash: &str) -> Result<bool> {
+        // DeleteObject succeeds whether or not the key exists
+        if !self.exists(kind, hash).await? {
+            return Ok(false);
+        }
+        let key = self.key(kind, hash)?;
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .send()
+            .await
+            .map_err(|e| anyhow!("Failed to delete s3://{}/{}: {}", self.bucket, key, DisplayErrorContext(e)))?;
+        tracing::debug!("Deleted s3://{}/{}", self.bucket, key);
+        Ok(true)
+    }
+}
+
+/// Chunk object body: header, then the stored bytes
+fn encode_object(chunk: &StoredChunk) -> Vec<u8> {
+    let mut flags = 0;
+    if chunk.compressed {
+        flags |= FLAG_COMPRESSED;
+    }
+    if chunk.encrypted {
+        flags |= FLAG_ENCRYPTED;
+    }
+    let mut object = Vec::with_capacity(OBJECT_HEADER_SIZE + chunk.data.len());
+    object.extend_from_slice(OBJECT_MAGIC);
+    object.extend_from_slice(&[OBJECT_FORMAT_VERSION, flags, 0, 0]);
+    object.extend_from_slice(&chunk.data);
+    object
+}
+
+fn decode_object(mut object: Vec<u8>) -> Result<StoredChunk> {
+    if object.len() < OBJECT_HEADER_SIZE || &object[0..4] != OBJECT_MAGIC {
+        return Err(anyhow!("Invalid chunk object header"));
+    }
+    if object[4] != OBJECT_FORMAT_VERSION {
+        return Err(anyhow!("Unsupported chunk object version: {}", object[4]));
+    }
+    let flags = object[5];
+    object.drain(..OBJECT_HEADER_SIZE);
+    Ok(StoredChunk {
+        data: object,
+        compressed: flags & FLAG_COMPRESSED != 0,
+        encrypted: flags & FLAG_ENCRYPTED != 0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_object_round_trip() {
+        for (compressed, encrypted) in [(false, false), (true, false), (false, true), (true, true)] {
+            let chunk = StoredChunk {
+                data: b"chunk bytes".to_vec(),
+                compressed,
+                encrypted,
+            };
+            let object = encode_object(&chunk);
+            assert_eq!(&object[..4], b"ENTO");
+            assert_eq!(object.len(), OBJECT_HEADER_SIZE + chunk.data.len());
+
+            let decoded = decode_object(object).unwrap();
+            assert_eq!(decoded.data, chunk.data);
+            assert_eq!((decoded.compressed, decoded.encrypted), (compressed, encrypted));
+        }
+
+        assert!(decode_object(b"ENTO".to_vec()).is_err());
+        assert!(decode_object(b"ENTG\x01\x00\x00\x00data".to_vec()).is_err());
+        assert!(decode_object(b"ENTO\x02\x00\x00\x00data".to_vec()).is_err());
+    }
+
+    #[test]
+    fn test_shard() {
+        assert_eq!(shard("abcdef").unwrap(), "ab");
+        assert!(shard("abc").is_err());
+    }
+}
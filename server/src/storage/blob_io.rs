@@ -16,31 +16,101 @@
 //! - Bytes 0-3: Magic "ENTG" (0x454E5447)
 //! - Byte 4: Version (0x01)
 //! - Bytes 5-7: Reserved (0x00)
+//!
+//! Containers created while an encryption key is configured are marked
+//! `encrypted` in the database and hold sealed chunks; see [`super::encryption`].
+//!
+//! ## Layout
+//!
+//! ```text
+//! BLOB_STORAGE_PATH/ab/<hash>                         legacy blobs, standalone chunks
+//! BLOB_STORAGE_PATH/containers/YYYY/MM/ab/pack_<id>.blob
+//! ```
+//!
+//! Files are spread over `BLOB_SHARD_LEVELS` directory levels (default 1),
+//! each named by the next two hex characters of the hash or container id, so
+//! no directory grows too large: two levels give `ab/cd/<hash>`. Legacy
+//! blobs are looked up under every shard level, so changing the setting
+//! leaves existing files readable where they are; containers are found by
+//! the path recorded for them. `tangled reshard` moves both to the
+//! configured layout.
+//!
+//! [`LocalBackend`] implements this format and the standalone blob files.
+//! [`BlobManager`] sits in front of it (and of a remote backend, if one is
+//! configured; see [`super::backend`]) and handles compression, encryption
+//! and read-ahead.
 
-use crate::db::{self, containers, ChunkTier, DbPool, NewChunk};
+use super::backend::{shard, BlobBackend, ObjectKind, StoredChunk, StoredLocation};
+use super::chunk_filter::{ChunkFilter, DEFAULT_FALSE_POSITIVE_RATE};
+use super::chunking::EMPTY_CHUNK_HASH;
+use super::disk_space::{DiskReserve, DiskSpaceGuard};
+use super::encryption::ChunkCipher;
+use crate::db::{self, chunks, containers, ChunkLocation, ChunkTier, DbPool, NewChunk, StorageBackend};
 use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::sync::Mutex;
+use tokio_stream::Stream;
 use uuid::Uuid;
 
 // Container file constants
 const MAGIC_BYTES: &[u8; 4] = b"ENTG";
 const FORMAT_VERSION: u8 = 0x01;
 const HEADER_SIZE: u64 = 8;
-const DEFAULT_MAX_CONTAINER_SIZE: u64 = 64 * 1024 * 1024; // 64 MB
 const ZSTD_COMPRESSION_LEVEL: i32 = 3;
+const DEFAULT_PREFETCH_DEPTH: usize = 4;
+
+/// Container size at which a new container is started
+pub const DEFAULT_CONTAINER_TARGET_BYTES: u64 = 64 * 1024 * 1024; // 64 MB
+pub const MIN_CONTAINER_TARGET_BYTES: u64 = 1024 * 1024; // 1 MB
+pub const MAX_CONTAINER_TARGET_BYTES: u64 = 4 * 1024 * 1024 * 1024; // 4 GB
+
+/// Directory levels blob and container files are spread over
+pub const DEFAULT_SHARD_LEVELS: u8 = 1;
+pub const MAX_SHARD_LEVELS: u8 = 4;
+
+/// Outcome of repacking a container
+#[derive(Debug)]
+pub struct RepackResult {
+    /// Replacement container (None when no live chunks remained)
+    pub new_container_id: Option<Uuid>,
+    pub chunks_moved: usize,
+    pub bytes_before: i64,
+    pub bytes_after: i64,
+}
+
+/// Returned (inside `anyhow::Error`) when a repack of the container is
+/// already running
+#[derive(Debug)]
+pub struct RepackInProgress(pub Uuid);
+
+impl std::fmt::Display for RepackInProgress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Container {} is already being repacked", self.0)
+    }
+}
+
+impl std::error::Error for RepackInProgress {}
+
+/// Returned (inside `anyhow::Error`) when a whole-file legacy blob would be
+/// written while chunks are encrypted at rest: legacy blobs can't be sealed
+#[derive(Debug)]
+pub struct UnencryptedBlobRefused;
 
-/// Location of a chunk within the storage system
-#[derive(Debug, Clone)]
-pub struct ChunkLocation {
-    pub container_id: Uuid,
-    pub offset: u64,
-    pub length: u32,
-    pub compressed: bool,
+impl std::fmt::Display for UnencryptedBlobRefused {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Whole-file uploads are stored unencrypted and are refused while encryption at rest is on; upload in chunks instead"
+        )
+    }
 }
 
+impl std::error::Error for UnencryptedBlobRefused {}
+
 /// An open container file ready for writing
 #[allow(dead_code)]
 struct OpenContainer {
@@ -50,42 +120,204 @@ struct OpenContainer {
     current_offset: u64,
 }
 
-/// Manages blob container storage
+/// Read-ahead tasks owned by a `read_chunks_ordered` stream
 ///
-/// Thread-safe: uses a Mutex to serialize writes to the current container.
+/// Aborted on drop so a cancelled download doesn't keep reading.
+struct InFlightReads(VecDeque<tokio::task::JoinHandle<Result<Vec<u8>>>>);
+
+impl Drop for InFlightReads {
+    fn drop(&mut self) {
+        for read in &self.0 {
+            read.abort();
+        }
+    }
+}
+
+/// Which containers are being read from or repacked
+///
+/// A repacked container's file is kept until the reads that started before
+/// the repack committed are done with it.
+#[derive(Default)]
+struct ContainerUsage {
+    /// Reads in progress per container
+    reads: HashMap<Uuid, usize>,
+    /// Files of repacked containers, removed when their last read finishes
+    retired: HashMap<Uuid, PathBuf>,
+    /// Containers with a repack in progress
+    repacking: HashSet<Uuid>,
+}
+
+type SharedUsage = Arc<std::sync::Mutex<ContainerUsage>>;
+
+fn lock_usage(usage: &SharedUsage) -> std::sync::MutexGuard<'_, ContainerUsage> {
+    usage.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Remove a repacked container's file
+fn remove_retired_container(path: &Path) {
+    if let Err(e) = std::fs::remove_file(path) {
+        tracing::warn!("Failed to remove repacked container {}: {}", path.display(), e);
+    }
+}
+
+/// A read of a container file, counted in [`ContainerUsage::reads`]
+struct ContainerRead {
+    usage: SharedUsage,
+    container_id: Uuid,
+}
+
+impl ContainerRead {
+    fn begin(usage: &SharedUsage, container_id: Uuid) -> Self {
+        *lock_usage(usage).reads.entry(container_id).or_default() += 1;
+        ContainerRead { usage: Arc::clone(usage), container_id }
+    }
+}
+
+impl Drop for ContainerRead {
+    fn drop(&mut self) {
+        let mut usage = lock_usage(&self.usage);
+        let Some(count) = usage.reads.get_mut(&self.container_id) else {
+            return;
+        };
+        *count -= 1;
+        if *count == 0 {
+            usage.reads.remove(&self.container_id);
+            if let Some(path) = usage.retired.remove(&self.container_id) {
+                remove_retired_container(&path);
+            }
+        }
+    }
+}
+
+/// Claim on a container for one repack, released on drop
+struct RepackClaim {
+    usage: SharedUsage,
+    container_id: Uuid,
+}
+
+impl Drop for RepackClaim {
+    fn drop(&mut self) {
+        lock_usage(&self.usage).repacking.remove(&self.container_id);
+    }
+}
+
+/// Chunk storage front end: compression, encryption, the chunk filter and
+/// read-ahead, over a local backend plus an optional remote one
+///
+/// Thread-safe: share it behind an `Arc`.
 pub struct BlobManager {
-    base_path: PathBuf,
-    db_pool: DbPool,
-    /// Guards the current open container to prevent concurrent writes
-    current_container: Arc<Mutex<Option<OpenContainer>>>,
-    max_container_size: u64,
+    local: LocalBackend,
+    /// Remote backend for chunks recorded with its kind (None = local only)
+    remote: Option<Box<dyn BlobBackend>>,
+    /// Whether new chunks and legacy blobs go to `remote`
+    write_remote: bool,
+    /// Number of chunks `read_chunks_ordered` keeps in flight
+    prefetch_depth: usize,
+    /// Bloom filter of stored chunk hashes (empty until rebuilt)
+    chunk_filter: ChunkFilter,
+    /// Encrypts new chunks and decrypts encrypted ones
+    cipher: Option<ChunkCipher>,
 }
 
 impl BlobManager {
     /// Create a new BlobManager
     pub fn new(base_path: impl AsRef<Path>, db_pool: DbPool) -> Result<Self> {
-        let base_path = base_path.as_ref().to_path_buf();
-        std::fs::create_dir_all(&base_path)
-            .context("Failed to create blob storage directory")?;
-
         Ok(Self {
-            base_path,
-            db_pool,
-            current_container: Arc::new(Mutex::new(None)),
-            max_container_size: DEFAULT_MAX_CONTAINER_SIZE,
+            local: LocalBackend::new(base_path, db_pool)?,
+            remote: None,
+            write_remote: false,
+            prefetch_depth: DEFAULT_PREFETCH_DEPTH,
+            chunk_filter: ChunkFilter::new(DEFAULT_FALSE_POSITIVE_RATE),
+            cipher: None,
         })
     }
 
+    /// Set the size at which the open container is sealed and a new one
+    /// started, clamped to [`MIN_CONTAINER_TARGET_BYTES`]..=[`MAX_CONTAINER_TARGET_BYTES`]
+    pub fn with_container_target_bytes(mut self, bytes: u64) -> Self {
+        self.local.container_target_bytes = bytes.clamp(MIN_CONTAINER_TARGET_BYTES, MAX_CONTAINER_TARGET_BYTES);
+        self
+    }
+
+    /// Set how many chunks are read ahead during downloads (minimum 1)
+    pub fn with_prefetch_depth(mut self, depth: usize) -> Self {
+        self.prefetch_depth = depth.max(1);
+        self
+    }
+
+    /// Set the target false-positive rate of the chunk existence filter
+    pub fn with_chunk_filter_fp_rate(mut self, rate: f64) -> Self {
+        self.chunk_filter = ChunkFilter::new(rate);
+        self
+    }
+
+    /// Encrypt new chunks at rest under `key_material`. Without a key, new
+    /// chunks are stored in plaintext and encrypted chunks can't be read.
+    pub fn with_encryption_key(mut self, key_material: Option<&str>) -> Self {
+        self.cipher = key_material.map(ChunkCipher::new);
+        self.local.encrypted = self.cipher.is_some();
+        self
+    }
+
+    /// Keep `reserve` free on the storage volume, failing new chunks and
+    /// legacy blobs with [`InsufficientSpace`] instead
+    ///
+    /// [`InsufficientSpace`]: super::disk_space::InsufficientSpace
+    pub fn with_disk_reserve(mut self, reserve: Option<DiskReserve>) -> Self {
+        self.local.disk_guard = reserve.map(|r| DiskSpaceGuard::new(&self.local.base_path, r));
+        self
+    }
+
+    /// Spread new blob and container files over `levels` directory levels,
+    /// at most [`MAX_SHARD_LEVELS`]
+    pub fn with_shard_levels(mut self, levels: u8) -> Self {
+        self.local.shard_levels = levels.min(MAX_SHARD_LEVELS);
+        self
+    }
+
+    /// Read chunks recorded in `backend`'s storage, and with `write`, store
+    /// new chunks and legacy blobs there instead of locally
+    pub fn with_remote_backend(mut self, backend: Option<Box<dyn BlobBackend>>, write: bool) -> Self {
+        self.write_remote = write && backend.is_some();
+        self.remote = backend;
+        self
+    }
+
+    /// Bloom filter of stored chunk hashes, used to short-circuit existence checks
+    pub fn chunk_filter(&self) -> &ChunkFilter {
+        &self.chunk_filter
+    }
+
+    /// Size at which an open container is sealed and a new one started
+    pub fn container_target_bytes(&self) -> u64 {
+        self.local.container_target_bytes
+    }
+
+    /// Backend new chunks and legacy blobs are written to
+    fn writer(&self) -> &dyn BlobBackend {
+        match &self.remote {
+            Some(remote) if self.write_remote => remote.as_ref(),
+            _ => &self.local,
+        }
+    }
+
+    /// Backend holding chunks recorded with `kind`
+    fn backend(&self, kind: StorageBackend) -> Result<&dyn BlobBackend> {
+        if kind == StorageBackend::Local {
+            return Ok(&self.local);
+        }
+        match &self.remote {
+            Some(remote) if remote.kind() == kind => Ok(remote.as_ref()),
+            _ => Err(anyhow!("Chunk is stored in {} but that backend is not configured", kind.as_str())),
+        }
+    }
+
     /// Write a chunk to storage
     ///
-    /// Returns the location where the chunk was stored.
-    /// Compresses data using Zstd for tiers 0-2, skips compression for tiers 3-4.
-    pub async fn write_chunk(
-        &self,
-        hash: &str,
-        data: &[u8],
-        tier: ChunkTier,
-    ) -> Result<ChunkLocation> {
+    /// Returns the chunk record to store. Compresses data using Zstd for
+    /// tiers 0-2, skips compression for tiers 3-4, then encrypts it if an
+    /// encryption key is configured.
+    pub async fn write_chunk(&self, hash: &str, data: &[u8], tier: ChunkTier) -> Result<NewChunk> {
         // Determine if we should compress based on tier
         let should_compress = matches!(tier, ChunkTier::Inline | ChunkTier::Granular | ChunkTier::Standard);
         
@@ -103,8 +335,291 @@ impl BlobManager {
         } else {
             (data.to_vec(), false)
         };
+        let stored = match &self.cipher {
+            Some(cipher) => StoredChunk {
+                data: cipher.seal(&write_data, compressed)?,
+                compressed,
+                encrypted: true,
+            },
+            None => StoredChunk {
+                data: write_data,
+                compressed,
+                encrypted: false,
+            },
+        };
+
+        let backend = self.writer();
+        let location = backend.store_chunk(hash, &stored).await?;
+        Ok(NewChunk {
+            hash: hash.to_string(),
+            size_bytes: data.len() as i32,
+            container_id: location.container_id,
+            offset_bytes: location.offset,
+            length_bytes: Some(location.length),
+            backend: backend.kind(),
+        })
+    }
+
+    /// Read a chunk from whichever backend holds it
+    pub async fn read_chunk(&self, chunk: &db::Chunk) -> Result<Vec<u8>> {
+        let stored = self.backend(chunk.backend)?.read_chunk(chunk).await?;
 
-        let data_len = write_data.len() as u32;
+        let (data, compressed) = if stored.encrypted {
+            let cipher = self.cipher.as_ref().ok_or_else(|| {
+                anyhow!("Chunk {} is encrypted but STORAGE_ENCRYPTION_KEY is not set", chunk.hash)
+            })?;
+            cipher.open(&stored.data)?
+        } else {
+            (stored.data, stored.compressed)
+        };
+
+        // Decompress if needed
+        if compressed {
+            let decompressed = zstd::decode_all(&data[..])
+                .context("Zstd decompression failed")?;
+            Ok(decompressed)
+        } else {
+            Ok(data)
+        }
+    }
+
+    /// Read chunks concurrently, yielding them in the order given
+    ///
+    /// Up to `prefetch_depth` reads are in flight at once, so storage I/O
+    /// overlaps with sending earlier chunks. Memory stays bounded by
+    /// `prefetch_depth` decoded chunks regardless of file size. The first
+    /// error ends the stream.
+    pub fn read_chunks_ordered(
+        self: &Arc<Self>,
+        chunks: &[db::Chunk],
+    ) -> impl Stream<Item = Result<Vec<u8>>> + Send + 'static {
+        let manager = Arc::clone(self);
+        let depth = self.prefetch_depth;
+        let mut pending_chunks: VecDeque<db::Chunk> = chunks.iter().cloned().collect();
+
+        async_stream::stream! {
+            let mut in_flight = InFlightReads(VecDeque::with_capacity(depth));
+            loop {
+                while in_flight.0.len() < depth {
+                    let Some(chunk) = pending_chunks.pop_front() else { break };
+                    let manager = Arc::clone(&manager);
+                    in_flight.0.push_back(tokio::spawn(async move {
+                        manager.read_chunk(&chunk).await
+                    }));
+                }
+
+                let Some(read) = in_flight.0.pop_front() else { break };
+                match read.await {
+                    Ok(Ok(data)) => yield Ok(data),
+                    Ok(Err(e)) => {
+                        yield Err(e);
+                        break;
+                    }
+                    Err(e) => {
+                        yield Err(anyhow!("Chunk read task failed: {}", e));
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Whether the chunk `hash`, recorded in `backend` outside a container,
+    /// is still there
+    pub async fn standalone_exists(&self, backend: StorageBackend, hash: &str) -> Result<bool> {
+        self.backend(backend)?.exists(ObjectKind::Chunk, hash).await
+    }
+
+    /// Remove the stored data of a chunk recorded in `backend` outside a
+    /// container. Returns false if it was already gone.
+    pub async fn delete_standalone(&self, backend: StorageBackend, hash: &str) -> Result<bool> {
+        self.backend(backend)?.delete(ObjectKind::Chunk, hash).await
+    }
+
+    /// Remove the data of a standalone chunk whose record was dropped,
+    /// unless it is in use again. Runs under the chunk's data lock (see
+    /// [`db::chunks::lock_chunk_data`]), so an upload of the same content
+    /// either recorded the chunk first and keeps its data, or waits and
+    /// writes it again. Returns whether data was removed.
+    pub async fn delete_released_chunk(
+        &self,
+        pool: &DbPool,
+        chunk: &db::versions::ReleasedChunk,
+    ) -> Result<bool> {
+        let mut tx = pool.begin().await?;
+        if db::chunks::lock_chunk_data(&mut tx, &chunk.hash, chunk.backend).await? {
+            return Ok(false);
+        }
+        let removed = self.delete_standalone(chunk.backend, &chunk.hash).await?;
+        tx.commit().await?;
+        Ok(removed)
+    }
+
+    /// Remove the data of chunks whose records were dropped (see
+    /// [`db::versions::ReleasedChunk`]). The records are already gone, so a
+    /// failure only strands the data and is logged rather than returned.
+    pub async fn delete_released(&self, pool: &DbPool, released: &[db::versions::ReleasedChunk]) {
+        for chunk in released {
+            if let Err(e) = self.delete_released_chunk(pool, chunk).await {
+                tracing::warn!("Released chunk {} but not its data: {}", chunk.hash, e);
+            }
+        }
+    }
+
+    /// Whether `container_id` is the container currently receiving writes
+    pub async fn is_open_container(&self, container_id: Uuid) -> bool {
+        self.local.is_open_container(container_id).await
+    }
+
+    /// See [`LocalBackend::repack_container`]
+    pub async fn repack_container(&self, container_id: Uuid) -> Result<RepackResult> {
+        self.local.repack_container(container_id).await
+    }
+
+    /// Directory levels new blob and container files are spread over
+    pub fn shard_levels(&self) -> u8 {
+        self.local.shard_levels
+    }
+
+    /// Directory holding legacy blobs and standalone chunks
+    pub fn legacy_base(&self) -> Result<&Path> {
+        self.local.legacy_base()
+    }
+
+    /// See [`LocalBackend::reshard_legacy_blob`]
+    pub fn reshard_legacy_blob(&self, path: &Path, hash: &str) -> Result<bool> {
+        self.local.reshard_legacy_blob(path, hash)
+    }
+
+    /// See [`LocalBackend::reshard_container`]
+    pub async fn reshard_container(&self, container: &db::BlobContainer) -> Result<bool> {
+        self.local.reshard_container(container).await
+    }
+
+    /// Seal the current container (if any) and prepare for shutdown
+    #[allow(dead_code)]
+    pub async fn flush(&self) -> Result<()> {
+        self.local.flush().await
+    }
+
+    /// Get the base storage path
+    #[allow(dead_code)]
+    pub fn base_path(&self) -> &Path {
+        &self.local.base_path
+    }
+
+    // =========================================================================
+    // LEGACY BLOB SUPPORT
+    // Whole-file blobs from the legacy upload API. Blobs already on local
+    // disk are read from there; new ones go to the configured backend.
+    // =========================================================================
+
+    /// Get the local storage path for a blob hash (see [`LocalBackend::legacy_blob_path`])
+    pub fn legacy_blob_path(&self, hash: &str) -> Result<PathBuf> {
+        self.local.legacy_blob_path(hash)
+    }
+
+    /// Check if a legacy blob exists in any backend
+    pub async fn legacy_exists(&self, hash: &str) -> Result<bool> {
+        if hash == EMPTY_CHUNK_HASH {
+            return Ok(true);
+        }
+        if self.local.exists(ObjectKind::LegacyBlob, hash).await? {
+            return Ok(true);
+        }
+        match &self.remote {
+            Some(remote) => remote.exists(ObjectKind::LegacyBlob, hash).await,
+            None => Ok(false),
+        }
+    }
+
+    /// Length of a legacy blob, from whichever backend holds it, without
+    /// reading it. None if no backend does.
+    pub async fn legacy_blob_size(&self, hash: &str) -> Result<Option<u64>> {
+        if hash == EMPTY_CHUNK_HASH {
+            return Ok(Some(0));
+        }
+        if let Some(size) = self.local.size(ObjectKind::LegacyBlob, hash).await? {
+            return Ok(Some(size));
+        }
+        match &self.remote {
+            Some(remote) => remote.size(ObjectKind::LegacyBlob, hash).await,
+            None => Ok(None),
+        }
+    }
+
+    /// Read a legacy blob (old BlobStore format). Empty content reads as
+    /// empty whether or not a blob was ever written for it.
+    pub async fn read_legacy_blob(&self, hash: &str) -> Result<Vec<u8>> {
+        if hash == EMPTY_CHUNK_HASH {
+            return Ok(Vec::new());
+        }
+        match &self.remote {
+            Some(remote) if !self.local.legacy_blob_path(hash)?.exists() => remote.read_legacy_blob(hash).await,
+            _ => self.local.read_legacy_blob(hash).await,
+        }
+    }
+
+    /// Open a local legacy blob for streaming reads, for blobs too large to
+    /// load with [`read_legacy_blob`](Self::read_legacy_blob)
+    pub fn open_legacy_blob(&self, hash: &str) -> Result<std::fs::File> {
+        self.local.open_legacy_blob(hash)
+    }
+
+    /// Delete a legacy blob from every backend holding it. Returns false if
+    /// none did.
+    pub async fn delete_legacy_blob(&self, hash: &str) -> Result<bool> {
+        let mut removed = self.local.delete(ObjectKind::LegacyBlob, hash).await?;
+        if let Some(remote) = &self.remote {
+            removed |= remote.delete(ObjectKind::LegacyBlob, hash).await?;
+        }
+        Ok(removed)
+    }
+
+    /// Write a legacy blob (old BlobStore format)
+    /// Used for backwards compatibility with index/export commands
+    ///
+    /// Legacy blobs are whole plaintext files, so with an encryption key set
+    /// they are refused with [`UnencryptedBlobRefused`] instead.
+    pub async fn write_legacy_blob(&self, hash: &str, content: &[u8]) -> Result<()> {
+        if self.cipher.is_some() {
+            return Err(UnencryptedBlobRefused.into());
+        }
+        self.writer().write_legacy_blob(hash, content).await
+    }
+}
+
+/// Containers and standalone blob files on the local filesystem
+///
+/// Thread-safe: uses a Mutex to serialize writes to the current container.
+pub struct LocalBackend {
+    base_path: PathBuf,
+    db_pool: DbPool,
+    /// Guards the current open container to prevent concurrent writes
+    current_container: Arc<Mutex<Option<OpenContainer>>>,
+    container_target_bytes: u64,
+    /// Whether new containers hold sealed chunks
+    encrypted: bool,
+    /// Refuses writes that would eat into the reserved free space
+    disk_guard: Option<DiskSpaceGuard>,
+    /// Directory levels new blob and container files are spread over
+    shard_levels: u8,
+    /// Reads and repacks in progress, per container
+    container_usage: SharedUsage,
+}
+
+#[async_trait]
+impl BlobBackend for LocalBackend {
+    fn kind(&self) -> StorageBackend {
+        StorageBackend::Local
+    }
+
+    /// Append a chunk to the open container
+    async fn store_chunk(&self, hash: &str, chunk: &StoredChunk) -> Result<StoredLocation> {
+        let data_len = chunk.data.len() as u32;
+        if let Some(guard) = &self.disk_guard {
+            guard.check(data_len as u64)?;
+        }
 
         // Lock the container for writing
         let mut guard = self.current_container.lock().await;
@@ -118,7 +633,7 @@ impl BlobManager {
         let offset = container.current_offset;
         container
             .file
-            .write_all(&write_data)
+            .write_all(&chunk.data)
             .context("Failed to write chunk data")?;
         container.file.flush().context("Failed to flush chunk data")?;
 
@@ -130,27 +645,45 @@ impl BlobManager {
             .await
             .context("Failed to update container stats")?;
 
-        let location = ChunkLocation {
-            container_id: container.id,
-            offset,
-            length: data_len,
-            compressed,
-        };
-
         tracing::debug!(
             "Wrote chunk {} to container {} at offset {} ({} bytes, compressed={})",
-            hash, container.id, offset, data_len, compressed
+            hash, container.id, offset, data_len, chunk.compressed
         );
 
-        Ok(location)
+        Ok(StoredLocation {
+            container_id: Some(container.id),
+            offset: Some(offset as i64),
+            length: data_len as i32,
+        })
     }
 
-    /// Read a chunk from storage
-    pub async fn read_chunk(&self, location: &ChunkLocation) -> Result<Vec<u8>> {
-        // Get container info from database
-        let container = containers::get_container(&self.db_pool, location.container_id)
-            .await?
-            .ok_or_else(|| anyhow!("Container {} not found", location.container_id))?;
+    /// Read a chunk from its container, or from its standalone blob file
+    async fn read_chunk(&self, chunk: &db::Chunk) -> Result<StoredChunk> {
+        let (container_id, offset, length) = match chunk.location() {
+            ChunkLocation::Container { container_id, offset, length } => (container_id, offset, length),
+            ChunkLocation::Standalone { hash } => {
+                return Ok(StoredChunk {
+                    data: self.read_legacy_blob(&hash).await?,
+                    compressed: false,
+                    encrypted: false,
+                });
+            }
+            ChunkLocation::Object { backend, .. } => {
+                return Err(anyhow!("Chunk {} is stored in {}, not locally", chunk.hash, backend.as_str()));
+            }
+        };
+
+        // Counted before the lookup: a repack that commits from here on
+        // leaves the file in place until this read is done
+        let _read = ContainerRead::begin(&self.container_usage, container_id);
+        let Some(container) = containers::get_container(&self.db_pool, container_id).await? else {
+            // Repacked since the caller looked the chunk up: read it from
+            // where it is now
+            return match chunks::get_chunk_with_location(&self.db_pool, &chunk.hash).await? {
+                Some(moved) if moved.container_id != Some(container_id) => self.read_chunk(&moved).await,
+                _ => Err(anyhow!("Container {} not found", container_id)),
+            };
+        };
 
         let file_path = self.base_path.join(&container.disk_path);
         
@@ -158,22 +691,247 @@ impl BlobManager {
             .with_context(|| format!("Failed to open container file: {}", file_path.display()))?;
 
         // Seek to chunk offset
-        file.seek(SeekFrom::Start(location.offset))
+        file.seek(SeekFrom::Start(offset as u64))
             .context("Failed to seek to chunk offset")?;
 
         // Read chunk data
-        let mut data = vec![0u8; location.length as usize];
+        let mut data = vec![0u8; length as usize];
         file.read_exact(&mut data)
             .context("Failed to read chunk data")?;
 
-        // Decompress if needed
-        if location.compressed {
-            let decompressed = zstd::decode_all(&data[..])
-                .context("Zstd decompression failed")?;
-            Ok(decompressed)
+        // Encrypted chunks record compression inside the sealed envelope
+        Ok(StoredChunk {
+            data,
+            compressed: !container.encrypted && length < chunk.size_bytes,
+            encrypted: container.encrypted,
+        })
+    }
+
+    /// Read a legacy blob (old BlobStore format)
+    async fn read_legacy_blob(&self, hash: &str) -> Result<Vec<u8>> {
+        let path = self.legacy_blob_path(hash)?;
+        
+        if !path.exists() {
+            return Err(anyhow!("Legacy blob not found: {}", hash));
+        }
+        
+        let content = std::fs::read(&path)
+            .with_context(|| format!("Failed to read legacy blob: {}", path.display()))?;
+        
+        tracing::debug!("Read legacy blob {} ({} bytes)", hash, content.len());
+        Ok(content)
+    }
+
+    /// Write a legacy blob (old BlobStore format)
+    async fn write_legacy_blob(&self, hash: &str, content: &[u8]) -> Result<()> {
+        let path = self.sharded_blob_path(hash, self.shard_levels)?;
+        if let Some(guard) = &self.disk_guard {
+            guard.check(content.len() as u64)?;
+        }
+
+        // Create shard directory if needed
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        // Write atomically using temp file
+        let temp_path = path.with_extension("tmp");
+        {
+            let mut file = std::fs::File::create(&temp_path)?;
+            file.write_all(content)?;
+            file.sync_all()?;
+        }
+
+        // Rename to final path (atomic on most filesystems)
+        std::fs::rename(&temp_path, &path)?;
+
+        tracing::debug!("Wrote legacy blob {} ({} bytes)", hash, content.len());
+        Ok(())
+    }
+
+    /// Standalone chunks and legacy blobs share the sharded blob layout
+    async fn exists(&self, _kind: ObjectKind, hash: &str) -> Result<bool> {
+        Ok(self.legacy_blob_path(hash)?.exists())
+    }
+
+    async fn size(&self, _kind: ObjectKind, hash: &str) -> Result<Option<u64>> {
+        match std::fs::metadata(self.legacy_blob_path(hash)?) {
+            Ok(metadata) => Ok(Some(metadata.len())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Removes the file from every shard level it is found under
+    async fn delete(&self, _kind: ObjectKind, hash: &str) -> Result<bool> {
+        let mut removed = false;
+        for levels in 0..=MAX_SHARD_LEVELS {
+            let path = self.sharded_blob_path(hash, levels)?;
+            match std::fs::remove_file(&path) {
+                Ok(()) => {
+                    tracing::debug!("Deleted blob file {}", path.display());
+                    removed = true;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(anyhow!("Failed to delete {}: {}", path.display(), e)),
+            }
+        }
+        Ok(removed)
+    }
+}
+
+impl LocalBackend {
+    pub fn new(base_path: impl AsRef<Path>, db_pool: DbPool) -> Result<Self> {
+        let base_path = base_path.as_ref().to_path_buf();
+        std::fs::create_dir_all(&base_path)
+            .context("Failed to create blob storage directory")?;
+
+        Ok(Self {
+            base_path,
+            db_pool,
+            current_container: Arc::new(Mutex::new(None)),
+            container_target_bytes: DEFAULT_CONTAINER_TARGET_BYTES,
+            encrypted: false,
+            disk_guard: None,
+            shard_levels: DEFAULT_SHARD_LEVELS,
+            container_usage: SharedUsage::default(),
+        })
+    }
+
+    /// Whether `container_id` is the container currently receiving writes
+    pub async fn is_open_container(&self, container_id: Uuid) -> bool {
+        let guard = self.current_container.lock().await;
+        guard.as_ref().is_some_and(|c| c.id == container_id)
+    }
+
+    /// Copy a container's live chunks into a new sealed container and delete the old one
+    ///
+    /// Chunk bytes are copied verbatim (compressed chunks stay compressed,
+    /// encrypted chunks stay encrypted under the same key).
+    /// Chunk locations are switched over in a single transaction, so readers
+    /// see either the old or the new location, never a mix. The old file is
+    /// removed after the commit, once no read of it is in progress. Only one
+    /// repack of a container runs at a time; others fail straight away.
+    pub async fn repack_container(&self, container_id: Uuid) -> Result<RepackResult> {
+        // Writers only ever append to the current container, so once it is
+        // ruled out nothing else can add chunks to this one
+        if self.is_open_container(container_id).await {
+            return Err(anyhow!("Container {} is open for writes", container_id));
+        }
+        let _claim = self
+            .claim_for_repack(container_id)
+            .ok_or(RepackInProgress(container_id))?;
+
+        let container = containers::get_container(&self.db_pool, container_id)
+            .await?
+            .ok_or_else(|| anyhow!("Container {} not found", container_id))?;
+        let live = containers::get_container_chunks(&self.db_pool, container_id).await?;
+        let old_path = self.base_path.join(&container.disk_path);
+
+        let (new_relative_path, moved, bytes_after) = if live.is_empty() {
+            (None, Vec::new(), 0)
         } else {
-            Ok(data)
+            let relative_path = self.new_container_path()?;
+            let new_path = self.base_path.join(&relative_path);
+            match Self::copy_chunks(&old_path, &new_path, &live) {
+                Ok((moved, total)) => (Some(relative_path), moved, total),
+                Err(e) => {
+                    let _ = std::fs::remove_file(&new_path);
+                    return Err(e);
+                }
+            }
+        };
+
+        let new_container_id = match containers::replace_container(
+            &self.db_pool,
+            container_id,
+            new_relative_path.as_deref(),
+            &moved,
+            bytes_after,
+        )
+        .await
+        {
+            Ok(id) => id,
+            Err(e) => {
+                if let Some(relative_path) = &new_relative_path {
+                    let _ = std::fs::remove_file(self.base_path.join(relative_path));
+                }
+                return Err(e.context("Failed to switch chunks to repacked container"));
+            }
+        };
+
+        self.retire_container(container_id, old_path);
+
+        tracing::info!(
+            "Repacked container {} -> {:?} ({} chunks, {} -> {} bytes)",
+            container_id, new_container_id, moved.len(), container.total_size, bytes_after
+        );
+
+        Ok(RepackResult {
+            new_container_id,
+            chunks_moved: moved.len(),
+            bytes_before: container.total_size,
+            bytes_after,
+        })
+    }
+
+    /// Mark a container as being repacked; None if it already is
+    fn claim_for_repack(&self, container_id: Uuid) -> Option<RepackClaim> {
+        lock_usage(&self.container_usage)
+            .repacking
+            .insert(container_id)
+            .then(|| RepackClaim { usage: Arc::clone(&self.container_usage), container_id })
+    }
+
+    /// Remove a repacked container's file now, or when its last read finishes
+    fn retire_container(&self, container_id: Uuid, path: PathBuf) {
+        let mut usage = lock_usage(&self.container_usage);
+        if usage.reads.contains_key(&container_id) {
+            usage.retired.insert(container_id, path);
+        } else {
+            drop(usage);
+            remove_retired_container(&path);
+        }
+    }
+
+    /// Copy the given chunks from one container file into a new one
+    ///
+    /// Returns each chunk's hash with its new offset, and the bytes written
+    /// after the header.
+    fn copy_chunks(
+        old_path: &Path,
+        new_path: &Path,
+        chunks: &[containers::ContainerChunk],
+    ) -> Result<(Vec<(String, i64)>, i64)> {
+        if let Some(parent) = new_path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+        }
+
+        let mut source = std::fs::File::open(old_path)
+            .with_context(|| format!("Failed to open container file: {}", old_path.display()))?;
+        let mut target = std::fs::File::create(new_path)
+            .with_context(|| format!("Failed to create container file: {}", new_path.display()))?;
+        target.write_all(&Self::create_header())
+            .context("Failed to write container header")?;
+
+        let mut moved = Vec::with_capacity(chunks.len());
+        let mut offset = HEADER_SIZE;
+        let mut buf = Vec::new();
+        for chunk in chunks {
+            buf.resize(chunk.length_bytes as usize, 0);
+            source.seek(SeekFrom::Start(chunk.offset_bytes as u64))
+                .context("Failed to seek to chunk offset")?;
+            source.read_exact(&mut buf)
+                .with_context(|| format!("Failed to read chunk {}", chunk.hash))?;
+            target.write_all(&buf)
+                .context("Failed to write chunk data")?;
+            moved.push((chunk.hash.clone(), offset as i64));
+            offset += buf.len() as u64;
         }
+
+        target.sync_all().context("Failed to sync container file")?;
+        Ok((moved, (offset - HEADER_SIZE) as i64))
     }
 
     /// Get or create an open container for writing
@@ -182,10 +940,9 @@ impl BlobManager {
         guard: &'a mut Option<OpenContainer>,
         required_size: u64,
     ) -> Result<&'a mut OpenContainer> {
-        // Check if current container has space
         let needs_new = match guard.as_ref() {
             Some(container) => {
-                container.current_offset + required_size > self.max_container_size
+                needs_rollover(container.current_offset, required_size, self.container_target_bytes)
             }
             None => true,
         };
@@ -204,14 +961,16 @@ impl BlobManager {
         guard.as_mut().ok_or_else(|| anyhow!("No open container available after creation"))
     }
 
+    /// Relative path for a new container file: YYYY/MM/<shard dirs>/pack_<uuid>.blob
+    fn new_container_path(&self) -> Result<String> {
+        let month = chrono::Utc::now().format("%Y/%m").to_string();
+        let file_name = format!("pack_{}.blob", Uuid::new_v4().simple());
+        container_path(&month, &file_name, self.shard_levels)
+    }
+
     /// Create a new container file
     async fn create_container(&self) -> Result<OpenContainer> {
-        // Generate path: YYYY/MM/pack_<uuid>.blob
-        let now = chrono::Utc::now();
-        let year_month = now.format("%Y/%m").to_string();
-        let container_id = Uuid::new_v4();
-        let filename = format!("pack_{}.blob", container_id.simple());
-        let relative_path = format!("{}/{}", year_month, filename);
+        let relative_path = self.new_container_path()?;
 
         let full_path = self.base_path.join(&relative_path);
 
@@ -236,6 +995,7 @@ impl BlobManager {
             &self.db_pool,
             &db::NewBlobContainer {
                 disk_path: relative_path.clone(),
+                encrypted: self.encrypted,
             },
         )
         .await
@@ -301,81 +1061,124 @@ impl BlobManager {
         Ok(())
     }
 
-    /// Get the base storage path
-    #[allow(dead_code)]
-    pub fn base_path(&self) -> &Path {
-        &self.base_path
+    /// Legacy blobs are stored at base_path/../ (parent of containers dir)
+    fn legacy_base(&self) -> Result<&Path> {
+        self.base_path.parent()
+            .ok_or_else(|| anyhow!("Cannot get parent of base path"))
     }
 
-    // =========================================================================
-    // LEGACY BLOB SUPPORT
-    // These methods provide backwards compatibility with the old BlobStore
-    // 2-char sharded storage format. New code should use write_chunk/read_chunk.
-    // =========================================================================
-
-    /// Get the legacy storage path for a blob hash (sharded by first 2 chars)
-    fn legacy_blob_path(&self, hash: &str) -> Result<PathBuf> {
-        if hash.len() < 4 {
-            return Err(anyhow!("Invalid hash format: {}", hash));
-        }
-        let shard = &hash[..2];
-        // Legacy blobs are stored at base_path/../ (parent of containers dir)
-        let legacy_base = self.base_path.parent()
-            .ok_or_else(|| anyhow!("Cannot get parent of base path"))?;
-        Ok(legacy_base.join(shard).join(hash))
+    /// Path of a blob file under `levels` shard directories
+    fn sharded_blob_path(&self, hash: &str, levels: u8) -> Result<PathBuf> {
+        let mut path = self.legacy_base()?.to_path_buf();
+        path.extend(shard_dirs(hash, levels)?);
+        Ok(path.join(hash))
     }
 
-    /// Check if a legacy blob exists
-    pub fn legacy_exists(&self, hash: &str) -> Result<bool> {
-        let path = self.legacy_blob_path(hash)?;
-        Ok(path.exists())
+    /// Get the legacy storage path for a blob hash: where it is stored,
+    /// trying the configured shard levels before the others, or else where
+    /// it would be written
+    pub fn legacy_blob_path(&self, hash: &str) -> Result<PathBuf> {
+        let path = self.sharded_blob_path(hash, self.shard_levels)?;
+        if path.exists() {
+            return Ok(path);
+        }
+        for levels in (0..=MAX_SHARD_LEVELS).filter(|&l| l != self.shard_levels) {
+            let other = self.sharded_blob_path(hash, levels)?;
+            if other.exists() {
+                return Ok(other);
+            }
+        }
+        Ok(path)
     }
 
-    /// Read a legacy blob (old BlobStore format)
-    pub fn read_legacy_blob(&self, hash: &str) -> Result<Vec<u8>> {
-        let path = self.legacy_blob_path(hash)?;
-        
-        if !path.exists() {
-            return Err(anyhow!("Legacy blob not found: {}", hash));
+    /// Move the blob file at `path` to the configured shard levels. Returns
+    /// false if it is there already.
+    ///
+    /// The file is hard-linked into place before the old name is removed, so
+    /// readers find it under one name or the other throughout.
+    pub fn reshard_legacy_blob(&self, path: &Path, hash: &str) -> Result<bool> {
+        let target = self.sharded_blob_path(hash, self.shard_levels)?;
+        if target == path {
+            return Ok(false);
         }
-        
-        let content = std::fs::read(&path)
-            .with_context(|| format!("Failed to read legacy blob: {}", path.display()))?;
-        
-        tracing::debug!("Read legacy blob {} ({} bytes)", hash, content.len());
-        Ok(content)
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+        }
+        match std::fs::hard_link(path, &target) {
+            // Same name, same content: only the old copy needs to go
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(anyhow!("Failed to link {} to {}: {}", path.display(), target.display(), e)),
+        }
+        std::fs::remove_file(path).with_context(|| format!("Failed to remove {}", path.display()))?;
+        remove_empty_dirs(path, self.legacy_base()?);
+        Ok(true)
     }
 
-    /// Write a legacy blob (old BlobStore format)
-    /// Used for backwards compatibility with index/export commands
-    pub fn write_legacy_blob(&self, hash: &str, content: &[u8]) -> Result<()> {
-        let path = self.legacy_blob_path(hash)?;
-
-        // Create shard directory if needed
-        if let Some(parent) = path.parent() {
-            std::fs::create_dir_all(parent)?;
+    /// Move a container file to the configured shard levels and record its
+    /// new path. Returns false if it is there already.
+    ///
+    /// The file is hard-linked into place and the path switched before the
+    /// old name is removed. Writes to the open container go on through the
+    /// same inode, so even that one can be moved while the server runs. A
+    /// link an interrupted run left at the new name is replaced.
+    pub async fn reshard_container(&self, container: &db::BlobContainer) -> Result<bool> {
+        let target = resharded_container_path(&container.disk_path, self.shard_levels)?;
+        if target == container.disk_path {
+            return Ok(false);
         }
+        let old_path = self.base_path.join(&container.disk_path);
+        let new_path = self.base_path.join(&target);
+        if let Some(parent) = new_path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+        }
+        if new_path.exists() {
+            // Left by a run that stopped before recording the new path: the
+            // container still lives at the old one, so link it afresh
+            std::fs::remove_file(&new_path)
+                .with_context(|| format!("Failed to remove stale link {}", new_path.display()))?;
+        }
+        std::fs::hard_link(&old_path, &new_path)
+            .with_context(|| format!("Failed to link {} to {}", old_path.display(), new_path.display()))?;
 
-        // Write atomically using temp file
-        let temp_path = path.with_extension("tmp");
-        {
-            let mut file = std::fs::File::create(&temp_path)?;
-            file.write_all(content)?;
-            file.sync_all()?;
+        match containers::set_disk_path(&self.db_pool, container.id, &target).await {
+            Ok(true) => {}
+            // Repacked away meanwhile
+            Ok(false) => {
+                let _ = std::fs::remove_file(&new_path);
+                return Ok(false);
+            }
+            Err(e) => {
+                let _ = std::fs::remove_file(&new_path);
+                return Err(e.context("Failed to record the container's new path"));
+            }
         }
 
-        // Rename to final path (atomic on most filesystems)
-        std::fs::rename(&temp_path, &path)?;
+        if let Err(e) = std::fs::remove_file(&old_path) {
+            tracing::warn!("Failed to remove resharded container {}: {}", old_path.display(), e);
+        }
+        remove_empty_dirs(&old_path, &self.base_path);
+        Ok(true)
+    }
 
-        tracing::debug!("Wrote legacy blob {} ({} bytes)", hash, content.len());
-        Ok(())
+    /// Open a legacy blob for streaming reads
+    pub fn open_legacy_blob(&self, hash: &str) -> Result<std::fs::File> {
+        let path = self.legacy_blob_path(hash)?;
+        std::fs::File::open(&path).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => anyhow!("Legacy blob not found: {}", hash),
+            _ => anyhow::Error::new(e).context(format!("Failed to open legacy blob: {}", path.display())),
+        })
     }
 }
 
 /// Write a chunk to storage and record it in the database
 ///
 /// This is a convenience function that combines BlobManager::write_chunk
-/// with the database upsert.
+/// with the database upsert, then adds the hash to the chunk filter. The
+/// chunk's data lock is held shared throughout, so removing the data of a
+/// released chunk with the same hash can't interleave with the write.
 pub async fn store_chunk(
     blob_manager: &BlobManager,
     db_pool: &DbPool,
@@ -383,32 +1186,200 @@ pub async fn store_chunk(
     data: &[u8],
     tier: ChunkTier,
 ) -> Result<db::Chunk> {
+    let mut tx = db_pool.begin().await?;
+    db::chunks::share_chunk_data_lock(&mut tx, hash).await?;
+
     // Write to physical storage
-    let location = blob_manager.write_chunk(hash, data, tier).await?;
+    let new_chunk = blob_manager.write_chunk(hash, data, tier).await?;
 
     // Record in database
-    let new_chunk = NewChunk {
-        hash: hash.to_string(),
-        size_bytes: data.len() as i32,
-        container_id: Some(location.container_id),
-        offset_bytes: Some(location.offset as i64),
-        length_bytes: Some(location.length as i32),
-    };
-
-    let chunk = db::chunks::upsert_chunk_with_location(db_pool, &new_chunk)
+    let chunk = db::chunks::upsert_chunk_with_location(&mut tx, &new_chunk)
         .await
         .context("Failed to record chunk in database")?;
+    tx.commit().await?;
+    blob_manager.chunk_filter().insert(hash);
 
     Ok(chunk)
 }
 
+/// Shard directories for a hash: `levels` directories named by its next two
+/// characters each, e.g. `["ab", "cd"]` for two levels
+fn shard_dirs(hash: &str, levels: u8) -> Result<Vec<&str>> {
+    let levels = levels as usize;
+    // Validates the hash as for the single-level layout
+    shard(hash)?;
+    if hash.len() < 2 * levels + 2 {
+        return Err(anyhow!("Hash too short for {} shard levels: {}", levels, hash));
+    }
+    Ok((0..levels).map(|level| &hash[2 * level..2 * level + 2]).collect())
+}
+
+/// Relative path of a container file in `month` (`YYYY/MM`), sharded by
+/// the id in its `pack_<id>.blob` name
+fn container_path(month: &str, file_name: &str, levels: u8) -> Result<String> {
+    let id = file_name
+        .strip_prefix("pack_")
+        .and_then(|name| name.strip_suffix(".blob"))
+        .ok_or_else(|| anyhow!("Unexpected container file name: {}", file_name))?;
+    let mut parts = vec![month];
+    parts.extend(shard_dirs(id, levels)?);
+    parts.push(file_name);
+    Ok(parts.join("/"))
+}
+
+/// Where a container recorded at `disk_path` belongs under `levels` shard
+/// directories: the same month and file name
+fn resharded_container_path(disk_path: &str, levels: u8) -> Result<String> {
+    let parts: Vec<&str> = disk_path.split('/').collect();
+    match parts.as_slice() {
+        [year, month, .., file_name] => {
+            container_path(&format!("{}/{}", year, month), file_name, levels)
+        }
+        _ => Err(anyhow!("Unexpected container path: {}", disk_path)),
+    }
+}
+
+/// Remove the directories above `path` that moving it emptied, up to `stop`
+fn remove_empty_dirs(path: &Path, stop: &Path) {
+    let mut dir = path.parent();
+    while let Some(d) = dir {
+        if d == stop || !d.starts_with(stop) || std::fs::remove_dir(d).is_err() {
+            break;
+        }
+        dir = d.parent();
+    }
+}
+
+/// True if writing `incoming` bytes at `offset` would take a container past
+/// `target`. A chunk larger than the target still goes into an empty
+/// container rather than sealing it with nothing in it.
+fn needs_rollover(offset: u64, incoming: u64, target: u64) -> bool {
+    offset > HEADER_SIZE && offset + incoming > target
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn local_backend(dir: &Path) -> LocalBackend {
+        let pool = sqlx::PgPool::connect_lazy("postgres://localhost/unused").unwrap();
+        LocalBackend::new(dir, pool).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_repacked_container_outlives_reads() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = local_backend(dir.path());
+        let (id, path) = (Uuid::new_v4(), dir.path().join("pack.blob"));
+
+        std::fs::write(&path, b"ENTG").unwrap();
+        let first = ContainerRead::begin(&backend.container_usage, id);
+        let second = ContainerRead::begin(&backend.container_usage, id);
+        backend.retire_container(id, path.clone());
+        drop(first);
+        assert!(path.exists());
+        drop(second);
+        assert!(!path.exists());
+
+        // With no reads in progress the file goes straight away
+        std::fs::write(&path, b"ENTG").unwrap();
+        backend.retire_container(id, path.clone());
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_legacy_blobs_refused_when_encrypting() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = sqlx::PgPool::connect_lazy("postgres://localhost/unused").unwrap();
+        let manager = BlobManager::new(dir.path(), pool).unwrap();
+        let hash = blake3::hash(b"plain").to_hex().to_string();
+        manager.write_legacy_blob(&hash, b"plain").await.unwrap();
+
+        let manager = manager.with_encryption_key(Some("key material"));
+        let err = manager.write_legacy_blob(&hash, b"plain").await.unwrap_err();
+        assert!(err.downcast_ref::<UnencryptedBlobRefused>().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_reshard_container_after_interrupted_run() {
+        let Some(pool) = crate::db::test_pool().await else { return };
+        let dir = tempfile::tempdir().unwrap();
+        let backend = LocalBackend::new(dir.path(), pool.clone()).unwrap();
+        let disk_path = format!("2026/10/pack_{}.blob", Uuid::new_v4().simple());
+        let container = containers::create_container(
+            &pool,
+            &db::NewBlobContainer { disk_path: disk_path.clone(), encrypted: false },
+        )
+        .await
+        .unwrap();
+        let old_path = dir.path().join(&disk_path);
+        std::fs::create_dir_all(old_path.parent().unwrap()).unwrap();
+        std::fs::write(&old_path, b"ENTG contents").unwrap();
+
+        // A link left behind by a run that stopped before recording the path
+        let target = resharded_container_path(&disk_path, DEFAULT_SHARD_LEVELS).unwrap();
+        let new_path = dir.path().join(&target);
+        std::fs::create_dir_all(new_path.parent().unwrap()).unwrap();
+        std::fs::write(&new_path, b"ENTG stale").unwrap();
+
+        assert!(backend.reshard_container(&container).await.unwrap());
+        assert_eq!(std::fs::read(&new_path).unwrap(), b"ENTG contents");
+        assert!(!old_path.exists());
+        let recorded = containers::get_container(&pool, container.id).await.unwrap().unwrap();
+        assert_eq!(recorded.disk_path, target);
+    }
+
+    #[tokio::test]
+    async fn test_one_repack_per_container() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = local_backend(dir.path());
+        let id = Uuid::new_v4();
+
+        let claim = backend.claim_for_repack(id).unwrap();
+        assert!(backend.claim_for_repack(id).is_none());
+        assert!(backend.claim_for_repack(Uuid::new_v4()).is_some());
+        drop(claim);
+        assert!(backend.claim_for_repack(id).is_some());
+    }
+
+    #[test]
+    fn test_needs_rollover() {
+        let target = MIN_CONTAINER_TARGET_BYTES;
+        assert!(!needs_rollover(HEADER_SIZE, 1000, target));
+        assert!(!needs_rollover(target - 1000, 1000, target));
+        assert!(needs_rollover(target - 1000, 1001, target));
+        // Oversized chunk: fresh containers take it, used ones roll over first
+        assert!(!needs_rollover(HEADER_SIZE, 2 * target, target));
+        assert!(needs_rollover(HEADER_SIZE + 1, 2 * target, target));
+    }
+
+    #[test]
+    fn test_shard_dirs() {
+        assert_eq!(shard_dirs("abcdef12", 0).unwrap(), Vec::<&str>::new());
+        assert_eq!(shard_dirs("abcdef12", 1).unwrap(), vec!["ab"]);
+        assert_eq!(shard_dirs("abcdef12", 3).unwrap(), vec!["ab", "cd", "ef"]);
+        assert!(shard_dirs("abcdef", 3).is_err());
+        assert!(shard_dirs("abc", 0).is_err());
+    }
+
+    #[test]
+    fn test_container_paths() {
+        let name = "pack_0123456789abcdef0123456789abcdef.blob";
+        assert_eq!(container_path("2026/10", name, 0).unwrap(), format!("2026/10/{}", name));
+        assert_eq!(container_path("2026/10", name, 2).unwrap(), format!("2026/10/01/23/{}", name));
+        assert!(container_path("2026/10", "other.blob", 1).is_err());
+
+        // The month is kept whatever the path was sharded by before
+        let flat = format!("2026/10/{}", name);
+        assert_eq!(resharded_container_path(&flat, 1).unwrap(), format!("2026/10/01/{}", name));
+        let deep = format!("2026/10/01/23/{}", name);
+        assert_eq!(resharded_container_path(&deep, 0).unwrap(), flat);
+        assert!(resharded_container_path(name, 1).is_err());
+    }
+
     #[test]
     fn test_header_creation() {
-        let header = BlobManager::create_header();
+        let header = LocalBackend::create_header();
         assert_eq!(&header[0..4], b"ENTG");
         assert_eq!(header[4], 0x01);
         assert_eq!(&header[5..8], &[0, 0, 0]);
@@ -416,15 +1387,15 @@ mod tests {
 
     #[test]
     fn test_header_verification() {
-        let header = BlobManager::create_header();
-        assert!(BlobManager::verify_header(&header).is_ok());
+        let header = LocalBackend::create_header();
+        assert!(LocalBackend::verify_header(&header).is_ok());
 
         let bad_magic = [0u8; 8];
-        assert!(BlobManager::verify_header(&bad_magic).is_err());
+        assert!(LocalBackend::verify_header(&bad_magic).is_err());
 
-        let mut bad_version = BlobManager::create_header();
+        let mut bad_version = LocalBackend::create_header();
         bad_version[4] = 0xFF;
-        assert!(BlobManager::verify_header(&bad_version).is_err());
+        assert!(LocalBackend::verify_header(&bad_version).is_err());
     }
 
     #[test]
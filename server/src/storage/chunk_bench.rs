@@ -0,0 +1,275 @@
+//! Chunking benchmark (`tangled bench-chunking`)
+//!
+//! Chunks a sample corpus with several FastCDC parameter sets and compares
+//! how well each deduplicates and how fast it runs, so admins can see which
+//! tier parameters suit their data. Nothing is stored: chunks only exist as
+//! hashes in memory for the length of the run.
+//!
+//! The recommended set is the one with the smallest estimated footprint:
+//! unique chunk bytes plus the database rows each chunk and each chunk
+//! reference costs. Smaller chunks always find more duplicates, so the row
+//! cost is what keeps the recommendation from sliding to the smallest size.
+
+use super::chunking::StreamChunker;
+use super::tiering::{streaming_config, ChunkConfig, DefaultTierStrategy, Tier, TierStrategy};
+use std::collections::HashSet;
+use std::io::{self, Read};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Estimated bytes per `chunks` row (hash, location, ref count, index entry)
+const CHUNK_ROW_BYTES: u64 = 160;
+
+/// Estimated bytes per `version_chunks` row (one per chunk of every file)
+const CHUNK_REF_ROW_BYTES: u64 = 120;
+
+/// Bytes read from a sample file at a time
+const READ_BUFFER_BYTES: usize = 1024 * 1024;
+
+/// A set of FastCDC parameters to try
+#[derive(Debug, Clone)]
+pub struct ParamSet {
+    pub name: String,
+    /// None chooses each file's tier from its size and type and chunks with
+    /// it as the server does (see [`streaming_config`])
+    pub config: Option<ChunkConfig>,
+}
+
+impl ParamSet {
+    fn fixed(name: &str, min_size: usize, avg_size: usize, max_size: usize) -> Self {
+        Self {
+            name: name.to_string(),
+            config: Some(ChunkConfig { min_size, avg_size, max_size }),
+        }
+    }
+}
+
+/// The per-file tiering used today, each fixed tier, and the sizes between
+/// the standard and large tiers. The jumbo tier is left out: its sizes are
+/// beyond what FastCDC accepts.
+pub fn default_param_sets() -> Vec<ParamSet> {
+    const KIB: usize = 1024;
+    let tier = |name: &str, tier: Tier| ParamSet {
+        name: name.to_string(),
+        config: Some(tier.config()),
+    };
+    vec![
+        ParamSet { name: "auto".to_string(), config: None },
+        tier("granular", Tier::T1Granular),
+        ParamSet::fixed("8k", 4 * KIB, 8 * KIB, 16 * KIB),
+        tier("standard", Tier::T2Standard),
+        ParamSet::fixed("128k", 64 * KIB, 128 * KIB, 256 * KIB),
+        ParamSet::fixed("256k", 128 * KIB, 256 * KIB, 512 * KIB),
+        tier("large", Tier::T3Large),
+    ]
+}
+
+/// Refuse sizes FastCDC would panic on instead of chunking
+fn check_config(config: ChunkConfig) -> io::Result<()> {
+    use fastcdc::v2020::{AVERAGE_MAX, AVERAGE_MIN, MAXIMUM_MAX, MAXIMUM_MIN, MINIMUM_MAX, MINIMUM_MIN};
+
+    let in_range = |size: usize, min: u32, max: u32| (min as usize..=max as usize).contains(&size);
+    if config.max_size == 0
+        || (in_range(config.min_size, MINIMUM_MIN, MINIMUM_MAX)
+            && in_range(config.avg_size, AVERAGE_MIN, AVERAGE_MAX)
+            && in_range(config.max_size, MAXIMUM_MIN, MAXIMUM_MAX))
+    {
+        return Ok(());
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!(
+            "FastCDC does not support chunk sizes {}/{}/{}",
+            config.min_size, config.avg_size, config.max_size
+        ),
+    ))
+}
+
+/// Totals for one parameter set over the whole corpus
+#[derive(Debug, Clone)]
+pub struct BenchResult {
+    pub set: ParamSet,
+    pub total_bytes: u64,
+    pub chunk_count: u64,
+    pub unique_chunks: u64,
+    pub unique_bytes: u64,
+    /// Time spent chunking and hashing (file reads excluded)
+    pub elapsed: Duration,
+}
+
+impl BenchResult {
+    pub fn avg_chunk_size(&self) -> u64 {
+        self.total_bytes.checked_div(self.chunk_count).unwrap_or(0)
+    }
+
+    /// Input bytes per unique stored byte (1.0 = no duplicates found)
+    pub fn dedup_ratio(&self) -> f64 {
+        if self.unique_bytes == 0 {
+            return 1.0;
+        }
+        self.total_bytes as f64 / self.unique_bytes as f64
+    }
+
+    pub fn throughput_mib_s(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs == 0.0 {
+            return 0.0;
+        }
+        self.total_bytes as f64 / (1024.0 * 1024.0) / secs
+    }
+
+    /// Unique chunk bytes plus the estimated database rows to track them
+    pub fn estimated_footprint(&self) -> u64 {
+        self.unique_bytes + self.unique_chunks * CHUNK_ROW_BYTES + self.chunk_count * CHUNK_REF_ROW_BYTES
+    }
+}
+
+/// Runs every parameter set over the files it is given
+pub struct ChunkBench {
+    runs: Vec<(BenchResult, HashSet<[u8; 32]>)>,
+}
+
+impl ChunkBench {
+    pub fn new(sets: Vec<ParamSet>) -> Self {
+        let runs = sets
+            .into_iter()
+            .map(|set| {
+                let result = BenchResult {
+                    set,
+                    total_bytes: 0,
+                    chunk_count: 0,
+                    unique_chunks: 0,
+                    unique_bytes: 0,
+                    elapsed: Duration::ZERO,
+                };
+                (result, HashSet::new())
+            })
+            .collect();
+        Self { runs }
+    }
+
+    /// Chunk one file's content, read from `reader` a piece at a time, with
+    /// every set. `path` and `size` pick the tier for the per-file set.
+    pub fn add(&mut self, path: &Path, size: u64, mut reader: impl Read) -> io::Result<()> {
+        let mut chunkers = Vec::with_capacity(self.runs.len());
+        for (result, _) in &self.runs {
+            let config = result.set.config.unwrap_or_else(|| {
+                streaming_config(DefaultTierStrategy::determine_tier(path, size).into())
+            });
+            check_config(config)
+                .map_err(|e| io::Error::new(e.kind(), format!("{} ({}): {}", path.display(), result.set.name, e)))?;
+            chunkers.push(StreamChunker::new(config));
+        }
+
+        let mut buffer = vec![0u8; READ_BUFFER_BYTES];
+        loop {
+            let n = reader.read(&mut buffer)?;
+            if n == 0 {
+                break;
+            }
+            for ((result, seen), chunker) in self.runs.iter_mut().zip(&mut chunkers) {
+                let start = Instant::now();
+                let chunks = chunker.push(&buffer[..n]);
+                record_chunks(result, seen, &chunks);
+                result.elapsed += start.elapsed();
+            }
+        }
+        for ((result, seen), chunker) in self.runs.iter_mut().zip(chunkers) {
+            let start = Instant::now();
+            let (chunks, _) = chunker.finish();
+            record_chunks(result, seen, &chunks);
+            result.elapsed += start.elapsed();
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> Vec<BenchResult> {
+        self.runs.into_iter().map(|(result, _)| result).collect()
+    }
+}
+
+/// Count chunks a set produced, hashing them to find the ones already seen
+fn record_chunks(result: &mut BenchResult, seen: &mut HashSet<[u8; 32]>, chunks: &[Vec<u8>]) {
+    for chunk in chunks {
+        result.total_bytes += chunk.len() as u64;
+        result.chunk_count += 1;
+        if seen.insert(*blake3::hash(chunk).as_bytes()) {
+            result.unique_chunks += 1;
+            result.unique_bytes += chunk.len() as u64;
+        }
+    }
+}
+
+/// The set with the smallest estimated footprint
+pub fn recommend(results: &[BenchResult]) -> Option<&BenchResult> {
+    results.iter().min_by_key(|r| r.estimated_footprint())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                (state >> 33) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_duplicate_files_dedup() {
+        let data = sample(512 * 1024, 7);
+        let mut bench = ChunkBench::new(default_param_sets());
+        bench.add(Path::new("a.bin"), data.len() as u64, &data[..]).unwrap();
+        bench.add(Path::new("b.bin"), data.len() as u64, &data[..]).unwrap();
+
+        for result in bench.finish() {
+            assert_eq!(result.total_bytes, 2 * data.len() as u64, "{}", result.set.name);
+            assert_eq!(result.unique_bytes, data.len() as u64, "{}", result.set.name);
+            assert!((result.dedup_ratio() - 2.0).abs() < 1e-9);
+            assert_eq!(result.chunk_count, 2 * result.unique_chunks);
+        }
+    }
+
+    #[test]
+    fn test_disk_image_is_streamed() {
+        // Disk images pick the jumbo tier, which FastCDC can't chunk with
+        let data = sample(5 * 1024 * 1024, 3);
+        let mut bench = ChunkBench::new(vec![ParamSet { name: "auto".to_string(), config: None }]);
+        bench.add(Path::new("disk.iso"), data.len() as u64, &data[..]).unwrap();
+
+        let result = &bench.finish()[0];
+        assert_eq!(result.total_bytes, data.len() as u64);
+        assert!(result.chunk_count > 1);
+    }
+
+    #[test]
+    fn test_recommend_weighs_row_overhead() {
+        let result = |name: &str, chunk_count, unique_bytes| BenchResult {
+            set: ParamSet { name: name.to_string(), config: None },
+            total_bytes: 10_000_000,
+            chunk_count,
+            unique_chunks: chunk_count,
+            unique_bytes,
+            elapsed: Duration::from_millis(10),
+        };
+        // Finds 20 KB more duplicates but needs 900 more chunks to do it
+        let results = vec![result("small", 1000, 9_980_000), result("big", 100, 10_000_000)];
+        assert_eq!(recommend(&results).unwrap().set.name, "big");
+        assert!(recommend(&[]).is_none());
+    }
+
+    #[test]
+    fn test_unsupported_sizes_are_refused() {
+        for set in default_param_sets() {
+            if let Some(config) = set.config {
+                assert!(check_config(config).is_ok(), "{}", set.name);
+            }
+        }
+        assert!(check_config(Tier::T0Inline.config()).is_ok());
+        assert!(check_config(Tier::T4Jumbo.config()).is_err());
+    }
+}
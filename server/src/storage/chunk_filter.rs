@@ -0,0 +1,187 @@
+//! In-memory bloom filter of stored chunk hashes
+//!
+//! Lets `/chunks/check` answer "definitely missing" without a database round
+//! trip; only possible hits are looked up in the `chunks` table. The filter is
+//! built from the table at startup, updated by [`store_chunk`] and chunked
+//! file creation, and rebuilt periodically so deleted chunks and growth past
+//! the sized capacity don't erode the false-positive rate.
+//!
+//! A false positive only costs a database lookup. Until the first build
+//! completes, every hash is reported as a possible hit.
+//!
+//! [`store_chunk`]: super::store_chunk
+
+use crate::db::DbPool;
+use std::sync::RwLock;
+use tokio_stream::StreamExt;
+
+pub const DEFAULT_FALSE_POSITIVE_RATE: f64 = 0.01;
+
+/// Extra capacity over the current chunk count, so inserts between rebuilds
+/// don't push the false-positive rate past its target
+const HEADROOM: f64 = 1.5;
+const MIN_CAPACITY: usize = 1024;
+
+pub struct ChunkFilter {
+    false_positive_rate: f64,
+    state: RwLock<FilterState>,
+}
+
+#[derive(Default)]
+struct FilterState {
+    /// None until the first build completes
+    bloom: Option<Bloom>,
+    /// Hashes stored while a rebuild is scanning the table
+    pending: Option<Vec<String>>,
+}
+
+impl ChunkFilter {
+    pub fn new(false_positive_rate: f64) -> Self {
+        Self {
+            false_positive_rate,
+            state: RwLock::default(),
+        }
+    }
+
+    /// Record a newly stored chunk
+    pub fn insert(&self, hash: &str) {
+        let mut state = self.lock_write();
+        if let Some(bloom) = &mut state.bloom {
+            bloom.insert(hash);
+        }
+        if let Some(pending) = &mut state.pending {
+            pending.push(hash.to_string());
+        }
+    }
+
+    /// Split `hashes` into possible hits (to confirm against the database)
+    /// and hashes that are definitely not stored
+    pub fn partition(&self, hashes: &[String]) -> (Vec<String>, Vec<String>) {
+        let state = self.state.read().unwrap_or_else(|e| e.into_inner());
+        match &state.bloom {
+            Some(bloom) => hashes.iter().cloned().partition(|h| bloom.contains(h)),
+            None => (hashes.to_vec(), Vec::new()),
+        }
+    }
+
+    /// Rebuild from the `chunks` table, returning the number of hashes loaded.
+    /// Chunks stored during the scan are carried over into the new filter.
+    pub async fn rebuild(&self, pool: &DbPool) -> anyhow::Result<usize> {
+        let (count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM chunks")
+            .fetch_one(pool)
+            .await?;
+        let capacity = ((count as f64 * HEADROOM) as usize).max(MIN_CAPACITY);
+        let mut bloom = Bloom::new(capacity, self.false_positive_rate);
+
+        self.lock_write().pending = Some(Vec::new());
+        let scanned = Self::scan(pool, &mut bloom).await;
+
+        let mut state = self.lock_write();
+        let pending = state.pending.take().unwrap_or_default();
+        let loaded = scanned?;
+        for hash in &pending {
+            bloom.insert(hash);
+        }
+        state.bloom = Some(bloom);
+        Ok(loaded)
+    }
+
+    async fn scan(pool: &DbPool, bloom: &mut Bloom) -> anyhow::Result<usize> {
+        let mut rows = sqlx::query_as::<_, (String,)>("SELECT hash FROM chunks").fetch(pool);
+        let mut loaded = 0;
+        while let Some(row) = rows.next().await {
+            bloom.insert(&row?.0);
+            loaded += 1;
+        }
+        Ok(loaded)
+    }
+
+    /// Size of the bit array in bytes (0 before the first build)
+    pub fn size_bytes(&self) -> usize {
+        let state = self.state.read().unwrap_or_else(|e| e.into_inner());
+        state.bloom.as_ref().map_or(0, |b| b.bits.len() * 8)
+    }
+
+    fn lock_write(&self) -> std::sync::RwLockWriteGuard<'_, FilterState> {
+        self.state.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Fixed-size bloom filter using double hashing over a BLAKE3 digest
+struct Bloom {
+    bits: Vec<u64>,
+    num_bits: u64,
+    num_hashes: u32,
+}
+
+impl Bloom {
+    /// Size for `capacity` items at the given false-positive rate
+    fn new(capacity: usize, false_positive_rate: f64) -> Self {
+        let n = capacity.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits = (-n * false_positive_rate.ln() / (ln2 * ln2)).ceil().max(64.0) as u64;
+        let num_hashes = ((num_bits as f64 / n) * ln2).round().clamp(1.0, 32.0) as u32;
+        Self {
+            bits: vec![0; num_bits.div_ceil(64) as usize],
+            num_bits,
+            num_hashes,
+        }
+    }
+
+    fn positions(&self, key: &str) -> impl Iterator<Item = u64> + '_ {
+        let digest = blake3::hash(key.as_bytes());
+        let bytes = digest.as_bytes();
+        let h1 = u64::from_le_bytes(bytes[0..8].try_into().expect("8 bytes"));
+        let h2 = u64::from_le_bytes(bytes[8..16].try_into().expect("8 bytes")) | 1;
+        (0..self.num_hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % self.num_bits)
+    }
+
+    fn insert(&mut self, key: &str) {
+        let positions: Vec<u64> = self.positions(key).collect();
+        for bit in positions {
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+    }
+
+    fn contains(&self, key: &str) -> bool {
+        self.positions(key)
+            .all(|bit| self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bloom_has_no_false_negatives() {
+        let mut bloom = Bloom::new(1000, 0.01);
+        let keys: Vec<String> = (0..1000).map(|i| format!("chunk-{}", i)).collect();
+        for key in &keys {
+            bloom.insert(key);
+        }
+        assert!(keys.iter().all(|k| bloom.contains(k)));
+    }
+
+    #[test]
+    fn test_bloom_false_positive_rate() {
+        let mut bloom = Bloom::new(10_000, 0.01);
+        for i in 0..10_000 {
+            bloom.insert(&format!("stored-{}", i));
+        }
+        let false_positives = (0..10_000)
+            .filter(|i| bloom.contains(&format!("absent-{}", i)))
+            .count();
+        // Target is 1%; allow for variance
+        assert!(false_positives < 200, "{} false positives", false_positives);
+    }
+
+    #[test]
+    fn test_unbuilt_filter_reports_possible_hits() {
+        let filter = ChunkFilter::new(DEFAULT_FALSE_POSITIVE_RATE);
+        filter.insert("abc");
+        let hashes = vec!["abc".to_string(), "def".to_string()];
+        assert_eq!(filter.partition(&hashes), (hashes.clone(), vec![]));
+        assert_eq!(filter.size_bytes(), 0);
+    }
+}
@@ -3,8 +3,8 @@
 //! This module implements content-based file chunking for efficient delta sync.
 //! It supports dynamic tiering based on file size/type.
 //!
-//! Currently unused: Client-side Swift implementation handles chunking.
-//! Preserved for future server-side re-chunking or alternate client support.
+//! The sync clients chunk on their side; the server uses [`StreamChunker`]
+//! for browser multipart uploads.
 
 #![allow(dead_code)]
 
@@ -14,6 +14,17 @@ use std::io::{self, Read, BufReader};
 use std::path::Path;
 use super::tiering::{TierStrategy, DefaultTierStrategy, ChunkConfig};
 
+/// BLAKE3 of no bytes. Sync clients send it as the one chunk of an empty
+/// file, but an empty file has no chunks on the server, and empty content
+/// reads as empty without anything stored for it.
+pub const EMPTY_CHUNK_HASH: &str = "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262";
+
+/// `hashes` in order without [`EMPTY_CHUNK_HASH`]: a file's chunks as the
+/// server stores them
+pub fn stored_chunk_hashes(hashes: &[String]) -> Vec<String> {
+    hashes.iter().filter(|h| *h != EMPTY_CHUNK_HASH).cloned().collect()
+}
+
 /// Represents a chunk of file content
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Chunk {
@@ -114,6 +125,44 @@ impl ChunkDiff {
     }
 }
 
+/// Merkle root over a version's ordered chunk hashes
+///
+/// Leaves are the 32-byte chunk hashes. Each level hashes adjacent pairs as
+/// `BLAKE3(0x01 || left || right)`; an odd node at the end of a level is
+/// carried up unchanged. A single chunk's root is its own hash, which for a
+/// one-chunk file equals the file hash. Returns `None` for no chunks.
+pub fn merkle_root<S: AsRef<str>>(chunk_hashes: &[S]) -> Option<String> {
+    let mut level: Vec<[u8; 32]> = chunk_hashes
+        .iter()
+        .map(|h| match blake3::Hash::from_hex(h.as_ref()) {
+            Ok(hash) => *hash.as_bytes(),
+            // Non-BLAKE3 legacy hashes still get a stable leaf
+            Err(_) => *blake3::hash(h.as_ref().as_bytes()).as_bytes(),
+        })
+        .collect();
+    if level.is_empty() {
+        return None;
+    }
+
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => {
+                    let mut hasher = blake3::Hasher::new();
+                    hasher.update(&[0x01]);
+                    hasher.update(left);
+                    hasher.update(right);
+                    *hasher.finalize().as_bytes()
+                }
+                [single] => *single,
+                _ => unreachable!("chunks(2) yields one or two items"),
+            })
+            .collect();
+    }
+    Some(hex::encode(level[0]))
+}
+
 /// Chunk a file using FastCDC algorithm with Dynamic Tiering
 pub fn chunk_file(path: &Path) -> io::Result<ChunkManifest> {
     let file = File::open(path)?;
@@ -138,6 +187,15 @@ pub fn chunk_data_with_config(data: &[u8], config: ChunkConfig) -> io::Result<Ch
     // Calculate full file hash using BLAKE3 (one-shot for whole file)
     let file_hash: [u8; 32] = *blake3::hash(data).as_bytes();
     
+    // An empty file has no chunks
+    if data.is_empty() {
+        return Ok(ChunkManifest {
+            total_size: 0,
+            file_hash,
+            chunks: Vec::new(),
+        });
+    }
+
     // Handle T0 (Inline) or empty config
     if config.max_size == 0 || data.len() < config.min_size {
         // Just return one chunk for the whole file
@@ -195,6 +253,85 @@ pub fn chunk_data(data: &[u8]) -> io::Result<ChunkManifest> {
     chunk_data_with_config(data, config)
 }
 
+/// Incremental FastCDC over data that arrives in pieces
+///
+/// A cut point depends only on the `max_size` bytes after the chunk start, so
+/// cutting once that much is buffered yields the same chunks as
+/// [`chunk_data_with_config`] on the whole input. At most `max_size` plus one
+/// pushed piece is held in memory (the whole input for inline configs).
+pub struct StreamChunker {
+    config: ChunkConfig,
+    buffer: Vec<u8>,
+    file_hasher: blake3::Hasher,
+    total_size: u64,
+}
+
+impl StreamChunker {
+    pub fn new(config: ChunkConfig) -> Self {
+        Self {
+            config,
+            buffer: Vec::new(),
+            file_hasher: blake3::Hasher::new(),
+            total_size: 0,
+        }
+    }
+
+    /// Bytes pushed so far
+    pub fn total_size(&self) -> u64 {
+        self.total_size
+    }
+
+    /// Add data, returning the chunks it completed
+    pub fn push(&mut self, data: &[u8]) -> Vec<Vec<u8>> {
+        self.file_hasher.update(data);
+        self.total_size += data.len() as u64;
+        self.buffer.extend_from_slice(data);
+
+        let mut chunks = Vec::new();
+        if self.config.max_size == 0 {
+            return chunks;
+        }
+        while self.buffer.len() >= self.config.max_size {
+            let cut = self.next_cut();
+            chunks.push(self.buffer.drain(..cut).collect());
+        }
+        chunks
+    }
+
+    /// Flush the remaining chunks and return them with the whole-file hash
+    pub fn finish(mut self) -> (Vec<Vec<u8>>, [u8; 32]) {
+        let file_hash = *self.file_hasher.finalize().as_bytes();
+        let mut chunks = Vec::new();
+        // Matches chunk_data_with_config: empty input has no chunks, small or
+        // inline input is one
+        if self.total_size == 0 {
+            return (chunks, file_hash);
+        }
+        if self.config.max_size == 0 || self.total_size < self.config.min_size as u64 {
+            chunks.push(std::mem::take(&mut self.buffer));
+            return (chunks, file_hash);
+        }
+        while !self.buffer.is_empty() {
+            let cut = self.next_cut();
+            chunks.push(self.buffer.drain(..cut).collect());
+        }
+        (chunks, file_hash)
+    }
+
+    fn next_cut(&self) -> usize {
+        use fastcdc::v2020::FastCDC;
+
+        FastCDC::new(
+            &self.buffer,
+            self.config.min_size as u32,
+            self.config.avg_size as u32,
+            self.config.max_size as u32,
+        )
+        .next()
+        .map_or(self.buffer.len(), |chunk| chunk.length)
+    }
+}
+
 /// Reassemble chunks into complete file data
 pub fn reassemble_chunks(chunks: &[(&[u8; 32], &[u8])], expected_size: u64) -> io::Result<Vec<u8>> {
     let mut result = Vec::with_capacity(expected_size as usize);
@@ -235,6 +372,29 @@ pub fn reassemble_chunks(chunks: &[(&[u8; 32], &[u8])], expected_size: u64) -> i
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_merkle_root() {
+        let a = blake3::hash(b"a").to_hex().to_string();
+        let b = blake3::hash(b"b").to_hex().to_string();
+        let c = blake3::hash(b"c").to_hex().to_string();
+        let parent = |l: &str, r: &str| {
+            let mut hasher = blake3::Hasher::new();
+            hasher.update(&[0x01]);
+            hasher.update(blake3::Hash::from_hex(l).unwrap().as_bytes());
+            hasher.update(blake3::Hash::from_hex(r).unwrap().as_bytes());
+            hasher.finalize().to_hex().to_string()
+        };
+
+        assert_eq!(merkle_root::<String>(&[]), None);
+        assert_eq!(merkle_root(&[&a]), Some(a.clone()));
+        let ab = parent(&a, &b);
+        assert_eq!(merkle_root(&[&a, &b]).as_deref(), Some(ab.as_str()));
+        // Odd node carried up, then paired
+        assert_eq!(merkle_root(&[&a, &b, &c]), Some(parent(&ab, &c)));
+        // Order matters
+        assert_ne!(merkle_root(&[&b, &a]), merkle_root(&[&a, &b]));
+    }
+
     #[test]
     fn test_blake3_hash_format() {
         let data = b"hello world";
@@ -252,6 +412,65 @@ mod tests {
         );
     }
     
+    #[test]
+    fn test_stream_chunker_matches_whole_input() {
+        let config = ChunkConfig { min_size: 2 * 1024, avg_size: 4 * 1024, max_size: 8 * 1024 };
+        // Deterministic pseudo-random data so FastCDC finds content-defined cuts
+        let mut state = 0x2545f4914f6cdd1du64;
+        let data: Vec<u8> = (0..100_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        let expected = chunk_data_with_config(&data, config).unwrap();
+
+        let mut chunker = StreamChunker::new(config);
+        let mut chunks = Vec::new();
+        for piece in data.chunks(3001) {
+            chunks.extend(chunker.push(piece));
+        }
+        let (rest, file_hash) = chunker.finish();
+        chunks.extend(rest);
+
+        assert_eq!(file_hash, expected.file_hash);
+        let hashes: Vec<[u8; 32]> = chunks.iter().map(|c| *blake3::hash(c).as_bytes()).collect();
+        let expected_hashes: Vec<[u8; 32]> = expected.chunks.iter().map(|c| c.hash).collect();
+        assert_eq!(hashes, expected_hashes);
+    }
+
+    #[test]
+    fn test_stream_chunker_small_input_is_one_chunk() {
+        let mut chunker = StreamChunker::new(ChunkConfig { min_size: 16, avg_size: 32, max_size: 64 });
+        assert!(chunker.push(b"tiny").is_empty());
+        let (chunks, _) = chunker.finish();
+        assert_eq!(chunks, vec![b"tiny".to_vec()]);
+    }
+
+    #[test]
+    fn test_empty_input_has_no_chunks() {
+        assert_eq!(blake3::hash(b"").to_hex().as_str(), EMPTY_CHUNK_HASH);
+
+        let manifest = chunk_data(b"").unwrap();
+        assert!(manifest.chunks.is_empty());
+        assert_eq!(manifest.file_hash_hex(), EMPTY_CHUNK_HASH);
+
+        let mut chunker = StreamChunker::new(ChunkConfig { min_size: 0, avg_size: 0, max_size: 0 });
+        assert!(chunker.push(b"").is_empty());
+        let (chunks, file_hash) = chunker.finish();
+        assert!(chunks.is_empty());
+        assert_eq!(hex::encode(file_hash), EMPTY_CHUNK_HASH);
+    }
+
+    #[test]
+    fn test_stored_chunk_hashes() {
+        assert!(stored_chunk_hashes(&[EMPTY_CHUNK_HASH.to_string()]).is_empty());
+        let hashes = vec!["b".to_string(), EMPTY_CHUNK_HASH.to_string(), "a".to_string(), "b".to_string()];
+        assert_eq!(stored_chunk_hashes(&hashes), vec!["b", "a", "b"]);
+    }
+
     #[test]
     fn test_chunk_hash_consistency() {
         let data = b"test chunk data for hashing";
@@ -0,0 +1,177 @@
+//! Background container compaction
+//!
+//! Deleting versions leaves dead chunks behind in sealed containers. Every
+//! `COMPACTION_INTERVAL_SECS` the compactor repacks sealed containers whose
+//! live bytes have fallen below `COMPACTION_THRESHOLD` of their size, using
+//! the same transactional [`repack_container`] as the admin endpoint.
+//!
+//! Containers are repacked one at a time with a pause in between, and only
+//! inside `COMPACTION_WINDOW` (UTC hours, e.g. `1-5`) when one is set; a run
+//! that reaches the end of the window stops and resumes on the next one.
+//! `COMPACTION_PAUSED=true` disables it without losing the other settings.
+//!
+//! [`repack_container`]: super::BlobManager::repack_container
+
+use super::BlobManager;
+use crate::db::{containers, DbPool};
+use chrono::{DateTime, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::time::Duration;
+
+pub const DEFAULT_THRESHOLD: f64 = 0.4;
+
+/// Pause between two repacks, so compaction never saturates the disk
+const PAUSE_BETWEEN_REPACKS: Duration = Duration::from_secs(1);
+
+/// Hours of the day (UTC) during which compaction may run
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub struct OffPeakWindow {
+    /// First hour of the window (inclusive)
+    pub start_hour: u32,
+    /// Hour the window closes (exclusive); before `start_hour` wraps past midnight
+    pub end_hour: u32,
+}
+
+impl OffPeakWindow {
+    /// Parse `start-end` in whole UTC hours, e.g. `1-5` or `22-4`
+    pub fn parse(spec: &str) -> anyhow::Result<Self> {
+        let invalid = || anyhow::anyhow!("COMPACTION_WINDOW must look like 1-5 (UTC hours), got {:?}", spec);
+        let (start, end) = spec.trim().split_once('-').ok_or_else(invalid)?;
+        let start_hour: u32 = start.trim().parse().map_err(|_| invalid())?;
+        let end_hour: u32 = end.trim().parse().map_err(|_| invalid())?;
+        if start_hour > 23 || end_hour > 24 || start_hour == end_hour {
+            return Err(invalid());
+        }
+        Ok(Self { start_hour, end_hour })
+    }
+
+    pub fn contains(&self, at: DateTime<Utc>) -> bool {
+        let hour = at.hour();
+        if self.start_hour < self.end_hour {
+            (self.start_hour..self.end_hour).contains(&hour)
+        } else {
+            hour >= self.start_hour || hour < self.end_hour
+        }
+    }
+}
+
+/// Outcome of one compaction pass
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct CompactionRun {
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    /// Sealed containers below the threshold when the pass started
+    pub candidates: usize,
+    pub containers_repacked: usize,
+    pub chunks_moved: usize,
+    pub bytes_reclaimed: i64,
+    pub errors: usize,
+    /// True if the off-peak window closed before every candidate was repacked
+    pub interrupted: bool,
+}
+
+pub struct Compactor {
+    threshold: f64,
+    window: Option<OffPeakWindow>,
+    last_run: Mutex<Option<CompactionRun>>,
+}
+
+impl Compactor {
+    pub fn new(threshold: f64, window: Option<OffPeakWindow>) -> Self {
+        Self {
+            threshold,
+            window,
+            last_run: Mutex::new(None),
+        }
+    }
+
+    pub fn threshold(&self) -> f64 {
+        self.threshold
+    }
+
+    pub fn window(&self) -> Option<OffPeakWindow> {
+        self.window
+    }
+
+    /// Stats of the most recent completed pass
+    pub fn last_run(&self) -> Option<CompactionRun> {
+        self.last_run.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    fn in_window(&self) -> bool {
+        self.window.is_none_or(|w| w.contains(Utc::now()))
+    }
+
+    /// Repack every sealed container below the threshold, one at a time.
+    /// Returns None without doing anything outside the off-peak window.
+    pub async fn run_once(&self, blobs: &BlobManager, pool: &DbPool) -> anyhow::Result<Option<CompactionRun>> {
+        if !self.in_window() {
+            return Ok(None);
+        }
+
+        let mut run = CompactionRun {
+            started_at: Utc::now(),
+            ..Default::default()
+        };
+        let mut candidates = Vec::new();
+        for container in containers::list_container_usage(pool).await? {
+            let sparse = container.is_sealed && container.total_size > 0 && container.fill_ratio() < self.threshold;
+            if sparse && !blobs.is_open_container(container.id).await {
+                candidates.push(container);
+            }
+        }
+        run.candidates = candidates.len();
+
+        for (i, container) in candidates.iter().enumerate() {
+            if !self.in_window() {
+                run.interrupted = true;
+                break;
+            }
+            if i > 0 {
+                tokio::time::sleep(PAUSE_BETWEEN_REPACKS).await;
+            }
+            match blobs.repack_container(container.id).await {
+                Ok(result) => {
+                    run.containers_repacked += 1;
+                    run.chunks_moved += result.chunks_moved;
+                    run.bytes_reclaimed += result.bytes_before - result.bytes_after;
+                }
+                Err(e) => {
+                    run.errors += 1;
+                    tracing::warn!("compaction: failed to repack container {}: {:#}", container.id, e);
+                }
+            }
+        }
+
+        run.finished_at = Utc::now();
+        *self.last_run.lock().unwrap_or_else(|e| e.into_inner()) = Some(run.clone());
+        Ok(Some(run))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 10, 16, hour, 30, 0).unwrap()
+    }
+
+    #[test]
+    fn test_off_peak_window() {
+        let night = OffPeakWindow::parse("1-5").unwrap();
+        assert!(night.contains(at(1)) && night.contains(at(4)));
+        assert!(!night.contains(at(5)) && !night.contains(at(0)));
+
+        let wrapping = OffPeakWindow::parse(" 22 - 4 ").unwrap();
+        assert!(wrapping.contains(at(23)) && wrapping.contains(at(0)) && wrapping.contains(at(3)));
+        assert!(!wrapping.contains(at(4)) && !wrapping.contains(at(12)));
+
+        assert!(OffPeakWindow::parse("0-24").unwrap().contains(at(23)));
+        for bad in ["", "3", "3-3", "24-2", "1-25", "a-b"] {
+            assert!(OffPeakWindow::parse(bad).is_err(), "{:?} should be rejected", bad);
+        }
+    }
+}
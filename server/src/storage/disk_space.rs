@@ -0,0 +1,205 @@
+//! Reserved free space on the blob storage volume
+//!
+//! A write that runs out of disk halfway through a chunk leaves a torn
+//! container tail. [`DiskSpaceGuard`] refuses new chunks and blobs that would
+//! take the volume's free space below `DISK_RESERVE` (a size such as `1GB`, or
+//! a share of the volume such as `5%`), which the REST API reports as
+//! `507 Insufficient Storage`.
+//!
+//! Free space is read with `statvfs` at most once per [`REFRESH_INTERVAL`];
+//! bytes accepted in between are subtracted from the cached figure. If the
+//! volume can't be measured the write is allowed, so a broken check never
+//! blocks uploads on its own.
+
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_RESERVE: &str = "1GB";
+
+/// How long a free-space reading is trusted
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Free space to keep on the blob storage volume
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub enum DiskReserve {
+    Bytes(u64),
+    /// Percentage of the volume's total size
+    Percent(f64),
+}
+
+impl DiskReserve {
+    /// Parse a byte count with an optional binary unit (`512MB`, `1GB`, `2T`)
+    /// or a percentage (`5%`)
+    pub fn parse(spec: &str) -> anyhow::Result<Self> {
+        let invalid = || anyhow::anyhow!("DISK_RESERVE must look like 1GB or 5%, got {:?}", spec);
+        let spec = spec.trim();
+        if let Some(percent) = spec.strip_suffix('%') {
+            let percent: f64 = percent.trim().parse().map_err(|_| invalid())?;
+            if !(0.0..100.0).contains(&percent) {
+                return Err(invalid());
+            }
+            return Ok(Self::Percent(percent));
+        }
+
+        let split = spec.find(|c: char| !c.is_ascii_digit()).unwrap_or(spec.len());
+        let (number, unit) = spec.split_at(split);
+        let number: u64 = number.parse().map_err(|_| invalid())?;
+        let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+            "" | "B" => 1,
+            "K" | "KB" => 1 << 10,
+            "M" | "MB" => 1 << 20,
+            "G" | "GB" => 1 << 30,
+            "T" | "TB" => 1 << 40,
+            _ => return Err(invalid()),
+        };
+        number.checked_mul(multiplier).map(Self::Bytes).ok_or_else(invalid)
+    }
+
+    /// A zero reserve (`0`, `0%`) turns the check off
+    pub fn is_zero(&self) -> bool {
+        matches!(*self, Self::Bytes(0)) || matches!(*self, Self::Percent(p) if p == 0.0)
+    }
+
+    /// Reserved bytes on a volume of `total` bytes
+    pub fn bytes(&self, total: u64) -> u64 {
+        match *self {
+            Self::Bytes(bytes) => bytes,
+            Self::Percent(percent) => (total as f64 * percent / 100.0) as u64,
+        }
+    }
+}
+
+/// A write was refused to keep the reserve free
+#[derive(Debug)]
+pub struct InsufficientSpace {
+    pub available: u64,
+    pub reserve: u64,
+}
+
+impl std::fmt::Display for InsufficientSpace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Insufficient storage: {} bytes free, {} bytes reserved",
+            self.available, self.reserve
+        )
+    }
+}
+
+impl std::error::Error for InsufficientSpace {}
+
+pub struct DiskSpaceGuard {
+    path: PathBuf,
+    reserve: DiskReserve,
+    sample: Mutex<Option<Sample>>,
+}
+
+struct Sample {
+    taken_at: Instant,
+    total: u64,
+    available: u64,
+}
+
+impl DiskSpaceGuard {
+    pub fn new(path: impl AsRef<Path>, reserve: DiskReserve) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            reserve,
+            sample: Mutex::new(None),
+        }
+    }
+
+    /// Account for `incoming` bytes about to be written, or refuse them if
+    /// they would eat into the reserve
+    pub fn check(&self, incoming: u64) -> Result<(), InsufficientSpace> {
+        let mut sample = self.sample.lock().unwrap_or_else(|e| e.into_inner());
+        if sample.as_ref().is_none_or(|s| s.taken_at.elapsed() >= REFRESH_INTERVAL) {
+            match volume_space(&self.path) {
+                Ok((total, available)) => {
+                    *sample = Some(Sample {
+                        taken_at: Instant::now(),
+                        total,
+                        available,
+                    })
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to read free space of {}: {}", self.path.display(), e);
+                    *sample = None;
+                    return Ok(());
+                }
+            }
+        }
+        let Some(sample) = sample.as_mut() else {
+            return Ok(());
+        };
+
+        let reserve = self.reserve.bytes(sample.total);
+        if sample.available.saturating_sub(incoming) < reserve {
+            return Err(InsufficientSpace {
+                available: sample.available,
+                reserve,
+            });
+        }
+        sample.available -= incoming;
+        Ok(())
+    }
+}
+
+/// Total and available bytes of the volume holding `path`
+#[cfg(unix)]
+fn volume_space(path: &Path) -> std::io::Result<(u64, u64)> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+    // SAFETY: `path` is NUL-terminated and `stat` is a valid out-pointer
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let block = stat.f_frsize as u64;
+    Ok((stat.f_blocks as u64 * block, stat.f_bavail as u64 * block))
+}
+
+#[cfg(not(unix))]
+fn volume_space(_path: &Path) -> std::io::Result<(u64, u64)> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "free space checks are only supported on Unix",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_reserve() {
+        assert_eq!(DiskReserve::parse("1GB").unwrap(), DiskReserve::Bytes(1 << 30));
+        assert_eq!(DiskReserve::parse(" 512 mb ").unwrap(), DiskReserve::Bytes(512 << 20));
+        assert_eq!(DiskReserve::parse("4096").unwrap(), DiskReserve::Bytes(4096));
+        assert_eq!(DiskReserve::parse("5%").unwrap(), DiskReserve::Percent(5.0));
+        assert_eq!(DiskReserve::parse("5%").unwrap().bytes(1000), 50);
+        for bad in ["", "GB", "1XB", "-1GB", "100%", "x%", "99999999999T"] {
+            assert!(DiskReserve::parse(bad).is_err(), "{:?} should be rejected", bad);
+        }
+    }
+
+    #[test]
+    fn test_guard_keeps_reserve_free() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_, available) = volume_space(dir.path()).unwrap();
+
+        let guard = DiskSpaceGuard::new(dir.path(), DiskReserve::Bytes(0));
+        assert!(guard.check(1024).is_ok());
+
+        // Accepted bytes count against the cached reading
+        let guard = DiskSpaceGuard::new(dir.path(), DiskReserve::Bytes(available / 2));
+        assert!(guard.check(available / 4).is_ok());
+        assert!(guard.check(available / 2).is_err());
+
+        let guard = DiskSpaceGuard::new(dir.path(), DiskReserve::Bytes(available + 1));
+        assert!(guard.check(0).is_err());
+    }
+}
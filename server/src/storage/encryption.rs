@@ -0,0 +1,106 @@
+//! Encryption at rest for container chunks
+//!
+//! When `STORAGE_ENCRYPTION_KEY` is set, chunks are written to containers
+//! marked `encrypted`, each sealed with AES-256-GCM under a fresh random
+//! nonce:
+//!
+//! ```text
+//! +-------------+------------------------------------------------+
+//! | Nonce (12)  | AES-256-GCM( flags (1) | payload ) + tag (16)  |
+//! +-------------+------------------------------------------------+
+//! ```
+//!
+//! Bit 0 of `flags` records whether the payload is zstd-compressed, since the
+//! usual "stored length < chunk size" test doesn't hold once the nonce and
+//! tag are added. Chunks stay addressed by the BLAKE3 hash of their
+//! plaintext, so dedup is unaffected.
+//!
+//! The key is never stored. Losing it makes every encrypted chunk unreadable.
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use anyhow::{anyhow, Result};
+
+/// blake3 key derivation context for the chunk encryption key
+const KEY_CONTEXT: &str = "Entanglement 2026-10-16 chunk encryption at rest";
+
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const FLAG_COMPRESSED: u8 = 0x01;
+
+/// Bytes added to each chunk by [`ChunkCipher::seal`]
+pub const OVERHEAD: usize = NONCE_LEN + 1 + TAG_LEN;
+
+pub struct ChunkCipher(Aes256Gcm);
+
+impl ChunkCipher {
+    /// Derive the cipher key from arbitrary key material
+    pub fn new(key_material: &str) -> Self {
+        let key = blake3::derive_key(KEY_CONTEXT, key_material.as_bytes());
+        Self(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)))
+    }
+
+    /// Encrypt a (possibly compressed) chunk payload for storage
+    pub fn seal(&self, payload: &[u8], compressed: bool) -> Result<Vec<u8>> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let mut plaintext = Vec::with_capacity(payload.len() + 1);
+        plaintext.push(if compressed { FLAG_COMPRESSED } else { 0 });
+        plaintext.extend_from_slice(payload);
+
+        let ciphertext = self
+            .0
+            .encrypt(&nonce, plaintext.as_slice())
+            .map_err(|_| anyhow!("Failed to encrypt chunk"))?;
+        let mut stored = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+        stored.extend_from_slice(&nonce);
+        stored.extend_from_slice(&ciphertext);
+        Ok(stored)
+    }
+
+    /// Decrypt a chunk produced by [`seal`](Self::seal), returning the
+    /// payload and whether it is compressed
+    pub fn open(&self, stored: &[u8]) -> Result<(Vec<u8>, bool)> {
+        if stored.len() < OVERHEAD {
+            return Err(anyhow!("Encrypted chunk is truncated"));
+        }
+        let (nonce, ciphertext) = stored.split_at(NONCE_LEN);
+        let mut plaintext = self
+            .0
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| anyhow!("Failed to decrypt chunk (wrong STORAGE_ENCRYPTION_KEY?)"))?;
+        let flags = plaintext.remove(0);
+        Ok((plaintext, flags & FLAG_COMPRESSED != 0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal_open_round_trip() {
+        let cipher = ChunkCipher::new("correct horse battery staple");
+        let sealed = cipher.seal(b"chunk data", true).unwrap();
+        assert_eq!(sealed.len(), b"chunk data".len() + OVERHEAD);
+        assert!(!sealed.windows(10).any(|w| w == b"chunk data"));
+        assert_eq!(cipher.open(&sealed).unwrap(), (b"chunk data".to_vec(), true));
+
+        // Fresh nonce per chunk
+        assert_ne!(cipher.seal(b"chunk data", true).unwrap(), sealed);
+
+        let (empty, compressed) = cipher.open(&cipher.seal(b"", false).unwrap()).unwrap();
+        assert!(empty.is_empty() && !compressed);
+    }
+
+    #[test]
+    fn test_open_rejects_wrong_key_and_tampering() {
+        let sealed = ChunkCipher::new("key one").seal(b"secret", false).unwrap();
+        assert!(ChunkCipher::new("key two").open(&sealed).is_err());
+
+        let cipher = ChunkCipher::new("key one");
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(cipher.open(&tampered).is_err());
+        assert!(cipher.open(&sealed[..OVERHEAD - 1]).is_err());
+    }
+}
@@ -0,0 +1,92 @@
+//! Manifest for incremental `tangled export`
+//!
+//! `manifest.json` in the export folder records the version written for each
+//! path. A later export compares against it and only rewrites files whose
+//! version (or deleted state) changed.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use uuid::Uuid;
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// What was exported for one path
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub version_id: Uuid,
+    /// Content hash of the exported version
+    pub hash: String,
+    /// Exported under `deleted/` rather than `current/`
+    pub deleted: bool,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ExportManifest {
+    pub exported_at: Option<DateTime<Utc>>,
+    /// Entries keyed by server path
+    pub files: BTreeMap<String, ManifestEntry>,
+}
+
+impl ExportManifest {
+    /// Load the manifest from an export folder (empty if there is none yet)
+    pub fn load(export_dir: &Path) -> anyhow::Result<Self> {
+        let path = export_dir.join(MANIFEST_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let data = std::fs::read(&path)?;
+        serde_json::from_slice(&data)
+            .map_err(|e| anyhow::anyhow!("Invalid export manifest {}: {}", path.display(), e))
+    }
+
+    /// Write the manifest atomically (temp file then rename)
+    pub fn save(&self, export_dir: &Path) -> anyhow::Result<()> {
+        let path = export_dir.join(MANIFEST_FILE);
+        let tmp = export_dir.join(format!("{}.tmp", MANIFEST_FILE));
+        std::fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    /// Whether `path` must be (re)written for `entry`
+    pub fn is_stale(&self, path: &str, entry: &ManifestEntry) -> bool {
+        self.files.get(path) != Some(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(version_id: Uuid, deleted: bool) -> ManifestEntry {
+        ManifestEntry { version_id, hash: "abc".into(), deleted }
+    }
+
+    #[test]
+    fn test_is_stale() {
+        let v1 = Uuid::new_v4();
+        let mut manifest = ExportManifest::default();
+        assert!(manifest.is_stale("/a.txt", &entry(v1, false)));
+
+        manifest.files.insert("/a.txt".into(), entry(v1, false));
+        assert!(!manifest.is_stale("/a.txt", &entry(v1, false)));
+        // New version or a deletion both need a rewrite
+        assert!(manifest.is_stale("/a.txt", &entry(Uuid::new_v4(), false)));
+        assert!(manifest.is_stale("/a.txt", &entry(v1, true)));
+    }
+
+    #[test]
+    fn test_manifest_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(ExportManifest::load(dir.path()).unwrap().files.is_empty());
+
+        let mut manifest = ExportManifest { exported_at: Some(Utc::now()), ..Default::default() };
+        manifest.files.insert("/docs/a.txt".into(), entry(Uuid::new_v4(), false));
+        manifest.save(dir.path()).unwrap();
+
+        let loaded = ExportManifest::load(dir.path()).unwrap();
+        assert_eq!(loaded.files, manifest.files);
+    }
+}
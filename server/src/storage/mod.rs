@@ -1,7 +1,15 @@
+pub mod backend;
 pub mod blob_io;
 pub mod cas;
+pub mod chunk_bench;
+pub mod chunk_filter;
 pub mod chunking;
+pub mod compaction;
+pub mod disk_space;
+pub mod encryption;
+pub mod export_manifest;
 pub mod tiering;
 
-pub use blob_io::{BlobManager, ChunkLocation, store_chunk};
+pub use backend::{BlobBackend, S3Backend};
+pub use blob_io::{BlobManager, store_chunk};
 pub use chunking::{Chunk, ChunkManifest, ChunkDiff, chunk_file, chunk_data};
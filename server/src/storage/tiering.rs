@@ -5,6 +5,7 @@
 
 #![allow(dead_code)]
 
+use crate::db::ChunkTier;
 use std::path::Path;
 
 /// Configuration for FastCDC chunking.
@@ -71,6 +72,96 @@ impl Tier {
     }
 }
 
+impl From<Tier> for ChunkTier {
+    fn from(tier: Tier) -> Self {
+        match tier {
+            Tier::T0Inline => ChunkTier::Inline,
+            Tier::T1Granular => ChunkTier::Granular,
+            Tier::T2Standard => ChunkTier::Standard,
+            Tier::T3Large => ChunkTier::Large,
+            Tier::T4Jumbo => ChunkTier::Jumbo,
+        }
+    }
+}
+
+impl From<ChunkTier> for ChunkConfig {
+    fn from(tier: ChunkTier) -> Self {
+        let (min_size, avg_size, max_size) = tier.chunk_sizes();
+        ChunkConfig { min_size, avg_size, max_size }
+    }
+}
+
+/// Bytes compressed from each of the start, middle and end of a chunk
+const PROBE_WINDOW_BYTES: usize = 4096;
+
+/// Chunks smaller than this are too short to judge and are compressed
+/// (`write_chunk` keeps the raw bytes if that doesn't help)
+const PROBE_MIN_BYTES: usize = 256;
+
+/// Tier choice for chunks uploaded without `X-Chunk-Tier`
+///
+/// Tiers 0-2 are stored zstd-compressed and tiers 3-4 as-is, so a chunk
+/// that is already compressed (JPEG, MP4, ZIP...) is better off as
+/// [`ChunkTier::Large`]. A sample of up to three windows of the chunk is
+/// compressed at the fastest level; unless that saves at least
+/// `min_savings_percent`, compressing the whole chunk isn't worth the CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressionProbe {
+    pub min_savings_percent: u8,
+}
+
+impl CompressionProbe {
+    /// [`ChunkTier::Standard`] for compressible data, [`ChunkTier::Large`] otherwise
+    pub fn tier_for_chunk(&self, data: &[u8]) -> ChunkTier {
+        if self.is_compressible(data) {
+            ChunkTier::Standard
+        } else {
+            ChunkTier::Large
+        }
+    }
+
+    pub fn is_compressible(&self, data: &[u8]) -> bool {
+        if data.len() < PROBE_MIN_BYTES {
+            return true;
+        }
+        let sample = if data.len() <= 3 * PROBE_WINDOW_BYTES {
+            data.to_vec()
+        } else {
+            let middle = (data.len() - PROBE_WINDOW_BYTES) / 2;
+            [
+                &data[..PROBE_WINDOW_BYTES],
+                &data[middle..middle + PROBE_WINDOW_BYTES],
+                &data[data.len() - PROBE_WINDOW_BYTES..],
+            ]
+            .concat()
+        };
+        let Ok(compressed) = zstd::bulk::compress(&sample, 1) else {
+            return true;
+        };
+        let budget = sample.len() * (100 - usize::from(self.min_savings_percent.min(100))) / 100;
+        compressed.len() <= budget
+    }
+}
+
+/// Tier the server chunks a stream in when `tier` was chosen for the file
+///
+/// FastCDC refuses minimum chunk sizes over 1MB, so Jumbo streams are cut
+/// with Large parameters. Inline would buffer the whole stream as one chunk,
+/// so it is cut with Granular parameters instead.
+pub fn streaming_tier(tier: ChunkTier) -> ChunkTier {
+    match tier {
+        ChunkTier::Inline => ChunkTier::Granular,
+        ChunkTier::Jumbo => ChunkTier::Large,
+        tier => tier,
+    }
+}
+
+/// FastCDC parameters for [`StreamChunker`](crate::storage::chunking::StreamChunker)
+/// that are always valid and never unbounded
+pub fn streaming_config(tier: ChunkTier) -> ChunkConfig {
+    streaming_tier(tier).into()
+}
+
 pub trait TierStrategy {
     fn determine_tier(path: &Path, size: u64) -> Tier;
 }
@@ -134,6 +225,34 @@ mod tests {
     use super::*;
     use std::path::PathBuf;
 
+    /// Bytes that don't compress, deterministically
+    fn noise(len: usize) -> Vec<u8> {
+        let mut data = vec![0u8; len];
+        blake3::Hasher::new().update(b"noise").finalize_xof().fill(&mut data);
+        data
+    }
+
+    #[test]
+    fn test_compression_probe() {
+        let probe = CompressionProbe { min_savings_percent: 10 };
+        let text = "fn main() { println!(\"hello\"); }\n".repeat(2000);
+        assert_eq!(probe.tier_for_chunk(text.as_bytes()), ChunkTier::Standard);
+        assert_eq!(probe.tier_for_chunk(&noise(64 * 1024)), ChunkTier::Large);
+        // Small chunks sample the whole chunk
+        assert_eq!(probe.tier_for_chunk(&noise(5000)), ChunkTier::Large);
+        // Too short to judge
+        assert_eq!(probe.tier_for_chunk(&noise(100)), ChunkTier::Standard);
+
+        // Text with random bytes between the sampled windows is judged by the windows
+        let mut mixed = text.as_bytes()[..3 * PROBE_WINDOW_BYTES].to_vec();
+        mixed.splice(PROBE_WINDOW_BYTES..PROBE_WINDOW_BYTES, noise(PROBE_WINDOW_BYTES));
+        mixed.splice(3 * PROBE_WINDOW_BYTES..3 * PROBE_WINDOW_BYTES, noise(PROBE_WINDOW_BYTES));
+        assert!(probe.is_compressible(&mixed));
+
+        // Savings of 100% are never reached
+        assert!(!CompressionProbe { min_savings_percent: 100 }.is_compressible(text.as_bytes()));
+    }
+
     #[test]
     fn test_tier_selection_by_size() {
         // T0: < 4KB
@@ -212,4 +331,19 @@ mod tests {
         let tier = DefaultTierStrategy::determine_tier(&PathBuf::from("small.iso"), 100);
         assert_eq!(tier, Tier::T4Jumbo);
     }
+
+    #[test]
+    fn test_streaming_config_is_valid_fastcdc() {
+        use fastcdc::v2020::{MAXIMUM_MAX, MINIMUM_MAX, MINIMUM_MIN};
+
+        for tier in [ChunkTier::Inline, ChunkTier::Granular, ChunkTier::Standard, ChunkTier::Large, ChunkTier::Jumbo] {
+            let config = streaming_config(tier);
+            assert!(config.max_size > 0, "{:?} streams unbounded", tier);
+            assert!((MINIMUM_MIN as usize..=MINIMUM_MAX as usize).contains(&config.min_size));
+            assert!(config.max_size <= MAXIMUM_MAX as usize);
+        }
+        assert_eq!(streaming_tier(ChunkTier::Jumbo), ChunkTier::Large);
+        assert_eq!(streaming_tier(ChunkTier::Inline), ChunkTier::Granular);
+        assert_eq!(streaming_tier(ChunkTier::Standard), ChunkTier::Standard);
+    }
 }
@@ -0,0 +1,123 @@
+//! systemd service integration (`Type=notify`)
+//!
+//! When systemd starts `tangled serve --foreground` with `Type=notify` it
+//! passes a datagram socket in `NOTIFY_SOCKET`. The server reports
+//! `READY=1` once the REST listener is bound and `STOPPING=1` when graceful
+//! shutdown begins. If the unit sets `WatchdogSec=`, systemd also passes
+//! `WATCHDOG_USEC` and the server sends `WATCHDOG=1` at half that interval
+//! for as long as its runtime keeps scheduling tasks.
+//!
+//! systemd tracks the main process itself, so no pid file is written under
+//! it. Outside systemd every call here is a no-op.
+//!
+//! ```ini
+//! [Service]
+//! Type=notify
+//! ExecStart=/usr/local/bin/tangled serve --foreground
+//! WatchdogSec=30
+//! Restart=on-failure
+//! ```
+
+use std::time::Duration;
+
+/// Whether systemd is waiting for readiness notifications
+pub fn under_systemd() -> bool {
+    std::env::var_os("NOTIFY_SOCKET").is_some_and(|s| !s.is_empty())
+}
+
+/// Send a state string such as `READY=1` to the service manager. Does
+/// nothing outside systemd; failures are logged rather than returned, as the
+/// server runs the same either way.
+pub fn notify(state: &str) {
+    let Some(socket) = std::env::var_os("NOTIFY_SOCKET").filter(|s| !s.is_empty()) else {
+        return;
+    };
+    if let Err(e) = send(&socket, state) {
+        tracing::warn!("sd_notify {} failed: {}", state, e);
+    }
+}
+
+#[cfg(unix)]
+fn send(socket: &std::ffi::OsStr, state: &str) -> std::io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::net::UnixDatagram;
+
+    let datagram = UnixDatagram::unbound()?;
+    match socket.as_bytes() {
+        // Abstract namespace socket
+        #[cfg(target_os = "linux")]
+        [b'@', name @ ..] => {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            datagram.send_to_addr(state.as_bytes(), &addr)?;
+        }
+        _ => {
+            datagram.send_to(state.as_bytes(), socket)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn send(_socket: &std::ffi::OsStr, _state: &str) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "sd_notify is only supported on Unix",
+    ))
+}
+
+/// The watchdog timeout systemd expects pings within, if it set one for
+/// this process
+fn watchdog_timeout() -> Option<Duration> {
+    parse_watchdog(
+        std::env::var("WATCHDOG_USEC").ok().as_deref(),
+        std::env::var("WATCHDOG_PID").ok().as_deref(),
+        std::process::id(),
+    )
+}
+
+fn parse_watchdog(usec: Option<&str>, pid: Option<&str>, own_pid: u32) -> Option<Duration> {
+    // WATCHDOG_PID names the process the timeout is meant for
+    if let Some(pid) = pid {
+        if pid.trim().parse::<u32>().ok() != Some(own_pid) {
+            return None;
+        }
+    }
+    let usec = usec?.trim().parse::<u64>().ok().filter(|&u| u > 0)?;
+    Some(Duration::from_micros(usec))
+}
+
+/// Ping the watchdog at half its timeout, as systemd recommends. Does
+/// nothing if no watchdog is configured.
+pub fn spawn_watchdog() {
+    if !under_systemd() {
+        return;
+    }
+    let Some(timeout) = watchdog_timeout() else {
+        return;
+    };
+    let interval = timeout / 2;
+    tracing::info!("systemd watchdog: pinging every {}ms", interval.as_millis());
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            notify("WATCHDOG=1");
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_watchdog() {
+        assert_eq!(parse_watchdog(Some("30000000"), None, 42), Some(Duration::from_secs(30)));
+        assert_eq!(parse_watchdog(Some("30000000"), Some("42"), 42), Some(Duration::from_secs(30)));
+        assert_eq!(parse_watchdog(Some("30000000"), Some("7"), 42), None);
+        assert_eq!(parse_watchdog(Some("0"), None, 42), None);
+        assert_eq!(parse_watchdog(Some("soon"), None, 42), None);
+        assert_eq!(parse_watchdog(None, None, 42), None);
+    }
+}
@@ -317,11 +317,15 @@ async fn run_app(
             }
             Screen::CreatingUser => {
                 if let Some(pool) = &app.db_pool {
-                    match auth::hash_password(&app.password_input) {
+                    match auth::hash_password(&app.password_input, &app.config.hash_params()) {
                         Ok(hash) => {
                             // First user is always admin
-                            let is_admin = app.is_first_user;
-                            match db::users::create_user(pool, &app.username_input, &hash, is_admin).await {
+                            let role = if app.is_first_user {
+                                db::users::UserRole::Admin
+                            } else {
+                                db::users::UserRole::Editor
+                            };
+                            match db::users::create_user(pool, &app.username_input, &hash, role).await {
                                 Ok(user) => {
                                     app.is_first_user = false;
                                     app.screen = Screen::UserCreated(user.id.to_string());
@@ -428,8 +432,11 @@ async fn run_app(
                             KeyCode::Char(c) => app.password_input.push(c),
                             KeyCode::Backspace => { app.password_input.pop(); }
                             KeyCode::Enter => {
-                                if app.password_input.len() < 8 {
-                                    app.error_message = Some("min 8 characters".to_string());
+                                if let Err(e) = auth::validate_password_policy(
+                                    &app.password_input,
+                                    &app.config.password_policy(),
+                                ) {
+                                    app.error_message = Some(e.to_string());
                                 } else {
                                     app.screen = Screen::ConfirmPassword;
                                 }